@@ -0,0 +1,21 @@
+#!/usr/bin/env bash
+# Runs the engine's benchmark self-test and compares it with a stored baseline.
+# The first run (or one with --update) writes the baseline; later runs fail on any kernel
+# whose single-thread rate fell by more than TOLERANCE (default 0.1).
+set -euo pipefail
+BASE=${1:-http://localhost:8081}
+BASELINE=${2:-bench-baseline.json}
+SECONDS_PER_KERNEL=${SECONDS_PER_KERNEL:-2}
+TOLERANCE=${TOLERANCE:-0.1}
+url="$BASE/api/v1/bio/benchmark"
+if [ ! -f "$BASELINE" ] || [ "${3:-}" = "--update" ]; then
+  curl -sf "$url" -H 'Content-Type: application/json' -d "{\"seconds_per_kernel\": $SECONDS_PER_KERNEL}" -o "$BASELINE"
+  echo "Baseline written to $BASELINE"
+  exit 0
+fi
+report=$(curl -sf "$url" -H 'Content-Type: application/json' -d "{\"seconds_per_kernel\": $SECONDS_PER_KERNEL, \"tolerance\": $TOLERANCE, \"baseline\": $(cat "$BASELINE")}")
+echo "$report"
+echo "---"
+if echo "$report" | grep -q '"regressions":\[\]'; then echo "No regressions"; exit 0; fi
+echo "Regressions: $(echo "$report" | sed -E 's/.*"regressions":\[([^]]*)\].*/\1/')"
+exit 1
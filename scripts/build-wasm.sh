@@ -0,0 +1,11 @@
+#!/usr/bin/env bash
+# Builds the engine's preview functions for the browser: bio-engine-core without its host
+# feature, compiled to wasm32 with JavaScript bindings written to OUT (default
+# frontend/public/wasm). Needs the wasm32-unknown-unknown target and the wasm-bindgen CLI at
+# the version the crate resolves to.
+set -euo pipefail
+OUT=${1:-frontend/public/wasm}
+cd "$(dirname "$0")/.."
+cargo rustc --manifest-path services/core-engine/core/Cargo.toml --release --target wasm32-unknown-unknown --no-default-features --features wasm --crate-type cdylib
+wasm-bindgen --target web --out-dir "$OUT" services/core-engine/target/wasm32-unknown-unknown/release/bio_engine_core.wasm
+echo "Bindings written to $OUT"
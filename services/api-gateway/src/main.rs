@@ -9,14 +9,28 @@ use axum::{
 };
 use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Instant;
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::trace::TraceLayer;
 
+/// Workspace of credentials that name no project.
+const DEFAULT_PROJECT: &str = "default";
+/// Subject recorded for requests authenticated with an API key.
+const API_KEY_SUBJECT: &str = "api-key-user";
+
 struct AppState {
     core_url: String,
     jwt_secret: String,
+    /// API key -> (project it is scoped to, role). Empty means any key is accepted (dev mode).
+    api_keys: HashMap<String, (String, Role)>,
+    /// Subject -> projects it may pick with `X-Project-Id` when its credential names none.
+    members: HashMap<String, Vec<String>>,
+    /// Screens over this many compounds need the admin role.
+    large_screen_threshold: u64,
+    /// Sweeps over this many MD steps, summed over their grid, need the admin role.
+    large_sweep_steps: u64,
     rate_limiters: DashMap<String, TokenBucket>,
     start_time: Instant,
 }
@@ -41,17 +55,36 @@ impl TokenBucket {
     }
 }
 
+/// Roles ordered by privilege: each role may do everything the ones before it can.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum Role { Viewer, Scientist, Admin }
+
+impl Role {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "viewer" => Some(Self::Viewer),
+            // `api` and `authenticated` are the legacy API-key and Supabase default roles.
+            "scientist" | "api" | "authenticated" => Some(Self::Scientist),
+            "admin" | "service_role" => Some(Self::Admin),
+            _ => None,
+        }
+    }
+    fn as_str(self) -> &'static str {
+        match self { Self::Viewer => "viewer", Self::Scientist => "scientist", Self::Admin => "admin" }
+    }
+}
+
 #[derive(Serialize)]
 struct Health { status: String, version: String, uptime_secs: u64 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Debug)]
 struct Err { error: String, #[serde(skip_serializing_if = "Option::is_none")] details: Option<String> }
 
 #[derive(Serialize)]
 struct LicenseInfo { license: String, source_code: String, notice: String }
 
 #[derive(Deserialize, Serialize, Clone)]
-struct Claims { sub: String, email: Option<String>, role: Option<String>, exp: usize }
+struct Claims { sub: String, email: Option<String>, role: Option<String>, #[serde(default)] project: Option<String>, exp: usize }
 
 #[tokio::main]
 async fn main() {
@@ -65,6 +98,10 @@ async fn main() {
     let state = Arc::new(AppState {
         core_url: env("CORE_ENGINE_URL", "http://core-engine:8081"),
         jwt_secret: env("JWT_SECRET", "dev-secret-change-me"),
+        api_keys: parse_api_keys(&env("API_KEYS", "")),
+        members: parse_members(&env("PROJECT_MEMBERS", "")),
+        large_screen_threshold: env("LARGE_SCREEN_THRESHOLD", "100000").parse().unwrap_or(100_000),
+        large_sweep_steps: env("LARGE_SWEEP_STEPS", "1000000").parse().unwrap_or(1_000_000),
         rate_limiters: DashMap::new(),
         start_time: Instant::now(),
     });
@@ -73,7 +110,8 @@ async fn main() {
         .route("/health", get(health))
         .route("/license", get(license_handler));
     let api = Router::new()
-        .route("/api/v1/{*p}", any(proxy_core))
+        .route("/api/v1/*p", any(proxy_core))
+        .layer(middleware::from_fn_with_state(state.clone(), authz_mw))
         .layer(middleware::from_fn_with_state(state.clone(), auth_mw))
         .layer(middleware::from_fn_with_state(state.clone(), rate_mw));
     let app = Router::new()
@@ -111,6 +149,7 @@ async fn auth_mw(
 ) -> Result<Response, (StatusCode, Json<Err>)> {
     let auth = req.headers().get("Authorization").and_then(|h| h.to_str().ok()).map(|s| s.to_string());
     let api_key = req.headers().get("X-API-Key").and_then(|h| h.to_str().ok()).map(|s| s.to_string());
+    let requested = req.headers().get("X-Project-Id").and_then(|h| h.to_str().ok()).map(|s| s.to_string());
     if let Some(a) = &auth {
         if let Some(token) = a.strip_prefix("Bearer ") {
             let mut val = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS256);
@@ -120,18 +159,182 @@ async fn auth_mw(
                 &jsonwebtoken::DecodingKey::from_secret(s.jwt_secret.as_bytes()),
                 &val,
             ) {
-                Ok(data) => { req.extensions_mut().insert(data.claims); return Ok(next.run(req).await); }
+                Ok(data) => {
+                    let mut claims = data.claims;
+                    claims.project = Some(scope_project(claims.project.take(), requested, s.members.get(&claims.sub))?);
+                    req.extensions_mut().insert(claims);
+                    return Ok(next.run(req).await);
+                }
                 Err(e) => return Err((StatusCode::UNAUTHORIZED, Json(Err { error: "Invalid token".into(), details: Some(e.to_string()) }))),
             }
         }
     }
-    if api_key.is_some() {
-        req.extensions_mut().insert(Claims { sub: "api-key-user".into(), email: None, role: Some("api".into()), exp: usize::MAX });
+    if let Some(key) = api_key {
+        let (pinned, role) = if s.api_keys.is_empty() { (None, Role::Scientist) } else {
+            let (p, r) = s.api_keys.get(&key).cloned().ok_or_else(|| (StatusCode::UNAUTHORIZED, Json(Err { error: "Invalid API key".into(), details: None })))?;
+            (Some(p), r)
+        };
+        let project = scope_project(pinned, requested, s.members.get(API_KEY_SUBJECT))?;
+        req.extensions_mut().insert(Claims { sub: API_KEY_SUBJECT.into(), email: None, role: Some(role.as_str().into()), project: Some(project), exp: usize::MAX });
         return Ok(next.run(req).await);
     }
     Err((StatusCode::UNAUTHORIZED, Json(Err { error: "Auth required".into(), details: Some("Provide Bearer token or X-API-Key".into()) })))
 }
 
+/// Parses `API_KEYS` as comma-separated `key:project[:role]` entries; role defaults to scientist.
+fn parse_api_keys(raw: &str) -> HashMap<String, (String, Role)> {
+    raw.split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.trim().splitn(3, ':').map(str::trim);
+            let (key, project) = (parts.next()?, parts.next()?);
+            let role = parts.next().map_or(Some(Role::Scientist), Role::parse)?;
+            (!key.is_empty() && !project.is_empty()).then(|| (key.to_string(), (project.to_string(), role)))
+        })
+        .collect()
+}
+
+/// Parses `PROJECT_MEMBERS` as comma-separated `subject:project|project...` entries.
+fn parse_members(raw: &str) -> HashMap<String, Vec<String>> {
+    let mut out: HashMap<String, Vec<String>> = HashMap::new();
+    for entry in raw.split(',') {
+        let Some((subject, projects)) = entry.trim().split_once(':') else { continue };
+        let projects = projects.split('|').map(str::trim).filter(|p| !p.is_empty()).map(str::to_string);
+        if !subject.trim().is_empty() { out.entry(subject.trim().to_string()).or_default().extend(projects); }
+    }
+    out
+}
+
+/// Resolves the project a request acts in. A credential pinned to a project may not reach
+/// into another one. Unpinned credentials work in `default`, and may pick another workspace
+/// with `X-Project-Id` only if `PROJECT_MEMBERS` lists their subject as a member of it.
+fn scope_project(pinned: Option<String>, requested: Option<String>, member_of: Option<&Vec<String>>) -> Result<String, (StatusCode, Json<Err>)> {
+    match (pinned, requested) {
+        (Some(p), Some(r)) if p != r => Err((StatusCode::FORBIDDEN, Json(Err { error: "Project not permitted".into(), details: Some(format!("credential is scoped to project {p}")) }))),
+        (Some(p), _) => Ok(p),
+        (None, Some(r)) if r == DEFAULT_PROJECT || member_of.is_some_and(|m| m.contains(&r)) => Ok(r),
+        (None, Some(r)) => Err((StatusCode::FORBIDDEN, Json(Err { error: "Project not permitted".into(), details: Some(format!("credential is not a member of project {r}")) }))),
+        (None, None) => Ok(DEFAULT_PROJECT.into()),
+    }
+}
+
+/// Shared authorization policy for everything behind `/api/v1`: viewers are read-only,
+/// scientists may run compute and upload libraries, and deleting results, creating, archiving
+/// or restoring projects, managing force fields and protocols and launching large screens are
+/// reserved for admins.
+async fn authz_mw(
+    State(s): State<Arc<AppState>>, req: Request, next: Next,
+) -> Result<Response, (StatusCode, Json<Err>)> {
+    let role = req.extensions().get::<Claims>()
+        .and_then(|c| c.role.as_deref())
+        .map_or(Some(Role::Scientist), Role::parse)
+        .unwrap_or(Role::Viewer);
+    let path = req.uri().path().to_owned();
+    let method = req.method().clone();
+    let batch = path.strip_prefix("/api/v1/bio").filter(|p| method == axum::http::Method::POST && BATCH_ROUTES.contains(p));
+    let (req, required) = if let Some(route) = batch {
+        let (parts, body) = req.into_parts();
+        let bytes = axum::body::to_bytes(body, MAX_BATCH_BODY).await
+            .map_err(|e| (StatusCode::BAD_REQUEST, Json(Err { error: "Body read fail".into(), details: Some(e.to_string()) })))?;
+        let body = serde_json::from_slice::<serde_json::Value>(&bytes).unwrap_or_default();
+        // A validation-only request computes nothing, however large.
+        let dry_run = body.get("validate_only").and_then(|d| d.as_bool()) == Some(true);
+        // A sweep's grid is at most 1000 points, so it is weighed in MD steps instead.
+        let threshold = if route == "/sweeps" { s.large_sweep_steps } else { s.large_screen_threshold };
+        let required = if batch_size(route, &body) > threshold && !dry_run { Role::Admin } else { required_role(&method, &path) };
+        (Request::from_parts(parts, Body::from(bytes)), required)
+    } else {
+        (req, required_role(&method, &path))
+    };
+    if role < required {
+        return Err((StatusCode::FORBIDDEN, Json(Err {
+            error: "Insufficient role".into(),
+            details: Some(format!("{method} {path} requires the {} role; caller has {}", required.as_str(), role.as_str())),
+        })));
+    }
+    Ok(next.run(req).await)
+}
+
+fn required_role(method: &axum::http::Method, path: &str) -> Role {
+    use axum::http::Method;
+    // Whole-project bundles carry everything a project holds.
+    if path.starts_with("/api/v1/bio/export/project/") || path == "/api/v1/bio/import/project" { return Role::Admin; }
+    // Slack webhook URLs are credentials, so even reading them is for admins.
+    if path.starts_with("/api/v1/bio/projects/") && path.ends_with("/notifications") { return Role::Admin; }
+    // So are the headers of registry connectors; running a sync is ordinary work.
+    if path.starts_with("/api/v1/bio/registry/connectors") && !path.ends_with("/sync") { return Role::Admin; }
+    if *method == Method::GET || *method == Method::HEAD || *method == Method::OPTIONS { return Role::Viewer; }
+    if *method == Method::DELETE || path.starts_with("/api/v1/bio/force-fields") || path.starts_with("/api/v1/bio/protocols") { return Role::Admin; }
+    // A new project is reachable only once an operator lists its members.
+    if path == "/api/v1/bio/projects" { return Role::Admin; }
+    if path.starts_with("/api/v1/bio/projects/") && (path.ends_with("/archive") || path.ends_with("/restore") || path.ends_with("/standardization")) { return Role::Admin; }
+    Role::Scientist
+}
+
+/// Entry points that launch a batch of docking, design or simulation work, under `/api/v1/bio`.
+const BATCH_ROUTES: &[&str] = &["/screen", "/screen/from-sequence", "/pipelines", "/sweeps", "/druggability/triage", "/peptides/design"];
+/// Batch requests are JSON; anything bigger is not a request the engine would take.
+const MAX_BATCH_BODY: usize = 10 * 1024 * 1024;
+/// MD steps the engine runs for a simulation that names none.
+const DEFAULT_MD_STEPS: u64 = 10_000;
+/// Amino acids a peptide template's `X` stands for, unless `alphabet` narrows them.
+const PEPTIDE_ALPHABET: u64 = 19;
+
+/// How many compounds, peptides, MD steps or targets a batch request asks the engine to work
+/// through, at most. Every entry point is measured here so that none can launch a large
+/// screen under a scientist's role.
+fn batch_size(route: &str, body: &serde_json::Value) -> u64 {
+    let count = |v: &serde_json::Value, k: &str| v.get(k).and_then(|a| a.as_array()).map_or(0, |a| a.len() as u64);
+    match route {
+        "/screen" => screen_size(body),
+        "/screen/from-sequence" => body.get("screen").map_or(screen_size(&serde_json::Value::Null), screen_size),
+        "/pipelines" => body.get("steps").and_then(|s| s.as_array()).into_iter().flatten()
+            .filter(|step| step.get("kind").and_then(|k| k.as_str()) == Some("screen"))
+            .map(|step| step.get("params").map_or(screen_size(&serde_json::Value::Null), screen_size))
+            .fold(0, u64::saturating_add),
+        "/sweeps" => sweep_steps(body),
+        "/druggability/triage" => count(body, "accessions"),
+        "/peptides/design" => peptide_library(body),
+        _ => 0,
+    }
+}
+
+/// MD steps a sweep runs: every temperature and force field at each of its step counts, or at
+/// the engine's default when it sweeps none.
+fn sweep_steps(body: &serde_json::Value) -> u64 {
+    let count = |k: &str| body.get(k).and_then(|a| a.as_array()).map_or(0, |a| a.len() as u64).max(1);
+    let steps = body.get("steps").and_then(|a| a.as_array()).filter(|a| !a.is_empty())
+        .map_or(DEFAULT_MD_STEPS, |a| a.iter().map(|n| n.as_u64().unwrap_or(0)).fold(0, u64::saturating_add));
+    count("temperatures_k").saturating_mul(count("force_fields")).saturating_mul(steps)
+}
+
+/// Compounds a screen docks: `library_size`, or the engine's default for the mode.
+fn screen_size(screen: &serde_json::Value) -> u64 {
+    let fragments = screen.get("mode").and_then(|m| m.as_str()) == Some("fragment");
+    screen.get("library_size").and_then(|n| n.as_u64()).unwrap_or(if fragments { 1_000 } else { 10_000 })
+}
+
+/// Peptides a design enumerates: every variant of a scan, or the template's combinations.
+fn peptide_library(body: &serde_json::Value) -> u64 {
+    let alphabet = body.get("alphabet").and_then(|a| a.as_str())
+        .map_or(PEPTIDE_ALPHABET, |a| a.bytes().filter(u8::is_ascii_alphabetic).map(|c| c.to_ascii_uppercase()).collect::<std::collections::BTreeSet<_>>().len() as u64);
+    if let Some(template) = body.get("template").and_then(|t| t.as_str()) {
+        let (mut size, mut set, mut open) = (1u64, std::collections::BTreeSet::new(), false);
+        for c in template.bytes().filter(|c| !c.is_ascii_whitespace()).map(|c| c.to_ascii_uppercase()) {
+            match (c, open) {
+                (b'[', false) => { open = true; set.clear(); }
+                (b']', true) => { open = false; size = size.saturating_mul(set.len().max(1) as u64); }
+                (_, true) => { set.insert(c); }
+                (b'X', false) => size = size.saturating_mul(alphabet),
+                _ => {}
+            }
+        }
+        return size;
+    }
+    let positions = body.get("positions").and_then(|p| p.as_array()).map(|p| p.len())
+        .or_else(|| body.get("sequence").and_then(|s| s.as_str()).map(str::len)).unwrap_or(0);
+    (positions as u64).saturating_mul(alphabet)
+}
+
 async fn rate_mw(
     State(s): State<Arc<AppState>>, req: Request, next: Next,
 ) -> Result<Response, (StatusCode, Json<Err>)> {
@@ -150,8 +353,8 @@ async fn forward(url: &str, req: Request) -> Result<Response, (StatusCode, Json<
     let q = req.uri().query().map(|q| format!("?{q}")).unwrap_or_default();
     let method = req.method().clone();
     let hdrs = req.headers().clone();
-    let body = axum::body::to_bytes(req.into_body(), 100 * 1024 * 1024).await
-        .map_err(|e| (StatusCode::BAD_REQUEST, Json(Err { error: "Body read fail".into(), details: Some(e.to_string()) })))?;
+    // Streamed through, so a large upload (a compound library) is never held here.
+    let body = reqwest::Body::wrap_stream(req.into_body().into_data_stream());
     let mut r = client.request(method, format!("{url}{path}{q}"));
     for (k, v) in hdrs.iter() { if k != "host" { r = r.header(k, v); } }
     let resp = r.body(body).send().await
@@ -167,7 +370,125 @@ async fn forward(url: &str, req: Request) -> Result<Response, (StatusCode, Json<
 }
 
 async fn proxy_core(
-    State(s): State<Arc<AppState>>, req: Request,
+    State(s): State<Arc<AppState>>, mut req: Request,
 ) -> Result<Response, (StatusCode, Json<Err>)> {
+    // Identity for the engine's audit trail; never trust a client-supplied value.
+    let claims = req.extensions().get::<Claims>().cloned();
+    req.headers_mut().remove("x-user-id");
+    req.headers_mut().remove("x-project-id");
+    if let Some(c) = claims {
+        if let Ok(uid) = c.sub.parse() { req.headers_mut().insert("x-user-id", uid); }
+        if let Some(pid) = c.project.and_then(|p| p.parse().ok()) { req.headers_mut().insert("x-project-id", pid); }
+    }
     forward(&s.core_url, req).await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn members(raw: &str, subject: &str) -> Option<Vec<String>> { parse_members(raw).get(subject).cloned() }
+
+    #[test]
+    fn pinned_credentials_stay_in_their_project() {
+        assert_eq!(scope_project(Some("lab-a".into()), None, None).unwrap(), "lab-a");
+        assert_eq!(scope_project(Some("lab-a".into()), Some("lab-a".into()), None).unwrap(), "lab-a");
+        let (status, _) = scope_project(Some("lab-a".into()), Some("lab-b".into()), None).unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn unscoped_credentials_only_reach_default() {
+        assert_eq!(scope_project(None, None, None).unwrap(), "default");
+        assert_eq!(scope_project(None, Some("default".into()), None).unwrap(), "default");
+        let (status, _) = scope_project(None, Some("lab-b".into()), None).unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn members_reach_listed_projects_only() {
+        let m = members("alice:lab-a|lab-b, bob:lab-c", "alice");
+        assert_eq!(scope_project(None, Some("lab-b".into()), m.as_ref()).unwrap(), "lab-b");
+        assert!(scope_project(None, Some("lab-c".into()), m.as_ref()).is_err());
+        assert_eq!(members("alice:lab-a|lab-b, bob:lab-c", "bob"), Some(vec!["lab-c".to_string()]));
+        assert_eq!(members("", "alice"), None);
+    }
+
+    #[test]
+    fn api_keys_parse_with_default_role() {
+        let keys = parse_api_keys("k1:lab-a, k2:lab-b:admin, k3:lab-c:root, :lab-d");
+        assert_eq!(keys.get("k1"), Some(&("lab-a".to_string(), Role::Scientist)));
+        assert_eq!(keys.get("k2"), Some(&("lab-b".to_string(), Role::Admin)));
+        assert_eq!(keys.len(), 2);
+    }
+
+    fn batch(route: &str, body: serde_json::Value) -> u64 { batch_size(route, &body) }
+
+    #[test]
+    fn every_screening_entry_point_is_sized() {
+        use serde_json::json;
+        assert_eq!(batch("/screen", json!({"target_protein": "EGFR", "library_size": 250000})), 250_000);
+        assert_eq!(batch("/screen", json!({"target_protein": "EGFR"})), 10_000);
+        assert_eq!(batch("/screen", json!({"mode": "fragment"})), 1_000);
+        assert_eq!(batch("/screen/from-sequence", json!({"sequence": "MKT", "screen": {"library_size": 500000}})), 500_000);
+        let pipeline = json!({"steps": [
+            {"id": "a", "kind": "screen", "params": {"library_size": 60000}},
+            {"id": "b", "kind": "screen", "params": {"library_size": 60000}},
+            {"id": "c", "kind": "simulate", "params": {"library_size": 1000000}}]});
+        assert_eq!(batch("/pipelines", pipeline), 120_000);
+        assert_eq!(batch("/sweeps", json!({"molecule": "x", "temperatures_k": [280, 300, 320], "steps": [1000, 2000]})), 9_000);
+        assert_eq!(batch("/sweeps", json!({"molecule": "x", "temperatures_k": [280, 300], "force_fields": ["a", "b"]})), 40_000);
+        assert_eq!(batch("/druggability/triage", json!({"accessions": ["P1", "P2"]})), 2);
+        assert_eq!(batch("/peptides/design", json!({"template": "XX[KR]A"})), 19 * 19 * 2);
+        assert_eq!(batch("/peptides/design", json!({"template": "XXXX", "alphabet": "KRE"})), 81);
+        assert_eq!(batch("/peptides/design", json!({"sequence": "ACDEFG", "positions": [1, 2]})), 2 * 19);
+        assert_eq!(batch("/peptides/design", json!({"template": "XXXXXXXXXXXXXXXXXXXXXXXXXXXXXX"})), u64::MAX);
+    }
+
+    #[test]
+    fn model_management_needs_admin() {
+        use axum::http::Method;
+        for path in ["/api/v1/bio/force-fields", "/api/v1/bio/protocols", "/api/v1/bio/protocols/my-md"] {
+            assert_eq!(required_role(&Method::POST, path), Role::Admin);
+            assert_eq!(required_role(&Method::PUT, path), Role::Admin);
+            assert_eq!(required_role(&Method::GET, path), Role::Viewer);
+        }
+        assert_eq!(required_role(&Method::POST, "/api/v1/bio/simulate"), Role::Scientist);
+        assert_eq!(required_role(&Method::DELETE, "/api/v1/bio/jobs/1"), Role::Admin);
+        assert_eq!(required_role(&Method::POST, "/api/v1/bio/projects"), Role::Admin);
+        assert_eq!(required_role(&Method::GET, "/api/v1/bio/projects"), Role::Viewer);
+    }
+
+    #[tokio::test]
+    async fn large_batches_need_admin_unless_dry_run() {
+        use axum::http::Method;
+        use tower::ServiceExt;
+        let state = Arc::new(AppState {
+            core_url: String::new(), jwt_secret: String::new(), api_keys: HashMap::new(), members: HashMap::new(),
+            large_screen_threshold: 100_000, large_sweep_steps: 1_000_000, rate_limiters: DashMap::new(), start_time: Instant::now(),
+        });
+        let app = Router::new()
+            .route("/api/v1/*p", any(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(state.clone(), authz_mw))
+            .with_state(state);
+        let call = |role: &str, path: &str, body: serde_json::Value| {
+            let mut req = Request::builder().method(Method::POST).uri(path).body(Body::from(body.to_string())).unwrap();
+            req.extensions_mut().insert(Claims { sub: "u".into(), email: None, role: Some(role.into()), project: None, exp: usize::MAX });
+            let app = app.clone();
+            async move { app.oneshot(req).await.unwrap().status() }
+        };
+        let big = serde_json::json!({"screen": {"library_size": 200000}});
+        assert_eq!(call("scientist", "/api/v1/bio/screen/from-sequence", big.clone()).await, StatusCode::FORBIDDEN);
+        assert_eq!(call("admin", "/api/v1/bio/screen/from-sequence", big).await, StatusCode::OK);
+        let pipeline = serde_json::json!({"steps": [{"id": "s", "kind": "screen", "params": {"library_size": 200000}}]});
+        assert_eq!(call("scientist", "/api/v1/bio/pipelines", pipeline).await, StatusCode::FORBIDDEN);
+        assert_eq!(call("scientist", "/api/v1/bio/screen", serde_json::json!({"library_size": 200000, "validate_only": true})).await, StatusCode::OK);
+        assert_eq!(call("scientist", "/api/v1/bio/screen", serde_json::json!({"library_size": 5000})).await, StatusCode::OK);
+        assert_eq!(call("viewer", "/api/v1/bio/screen", serde_json::json!({"library_size": 5000})).await, StatusCode::FORBIDDEN);
+        // 10 temperatures × 2 force fields × 100,000 steps is 2,000,000 MD steps over 20 points.
+        let sweep = serde_json::json!({"molecule": "CCO", "temperatures_k": (0..10).map(|i| 280 + 5 * i).collect::<Vec<_>>(), "force_fields": ["amber-ff14", "charmm36"], "steps": [100000]});
+        assert_eq!(call("scientist", "/api/v1/bio/sweeps", sweep.clone()).await, StatusCode::FORBIDDEN);
+        assert_eq!(call("admin", "/api/v1/bio/sweeps", sweep).await, StatusCode::OK);
+        assert_eq!(call("scientist", "/api/v1/bio/sweeps", serde_json::json!({"molecule": "CCO", "temperatures_k": [280, 300], "steps": [5000]})).await, StatusCode::OK);
+    }
+}
@@ -0,0 +1,34 @@
+//! Docking score of a library compound: pose, hydration and electrostatics.
+//!
+//! `cargo bench --bench docking` times what a screen spends on each compound of its library,
+//! and the pose alone.
+
+use std::hint::black_box;
+
+use bio_engine_core::{charges::{self, ChargeModel}, chem, frame::Frame, hydration, library, pockets, poses};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+const TARGET: &str = "EGFR";
+
+fn docking(c: &mut Criterion) {
+    let smiles: Vec<String> = (0..256u64).filter_map(|n| chem::canonicalize(&library::compound(n * 7919)).ok()).collect();
+    let pocket = pockets::detect(TARGET).into_iter().next();
+    let sites = pocket.as_ref().map(hydration::sites).unwrap_or_default();
+    let charged = Frame::sites(&pocket.as_ref().map(pockets::charged_sites).unwrap_or_default());
+    let no_qm = |_: &str| Err("no QM service".to_string());
+    let mut i = 0;
+    c.bench_function("pose", |b| b.iter(|| {
+        i += 1;
+        black_box(poses::dock(TARGET, pocket.as_ref(), &library::compound_id(i as u64), &smiles[i % smiles.len()]))
+    }));
+    c.bench_function("score_compound", |b| b.iter(|| {
+        i += 1;
+        let smiles = &smiles[i % smiles.len()];
+        let Some(pose) = poses::dock(TARGET, pocket.as_ref(), &library::compound_id(i as u64), smiles) else { return 0.0 };
+        let q = charges::compute(smiles, ChargeModel::Gasteiger, &no_qm).map(|q| q.atoms).unwrap_or_default();
+        black_box(hydration::displacement(&sites, &pose.coords) + charges::interaction(&pose.coords, &q, &charged))
+    }));
+}
+
+criterion_group!(benches, docking);
+criterion_main!(benches);
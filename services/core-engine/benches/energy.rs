@@ -0,0 +1,26 @@
+//! Force-field energy and gradient of a docked ligand in a pocket.
+//!
+//! `cargo bench --bench energy` times one evaluation of the full system (internal terms,
+//! receptor interaction and positional restraints), as every minimization and MD step does.
+
+use std::hint::black_box;
+
+use bio_engine_core::{chem, conformer, forcefield::System, frame::Frame, library, pockets, poses};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+const TARGET: &str = "EGFR";
+
+fn energy(c: &mut Criterion) {
+    let ligand = chem::parse_smiles(&library::compound(0)).expect("library compounds parse");
+    let pocket = pockets::detect(TARGET).into_iter().next();
+    let receptor = Frame::new(&pocket.as_ref().map(pockets::lining).unwrap_or_default());
+    let pose = poses::dock(TARGET, pocket.as_ref(), "bench", &ligand.to_canonical_smiles()).map_or_else(|| conformer::embed(&ligand, 0), |p| p.coords);
+    let restraints = conformer::restraints(&ligand);
+    let system = System { restraints: &restraints, receptor: &receptor, metals: None, anchor: &pose, k_pos: 1.0, bias: None };
+    let mut grad = vec![[0.0; 3]; pose.len()];
+    c.bench_function("energy_and_gradient", |b| b.iter(|| black_box(system.energy(black_box(&pose), Some(&mut grad)))));
+    c.bench_function("energy_only", |b| b.iter(|| black_box(system.energy(black_box(&pose), None))));
+}
+
+criterion_group!(benches, energy);
+criterion_main!(benches);
@@ -0,0 +1,24 @@
+//! Morgan fingerprinting and Tanimoto similarity search.
+//!
+//! `cargo bench --bench fingerprint` times fingerprinting one compound and searching a new
+//! fingerprint against 100 references, as novelty checks and diversity picks do.
+
+use std::hint::black_box;
+
+use bio_engine_core::{chem::{self, Molecule}, fingerprint, library};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+fn fingerprints(c: &mut Criterion) {
+    let molecules: Vec<Molecule> = (0..256u64).filter_map(|n| chem::parse_smiles(&library::compound(n * 7919)).ok()).collect();
+    let references: Vec<_> = molecules.iter().take(100).map(|m| fingerprint::morgan(m, fingerprint::RADIUS)).collect();
+    let mut i = 0;
+    c.bench_function("morgan", |b| b.iter(|| { i += 1; black_box(fingerprint::morgan(&molecules[i % molecules.len()], fingerprint::RADIUS)) }));
+    c.bench_function("search_100", |b| b.iter(|| {
+        i += 1;
+        let fp = fingerprint::morgan(&molecules[i % molecules.len()], fingerprint::RADIUS);
+        black_box(references.iter().map(|r| fingerprint::tanimoto(&fp, r)).fold(0.0, f64::max))
+    }));
+}
+
+criterion_group!(benches, fingerprints);
+criterion_main!(benches);
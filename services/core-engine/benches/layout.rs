@@ -0,0 +1,111 @@
+//! Ligand-receptor pair loops over `[x, y, z]` rows against the same loops over a `Frame`.
+//!
+//! `cargo bench --bench layout` times one energy-and-gradient evaluation of a 40-atom ligand
+//! against receptors of increasing size, as refinement and MD do every step, and the Coulomb
+//! sum of a screen's electrostatics term, in both layouts.
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use bio_engine_core::frame::Frame;
+
+const LJ_RMIN: f64 = 3.8;
+const LJ_EPSILON: f64 = 0.15;
+const SOFT_R: f64 = 2.6;
+const CUTOFF: f64 = 8.0;
+
+/// `forcefield::lj`.
+fn lj(r: f64) -> (f64, f64) {
+    let rs = r.max(SOFT_R);
+    let s6 = (LJ_RMIN / rs).powi(6);
+    let de_dr = 12.0 * LJ_EPSILON * (s6 - s6 * s6) / rs;
+    (LJ_EPSILON * (s6 * s6 - 2.0 * s6) + de_dr * (r - rs), de_dr)
+}
+
+/// The interaction loop over rows, with the same arithmetic as the frame's: the cutoff is
+/// tested on r², and only pairs inside it take a square root.
+fn rows(receptor: &[[f64; 3]], x: &[[f64; 3]], grad: &mut [[f64; 3]]) -> f64 {
+    let mut e = 0.0;
+    for (i, p) in x.iter().enumerate() {
+        for q in receptor {
+            let d = [p[0] - q[0], p[1] - q[1], p[2] - q[2]];
+            let r2 = d[0] * d[0] + d[1] * d[1] + d[2] * d[2];
+            if r2 > CUTOFF * CUTOFF { continue; }
+            let r = r2.sqrt();
+            let (pair, de_dr) = lj(r);
+            e += pair;
+            let f = de_dr / r.max(1e-6);
+            for k in 0..3 { grad[i][k] += f * d[k]; }
+        }
+    }
+    e
+}
+
+/// `forcefield::interaction_energy`.
+fn columns(receptor: &Frame, x: &[[f64; 3]], grad: &mut [[f64; 3]]) -> f64 {
+    let mut e = 0.0;
+    for (i, &p) in x.iter().enumerate() {
+        let mut g = [0.0; 3];
+        receptor.within(p, CUTOFF, |_, d, r2| {
+            let r = r2.sqrt();
+            let (pair, de_dr) = lj(r);
+            e += pair;
+            let f = de_dr / r.max(1e-6);
+            for k in 0..3 { g[k] += f * d[k]; }
+        });
+        for k in 0..3 { grad[i][k] += g[k]; }
+    }
+    e
+}
+
+/// The Coulomb sum over `(position, charge)` sites, on r² like the frame's.
+fn coulomb_rows(x: &[[f64; 3]], q: &[f64], sites: &[([f64; 3], f64)]) -> f64 {
+    x.iter().zip(q).map(|(&c, &qi)| sites.iter().map(|&(p, qj)| qj / (4.0 * ((c[0] - p[0]).powi(2) + (c[1] - p[1]).powi(2) + (c[2] - p[2]).powi(2)).max(0.25))).sum::<f64>() * qi).sum()
+}
+
+/// `charges::interaction`, without the Coulomb constant.
+fn coulomb_columns(x: &[[f64; 3]], q: &[f64], sites: &Frame) -> f64 {
+    let qs = sites.charges().unwrap_or(&[]);
+    x.iter().zip(q).map(|(&c, &qi)| {
+        sites.x().iter().zip(sites.y()).zip(sites.z()).zip(qs).map(|(((&x, &y), &z), &qj)| qj / (4.0 * ((c[0] - x).powi(2) + (c[1] - y).powi(2) + (c[2] - z).powi(2)).max(0.25))).sum::<f64>() * qi
+    }).sum()
+}
+
+/// Points spread over a cube of `side` Å centred on the origin.
+fn points(n: usize, side: f64, seed: u64) -> Vec<[f64; 3]> {
+    let mut s = seed | 1;
+    let mut next = move || { s ^= s << 13; s ^= s >> 7; s ^= s << 17; ((s >> 11) as f64 / (1u64 << 53) as f64 - 0.5) * side };
+    (0..n).map(|_| [next(), next(), next()]).collect()
+}
+
+/// Mean time of one call of `f` in µs, over enough calls to fill about half a second.
+fn time(mut f: impl FnMut() -> f64) -> f64 {
+    let t = Instant::now();
+    black_box(f());
+    let reps = ((0.5 / t.elapsed().as_secs_f64().max(1e-9)) as usize).clamp(1, 1_000_000);
+    let t = Instant::now();
+    for _ in 0..reps { black_box(f()); }
+    t.elapsed().as_secs_f64() * 1e6 / reps as f64
+}
+
+fn main() {
+    let ligand = points(40, 8.0, 7);
+    println!("{:<28} {:>10} {:>10} {:>8}", "kernel", "rows µs", "frame µs", "speedup");
+    for atoms in [500, 2_000, 8_000, 32_000] {
+        // Receptor density stays near that of a protein, ~0.05 heavy atoms/Å³.
+        let receptor = points(atoms, (atoms as f64 / 0.05).cbrt(), atoms as u64);
+        let framed = Frame::new(&receptor);
+        let mut grad = vec![[0.0; 3]; ligand.len()];
+        let a = time(|| rows(black_box(&receptor), black_box(&ligand), &mut grad));
+        let b = time(|| columns(black_box(&framed), black_box(&ligand), &mut grad));
+        println!("{:<28} {a:>10.2} {b:>10.2} {:>7.2}x", format!("lennard-jones, {atoms} atoms"), a / b);
+    }
+    let charges: Vec<f64> = (0..ligand.len()).map(|i| if i % 3 == 0 { -0.3 } else { 0.15 }).collect();
+    for n in [16, 256] {
+        let sites: Vec<([f64; 3], f64)> = points(n, 20.0, n as u64).into_iter().enumerate().map(|(i, p)| (p, if i % 2 == 0 { 1.0 } else { -1.0 })).collect();
+        let framed = Frame::sites(&sites);
+        let a = time(|| coulomb_rows(black_box(&ligand), &charges, black_box(&sites)));
+        let b = time(|| coulomb_columns(black_box(&ligand), &charges, black_box(&framed)));
+        println!("{:<28} {a:>10.2} {b:>10.2} {:>7.2}x", format!("coulomb, {n} sites"), a / b);
+    }
+}
@@ -0,0 +1,23 @@
+//! SMILES and SDF parsing of library compounds.
+//!
+//! `cargo bench --bench parsing` times canonicalizing a SMILES string and reading one SDF
+//! record, the per-compound input cost of screens, uploads and conversions.
+
+use std::hint::black_box;
+
+use bio_engine_core::{chem, conformer, convert, library, poses};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+fn parsing(c: &mut Criterion) {
+    let smiles: Vec<String> = (0..256u64).map(|n| library::compound(n * 7919)).collect();
+    let sdf: Vec<String> = smiles.iter().enumerate().filter_map(|(i, s)| {
+        let m = chem::parse_smiles(s).ok()?;
+        convert::write_sdf(&library::compound_id(i as u64), "", &m, &conformer::embed(&m, i as u64), &[]).ok()
+    }).collect();
+    let mut i = 0;
+    c.bench_function("smiles_canonicalize", |b| b.iter(|| { i += 1; black_box(chem::canonicalize(&smiles[i % smiles.len()])) }));
+    c.bench_function("sdf_record", |b| b.iter(|| { i += 1; black_box(poses::parse_sdf(&sdf[i % sdf.len()])) }));
+}
+
+criterion_group!(benches, parsing);
+criterion_main!(benches);
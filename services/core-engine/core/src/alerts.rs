@@ -0,0 +1,87 @@
+//! Structural alerts: PAINS and toxicophore substructures as SMARTS.
+//!
+//! The alert set is a tab-separated file of category, name, SMARTS (see `smarts` for the
+//! subset understood) and description. A bundled file holds the PAINS families of Baell &
+//! Holloway (2010), each condensed to its core, and common reactive and mutagenic groups; a
+//! local file (the service's `BIO_ALERTS_FILE`) adds on top, its alerts replacing bundled
+//! ones of the same category and name and possibly bringing new categories. An alert is
+//! identified as `category:name`. Matched atoms are 1-based in canonical SMILES order.
+
+use serde::Serialize;
+
+use crate::{chem::Molecule, smarts::{Pattern, Target}};
+
+const BUNDLED: &str = include_str!("../data/structural_alerts.txt");
+
+pub struct Alert { pub category: String, pub name: String, pub smarts: String, pub description: String, pattern: Pattern }
+
+impl Alert {
+    pub fn id(&self) -> String { format!("{}:{}", self.category, self.name) }
+}
+
+pub struct AlertSet { pub alerts: Vec<Alert>, pub source: String }
+
+/// An alert found in a compound.
+#[derive(Serialize, Clone)]
+pub struct Flag { pub alert: String, pub category: String, pub description: String, pub atoms: Vec<usize> }
+
+/// Alerts of `text`, skipping lines that aren't four tab-separated fields and warning about
+/// SMARTS that don't parse.
+fn parse(text: &str, origin: &str) -> Vec<Alert> {
+    let mut alerts = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+        let [category, name, smarts, description] = fields[..] else { continue };
+        match Pattern::parse(smarts) {
+            Ok(pattern) => alerts.push(Alert { category: category.into(), name: name.into(), smarts: smarts.into(), description: description.into(), pattern }),
+            Err(e) => warn(format!("alert {category}:{name} ({origin} line {}) skipped: {e}", n + 1)),
+        }
+    }
+    alerts
+}
+
+/// Logged on hosts; a wasm build has no subscriber to hear it.
+fn warn(message: String) {
+    #[cfg(feature = "host")]
+    tracing::warn!("{message}");
+    #[cfg(not(feature = "host"))]
+    let _ = message;
+}
+
+impl AlertSet {
+    pub fn bundled() -> Self { Self { alerts: parse(BUNDLED, "bundled"), source: "bundled".into() } }
+
+    /// The bundled set with the alerts of the file at `path` on top.
+    #[cfg(feature = "host")]
+    pub fn load(path: Option<String>) -> Self {
+        let mut set = Self::bundled();
+        if let Some(p) = path {
+            match std::fs::read_to_string(&p) {
+                Ok(text) => {
+                    for alert in parse(&text, &p) {
+                        match set.alerts.iter_mut().find(|a| a.category == alert.category && a.name == alert.name) { Some(a) => *a = alert, None => set.alerts.push(alert) }
+                    }
+                    set.source = format!("bundled+{p}");
+                }
+                Err(e) => tracing::warn!("alerts file {p} unavailable: {e}; using the bundled set"),
+            }
+        }
+        set
+    }
+
+    pub fn categories(&self) -> Vec<&str> {
+        let mut c: Vec<&str> = self.alerts.iter().map(|a| a.category.as_str()).collect();
+        c.sort_unstable();
+        c.dedup();
+        c
+    }
+
+    /// The alerts of the categories `wanted` accepts that `m` contains.
+    pub fn check(&self, m: &Molecule, wanted: &dyn Fn(&str) -> bool) -> Vec<Flag> {
+        let target = Target::new(m);
+        self.alerts.iter().filter(|a| wanted(&a.category)).filter_map(|a| {
+            let atoms = a.pattern.find(&target)?;
+            Some(Flag { alert: a.id(), category: a.category.clone(), description: a.description.clone(), atoms: atoms.into_iter().map(|i| i + 1).collect() })
+        }).collect()
+    }
+}
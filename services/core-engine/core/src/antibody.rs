@@ -0,0 +1,215 @@
+//! Antibody variable domains: chain typing, CDR identification and numbering.
+//!
+//! Variable domains are found from their conserved anchors: the intradomain disulfide
+//! cysteines, the framework-2 tryptophan (`W[VIAL][RK]Q` in VH, `W[YLF][QL]` in VL) and the
+//! framework-4 `WGxG` (heavy) or `FGxG` (light) motif. CDRs follow from the anchors by the
+//! usual Kabat rules, and residues are numbered in the Kabat or IMGT scheme, with insertion
+//! codes placed where each scheme puts them. Several domains in one sequence (scFv,
+//! bispecifics) are reported in order.
+
+use serde::Serialize;
+
+use crate::predict::DomainInfo;
+
+#[derive(Serialize, Clone)]
+pub struct Cdr { pub name: String, pub start: usize, pub end: usize, pub sequence: String }
+#[derive(Serialize, Clone)]
+pub struct NumberedResidue { pub position: String, pub residue: char, pub region: String }
+#[derive(Serialize, Clone)]
+pub struct VariableDomain { pub chain_type: String, pub start: usize, pub end: usize, pub cdrs: Vec<Cdr>, pub numbering: Vec<NumberedResidue> }
+#[derive(Serialize, Clone)]
+pub struct AntibodyReport { pub scheme: String, pub domains: Vec<VariableDomain> }
+
+/// Anchor indices of one variable domain.
+struct Anchors { heavy: bool, c1: usize, w: usize, c2: usize, fg: usize }
+
+fn matches(seq: &[u8], at: usize, pattern: &[&[u8]]) -> bool {
+    at + pattern.len() <= seq.len() && pattern.iter().enumerate().all(|(k, allowed)| allowed.is_empty() || allowed.contains(&seq[at + k]))
+}
+
+fn find(seq: &[u8], from: usize, to: usize, pattern: &[&[u8]]) -> Option<usize> {
+    (from..to.min(seq.len())).find(|&i| matches(seq, i, pattern))
+}
+
+fn anchors(seq: &[u8], from: usize) -> Option<Anchors> {
+    let mut c1 = from;
+    while let Some(c) = find(seq, c1, seq.len(), &[b"C"]) {
+        for heavy in [true, false] {
+            let (w_pat, w_gap, c2_gap, fg_pat, fg_gap): (&[&[u8]], _, _, &[&[u8]], _) = if heavy {
+                (&[b"W", b"VIAL", b"RK", b"Q"], 10..17, 52..64, &[b"W", b"G", b"", b"G"], 5..32)
+            } else {
+                (&[b"W", b"YLF", b"QL"], 11..22, 48..56, &[b"F", b"G", b"", b"G"], 7..15)
+            };
+            let Some(w) = find(seq, c + w_gap.start, c + w_gap.end, w_pat) else { continue };
+            let Some(c2) = find(seq, w + c2_gap.start, w + c2_gap.end, &[b"C"]) else { continue };
+            let Some(fg) = find(seq, c2 + fg_gap.start, c2 + fg_gap.end, fg_pat) else { continue };
+            return Some(Anchors { heavy, c1: c, w, c2, fg });
+        }
+        c1 = c + 1;
+    }
+    None
+}
+
+fn light_type(seq: &[u8], a: &Anchors) -> &'static str {
+    let fr4 = String::from_utf8_lossy(&seq[a.fg..(a.fg + 11).min(seq.len())]).to_string();
+    if ["TVL", "TVT", "KLT"].iter().any(|m| fr4.contains(m)) { "lambda" } else { "kappa" }
+}
+
+/// Kabat CDR ranges (inclusive) from the anchors.
+fn kabat_cdrs(seq: &[u8], a: &Anchors) -> [(usize, usize); 3] {
+    if a.heavy {
+        let h1 = (a.c1 + 9, a.w - 1);
+        let h2_start = a.w + 14;
+        // CDR-H2 ends just before the framework-3 K/R-[LIVFTA]-[TSIA] at Kabat 66-68.
+        let h2_end = find(seq, h2_start + 15, a.c2, &[b"KR", b"LIVFTA", b"TSIA"]).map_or(h2_start + 15, |k| k - 1);
+        [h1, (h2_start, h2_end), (a.c2 + 3, a.fg - 1)]
+    } else {
+        let l2 = a.w + 15;
+        [(a.c1 + 1, a.w - 1), (l2, l2 + 6), (a.c2 + 1, a.fg - 1)]
+    }
+}
+
+/// Numbers `len` residues onto a region of nominal positions `first..=last`, with insertions
+/// after `insert_after` (Kabat letters) or deletions taken from just before it.
+fn kabat_region(first: u32, last: u32, insert_after: u32, len: usize) -> Vec<String> {
+    let nominal = (last - first + 1) as usize;
+    let mut out: Vec<String> = Vec::with_capacity(len);
+    if len >= nominal {
+        for p in first..=last {
+            out.push(p.to_string());
+            if p == insert_after { for k in 0..len - nominal { out.push(format!("{p}{}", (b'A' + k as u8) as char)); } }
+        }
+    } else {
+        let drop = nominal - len;
+        out.extend((first..=last).filter(|&p| p > insert_after || p + drop as u32 <= insert_after).map(|p| p.to_string()));
+    }
+    out
+}
+
+/// IMGT numbering of a region: insertions and deletions sit symmetrically around its centre,
+/// with insertion codes `.1`, `.2`, ...
+fn imgt_region(first: u32, last: u32, len: usize) -> Vec<String> {
+    let nominal = (last - first + 1) as usize;
+    let positions: Vec<u32> = (first..=last).collect();
+    if len <= nominal {
+        let (left, right) = (len.div_ceil(2), len / 2);
+        return positions[..left].iter().chain(&positions[nominal - right..]).map(|p| p.to_string()).collect();
+    }
+    let extra = len - nominal;
+    let mid = nominal / 2;
+    let (lp, rp) = (positions[mid - 1], positions[mid]);
+    let mut out: Vec<String> = positions[..mid].iter().map(|p| p.to_string()).collect();
+    out.extend((1..=extra.div_ceil(2)).map(|k| format!("{lp}.{k}")));
+    out.extend((1..=extra / 2).rev().map(|k| format!("{rp}.{k}")));
+    out.extend(positions[mid..].iter().map(|p| p.to_string()));
+    out
+}
+
+/// Framework positions counted back from the region end (FR1) or forward from its start.
+fn framework(first: u32, last: u32, len: usize, from_end: bool) -> Vec<String> {
+    let nominal = (last - first + 1) as usize;
+    if len <= nominal {
+        let range: Vec<u32> = if from_end { (last + 1 - len as u32..=last).collect() } else { (first..first + len as u32).collect() };
+        return range.into_iter().map(|p| p.to_string()).collect();
+    }
+    // Longer than nominal: the surplus is lettered after the last position.
+    let mut out: Vec<String> = (first..=last).map(|p| p.to_string()).collect();
+    out.extend((0..len - nominal).map(|k| format!("{last}{}", (b'A' + k as u8) as char)));
+    out
+}
+
+/// IMGT framework numbering: the usual gapped positions when the length matches them, else
+/// the full ungapped range as in `framework`.
+fn imgt_framework(first: u32, last: u32, gaps: &[u32], len: usize, from_end: bool) -> Vec<String> {
+    let gapped: Vec<u32> = (first..=last).filter(|p| !gaps.contains(p)).collect();
+    if len == gapped.len() { return gapped.iter().map(|p| p.to_string()).collect(); }
+    framework(first, last, len, from_end)
+}
+
+/// CDR ranges (inclusive) in the given scheme.
+fn cdr_bounds(seq: &[u8], a: &Anchors, imgt: bool) -> [(usize, usize); 3] {
+    // IMGT CDRs: 27-38, 56-65, 105-117 with Cys23, Trp41, Cys104 and W/F118 as anchors.
+    // FR3 (66-104) usually has 38 residues in VH (gap at 73) and 36 in VL (gaps at 73, 81, 82).
+    let fr3 = if a.heavy { 38 } else { 36 };
+    if imgt { [(a.c1 + 4, a.w - 3), (a.w + 15, a.c2 - fr3), (a.c2 + 1, a.fg - 1)] } else { kabat_cdrs(seq, a) }
+}
+
+fn number(seq: &[u8], a: &Anchors, start: usize, end: usize, cdrs: &[(usize, usize); 3], imgt: bool) -> Vec<NumberedResidue> {
+    let prefix = if a.heavy { "H" } else { "L" };
+    let chain = if a.heavy { 'H' } else { 'L' };
+    // Region boundaries: [start, cdr1), cdr1, (cdr1, cdr2), cdr2, (cdr2, cdr3), cdr3, (cdr3, end].
+    let [b1, b2, b3] = *cdrs;
+    let regions: [(usize, usize, &str); 7] = [(start, b1.0, "FR1"), (b1.0, b1.1 + 1, "CDR1"), (b1.1 + 1, b2.0, "FR2"), (b2.0, b2.1 + 1, "CDR2"), (b2.1 + 1, b3.0, "FR3"), (b3.0, b3.1 + 1, "CDR3"), (b3.1 + 1, end + 1, "FR4")];
+    let mut out = Vec::new();
+    for (k, &(from, to, name)) in regions.iter().enumerate() {
+        let len = to.saturating_sub(from);
+        let labels = match (imgt, a.heavy, k) {
+            (true, _, 0) => imgt_framework(1, 26, &[10], len, true),
+            (true, _, 1) => imgt_region(27, 38, len),
+            (true, _, 2) => framework(39, 55, len, false),
+            (true, _, 3) => imgt_region(56, 65, len),
+            (true, true, 4) => imgt_framework(66, 104, &[73], len, true),
+            (true, false, 4) => imgt_framework(66, 104, &[73, 81, 82], len, true),
+            (true, _, 5) => imgt_region(105, 117, len),
+            (true, _, _) => framework(118, 128, len, false),
+            (false, true, 0) => framework(1, 30, len, true),
+            (false, true, 1) => kabat_region(31, 35, 35, len),
+            (false, true, 2) => framework(36, 49, len, false),
+            (false, true, 3) => kabat_region(50, 65, 52, len),
+            (false, true, 4) => kabat_region(66, 94, 82, len),
+            (false, true, 5) => kabat_region(95, 102, 100, len),
+            (false, true, _) => framework(103, 113, len, false),
+            (false, false, 0) => framework(1, 23, len, true),
+            (false, false, 1) => kabat_region(24, 34, 27, len),
+            (false, false, 2) => framework(35, 49, len, false),
+            (false, false, 3) => kabat_region(50, 56, 56, len),
+            (false, false, 4) => framework(57, 88, len, true),
+            (false, false, 5) => kabat_region(89, 97, 95, len),
+            (false, false, _) => framework(98, 107, len, false),
+        };
+        let region = name.strip_prefix("CDR").map_or_else(|| name.to_string(), |n| format!("CDR-{chain}{n}"));
+        out.extend(labels.into_iter().zip(&seq[from..to]).map(|(p, &r)| NumberedResidue { position: format!("{prefix}{p}"), residue: r as char, region: region.clone() }));
+    }
+    out
+}
+
+/// Every variable domain in `sequence`, numbered in `scheme` ("kabat" or "imgt").
+pub fn analyze(sequence: &str, scheme: &str) -> Vec<VariableDomain> {
+    let seq: Vec<u8> = sequence.bytes().map(|b| b.to_ascii_uppercase()).collect();
+    let imgt = scheme.eq_ignore_ascii_case("imgt");
+    let mut out = Vec::new();
+    let mut from = 0;
+    while let Some(a) = anchors(&seq, from) {
+        let chain_type = if a.heavy { "heavy" } else { light_type(&seq, &a) };
+        let nominal_fr1 = if a.heavy { 21 } else { 22 };
+        let start = a.c1.saturating_sub(nominal_fr1).max(from);
+        let end = (a.fg + if a.heavy { 10 } else { 9 }).min(seq.len() - 1);
+        let cdrs = cdr_bounds(&seq, &a, imgt);
+        let chain = if a.heavy { 'H' } else { 'L' };
+        let cdr_list = cdrs.iter().enumerate().map(|(k, &(s, e))| Cdr { name: format!("CDR-{chain}{}", k + 1), start: s, end: e, sequence: String::from_utf8_lossy(&seq[s..=e]).into() }).collect();
+        let numbering = number(&seq, &a, start, end, &cdrs, imgt);
+        out.push(VariableDomain { chain_type: chain_type.into(), start, end, cdrs: cdr_list, numbering });
+        from = end + 1;
+    }
+    out
+}
+
+/// Domain list for an antibody: each variable domain with its CDRs, then any constant region
+/// left after the last one. CDR-H3 gets lower confidence, being the hardest loop to model.
+pub fn domains(variable: &[VariableDomain], seq_len: usize, confidence: f64) -> Vec<DomainInfo> {
+    let mut out = Vec::new();
+    for v in variable {
+        let name = match v.chain_type.as_str() { "heavy" => "VH", "kappa" => "VL_kappa", _ => "VL_lambda" };
+        out.push(DomainInfo { name: name.into(), start: v.start, end: v.end + 1, domain_type: "antibody_variable".into(), confidence });
+        for c in &v.cdrs {
+            let conf = if c.name == "CDR-H3" { confidence - 0.15 } else { confidence - 0.05 };
+            out.push(DomainInfo { name: c.name.clone(), start: c.start, end: c.end + 1, domain_type: "cdr".into(), confidence: conf });
+        }
+    }
+    let tail = variable.last().map_or(0, |v| v.end + 1);
+    if seq_len.saturating_sub(tail) >= 60 {
+        let name = if variable.last().is_some_and(|v| v.chain_type == "heavy") { "CH1" } else { "CL" };
+        out.push(DomainInfo { name: name.into(), start: tail, end: seq_len, domain_type: "antibody_constant".into(), confidence });
+    }
+    out
+}
@@ -0,0 +1,270 @@
+//! Partial charges for the molecule pipeline.
+//!
+//! Three models, picked with `charge_model`: Gasteiger–Marsili (PEOE) iterative equalization,
+//! MMFF94 bond charge increments, and AM1-BCC from a QM calculation. Every model works
+//! on the hydrogen-complete graph; the engine's coordinates are heavy-atom only, so each
+//! hydrogen's charge is then merged into its heavy atom. MMFF94 types the common organic
+//! atoms (sp3/sp2/sp/aromatic C, amine, amide, aniline, imine, pyridine, pyrrole, nitrile and
+//! ammonium N, ether/hydroxyl, carbonyl and carboxylate O, S, P, halogens) and shares a
+//! carboxylate's or amidinium's formal charge over its equivalent atoms; a bond between types
+//! without an increment gets one from the Pauling electronegativity difference. AM1-BCC
+//! charges come from the caller, one per atom of the SMILES with its hydrogens listed after
+//! the heavy atoms in parent order (or one per heavy atom with hydrogens already merged).
+
+use serde::Serialize;
+
+use crate::{chem::{self, Bond, BondKind, Molecule}, convert, frame::Frame, vec3::dist};
+
+pub const MODELS: [&str; 3] = ["gasteiger", "mmff94", "am1-bcc"];
+/// Coulomb's constant in kcal·Å/(mol·e²).
+const COULOMB: f64 = 332.0637;
+/// MMFF94 scales 1-4 electrostatics.
+const SCALE_14: f64 = 0.75;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ChargeModel { Gasteiger, Mmff94, Am1Bcc }
+
+impl ChargeModel {
+    /// The requested model; without one, MMFF94 for MMFF force fields and Gasteiger otherwise.
+    pub fn parse(name: Option<&str>, force_field: &str) -> Result<Self, String> {
+        match name.map(|n| n.to_lowercase().replace('_', "-")).as_deref() {
+            None if force_field.to_lowercase().starts_with("mmff") => Ok(Self::Mmff94),
+            None | Some("gasteiger") => Ok(Self::Gasteiger),
+            Some("mmff94" | "mmff") => Ok(Self::Mmff94),
+            Some("am1-bcc" | "am1bcc") => Ok(Self::Am1Bcc),
+            Some(other) => Err(format!("unknown charge_model {other}; expected one of {}", MODELS.join(", "))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self { Self::Gasteiger => MODELS[0], Self::Mmff94 => MODELS[1], Self::Am1Bcc => MODELS[2] }
+    }
+}
+
+/// Partial charges per heavy atom (hydrogens merged in) and their sum.
+#[derive(Serialize, Clone)]
+pub struct Charges { pub net_charge: f64, pub atoms: Vec<f64> }
+
+/// The molecule with its hydrogens as atoms, after the heavy atoms in parent order.
+pub fn with_hydrogens(mol: &Molecule) -> Result<Molecule, String> {
+    let mut atoms: Vec<(String, i8, Option<u8>)> = mol.atoms.iter().map(|a| (a.element.clone(), a.charge, Some(0))).collect();
+    let mut bonds = mol.bonds.clone();
+    for (i, a) in mol.atoms.iter().enumerate() {
+        for _ in 0..a.hydrogens { bonds.push(Bond { a: i, b: atoms.len(), kind: BondKind::Single }); atoms.push(("H".into(), 0, Some(0))); }
+    }
+    chem::from_graph(atoms, bonds)
+}
+
+/// Folds hydrogen charges of a hydrogen-complete molecule into the first `heavy` atoms.
+fn merge_hydrogens(full: &Molecule, heavy: usize, q: &[f64]) -> Vec<f64> {
+    let mut out = q[..heavy].to_vec();
+    for b in &full.bonds {
+        if b.b >= heavy && b.a < heavy { out[b.a] += q[b.b]; } else if b.a >= heavy && b.b < heavy { out[b.b] += q[b.a]; }
+    }
+    out
+}
+
+/// MMFF94 symbolic atom type number for an atom of a hydrogen-complete molecule.
+fn mmff_type(m: &Molecule, adj: &[Vec<(usize, BondKind)>], i: usize) -> u8 {
+    let a = &m.atoms[i];
+    let el = |j: usize| m.atoms[j].element.as_str();
+    let double_to = |j: usize, what: &[&str]| adj[j].iter().any(|&(k, b)| b == BondKind::Double && what.contains(&el(k)));
+    let terminal_o = |j: usize| adj[j].iter().filter(|&&(k, _)| el(k) == "O" && adj[k].len() == 1).count();
+    let conjugated = |j: usize| m.atoms[j].aromatic || adj[j].iter().any(|&(_, b)| b == BondKind::Double);
+    match a.element.as_str() {
+        "H" => {
+            let Some(&(p, _)) = adj[i].first() else { return 5 };
+            match el(p) {
+                "O" if adj[p].iter().any(|&(k, _)| el(k) == "C" && double_to(k, &["O"])) => 24,
+                "O" if adj[p].iter().all(|&(k, _)| el(k) == "H") => 31,
+                "O" if adj[p].iter().any(|&(k, _)| el(k) == "C" && conjugated(k)) => 29,
+                "O" => 21,
+                "N" if m.atoms[p].charge > 0 => 36,
+                "N" if m.atoms[p].aromatic || adj[p].iter().any(|&(k, _)| el(k) == "C" && conjugated(k)) => 28,
+                "N" => 23,
+                "S" => 71,
+                _ => 5,
+            }
+        }
+        "C" if a.aromatic => 37,
+        "C" if terminal_o(i) == 2 && adj[i].iter().any(|&(k, _)| el(k) == "O" && m.atoms[k].charge < 0) => 41,
+        "C" if adj[i].iter().any(|&(_, b)| b == BondKind::Triple) || adj[i].iter().filter(|&&(_, b)| b == BondKind::Double).count() == 2 => 4,
+        "C" if double_to(i, &["O", "N", "S"]) => 3,
+        "C" if double_to(i, &["C"]) => 2,
+        "C" => 1,
+        "N" if a.aromatic && a.charge > 0 => 58,
+        "N" if a.aromatic && (adj[i].len() == 3) => 39,
+        "N" if a.aromatic => 38,
+        "N" if a.charge > 0 && adj[i].iter().all(|&(_, b)| b == BondKind::Single) => 34,
+        "N" if adj[i].iter().any(|&(_, b)| b == BondKind::Triple) => 42,
+        "N" if double_to(i, &["C"]) => 9,
+        "N" if adj[i].iter().any(|&(k, _)| el(k) == "C" && double_to(k, &["O", "S"])) => 10,
+        "N" if adj[i].iter().any(|&(k, _)| el(k) == "C" && conjugated(k)) => 40,
+        "N" => 8,
+        "O" if adj[i].len() == 1 && adj[i].iter().any(|&(k, _)| matches!(el(k), "S" | "P")) => 32,
+        "O" if adj[i].len() == 1 && adj[i].iter().any(|&(k, _)| terminal_o(k) == 2 && el(k) == "C" && adj[k].iter().any(|&(o, _)| el(o) == "O" && m.atoms[o].charge < 0)) => 32,
+        "O" if a.charge < 0 => 35,
+        "O" if adj[i].iter().any(|&(_, b)| b == BondKind::Double) => 7,
+        "O" if a.aromatic => 59,
+        "O" if adj[i].iter().all(|&(k, _)| el(k) == "H") => 70,
+        "O" => 6,
+        "S" if a.aromatic => 44,
+        "S" => match adj[i].iter().filter(|&&(k, _)| el(k) == "O" && adj[k].len() == 1).count() { 0 if double_to(i, &["C"]) => 16, 0 => 15, 1 => 17, _ => 18 },
+        "P" => 25,
+        "F" => 11,
+        "Cl" => 12,
+        "Br" => 13,
+        "I" => 14,
+        _ => 0,
+    }
+}
+
+/// Bond charge increments: the first type gains the increment, the second loses it.
+const BCI: &[(u8, u8, f64)] = &[
+    (1, 1, 0.0), (1, 2, 0.0), (1, 3, 0.0), (1, 4, 0.0), (1, 37, 0.0), (2, 2, 0.0), (2, 37, 0.0), (37, 37, 0.0), (1, 41, 0.0), (37, 3, 0.0),
+    (1, 5, 0.0), (2, 5, -0.15), (37, 5, -0.15), (3, 5, -0.06), (4, 5, -0.15),
+    (1, 6, 0.28), (2, 6, 0.20), (3, 6, 0.15), (37, 6, 0.16), (21, 6, 0.40), (29, 6, 0.45), (24, 6, 0.50), (31, 70, 0.43),
+    (3, 7, 0.57), (41, 32, 0.40), (25, 32, 0.50), (18, 32, 0.54), (17, 32, 0.60), (1, 35, 0.0),
+    (1, 8, 0.27), (23, 8, 0.36), (3, 10, 0.0), (1, 10, 0.27), (28, 10, 0.37), (37, 40, 0.10), (1, 40, 0.27), (28, 40, 0.40),
+    (3, 9, 0.36), (1, 9, 0.27), (28, 9, 0.27), (37, 38, 0.24), (37, 39, 0.04), (28, 39, 0.40), (37, 58, 0.40), (36, 58, 0.45),
+    (4, 42, 0.43), (1, 34, 0.50), (36, 34, 0.45),
+    (1, 15, 0.23), (37, 15, 0.20), (71, 15, 0.18), (3, 16, 0.45), (1, 18, 0.10), (37, 18, 0.10), (1, 17, 0.10), (37, 44, 0.10),
+    (1, 11, 0.23), (1, 12, 0.29), (1, 13, 0.23), (1, 14, 0.18), (37, 11, 0.18), (37, 12, 0.16), (37, 13, 0.13), (37, 14, 0.09),
+];
+
+fn pauling(el: &str) -> f64 {
+    match el { "H" => 2.20, "C" => 2.55, "N" => 3.04, "O" => 3.44, "F" => 3.98, "P" => 2.19, "S" => 2.58, "Cl" => 3.16, "Br" => 2.96, "I" => 2.66, "B" => 2.04, "Si" => 1.90, "Se" => 2.55, _ => 2.0 }
+}
+
+/// MMFF94 charges of a hydrogen-complete molecule: shared formal charges plus bond increments.
+pub fn mmff94(m: &Molecule) -> Vec<f64> {
+    let adj = m.neighbors();
+    let types: Vec<u8> = (0..m.atoms.len()).map(|i| mmff_type(m, &adj, i)).collect();
+    let mut q: Vec<f64> = m.atoms.iter().map(|a| a.charge as f64).collect();
+    // Carboxylate, sulfonate and phosphate charge over their terminal oxygens; amidinium and
+    // guanidinium charge over the nitrogens of the central carbon.
+    for i in 0..m.atoms.len() {
+        let a = &m.atoms[i];
+        let share: Vec<usize> = match a.element.as_str() {
+            "O" if a.charge < 0 && adj[i].len() == 1 => { let c = adj[i][0].0; adj[c].iter().map(|&(k, _)| k).filter(|&k| m.atoms[k].element == "O" && adj[k].len() == 1).collect() }
+            "N" if a.charge > 0 && !a.aromatic && adj[i].iter().any(|&(_, b)| b == BondKind::Double) => {
+                let c = adj[i].iter().find(|&&(_, b)| b == BondKind::Double).map(|&(k, _)| k).unwrap_or(i);
+                adj[c].iter().map(|&(k, _)| k).filter(|&k| m.atoms[k].element == "N" && !m.atoms[k].aromatic && adj[k].iter().all(|&(j, _)| j == c || m.atoms[j].element != "N")).collect()
+            }
+            _ => continue,
+        };
+        if share.len() < 2 { continue; }
+        let part = q[i] / share.len() as f64;
+        q[i] = 0.0;
+        for k in share { q[k] += part; }
+    }
+    for b in &m.bonds {
+        let (ta, tb) = (types[b.a], types[b.b]);
+        let delta = BCI.iter().find_map(|&(x, y, d)| if (x, y) == (ta, tb) { Some(d) } else if (x, y) == (tb, ta) { Some(-d) } else { None })
+            .unwrap_or_else(|| 0.2 * (pauling(&m.atoms[b.b].element) - pauling(&m.atoms[b.a].element)));
+        q[b.a] += delta;
+        q[b.b] -= delta;
+    }
+    q
+}
+
+/// Partial charges of a molecule parsed from `smiles`; `am1bcc` supplies AM1-BCC charges for it.
+pub fn compute(smiles: &str, model: ChargeModel, am1bcc: &dyn Fn(&str) -> Result<Vec<f64>, String>) -> Result<Charges, String> {
+    let mol = chem::parse_smiles(smiles)?;
+    let heavy = mol.atoms.len();
+    let full = with_hydrogens(&mol)?;
+    let atoms = match model {
+        ChargeModel::Gasteiger => merge_hydrogens(&full, heavy, &convert::gasteiger(&full)),
+        ChargeModel::Mmff94 => merge_hydrogens(&full, heavy, &mmff94(&full)),
+        ChargeModel::Am1Bcc => {
+            let q = am1bcc(smiles)?;
+            match q.len() {
+                n if n == full.atoms.len() => merge_hydrogens(&full, heavy, &q),
+                n if n == heavy => q,
+                n => return Err(format!("QM service returned {n} charges for {} atoms ({heavy} heavy)", full.atoms.len())),
+            }
+        }
+    };
+    let atoms: Vec<f64> = atoms.iter().map(|q| (q * 1e4).round() / 1e4).collect();
+    Ok(Charges { net_charge: (atoms.iter().sum::<f64>() * 1e3).round() / 1e3 + 0.0, atoms })
+}
+
+/// Intramolecular Coulomb energy (kcal/mol) with a distance-dependent dielectric ε = 4r, over
+/// atom pairs at least three bonds apart (1-4 pairs scaled).
+pub fn coulomb(mol: &Molecule, coords: &[[f64; 3]], q: &[f64]) -> f64 {
+    let adj = mol.neighbors();
+    let n = mol.atoms.len();
+    let mut e = 0.0;
+    for i in 0..n {
+        // Bond separation from i, up to three.
+        let mut sep = vec![u8::MAX; n];
+        sep[i] = 0;
+        let mut frontier = vec![i];
+        for d in 1..=3u8 {
+            let mut next = Vec::new();
+            for &a in &frontier { for &(b, _) in &adj[a] { if sep[b] == u8::MAX { sep[b] = d; next.push(b); } } }
+            frontier = next;
+        }
+        for j in i + 1..n {
+            if sep[j] < 3 { continue; }
+            e += pair(q[i], q[j], dist(coords[i], coords[j]), sep[j]);
+        }
+    }
+    e
+}
+
+/// Coulomb energy (kcal/mol) of charges `r` apart and `sep` bonds apart, at least three.
+pub fn pair(qi: f64, qj: f64, r: f64, sep: u8) -> f64 {
+    let r = r.max(0.5);
+    let scale = if sep == 3 { SCALE_14 } else { 1.0 };
+    scale * COULOMB * qi * qj / (4.0 * r * r)
+}
+
+/// Ligand interaction energy (kcal/mol) with the point charges of `sites`, same dielectric.
+pub fn interaction(coords: &[[f64; 3]], q: &[f64], sites: &Frame) -> f64 {
+    let Some(qs) = sites.charges() else { return 0.0 };
+    coords.iter().zip(q).map(|(&c, &qi)| {
+        // ε(r) = 4r, so each pair is q·q / 4r²; below 0.5 Å the distance is clamped.
+        sites.x().iter().zip(sites.y()).zip(sites.z()).zip(qs).map(|(((&x, &y), &z), &qj)| qj / (4.0 * ((c[0] - x).powi(2) + (c[1] - y).powi(2) + (c[2] - z).powi(2)).max(0.25))).sum::<f64>() * COULOMB * qi
+    }).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_qm(_: &str) -> Result<Vec<f64>, String> { Err("no QM service".into()) }
+
+    #[test]
+    fn model_defaults_to_the_force_field_family() {
+        assert!(ChargeModel::parse(None, "MMFF94") == Ok(ChargeModel::Mmff94));
+        assert!(ChargeModel::parse(None, "gaff") == Ok(ChargeModel::Gasteiger));
+        assert!(ChargeModel::parse(Some("AM1_BCC"), "gaff") == Ok(ChargeModel::Am1Bcc));
+        assert!(ChargeModel::parse(Some("resp"), "gaff").is_err());
+    }
+
+    #[test]
+    fn charges_sum_to_the_formal_charge() {
+        for model in [ChargeModel::Gasteiger, ChargeModel::Mmff94] {
+            let ethanol = compute("CCO", model, &no_qm).unwrap();
+            assert_eq!((ethanol.atoms.len(), ethanol.net_charge), (3, 0.0), "{}", model.name());
+            assert!(ethanol.atoms[2] < 0.0, "{}", model.name());
+            assert_eq!(compute("CC(=O)[O-]", model, &no_qm).unwrap().net_charge, -1.0, "{}", model.name());
+        }
+    }
+
+    #[test]
+    fn am1bcc_charges_must_cover_every_atom() {
+        let short = |_: &str| -> Result<Vec<f64>, String> { Ok(vec![0.1, -0.1]) };
+        assert!(compute("CCO", ChargeModel::Am1Bcc, &short).is_err());
+        let heavy = |_: &str| -> Result<Vec<f64>, String> { Ok(vec![0.1, 0.2, -0.3]) };
+        assert_eq!(compute("CCO", ChargeModel::Am1Bcc, &heavy).unwrap().atoms, [0.1, 0.2, -0.3]);
+        assert!(compute("CCO", ChargeModel::Am1Bcc, &no_qm).is_err());
+    }
+
+    #[test]
+    fn one_four_pairs_are_scaled() {
+        assert!((pair(1.0, -1.0, 3.0, 3) - SCALE_14 * pair(1.0, -1.0, 3.0, 4)).abs() < 1e-12);
+        assert_eq!(pair(1.0, 1.0, 0.1, 4), pair(1.0, 1.0, 0.5, 4));
+    }
+}
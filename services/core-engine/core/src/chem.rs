@@ -0,0 +1,511 @@
+//! Small-molecule graph model with a SMILES reader and canonical SMILES writer.
+//!
+//! The reader covers the OpenSMILES organic subset, bracket atoms (isotope, hydrogen count,
+//! charge), branches, ring closures (including `%nn`) and disconnected components.
+//! Stereochemistry (`@`, `/`, `\`) is accepted but discarded. Kekulé rings of five and six
+//! atoms are perceived as aromatic so that Kekulé and aromatic spellings of the same
+//! molecule canonicalize identically.
+
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BondKind { Single, Double, Triple, Aromatic }
+
+impl BondKind {
+    /// Contribution to the valence of each endpoint; aromatic bonds count as one here and the
+    /// pi electron is accounted for per atom.
+    pub fn valence(self) -> u8 { match self { Self::Single | Self::Aromatic => 1, Self::Double => 2, Self::Triple => 3 } }
+    fn code(self) -> u8 { match self { Self::Single => 1, Self::Double => 2, Self::Triple => 3, Self::Aromatic => 4 } }
+}
+
+#[derive(Clone, Debug)]
+pub struct Atom { pub element: String, pub aromatic: bool, pub charge: i8, pub isotope: Option<u16>, pub hydrogens: u8, bracket: bool }
+#[derive(Clone, Copy, Debug)]
+pub struct Bond { pub a: usize, pub b: usize, pub kind: BondKind }
+
+#[derive(Clone, Default, Debug)]
+pub struct Molecule { pub atoms: Vec<Atom>, pub bonds: Vec<Bond> }
+
+/// Symbol, atomic number and the default valences used to infer implicit hydrogens.
+const ELEMENTS: &[(&str, u8, &[u8])] = &[
+    ("H", 1, &[1]), ("He", 2, &[]), ("Li", 3, &[]), ("Be", 4, &[]), ("B", 5, &[3]), ("C", 6, &[4]), ("N", 7, &[3, 5]), ("O", 8, &[2]), ("F", 9, &[1]),
+    ("Ne", 10, &[]), ("Na", 11, &[]), ("Mg", 12, &[]), ("Al", 13, &[]), ("Si", 14, &[4]), ("P", 15, &[3, 5]), ("S", 16, &[2, 4, 6]), ("Cl", 17, &[1]),
+    ("Ar", 18, &[]), ("K", 19, &[]), ("Ca", 20, &[]), ("Mn", 25, &[]), ("Fe", 26, &[]), ("Co", 27, &[]), ("Ni", 28, &[]), ("Cu", 29, &[]),
+    ("Zn", 30, &[]), ("As", 33, &[3, 5]), ("Se", 34, &[2, 4, 6]), ("Br", 35, &[1]), ("Mo", 42, &[]), ("Pt", 78, &[]), ("Hg", 80, &[]), ("I", 53, &[1]),
+];
+const ORGANIC_SUBSET: &[&str] = &["B", "C", "N", "O", "P", "S", "F", "Cl", "Br", "I"];
+const AROMATIC_SYMBOLS: &[&str] = &["b", "c", "n", "o", "p", "s", "se", "as"];
+
+pub fn atomic_number(element: &str) -> Option<u8> { ELEMENTS.iter().find(|e| e.0 == element).map(|e| e.1) }
+pub fn default_valences(element: &str) -> &'static [u8] { ELEMENTS.iter().find(|e| e.0 == element).map_or(&[], |e| e.2) }
+
+/// Hydrogens a SMILES reader infers for a bare organic-subset atom with the given bond valence.
+fn implicit_hydrogens(element: &str, aromatic: bool, bond_valence: u8) -> u8 {
+    let pi = match (aromatic, element) {
+        (true, "C" | "B") => 1,
+        (true, "N" | "P") if bond_valence == 2 => 1,
+        _ => 0,
+    };
+    let used = bond_valence + pi;
+    default_valences(element).iter().find(|&&v| v >= used).map_or(0, |v| v - used)
+}
+
+fn capitalize(sym: &str) -> String {
+    let mut c = sym.chars();
+    c.next().map(|f| f.to_ascii_uppercase().to_string() + c.as_str()).unwrap_or_default()
+}
+
+fn dense<K: Ord + Clone>(keys: &[K]) -> Vec<usize> {
+    let mut sorted: Vec<K> = keys.to_vec();
+    sorted.sort();
+    sorted.dedup();
+    keys.iter().map(|k| sorted.binary_search(k).unwrap()).collect()
+}
+
+fn distinct(ranks: &[usize]) -> usize { let mut v = ranks.to_vec(); v.sort_unstable(); v.dedup(); v.len() }
+
+/// Refines ranks by neighbour ranks until the partition stops splitting.
+fn refine(adj: &[Vec<(usize, BondKind)>], mut ranks: Vec<usize>) -> Vec<usize> {
+    loop {
+        let keys: Vec<(usize, Vec<(usize, u8)>)> = (0..adj.len()).map(|i| {
+            let mut nb: Vec<(usize, u8)> = adj[i].iter().map(|&(j, k)| (ranks[j], k.code())).collect();
+            nb.sort_unstable();
+            (ranks[i], nb)
+        }).collect();
+        let next = dense(&keys);
+        let done = distinct(&next) == distinct(&ranks);
+        ranks = next;
+        if done { return ranks; }
+    }
+}
+
+impl Molecule {
+    pub fn neighbors(&self) -> Vec<Vec<(usize, BondKind)>> {
+        let mut adj = vec![Vec::new(); self.atoms.len()];
+        for b in &self.bonds { adj[b.a].push((b.b, b.kind)); adj[b.b].push((b.a, b.kind)); }
+        adj
+    }
+
+    pub fn bond_valence(&self, atom: usize) -> u8 {
+        self.bonds.iter().filter(|b| b.a == atom || b.b == atom).map(|b| b.kind.valence()).sum()
+    }
+
+    /// True if removing the bond leaves its endpoints connected.
+    pub fn bond_in_ring(&self, bond: usize) -> bool {
+        let Bond { a, b, .. } = self.bonds[bond];
+        let adj: Vec<Vec<usize>> = {
+            let mut adj = vec![Vec::new(); self.atoms.len()];
+            for (i, x) in self.bonds.iter().enumerate() { if i != bond { adj[x.a].push(x.b); adj[x.b].push(x.a); } }
+            adj
+        };
+        let mut seen = vec![false; self.atoms.len()];
+        let mut stack = vec![a];
+        seen[a] = true;
+        while let Some(u) = stack.pop() {
+            if u == b { return true; }
+            for &v in &adj[u] { if !seen[v] { seen[v] = true; stack.push(v); } }
+        }
+        false
+    }
+
+    /// The first mapping of a query graph's atoms onto distinct atoms of the molecule, if any.
+    /// `qadj` lists each query atom's neighbours with the query bond joining them; `atom_ok`
+    /// tests a query atom against an atom and `bond_ok` a query bond against a bond (by index).
+    /// The molecule may have more bonds among the matched atoms than the query.
+    pub fn find(&self, qadj: &[Vec<(usize, usize)>], atom_ok: &dyn Fn(usize, usize) -> bool, bond_ok: &dyn Fn(usize, usize) -> bool) -> Option<Vec<usize>> {
+        let mut adj = vec![Vec::new(); self.atoms.len()];
+        for (i, b) in self.bonds.iter().enumerate() { adj[b.a].push((b.b, i)); adj[b.b].push((b.a, i)); }
+        // Query atoms in breadth-first order, so each but a component's first has a neighbour placed before it.
+        let mut order: Vec<usize> = Vec::with_capacity(qadj.len());
+        for start in 0..qadj.len() {
+            if order.contains(&start) { continue; }
+            let mut i = order.len();
+            order.push(start);
+            while i < order.len() {
+                for &(q, _) in &qadj[order[i]] { if !order.contains(&q) { order.push(q); } }
+                i += 1;
+            }
+        }
+        struct Search<'a> { order: &'a [usize], adj: &'a [Vec<(usize, usize)>], qadj: &'a [Vec<(usize, usize)>], atom_ok: &'a dyn Fn(usize, usize) -> bool, bond_ok: &'a dyn Fn(usize, usize) -> bool, map: Vec<usize>, used: Vec<bool> }
+        fn extend(s: &mut Search, k: usize) -> bool {
+            let Some(&p) = s.order.get(k) else { return true };
+            let placed: Vec<(usize, usize)> = s.qadj[p].iter().filter(|e| s.map[e.0] != usize::MAX).map(|&(q, bond)| (s.map[q], bond)).collect();
+            let candidates: Vec<usize> = match placed.first() { Some(&(a, _)) => s.adj[a].iter().map(|e| e.0).collect(), None => (0..s.adj.len()).collect() };
+            for a in candidates {
+                if s.used[a] || !(s.atom_ok)(p, a) || !placed.iter().all(|&(b, qb)| s.adj[a].iter().any(|&(c, bond)| c == b && (s.bond_ok)(qb, bond))) { continue; }
+                s.map[p] = a;
+                s.used[a] = true;
+                if extend(s, k + 1) { return true; }
+                s.map[p] = usize::MAX;
+                s.used[a] = false;
+            }
+            false
+        }
+        let mut search = Search { order: &order, adj: &adj, qadj, atom_ok, bond_ok, map: vec![usize::MAX; qadj.len()], used: vec![false; self.atoms.len()] };
+        extend(&mut search, 0).then_some(search.map)
+    }
+
+    /// All simple cycles with at most `max_len` atoms, each listed once.
+    pub fn rings(&self, max_len: usize) -> Vec<Vec<usize>> {
+        let adj = self.neighbors();
+        let mut out = Vec::new();
+        fn walk(adj: &[Vec<(usize, BondKind)>], path: &mut Vec<usize>, max_len: usize, out: &mut Vec<Vec<usize>>) {
+            let (start, last) = (path[0], *path.last().unwrap());
+            for &(v, _) in &adj[last] {
+                if v == start && path.len() >= 3 && path[1] < last { out.push(path.clone()); }
+                if v > start && !path.contains(&v) && path.len() < max_len { path.push(v); walk(adj, path, max_len, out); path.pop(); }
+            }
+        }
+        for start in 0..self.atoms.len() { walk(&adj, &mut vec![start], max_len, &mut out); }
+        out
+    }
+
+    fn ring_bond(&self, x: usize, y: usize) -> Option<usize> {
+        self.bonds.iter().position(|b| (b.a == x && b.b == y) || (b.a == y && b.b == x))
+    }
+
+    /// Marks Kekulé five- and six-membered rings aromatic (benzene/pyridine-like six-rings,
+    /// pyrrole/furan/thiophene-like five-rings), iterating so fused systems are caught.
+    fn perceive_aromaticity(&mut self) {
+        let rings = self.rings(6);
+        let double_partner = |m: &Molecule, i: usize| -> Vec<usize> {
+            m.bonds.iter().filter(|b| b.kind == BondKind::Double && (b.a == i || b.b == i)).map(|b| if b.a == i { b.b } else { b.a }).collect()
+        };
+        loop {
+            let mut changed = false;
+            for ring in &rings {
+                if ring.iter().all(|&i| self.atoms[i].aromatic) { continue; }
+                let aromatic = match ring.len() {
+                    6 => ring.iter().all(|&i| {
+                        let a = &self.atoms[i];
+                        let partners = double_partner(self, i);
+                        matches!(a.element.as_str(), "C" | "N") && (a.aromatic || (partners.len() == 1 && (ring.contains(&partners[0]) || self.atoms[partners[0]].aromatic)))
+                    }),
+                    5 => {
+                        let in_ring_doubles = |i: usize| double_partner(self, i).iter().filter(|p| ring.contains(p)).count();
+                        let donors: Vec<usize> = ring.iter().copied().filter(|&i| in_ring_doubles(i) == 0).collect();
+                        donors.len() == 1
+                            && matches!(self.atoms[donors[0]].element.as_str(), "N" | "O" | "S")
+                            && double_partner(self, donors[0]).is_empty()
+                            && ring.iter().all(|&i| i == donors[0] || (matches!(self.atoms[i].element.as_str(), "C" | "N") && in_ring_doubles(i) == 1 && double_partner(self, i).len() == 1))
+                    }
+                    _ => false,
+                };
+                if !aromatic { continue; }
+                for (k, &i) in ring.iter().enumerate() {
+                    self.atoms[i].aromatic = true;
+                    if let Some(bi) = self.ring_bond(i, ring[(k + 1) % ring.len()]) { self.bonds[bi].kind = BondKind::Aromatic; }
+                }
+                changed = true;
+            }
+            if !changed { break; }
+        }
+    }
+
+    /// Atom classes from iterative neighbourhood refinement of atom invariants;
+    /// symmetry-equivalent atoms share a class.
+    pub fn symmetry_classes(&self) -> Vec<usize> {
+        let adj = self.neighbors();
+        // Degree first so the lowest-ranked atom, where writing starts, is a terminal one.
+        let inv: Vec<(usize, u8, bool, i8, u8, u16)> = self.atoms.iter().enumerate()
+            .map(|(i, a)| (adj[i].len(), atomic_number(&a.element).unwrap_or(0), a.aromatic, a.charge, a.hydrogens, a.isotope.unwrap_or(0))).collect();
+        refine(&adj, dense(&inv))
+    }
+
+    /// Canonical atom ranks: symmetry classes with remaining ties broken by lowest index.
+    pub fn canonical_ranks(&self) -> Vec<usize> {
+        let n = self.atoms.len();
+        let adj = self.neighbors();
+        let mut ranks = self.symmetry_classes();
+        loop {
+            if distinct(&ranks) == n { return ranks; }
+            let tied = (0..n).filter(|&r| ranks.iter().filter(|&&x| x == r).count() > 1).min().unwrap();
+            let pick = (0..n).find(|&i| ranks[i] == tied).unwrap();
+            let doubled: Vec<usize> = ranks.iter().enumerate().map(|(i, &r)| if i == pick { 2 * r } else { 2 * r + 1 }).collect();
+            ranks = refine(&adj, dense(&doubled));
+        }
+    }
+
+    /// Canonical SMILES (no stereochemistry). Components are written in canonical order.
+    pub fn to_canonical_smiles(&self) -> String {
+        let ranks = self.canonical_ranks();
+        let adj: Vec<Vec<(usize, BondKind)>> = self.neighbors().into_iter().map(|mut nb| { nb.sort_by_key(|&(j, _)| ranks[j]); nb }).collect();
+        let n = self.atoms.len();
+        // Pass 1: DFS to split edges into tree edges and ring closures.
+        let mut visited = vec![false; n];
+        let mut order = Vec::with_capacity(n);
+        let mut children: Vec<Vec<(usize, BondKind)>> = vec![Vec::new(); n];
+        let mut closures: Vec<Vec<(usize, BondKind)>> = vec![Vec::new(); n];
+        let mut seen_edge = std::collections::HashSet::new();
+        let mut roots: Vec<usize> = (0..n).collect();
+        roots.sort_by_key(|&i| ranks[i]);
+        let mut components = Vec::new();
+        fn dfs(u: usize, adj: &[Vec<(usize, BondKind)>], visited: &mut [bool], order: &mut Vec<usize>, children: &mut [Vec<(usize, BondKind)>], closures: &mut [Vec<(usize, BondKind)>], seen: &mut std::collections::HashSet<(usize, usize)>) {
+            visited[u] = true;
+            order.push(u);
+            for &(v, k) in &adj[u] {
+                let key = (u.min(v), u.max(v));
+                if seen.contains(&key) { continue; }
+                seen.insert(key);
+                if visited[v] { closures[v].push((u, k)); closures[u].push((v, k)); }
+                else { children[u].push((v, k)); dfs(v, adj, visited, order, children, closures, seen); }
+            }
+        }
+        for &r in &roots { if !visited[r] { components.push(r); dfs(r, &adj, &mut visited, &mut order, &mut children, &mut closures, &mut seen_edge); } }
+        let position: Vec<usize> = { let mut p = vec![0; n]; for (i, &a) in order.iter().enumerate() { p[a] = i; } p };
+        // Pass 2: emit atoms, assigning the lowest free ring digit at each opening.
+        let mut w = Writer { m: self, children, closures, position, digits: HashMap::new(), free: vec![true; 100], out: String::new() };
+        for (i, &root) in components.iter().enumerate() {
+            if i > 0 { w.out.push('.'); }
+            w.emit(root);
+        }
+        w.out
+    }
+
+    fn bond_token(&self, u: usize, v: usize, k: BondKind) -> &'static str {
+        match k {
+            BondKind::Single if self.atoms[u].aromatic && self.atoms[v].aromatic => "-",
+            BondKind::Single | BondKind::Aromatic => "",
+            BondKind::Double => "=",
+            BondKind::Triple => "#",
+        }
+    }
+
+    fn atom_token(&self, i: usize) -> String {
+        let a = &self.atoms[i];
+        let symbol = if a.aromatic { a.element.to_lowercase() } else { a.element.clone() };
+        let bare_ok = a.charge == 0 && a.isotope.is_none() && ORGANIC_SUBSET.contains(&a.element.as_str())
+            && (!a.aromatic || AROMATIC_SYMBOLS.contains(&symbol.as_str()))
+            && implicit_hydrogens(&a.element, a.aromatic, self.bond_valence(i)) == a.hydrogens;
+        if bare_ok { return symbol; }
+        let mut t = String::from("[");
+        if let Some(iso) = a.isotope { t.push_str(&iso.to_string()); }
+        t.push_str(&symbol);
+        match a.hydrogens { 0 => {}, 1 => t.push('H'), h => t.push_str(&format!("H{h}")) }
+        match a.charge { 0 => {}, 1 => t.push('+'), -1 => t.push('-'), c if c > 0 => t.push_str(&format!("+{c}")), c => t.push_str(&format!("-{}", -c)) }
+        t.push(']');
+        t
+    }
+}
+
+struct Writer<'a> { m: &'a Molecule, children: Vec<Vec<(usize, BondKind)>>, closures: Vec<Vec<(usize, BondKind)>>, position: Vec<usize>, digits: HashMap<(usize, usize), usize>, free: Vec<bool>, out: String }
+
+impl Writer<'_> {
+    fn emit(&mut self, u: usize) {
+        self.out.push_str(&self.m.atom_token(u));
+        let mut ring_partners = self.closures[u].clone();
+        ring_partners.sort_by_key(|&(v, _)| self.position[v]);
+        for (v, k) in ring_partners {
+            let key = (u.min(v), u.max(v));
+            let d = if let Some(d) = self.digits.remove(&key) { self.free[d] = true; d } else {
+                let d = (1..100).find(|&d| self.free[d]).unwrap_or(99);
+                self.free[d] = false;
+                self.digits.insert(key, d);
+                self.out.push_str(self.m.bond_token(u, v, k));
+                d
+            };
+            if d < 10 { self.out.push_str(&d.to_string()); } else { self.out.push_str(&format!("%{d}")); }
+        }
+        let kids = self.children[u].clone();
+        for (i, &(v, k)) in kids.iter().enumerate() {
+            let branch = i + 1 < kids.len();
+            if branch { self.out.push('('); }
+            self.out.push_str(self.m.bond_token(u, v, k));
+            self.emit(v);
+            if branch { self.out.push(')'); }
+        }
+    }
+}
+
+/// Parses a SMILES string into a molecule graph with explicit hydrogen counts per atom.
+pub fn parse_smiles(smiles: &str) -> Result<Molecule, String> {
+    let s = smiles.trim();
+    if s.is_empty() { return Err("empty SMILES".into()); }
+    let b = s.as_bytes();
+    let mut m = Molecule::default();
+    let mut prev: Option<usize> = None;
+    let mut branches: Vec<Option<usize>> = Vec::new();
+    let mut pending: Option<BondKind> = None;
+    let mut open_rings: HashMap<u32, (usize, Option<BondKind>)> = HashMap::new();
+    let mut i = 0;
+    let default_bond = |m: &Molecule, x: usize, y: usize| if m.atoms[x].aromatic && m.atoms[y].aromatic { BondKind::Aromatic } else { BondKind::Single };
+    let add_atom = |m: &mut Molecule, atom: Atom, prev: &mut Option<usize>, pending: &mut Option<BondKind>| {
+        m.atoms.push(atom);
+        let idx = m.atoms.len() - 1;
+        if let Some(p) = *prev {
+            let kind = pending.take().unwrap_or_else(|| default_bond(m, p, idx));
+            m.bonds.push(Bond { a: p, b: idx, kind });
+        }
+        *pending = None;
+        *prev = Some(idx);
+    };
+    while i < b.len() {
+        let c = b[i];
+        match c {
+            b'(' => { if prev.is_none() { return Err(format!("branch without an atom at {i}")); } branches.push(prev); i += 1; }
+            b')' => { prev = branches.pop().ok_or_else(|| format!("unbalanced ')' at {i}"))?; i += 1; }
+            b'-' => { pending = Some(BondKind::Single); i += 1; }
+            b'=' => { pending = Some(BondKind::Double); i += 1; }
+            b'#' => { pending = Some(BondKind::Triple); i += 1; }
+            b':' => { pending = Some(BondKind::Aromatic); i += 1; }
+            b'/' | b'\\' => { pending = Some(BondKind::Single); i += 1; }
+            b'.' => { prev = None; pending = None; i += 1; }
+            b'0'..=b'9' | b'%' => {
+                let (num, len) = if c == b'%' {
+                    let d = s.get(i + 1..i + 3).filter(|d| d.bytes().all(|x| x.is_ascii_digit())).ok_or_else(|| format!("bad ring number at {i}"))?;
+                    (d.parse::<u32>().unwrap(), 3)
+                } else { ((c - b'0') as u32, 1) };
+                let atom = prev.ok_or_else(|| format!("ring closure without an atom at {i}"))?;
+                if let Some((other, kind)) = open_rings.remove(&num) {
+                    if other == atom { return Err(format!("ring {num} closes on itself")); }
+                    let kind = pending.take().or(kind).unwrap_or_else(|| default_bond(&m, other, atom));
+                    m.bonds.push(Bond { a: other, b: atom, kind });
+                } else {
+                    open_rings.insert(num, (atom, pending.take()));
+                }
+                i += len;
+            }
+            b'[' => {
+                let end = s[i..].find(']').map(|e| i + e).ok_or("unterminated bracket atom")?;
+                let atom = parse_bracket(&s[i + 1..end]).map_err(|e| format!("{e} in [{}]", &s[i + 1..end]))?;
+                add_atom(&mut m, atom, &mut prev, &mut pending);
+                i = end + 1;
+            }
+            _ if c.is_ascii_alphabetic() => {
+                let two = s.get(i..i + 2);
+                let (sym, aromatic, len) = match two {
+                    Some("Cl") => ("Cl".to_string(), false, 2),
+                    Some("Br") => ("Br".to_string(), false, 2),
+                    _ => {
+                        let one = (c as char).to_string();
+                        if ORGANIC_SUBSET.contains(&one.as_str()) { (one, false, 1) }
+                        else if AROMATIC_SYMBOLS.contains(&one.as_str()) { (capitalize(&one), true, 1) }
+                        else { return Err(format!("unexpected '{}' at {i}", c as char)); }
+                    }
+                };
+                add_atom(&mut m, Atom { element: sym, aromatic, charge: 0, isotope: None, hydrogens: 0, bracket: false }, &mut prev, &mut pending);
+                i += len;
+            }
+            _ => return Err(format!("unexpected '{}' at {i}", c as char)),
+        }
+    }
+    if !branches.is_empty() { return Err("unbalanced '('".into()); }
+    if let Some(n) = open_rings.keys().next() { return Err(format!("ring {n} is never closed")); }
+    // An unspecified bond between aromatic atoms outside any ring (biphenyl) is single.
+    for bi in 0..m.bonds.len() {
+        if m.bonds[bi].kind == BondKind::Aromatic && !m.bond_in_ring(bi) { m.bonds[bi].kind = BondKind::Single; }
+    }
+    for i in 0..m.atoms.len() {
+        if !m.atoms[i].bracket { m.atoms[i].hydrogens = implicit_hydrogens(&m.atoms[i].element, m.atoms[i].aromatic, m.bond_valence(i)); }
+    }
+    m.perceive_aromaticity();
+    Ok(m)
+}
+
+/// Builds a molecule from an explicit graph, e.g. a molfile connection table. Each atom is
+/// (element, charge, hydrogen count); a `None` count is inferred from default valences, as
+/// for unbracketed SMILES atoms, shifted by the charge (so a bare N⁺ with one bond is NH3⁺).
+/// Aromatic bonds mark their endpoints aromatic.
+pub fn from_graph(atoms: Vec<(String, i8, Option<u8>)>, bonds: Vec<Bond>) -> Result<Molecule, String> {
+    let mut m = Molecule::default();
+    let mut counts = Vec::new();
+    for (element, charge, hydrogens) in atoms {
+        if atomic_number(&element).is_none() { return Err(format!("unknown element '{element}'")); }
+        counts.push((hydrogens.is_some(), charge));
+        let bracket = hydrogens.is_some() || charge != 0;
+        m.atoms.push(Atom { element, aromatic: false, charge, isotope: None, hydrogens: hydrogens.unwrap_or(0), bracket });
+    }
+    for b in &bonds {
+        if b.a >= m.atoms.len() || b.b >= m.atoms.len() || b.a == b.b { return Err(format!("bad bond {}-{}", b.a + 1, b.b + 1)); }
+        if b.kind == BondKind::Aromatic { m.atoms[b.a].aromatic = true; m.atoms[b.b].aromatic = true; }
+    }
+    m.bonds = bonds;
+    for (i, &(known, charge)) in counts.iter().enumerate() {
+        if known { continue; }
+        let a = &m.atoms[i];
+        // A charge shifts the valence: N⁺ takes one more bond, O⁻ and C⁺/C⁻ one fewer.
+        let shift: i16 = match a.element.as_str() { "N" | "P" | "O" | "S" | "Se" | "As" => charge as i16, _ => -(charge.abs() as i16) };
+        let used = m.bond_valence(i) as i16 - shift;
+        m.atoms[i].hydrogens = if used < 0 { 0 } else { implicit_hydrogens(&a.element, a.aromatic, used as u8) };
+    }
+    m.perceive_aromaticity();
+    Ok(m)
+}
+
+fn parse_bracket(t: &str) -> Result<Atom, String> {
+    let b = t.as_bytes();
+    let mut i = 0;
+    while i < b.len() && b[i].is_ascii_digit() { i += 1; }
+    let isotope = if i > 0 { Some(t[..i].parse::<u16>().map_err(|e| e.to_string())?) } else { None };
+    let rest = &t[i..];
+    let (element, aromatic, len) = if let Some(sym) = ["se", "as"].iter().find(|p| rest.starts_with(**p)) {
+        (capitalize(sym), true, 2)
+    } else if rest.starts_with(|ch: char| ch.is_ascii_lowercase()) {
+        let sym = &rest[..1];
+        if !AROMATIC_SYMBOLS.contains(&sym) { return Err(format!("'{sym}' is not an aromatic element")); }
+        (capitalize(sym), true, 1)
+    } else {
+        let two = rest.get(..2).filter(|s| s.as_bytes()[1].is_ascii_lowercase() && atomic_number(s).is_some());
+        let sym = two.or_else(|| rest.get(..1)).ok_or("missing element")?;
+        if atomic_number(sym).is_none() { return Err(format!("unknown element '{sym}'")); }
+        (sym.to_string(), false, sym.len())
+    };
+    i += len;
+    while i < b.len() && b[i] == b'@' { i += 1; }
+    let mut hydrogens = 0u8;
+    if i < b.len() && b[i] == b'H' {
+        i += 1;
+        let start = i;
+        while i < b.len() && b[i].is_ascii_digit() { i += 1; }
+        hydrogens = if i > start { t[start..i].parse().map_err(|_| "bad hydrogen count")? } else { 1 };
+    }
+    let mut charge = 0i8;
+    if i < b.len() && (b[i] == b'+' || b[i] == b'-') {
+        let sign: i8 = if b[i] == b'+' { 1 } else { -1 };
+        let sym = b[i];
+        i += 1;
+        let start = i;
+        while i < b.len() && b[i].is_ascii_digit() { i += 1; }
+        charge = if i > start { sign * t[start..i].parse::<i8>().map_err(|_| "bad charge")? } else {
+            let mut n = 1;
+            while i < b.len() && b[i] == sym { n += 1; i += 1; }
+            sign * n
+        };
+    }
+    if i < b.len() && b[i] == b':' { i = b.len(); } // atom class, ignored
+    if i != b.len() { return Err(format!("unexpected '{}'", &t[i..])); }
+    Ok(Atom { element, aromatic, charge, isotope, hydrogens, bracket: true })
+}
+
+/// Parses and re-writes a SMILES string in canonical form.
+pub fn canonicalize(smiles: &str) -> Result<String, String> { parse_smiles(smiles).map(|m| m.to_canonical_smiles()) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spellings_of_one_molecule_canonicalize_alike() {
+        assert_eq!(canonicalize("C1=CC=CC=C1").unwrap(), canonicalize("c1ccccc1").unwrap());
+        assert_eq!(canonicalize("OCC").unwrap(), canonicalize("C(C)O").unwrap());
+        assert_eq!(canonicalize("c1ccc(cc1)c1ccccc1").unwrap(), canonicalize("c1ccccc1-c1ccccc1").unwrap());
+        assert_eq!(canonicalize("O.CC").unwrap(), canonicalize("CC.O").unwrap());
+    }
+
+    #[test]
+    fn hydrogens_charges_and_isotopes() {
+        let m = parse_smiles("CCO").unwrap();
+        assert_eq!(m.atoms.iter().map(|a| a.hydrogens).collect::<Vec<_>>(), [3, 2, 1]);
+        let m = parse_smiles("[NH4+]").unwrap();
+        assert_eq!((m.atoms[0].element.as_str(), m.atoms[0].hydrogens, m.atoms[0].charge), ("N", 4, 1));
+        let m = parse_smiles("[13CH3]C(=O)[O-]").unwrap();
+        assert_eq!(m.atoms[0].isotope, Some(13));
+        assert_eq!((m.atoms[3].charge, m.atoms[3].hydrogens), (-1, 0));
+        assert_eq!(m.bonds[1].kind, BondKind::Double);
+    }
+
+    #[test]
+    fn malformed_smiles_is_refused() {
+        for bad in ["", "C1CC", "C(C", "CC)", "[Xx]", "C%1"] { assert!(parse_smiles(bad).is_err(), "{bad}"); }
+    }
+}
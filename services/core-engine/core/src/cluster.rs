@@ -0,0 +1,68 @@
+//! Butina clustering and diversity selection of screening hits.
+//!
+//! Hits are compared by the Tanimoto similarity of their Morgan fingerprints (see
+//! `fingerprint`). Butina's algorithm (J. Chem. Inf. Comput. Sci. 1999) takes the hit with the
+//! most neighbours at or above the similarity threshold as a centroid, puts it and its
+//! unassigned neighbours in a cluster, and repeats on what is left, so every hit lands in
+//! exactly one cluster and singletons come last. A cluster's representative is its tightest
+//! binder. A diverse pick takes the representatives first, best binder first, then each
+//! cluster's second-best member and so on, so one scaffold's analogs only fill the list once
+//! every cluster has contributed.
+//!
+//! `butina_by` runs the same algorithm on any neighbour test; protein structures are clustered
+//! with it by TM-score (see the service's `superpose`).
+
+use serde::Serialize;
+
+use crate::fingerprint::{self, Fingerprint};
+
+pub const DEFAULT_SIMILARITY: f64 = 0.6;
+pub const MAX_DIVERSE: usize = 100;
+/// A diverse pick of `n` docks this many times `n` candidates to choose from.
+pub const POOL_FACTOR: usize = 5;
+
+#[derive(Serialize, Clone)]
+pub struct HitCluster { pub cluster_id: usize, pub size: usize, pub centroid: String, pub representative: String, pub members: Vec<String> }
+
+/// Clusters of `fps` as indices, each starting with its centroid, largest first.
+pub fn butina(fps: &[Fingerprint], similarity: f64) -> Vec<Vec<usize>> {
+    butina_by(fps.len(), |i, j| fingerprint::tanimoto(&fps[i], &fps[j]) >= similarity)
+}
+
+/// Butina clusters of `n` items, `similar` saying which pairs are neighbours.
+pub fn butina_by(n: usize, similar: impl Fn(usize, usize) -> bool) -> Vec<Vec<usize>> {
+    let neighbours: Vec<Vec<usize>> = (0..n).map(|i| (0..n).filter(|&j| j != i && similar(i, j)).collect()).collect();
+    let mut assigned = vec![false; n];
+    let mut clusters = Vec::new();
+    while let Some(centroid) = (0..n).filter(|&i| !assigned[i]).max_by_key(|&i| (neighbours[i].iter().filter(|&&j| !assigned[j]).count(), std::cmp::Reverse(i))) {
+        let mut cluster = vec![centroid];
+        cluster.extend(neighbours[centroid].iter().copied().filter(|&j| !assigned[j]));
+        for &i in &cluster { assigned[i] = true; }
+        clusters.push(cluster);
+    }
+    clusters
+}
+
+/// Up to `n` hit indices, one per cluster per round, each round's picks tightest binder
+/// (lowest `affinity`) first.
+pub fn diverse(clusters: &[Vec<usize>], affinity: &[f64], n: usize) -> Vec<usize> {
+    let mut ranked: Vec<Vec<usize>> = clusters.iter().map(|c| { let mut c = c.clone(); c.sort_by(|&a, &b| affinity[a].total_cmp(&affinity[b])); c }).collect();
+    ranked.sort_by(|a, b| affinity[a[0]].total_cmp(&affinity[b[0]]));
+    let mut picked = Vec::new();
+    for round in 0.. {
+        let mut picks: Vec<usize> = ranked.iter().filter_map(|c| c.get(round).copied()).collect();
+        if picks.is_empty() { break; }
+        picks.sort_by(|&a, &b| affinity[a].total_cmp(&affinity[b]));
+        picked.extend(picks.into_iter().take(n - picked.len()));
+        if picked.len() == n { break; }
+    }
+    picked
+}
+
+/// The clusters as reported, with `ids` naming the hits and `affinity` picking representatives.
+pub fn report(clusters: &[Vec<usize>], ids: &[String], affinity: &[f64]) -> Vec<HitCluster> {
+    clusters.iter().enumerate().map(|(k, c)| {
+        let best = c.iter().copied().min_by(|&a, &b| affinity[a].total_cmp(&affinity[b])).unwrap_or(c[0]);
+        HitCluster { cluster_id: k + 1, size: c.len(), centroid: ids[c[0]].clone(), representative: ids[best].clone(), members: c.iter().map(|&i| ids[i].clone()).collect() }
+    }).collect()
+}
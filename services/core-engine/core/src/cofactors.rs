@@ -0,0 +1,126 @@
+//! Parameters for metal ions and common cofactors.
+//!
+//! Metal ions carry their ion charge, a 12-6 radius and well depth (after Li and Merz's
+//! hydration free energy set) and the distances at which they bind oxygen or nitrogen and
+//! sulfur. In pose work (see `forcefield::Metals`) a ligand N, O or S is drawn to that distance
+//! and any other atom meets the ion's radius, where the generic heavy-atom term would treat a
+//! zinc as a carbon and count each coordinating atom as a clash. Cofactors are recognized by
+//! their PDB chemical component ID and matched atom for atom to a template, the component at
+//! pH 7 as SMILES, from which they take their bond orders, formal charges and hydrogens. A
+//! hetero group that is neither, or a cofactor with atoms missing, has no parameters, and every
+//! endpoint that reads one says so rather than passing over it.
+
+use crate::{chem::{self, Bond, BondKind, Molecule}, pdbqt::{self, Residue}};
+
+/// A metal ion: charge, Rmin/2 (Å) and ε (kcal/mol), and its coordination distances (Å) to
+/// O or N and to S.
+pub struct Metal { pub element: &'static str, pub charge: i8, pub rmin_half: f64, pub epsilon: f64, pub to_on: f64, pub to_s: f64 }
+
+const METALS: [Metal; 12] = [
+    Metal { element: "Zn", charge: 2, rmin_half: 1.271, epsilon: 0.0033, to_on: 2.07, to_s: 2.31 },
+    Metal { element: "Mg", charge: 2, rmin_half: 1.360, epsilon: 0.0102, to_on: 2.09, to_s: 2.55 },
+    Metal { element: "Fe", charge: 2, rmin_half: 1.409, epsilon: 0.0169, to_on: 2.08, to_s: 2.30 },
+    Metal { element: "Ca", charge: 2, rmin_half: 1.649, epsilon: 0.0975, to_on: 2.39, to_s: 2.80 },
+    Metal { element: "Mn", charge: 2, rmin_half: 1.407, epsilon: 0.0167, to_on: 2.19, to_s: 2.50 },
+    Metal { element: "Co", charge: 2, rmin_half: 1.299, epsilon: 0.0057, to_on: 2.10, to_s: 2.30 },
+    Metal { element: "Ni", charge: 2, rmin_half: 1.255, epsilon: 0.0036, to_on: 2.08, to_s: 2.30 },
+    Metal { element: "Cu", charge: 2, rmin_half: 1.218, epsilon: 0.0025, to_on: 2.00, to_s: 2.25 },
+    Metal { element: "Cd", charge: 2, rmin_half: 1.412, epsilon: 0.0172, to_on: 2.30, to_s: 2.54 },
+    Metal { element: "Hg", charge: 2, rmin_half: 1.446, epsilon: 0.0241, to_on: 2.40, to_s: 2.45 },
+    Metal { element: "Na", charge: 1, rmin_half: 1.475, epsilon: 0.0306, to_on: 2.41, to_s: 2.90 },
+    Metal { element: "K", charge: 1, rmin_half: 1.705, epsilon: 0.1937, to_on: 2.84, to_s: 3.30 },
+];
+
+/// A cofactor: its chemical component ID, name and pH 7 structure.
+pub struct Cofactor { pub code: &'static str, pub name: &'static str, smiles: &'static str }
+
+const COFACTORS: [Cofactor; 14] = [
+    Cofactor { code: "HEM", name: "heme b", smiles: "CC1=C(CCC(=O)[O-])C2=CC3=NC(=CC4=C(C)C(C=C)=C([N-]4)C=C4N=C(C=C1[N-]2)C(C)=C4C=C)C(C)=C3CCC(=O)[O-].[Fe+2]" },
+    Cofactor { code: "NAD", name: "NAD+", smiles: "NC(=O)c1ccc[n+](c1)C1OC(COP(=O)([O-])OP(=O)([O-])OCC2OC(n3cnc4c(N)ncnc43)C(O)C2O)C(O)C1O" },
+    Cofactor { code: "NAI", name: "NADH", smiles: "NC(=O)C1=CN(C=CC1)C1OC(COP(=O)([O-])OP(=O)([O-])OCC2OC(n3cnc4c(N)ncnc43)C(O)C2O)C(O)C1O" },
+    Cofactor { code: "NAP", name: "NADP+", smiles: "NC(=O)c1ccc[n+](c1)C1OC(COP(=O)([O-])OP(=O)([O-])OCC2OC(n3cnc4c(N)ncnc43)C(OP(=O)([O-])[O-])C2O)C(O)C1O" },
+    Cofactor { code: "NDP", name: "NADPH", smiles: "NC(=O)C1=CN(C=CC1)C1OC(COP(=O)([O-])OP(=O)([O-])OCC2OC(n3cnc4c(N)ncnc43)C(OP(=O)([O-])[O-])C2O)C(O)C1O" },
+    Cofactor { code: "FAD", name: "FAD", smiles: "CC1=CC2=C(C=C1C)N(CC(O)C(O)C(O)COP(=O)([O-])OP(=O)([O-])OCC1OC(n3cnc4c(N)ncnc43)C(O)C1O)C1=NC(=O)NC(=O)C1=N2" },
+    Cofactor { code: "FMN", name: "FMN", smiles: "CC1=CC2=C(C=C1C)N(CC(O)C(O)C(O)COP(=O)([O-])[O-])C1=NC(=O)NC(=O)C1=N2" },
+    Cofactor { code: "ATP", name: "ATP", smiles: "Nc1ncnc2c1ncn2C1OC(COP(=O)([O-])OP(=O)([O-])OP(=O)([O-])[O-])C(O)C1O" },
+    Cofactor { code: "ADP", name: "ADP", smiles: "Nc1ncnc2c1ncn2C1OC(COP(=O)([O-])OP(=O)([O-])[O-])C(O)C1O" },
+    Cofactor { code: "AMP", name: "AMP", smiles: "Nc1ncnc2c1ncn2C1OC(COP(=O)([O-])[O-])C(O)C1O" },
+    Cofactor { code: "GTP", name: "GTP", smiles: "NC1=NC2=C(N=CN2C2OC(COP(=O)([O-])OP(=O)([O-])OP(=O)([O-])[O-])C(O)C2O)C(=O)N1" },
+    Cofactor { code: "GDP", name: "GDP", smiles: "NC1=NC2=C(N=CN2C2OC(COP(=O)([O-])OP(=O)([O-])[O-])C(O)C2O)C(=O)N1" },
+    Cofactor { code: "SAM", name: "S-adenosylmethionine", smiles: "C[S+](CCC([NH3+])C(=O)[O-])CC1OC(n2cnc3c(N)ncnc32)C(O)C1O" },
+    Cofactor { code: "SAH", name: "S-adenosylhomocysteine", smiles: "[NH3+]C(CCSCC1OC(n2cnc3c(N)ncnc32)C(O)C1O)C(=O)[O-]" },
+];
+
+/// Elements the charge models and force field have parameters for.
+const ORGANIC: [&str; 10] = ["H", "C", "N", "O", "S", "P", "F", "Cl", "Br", "I"];
+
+pub fn metal(element: &str) -> Option<&'static Metal> { METALS.iter().find(|m| m.element == element) }
+
+pub fn cofactor(code: &str) -> Option<&'static Cofactor> { COFACTORS.iter().find(|c| c.code == code) }
+
+/// Whether a residue is a lone metal ion.
+pub fn is_ion(r: &Residue) -> bool { r.atoms.len() == 1 && metal(&r.atoms[0].1).is_some() }
+
+/// Formal charge and hydrogen count of each atom, and the order of each bond.
+pub type Assigned = (Vec<(i8, u8)>, Vec<BondKind>);
+
+/// The charges, hydrogens and bond orders of a cofactor's non-metal heavy atoms and the
+/// `bonds` between them, from its template. `None` when the atoms don't match the template
+/// one for one.
+pub fn assign(c: &Cofactor, elements: &[String], bonds: &[Bond]) -> Option<Assigned> {
+    let template = chem::parse_smiles(c.smiles).ok()?;
+    let heavy: Vec<usize> = (0..template.atoms.len()).filter(|&i| metal(&template.atoms[i].element).is_none()).collect();
+    if heavy.len() != elements.len() { return None; }
+    let index = |i: usize| heavy.iter().position(|&h| h == i);
+    let mut qadj = vec![Vec::new(); heavy.len()];
+    for (k, b) in template.bonds.iter().enumerate() {
+        let (Some(a), Some(b)) = (index(b.a), index(b.b)) else { continue };
+        qadj[a].push((b, k));
+        qadj[b].push((a, k));
+    }
+    let target = chem::from_graph(elements.iter().map(|e| (e.clone(), 0, Some(0))).collect(), bonds.to_vec()).ok()?;
+    let map = target.find(&qadj, &|q, t| template.atoms[heavy[q]].element == target.atoms[t].element, &|_, _| true)?;
+    let mut states = vec![(0, 0); elements.len()];
+    for (q, &t) in map.iter().enumerate() { states[t] = (template.atoms[heavy[q]].charge, template.atoms[heavy[q]].hydrogens); }
+    let from = |t: usize| map.iter().position(|&m| m == t).map(|q| heavy[q]);
+    let kinds = bonds.iter().map(|b| {
+        let (Some(a), Some(z)) = (from(b.a), from(b.b)) else { return BondKind::Single };
+        template.bonds.iter().find(|t| (t.a == a && t.b == z) || (t.a == z && t.b == a)).map_or(BondKind::Single, |t| t.kind)
+    }).collect();
+    Some((states, kinds))
+}
+
+/// Names of the hetero groups among `residues` that have no parameters: neither amino acids,
+/// waters, metal ions nor templated cofactors.
+pub fn unparameterized(residues: &[Residue]) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for r in residues {
+        let known = pdbqt::TEMPLATED.contains(&r.name.as_str()) || pdbqt::WATERS.contains(&r.name.as_str()) || is_ion(r) || cofactor(&r.name).is_some();
+        if !known && !names.contains(&r.name) { names.push(r.name.clone()); }
+    }
+    names
+}
+
+/// Atoms of a molecule the charge models have no parameters for: elements other than H, C, N,
+/// O, S, P and the halogens, except metal ions standing free.
+pub fn unparameterized_atoms(m: &Molecule) -> Vec<String> {
+    let adj = m.neighbors();
+    m.atoms.iter().enumerate().filter(|(i, a)| !ORGANIC.contains(&a.element.as_str()) && (!adj[*i].is_empty() || metal(&a.element).is_none()))
+        .map(|(i, a)| format!("{}{}", a.element, i + 1)).collect()
+}
+
+/// A receptor for pose work: heavy atoms for the generic term, metal ions apart (heme iron
+/// included), and warnings for hetero groups without parameters.
+pub struct Receptor { pub atoms: Vec<[f64; 3]>, pub metals: Vec<([f64; 3], &'static Metal)>, pub warnings: Vec<String> }
+
+/// The receptor in a PDB file (first model, no waters or hydrogens).
+pub fn receptor(text: &str) -> Receptor {
+    let residues = pdbqt::parse_residues(text, false);
+    let (mut atoms, mut metals) = (Vec::new(), Vec::new());
+    for a in residues.iter().flat_map(|r| &r.atoms) {
+        match metal(&a.1) { Some(m) => metals.push((a.2, m)), None => atoms.push(a.2) }
+    }
+    let unknown = unparameterized(&residues);
+    let warnings = if unknown.is_empty() { Vec::new() } else { vec![format!("{} have no parameters; their atoms are scored as generic heavy atoms", unknown.join(", "))] };
+    Receptor { atoms, metals, warnings }
+}
@@ -0,0 +1,61 @@
+//! What a `/simulate` run actually simulated.
+//!
+//! The `system` block counts the atoms (hydrogens included, implicit or not), the peptide
+//! residues perceived along the chain (see `selection::residues`) by name, and the separate
+//! molecules of the input. Water molecules among them are reported as solvent and charged
+//! single atoms as ions, with the formal charge of the whole system. The engine runs in vacuo
+//! without periodic boundaries: it neither solvates nor neutralizes, so there is no box and every
+//! solvent molecule or ion counted came with the input. The extent is the starting conformer's
+//! bounding box, the space a box would at least have to hold.
+
+use serde::Serialize;
+
+use crate::{chem, conformer, depict, fnv1a, resolver, selection};
+
+#[derive(Serialize, Clone)]
+pub struct Count { pub name: String, pub count: usize }
+
+#[derive(Serialize, Clone)]
+pub struct SystemReport {
+    pub atoms: usize, pub heavy_atoms: usize, pub hydrogens: usize, pub residues: usize, pub residue_counts: Vec<Count>, pub molecules: usize,
+    pub solvent: &'static str, pub solvent_molecules: usize, pub ions: Vec<Count>, pub ions_added: usize, pub periodic: bool,
+    #[serde(skip_serializing_if = "Option::is_none")] pub box_angstrom: Option<[f64; 3]>, pub extent_angstrom: [f64; 3], pub total_charge: i32,
+}
+
+fn tally(counts: &mut Vec<Count>, name: String) {
+    match counts.iter_mut().find(|c| c.name == name) { Some(c) => c.count += 1, None => counts.push(Count { name, count: 1 }) }
+}
+
+/// The composition of `mol`, or `None` when it doesn't resolve to a structure.
+pub fn report(mol: &resolver::Resolved) -> Option<SystemReport> {
+    let smiles = mol.canonical_smiles.as_deref()?;
+    let m = chem::parse_smiles(smiles).ok()?;
+    let hydrogens = m.atoms.iter().map(|a| a.hydrogens as usize + usize::from(a.element == "H")).sum::<usize>();
+    let heavy_atoms = m.atoms.iter().filter(|a| a.element != "H").count();
+    let residues = selection::residues(&m);
+    let mut residue_counts = Vec::new();
+    for r in &residues { tally(&mut residue_counts, r.name.to_string()); }
+    residue_counts.sort_by(|a, b| a.name.cmp(&b.name));
+    let components = depict::components(&m.neighbors());
+    let mut ions = Vec::new();
+    let mut solvent_molecules = 0;
+    for comp in &components {
+        let [i] = comp.as_slice() else { continue };
+        let a = &m.atoms[*i];
+        if a.element == "O" && a.hydrogens == 2 && a.charge == 0 { solvent_molecules += 1; }
+        else if a.charge != 0 {
+            let sign = if a.charge > 0 { "+" } else { "-" };
+            tally(&mut ions, format!("{}{}{sign}", a.element, if a.charge.abs() > 1 { a.charge.abs().to_string() } else { String::new() }));
+        }
+    }
+    let x = conformer::embed(&m, fnv1a(smiles.as_bytes()));
+    let mut extent = [0.0; 3];
+    for (k, e) in extent.iter_mut().enumerate() {
+        let (lo, hi) = x.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), p| (lo.min(p[k]), hi.max(p[k])));
+        *e = if x.is_empty() { 0.0 } else { ((hi - lo) * 1e3).round() / 1e3 };
+    }
+    Some(SystemReport {
+        atoms: heavy_atoms + hydrogens, heavy_atoms, hydrogens, residues: residues.len(), residue_counts, molecules: components.len(),
+        solvent: "vacuum", solvent_molecules, ions, ions_added: 0, periodic: false, box_angstrom: None, extent_angstrom: extent, total_charge: m.atoms.iter().map(|a| a.charge as i32).sum(),
+    })
+}
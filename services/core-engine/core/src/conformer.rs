@@ -0,0 +1,187 @@
+//! 3D conformer embedding.
+//!
+//! Heavy atoms only; hydrogens stay implicit. Target interatomic distances come from ideal
+//! bond lengths (1-2), hybridization angles (1-3) and regular-polygon chords within small
+//! rings; atoms further apart only get a minimum separation. The 2D depiction layout,
+//! lifted off the plane by a seeded perturbation, is relaxed onto those targets by 3D stress
+//! majorization. Macrocycles (cyclic and stapled peptides, macrolides) relax much more slowly
+//! and can pucker many ways, so they get longer relaxations from several seeded starts, and
+//! the least strained one is kept.
+
+use crate::{chem::{BondKind, Molecule}, depict, fnv1a, forcefield};
+
+const ITERATIONS: usize = 200;
+/// Rings at least this large are macrocycles.
+pub const MACROCYCLE: usize = 12;
+const MACROCYCLE_ITERATIONS: usize = 1000;
+const MACROCYCLE_STARTS: u64 = 4;
+
+/// Single-bond covalent radius in Å.
+pub fn covalent_radius(element: &str) -> f64 {
+    match element { "H" => 0.31, "C" => 0.76, "N" => 0.71, "O" => 0.66, "F" => 0.57, "P" => 1.07, "S" => 1.05, "Cl" => 1.02, "Br" => 1.20, "I" => 1.39, "B" => 0.84, "Si" => 1.11, "Se" => 1.20, _ => 1.2 }
+}
+
+/// Ideal bond length in Å, from element covalent radii shortened by bond order.
+pub fn bond_length(mol: &Molecule, a: usize, b: usize, kind: BondKind) -> f64 {
+    let single = covalent_radius(&mol.atoms[a].element) + covalent_radius(&mol.atoms[b].element);
+    single * match kind { BondKind::Single => 1.0, BondKind::Aromatic => 0.91, BondKind::Double => 0.87, BondKind::Triple => 0.78 }
+}
+
+/// Ideal bond angle in radians at `atom`, from its hybridization.
+pub fn bond_angle(mol: &Molecule, adj: &[Vec<(usize, BondKind)>], atom: usize) -> f64 {
+    let triple = adj[atom].iter().any(|&(_, k)| k == BondKind::Triple);
+    let doubles = adj[atom].iter().filter(|&&(_, k)| k == BondKind::Double).count();
+    let degree = adj[atom].len() + mol.atoms[atom].hydrogens as usize;
+    let deg: f64 = if triple || (doubles >= 2 && adj[atom].len() == 2) { 180.0 } else if doubles == 1 || mol.atoms[atom].aromatic || (degree <= 3 && mol.atoms[atom].element == "N" && adj[atom].iter().any(|&(n, _)| mol.atoms[n].aromatic)) { 120.0 } else { 109.5 };
+    deg.to_radians()
+}
+
+/// A target distance between two atoms. Distant pairs are only lower bounds, keeping atoms
+/// apart without pinning the molecule to one extended shape.
+#[derive(Clone, Copy)]
+pub struct Restraint { pub i: usize, pub j: usize, pub target: f64, pub weight: f64, pub lower_only: bool }
+
+pub fn restraints(mol: &Molecule) -> Vec<Restraint> {
+    let n = mol.atoms.len();
+    let adj = mol.neighbors();
+    // (target, weight, lower_only) per pair; graph distances first, as the extended-chain fallback.
+    let mut d = vec![vec![(0.0, 0.0, true); n]; n];
+    for i in 0..n {
+        let mut hops = vec![usize::MAX; n];
+        hops[i] = 0;
+        let mut queue = std::collections::VecDeque::from([i]);
+        while let Some(u) = queue.pop_front() {
+            for &(v, _) in &adj[u] { if hops[v] == usize::MAX { hops[v] = hops[u] + 1; queue.push_back(v); } }
+        }
+        for j in 0..n {
+            d[i][j] = match hops[j] { 0 => (0.0, 0.0, true), usize::MAX => (4.0, 1.0, true), 3 => (2.9, 1.0, true), _ => (3.2, 1.0, true) };
+        }
+    }
+    for b in &mol.bonds {
+        let len = bond_length(mol, b.a, b.b, b.kind);
+        d[b.a][b.b] = (len, 100.0, false);
+        d[b.b][b.a] = (len, 100.0, false);
+    }
+    for c in 0..n {
+        let theta = bond_angle(mol, &adj, c);
+        for (x, &(i, _)) in adj[c].iter().enumerate() {
+            for &(j, _) in &adj[c][x + 1..] {
+                let (a, b) = (d[c][i].0, d[c][j].0);
+                let len = (a * a + b * b - 2.0 * a * b * theta.cos()).sqrt();
+                d[i][j] = (len, 30.0, false);
+                d[j][i] = (len, 30.0, false);
+            }
+        }
+    }
+    // Within a ring, atoms sit on a regular polygon; the smallest ring wins for fused systems.
+    let mut rings = mol.rings(8);
+    rings.sort_by_key(|r| std::cmp::Reverse(r.len()));
+    for ring in &rings {
+        let m = ring.len();
+        let side = (0..m).map(|x| d[ring[x]][ring[(x + 1) % m]].0).sum::<f64>() / m as f64;
+        for x in 0..m {
+            for y in x + 1..m {
+                let k = (y - x).min(m - (y - x));
+                let chord = side * (std::f64::consts::PI * k as f64 / m as f64).sin() / (std::f64::consts::PI / m as f64).sin();
+                if k > 1 { d[ring[x]][ring[y]] = (chord, 30.0, false); d[ring[y]][ring[x]] = (chord, 30.0, false); }
+            }
+        }
+    }
+    let mut out = Vec::new();
+    for (i, row) in d.iter().enumerate() {
+        for (j, &(target, weight, lower_only)) in row.iter().enumerate().skip(i + 1) {
+            if weight > 0.0 { out.push(Restraint { i, j, target, weight, lower_only }); }
+        }
+    }
+    out
+}
+
+/// Rings of at least `MACROCYCLE` atoms: for each bond, the smallest ring through it when
+/// that is so large, each ring listed once.
+pub fn macrocycles(mol: &Molecule) -> Vec<Vec<usize>> {
+    let adj = mol.neighbors();
+    let mut out: Vec<Vec<usize>> = Vec::new();
+    for b in &mol.bonds {
+        // The shortest path from one end to the other that doesn't use the bond itself.
+        let mut prev = vec![usize::MAX; mol.atoms.len()];
+        prev[b.a] = b.a;
+        let mut queue = std::collections::VecDeque::from([b.a]);
+        while let Some(u) = queue.pop_front() {
+            if u == b.b { break; }
+            for &(v, _) in &adj[u] {
+                if prev[v] == usize::MAX && !(u == b.a && v == b.b) { prev[v] = u; queue.push_back(v); }
+            }
+        }
+        if prev[b.b] == usize::MAX { continue; }
+        let mut ring = vec![b.b];
+        while *ring.last().unwrap() != b.a { ring.push(prev[*ring.last().unwrap()]); }
+        if ring.len() < MACROCYCLE { continue; }
+        let mut key = ring.clone();
+        key.sort_unstable();
+        if !out.iter().any(|r| { let mut k = r.clone(); k.sort_unstable(); k == key }) { out.push(ring); }
+    }
+    out
+}
+
+/// Heavy-atom coordinates in Å, centred on the origin. `seed` picks among conformers.
+pub fn embed(mol: &Molecule, seed: u64) -> Vec<[f64; 3]> {
+    let restraints = restraints(mol);
+    if macrocycles(mol).is_empty() { return relax(mol, &restraints, seed, ITERATIONS); }
+    let stress = |x: &[[f64; 3]]| forcefield::internal_energy(&restraints, x, None);
+    (0..MACROCYCLE_STARTS).map(|k| relax(mol, &restraints, seed.wrapping_add(k.wrapping_mul(0x9E37_79B9_7F4A_7C15)), MACROCYCLE_ITERATIONS))
+        .min_by(|a, b| stress(a).total_cmp(&stress(b))).unwrap_or_default()
+}
+
+/// Stress majorization from the seeded, lifted depiction layout.
+fn relax(mol: &Molecule, restraints: &[Restraint], seed: u64, iterations: usize) -> Vec<[f64; 3]> {
+    let n = mol.atoms.len();
+    let mut by_atom: Vec<Vec<(usize, f64, f64, bool)>> = vec![Vec::new(); n];
+    for r in restraints {
+        by_atom[r.i].push((r.j, r.target, r.weight, r.lower_only));
+        by_atom[r.j].push((r.i, r.target, r.weight, r.lower_only));
+    }
+    let flat = depict::layout(mol);
+    let mut pos: Vec<[f64; 3]> = flat.atoms.iter().enumerate().map(|(i, a)| {
+        let z = (fnv1a(&(seed ^ (i as u64).wrapping_mul(0x9E37_79B9)).to_le_bytes()) % 1000) as f64 / 1000.0 - 0.5;
+        [a.x * 1.45, a.y * 1.45, z * 0.8]
+    }).collect();
+    for _ in 0..iterations {
+        for i in 0..n {
+            let (mut acc, mut sw) = ([0.0; 3], 0.0);
+            for &(j, t, w, lower_only) in &by_atom[i] {
+                let delta = [pos[i][0] - pos[j][0], pos[i][1] - pos[j][1], pos[i][2] - pos[j][2]];
+                let len = (delta[0] * delta[0] + delta[1] * delta[1] + delta[2] * delta[2]).sqrt().max(1e-6);
+                // A satisfied lower bound votes for staying put.
+                let t = if lower_only { t.max(len) } else { t };
+                for k in 0..3 { acc[k] += w * (pos[j][k] + t * delta[k] / len); }
+                sw += w;
+            }
+            if sw > 0.0 { pos[i] = [acc[0] / sw, acc[1] / sw, acc[2] / sw]; }
+        }
+    }
+    centre(&mut pos);
+    pos
+}
+
+pub fn centre(pos: &mut [[f64; 3]]) {
+    let n = pos.len().max(1) as f64;
+    let c = pos.iter().fold([0.0; 3], |c, p| [c[0] + p[0] / n, c[1] + p[1] / n, c[2] + p[2] / n]);
+    for p in pos.iter_mut() { for k in 0..3 { p[k] -= c[k]; } }
+}
+
+/// Rotate by the unit quaternion derived from `seed`, then translate to `to`.
+pub fn place(pos: &mut [[f64; 3]], seed: u64, to: [f64; 3]) {
+    let u = |shift: u32| (seed.rotate_left(shift) % 10_000) as f64 / 10_000.0;
+    let (u1, u2, u3) = (u(0), u(21), u(42));
+    let tau = std::f64::consts::TAU;
+    let (w, x, y, z) = ((1.0 - u1).sqrt() * (tau * u2).sin(), (1.0 - u1).sqrt() * (tau * u2).cos(), u1.sqrt() * (tau * u3).sin(), u1.sqrt() * (tau * u3).cos());
+    let r = [
+        [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - z * w), 2.0 * (x * z + y * w)],
+        [2.0 * (x * y + z * w), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - x * w)],
+        [2.0 * (x * z - y * w), 2.0 * (y * z + x * w), 1.0 - 2.0 * (x * x + y * y)],
+    ];
+    for p in pos.iter_mut() {
+        let q = *p;
+        for k in 0..3 { p[k] = r[k][0] * q[0] + r[k][1] * q[1] + r[k][2] * q[2] + to[k]; }
+    }
+}
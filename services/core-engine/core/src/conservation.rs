@@ -0,0 +1,93 @@
+//! Per-residue conservation from a multiple sequence alignment.
+//!
+//! The alignment comes with the prediction request as FASTA or A3M text (lowercase A3M
+//! insertions are dropped), the query first. Sequences are weighted by 1/(number of
+//! sequences ≥ 80% identical) so near-duplicates don't dominate; each query column's
+//! conservation is 1 − H/log2(21), H being the weighted Shannon entropy over the 20 amino
+//! acids and gap, scaled down by the column's gap fraction. Domain confidence moves with
+//! how conserved the domain is relative to the whole chain, and highly conserved catalytic
+//! and structural residues are flagged as likely functional sites.
+
+use serde::Serialize;
+
+use crate::predict::DomainInfo;
+
+const SYMBOLS: &[u8] = b"ARNDCQEGHILKMFPSTWYV-";
+const CLUSTER_IDENTITY: f64 = 0.8;
+const FUNCTIONAL_CONSERVATION: f64 = 0.8;
+/// Confidence shift per unit of conservation above or below the chain mean.
+const CONFIDENCE_WEIGHT: f64 = 0.5;
+
+#[derive(Serialize, Clone)]
+pub struct FunctionalSite { pub position: usize, pub residue: char, pub conservation: f64, pub role: &'static str }
+#[derive(Serialize, Clone)]
+pub struct ConservationReport { pub sequences: usize, pub effective_sequences: f64, pub mean_conservation: f64, pub scores: Vec<f64>, pub functional_sites: Vec<FunctionalSite> }
+
+/// Aligned rows (query first), each as long as the query, from FASTA/A3M or one row per line.
+pub fn parse(text: &str, query: &str) -> Result<Vec<Vec<u8>>, String> {
+    let mut rows: Vec<String> = Vec::new();
+    let fasta = text.trim_start().starts_with('>');
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+        if fasta {
+            if line.starts_with('>') { rows.push(String::new()); } else if let Some(last) = rows.last_mut() { last.push_str(line); }
+        } else {
+            rows.push(line.into());
+        }
+    }
+    let rows: Vec<Vec<u8>> = rows.iter().map(|r| r.bytes().filter(|b| !b.is_ascii_lowercase() && *b != b'.').map(|b| if b == b'X' { b'-' } else { b }).collect()).collect();
+    let first = rows.first().ok_or("msa has no sequences")?;
+    let degapped: Vec<u8> = first.iter().copied().filter(|&b| b != b'-').collect();
+    if !degapped.eq_ignore_ascii_case(query.as_bytes()) { return Err("the first msa sequence must be the query sequence".into()); }
+    if let Some(k) = rows.iter().position(|r| r.len() != first.len()) { return Err(format!("msa sequence {} has {} columns, expected {}", k + 1, rows[k].len(), first.len())); }
+    // Keep only the columns where the query has a residue.
+    let columns: Vec<usize> = (0..first.len()).filter(|&c| first[c] != b'-').collect();
+    Ok(rows.iter().map(|r| columns.iter().map(|&c| r[c]).collect()).collect())
+}
+
+fn weights(rows: &[Vec<u8>]) -> Vec<f64> {
+    let identity = |a: &[u8], b: &[u8]| {
+        let aligned = a.iter().zip(b).filter(|(x, y)| **x != b'-' && **y != b'-').count();
+        a.iter().zip(b).filter(|(x, y)| **x != b'-' && x == y).count() as f64 / aligned.max(1) as f64
+    };
+    rows.iter().map(|a| 1.0 / rows.iter().filter(|b| identity(a, b) >= CLUSTER_IDENTITY).count() as f64).collect()
+}
+
+fn role(r: u8) -> Option<&'static str> {
+    match r {
+        b'C' => Some("disulfide or metal ligand"),
+        b'H' | b'D' | b'E' | b'S' | b'K' | b'Y' => Some("catalytic or binding"),
+        b'G' | b'P' => Some("structural turn"),
+        b'W' => Some("core packing"),
+        _ => None,
+    }
+}
+
+pub fn analyze(rows: &[Vec<u8>]) -> ConservationReport {
+    let w = weights(rows);
+    let total: f64 = w.iter().sum();
+    let max_entropy = (SYMBOLS.len() as f64).log2();
+    let scores: Vec<f64> = (0..rows[0].len()).map(|c| {
+        let mut freq = [0.0; 21];
+        for (row, &wi) in rows.iter().zip(&w) {
+            let k = SYMBOLS.iter().position(|&s| s == row[c].to_ascii_uppercase()).unwrap_or(20);
+            freq[k] += wi / total;
+        }
+        let entropy: f64 = freq.iter().filter(|&&p| p > 0.0).map(|&p| -p * p.log2()).sum();
+        (((1.0 - entropy / max_entropy) * (1.0 - freq[20])) * 1000.0).round() / 1000.0
+    }).collect();
+    let mean = scores.iter().sum::<f64>() / scores.len().max(1) as f64;
+    let functional_sites = rows[0].iter().zip(&scores).enumerate().filter(|(_, (_, &s))| s >= FUNCTIONAL_CONSERVATION && rows.len() > 1)
+        .filter_map(|(i, (&r, &s))| role(r.to_ascii_uppercase()).map(|role| FunctionalSite { position: i, residue: r.to_ascii_uppercase() as char, conservation: s, role }))
+        .collect();
+    ConservationReport { sequences: rows.len(), effective_sequences: (total * 10.0).round() / 10.0, mean_conservation: (mean * 1000.0).round() / 1000.0, scores, functional_sites }
+}
+
+/// Shifts each domain's confidence by how much more (or less) conserved it is than the chain.
+pub fn weight_domains(domains: &mut [DomainInfo], report: &ConservationReport) {
+    for d in domains.iter_mut() {
+        let span = &report.scores[d.start.min(report.scores.len())..d.end.min(report.scores.len())];
+        if span.is_empty() { continue; }
+        let mean = span.iter().sum::<f64>() / span.len() as f64;
+        d.confidence = ((d.confidence + CONFIDENCE_WEIGHT * (mean - report.mean_conservation)).clamp(0.05, 0.99) * 1000.0).round() / 1000.0;
+    }
+}
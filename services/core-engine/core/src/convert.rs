@@ -0,0 +1,700 @@
+//! Structure file format conversion.
+//!
+//! Reads SMILES, SDF/molfile, Tripos MOL2, PDB,
+//! PDBQT, mmCIF (`_atom_site` or `_chem_comp_atom`, with `_chem_comp_bond` when present) and
+//! XYZ, and writes any of them. Internally a structure is the heavy-atom graph with hydrogen
+//! counts plus optional 3D coordinates; explicit hydrogens in the input are folded into their
+//! heavy atom. Formats without bond orders (PDB without `CONECT`, PDBQT, XYZ, mmCIF without a
+//! bond loop) get bonds from covalent radii; when the input carries hydrogens, double and
+//! triple bonds are assigned to satisfy valences, otherwise every bond is single. A structure
+//! without coordinates is embedded in 3D for the coordinate formats, and added hydrogens are
+//! placed on ideal sp/sp²/sp³ geometry. Partial charges are Gasteiger–Marsili (PEOE).
+
+use std::collections::HashMap;
+
+use crate::{chem::{self, Bond, BondKind, Molecule}, conformer, fnv1a, poses, vec3::{sub, cross, norm, dist}};
+
+pub const FORMATS: [&str; 7] = ["smiles", "sdf", "mol2", "pdb", "pdbqt", "mmcif", "xyz"];
+/// Coordinate-only formats are bonded by distance; past this many atoms that gets slow.
+pub const MAX_ATOMS: usize = 20_000;
+/// Slack over the sum of covalent radii within which two atoms count as bonded.
+const BOND_TOLERANCE: f64 = 0.45;
+const CH_LENGTH: f64 = 1.09;
+const XH_LENGTH: f64 = 1.01;
+
+/// A parsed structure: heavy-atom graph, coordinates when the format has them, and whether
+/// the input listed its hydrogens.
+pub struct Structure { pub name: String, pub mol: Molecule, pub coords: Option<Vec<[f64; 3]>>, pub explicit_h: bool }
+
+/// Which hydrogens a writer emits as atoms.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Hydrogens { None, Polar, All }
+
+/// Folds explicit hydrogens into their heavy atom's count and drops them from the coordinates.
+/// Without any hydrogen atoms the counts are inferred from default valences; in a
+/// `united_atom` table (polar hydrogens only, as none are bonded to carbon or the caller
+/// says so) that holds for carbons alone.
+pub fn fold_hydrogens(atoms: &[(String, i8)], coords: &[[f64; 3]], bonds: &[Bond], united_atom: bool) -> Result<(Molecule, Vec<[f64; 3]>), String> {
+    let explicit_h = atoms.iter().any(|a| a.0 == "H");
+    // Hydrogens on heteroatoms only also mean united atoms.
+    let united_atom = united_atom || (explicit_h && !bonds.iter().any(|b| (atoms[b.a].0 == "H" && atoms[b.b].0 == "C") || (atoms[b.b].0 == "H" && atoms[b.a].0 == "C")));
+    let heavy: Vec<usize> = (0..atoms.len()).filter(|&i| atoms[i].0 != "H").collect();
+    let index: HashMap<usize, usize> = heavy.iter().enumerate().map(|(k, &i)| (i, k)).collect();
+    let mut h_count = vec![0u8; atoms.len()];
+    for b in bonds {
+        if atoms[b.a].0 == "H" { h_count[b.b] += 1; }
+        if atoms[b.b].0 == "H" { h_count[b.a] += 1; }
+    }
+    let graph_atoms = heavy.iter().map(|&i| (atoms[i].0.clone(), atoms[i].1, ((explicit_h || united_atom) && !(united_atom && atoms[i].0 == "C")).then_some(h_count[i]))).collect();
+    let graph_bonds = bonds.iter().filter_map(|b| Some(Bond { a: *index.get(&b.a)?, b: *index.get(&b.b)?, kind: b.kind })).collect();
+    let mol = chem::from_graph(graph_atoms, graph_bonds)?;
+    Ok((mol, heavy.iter().map(|&i| coords[i]).collect()))
+}
+
+/// Single bonds between atoms closer than their covalent radii allow; a hydrogen keeps only
+/// its nearest partner.
+pub fn bonds_by_distance(atoms: &[(String, i8)], coords: &[[f64; 3]]) -> Vec<Bond> {
+    let mut bonds = Vec::new();
+    let mut h_partner: HashMap<usize, (usize, f64)> = HashMap::new();
+    for i in 0..atoms.len() {
+        for j in i + 1..atoms.len() {
+            if atoms[i].0 == "H" && atoms[j].0 == "H" { continue; }
+            let d = dist(coords[i], coords[j]);
+            if d < 0.4 || d > conformer::covalent_radius(&atoms[i].0) + conformer::covalent_radius(&atoms[j].0) + BOND_TOLERANCE { continue; }
+            let h = if atoms[i].0 == "H" { Some((i, j)) } else if atoms[j].0 == "H" { Some((j, i)) } else { None };
+            match h {
+                Some((h, other)) => { if h_partner.get(&h).is_none_or(|p| d < p.1) { h_partner.insert(h, (other, d)); } }
+                None => bonds.push(Bond { a: i, b: j, kind: BondKind::Single }),
+            }
+        }
+    }
+    bonds.extend(h_partner.into_iter().map(|(h, (other, _))| Bond { a: h, b: other, kind: BondKind::Single }));
+    bonds
+}
+
+/// Raises single bonds to double/triple until every atom reaches its valence, pairing the
+/// most constrained unsaturated atoms first. Needs the hydrogens present to be meaningful.
+/// A united-atom table (PDBQT) omits carbon hydrogens: each carbon then takes at most one
+/// multiple bond (a nitrile carbon its triple), and only aromatic ones (`unsaturable`) bond
+/// multiply to another carbon.
+pub fn assign_orders(atoms: &[(String, i8)], united: Option<&[bool]>, bonds: &mut [Bond]) {
+    let target = |el: &str, charge: i8| -> i32 {
+        match el { "C" => 4 - charge.abs() as i32, "N" | "P" => 3 + charge as i32, "O" | "S" | "Se" => 2 + charge as i32, "B" => 3, _ => 1 }
+    };
+    let mut incident = vec![Vec::new(); atoms.len()];
+    for (k, b) in bonds.iter().enumerate() { incident[b.a].push(k); incident[b.b].push(k); }
+    let other = |k: usize, i: usize, bonds: &[Bond]| if bonds[k].a == i { bonds[k].b } else { bonds[k].a };
+    let used = |bonds: &[Bond], i: usize| -> i32 { incident[i].iter().map(|&k| bonds[k].kind.valence() as i32).sum() };
+    let degree = |i: usize| incident[i].len() as i32;
+    let halogen = |el: &str| matches!(el, "F" | "Cl" | "Br" | "I" | "H");
+    let carbon = |i: usize| atoms[i].0 == "C";
+    let free = |i: usize| united.is_none_or(|u| u[i]);
+    let nitrile = |i: usize| incident[i].iter().any(|&k| { let j = other(k, i, bonds); atoms[j].0 == "N" && degree(j) == 1 });
+    // A nitrile carbon may take its triple bond.
+    let cap: Vec<i32> = (0..atoms.len()).map(|i| if united.is_some() && carbon(i) { if nitrile(i) { 2 } else { 1 } } else { 0 }).collect();
+    let deficit_of = |bonds: &[Bond], i: usize| match () {
+        _ if halogen(&atoms[i].0) => 0,
+        _ if united.is_some() && carbon(i) => cap[i] - (used(bonds, i) - degree(i)),
+        _ => target(&atoms[i].0, atoms[i].1) - used(bonds, i),
+    };
+    let mut deficit: Vec<i32> = (0..atoms.len()).map(|i| deficit_of(bonds, i)).collect();
+    loop {
+        let eligible = |b: &Bond| deficit[b.a] > 0 && deficit[b.b] > 0 && b.kind != BondKind::Triple && (!carbon(b.a) || !carbon(b.b) || (free(b.a) && free(b.b)));
+        let open = |i: usize| incident[i].iter().filter(|&&k| eligible(&bonds[k])).count();
+        let pick = bonds.iter().enumerate().filter(|(_, b)| eligible(b))
+            .min_by_key(|(_, b)| (open(b.a).min(open(b.b)), open(b.a) + open(b.b))).map(|(k, _)| k);
+        let Some(k) = pick else { break };
+        bonds[k].kind = if bonds[k].kind == BondKind::Single { BondKind::Double } else { BondKind::Triple };
+        let (a, b) = (bonds[k].a, bonds[k].b);
+        deficit[a] = deficit_of(bonds, a);
+        deficit[b] = deficit_of(bonds, b);
+    }
+    // Terminal oxygens left unsaturated double-bond to a hypervalent S or P (sulfones, phosphates).
+    for k in 0..bonds.len() {
+        let (a, b) = (bonds[k].a, bonds[k].b);
+        let (o, x) = if atoms[a].0 == "O" { (a, b) } else { (b, a) };
+        if atoms[o].0 != "O" || !matches!(atoms[x].0.as_str(), "S" | "P") || bonds[k].kind != BondKind::Single { continue; }
+        if target("O", atoms[o].1) - used(bonds, o) > 0 && used(bonds, x) < if atoms[x].0 == "S" { 6 } else { 5 } { bonds[k].kind = BondKind::Double; }
+    }
+}
+
+/// Formal charges implied by valences, for tables that list none. Before bond orders are
+/// assigned (`before_orders`) a nitro-type N gets its +1; afterwards four-valent N/P become
+/// cations, and terminal single-bonded O/S become anions when next to such a cation or, with
+/// hydrogens listed (`explicit_h`), whenever they carry none.
+fn infer_charges(atoms: &mut [(String, i8)], bonds: &[Bond], before_orders: bool, explicit_h: bool) {
+    let degree = |i: usize| bonds.iter().filter(|b| b.a == i || b.b == i).count();
+    let valence = |i: usize| bonds.iter().filter(|b| b.a == i || b.b == i).map(|b| b.kind.valence() as usize).sum::<usize>();
+    let partners = |i: usize| bonds.iter().filter(move |b| b.a == i || b.b == i).map(move |b| if b.a == i { b.b } else { b.a });
+    for i in 0..atoms.len() {
+        let terminal_o = partners(i).filter(|&j| atoms[j].0 == "O" && degree(j) == 1).count();
+        match atoms[i].0.as_str() {
+            "N" if before_orders && degree(i) == 3 && terminal_o >= 2 => atoms[i].1 = 1,
+            "N" | "P" if !before_orders && valence(i) == 4 => atoms[i].1 = 1,
+            _ => {}
+        }
+    }
+    if before_orders { return; }
+    for i in 0..atoms.len() {
+        if !matches!(atoms[i].0.as_str(), "O" | "S") || degree(i) != 1 || valence(i) != 1 { continue; }
+        let Some(j) = partners(i).next() else { continue };
+        if atoms[j].1 > 0 || (explicit_h && atoms[j].0 != "H") { atoms[i].1 = -1; }
+    }
+}
+
+/// Builds a structure from an explicit atom table. Bonds missing from the format are perceived
+/// from distances, and bond orders it lacks (`orders` false) from valences when hydrogens are
+/// present. A table whose hydrogens are all polar is united-atom: carbons get theirs from
+/// valences, and `unsaturable` (from PDBQT atom types) names the carbons that may bond
+/// multiply to another carbon.
+fn from_table(name: String, mut atoms: Vec<(String, i8)>, coords: Vec<[f64; 3]>, bonds: Option<Vec<Bond>>, orders: bool, unsaturable: Option<Vec<bool>>, warnings: &mut Vec<String>) -> Result<Structure, String> {
+    if atoms.is_empty() { return Err("no atoms found".into()); }
+    if atoms.len() > MAX_ATOMS { return Err(format!("{} atoms is more than the {MAX_ATOMS} supported", atoms.len())); }
+    let explicit_h = atoms.iter().any(|a| a.0 == "H");
+    let charged = atoms.iter().any(|a| a.1 != 0);
+    let (mut bonds, orders) = match bonds {
+        Some(b) if !b.is_empty() || atoms.len() == 1 => (b, orders),
+        _ => { warnings.push("bonds were perceived from the coordinates".into()); (bonds_by_distance(&atoms, &coords), false) }
+    };
+    let carbon_h = bonds.iter().any(|b| (atoms[b.a].0 == "H" && atoms[b.b].0 == "C") || (atoms[b.b].0 == "H" && atoms[b.a].0 == "C"));
+    let united = unsaturable.or_else(|| (explicit_h && !carbon_h && atoms.iter().any(|a| a.0 == "C")).then(|| vec![false; atoms.len()]));
+    if united.is_some() && !orders { warnings.push("only polar hydrogens are listed; C=C and C≡C bonds outside aromatic rings can't be recovered".into()); }
+    let hydrogens_known = explicit_h || united.is_some();
+    if !orders && hydrogens_known {
+        if !charged { infer_charges(&mut atoms, &bonds, true, true); }
+        assign_orders(&atoms, united.as_deref(), &mut bonds);
+    }
+    if !orders && !hydrogens_known { warnings.push("without hydrogens bond orders can't be perceived; every bond is taken as single".into()); }
+    if !charged && (orders || hydrogens_known) { infer_charges(&mut atoms, &bonds, false, hydrogens_known); }
+    let (mol, coords) = fold_hydrogens(&atoms, &coords, &bonds, united.is_some())?;
+    Ok(Structure { name, mol, coords: Some(coords), explicit_h })
+}
+
+/// Element symbol with the case fixed ("CL" → "Cl").
+pub fn element(sym: &str) -> String {
+    let mut c = sym.trim().chars();
+    c.next().map(|f| f.to_ascii_uppercase().to_string() + &c.as_str().to_ascii_lowercase()).unwrap_or_default()
+}
+
+/// Element of an AutoDock atom type.
+fn ad_element(t: &str) -> String {
+    match t.trim() { "A" => "C".into(), "NA" | "NS" => "N".into(), "OA" | "OS" => "O".into(), "SA" => "S".into(), "HD" | "HS" => "H".into(), other => element(other) }
+}
+
+fn parse_pdb(text: &str, pdbqt: bool, warnings: &mut Vec<String>) -> Result<Structure, String> {
+    let mut name = String::new();
+    let (mut atoms, mut coords, mut serials) = (Vec::new(), Vec::new(), HashMap::new());
+    let (mut conect, mut unsaturable, mut ad_types) = (Vec::new(), Vec::new(), Vec::new());
+    for l in text.lines() {
+        if (l.starts_with("COMPND") || l.starts_with("HEADER")) && name.is_empty() { name = l.get(10..).unwrap_or("").trim().to_string(); }
+        if l.starts_with("ATOM") || l.starts_with("HETATM") {
+            let f = |a: usize, b: usize| l.get(a..b.min(l.len())).map(str::trim).unwrap_or("");
+            let xyz: Result<Vec<f64>, _> = [(30, 38), (38, 46), (46, 54)].iter().map(|&(a, b)| f(a, b).parse::<f64>()).collect();
+            let xyz = xyz.map_err(|_| format!("bad coordinates: {l}"))?;
+            let el = if pdbqt { ad_element(f(77, 79)) } else if !f(76, 78).is_empty() { element(f(76, 78)) } else { element(f(12, 16).trim_start_matches(|c: char| c.is_ascii_digit()).get(..1).unwrap_or("")) };
+            let charge = if pdbqt { 0 } else { match f(78, 80).as_bytes() { [d, b'+'] => (d - b'0') as i8, [d, b'-'] => -((d - b'0') as i8), _ => 0 } };
+            serials.insert(f(6, 11).parse::<usize>().unwrap_or(atoms.len() + 1), atoms.len());
+            unsaturable.push(f(77, 79) != "C");
+            ad_types.push(f(77, 79).to_string());
+            atoms.push((el, charge));
+            coords.push([xyz[0], xyz[1], xyz[2]]);
+        }
+        if let Some(rest) = l.strip_prefix("CONECT") {
+            let ids: Vec<usize> = rest.as_bytes().chunks(5).filter_map(|c| std::str::from_utf8(c).ok()?.trim().parse().ok()).collect();
+            if let Some((&a, others)) = ids.split_first() { conect.extend(others.iter().map(|&b| (a, b))); }
+        }
+    }
+    let mut bonds: Vec<Bond> = Vec::new();
+    for (a, b) in conect {
+        let (Some(&a), Some(&b)) = (serials.get(&a), serials.get(&b)) else { continue };
+        if a != b && !bonds.iter().any(|x| (x.a, x.b) == (a.min(b), a.max(b))) { bonds.push(Bond { a: a.min(b), b: a.max(b), kind: BondKind::Single }); }
+    }
+    // CONECT gives connectivity only; orders come from valences.
+    // Without nonpolar hydrogens (the AutoDock default) aromatic carbons are told apart by type.
+    let united = (pdbqt && !ad_types.iter().any(|t| t == "H")).then_some(unsaturable);
+    from_table(if name.is_empty() { "structure".into() } else { name }, atoms, coords, Some(bonds), false, united, warnings)
+}
+
+fn parse_mol2(text: &str, warnings: &mut Vec<String>) -> Result<Structure, String> {
+    let mut section = "";
+    let mut name = String::new();
+    let (mut atoms, mut types, mut coords, mut ids) = (Vec::new(), Vec::new(), Vec::new(), HashMap::new());
+    let mut bonds = Vec::new();
+    for l in text.lines() {
+        let l = l.trim();
+        if let Some(s) = l.strip_prefix("@<TRIPOS>") { section = if s == "MOLECULE" && !atoms.is_empty() { "END" } else { s }; continue; }
+        if l.is_empty() || l.starts_with('#') { continue; }
+        let t: Vec<&str> = l.split_whitespace().collect();
+        match section {
+            "MOLECULE" if name.is_empty() => name = l.to_string(),
+            "ATOM" => {
+                if t.len() < 6 { return Err(format!("bad atom line: {l}")); }
+                let xyz: Result<Vec<f64>, _> = t[2..5].iter().map(|v| v.parse::<f64>()).collect();
+                let xyz = xyz.map_err(|_| format!("bad atom line: {l}"))?;
+                let sybyl = t[5];
+                ids.insert(t[0].to_string(), atoms.len());
+                atoms.push((element(sybyl.split('.').next().unwrap_or(sybyl)), if sybyl == "N.4" { 1 } else { 0 }));
+                types.push(sybyl.to_string());
+                coords.push([xyz[0], xyz[1], xyz[2]]);
+            }
+            "BOND" => {
+                if t.len() < 4 { return Err(format!("bad bond line: {l}")); }
+                let (Some(&a), Some(&b)) = (ids.get(t[1]), ids.get(t[2])) else { return Err(format!("bond references a missing atom: {l}")) };
+                let kind = match t[3] { "2" => BondKind::Double, "3" => BondKind::Triple, "ar" => BondKind::Aromatic, _ => BondKind::Single };
+                bonds.push(Bond { a, b, kind });
+            }
+            _ => {}
+        }
+    }
+    // Carboxylate oxygens (O.co2) share an "ar" bond: make one C=O and one C–O⁻ per carbon.
+    let mut seen_co2: Vec<usize> = Vec::new();
+    for b in bonds.iter_mut().filter(|b| b.kind == BondKind::Aromatic) {
+        let (o, c) = if types[b.a] == "O.co2" { (b.a, b.b) } else if types[b.b] == "O.co2" { (b.b, b.a) } else { continue };
+        if seen_co2.contains(&c) { b.kind = BondKind::Single; atoms[o].1 = -1; } else { b.kind = BondKind::Double; seen_co2.push(c); }
+    }
+    from_table(if name.is_empty() { "structure".into() } else { name }, atoms, coords, Some(bonds), true, None, warnings)
+}
+
+/// Whitespace tokens of a CIF data line, honouring single and double quotes.
+fn cif_tokens(l: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut chars = l.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() { chars.next(); continue; }
+        if c == '\'' || c == '"' {
+            chars.next();
+            out.push(chars.by_ref().take_while(|&x| x != c).collect());
+        } else {
+            let mut t = String::new();
+            while let Some(&x) = chars.peek() { if x.is_whitespace() { break; } t.push(x); chars.next(); }
+            out.push(t);
+        }
+    }
+    out
+}
+
+/// `loop_` tables of a CIF block: column names and rows per category.
+fn cif_loops(text: &str) -> HashMap<String, (Vec<String>, Vec<Vec<String>>)> {
+    let mut loops: HashMap<String, (Vec<String>, Vec<Vec<String>>)> = HashMap::new();
+    let mut current: Option<(String, Vec<String>, Vec<String>)> = None;
+    let mut in_header = false;
+    let finish = |cur: Option<(String, Vec<String>, Vec<String>)>, loops: &mut HashMap<String, (Vec<String>, Vec<Vec<String>>)>| {
+        if let Some((cat, cols, values)) = cur { if !cols.is_empty() { loops.insert(cat, (cols.clone(), values.chunks(cols.len()).map(|r| r.to_vec()).collect())); } }
+    };
+    for l in text.lines() {
+        let t = l.trim();
+        if t == "loop_" { finish(current.take(), &mut loops); current = Some((String::new(), Vec::new(), Vec::new())); in_header = true; continue; }
+        let Some(cur) = current.as_mut() else { continue };
+        if t.starts_with('_') && in_header {
+            let (cat, col) = t.split_once('.').unwrap_or((t, ""));
+            cur.0 = cat.to_string();
+            cur.1.push(col.split_whitespace().next().unwrap_or("").to_string());
+        } else if t.starts_with('_') || t.starts_with('#') || t.starts_with("data_") {
+            finish(current.take(), &mut loops);
+        } else if !t.is_empty() {
+            in_header = false;
+            cur.2.extend(cif_tokens(t));
+        }
+    }
+    finish(current, &mut loops);
+    loops
+}
+
+fn parse_mmcif(text: &str, warnings: &mut Vec<String>) -> Result<Structure, String> {
+    let name = text.lines().find_map(|l| l.trim().strip_prefix("data_")).unwrap_or("structure").to_string();
+    let loops = cif_loops(text);
+    let (cat, x, id) = if loops.contains_key("_atom_site") { ("_atom_site", "Cartn_x", "label_atom_id") }
+        else if loops.get("_chem_comp_atom").is_some_and(|l| l.0.iter().any(|c| c == "model_Cartn_x")) { ("_chem_comp_atom", "model_Cartn_x", "atom_id") }
+        else { ("_chem_comp_atom", "pdbx_model_Cartn_x_ideal", "atom_id") };
+    let (cols, rows) = loops.get(cat).ok_or("no _atom_site or _chem_comp_atom loop")?;
+    let col = |c: &str| cols.iter().position(|k| k == c);
+    let (el, xc, ic) = (col("type_symbol").ok_or("missing type_symbol column")?, col(x).ok_or(format!("missing {x} column"))?, col(id));
+    let charge = col("pdbx_formal_charge").or_else(|| col("charge"));
+    let (mut atoms, mut coords, mut names) = (Vec::new(), Vec::new(), HashMap::new());
+    for r in rows {
+        let xyz: Result<Vec<f64>, _> = (xc..xc + 3).map(|k| r.get(k).map_or("", String::as_str).parse::<f64>()).collect();
+        let xyz = xyz.map_err(|_| format!("bad coordinates in row {}", r.join(" ")))?;
+        if let Some(k) = ic { names.insert(r[k].clone(), atoms.len()); }
+        atoms.push((element(&r[el]), charge.and_then(|k| r.get(k)?.parse().ok()).unwrap_or(0)));
+        coords.push([xyz[0], xyz[1], xyz[2]]);
+    }
+    let bonds = loops.get("_chem_comp_bond").map(|(cols, rows)| {
+        let col = |c: &str| cols.iter().position(|k| k == c);
+        let (a, b, order, arom) = (col("atom_id_1"), col("atom_id_2"), col("value_order"), col("pdbx_aromatic_flag"));
+        rows.iter().filter_map(|r| {
+            let (i, j) = (*names.get(&r[a?])?, *names.get(&r[b?])?);
+            let aromatic = arom.is_some_and(|k| r[k].eq_ignore_ascii_case("Y"));
+            let kind = match order.map(|k| r[k].to_ascii_uppercase()).as_deref() { _ if aromatic => BondKind::Aromatic, Some("DOUB") => BondKind::Double, Some("TRIP") => BondKind::Triple, Some("AROM") => BondKind::Aromatic, _ => BondKind::Single };
+            Some(Bond { a: i, b: j, kind })
+        }).collect()
+    });
+    from_table(name, atoms, coords, bonds, true, None, warnings)
+}
+
+fn parse_xyz(text: &str, warnings: &mut Vec<String>) -> Result<Structure, String> {
+    let mut lines = text.lines();
+    let n: usize = lines.next().unwrap_or("").trim().parse().map_err(|_| "XYZ must start with the atom count")?;
+    let name = lines.next().unwrap_or("").trim().to_string();
+    let (mut atoms, mut coords) = (Vec::new(), Vec::new());
+    for l in lines.take(n) {
+        let t: Vec<&str> = l.split_whitespace().collect();
+        let xyz: Result<Vec<f64>, _> = t.get(1..4).unwrap_or(&[]).iter().map(|v| v.parse::<f64>()).collect();
+        let xyz = xyz.ok().filter(|v| v.len() == 3).ok_or_else(|| format!("bad atom line: {l}"))?;
+        atoms.push((element(t[0]), 0));
+        coords.push([xyz[0], xyz[1], xyz[2]]);
+    }
+    if atoms.len() != n { return Err(format!("XYZ declares {n} atoms but lists {}", atoms.len())); }
+    from_table(if name.is_empty() { "structure".into() } else { name }, atoms, coords, None, false, None, warnings)
+}
+
+/// Guesses the format from the content.
+pub fn detect(text: &str) -> &'static str {
+    let t = text.trim_start();
+    let first = t.lines().next().unwrap_or("").trim();
+    if t.contains("@<TRIPOS>") { "mol2" }
+    else if t.contains("V2000") || t.contains("M  END") { "sdf" }
+    else if t.starts_with("data_") || t.contains("_atom_site.") || t.contains("_chem_comp_atom.") { "mmcif" }
+    else if t.lines().any(|l| l.starts_with("ATOM") || l.starts_with("HETATM")) { if t.contains("TORSDOF") || t.contains("ROOT") { "pdbqt" } else { "pdb" } }
+    else if first.parse::<usize>().is_ok() { "xyz" }
+    else { "smiles" }
+}
+
+/// Parses a coordinate or connection-table format; SMILES and names go through the resolver.
+pub fn parse(text: &str, format: &str, warnings: &mut Vec<String>) -> Result<Structure, String> {
+    match format {
+        "sdf" | "mol" => {
+            let (name, mol, coords) = poses::parse_sdf(text)?;
+            let explicit_h = text.lines().skip(4).any(|l| l.get(31..34).map(str::trim) == Some("H"));
+            Ok(Structure { name, mol, coords: Some(coords), explicit_h })
+        }
+        "mol2" => parse_mol2(text, warnings),
+        "pdb" => parse_pdb(text, false, warnings),
+        "pdbqt" => parse_pdb(text, true, warnings),
+        "mmcif" | "cif" => parse_mmcif(text, warnings),
+        "xyz" => parse_xyz(text, warnings),
+        other => Err(format!("unknown format {other}; expected one of {}", FORMATS.join(", "))),
+    }
+}
+
+fn hybridization(mol: &Molecule, adj: &[Vec<(usize, BondKind)>], i: usize) -> u8 {
+    let doubles = adj[i].iter().filter(|&&(_, k)| k == BondKind::Double).count();
+    if adj[i].iter().any(|&(_, k)| k == BondKind::Triple) || doubles >= 2 { 1 }
+    else if doubles == 1 || mol.atoms[i].aromatic { 2 }
+    else { 3 }
+}
+
+fn unit(a: [f64; 3]) -> Option<[f64; 3]> { let n = norm(a); (n > 1e-6).then(|| [a[0] / n, a[1] / n, a[2] / n]) }
+fn along(p: [f64; 3], dirs: &[([f64; 3], f64)]) -> [f64; 3] {
+    let mut out = p;
+    for &(d, s) in dirs { for k in 0..3 { out[k] += d[k] * s; } }
+    out
+}
+
+/// Positions for the hydrogens of atom `i`: opposite its neighbours, splayed to the ideal
+/// angle of its hybridization.
+fn place_hydrogens(mol: &Molecule, adj: &[Vec<(usize, BondKind)>], coords: &[[f64; 3]], i: usize) -> Vec<[f64; 3]> {
+    let n = mol.atoms[i].hydrogens as usize;
+    if n == 0 { return Vec::new(); }
+    let p = coords[i];
+    let len = if mol.atoms[i].element == "C" { CH_LENGTH } else { XH_LENGTH };
+    let bonds: Vec<[f64; 3]> = adj[i].iter().filter_map(|&(j, _)| unit(sub(coords[j], p))).collect();
+    let sum = bonds.iter().fold([0.0; 3], |s, b| [s[0] + b[0], s[1] + b[1], s[2] + b[2]]);
+    let d = unit([-sum[0], -sum[1], -sum[2]]).unwrap_or([0.0, 0.0, 1.0]);
+    // Two perpendiculars to d; the first lies in the plane of two neighbours when there are two.
+    let seed = bonds.get(1).map(|&b| cross(bonds[0], b)).and_then(unit).map(|nrm| cross(nrm, d))
+        .or_else(|| bonds.first().and_then(|&b| unit(cross(b, [0.3, 0.5, 0.8])))).unwrap_or([1.0, 0.0, 0.0]);
+    let u = unit(sub(seed, [d[0] * (seed[0] * d[0] + seed[1] * d[1] + seed[2] * d[2]), d[1] * (seed[0] * d[0] + seed[1] * d[1] + seed[2] * d[2]), d[2] * (seed[0] * d[0] + seed[1] * d[1] + seed[2] * d[2])])).unwrap_or([1.0, 0.0, 0.0]);
+    let v = cross(d, u);
+    // Amide and aniline nitrogens are planar.
+    let conjugated = mol.atoms[i].element == "N" && adj[i].iter().any(|&(j, _)| hybridization(mol, adj, j) == 2);
+    let hyb = if conjugated { 2 } else { hybridization(mol, adj, i) };
+    let (tilt, spread): (f64, Vec<f64>) = match (bonds.len(), hyb, n) {
+        (0, _, 1) => (0.0, vec![0.0]),
+        (0, _, _) => { // methane, water, ammonia: a tetrahedron around the atom
+            let t = [[1.0, 1.0, 1.0], [1.0, -1.0, -1.0], [-1.0, 1.0, -1.0], [-1.0, -1.0, 1.0]];
+            return t.iter().take(n).map(|x| along(p, &[([x[0] / 3f64.sqrt(), x[1] / 3f64.sqrt(), x[2] / 3f64.sqrt()], len)])).collect();
+        }
+        (_, 1, _) => (0.0, vec![0.0]),
+        (1, 2, _) => (60f64.to_radians(), vec![90f64.to_radians(), -90f64.to_radians()]),
+        (1, _, _) => (70.5f64.to_radians(), vec![0.0, 120f64.to_radians(), 240f64.to_radians()]),
+        (2, 3, _) => (54.75f64.to_radians(), vec![90f64.to_radians(), -90f64.to_radians()]),
+        _ => (0.0, vec![0.0]),
+    };
+    // The in-plane perpendicular of two-neighbour atoms is v here, since u lies in their plane.
+    spread.iter().take(n).map(|&phi| {
+        let (c, s) = (tilt.cos(), tilt.sin());
+        along(p, &[(d, len * c), (u, len * s * phi.cos()), (v, len * s * phi.sin())])
+    }).collect()
+}
+
+/// Gasteiger–Marsili electronegativity parameters (a, b, c) by element and hybridization.
+fn gasteiger_params(el: &str, hyb: u8) -> (f64, f64, f64) {
+    match (el, hyb) {
+        ("H", _) => (7.17, 6.24, -0.56),
+        ("C", 3) => (7.98, 9.18, 1.88), ("C", 2) => (8.79, 9.32, 1.51), ("C", _) => (10.39, 9.45, 0.73),
+        ("N", 3) => (11.54, 10.82, 1.36), ("N", 2) => (12.87, 11.15, 0.85), ("N", _) => (15.68, 11.70, -0.27),
+        ("O", 3) => (14.18, 12.92, 1.39), ("O", _) => (17.07, 13.79, 0.47),
+        ("S", _) => (10.14, 9.13, 1.38), ("P", _) => (8.90, 8.24, 0.96),
+        ("F", _) => (14.66, 13.85, 2.31), ("Cl", _) => (11.00, 9.69, 1.35), ("Br", _) => (10.08, 8.47, 1.16), ("I", _) => (9.90, 7.96, 0.96),
+        _ => (7.98, 9.18, 1.88),
+    }
+}
+
+/// Gasteiger–Marsili partial charges over six damped iterations, starting from the formal
+/// charges. Only atoms in the graph take part, so hydrogens must be explicit.
+pub fn gasteiger(mol: &Molecule) -> Vec<f64> {
+    let adj = mol.neighbors();
+    let params: Vec<(f64, f64, f64)> = (0..mol.atoms.len()).map(|i| gasteiger_params(&mol.atoms[i].element, hybridization(mol, &adj, i))).collect();
+    let mut q: Vec<f64> = mol.atoms.iter().map(|a| a.charge as f64).collect();
+    let mut damp = 1.0;
+    for _ in 0..6 {
+        damp *= 0.5;
+        let chi: Vec<f64> = params.iter().zip(&q).map(|(p, q)| p.0 + p.1 * q + p.2 * q * q).collect();
+        let mut dq = vec![0.0; q.len()];
+        for b in &mol.bonds {
+            let (donor, acceptor) = if chi[b.a] < chi[b.b] { (b.a, b.b) } else { (b.b, b.a) };
+            // Charge flows to the more electronegative atom, scaled by the donor's cation χ.
+            let p = params[donor];
+            let cation = if mol.atoms[donor].element == "H" { 20.02 } else { p.0 + p.1 + p.2 };
+            let t = (chi[acceptor] - chi[donor]) / cation * damp;
+            dq[donor] += t;
+            dq[acceptor] -= t;
+        }
+        q.iter_mut().zip(dq).for_each(|(q, d)| *q += d);
+    }
+    q
+}
+
+/// A structure ready to write: the requested hydrogens explicit, coordinates for every atom
+/// and Gasteiger charges when asked for.
+pub struct Explicit { pub mol: Molecule, pub coords: Vec<[f64; 3]>, pub charges: Option<Vec<f64>> }
+
+/// Makes hydrogens explicit (all, polar only, or none) with placed coordinates. Heavy atoms
+/// keep their indices and added hydrogens follow them. Charges are computed with every
+/// hydrogen present; those left implicit merge theirs into the heavy atom, as in AutoDock's
+/// united-atom model.
+pub fn explicit(mol: &Molecule, coords: &[[f64; 3]], hydrogens: Hydrogens, charges: bool) -> Result<Explicit, String> {
+    let adj = mol.neighbors();
+    let n = mol.atoms.len();
+    let mut bonds = mol.bonds.clone();
+    let mut xyz = coords.to_vec();
+    let mut parent = Vec::new();
+    for i in 0..n {
+        for h in place_hydrogens(mol, &adj, coords, i) {
+            bonds.push(Bond { a: i, b: n + parent.len(), kind: BondKind::Single });
+            xyz.push(h);
+            parent.push(i);
+        }
+    }
+    let heavy = mol.atoms.iter().map(|a| (a.element.clone(), a.charge, Some(0)));
+    let full = chem::from_graph(heavy.chain(parent.iter().map(|_| ("H".to_string(), 0, Some(0)))).collect(), bonds.clone())?;
+    let mut q = charges.then(|| gasteiger(&full));
+    let keep = |p: usize| match hydrogens { Hydrogens::All => true, Hydrogens::Polar => mol.atoms[p].element != "C", Hydrogens::None => false };
+    let mut implicit = vec![0u8; n];
+    let mut index: Vec<Option<usize>> = (0..n).map(Some).collect();
+    let mut kept = n;
+    for (k, &p) in parent.iter().enumerate() {
+        if keep(p) { index.push(Some(kept)); kept += 1; continue; }
+        index.push(None);
+        implicit[p] += 1;
+        if let Some(q) = q.as_mut() { q[p] += q[n + k]; }
+    }
+    let atoms = mol.atoms.iter().enumerate().map(|(i, a)| (a.element.clone(), a.charge, Some(implicit[i])))
+        .chain(parent.iter().filter(|&&p| keep(p)).map(|_| ("H".to_string(), 0, Some(0)))).collect();
+    let bonds = bonds.iter().filter_map(|b| Some(Bond { a: index[b.a]?, b: index[b.b]?, kind: b.kind })).collect();
+    let keep_idx = |v: Vec<[f64; 3]>| v.into_iter().enumerate().filter(|&(k, _)| index[k].is_some()).map(|(_, x)| x).collect();
+    Ok(Explicit { mol: chem::from_graph(atoms, bonds)?, coords: keep_idx(xyz), charges: q.map(|q| q.into_iter().enumerate().filter(|&(k, _)| index[k].is_some()).map(|(_, x)| x).collect()) })
+}
+
+fn bond_order(kind: BondKind) -> u8 { match kind { BondKind::Single => 1, BondKind::Double => 2, BondKind::Triple => 3, BondKind::Aromatic => 4 } }
+
+/// MDL molfile charge code: 1..3 for +3..+1, 5..7 for -1..-3.
+fn charge_code(charge: i8) -> u8 { match charge { 1..=3 => (4 - charge) as u8, -3..=-1 => (4 - charge) as u8, _ => 0 } }
+
+/// PDB-style atom names: element plus a running count per element.
+pub fn atom_names(mol: &Molecule) -> Vec<String> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    mol.atoms.iter().map(|a| { let n = counts.entry(a.element.as_str()).or_insert(0); *n += 1; format!("{}{}", a.element.to_uppercase(), n) }).collect()
+}
+
+/// MDL SDF (V2000) record; `props` become data items.
+pub fn write_sdf(name: &str, comment: &str, mol: &Molecule, coords: &[[f64; 3]], props: &[(&str, String)]) -> Result<String, String> {
+    if mol.atoms.len() > 999 || mol.bonds.len() > 999 { return Err("V2000 molfiles hold at most 999 atoms and bonds".into()); }
+    let mut out = format!("{name}\n  ALICE-bio         3D\n{comment}\n");
+    out.push_str(&format!("{:>3}{:>3}  0  0  0  0  0  0  0  0999 V2000\n", mol.atoms.len(), mol.bonds.len()));
+    for (a, c) in mol.atoms.iter().zip(coords) {
+        out.push_str(&format!("{:>10.4}{:>10.4}{:>10.4} {:<3} 0{:>3}  0  0  0  0  0  0  0  0  0  0\n", c[0], c[1], c[2], a.element, charge_code(a.charge)));
+    }
+    for b in &mol.bonds { out.push_str(&format!("{:>3}{:>3}{:>3}  0\n", b.a + 1, b.b + 1, bond_order(b.kind))); }
+    for (i, a) in mol.atoms.iter().enumerate().filter(|(_, a)| a.charge != 0) { out.push_str(&format!("M  CHG  1 {:>3} {:>3}\n", i + 1, a.charge)); }
+    out.push_str("M  END\n");
+    for (k, v) in props { out.push_str(&format!("> <{k}>\n{v}\n\n")); }
+    out.push_str("$$$$\n");
+    Ok(out)
+}
+
+/// PDB `HETATM` records with `CONECT` bonds, after the given header lines.
+pub fn write_pdb(header: &[String], mol: &Molecule, coords: &[[f64; 3]]) -> String {
+    let mut out: String = header.iter().map(|h| format!("{h}\n")).collect();
+    for (i, ((a, c), name)) in mol.atoms.iter().zip(coords).zip(atom_names(mol)).enumerate() {
+        let charge = match a.charge { 0 => String::new(), c if c > 0 => format!("{c}+"), c => format!("{}-", -c) };
+        out.push_str(&format!("HETATM{:>5} {:<4} LIG L   1    {:>8.3}{:>8.3}{:>8.3}  1.00  0.00          {:>2}{:<2}\n", i + 1, name, c[0], c[1], c[2], a.element.to_uppercase(), charge));
+    }
+    for (i, ns) in mol.neighbors().iter().enumerate().filter(|(_, ns)| !ns.is_empty()) {
+        for chunk in ns.chunks(4) {
+            out.push_str(&format!("CONECT{:>5}", i + 1));
+            for &(j, _) in chunk { out.push_str(&format!("{:>5}", j + 1)); }
+            out.push('\n');
+        }
+    }
+    out.push_str("END\n");
+    out
+}
+
+/// SYBYL atom type for MOL2.
+fn sybyl_type(mol: &Molecule, adj: &[Vec<(usize, BondKind)>], i: usize) -> String {
+    let a = &mol.atoms[i];
+    let doubles_to = |el: &str| adj[i].iter().filter(|&&(j, k)| k == BondKind::Double && mol.atoms[j].element == el).count();
+    let t = match a.element.as_str() {
+        "C" if a.aromatic => "C.ar",
+        "C" => ["", "C.1", "C.2", "C.3"][hybridization(mol, adj, i) as usize],
+        "N" if a.aromatic => "N.ar",
+        "N" if a.charge > 0 && adj[i].len() + a.hydrogens as usize == 4 => "N.4",
+        "N" if hybridization(mol, adj, i) == 1 => "N.1",
+        "N" if hybridization(mol, adj, i) == 2 => "N.2",
+        "N" if adj[i].iter().any(|&(j, _)| mol.atoms[j].element == "C" && adj[j].iter().any(|&(o, k)| k == BondKind::Double && mol.atoms[o].element == "O")) => "N.am",
+        "N" if adj[i].iter().any(|&(j, _)| hybridization(mol, adj, j) < 3) => "N.pl3",
+        "N" => "N.3",
+        "O" if adj[i].len() == 1 && adj[adj[i][0].0].iter().filter(|&&(o, _)| mol.atoms[o].element == "O" && adj[o].len() == 1 && mol.atoms[o].hydrogens == 0).count() == 2 && mol.atoms[adj[i][0].0].element == "C" => "O.co2",
+        "O" if hybridization(mol, adj, i) == 2 => "O.2",
+        "O" => "O.3",
+        "S" if doubles_to("O") == 1 => "S.O",
+        "S" if doubles_to("O") >= 2 => "S.O2",
+        "S" if hybridization(mol, adj, i) == 2 => "S.2",
+        "S" => "S.3",
+        "P" => "P.3",
+        other => return other.to_string(),
+    };
+    t.to_string()
+}
+
+pub fn write_mol2(name: &str, mol: &Molecule, coords: &[[f64; 3]], charges: Option<&[f64]>) -> String {
+    let adj = mol.neighbors();
+    let types: Vec<String> = (0..mol.atoms.len()).map(|i| sybyl_type(mol, &adj, i)).collect();
+    let mut out = format!("@<TRIPOS>MOLECULE\n{name}\n{} {} 1 0 0\nSMALL\n{}\n\n@<TRIPOS>ATOM\n", mol.atoms.len(), mol.bonds.len(), if charges.is_some() { "GASTEIGER" } else { "NO_CHARGES" });
+    for (i, (c, n)) in coords.iter().zip(atom_names(mol)).enumerate() {
+        out.push_str(&format!("{:>7} {:<8}{:>10.4}{:>10.4}{:>10.4} {:<6}{:>5} LIG1 {:>10.4}\n", i + 1, n, c[0], c[1], c[2], types[i], 1, charges.map_or(0.0, |q| q[i])));
+    }
+    out.push_str("@<TRIPOS>BOND\n");
+    for (k, b) in mol.bonds.iter().enumerate() {
+        let t = match b.kind {
+            _ if types[b.a] == "O.co2" || types[b.b] == "O.co2" => "ar",
+            BondKind::Single => "1", BondKind::Double => "2", BondKind::Triple => "3", BondKind::Aromatic => "ar",
+        };
+        out.push_str(&format!("{:>6}{:>6}{:>6} {t}\n", k + 1, b.a + 1, b.b + 1));
+    }
+    out
+}
+
+/// AutoDock 4 atom type: aromatic carbon A, hydrogen-bond acceptors NA/OA/SA, polar HD.
+pub fn ad_type(mol: &Molecule, adj: &[Vec<(usize, BondKind)>], i: usize) -> String {
+    let a = &mol.atoms[i];
+    match a.element.as_str() {
+        "C" if a.aromatic => "A".into(),
+        "N" if a.hydrogens == 0 && a.charge <= 0 && adj[i].len() < 3 => "NA".into(),
+        "N" => "N".into(),
+        "O" => "OA".into(),
+        "S" => "SA".into(),
+        "H" if adj[i].iter().any(|&(j, _)| mol.atoms[j].element != "C") => "HD".into(),
+        other => other.to_string(),
+    }
+}
+
+/// PDBQT `ATOM` record: PDB columns 1–66, then the partial charge and AutoDock type.
+/// `residue` is (name, chain, number).
+pub fn pdbqt_atom(serial: usize, name: &str, residue: (&str, char, i64), c: [f64; 3], charge: f64, ad: &str) -> String {
+    format!("ATOM  {:>5} {:<4} {:<3} {}{:>4}    {:>8.3}{:>8.3}{:>8.3}  1.00  0.00    {:>+6.3} {:<2}\n", serial % 100_000, name, residue.0, residue.1, residue.2, c[0], c[1], c[2], charge, ad)
+}
+
+pub fn write_mmcif(name: &str, mol: &Molecule, coords: &[[f64; 3]]) -> String {
+    let names = atom_names(mol);
+    let id: String = name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' }).collect();
+    let mut out = format!("data_{}\n#\nloop_\n", if id.is_empty() { "structure".into() } else { id });
+    for col in ["group_PDB", "id", "type_symbol", "label_atom_id", "label_comp_id", "label_asym_id", "label_seq_id", "Cartn_x", "Cartn_y", "Cartn_z", "occupancy", "B_iso_or_equiv", "pdbx_formal_charge"] { out.push_str(&format!("_atom_site.{col}\n")); }
+    for (i, (a, c)) in mol.atoms.iter().zip(coords).enumerate() {
+        out.push_str(&format!("HETATM {} {} {} LIG A . {:.3} {:.3} {:.3} 1.00 0.00 {}\n", i + 1, a.element, names[i], c[0], c[1], c[2], a.charge));
+    }
+    out.push_str("#\nloop_\n");
+    for col in ["comp_id", "atom_id_1", "atom_id_2", "value_order", "pdbx_aromatic_flag"] { out.push_str(&format!("_chem_comp_bond.{col}\n")); }
+    for b in &mol.bonds {
+        let (order, arom) = match b.kind { BondKind::Single => ("SING", "N"), BondKind::Double => ("DOUB", "N"), BondKind::Triple => ("TRIP", "N"), BondKind::Aromatic => ("AROM", "Y") };
+        out.push_str(&format!("LIG {} {} {order} {arom}\n", names[b.a], names[b.b]));
+    }
+    out.push_str("#\n");
+    out
+}
+
+pub fn write_xyz(name: &str, mol: &Molecule, coords: &[[f64; 3]]) -> String {
+    let mut out = format!("{}\n{name}\n", mol.atoms.len());
+    for (a, c) in mol.atoms.iter().zip(coords) { out.push_str(&format!("{:<2} {:>12.5} {:>12.5} {:>12.5}\n", a.element, c[0], c[1], c[2])); }
+    out
+}
+
+/// Canonical format name for a requested one ("mol" → "sdf", "cif" → "mmcif"), or the
+/// format detected from `input` when none was given.
+pub fn format_name(requested: Option<&str>, input: &str) -> Result<String, String> {
+    let f = match requested.map(str::to_lowercase).as_deref() {
+        None => detect(input).to_string(),
+        Some("mol" | "sd") => "sdf".into(),
+        Some("cif") => "mmcif".into(),
+        Some("smi") => "smiles".into(),
+        Some(other) => other.to_string(),
+    };
+    if FORMATS.contains(&f.as_str()) { Ok(f) } else { Err(format!("unknown format {f}; expected one of {}", FORMATS.join(", "))) }
+}
+
+/// The structure's coordinates, embedded in 3D from the graph when the input had none.
+pub fn coordinates(st: &mut Structure, warnings: &mut Vec<String>) -> Vec<[f64; 3]> {
+    st.coords.take().unwrap_or_else(|| {
+        warnings.push("3D coordinates were generated from the graph".into());
+        let mut c = conformer::embed(&st.mol, fnv1a(st.mol.to_canonical_smiles().as_bytes()));
+        conformer::centre(&mut c);
+        c
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_are_detected_and_named() {
+        assert_eq!(detect("CCO"), "smiles");
+        assert_eq!(detect("3\nwater\nO 0 0 0\n"), "xyz");
+        assert_eq!(detect("@<TRIPOS>MOLECULE\nx\n"), "mol2");
+        assert_eq!(detect("HETATM    1  C1  LIG A   1       0.000   0.000   0.000  1.00  0.00           C\n"), "pdb");
+        assert_eq!(format_name(Some("MOL"), "").unwrap(), "sdf");
+        assert_eq!(format_name(Some("cif"), "").unwrap(), "mmcif");
+        assert_eq!(format_name(None, "CCO").unwrap(), "smiles");
+        assert!(format_name(Some("png"), "").is_err());
+        assert_eq!(element("CL"), "Cl");
+    }
+
+    #[test]
+    fn listed_hydrogens_fold_into_their_heavy_atom() {
+        let st = parse("3\nwater\nO 0.0 0.0 0.0\nH 0.9572 0.0 0.0\nH -0.2400 0.9266 0.0\n", "xyz", &mut Vec::new()).unwrap();
+        assert_eq!(st.name, "water");
+        assert!(st.explicit_h);
+        assert_eq!((st.mol.atoms.len(), st.mol.atoms[0].hydrogens), (1, 2));
+        assert_eq!(st.coords.map(|c| c.len()), Some(1));
+    }
+
+    #[test]
+    fn bond_orders_come_from_the_hydrogens() {
+        let st = parse("4\nformaldehyde\nC 0.0 0.0 0.0\nO 1.21 0.0 0.0\nH -0.54 0.94 0.0\nH -0.54 -0.94 0.0\n", "xyz", &mut Vec::new()).unwrap();
+        assert_eq!(st.mol.to_canonical_smiles(), chem::canonicalize("C=O").unwrap());
+        let mut warnings = Vec::new();
+        parse("2\n\nC 0.0 0.0 0.0\nO 1.21 0.0 0.0\n", "xyz", &mut warnings).unwrap();
+        assert!(warnings.iter().any(|w| w.contains("every bond is taken as single")));
+    }
+}
@@ -0,0 +1,214 @@
+//! Mixed-solvent MD: co-solvent probe occupancy on a protein, and the hotspots it maps.
+//!
+//! Small organic probes (isopropanol, acetonitrile, benzene and others) at a few percent in
+//! water gather where the surface binds them: at pockets, including cryptic ones the probes help
+//! open, and at allosteric sites. The protein is held rigid and each probe is one interaction
+//! centre with a well at contact depth for apolar (C, S) and polar (N, O) atoms and, for the
+//! ionic probes, a Coulomb term in water with the charged side chains. Probes don't interact
+//! with one another, as at the dilute limit, so the map doesn't depend on their concentration:
+//! each probe type is sampled on its own, by many independent copies making Metropolis moves
+//! at the run's temperature. Visits are counted on a 1 Å grid after equilibration; a point's
+//! free energy is -kT ln of its occupancy against the bulk's, each averaged over the 3 Å cube
+//! about the point. Points at or below the hotspot threshold for some probe are grown into
+//! hotspots from the deepest down, so a sticky surface patch doesn't merge neighbouring sites
+//! into one, and hotspots are ranked by how many probe types agree on them.
+
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::{pdbqt::Residue, pockets::Pocket, rng::Rng, umbrella::KB};
+
+/// A probe: contact well depths per apolar and per polar atom (kcal/mol, net of the water it
+/// displaces) and charge.
+pub struct Probe { pub name: &'static str, apolar: f64, polar: f64, charge: f64 }
+
+pub const PROBES: &[Probe] = &[
+    Probe { name: "isopropanol", apolar: 0.24, polar: 0.2, charge: 0.0 },
+    Probe { name: "acetonitrile", apolar: 0.16, polar: 0.2, charge: 0.0 },
+    Probe { name: "benzene", apolar: 0.32, polar: 0.06, charge: 0.0 },
+    Probe { name: "pyrimidine", apolar: 0.22, polar: 0.22, charge: 0.0 },
+    Probe { name: "acetamide", apolar: 0.08, polar: 0.28, charge: 0.0 },
+    Probe { name: "acetate", apolar: 0.08, polar: 0.2, charge: -1.0 },
+    Probe { name: "methylammonium", apolar: 0.08, polar: 0.2, charge: 1.0 },
+];
+pub const DEFAULT_PROBES: &[&str] = &["isopropanol", "acetonitrile", "benzene"];
+pub const DEFAULT_HOTSPOT_DG: f64 = -1.5;
+
+/// Probe–atom contact: repulsion inside `CONTACT`, a Gaussian well at `WELL` Å, nothing past `CUTOFF`.
+const CONTACT: f64 = 3.0;
+const WELL: f64 = 3.8;
+const WELL_WIDTH: f64 = 0.8;
+const CUTOFF: f64 = 6.0;
+const WATER_DIELECTRIC: f64 = 80.0;
+/// Solvent margin, Å, about the protein's bounding box.
+const MARGIN: f64 = 8.0;
+const SPACING: f64 = 1.0;
+/// Probe move size, Å per axis, and the share of the run that is equilibration.
+const MOVE: f64 = 0.7;
+/// Independent copies of each probe sampled at once.
+const WALKERS: usize = 256;
+const EQUILIBRATION: f64 = 0.2;
+/// Grid points a hotspot needs, and the lining distance for its residues.
+const MIN_POINTS: usize = 10;
+const LINING: f64 = 4.5;
+/// A hotspot whose centre is within this distance, Å, of the sphere of a pocket's volume about its
+/// centre is in that pocket.
+const IN_POCKET: f64 = 2.0;
+
+/// A protein interaction centre: position, lining residue, whether polar, and charge.
+pub struct Atom { pub pos: [f64; 3], pub residue: String, pub polar: bool, pub charge: f64 }
+
+#[derive(Serialize)]
+pub struct ProbeReport { pub probe: String, pub walkers: usize, pub samples: usize, pub acceptance: f64 }
+#[derive(Serialize)]
+pub struct ProbeHit { pub probe: String, pub peak_delta_g_kcal_mol: f64, pub points: usize }
+#[derive(Serialize)]
+pub struct Hotspot {
+    pub hotspot_id: String, pub center: [f64; 3], pub volume_a3: f64, pub peak_delta_g_kcal_mol: f64, pub consensus: usize, pub probes: Vec<ProbeHit>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub pocket_id: Option<String>, pub cryptic: bool, pub residues: Vec<String>,
+}
+#[derive(Serialize)]
+pub struct Map { pub probes: Vec<ProbeReport>, pub hotspots: Vec<Hotspot> }
+
+pub struct Settings { pub probes: Vec<&'static Probe>, pub steps: usize, pub temperature_k: f64, pub hotspot_dg: f64, pub seed: u64 }
+
+fn dist2(a: [f64; 3], b: [f64; 3]) -> f64 { (0..3).map(|k| (a[k] - b[k]).powi(2)).sum() }
+
+pub fn probe(name: &str) -> Option<&'static Probe> { PROBES.iter().find(|p| p.name.eq_ignore_ascii_case(name)) }
+
+/// Formal charge on a side-chain atom at pH 7, spread over carboxylate oxygens and guanidinium nitrogens.
+fn atom_charge(residue: &str, atom: &str) -> f64 {
+    match (residue, atom) { ("ASP", "OD1" | "OD2") | ("GLU", "OE1" | "OE2") => -0.5, ("LYS", "NZ") => 1.0, ("ARG", "NH1" | "NH2") => 0.5, _ => 0.0 }
+}
+
+/// Interaction centres of a structure's protein heavy atoms.
+pub fn atoms_of(residues: &[Residue]) -> Vec<Atom> {
+    residues.iter().filter(|r| !r.hetero).flat_map(|r| r.atoms.iter().map(move |(name, element, pos)| Atom {
+        pos: *pos, residue: format!("{}{}", r.name, r.number), polar: matches!(element.as_str(), "N" | "O"), charge: atom_charge(&r.name, name.trim()),
+    })).collect()
+}
+
+/// Samples each probe about `atoms` and maps its hotspots. `pockets` are those of the starting
+/// structure, to tell known sites from cryptic ones.
+pub fn run(atoms: &[Atom], pockets: &[Pocket], settings: &Settings) -> Result<Map, String> {
+    if atoms.is_empty() { return Err("no protein atoms to map".into()); }
+    let (mut lo, mut hi) = ([f64::INFINITY; 3], [f64::NEG_INFINITY; 3]);
+    for a in atoms { for k in 0..3 { (lo[k], hi[k]) = (lo[k].min(a.pos[k] - MARGIN), hi[k].max(a.pos[k] + MARGIN)); } }
+    let dims: [usize; 3] = std::array::from_fn(|k| ((hi[k] - lo[k]) / SPACING) as usize + 1);
+    let total: usize = dims.iter().product();
+    let index = |g: [usize; 3]| (g[0] * dims[1] + g[1]) * dims[2] + g[2];
+    let cell_of = |p: [f64; 3]| -> Option<[usize; 3]> {
+        let mut g = [0; 3];
+        for k in 0..3 { let v = ((p[k] - lo[k]) / SPACING).round(); if v < 0.0 || v as usize >= dims[k] { return None; } g[k] = v as usize; }
+        Some(g)
+    };
+    let position = |g: [usize; 3]| -> [f64; 3] { std::array::from_fn(|k| lo[k] + g[k] as f64 * SPACING) };
+    let mut cells: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+    for (i, a) in atoms.iter().enumerate() { cells.entry(a.pos.map(|v| (v / CUTOFF).floor() as i64)).or_default().push(i); }
+    let near = |p: [f64; 3], r: f64| -> Vec<usize> {
+        let c = p.map(|v| (v / CUTOFF).floor() as i64);
+        let mut out = Vec::new();
+        for dx in -1..=1 { for dy in -1..=1 { for dz in -1..=1 {
+            if let Some(v) = cells.get(&[c[0] + dx, c[1] + dy, c[2] + dz]) { out.extend(v.iter().filter(|&&i| dist2(atoms[i].pos, p) <= r * r)); }
+        } } }
+        out
+    };
+    let energy = |probe: &Probe, p: [f64; 3]| -> f64 {
+        near(p, CUTOFF).into_iter().map(|i| {
+            let a = &atoms[i];
+            let r = dist2(a.pos, p).sqrt().max(0.5);
+            let repulsion = if r < CONTACT { 10.0 * (CONTACT - r).powi(2) } else { 0.0 };
+            let well = -(if a.polar { probe.polar } else { probe.apolar }) * (-((r - WELL) / WELL_WIDTH).powi(2)).exp();
+            // Coulomb in water, held at its contact value inside contact.
+            repulsion + well + 332.06 * probe.charge * a.charge / (WATER_DIELECTRIC * r.max(CONTACT))
+        }).sum()
+    };
+    let kt = KB * settings.temperature_k;
+    let equilibration = (settings.steps as f64 * EQUILIBRATION) as usize;
+    // Bulk points: no atom within the cutoff, so the probe there is free.
+    let bulk: Vec<bool> = (0..total).map(|i| { let g = [i / (dims[1] * dims[2]), i / dims[2] % dims[1], i % dims[2]]; near(position(g), CUTOFF).is_empty() }).collect();
+
+    let mut reports = Vec::new();
+    let mut maps: Vec<Vec<f64>> = Vec::new();
+    for (t, probe) in settings.probes.iter().enumerate() {
+        let mut rng = Rng((settings.seed ^ (t as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15)) | 1);
+        let random_point = |rng: &mut Rng| -> [f64; 3] { std::array::from_fn(|k| lo[k] + rng.uniform() * (hi[k] - lo[k])) };
+        let mut probes: Vec<([f64; 3], f64)> = (0..WALKERS).map(|_| {
+            let mut p = random_point(&mut rng);
+            for _ in 0..100 { if energy(probe, p) < 1.0 { break; } p = random_point(&mut rng); }
+            (p, energy(probe, p))
+        }).collect();
+        let mut counts = vec![0u32; total];
+        let (mut accepted, mut moves, mut samples) = (0usize, 0usize, 0usize);
+        for step in 0..settings.steps {
+            for (p, e) in probes.iter_mut() {
+                let trial: [f64; 3] = std::array::from_fn(|k| p[k] + MOVE * rng.gauss());
+                moves += 1;
+                if (0..3).any(|k| trial[k] < lo[k] || trial[k] > hi[k]) { continue; }
+                let et = energy(probe, trial);
+                if et <= *e || rng.uniform() < ((*e - et) / kt).exp() { (*p, *e) = (trial, et); accepted += 1; }
+                if step >= equilibration { if let Some(g) = cell_of(*p) { counts[index(g)] += 1; samples += 1; } }
+            }
+        }
+        // Occupancy over the 3 Å cube about each point, against the bulk's mean.
+        let mut smooth = vec![0.0; total];
+        for x in 0..dims[0] { for y in 0..dims[1] { for z in 0..dims[2] {
+            let mut sum = 0.0;
+            for dx in 0..3 { for dy in 0..3 { for dz in 0..3 {
+                let g = [(x + dx).wrapping_sub(1), (y + dy).wrapping_sub(1), (z + dz).wrapping_sub(1)];
+                if (0..3).all(|k| g[k] < dims[k]) { sum += counts[index(g)] as f64; }
+            } } }
+            smooth[index([x, y, z])] = sum;
+        } } }
+        let (bulk_sum, bulk_n) = smooth.iter().zip(&bulk).filter(|(_, b)| **b).fold((0.0, 0usize), |acc, (c, _)| (acc.0 + c, acc.1 + 1));
+        let reference = if bulk_n > 0 && bulk_sum > 0.0 { bulk_sum / bulk_n as f64 } else { 27.0 * samples as f64 / total as f64 };
+        maps.push(smooth.iter().map(|&c| if c > 0.0 { -kt * (c / reference).ln() } else { f64::INFINITY }).collect());
+        reports.push(ProbeReport { probe: probe.name.into(), walkers: WALKERS, samples, acceptance: (accepted as f64 / moves.max(1) as f64 * 1000.0).round() / 1000.0 });
+    }
+
+    // Hotspots, grown from their deepest points in order: a point at or below the threshold for
+    // some probe joins the deepest hotspot it touches, as a member if it is at least half as
+    // deep as that hotspot's peak, else as rim; a point touching none starts a hotspot.
+    let best: Vec<f64> = (0..total).map(|i| maps.iter().map(|m| m[i]).fold(f64::INFINITY, f64::min)).collect();
+    let mut order: Vec<usize> = (0..total).filter(|&i| best[i] <= settings.hotspot_dg).collect();
+    order.sort_by(|&a, &b| best[a].total_cmp(&best[b]));
+    let mut label = vec![usize::MAX; total];
+    let mut members: Vec<Vec<usize>> = Vec::new();
+    for i in order {
+        let g = [i / (dims[1] * dims[2]), i / dims[2] % dims[1], i % dims[2]];
+        let touching = [(0, 1), (0, -1), (1, 1), (1, -1), (2, 1), (2, -1)].into_iter().filter_map(|(k, d)| {
+            let mut q = g;
+            q[k] = g[k].checked_add_signed(d).filter(|&v| v < dims[k])?;
+            Some(label[index(q)]).filter(|&h| h != usize::MAX)
+        }).min_by(|&a, &b| best[members[a][0]].total_cmp(&best[members[b][0]]));
+        match touching {
+            Some(h) => { label[i] = h; if best[i] <= best[members[h][0]] / 2.0 { members[h].push(i); } }
+            None => { label[i] = members.len(); members.push(vec![i]); }
+        }
+    }
+    let mut hotspots = Vec::new();
+    for cluster in members {
+        if cluster.len() < MIN_POINTS { continue; }
+        let point = |i: usize| position([i / (dims[1] * dims[2]), i / dims[2] % dims[1], i % dims[2]]);
+        // Centre weighted by how strongly each point binds.
+        let weights: Vec<f64> = cluster.iter().map(|&i| -best[i]).collect();
+        let wsum: f64 = weights.iter().sum();
+        let center: [f64; 3] = std::array::from_fn(|k| (cluster.iter().zip(&weights).map(|(&i, w)| point(i)[k] * w).sum::<f64>() / wsum * 100.0).round() / 100.0);
+        let mut probes: Vec<ProbeHit> = settings.probes.iter().zip(&maps).filter_map(|(p, m)| {
+            let hits: Vec<f64> = cluster.iter().map(|&i| m[i]).filter(|&g| g <= settings.hotspot_dg).collect();
+            (!hits.is_empty()).then(|| ProbeHit { probe: p.name.into(), peak_delta_g_kcal_mol: (hits.iter().copied().fold(f64::INFINITY, f64::min) * 100.0).round() / 100.0, points: hits.len() })
+        }).collect();
+        probes.sort_by(|a, b| a.peak_delta_g_kcal_mol.total_cmp(&b.peak_delta_g_kcal_mol));
+        let mut residues: Vec<String> = cluster.iter().flat_map(|&i| near(point(i), LINING)).map(|a| atoms[a].residue.clone()).collect();
+        residues.sort();
+        residues.dedup();
+        let pocket_id = pockets.iter().map(|p| (p, dist2(p.center, center).sqrt() - (3.0 * p.volume_a3 / (4.0 * std::f64::consts::PI)).cbrt())).filter(|(_, d)| *d <= IN_POCKET).min_by(|a, b| a.1.total_cmp(&b.1)).map(|(p, _)| p.pocket_id.clone());
+        hotspots.push(Hotspot {
+            hotspot_id: String::new(), center, volume_a3: cluster.len() as f64 * SPACING.powi(3), peak_delta_g_kcal_mol: probes.first().map_or(0.0, |p| p.peak_delta_g_kcal_mol),
+            consensus: probes.len(), probes, cryptic: pocket_id.is_none(), pocket_id, residues,
+        });
+    }
+    hotspots.sort_by(|a, b| b.consensus.cmp(&a.consensus).then(a.peak_delta_g_kcal_mol.total_cmp(&b.peak_delta_g_kcal_mol)));
+    for (i, h) in hotspots.iter_mut().enumerate() { h.hotspot_id = format!("H{}", i + 1); }
+    Ok(Map { probes: reports, hotspots })
+}
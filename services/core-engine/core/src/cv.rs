@@ -0,0 +1,88 @@
+//! Collective variables over a molecule's heavy atoms.
+//!
+//! A distance, bond angle or dihedral between atoms given by 1-based index in the molecule's
+//! canonical SMILES order. Values are Å or radians internally and Å or degrees in the API;
+//! dihedrals are periodic, so their differences wrap into (−π, π]. Each variable has the
+//! analytic gradient that biased dynamics needs.
+
+use serde::Deserialize;
+
+use crate::vec3::{sub, add, scale, dot, cross};
+
+pub const KINDS: [&str; 3] = ["distance", "angle", "dihedral"];
+
+#[derive(Deserialize, Clone)]
+pub struct CvSpec { #[serde(rename = "type")] pub kind: String, pub atoms: Vec<usize> }
+
+#[derive(Clone, Copy)]
+pub enum Cv { Distance([usize; 2]), Angle([usize; 3]), Dihedral([usize; 4]) }
+
+impl Cv {
+    /// A variable over a molecule of `atoms` heavy atoms.
+    pub fn parse(spec: &CvSpec, atoms: usize) -> Result<Self, String> {
+        let want = match spec.kind.as_str() { "distance" => 2, "angle" => 3, "dihedral" | "torsion" => 4, other => return Err(format!("unknown collective variable {other}; expected one of {}", KINDS.join(", "))) };
+        if spec.atoms.len() != want { return Err(format!("a {} takes {want} atoms, not {}", spec.kind, spec.atoms.len())); }
+        if let Some(&bad) = spec.atoms.iter().find(|&&a| a == 0 || a > atoms) { return Err(format!("atom {bad} is out of range; the molecule has {atoms} heavy atoms")); }
+        let a: Vec<usize> = spec.atoms.iter().map(|a| a - 1).collect();
+        if (1..a.len()).any(|i| a[..i].contains(&a[i])) { return Err(format!("the atoms of a {} must be distinct", spec.kind)); }
+        Ok(match want { 2 => Self::Distance([a[0], a[1]]), 3 => Self::Angle([a[0], a[1], a[2]]), _ => Self::Dihedral([a[0], a[1], a[2], a[3]]) })
+    }
+
+    pub fn periodic(self) -> bool { matches!(self, Self::Dihedral(_)) }
+
+    pub fn unit(self) -> &'static str { if matches!(self, Self::Distance(_)) { "angstrom" } else { "degree" } }
+
+    /// "distance 1-8", "dihedral 2-3-4-5".
+    pub fn label(self) -> String {
+        let (kind, atoms): (&str, &[usize]) = match &self { Self::Distance(a) => ("distance", a), Self::Angle(a) => ("angle", a), Self::Dihedral(a) => ("dihedral", a) };
+        format!("{kind} {}", atoms.iter().map(|a| (a + 1).to_string()).collect::<Vec<_>>().join("-"))
+    }
+
+    /// API units (Å, degrees) to internal ones (Å, radians), and back.
+    pub fn to_internal(self, v: f64) -> f64 { if matches!(self, Self::Distance(_)) { v } else { v.to_radians() } }
+    pub fn to_api(self, v: f64) -> f64 { if matches!(self, Self::Distance(_)) { v } else { v.to_degrees() } }
+
+    /// `a − b`, wrapped into (−π, π] for periodic variables.
+    pub fn delta(self, a: f64, b: f64) -> f64 {
+        let d = a - b;
+        if !self.periodic() { return d; }
+        let tau = std::f64::consts::TAU;
+        d - tau * (d / tau).round()
+    }
+
+    pub fn value(self, x: &[[f64; 3]]) -> f64 { self.gradient(x).0 }
+
+    /// The value and its gradient on each atom involved.
+    pub fn gradient(self, x: &[[f64; 3]]) -> (f64, Vec<(usize, [f64; 3])>) {
+        match self {
+            Self::Distance([i, j]) => {
+                let d = sub(x[i], x[j]);
+                let r = dot(d, d).sqrt().max(1e-9);
+                (r, vec![(i, scale(d, 1.0 / r)), (j, scale(d, -1.0 / r))])
+            }
+            Self::Angle([i, j, k]) => {
+                let (u, v) = (sub(x[i], x[j]), sub(x[k], x[j]));
+                let (lu, lv) = (dot(u, u).sqrt().max(1e-9), dot(v, v).sqrt().max(1e-9));
+                let cos = (dot(u, v) / (lu * lv)).clamp(-1.0, 1.0);
+                let sin = (1.0 - cos * cos).sqrt().max(1e-6);
+                let gi = scale(sub(scale(v, 1.0 / (lu * lv)), scale(u, cos / (lu * lu))), -1.0 / sin);
+                let gk = scale(sub(scale(u, 1.0 / (lu * lv)), scale(v, cos / (lv * lv))), -1.0 / sin);
+                (cos.acos(), vec![(i, gi), (k, gk), (j, scale(add(gi, gk), -1.0))])
+            }
+            Self::Dihedral([i, j, k, l]) => {
+                // Blondel & Karplus (1996): F = ri − rj, G = rj − rk, H = rl − rk.
+                let (f, g, h) = (sub(x[i], x[j]), sub(x[j], x[k]), sub(x[l], x[k]));
+                let (a, b) = (cross(f, g), cross(h, g));
+                let (aa, bb) = (dot(a, a).max(1e-12), dot(b, b).max(1e-12));
+                let lg = dot(g, g).sqrt().max(1e-9);
+                let phi = (dot(cross(b, a), g) / lg).atan2(dot(a, b));
+                let gi = scale(a, -lg / aa);
+                let gl = scale(b, lg / bb);
+                let (fg, hg) = (dot(f, g) / (aa * lg), dot(h, g) / (bb * lg));
+                let gj = add(sub(scale(a, fg), scale(b, hg)), scale(gi, -1.0));
+                let gk = add(sub(scale(b, hg), scale(a, fg)), scale(gl, -1.0));
+                (phi, vec![(i, gi), (j, gj), (k, gk), (l, gl)])
+            }
+        }
+    }
+}
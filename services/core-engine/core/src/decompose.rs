@@ -0,0 +1,134 @@
+//! Per-residue and residue-pair energy decomposition for `/energy`.
+//!
+//! With `decompose`, the molecule's atoms are grouped into the peptide residues perceived along
+//! its chain (see `selection::residues`) and, for everything else, one group per separate
+//! molecule: `HOH` for a water, the element for a single-atom ion, `LIG` otherwise, numbered on
+//! from the last residue. On the generated conformer, the non-bonded energy between every two
+//! groups is summed over their atom pairs: Coulomb with the request's charge model, as in
+//! `electrostatic_energy`, and the softened Lennard-Jones term of the pose force field, with a
+//! free metal ion's own contact distances (see `forcefield::metal_pair`). Pairs within two bonds
+//! are skipped and 1-4 pairs scaled (electrostatics as `charges::coulomb`, van der Waals by ½).
+//! A residue's share is its interactions within itself plus half of every pair it is in, so the
+//! shares add up to the total; residue pairs that don't interact within the cutoff are left out.
+//! The pairs are computed one at a time, so a large system's can be streamed as they come, with
+//! the residues after them.
+
+use serde::Serialize;
+
+use crate::{charges, chem::Molecule, cofactors::{self, Metal}, depict, forcefield, selection};
+
+const SCALE_14_VDW: f64 = 0.5;
+
+#[derive(Serialize, Clone)]
+pub struct ResidueEnergy { pub residue: String, pub atoms: usize, pub electrostatic_kcal_mol: f64, pub vdw_kcal_mol: f64, pub total_kcal_mol: f64 }
+
+#[derive(Serialize, Clone)]
+pub struct PairEnergy { pub a: String, pub b: String, pub electrostatic_kcal_mol: f64, pub vdw_kcal_mol: f64, pub total_kcal_mol: f64 }
+
+#[derive(Serialize, Clone)]
+pub struct Decomposition { pub residues: Vec<ResidueEnergy>, pub pairs: Vec<PairEnergy> }
+
+/// One line of the NDJSON stream.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Line { Pair(PairEnergy), Residue(ResidueEnergy) }
+
+/// Yields each interacting residue pair, then every residue's share.
+pub struct Decomposer {
+    groups: Vec<(String, Vec<usize>)>, x: Vec<[f64; 3]>, q: Vec<f64>, elements: Vec<String>, ions: Vec<Option<&'static Metal>>, near: Vec<Vec<(usize, u8)>>,
+    next: (usize, usize), shares: Vec<(f64, f64)>, residues: std::vec::IntoIter<ResidueEnergy>,
+}
+
+fn round(v: f64) -> f64 { (v * 1e3).round() / 1e3 + 0.0 }
+
+impl Decomposer {
+    /// Decomposes `m` at coordinates `x` with per-atom charges `q`.
+    pub fn new(m: &Molecule, x: Vec<[f64; 3]>, q: Vec<f64>) -> Self {
+        let adj = m.neighbors();
+        let atoms = selection::molecule_atoms(m, None);
+        let mut residues: Vec<(i64, String, Vec<usize>)> = Vec::new();
+        for (i, a) in atoms.iter().enumerate().filter(|(_, a)| a.protein) {
+            match residues.iter_mut().find(|r| r.0 == a.resid) { Some(r) => r.2.push(i), None => residues.push((a.resid, format!("{}{}", a.resname, a.resid), vec![i])) }
+        }
+        residues.sort_by_key(|r| r.0);
+        let mut groups: Vec<(String, Vec<usize>)> = residues.into_iter().map(|r| (r.1, r.2)).collect();
+        for comp in depict::components(&adj).into_iter().filter(|c| !atoms[c[0]].protein) {
+            let name = match comp.as_slice() {
+                [i] if m.atoms[*i].element == "O" && m.atoms[*i].hydrogens == 2 => "HOH".to_string(),
+                [i] if m.atoms[*i].charge != 0 => m.atoms[*i].element.to_uppercase(),
+                _ => "LIG".to_string(),
+            };
+            groups.push((format!("{name}{}", groups.len() + 1), comp));
+        }
+        // Atoms up to three bonds from each atom, with their separation.
+        let near = (0..m.atoms.len()).map(|i| {
+            let mut seen = vec![(i, 0u8)];
+            let mut frontier = vec![i];
+            for d in 1..=3u8 {
+                let mut next = Vec::new();
+                for &a in &frontier { for &(b, _) in &adj[a] { if !seen.iter().any(|s| s.0 == b) { seen.push((b, d)); next.push(b); } } }
+                frontier = next;
+            }
+            seen
+        }).collect();
+        let n = groups.len();
+        let elements = m.atoms.iter().map(|a| a.element.clone()).collect();
+        let ions = (0..m.atoms.len()).map(|i| cofactors::metal(&m.atoms[i].element).filter(|_| adj[i].is_empty())).collect();
+        Self { groups, x, q, elements, ions, near, next: (0, 0), shares: vec![(0.0, 0.0); n], residues: Vec::new().into_iter() }
+    }
+
+    /// Electrostatic and van der Waals energy between groups `a` and `b` (within `a` when equal).
+    fn pair(&self, a: usize, b: usize) -> (f64, f64) {
+        let (mut elec, mut vdw) = (0.0, 0.0);
+        for &i in &self.groups[a].1 {
+            for &j in &self.groups[b].1 {
+                if a == b && j <= i { continue; }
+                let sep = self.near[i].iter().find(|s| s.0 == j).map_or(u8::MAX, |s| s.1);
+                if sep < 3 { continue; }
+                let r = (0..3).map(|k| (self.x[i][k] - self.x[j][k]).powi(2)).sum::<f64>().sqrt();
+                elec += charges::pair(self.q[i], self.q[j], r, sep);
+                let pair = match (self.ions[i], self.ions[j]) {
+                    (Some(ion), None) => forcefield::metal_pair(ion, &self.elements[j], r),
+                    (None, Some(ion)) => forcefield::metal_pair(ion, &self.elements[i], r),
+                    _ => forcefield::pair_vdw(r),
+                };
+                vdw += if sep == 3 { SCALE_14_VDW } else { 1.0 } * pair;
+            }
+        }
+        (elec, vdw)
+    }
+
+    /// Collects everything, for a JSON response.
+    pub fn collect(self) -> Decomposition {
+        let mut out = Decomposition { residues: Vec::new(), pairs: Vec::new() };
+        for line in self {
+            match line { Line::Pair(p) => out.pairs.push(p), Line::Residue(r) => out.residues.push(r) }
+        }
+        out
+    }
+}
+
+impl Iterator for Decomposer {
+    type Item = Line;
+
+    fn next(&mut self) -> Option<Line> {
+        let n = self.groups.len();
+        while self.next.0 < n {
+            let (a, b) = self.next;
+            self.next = if b + 1 < n { (a, b + 1) } else { (a + 1, a + 1) };
+            let (elec, vdw) = self.pair(a, b);
+            if a == b {
+                self.shares[a].0 += elec;
+                self.shares[a].1 += vdw;
+                continue;
+            }
+            for g in [a, b] { self.shares[g].0 += elec / 2.0; self.shares[g].1 += vdw / 2.0; }
+            if elec == 0.0 && vdw == 0.0 { continue; }
+            return Some(Line::Pair(PairEnergy { a: self.groups[a].0.clone(), b: self.groups[b].0.clone(), electrostatic_kcal_mol: round(elec), vdw_kcal_mol: round(vdw), total_kcal_mol: round(elec + vdw) }));
+        }
+        if !self.shares.is_empty() {
+            self.residues = self.groups.iter().zip(std::mem::take(&mut self.shares)).map(|((name, atoms), (elec, vdw))| ResidueEnergy { residue: name.clone(), atoms: atoms.len(), electrostatic_kcal_mol: round(elec), vdw_kcal_mol: round(vdw), total_kcal_mol: round(elec + vdw) }).collect::<Vec<_>>().into_iter();
+        }
+        self.residues.next().map(Line::Residue)
+    }
+}
@@ -0,0 +1,333 @@
+//! 2D depiction: coordinate layout plus SVG and PNG rendering.
+//!
+//! Coordinates come from stress majorization on graph distances, seeded by classical MDS,
+//! with bond length 1.0. Disconnected components are laid out separately and placed side by
+//! side; each is rotated so its long axis is horizontal. Hydrogens stay implicit and are
+//! drawn as part of heteroatom labels. PNG output carries no text, only element colouring.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+
+use crate::chem::{BondKind, Molecule};
+
+const ITERATIONS: usize = 300;
+
+#[derive(Serialize)]
+pub struct Layout { pub atoms: Vec<LaidAtom>, pub bonds: Vec<LaidBond>, pub width: f64, pub height: f64 }
+#[derive(Serialize)]
+pub struct LaidAtom { pub element: String, pub x: f64, pub y: f64, pub hydrogens: u8, pub charge: i8, pub aromatic: bool }
+#[derive(Serialize)]
+pub struct LaidBond { pub a: usize, pub b: usize, pub order: &'static str }
+
+/// Lay out every component of `mol` and pack them left to right.
+pub fn layout(mol: &Molecule) -> Layout {
+    let adj = mol.neighbors();
+    let mut coords = vec![(0.0, 0.0); mol.atoms.len()];
+    let mut offset = 0.0;
+    let mut height: f64 = 0.0;
+    for comp in components(&adj) {
+        let pos = layout_component(&comp, &adj);
+        let (min_x, max_x) = pos.iter().fold((f64::MAX, f64::MIN), |(lo, hi), p| (lo.min(p.0), hi.max(p.0)));
+        let (min_y, max_y) = pos.iter().fold((f64::MAX, f64::MIN), |(lo, hi), p| (lo.min(p.1), hi.max(p.1)));
+        for (&atom, p) in comp.iter().zip(&pos) { coords[atom] = (p.0 - min_x + offset, p.1 - min_y); }
+        offset += max_x - min_x + 1.5;
+        height = height.max(max_y - min_y);
+    }
+    let atoms = mol.atoms.iter().zip(&coords).map(|(a, &(x, y))| LaidAtom { element: a.element.clone(), x, y, hydrogens: a.hydrogens, charge: a.charge, aromatic: a.aromatic }).collect();
+    let bonds = mol.bonds.iter().map(|b| LaidBond { a: b.a, b: b.b, order: match b.kind { BondKind::Single => "single", BondKind::Double => "double", BondKind::Triple => "triple", BondKind::Aromatic => "aromatic" } }).collect();
+    Layout { atoms, bonds, width: (offset - 1.5).max(0.0), height }
+}
+
+/// Connected components of a molecular graph, each in breadth-first order from its lowest atom.
+pub fn components(adj: &[Vec<(usize, BondKind)>]) -> Vec<Vec<usize>> {
+    let mut seen = vec![false; adj.len()];
+    let mut out = Vec::new();
+    for start in 0..adj.len() {
+        if seen[start] { continue; }
+        seen[start] = true;
+        let mut comp = vec![start];
+        let mut i = 0;
+        while i < comp.len() {
+            for &(n, _) in &adj[comp[i]] { if !seen[n] { seen[n] = true; comp.push(n); } }
+            i += 1;
+        }
+        out.push(comp);
+    }
+    out
+}
+
+/// Positions for the atoms of one component, in the order given.
+fn layout_component(comp: &[usize], adj: &[Vec<(usize, BondKind)>]) -> Vec<(f64, f64)> {
+    let n = comp.len();
+    if n == 1 { return vec![(0.0, 0.0)]; }
+    let local: std::collections::HashMap<usize, usize> = comp.iter().enumerate().map(|(i, &a)| (a, i)).collect();
+    // Ideal distances: a 120° zigzag puts atoms k bonds apart about 0.87k bond lengths apart.
+    let ideal: Vec<Vec<f64>> = (0..n).map(|i| {
+        let hops = bfs_hops(comp[i], adj, &local, n);
+        hops.into_iter().map(|k| match k { 0 => 0.0, 1 => 1.0, k => 0.866 * k as f64 }).collect()
+    }).collect();
+    let mut pos = classical_mds(&ideal);
+    for _ in 0..ITERATIONS {
+        for i in 0..n {
+            let (mut sx, mut sy, mut sw) = (0.0, 0.0, 0.0);
+            for j in (0..n).filter(|&j| j != i) {
+                let d = ideal[i][j];
+                let w = 1.0 / (d * d);
+                let (dx, dy) = (pos[i].0 - pos[j].0, pos[i].1 - pos[j].1);
+                let len = (dx * dx + dy * dy).sqrt().max(1e-6);
+                sx += w * (pos[j].0 + d * dx / len);
+                sy += w * (pos[j].1 + d * dy / len);
+                sw += w;
+            }
+            pos[i] = (sx / sw, sy / sw);
+        }
+    }
+    align_principal_axis(&mut pos);
+    pos
+}
+
+fn bfs_hops(start: usize, adj: &[Vec<(usize, BondKind)>], local: &std::collections::HashMap<usize, usize>, n: usize) -> Vec<usize> {
+    let mut hops = vec![usize::MAX; n];
+    hops[local[&start]] = 0;
+    let mut queue = VecDeque::from([start]);
+    while let Some(a) = queue.pop_front() {
+        let h = hops[local[&a]];
+        for &(b, _) in &adj[a] {
+            if hops[local[&b]] == usize::MAX { hops[local[&b]] = h + 1; queue.push_back(b); }
+        }
+    }
+    hops
+}
+
+/// Top two eigenvectors of the double-centred squared distance matrix, by power iteration.
+fn classical_mds(d: &[Vec<f64>]) -> Vec<(f64, f64)> {
+    let n = d.len();
+    let sq: Vec<Vec<f64>> = d.iter().map(|r| r.iter().map(|v| v * v).collect()).collect();
+    let row: Vec<f64> = sq.iter().map(|r| r.iter().sum::<f64>() / n as f64).collect();
+    let all = row.iter().sum::<f64>() / n as f64;
+    let b: Vec<Vec<f64>> = (0..n).map(|i| (0..n).map(|j| -0.5 * (sq[i][j] - row[i] - row[j] + all)).collect()).collect();
+    let mut units: Vec<Vec<f64>> = Vec::new();
+    let mut axes: Vec<Vec<f64>> = Vec::new();
+    for k in 0..2 {
+        // Deterministic, non-symmetric start vector so symmetric molecules don't stall.
+        let mut v: Vec<f64> = (0..n).map(|i| ((i * (k + 2)) as f64 * 0.7).sin() + 0.01 * i as f64).collect();
+        let mut lambda = 0.0;
+        for _ in 0..100 {
+            let mut w: Vec<f64> = (0..n).map(|i| (0..n).map(|j| b[i][j] * v[j]).sum()).collect();
+            for u in &units { let dot: f64 = w.iter().zip(u).map(|(x, y)| x * y).sum(); for (x, y) in w.iter_mut().zip(u) { *x -= dot * y; } }
+            let norm = w.iter().map(|x| x * x).sum::<f64>().sqrt();
+            if norm < 1e-12 { break; }
+            lambda = norm;
+            v = w.into_iter().map(|x| x / norm).collect();
+        }
+        axes.push(v.iter().map(|x| x * lambda.sqrt()).collect());
+        units.push(v);
+    }
+    (0..n).map(|i| (axes[0][i], axes[1][i] + 1e-3 * i as f64)).collect()
+}
+
+fn align_principal_axis(pos: &mut [(f64, f64)]) {
+    let n = pos.len() as f64;
+    let (cx, cy) = pos.iter().fold((0.0, 0.0), |(x, y), p| (x + p.0 / n, y + p.1 / n));
+    let (mut sxx, mut syy, mut sxy) = (0.0, 0.0, 0.0);
+    for p in pos.iter() { let (x, y) = (p.0 - cx, p.1 - cy); sxx += x * x; syy += y * y; sxy += x * y; }
+    let theta = -0.5 * (2.0 * sxy).atan2(sxx - syy);
+    let (s, c) = theta.sin_cos();
+    for p in pos.iter_mut() { let (x, y) = (p.0 - cx, p.1 - cy); *p = (x * c - y * s, x * s + y * c); }
+}
+
+fn element_colour(element: &str) -> (u8, u8, u8) {
+    match element {
+        "N" => (48, 80, 248), "O" => (255, 13, 13), "S" => (200, 160, 0), "P" => (255, 128, 0), "F" | "Cl" => (31, 160, 31),
+        "Br" => (166, 41, 41), "I" => (148, 0, 148), "C" | "H" => (34, 34, 34), _ => (120, 90, 160),
+    }
+}
+
+/// Carbon is drawn as a bare vertex unless charged or isolated.
+fn label(a: &LaidAtom, degree: usize) -> Option<String> {
+    if a.element == "C" && a.charge == 0 && degree > 0 { return None; }
+    let mut s = a.element.clone();
+    match a.hydrogens { 0 => {}, 1 => s.push('H'), h => s.push_str(&format!("H{h}")) }
+    match a.charge { 0 => {}, 1 => s.push('+'), -1 => s.push('-'), c if c > 0 => s.push_str(&format!("{c}+")), c => s.push_str(&format!("{}-", -c)) }
+    Some(s)
+}
+
+/// Screen-space geometry shared by the SVG and PNG renderers.
+struct Frame { scale: f64, left: f64, top: f64, height: f64 }
+
+impl Frame {
+    fn new(l: &Layout, size: u32) -> Self {
+        let margin = size as f64 * 0.1;
+        let scale = ((size as f64 - 2.0 * margin) / l.width.max(l.height).max(1.0)).min(size as f64 / 4.0);
+        let (left, top) = ((size as f64 - l.width * scale) / 2.0, (size as f64 - l.height * scale) / 2.0);
+        Self { scale, left, top, height: l.height }
+    }
+    fn point(&self, x: f64, y: f64) -> (f64, f64) { (self.left + x * self.scale, self.top + (self.height - y) * self.scale) }
+}
+
+/// Line segments for every bond, with ends trimmed where the atom carries a label. Each
+/// segment is (x1, y1, x2, y2, dashed).
+fn bond_segments(l: &Layout, f: &Frame, labelled: &[bool], ring_centres: &[Option<(f64, f64)>]) -> Vec<(f64, f64, f64, f64, bool)> {
+    let mut out = Vec::new();
+    for (bi, b) in l.bonds.iter().enumerate() {
+        let (mut p, mut q) = (f.point(l.atoms[b.a].x, l.atoms[b.a].y), f.point(l.atoms[b.b].x, l.atoms[b.b].y));
+        let (dx, dy) = (q.0 - p.0, q.1 - p.1);
+        let len = (dx * dx + dy * dy).sqrt().max(1e-6);
+        let (ux, uy) = (dx / len, dy / len);
+        let trim = f.scale * 0.3;
+        if labelled[b.a] { p = (p.0 + ux * trim, p.1 + uy * trim); }
+        if labelled[b.b] { q = (q.0 - ux * trim, q.1 - uy * trim); }
+        out.push((p.0, p.1, q.0, q.1, false));
+        let gap = f.scale * 0.18;
+        let (nx, ny) = (-uy, ux);
+        match b.order {
+            "double" | "aromatic" => {
+                // Second line goes inside the ring when there is one, otherwise either side.
+                let dashed = b.order == "aromatic";
+                let side = match ring_centres[bi] {
+                    Some(c) => { let (mx, my) = ((p.0 + q.0) / 2.0, (p.1 + q.1) / 2.0); if (c.0 - mx) * nx + (c.1 - my) * ny >= 0.0 { 1.0 } else { -1.0 } }
+                    None if !dashed => { out.pop(); out.push((p.0 - nx * gap / 2.0, p.1 - ny * gap / 2.0, q.0 - nx * gap / 2.0, q.1 - ny * gap / 2.0, false)); out.push((p.0 + nx * gap / 2.0, p.1 + ny * gap / 2.0, q.0 + nx * gap / 2.0, q.1 + ny * gap / 2.0, false)); continue; }
+                    None => 1.0,
+                };
+                let shrink = len * 0.12;
+                out.push((p.0 + nx * gap * side + ux * shrink, p.1 + ny * gap * side + uy * shrink, q.0 + nx * gap * side - ux * shrink, q.1 + ny * gap * side - uy * shrink, dashed));
+            }
+            "triple" => {
+                out.push((p.0 + nx * gap, p.1 + ny * gap, q.0 + nx * gap, q.1 + ny * gap, false));
+                out.push((p.0 - nx * gap, p.1 - ny * gap, q.0 - nx * gap, q.1 - ny * gap, false));
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+/// Centroid of the smallest ring containing each bond, in screen space.
+fn ring_centres(mol: &Molecule, l: &Layout, f: &Frame) -> Vec<Option<(f64, f64)>> {
+    let rings = mol.rings(8);
+    mol.bonds.iter().map(|b| {
+        rings.iter().filter(|r| r.contains(&b.a) && r.contains(&b.b)).min_by_key(|r| r.len()).map(|r| {
+            let n = r.len() as f64;
+            let (x, y) = r.iter().fold((0.0, 0.0), |(x, y), &i| (x + l.atoms[i].x / n, y + l.atoms[i].y / n));
+            f.point(x, y)
+        })
+    }).collect()
+}
+
+pub fn svg(mol: &Molecule, size: u32) -> String {
+    let l = layout(mol);
+    let f = Frame::new(&l, size);
+    let degree: Vec<usize> = mol.neighbors().iter().map(Vec::len).collect();
+    let labels: Vec<Option<String>> = l.atoms.iter().zip(&degree).map(|(a, &d)| label(a, d)).collect();
+    let labelled: Vec<bool> = labels.iter().map(Option::is_some).collect();
+    let mut out = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{size}\" height=\"{size}\" viewBox=\"0 0 {size} {size}\"><rect width=\"100%\" height=\"100%\" fill=\"white\"/>");
+    let stroke = (f.scale * 0.06).max(1.0);
+    for (x1, y1, x2, y2, dashed) in bond_segments(&l, &f, &labelled, &ring_centres(mol, &l, &f)) {
+        let dash = if dashed { format!(" stroke-dasharray=\"{:.1},{:.1}\"", stroke * 2.0, stroke * 2.0) } else { String::new() };
+        out.push_str(&format!("<line x1=\"{x1:.1}\" y1=\"{y1:.1}\" x2=\"{x2:.1}\" y2=\"{y2:.1}\" stroke=\"#222\" stroke-width=\"{stroke:.1}\"{dash}/>"));
+    }
+    let font = f.scale * 0.45;
+    for (a, text) in l.atoms.iter().zip(&labels) {
+        let Some(text) = text else { continue };
+        let (x, y) = f.point(a.x, a.y);
+        let (r, g, b) = element_colour(&a.element);
+        out.push_str(&format!("<text x=\"{x:.1}\" y=\"{y:.1}\" font-family=\"sans-serif\" font-size=\"{font:.1}\" fill=\"#{r:02x}{g:02x}{b:02x}\" text-anchor=\"middle\" dominant-baseline=\"central\">{text}</text>"));
+    }
+    out.push_str("</svg>");
+    out
+}
+
+pub fn png(mol: &Molecule, size: u32) -> Vec<u8> {
+    let l = layout(mol);
+    let f = Frame::new(&l, size);
+    let degree: Vec<usize> = mol.neighbors().iter().map(Vec::len).collect();
+    let labelled: Vec<bool> = l.atoms.iter().zip(&degree).map(|(a, &d)| label(a, d).is_some()).collect();
+    let mut canvas = Canvas::new(size);
+    let width = (f.scale * 0.06).max(1.0);
+    for (x1, y1, x2, y2, dashed) in bond_segments(&l, &f, &labelled, &ring_centres(mol, &l, &f)) {
+        canvas.line(x1, y1, x2, y2, width, if dashed { width * 2.0 } else { 0.0 }, (34, 34, 34));
+    }
+    for (a, &is_label) in l.atoms.iter().zip(&labelled) {
+        if !is_label { continue; }
+        let (x, y) = f.point(a.x, a.y);
+        canvas.disc(x, y, f.scale * 0.22, element_colour(&a.element));
+    }
+    canvas.encode()
+}
+
+struct Canvas { size: u32, pixels: Vec<u8> }
+
+impl Canvas {
+    fn new(size: u32) -> Self { Self { size, pixels: vec![255; (size * size * 3) as usize] } }
+
+    fn set(&mut self, x: i64, y: i64, c: (u8, u8, u8)) {
+        if x < 0 || y < 0 || x >= self.size as i64 || y >= self.size as i64 { return; }
+        let i = ((y as u32 * self.size + x as u32) * 3) as usize;
+        self.pixels[i..i + 3].copy_from_slice(&[c.0, c.1, c.2]);
+    }
+
+    /// A thick segment; with `dash` > 0 it alternates drawn and blank runs of that length.
+    #[allow(clippy::too_many_arguments)]
+    fn line(&mut self, x1: f64, y1: f64, x2: f64, y2: f64, width: f64, dash: f64, c: (u8, u8, u8)) {
+        let len = ((x2 - x1).powi(2) + (y2 - y1).powi(2)).sqrt();
+        let steps = (len * 2.0).ceil().max(1.0) as usize;
+        for i in 0..=steps {
+            let t = i as f64 / steps as f64;
+            if dash > 0.0 && ((t * len / dash) as usize) % 2 == 1 { continue; }
+            self.disc(x1 + (x2 - x1) * t, y1 + (y2 - y1) * t, width / 2.0, c);
+        }
+    }
+
+    fn disc(&mut self, cx: f64, cy: f64, r: f64, c: (u8, u8, u8)) {
+        let r = r.max(0.5);
+        for y in (cy - r).floor() as i64..=(cy + r).ceil() as i64 {
+            for x in (cx - r).floor() as i64..=(cx + r).ceil() as i64 {
+                if (x as f64 - cx).powi(2) + (y as f64 - cy).powi(2) <= r * r { self.set(x, y, c); }
+            }
+        }
+    }
+
+    /// RGB8 PNG with the image data in uncompressed (stored) deflate blocks.
+    fn encode(&self) -> Vec<u8> {
+        let mut raw = Vec::with_capacity(self.pixels.len() + self.size as usize);
+        for row in self.pixels.chunks(self.size as usize * 3) { raw.push(0); raw.extend_from_slice(row); }
+        let mut z = vec![0x78, 0x01];
+        let blocks: Vec<&[u8]> = raw.chunks(65_535).collect();
+        for (i, block) in blocks.iter().enumerate() {
+            z.push((i + 1 == blocks.len()) as u8);
+            let len = block.len() as u16;
+            z.extend_from_slice(&len.to_le_bytes());
+            z.extend_from_slice(&(!len).to_le_bytes());
+            z.extend_from_slice(block);
+        }
+        z.extend_from_slice(&adler32(&raw).to_be_bytes());
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&self.size.to_be_bytes());
+        ihdr.extend_from_slice(&self.size.to_be_bytes());
+        ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+        let mut out = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+        for (kind, data) in [(b"IHDR", ihdr), (b"IDAT", z), (b"IEND", Vec::new())] {
+            out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            let mut chunk = kind.to_vec();
+            chunk.extend_from_slice(&data);
+            out.extend_from_slice(&chunk);
+            out.extend_from_slice(&crc32(&chunk).to_be_bytes());
+        }
+        out
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 { crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 }; }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &x in data { a = (a + x as u32) % 65_521; b = (b + a) % 65_521; }
+    (b << 16) | a
+}
@@ -0,0 +1,141 @@
+//! Molecular formula, exact mass and isotope distribution.
+//!
+//! The formula counts every atom of the SMILES graph plus its hydrogens, in Hill order. The
+//! monoisotopic mass sums each element's most abundant isotope (or the labelled isotope of a
+//! bracket atom such as `[13C]`) and corrects for the electrons of a net charge. The isotope
+//! pattern convolves the natural abundances (IUPAC 2013) atom by atom, binned by nominal
+//! mass, so each peak (M, M+1, M+2, ...) carries its abundance-weighted centroid mass. Common
+//! ESI adducts give the m/z to look for in LC-MS.
+
+use serde::Serialize;
+
+use crate::chem;
+
+const ELECTRON: f64 = 0.000548580;
+const PROTON: f64 = 1.007276;
+/// Peaks below this share of the most intense one are dropped.
+const MIN_RELATIVE: f64 = 0.1;
+const MAX_PEAKS: usize = 8;
+
+/// Stable isotopes per element: (mass, natural abundance), most abundant first.
+const ISOTOPES: &[(&str, &[(f64, f64)])] = &[
+    ("H", &[(1.00782503, 0.999885), (2.01410178, 0.000115)]),
+    ("Li", &[(7.01600344, 0.9241), (6.01512289, 0.0759)]),
+    ("Be", &[(9.0121831, 1.0)]),
+    ("B", &[(11.00930536, 0.801), (10.01293695, 0.199)]),
+    ("C", &[(12.0, 0.9893), (13.00335484, 0.0107)]),
+    ("N", &[(14.00307401, 0.99636), (15.00010890, 0.00364)]),
+    ("O", &[(15.99491462, 0.99757), (16.99913176, 0.00038), (17.99915961, 0.00205)]),
+    ("F", &[(18.99840316, 1.0)]),
+    ("Na", &[(22.98976928, 1.0)]),
+    ("Mg", &[(23.98504170, 0.7899), (24.98583692, 0.1000), (25.98259293, 0.1101)]),
+    ("Al", &[(26.98153853, 1.0)]),
+    ("Si", &[(27.97692653, 0.92223), (28.97649466, 0.04685), (29.97377014, 0.03092)]),
+    ("P", &[(30.97376200, 1.0)]),
+    ("S", &[(31.97207117, 0.9499), (32.97145891, 0.0075), (33.96786700, 0.0425), (35.96708071, 0.0001)]),
+    ("Cl", &[(34.96885268, 0.7576), (36.96590260, 0.2424)]),
+    ("K", &[(38.96370649, 0.932581), (40.96182526, 0.067302)]),
+    ("Ca", &[(39.96259086, 0.96941), (41.95861783, 0.00647), (42.95876644, 0.00135), (43.95548156, 0.02086)]),
+    ("Mn", &[(54.93804391, 1.0)]),
+    ("Fe", &[(55.93493633, 0.91754), (53.93960899, 0.05845), (56.93539284, 0.02119), (57.93327443, 0.00282)]),
+    ("Co", &[(58.93319429, 1.0)]),
+    ("Ni", &[(57.93534241, 0.68077), (59.93078588, 0.26223), (60.93105557, 0.011399), (61.92834537, 0.036346), (63.92796682, 0.009255)]),
+    ("Cu", &[(62.92959772, 0.6915), (64.92778970, 0.3085)]),
+    ("Zn", &[(63.92914201, 0.4917), (65.92603381, 0.2773), (66.92712775, 0.0404), (67.92484455, 0.1845), (69.9253192, 0.0061)]),
+    ("As", &[(74.92159457, 1.0)]),
+    ("Se", &[(79.9165218, 0.4961), (77.9173095, 0.2377), (75.9192141, 0.0937), (81.9166995, 0.0873), (76.9199146, 0.0763), (73.9224759, 0.0089)]),
+    ("Br", &[(78.9183376, 0.5069), (80.9162897, 0.4931)]),
+    ("I", &[(126.9044719, 1.0)]),
+];
+
+#[derive(Serialize, Clone)]
+pub struct IsotopePeak { pub label: String, pub mass: f64, pub relative_abundance: f64 }
+#[derive(Serialize, Clone)]
+pub struct Adduct { pub ion: &'static str, pub mz: f64 }
+#[derive(Serialize, Clone)]
+pub struct MassReport { pub formula: String, pub charge: i32, pub monoisotopic_mass: f64, pub average_mass: f64, pub nominal_mass: u32, pub isotope_pattern: Vec<IsotopePeak>, pub adducts: Vec<Adduct> }
+
+fn isotopes(element: &str) -> Result<&'static [(f64, f64)], String> {
+    ISOTOPES.iter().find(|e| e.0 == element).map(|e| e.1).ok_or_else(|| format!("no isotope data for {element}"))
+}
+
+/// Element counts in Hill order: C, H, then alphabetical (alphabetical throughout without C).
+pub fn formula(mol: &chem::Molecule) -> Vec<(String, usize)> {
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    let mut add = |el: &str, n: usize| {
+        if n == 0 { return; }
+        match counts.iter_mut().find(|c| c.0 == el) { Some(c) => c.1 += n, None => counts.push((el.into(), n)) }
+    };
+    for a in &mol.atoms { add(&a.element, 1); add("H", a.hydrogens as usize); }
+    let has_carbon = counts.iter().any(|c| c.0 == "C");
+    counts.sort_by_key(|c| (!(has_carbon && c.0 == "C"), !(has_carbon && c.0 == "H"), c.0.clone()));
+    counts
+}
+
+pub fn formula_string(counts: &[(String, usize)]) -> String {
+    counts.iter().map(|(el, n)| if *n == 1 { el.clone() } else { format!("{el}{n}") }).collect()
+}
+
+/// Every atom of the molecule, hydrogens included, as its isotope choices (labelled atoms
+/// have exactly one).
+fn atom_isotopes(mol: &chem::Molecule) -> Result<Vec<Vec<(f64, f64)>>, String> {
+    let hydrogen = isotopes("H")?.to_vec();
+    let mut out = Vec::new();
+    for a in &mol.atoms {
+        let natural = isotopes(&a.element)?;
+        out.push(match a.isotope {
+            Some(n) => vec![(natural.iter().find(|i| i.0.round() as u16 == n).map_or(n as f64, |i| i.0), 1.0)],
+            None => natural.to_vec(),
+        });
+        out.extend(std::iter::repeat_n(hydrogen.clone(), a.hydrogens as usize));
+    }
+    Ok(out)
+}
+
+fn average(atoms: &[Vec<(f64, f64)>]) -> f64 {
+    atoms.iter().map(|iso| iso.iter().map(|(m, p)| m * p).sum::<f64>() / iso.iter().map(|i| i.1).sum::<f64>()).sum()
+}
+
+/// Average molecular weight in Da, hydrogens included.
+pub fn molecular_weight(mol: &chem::Molecule) -> Result<f64, String> {
+    let charge: i32 = mol.atoms.iter().map(|a| a.charge as i32).sum();
+    Ok(average(&atom_isotopes(mol)?) - charge as f64 * ELECTRON)
+}
+
+fn round(v: f64, places: i32) -> f64 { let f = 10f64.powi(places); (v * f).round() / f }
+
+pub fn mass_report(mol: &chem::Molecule) -> Result<MassReport, String> {
+    if mol.atoms.is_empty() { return Err("molecule has no atoms".into()); }
+    let atoms = atom_isotopes(mol)?;
+    let charge: i32 = mol.atoms.iter().map(|a| a.charge as i32).sum();
+    let electrons = charge as f64 * ELECTRON;
+    let mono: f64 = atoms.iter().map(|iso| iso[0].0).sum::<f64>() - electrons;
+    let average = average(&atoms) - electrons;
+    // Probability and probability-weighted mass per nominal offset from the monoisotopic peak.
+    let mut dist = vec![(1.0, 0.0)];
+    for iso in &atoms {
+        let base = iso[0].0.round() as i64;
+        let mut next = vec![(0.0, 0.0); (dist.len() + 6).min(MAX_PEAKS + 4)];
+        for (k, &(p, pm)) in dist.iter().enumerate() {
+            for &(m, a) in iso {
+                let j = k as i64 + m.round() as i64 - base;
+                // Lighter minor isotopes (6Li, 54Fe, ...) are folded into the lightest bin.
+                let j = j.max(0) as usize;
+                if j >= next.len() { continue; }
+                next[j].0 += p * a;
+                next[j].1 += pm * a + p * a * m;
+            }
+        }
+        dist = next;
+    }
+    let max = dist.iter().map(|d| d.0).fold(0.0, f64::max);
+    let isotope_pattern = dist.iter().enumerate().filter(|(_, d)| d.0 / max * 100.0 >= MIN_RELATIVE).take(MAX_PEAKS)
+        .map(|(k, &(p, pm))| IsotopePeak { label: if k == 0 { "M".into() } else { format!("M+{k}") }, mass: round(pm / p - electrons, 5), relative_abundance: round(p / max * 100.0, 2) }).collect();
+    // Adducts of a neutral molecule; a charged one is its own ion.
+    let adducts = if charge == 0 {
+        vec![Adduct { ion: "[M+H]+", mz: round(mono + PROTON, 5) }, Adduct { ion: "[M+Na]+", mz: round(mono + 22.98976928 - ELECTRON, 5) }, Adduct { ion: "[M+NH4]+", mz: round(mono + 18.03437413 - ELECTRON, 5) }, Adduct { ion: "[M-H]-", mz: round(mono - PROTON, 5) }, Adduct { ion: "[M+2H]2+", mz: round((mono + 2.0 * PROTON) / 2.0, 5) }]
+    } else {
+        vec![Adduct { ion: if charge > 0 { "[M]+" } else { "[M]-" }, mz: round(mono / charge.unsigned_abs() as f64, 5) }]
+    };
+    Ok(MassReport { formula: formula_string(&formula(mol)), charge, monoisotopic_mass: round(mono, 5), average_mass: round(average, 4), nominal_mass: atoms.iter().map(|iso| iso[0].0.round() as u32).sum(), isotope_pattern, adducts })
+}
@@ -0,0 +1,83 @@
+//! Intrinsically disordered regions.
+//!
+//! Per-residue disorder propensity is the TOP-IDP scale averaged over a 21-residue window and
+//! mapped through a logistic onto 0..1, with a small boost for low-complexity windows (low
+//! sequence entropy), which are rarely folded. Runs of at least `MIN_REGION` residues above
+//! 0.5 are disordered regions; predicted domains are trimmed back from them so linkers and
+//! tails aren't reported as folded.
+
+use serde::Serialize;
+
+use crate::predict::DomainInfo;
+
+const AMINO: &[u8] = b"ARNDCQEGHILKMFPSTWYV";
+#[allow(clippy::approx_constant)] // Gln is 0.318, not 1/π
+const TOP_IDP: [f64; 20] = [0.06, 0.180, 0.007, 0.192, 0.02, 0.318, 0.736, 0.166, 0.303, -0.486, -0.326, 0.586, -0.397, -0.697, 0.987, 0.341, 0.059, -0.884, -0.510, -0.121];
+const WINDOW: usize = 21;
+/// Window-mean TOP-IDP at which disorder probability is 0.5.
+const MIDPOINT: f64 = 0.17;
+const MIN_REGION: usize = 10;
+/// Domains left shorter than this after trimming are dropped.
+const MIN_DOMAIN: usize = 20;
+
+#[derive(Serialize, Clone)]
+pub struct DisorderedRegion { pub start: usize, pub end: usize, pub sequence: String, pub mean_score: f64 }
+#[derive(Serialize, Clone)]
+pub struct DisorderReport { pub disordered_fraction: f64, pub regions: Vec<DisorderedRegion>, pub scores: Vec<f64> }
+
+fn entropy(window: &[u8]) -> f64 {
+    let mut counts = [0usize; 26];
+    for &r in window { if r.is_ascii_uppercase() { counts[(r - b'A') as usize] += 1; } }
+    counts.iter().filter(|&&c| c > 0).map(|&c| { let p = c as f64 / window.len() as f64; -p * p.log2() }).sum()
+}
+
+pub fn predict(sequence: &str) -> DisorderReport {
+    let seq: Vec<u8> = sequence.bytes().map(|b| b.to_ascii_uppercase()).collect();
+    let raw: Vec<f64> = seq.iter().map(|r| AMINO.iter().position(|a| a == r).map_or(0.0, |i| TOP_IDP[i])).collect();
+    let scores: Vec<f64> = (0..seq.len()).map(|i| {
+        let (from, to) = (i.saturating_sub(WINDOW / 2), (i + WINDOW / 2 + 1).min(seq.len()));
+        let mean = raw[from..to].iter().sum::<f64>() / (to - from) as f64;
+        // Random sequence over 20 residues has ~4.2 bits; below 3 the window is low-complexity.
+        let low_complexity = (3.0 - entropy(&seq[from..to])).max(0.0) * 0.1;
+        let p = 1.0 / (1.0 + (-(mean + low_complexity - MIDPOINT) * 12.0).exp());
+        (p * 1000.0).round() / 1000.0
+    }).collect();
+    let mut regions = Vec::new();
+    let mut i = 0;
+    while i < seq.len() {
+        if scores[i] < 0.5 { i += 1; continue; }
+        let start = i;
+        while i < seq.len() && scores[i] >= 0.5 { i += 1; }
+        if i - start >= MIN_REGION {
+            let mean = scores[start..i].iter().sum::<f64>() / (i - start) as f64;
+            regions.push(DisorderedRegion { start, end: i - 1, sequence: String::from_utf8_lossy(&seq[start..i]).into(), mean_score: (mean * 1000.0).round() / 1000.0 });
+        }
+    }
+    let disordered: usize = regions.iter().map(|r| r.end - r.start + 1).sum();
+    DisorderReport { disordered_fraction: (disordered as f64 / seq.len().max(1) as f64 * 1000.0).round() / 1000.0, regions, scores }
+}
+
+/// Folding state from the disordered fraction.
+pub fn folding_state(report: &DisorderReport) -> &'static str {
+    match report.disordered_fraction {
+        f if f >= 0.7 => "intrinsically_disordered",
+        f if f >= 0.1 => "partially_disordered",
+        _ => "folded",
+    }
+}
+
+/// Domains trimmed back from disordered regions overlapping their ends (and dropped if
+/// little is left), followed by the disordered regions themselves.
+pub fn trim_domains(domains: Vec<DomainInfo>, report: &DisorderReport) -> Vec<DomainInfo> {
+    let mut out: Vec<DomainInfo> = domains.into_iter().filter_map(|mut d| {
+        let (start, end) = (d.start, d.end);
+        for r in &report.regions {
+            if r.start <= d.start && r.end + 1 > d.start { d.start = r.end + 1; }
+            if r.start < d.end && r.end + 1 >= d.end { d.end = r.start; }
+        }
+        let untouched = (d.start, d.end) == (start, end);
+        (untouched || (d.end > d.start && d.end - d.start >= MIN_DOMAIN)).then_some(d)
+    }).collect();
+    out.extend(report.regions.iter().map(|r| DomainInfo { name: "disordered_region".into(), start: r.start, end: r.end + 1, domain_type: "disordered".into(), confidence: r.mean_score }));
+    out
+}
@@ -0,0 +1,140 @@
+//! Property and structural-alert filters applied to the library before a screen docks it.
+//!
+//! `filters` bounds the molecular weight (`mw: [min, max]`, Da), the calculated logP
+//! (`logp_min`, `logp_max`), hydrogen-bond donors and acceptors (Lipinski counts: N and O atoms
+//! carrying hydrogen, and all N and O atoms) and rotatable bonds (non-ring single bonds between
+//! two non-terminal heavy atoms, amide C–N excluded). logP sums Wildman–Crippen atom
+//! contributions (J. Chem. Inf. Comput. Sci. 1999) over a reduced set of atom types. `pains`
+//! and `toxicophores` reject compounds with an alert of the `pains` or `toxicophore` category
+//! (see `alerts`). Every compound of the library is checked, so the report counts the whole
+//! library: how many passed, and per reason how many compounds it excluded (one compound can
+//! fail several).
+
+use serde::{Deserialize, Serialize};
+
+use crate::{alerts::AlertSet, chem::{self, BondKind, Molecule}, descriptors, library};
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct Filters { pub mw: Option<[f64; 2]>, pub logp_min: Option<f64>, pub logp_max: Option<f64>, pub hbd_max: Option<usize>, pub hba_max: Option<usize>, pub rotatable_bonds_max: Option<usize>, #[serde(default)] pub pains: bool, #[serde(default)] pub toxicophores: bool }
+
+#[derive(Serialize, Clone)]
+pub struct Exclusion { pub reason: String, pub compounds: u64 }
+
+#[derive(Serialize, Clone)]
+pub struct FilterReport { pub evaluated: u64, pub passed: u64, pub excluded: u64, pub reasons: Vec<Exclusion> }
+
+/// Wildman–Crippen logP contribution of each heavy atom and its hydrogens.
+pub fn logp(m: &Molecule) -> f64 {
+    let adj = m.neighbors();
+    let el = |i: usize| m.atoms[i].element.as_str();
+    let hetero = |i: usize| !matches!(el(i), "C" | "H");
+    let carbonyl = |c: usize| el(c) == "C" && adj[c].iter().any(|&(o, k)| k == BondKind::Double && el(o) == "O");
+    let mut total = 0.0;
+    for (i, a) in m.atoms.iter().enumerate() {
+        let nb = &adj[i];
+        let to_aromatic = nb.iter().any(|&(j, k)| k != BondKind::Aromatic && m.atoms[j].aromatic);
+        let double = nb.iter().find(|e| e.1 == BondKind::Double).map(|e| e.0);
+        let h = a.hydrogens;
+        let heavy = match (el(i), a.aromatic) {
+            ("C", true) if h > 0 => 0.1581,
+            ("C", true) => match nb.iter().find(|e| e.1 != BondKind::Aromatic) {
+                None => 0.2955,
+                Some(&(_, BondKind::Double)) => -0.8186,
+                Some(&(j, _)) if m.atoms[j].aromatic => 0.2713,
+                Some(&(j, _)) => match el(j) { "N" => 0.4619, "O" => 0.5437, "S" => 0.1893, "Cl" => 0.245, "Br" => 0.198, "F" | "I" => 0.0, _ => 0.136 },
+            },
+            ("C", false) if nb.iter().any(|e| e.1 == BondKind::Triple) => 0.0017,
+            ("C", false) if double.is_some_and(hetero) => -0.2783,
+            ("C", false) if double.is_some() => 0.1551,
+            ("C", false) if to_aromatic && h == 3 && nb.iter().any(|e| m.atoms[e.0].aromatic && hetero(e.0)) => -0.1444,
+            ("C", false) if to_aromatic => [-0.0967, 0.1193, -0.0516, 0.08452][h.min(3) as usize],
+            ("C", false) if nb.iter().any(|e| hetero(e.0)) => if h >= 2 { -0.2035 } else { -0.2051 },
+            ("C", false) if h >= 2 => 0.1441,
+            ("C", false) => 0.0,
+            ("N", true) => if a.charge > 0 { -1.119 } else { -0.3239 },
+            ("N", false) if a.charge > 0 => if h > 0 { -1.950 } else { -0.3396 },
+            ("N", false) if nb.iter().any(|e| e.1 == BondKind::Triple) => 0.01508,
+            ("N", false) if double.is_some() => if h > 0 { 0.08387 } else { 0.1836 },
+            ("N", false) => match (h, to_aromatic) { (2.., true) => -1.027, (2.., false) => -1.019, (1, true) => -0.5188, (1, false) => -0.7096, (_, true) => -0.4458, (_, false) => -0.3187 },
+            ("O", true) => 0.1552,
+            ("O", false) if a.charge < 0 => match nb.first().map(|e| e.0) { Some(j) if el(j) == "N" => 0.0335, Some(j) if el(j) == "S" => -0.3339, Some(j) if carbonyl(j) => -1.326, _ => -1.189 },
+            ("O", false) if double.is_some() => double.map_or(0.0, |j| if el(j) != "C" { 0.0335 } else if m.atoms[j].aromatic { 0.1788 } else if adj[j].iter().any(|e| m.atoms[e.0].aromatic) { 0.1129 } else { -0.1526 }),
+            ("O", false) if h > 0 => -0.2893,
+            ("O", false) => if to_aromatic { -0.4195 } else { -0.0684 },
+            ("S", true) => 0.6237,
+            ("S", false) => if a.charge != 0 { -0.0024 } else { 0.6482 },
+            ("F", _) => 0.4202,
+            ("Cl", _) => 0.6895,
+            ("Br", _) => 0.8456,
+            ("I", _) => 0.8857,
+            ("P", _) => 0.8612,
+            _ => 0.0,
+        };
+        let per_h = match el(i) { "C" => 0.123, "N" => 0.2142, "O" if nb.iter().any(|e| carbonyl(e.0)) => 0.298, "O" => -0.2677, _ => 0.1125 };
+        total += heavy + per_h * h as f64;
+    }
+    total
+}
+
+/// Lipinski donors and acceptors.
+pub fn hbd(m: &Molecule) -> usize { m.atoms.iter().filter(|a| matches!(a.element.as_str(), "N" | "O") && a.hydrogens > 0).count() }
+pub fn hba(m: &Molecule) -> usize { m.atoms.iter().filter(|a| matches!(a.element.as_str(), "N" | "O")).count() }
+
+pub fn rotatable_bonds(m: &Molecule) -> usize {
+    let adj = m.neighbors();
+    let amide = |c: usize, n: usize| m.atoms[n].element == "N" && m.atoms[c].element == "C" && adj[c].iter().any(|&(o, k)| k == BondKind::Double && m.atoms[o].element == "O");
+    m.bonds.iter().enumerate().filter(|&(i, b)| {
+        b.kind == BondKind::Single && adj[b.a].len() > 1 && adj[b.b].len() > 1 && !amide(b.a, b.b) && !amide(b.b, b.a) && !m.bond_in_ring(i)
+    }).count()
+}
+
+impl Filters {
+    pub fn is_empty(&self) -> bool {
+        self.mw.is_none() && self.logp_min.is_none() && self.logp_max.is_none() && self.hbd_max.is_none() && self.hba_max.is_none() && self.rotatable_bonds_max.is_none() && !self.pains && !self.toxicophores
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some([lo, hi]) = self.mw { if lo.is_nan() || hi.is_nan() || lo > hi { return Err("filters.mw must be [min, max] with min <= max".into()); } }
+        if let (Some(lo), Some(hi)) = (self.logp_min, self.logp_max) { if lo > hi { return Err("filters.logp_min must not exceed logp_max".into()); } }
+        Ok(())
+    }
+
+    /// The reasons `smiles` fails the filters, with alerts from `alerts`; empty when it passes.
+    fn check(&self, smiles: &str, alerts: &AlertSet) -> Vec<String> {
+        let Ok(m) = chem::parse_smiles(smiles) else { return vec!["unparsable".into()] };
+        let mut reasons = Vec::new();
+        if let Some([lo, hi]) = self.mw { if descriptors::molecular_weight(&m).is_ok_and(|w| w < lo || w > hi) { reasons.push("mw".into()); } }
+        if self.logp_min.is_some() || self.logp_max.is_some() {
+            let p = logp(&m);
+            if self.logp_min.is_some_and(|lo| p < lo) || self.logp_max.is_some_and(|hi| p > hi) { reasons.push("logp".into()); }
+        }
+        if self.hbd_max.is_some_and(|n| hbd(&m) > n) { reasons.push("hbd".into()); }
+        if self.hba_max.is_some_and(|n| hba(&m) > n) { reasons.push("hba".into()); }
+        if self.rotatable_bonds_max.is_some_and(|n| rotatable_bonds(&m) > n) { reasons.push("rotatable_bonds".into()); }
+        if self.pains || self.toxicophores {
+            reasons.extend(alerts.check(&m, &|c| (self.pains && c == "pains") || (self.toxicophores && c == "toxicophore")).into_iter().map(|f| f.alert));
+        }
+        reasons
+    }
+
+    /// Library numbers `first`, `first + 1`, ... (`size` of them, wrapping) that pass, up to
+    /// `take`, and the exclusions over all `size`.
+    pub fn apply(&self, alerts: &AlertSet, first: u64, size: u64, take: usize) -> (Vec<u64>, FilterReport) {
+        // Compound structures repeat with the library's period, so each is checked once.
+        let mut seen: Vec<Option<Vec<String>>> = vec![None; library::PERIOD as usize];
+        let (mut passed, mut picked, mut reasons) = (0, Vec::new(), Vec::<Exclusion>::new());
+        for i in 0..size {
+            let n = first.wrapping_add(i) % library::LIBRARY_SIZE;
+            let failed = seen[(n % library::PERIOD) as usize].get_or_insert_with(|| self.check(&library::compound(n), alerts));
+            if failed.is_empty() {
+                passed += 1;
+                if picked.len() < take { picked.push(n); }
+            }
+            for r in failed.iter() {
+                match reasons.iter_mut().find(|e| &e.reason == r) { Some(e) => e.compounds += 1, None => reasons.push(Exclusion { reason: r.clone(), compounds: 1 }) }
+            }
+        }
+        reasons.sort_by(|a, b| b.compounds.cmp(&a.compounds).then_with(|| a.reason.cmp(&b.reason)));
+        (picked, FilterReport { evaluated: size, passed, excluded: size - passed, reasons })
+    }
+}
@@ -0,0 +1,55 @@
+//! Circular (Morgan) fingerprints and Tanimoto similarity.
+//!
+//! Every heavy atom starts from an identifier hashing its atomic number, heavy-atom degree,
+//! hydrogen count, formal charge, aromaticity and ring membership. Each round rehashes it with
+//! its neighbours' identifiers and bond kinds, sorted, so after `r` rounds it describes the atom's
+//! environment out to `r` bonds. The identifiers of every round are folded into a 2048-bit
+//! vector; radius 2 gives ECFP4-like bits.
+
+use crate::{chem::{self, BondKind, Molecule}, fnv1a};
+
+pub const BITS: usize = 2048;
+pub const RADIUS: usize = 2;
+
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Fingerprint([u64; BITS / 64]);
+
+impl Fingerprint {
+    fn set(&mut self, id: u64) { let bit = (id % BITS as u64) as usize; self.0[bit / 64] |= 1 << (bit % 64); }
+}
+
+pub fn morgan(m: &Molecule, radius: usize) -> Fingerprint {
+    let adj = m.neighbors();
+    let ring_atom: Vec<bool> = {
+        let mut ring = vec![false; m.atoms.len()];
+        for (i, b) in m.bonds.iter().enumerate() { if m.bond_in_ring(i) { ring[b.a] = true; ring[b.b] = true; } }
+        ring
+    };
+    let heavy: Vec<usize> = (0..m.atoms.len()).filter(|&i| m.atoms[i].element != "H").collect();
+    let mut ids: Vec<u64> = (0..m.atoms.len()).map(|i| {
+        let a = &m.atoms[i];
+        let degree = adj[i].iter().filter(|e| m.atoms[e.0].element != "H").count() as u8;
+        let h = a.hydrogens + adj[i].iter().filter(|e| m.atoms[e.0].element == "H").count() as u8;
+        fnv1a(&[chem::atomic_number(&a.element).unwrap_or(0), degree, h, a.charge as u8, a.aromatic as u8, ring_atom[i] as u8])
+    }).collect();
+    let mut fp = Fingerprint([0; BITS / 64]);
+    for &i in &heavy { fp.set(ids[i]); }
+    for round in 1..=radius {
+        ids = (0..m.atoms.len()).map(|i| {
+            let mut env: Vec<(u8, u64)> = adj[i].iter().filter(|e| m.atoms[e.0].element != "H").map(|&(j, k)| (match k { BondKind::Single => 1, BondKind::Double => 2, BondKind::Triple => 3, BondKind::Aromatic => 4 }, ids[j])).collect();
+            env.sort_unstable();
+            let mut bytes = vec![round as u8];
+            bytes.extend(ids[i].to_le_bytes());
+            for (k, id) in env { bytes.push(k); bytes.extend(id.to_le_bytes()); }
+            fnv1a(&bytes)
+        }).collect();
+        for &i in &heavy { fp.set(ids[i]); }
+    }
+    fp
+}
+
+/// Shared bits over bits in either; 0 when both are empty.
+pub fn tanimoto(a: &Fingerprint, b: &Fingerprint) -> f64 {
+    let (both, either) = a.0.iter().zip(&b.0).fold((0, 0), |(n, u), (x, y)| (n + (x & y).count_ones(), u + (x | y).count_ones()));
+    if either == 0 { 0.0 } else { both as f64 / either as f64 }
+}
@@ -0,0 +1,294 @@
+//! A small heavy-atom force field for pose work.
+//!
+//! Ligand internal energy is harmonic on the conformer restraints (bond lengths, angles, ring
+//! geometry) plus a repulsive wall between distant atoms. Ligand-receptor interaction is a
+//! softened 6-12 Lennard-Jones potential over receptor heavy atoms within the cutoff, read from
+//! a structure-of-arrays `Frame` built once per receptor. Receptor metal ions are kept out of
+//! the frame and scored by `Metals` with their own parameters (see `cofactors`).
+//! Energies are in kcal/mol, lengths in Å, time in fs.
+
+use crate::{cofactors::Metal, conformer::Restraint, frame::Frame, vec3::{sub, norm}};
+
+const LJ_RMIN: f64 = 3.8;
+const LJ_EPSILON: f64 = 0.15;
+const SOFT_R: f64 = 2.6;
+const CUTOFF: f64 = 8.0;
+/// Rmin/2 of a ligand heavy atom against a metal ion, and the well depth of a coordination bond.
+const LIGAND_RMIN_HALF: f64 = 1.908;
+const LIGAND_EPSILON: f64 = 0.086;
+const COORDINATION_EPSILON: f64 = 3.0;
+/// Closer than this fraction of its contact distance, a ligand atom clashes with a metal.
+const METAL_CLASH: f64 = 0.8;
+/// Closer than this, a ligand-receptor contact counts as a clash.
+pub const CLASH_DISTANCE: f64 = 2.8;
+
+pub type Coords = Vec<[f64; 3]>;
+
+/// Internal energy of a ligand conformation; adds its gradient to `grad` when given.
+pub fn internal_energy(restraints: &[Restraint], x: &[[f64; 3]], mut grad: Option<&mut [[f64; 3]]>) -> f64 {
+    let mut e = 0.0;
+    for r in restraints {
+        let d = sub(x[r.i], x[r.j]);
+        let len = norm(d).max(1e-6);
+        let dev = len - r.target;
+        if r.lower_only && dev >= 0.0 { continue; }
+        e += r.weight * dev * dev;
+        if let Some(g) = grad.as_deref_mut() {
+            let f = 2.0 * r.weight * dev / len;
+            for k in 0..3 { g[r.i][k] += f * d[k]; g[r.j][k] -= f * d[k]; }
+        }
+    }
+    e
+}
+
+/// Softened Lennard-Jones energy of one atom pair `r` apart and its derivative in `r`.
+fn lj(r: f64) -> (f64, f64) { lj_with(r, LJ_RMIN, LJ_EPSILON) }
+
+/// `lj` for a pair with its own minimum `rmin` and well depth `epsilon`.
+fn lj_with(r: f64, rmin: f64, epsilon: f64) -> (f64, f64) {
+    // Continued linearly below SOFT_R (scaled to rmin) so overlapping starts stay finite and minimizable.
+    let rs = r.max(SOFT_R * rmin / LJ_RMIN);
+    let s6 = (rmin / rs).powi(6);
+    let de_dr = 12.0 * epsilon * (s6 - s6 * s6) / rs;
+    (epsilon * (s6 * s6 - 2.0 * s6) + de_dr * (r - rs), de_dr)
+}
+
+/// Contact distance and well depth between a metal ion and an atom of `element`: its
+/// coordination distance for N, O and S, the sum of radii otherwise.
+fn metal_contact(m: &Metal, element: &str) -> (f64, f64) {
+    match element {
+        "N" | "O" => (m.to_on, COORDINATION_EPSILON),
+        "S" => (m.to_s, COORDINATION_EPSILON),
+        _ => (m.rmin_half + LIGAND_RMIN_HALF, (m.epsilon * LIGAND_EPSILON).sqrt()),
+    }
+}
+
+/// Energy of a metal ion and an atom of `element` `r` apart, zero beyond the cutoff.
+pub fn metal_pair(m: &Metal, element: &str, r: f64) -> f64 {
+    let (rmin, epsilon) = metal_contact(m, element);
+    if r > CUTOFF { 0.0 } else { lj_with(r, rmin, epsilon).0 }
+}
+
+/// A receptor's metal ions and each ligand atom's contact distance and well depth to each.
+pub struct Metals { sites: Vec<[f64; 3]>, contacts: Vec<Vec<(f64, f64)>> }
+
+impl Metals {
+    pub fn new(sites: &[([f64; 3], &Metal)], ligand_elements: &[&str]) -> Self {
+        Self { sites: sites.iter().map(|s| s.0).collect(), contacts: sites.iter().map(|s| ligand_elements.iter().map(|e| metal_contact(s.1, e)).collect()).collect() }
+    }
+
+    pub fn len(&self) -> usize { self.sites.len() }
+    pub fn is_empty(&self) -> bool { self.sites.is_empty() }
+
+    /// Ligand-metal energy; adds the ligand gradient to `grad` when given.
+    pub fn energy(&self, x: &[[f64; 3]], mut grad: Option<&mut [[f64; 3]]>) -> f64 {
+        let mut e = 0.0;
+        for (site, contacts) in self.sites.iter().zip(&self.contacts) {
+            for (i, (p, &(rmin, epsilon))) in x.iter().zip(contacts).enumerate() {
+                let d = sub(*p, *site);
+                let r = norm(d);
+                if r > CUTOFF { continue; }
+                let (pair, de_dr) = lj_with(r, rmin, epsilon);
+                e += pair;
+                if let Some(g) = grad.as_deref_mut() { for k in 0..3 { g[i][k] += de_dr / r.max(1e-6) * d[k]; } }
+            }
+        }
+        e
+    }
+
+    /// Ligand atoms well inside a metal's contact distance.
+    pub fn clashes(&self, x: &[[f64; 3]]) -> usize {
+        self.sites.iter().zip(&self.contacts).map(|(site, contacts)| x.iter().zip(contacts).filter(|(p, c)| norm(sub(**p, *site)) < METAL_CLASH * c.0).count()).sum()
+    }
+}
+
+/// Van der Waals energy of one atom pair `r` apart, zero beyond the cutoff.
+pub fn pair_vdw(r: f64) -> f64 { if r > CUTOFF { 0.0 } else { lj(r).0 } }
+
+/// Ligand-receptor interaction energy; adds the ligand gradient to `grad` when given.
+pub fn interaction_energy(receptor: &Frame, x: &[[f64; 3]], mut grad: Option<&mut [[f64; 3]]>) -> f64 {
+    let mut e = 0.0;
+    for (i, &p) in x.iter().enumerate() {
+        let mut g = [0.0; 3];
+        receptor.within(p, CUTOFF, |_, d, r2| {
+            let r = r2.sqrt();
+            let (pair, de_dr) = lj(r);
+            e += pair;
+            let f = de_dr / r.max(1e-6);
+            for k in 0..3 { g[k] += f * d[k]; }
+        });
+        if let Some(grad) = grad.as_deref_mut() { for k in 0..3 { grad[i][k] += g[k]; } }
+    }
+    e
+}
+
+pub fn clashes(receptor: &Frame, x: &[[f64; 3]]) -> usize {
+    let mut n = 0;
+    for &p in x { receptor.within(p, CLASH_DISTANCE, |_, _, r2| if r2 < CLASH_DISTANCE * CLASH_DISTANCE { n += 1 }); }
+    n
+}
+
+/// A biasing potential on the coordinates; adds its gradient to the one given.
+pub type Bias<'a> = dyn Fn(&[[f64; 3]], Option<&mut [[f64; 3]]>) -> f64 + 'a;
+
+/// Per-step callback of `System::anneal`: step, coordinates and kinetic temperature (K).
+pub type Observer<'a> = dyn FnMut(usize, &[[f64; 3]], f64) + 'a;
+
+/// Everything a refinement minimizes: internal + interaction energy (with the receptor's
+/// `metals`), plus a harmonic pull of `k_pos` kcal/mol/Å² back toward `anchor` so the pose stays
+/// near where it started, plus any `bias` an enhanced-sampling run adds.
+pub struct System<'a> { pub restraints: &'a [Restraint], pub receptor: &'a Frame, pub metals: Option<&'a Metals>, pub anchor: &'a [[f64; 3]], pub k_pos: f64, pub bias: Option<&'a Bias<'a>> }
+
+impl System<'_> {
+    pub fn energy(&self, x: &[[f64; 3]], mut grad: Option<&mut [[f64; 3]]>) -> f64 {
+        if let Some(g) = grad.as_deref_mut() { g.iter_mut().for_each(|v| *v = [0.0; 3]); }
+        let mut e = internal_energy(self.restraints, x, grad.as_deref_mut()) + interaction_energy(self.receptor, x, grad.as_deref_mut());
+        if let Some(m) = self.metals { e += m.energy(x, grad.as_deref_mut()); }
+        for (i, (p, a)) in x.iter().zip(self.anchor).enumerate() {
+            let d = sub(*p, *a);
+            e += self.k_pos * (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]);
+            if let Some(g) = grad.as_deref_mut() { for k in 0..3 { g[i][k] += 2.0 * self.k_pos * d[k]; } }
+        }
+        if let Some(bias) = self.bias { e += bias(x, grad); }
+        e
+    }
+
+    /// Steepest descent with an adaptive step; returns the steps taken. The trial step and its
+    /// gradient reuse two buffers swapped in on acceptance.
+    pub fn minimize(&self, x: &mut Coords, max_steps: usize) -> usize {
+        let mut grad = vec![[0.0; 3]; x.len()];
+        let mut e = self.energy(x, Some(&mut grad));
+        let (mut trial, mut trial_grad) = (x.clone(), vec![[0.0; 3]; x.len()]);
+        let mut step: f64 = 0.01;
+        for n in 0..max_steps {
+            let gmax = grad.iter().map(|g| norm(*g)).fold(0.0, f64::max);
+            if gmax < 0.05 { return n; }
+            // Cap the largest atom displacement at 0.2 Å per step.
+            let scale = step.min(0.2 / gmax);
+            for ((t, p), g) in trial.iter_mut().zip(x.iter()).zip(&grad) { *t = [p[0] - scale * g[0], p[1] - scale * g[1], p[2] - scale * g[2]]; }
+            let trial_e = self.energy(&trial, Some(&mut trial_grad));
+            if trial_e < e { std::mem::swap(x, &mut trial); std::mem::swap(&mut grad, &mut trial_grad); e = trial_e; step *= 1.2; } else { step *= 0.5; }
+            if step < 1e-8 { return n; }
+        }
+        max_steps
+    }
+
+    /// Langevin dynamics at `temperature_k` with a 1 fs step, all atoms given carbon mass.
+    /// `seed` drives the deterministic thermal noise.
+    pub fn dynamics(&self, x: &mut Coords, steps: usize, temperature_k: f64, seed: u64) { self.sample(x, steps, temperature_k, seed, &mut |_, _| {}); }
+
+    /// `dynamics` that hands each step's number and coordinates to `observe`.
+    pub fn sample(&self, x: &mut Coords, steps: usize, temperature_k: f64, seed: u64, observe: &mut dyn FnMut(usize, &[[f64; 3]])) {
+        self.anneal(x, steps, &|_| temperature_k, seed, &mut |step, x, _| observe(step, x));
+    }
+
+    /// `sample` with the thermostat set to `temperature(step)` at each step; `observe` also gets
+    /// the instantaneous kinetic temperature.
+    pub fn anneal(&self, x: &mut Coords, steps: usize, temperature: &dyn Fn(usize) -> f64, seed: u64, observe: &mut Observer) {
+        const MASS: f64 = 12.011; // amu
+        const ACCEL: f64 = 4.184e-4; // (kcal/mol/Å)/amu -> Å/fs²
+        const KB: f64 = 0.001_987_2; // kcal/mol/K
+        const GAMMA: f64 = 0.01; // 1/fs
+        let dt = 1.0;
+        let mut rng = seed | 1;
+        let mut gauss = move || {
+            // Box-Muller on a xorshift stream.
+            let mut next = || { rng ^= rng << 13; rng ^= rng >> 7; rng ^= rng << 17; (rng >> 11) as f64 / (1u64 << 53) as f64 };
+            let (u1, u2) = (next().max(1e-12), next());
+            (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos()
+        };
+        let mut v = vec![[0.0; 3]; x.len()];
+        let mut grad = vec![[0.0; 3]; x.len()];
+        for step in 0..steps {
+            let sigma = (2.0 * GAMMA * KB * temperature(step).max(0.0) * ACCEL / MASS / dt).sqrt();
+            self.energy(x, Some(&mut grad));
+            for i in 0..x.len() {
+                for k in 0..3 {
+                    v[i][k] += dt * (-grad[i][k] * ACCEL / MASS - GAMMA * v[i][k] + sigma * gauss());
+                    x[i][k] += dt * v[i][k];
+                }
+            }
+            // T = 2·KE / (3N·k_B), KE = ½mv² in kcal/mol.
+            let kinetic: f64 = v.iter().map(|v| 0.5 * MASS * (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]) / ACCEL).sum();
+            observe(step, x, 2.0 * kinetic / (3.0 * x.len().max(1) as f64 * KB));
+        }
+    }
+}
+
+pub fn rmsd(a: &[[f64; 3]], b: &[[f64; 3]]) -> f64 {
+    if a.is_empty() { return 0.0; }
+    (a.iter().zip(b).map(|(p, q)| { let d = sub(*p, *q); d[0] * d[0] + d[1] * d[1] + d[2] * d[2] }).sum::<f64>() / a.len() as f64).sqrt()
+}
+
+/// RMSD after the optimal superposition of `b` onto `a`.
+pub fn fitted_rmsd(a: &[[f64; 3]], b: &[[f64; 3]]) -> f64 { superpose(a, b).rmsd }
+
+/// The rigid motion taking `b` onto `a`: `rotation · (p − centre of b) + centre of a`.
+#[derive(Clone, Copy)]
+pub struct Fit { pub rotation: [[f64; 3]; 3], pub translation: [f64; 3], pub rmsd: f64 }
+
+impl Fit {
+    pub fn apply(&self, p: [f64; 3]) -> [f64; 3] {
+        let r = &self.rotation;
+        [0, 1, 2].map(|i| r[i][0] * p[0] + r[i][1] * p[1] + r[i][2] * p[2] + self.translation[i])
+    }
+}
+
+/// The least-squares superposition of `b` onto `a` (Horn's quaternion method: the eigenvector of
+/// the largest eigenvalue of the 4×4 key matrix, found by Jacobi rotations).
+pub fn superpose(a: &[[f64; 3]], b: &[[f64; 3]]) -> Fit {
+    const IDENTITY: [[f64; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    if a.is_empty() { return Fit { rotation: IDENTITY, translation: [0.0; 3], rmsd: 0.0 }; }
+    let n = a.len() as f64;
+    let centre = |x: &[[f64; 3]]| { let mut c = [0.0; 3]; for p in x { for k in 0..3 { c[k] += p[k] / n; } } c };
+    let (ca, cb) = (centre(a), centre(b));
+    let mut s = [[0.0; 3]; 3];
+    let mut e0 = 0.0;
+    for (p, q) in a.iter().zip(b) {
+        let (p, q) = (sub(*p, ca), sub(*q, cb));
+        e0 += p[0] * p[0] + p[1] * p[1] + p[2] * p[2] + q[0] * q[0] + q[1] * q[1] + q[2] * q[2];
+        for i in 0..3 { for j in 0..3 { s[i][j] += p[i] * q[j]; } }
+    }
+    let mut m = [
+        [s[0][0] + s[1][1] + s[2][2], s[1][2] - s[2][1], s[2][0] - s[0][2], s[0][1] - s[1][0]],
+        [s[1][2] - s[2][1], s[0][0] - s[1][1] - s[2][2], s[0][1] + s[1][0], s[2][0] + s[0][2]],
+        [s[2][0] - s[0][2], s[0][1] + s[1][0], -s[0][0] + s[1][1] - s[2][2], s[1][2] + s[2][1]],
+        [s[0][1] - s[1][0], s[2][0] + s[0][2], s[1][2] + s[2][1], -s[0][0] - s[1][1] + s[2][2]],
+    ];
+    let mut v = [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]];
+    for _ in 0..50 {
+        let off: f64 = (0..4).flat_map(|i| (0..4).filter(move |&j| j != i).map(move |j| (i, j))).map(|(i, j)| m[i][j] * m[i][j]).sum();
+        if off < 1e-18 { break; }
+        for p in 0..3 {
+            for q in p + 1..4 {
+                if m[p][q].abs() < 1e-30 { continue; }
+                let theta = (m[q][q] - m[p][p]) / (2.0 * m[p][q]);
+                let t = if theta >= 0.0 { 1.0 } else { -1.0 } / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let sn = t * c;
+                for row in m.iter_mut().chain(v.iter_mut()) {
+                    let (kp, kq) = (row[p], row[q]);
+                    row[p] = c * kp - sn * kq;
+                    row[q] = sn * kp + c * kq;
+                }
+                let (rp, rq) = (m[p], m[q]);
+                for k in 0..4 {
+                    m[p][k] = c * rp[k] - sn * rq[k];
+                    m[q][k] = sn * rp[k] + c * rq[k];
+                }
+            }
+        }
+    }
+    let top = (0..4).max_by(|&i, &j| m[i][i].total_cmp(&m[j][j])).unwrap_or(0);
+    let [q0, q1, q2, q3] = [0, 1, 2, 3].map(|k| v[k][top]);
+    // The quaternion turns `a` onto `b`; its transpose turns `b` onto `a`.
+    let rotation = [
+        [q0 * q0 + q1 * q1 - q2 * q2 - q3 * q3, 2.0 * (q1 * q2 + q0 * q3), 2.0 * (q1 * q3 - q0 * q2)],
+        [2.0 * (q1 * q2 - q0 * q3), q0 * q0 - q1 * q1 + q2 * q2 - q3 * q3, 2.0 * (q2 * q3 + q0 * q1)],
+        [2.0 * (q1 * q3 + q0 * q2), 2.0 * (q2 * q3 - q0 * q1), q0 * q0 - q1 * q1 - q2 * q2 + q3 * q3],
+    ];
+    let mut fit = Fit { rotation, translation: [0.0; 3], rmsd: ((e0 - 2.0 * m[top][top]).max(0.0) / n).sqrt() };
+    let turned = fit.apply(cb);
+    fit.translation = [0, 1, 2].map(|k| ca[k] - turned[k]);
+    fit
+}
@@ -0,0 +1,60 @@
+//! Structure-of-arrays coordinate frames for the pair loops.
+//!
+//! Molecules keep their graph as `chem::Molecule` and their coordinates as `[x, y, z]` rows,
+//! which suits building, editing and writing them. The loops that dominate MD and docking
+//! (every ligand atom against every receptor atom or charged site, every step) read a `Frame`
+//! instead: all x, then all y, then all z (then any per-atom charge), in one allocation, so
+//! a pass over the receptor streams through contiguous memory and atoms past the cutoff are
+//! rejected on squared distance without a square root. A frame is built once per receptor
+//! and borrowed by every evaluation. `benches/layout.rs` compares the two layouts.
+//!
+//! So far only the receptor side is a frame. Moving the ligand, poses and the MD state onto
+//! frames, and arena-allocating molecules, is outstanding (see the README's Performance section).
+
+/// Atom coordinates, and optionally charges, column by column.
+pub struct Frame { len: usize, columns: usize, data: Vec<f64> }
+
+/// The frame of no atoms, for systems without a receptor.
+pub static EMPTY: Frame = Frame { len: 0, columns: 3, data: Vec::new() };
+
+impl Frame {
+    pub fn new(coords: &[[f64; 3]]) -> Self { Self::build(coords, None) }
+
+    /// A frame whose atoms also carry `charges` (missing ones are zero).
+    pub fn with_charges(coords: &[[f64; 3]], charges: &[f64]) -> Self { Self::build(coords, Some(charges)) }
+
+    /// A frame of point charges at `sites`.
+    pub fn sites(sites: &[([f64; 3], f64)]) -> Self {
+        let (coords, charges): (Vec<[f64; 3]>, Vec<f64>) = sites.iter().copied().unzip();
+        Self::with_charges(&coords, &charges)
+    }
+
+    fn build(coords: &[[f64; 3]], charges: Option<&[f64]>) -> Self {
+        let len = coords.len();
+        let columns = if charges.is_some() { 4 } else { 3 };
+        let mut data = Vec::with_capacity(len * columns);
+        for k in 0..3 { data.extend(coords.iter().map(|c| c[k])); }
+        if let Some(q) = charges { data.extend(q.iter().copied().chain(std::iter::repeat(0.0)).take(len)); }
+        Self { len, columns, data }
+    }
+
+    pub fn len(&self) -> usize { self.len }
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+    fn column(&self, k: usize) -> &[f64] { &self.data[k * self.len..(k + 1) * self.len] }
+    pub fn x(&self) -> &[f64] { self.column(0) }
+    pub fn y(&self) -> &[f64] { self.column(1) }
+    pub fn z(&self) -> &[f64] { self.column(2) }
+    pub fn charges(&self) -> Option<&[f64]> { (self.columns == 4).then(|| self.column(3)) }
+
+    /// Calls `visit(j, d, r²)` for every atom `j` at most `cutoff` from `p`, where `d` is `p`
+    /// minus the atom's position.
+    #[inline]
+    pub fn within(&self, p: [f64; 3], cutoff: f64, mut visit: impl FnMut(usize, [f64; 3], f64)) {
+        let c2 = cutoff * cutoff;
+        for (j, ((&x, &y), &z)) in self.x().iter().zip(self.y()).zip(self.z()).enumerate() {
+            let d = [p[0] - x, p[1] - y, p[2] - z];
+            let r2 = d[0] * d[0] + d[1] * d[1] + d[2] * d[2];
+            if r2 <= c2 { visit(j, d, r2); }
+        }
+    }
+}
@@ -0,0 +1,283 @@
+//! GAFF-style parameters for arbitrary organic molecules.
+//!
+//! Every atom of the hydrogen-complete molecule gets a GAFF atom type from its element,
+//! hybridization, aromaticity and neighbours, and every term its parameters by GAFF's empirical
+//! rules rather than a lookup table, so a compound never seen before is covered. Bonds are
+//! harmonic about the ideal length the conformer embedding uses, with k = K·r₀^-4.5 for the
+//! element pair (after Wang et al., 2004); angles about their hybridization's ideal (60° and
+//! 90° in three- and four-membered rings), stiffer between heavy atoms than towards hydrogens.
+//! Torsions follow GAFF's generic `X-a-b-X` barriers by central bond: double, aromatic, amide,
+//! conjugated single and sp3–sp3, spread over the dihedrals around the bond; sp2 centres get
+//! an improper that keeps them planar. Lennard-Jones radii and well depths are GAFF's per
+//! type, combined by Lorentz–Berthelot over pairs three or more bonds apart, 1-4 pairs halved.
+//! Atoms GAFF has no type for take generic values and are reported. Charges come from the
+//! request's charge model (see `charges`).
+
+use serde::Serialize;
+
+use crate::{chem::{BondKind, Molecule}, conformer, convert::{self, Hydrogens}, vec3::{sub, dot, cross, norm, dist}};
+
+/// Lennard-Jones Rmin/2 (Å) and ε (kcal/mol) of atoms without a GAFF type.
+const GENERIC_LJ: (f64, f64) = (2.0, 0.1);
+/// AMBER scales 1-4 van der Waals pairs.
+const SCALE_14: f64 = 0.5;
+
+/// GAFF atom type of an atom of a hydrogen-complete molecule.
+pub fn atom_type(m: &Molecule, adj: &[Vec<(usize, BondKind)>], i: usize) -> String {
+    let a = &m.atoms[i];
+    let el = |j: usize| m.atoms[j].element.as_str();
+    let double_to = |j: usize, what: &[&str]| adj[j].iter().any(|&(k, b)| b == BondKind::Double && what.contains(&el(k)));
+    // Electron-withdrawing neighbours of a hydrogen's carbon.
+    let withdrawing = |j: usize| adj[j].iter().filter(|&&(k, _)| matches!(el(k), "N" | "O" | "S" | "F" | "Cl" | "Br" | "I")).count();
+    let t = match a.element.as_str() {
+        "H" => match adj[i].first().map(|&(p, _)| (p, el(p))) {
+            Some((p, "C")) if m.atoms[p].aromatic => ["ha", "h4", "h5"][withdrawing(p).min(2)],
+            Some((p, "C")) => ["hc", "h1", "h2", "h3"][withdrawing(p).min(3)],
+            Some((_, "N")) => "hn",
+            Some((_, "O")) => "ho",
+            Some((_, "S")) => "hs",
+            Some((_, "P")) => "hp",
+            _ => "hc",
+        },
+        "C" if a.aromatic => "ca",
+        "C" if adj[i].iter().any(|&(_, b)| b == BondKind::Triple) || adj[i].iter().filter(|&&(_, b)| b == BondKind::Double).count() == 2 => "c1",
+        "C" if double_to(i, &["O", "S"]) => "c",
+        "C" if double_to(i, &["C", "N", "P"]) => "c2",
+        "C" => "c3",
+        "N" if a.aromatic && adj[i].len() == 3 => "na",
+        "N" if a.aromatic => "nb",
+        "N" if a.charge > 0 && adj[i].iter().filter(|&&(k, _)| el(k) == "O").count() == 2 => "no",
+        "N" if adj[i].len() == 4 => "n4",
+        "N" if adj[i].iter().any(|&(_, b)| b == BondKind::Triple) => "n1",
+        "N" if double_to(i, &["C", "N", "O", "P", "S"]) => "n2",
+        "N" if adj[i].iter().any(|&(k, _)| el(k) == "C" && double_to(k, &["O", "S"])) => "n",
+        "N" if adj[i].iter().any(|&(k, _)| m.atoms[k].aromatic) => "nh",
+        "N" => "n3",
+        "O" if adj[i].len() == 1 && (a.charge < 0 || adj[i][0].1 == BondKind::Double) => "o",
+        "O" if adj[i].iter().any(|&(k, _)| el(k) == "H") => "oh",
+        "O" => "os",
+        "S" if adj[i].len() == 1 => "s",
+        "S" if adj[i].len() == 2 && adj[i].iter().any(|&(k, _)| el(k) == "H") => "sh",
+        "S" if adj[i].len() == 2 => "ss",
+        "S" if adj[i].len() == 3 => "s4",
+        "S" => "s6",
+        "P" if adj[i].len() == 4 => "p5",
+        "P" if adj[i].len() == 3 => "p3",
+        "P" => "p2",
+        other => return other.to_ascii_lowercase(),
+    };
+    t.to_string()
+}
+
+/// GAFF Lennard-Jones Rmin/2 (Å) and ε (kcal/mol) of a type.
+fn lj(t: &str) -> Option<(f64, f64)> {
+    Some(match t {
+        "c3" => (1.9080, 0.1094),
+        "c" | "c2" | "ca" => (1.9080, 0.0860),
+        "c1" => (1.9080, 0.2100),
+        "hc" | "hn" | "hs" | "hp" => (if t == "hc" { 1.4870 } else { 0.6000 }, 0.0157),
+        "h1" => (1.3870, 0.0157),
+        "h2" => (1.2870, 0.0157),
+        "h3" => (1.1870, 0.0157),
+        "ha" => (1.4590, 0.0150),
+        "h4" => (1.4090, 0.0150),
+        "h5" => (1.3590, 0.0150),
+        "ho" => (0.0, 0.0),
+        "o" => (1.6612, 0.2100),
+        "oh" => (1.7210, 0.2104),
+        "os" => (1.6837, 0.1700),
+        "n" | "n1" | "n2" | "n3" | "n4" | "na" | "nb" | "nh" | "no" => (1.8240, 0.1700),
+        "s" | "sh" | "ss" | "s4" | "s6" => (2.0000, 0.2500),
+        "p2" | "p3" | "p5" => (2.1000, 0.2000),
+        "f" => (1.7500, 0.0610),
+        "cl" => (1.9480, 0.2650),
+        "br" => (2.2200, 0.3200),
+        "i" => (2.3500, 0.4000),
+        _ => return None,
+    })
+}
+
+/// K in k = K·r₀^-4.5 (kcal/mol/Å² with r₀ in Å) for a bond between two elements, fitted to
+/// one GAFF bond of each pair.
+fn bond_scale(a: &str, b: &str) -> f64 {
+    let (a, b) = if a <= b { (a, b) } else { (b, a) };
+    match (a, b) {
+        ("C", "C") => 2085.0, ("C", "H") => 501.0, ("C", "N") => 1662.0, ("C", "O") => 1549.0, ("C", "S") => 3270.0, ("C", "P") => 3816.0,
+        ("C", "F") => 1375.0, ("C", "Cl") => 3155.0, ("Br", "C") => 4404.0, ("C", "I") => 6740.0,
+        ("H", "N") => 427.0, ("H", "O") => 328.0, ("H", "S") => 942.0, ("N", "N") => 1589.0, ("N", "O") => 1714.0, ("O", "S") => 2792.0, ("O", "P") => 3230.0,
+        _ => 1500.0,
+    }
+}
+
+fn sp2(t: &str) -> bool { matches!(t, "c" | "c2" | "ca" | "n" | "n2" | "na" | "nb" | "nh" | "no") }
+fn sp3(t: &str) -> bool { matches!(t, "c3" | "n3" | "n4" | "oh" | "os" | "sh" | "ss") }
+
+/// GAFF's generic torsion about a bond between types `x` and `y`: the barrier shared by the
+/// dihedrals around it (kcal/mol), periodicity and phase (degrees). `None` for a free rotor.
+fn torsion(kind: BondKind, x: &str, y: &str) -> Option<(f64, u8, f64)> {
+    let either = |f: &dyn Fn(&str) -> bool| f(x) || f(y);
+    match kind {
+        BondKind::Double => Some((26.6, 2, 180.0)),
+        BondKind::Aromatic => Some((14.5, 2, 180.0)),
+        BondKind::Triple => None,
+        BondKind::Single if (x == "c" && y.starts_with('n')) || (y == "c" && x.starts_with('n')) => Some((10.0, 2, 180.0)),
+        BondKind::Single if sp2(x) && sp2(y) => Some((4.0, 2, 180.0)),
+        BondKind::Single if sp3(x) && sp3(y) => Some((if either(&|t| t == "oh") { 0.5 } else if either(&|t| t == "os") { 1.15 } else if either(&|t| t.starts_with('n')) { 1.8 } else if either(&|t| t.starts_with('s')) { 1.0 } else { 1.4 }, 3, 0.0)),
+        BondKind::Single => None,
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct AtomParams { element: String, #[serde(rename = "type")] atom_type: String, rmin_half: f64, epsilon: f64 }
+#[derive(Serialize, Clone)]
+pub struct BondParams { atoms: [usize; 2], types: String, r0: f64, k: f64 }
+#[derive(Serialize, Clone)]
+pub struct AngleParams { atoms: [usize; 3], types: String, theta0_deg: f64, k: f64 }
+/// A proper or improper torsion term, E = barrier·(1 + cos(nφ − phase)).
+#[derive(Serialize, Clone)]
+pub struct TorsionParams { atoms: [usize; 4], types: String, barrier: f64, periodicity: u8, phase_deg: f64 }
+
+/// A hydrogen-complete molecule's parameters. Atoms are indexed as in the molecule.
+pub struct Parameters { pub atoms: Vec<AtomParams>, pub bonds: Vec<BondParams>, pub angles: Vec<AngleParams>, pub dihedrals: Vec<TorsionParams>, pub impropers: Vec<TorsionParams>, pub generic: Vec<String>, neighbors: Vec<Vec<usize>> }
+
+/// Bonded and van der Waals energy terms, kcal/mol.
+pub struct Energy { pub bond: f64, pub angle: f64, pub dihedral: f64, pub vdw: f64 }
+
+/// Parameters for every term of a hydrogen-complete molecule.
+pub fn parameterize(m: &Molecule) -> Parameters {
+    let adj = m.neighbors();
+    let types: Vec<String> = (0..m.atoms.len()).map(|i| atom_type(m, &adj, i)).collect();
+    let label = |ix: &[usize]| ix.iter().map(|&i| types[i].as_str()).collect::<Vec<_>>().join("-");
+    let mut generic = Vec::new();
+    let atoms = m.atoms.iter().enumerate().map(|(i, a)| {
+        let (rmin_half, epsilon) = lj(&types[i]).unwrap_or_else(|| { generic.push(format!("{}{}", a.element, i + 1)); GENERIC_LJ });
+        AtomParams { element: a.element.clone(), atom_type: types[i].clone(), rmin_half, epsilon }
+    }).collect();
+    let bonds = m.bonds.iter().map(|b| {
+        let r0 = conformer::bond_length(m, b.a, b.b, b.kind);
+        let k = bond_scale(&m.atoms[b.a].element, &m.atoms[b.b].element) * r0.powf(-4.5);
+        BondParams { atoms: [b.a, b.b], types: label(&[b.a, b.b]), r0: (r0 * 1e4).round() / 1e4, k: (k * 10.0).round() / 10.0 }
+    }).collect();
+    let bonded = |i: usize, j: usize| adj[i].iter().any(|&(k, _)| k == j);
+    let mut angles = Vec::new();
+    for j in 0..m.atoms.len() {
+        for (x, &(i, _)) in adj[j].iter().enumerate() {
+            for &(k, _) in &adj[j][x + 1..] {
+                // Three- and four-membered rings close the angle; otherwise the centre's hybridization sets it.
+                let ring4 = adj[i].iter().any(|&(n, _)| n != j && bonded(n, k));
+                let theta0 = if bonded(i, k) { 60.0 } else if ring4 { 90.0 } else { conformer::bond_angle(m, &adj, j).to_degrees() };
+                let hydrogens = [i, k].iter().filter(|&&e| m.atoms[e].element == "H").count();
+                angles.push(AngleParams { atoms: [i, j, k], types: label(&[i, j, k]), theta0_deg: (theta0 * 100.0).round() / 100.0, k: [63.2, 46.4, 39.4][hydrogens] });
+            }
+        }
+    }
+    let mut dihedrals = Vec::new();
+    for b in &m.bonds {
+        let Some((barrier, n, phase)) = torsion(b.kind, &types[b.a], &types[b.b]) else { continue };
+        let quads: Vec<[usize; 4]> = adj[b.a].iter().filter(|&&(i, _)| i != b.b).flat_map(|&(i, _)| adj[b.b].iter().filter(move |&&(l, _)| l != b.a && l != i).map(move |&(l, _)| [i, b.a, b.b, l])).collect();
+        let share = barrier / quads.len().max(1) as f64;
+        dihedrals.extend(quads.into_iter().map(|q| TorsionParams { atoms: q, types: label(&q), barrier: (share * 1e4).round() / 1e4, periodicity: n, phase_deg: phase }));
+    }
+    // Planar sp2 centres, the centre third as in AMBER.
+    let impropers = (0..m.atoms.len()).filter(|&c| adj[c].len() == 3 && sp2(&types[c])).map(|c| {
+        let q = [adj[c][0].0, adj[c][1].0, c, adj[c][2].0];
+        TorsionParams { atoms: q, types: label(&q), barrier: if types[c] == "c" { 10.5 } else { 1.1 }, periodicity: 2, phase_deg: 180.0 }
+    }).collect();
+    Parameters { atoms, bonds, angles, dihedrals, impropers, generic, neighbors: adj.iter().map(|n| n.iter().map(|e| e.0).collect()).collect() }
+}
+
+/// Dihedral angle of four points, radians.
+fn dihedral(p: [[f64; 3]; 4]) -> f64 {
+    let (b1, b2, b3) = (sub(p[1], p[0]), sub(p[2], p[1]), sub(p[3], p[2]));
+    let (n1, n2) = (cross(b1, b2), cross(b2, b3));
+    let m1 = cross(n1, b2);
+    (dot(m1, n2) / norm(b2)).atan2(dot(n1, n2))
+}
+
+impl Parameters {
+    /// The energy terms at coordinates `x`, one per atom.
+    pub fn energy(&self, x: &[[f64; 3]]) -> Energy {
+        let bond = self.bonds.iter().map(|b| { let d = dist(x[b.atoms[0]], x[b.atoms[1]]) - b.r0; b.k * d * d }).sum();
+        let angle = self.angles.iter().map(|a| {
+            let (u, v) = (sub(x[a.atoms[0]], x[a.atoms[1]]), sub(x[a.atoms[2]], x[a.atoms[1]]));
+            let theta = (dot(u, v) / (dot(u, u) * dot(v, v)).sqrt().max(1e-9)).clamp(-1.0, 1.0).acos();
+            let d = theta - a.theta0_deg.to_radians();
+            a.k * d * d
+        }).sum();
+        let dihedral = self.dihedrals.iter().chain(&self.impropers).map(|t| {
+            let phi = dihedral([x[t.atoms[0]], x[t.atoms[1]], x[t.atoms[2]], x[t.atoms[3]]]);
+            t.barrier * (1.0 + (f64::from(t.periodicity) * phi - t.phase_deg.to_radians()).cos())
+        }).sum();
+        let n = self.atoms.len();
+        let mut vdw = 0.0;
+        for i in 0..n {
+            // Bond separation from i, up to three.
+            let mut sep = vec![u8::MAX; n];
+            sep[i] = 0;
+            let mut frontier = vec![i];
+            for d in 1..=3u8 {
+                let mut next = Vec::new();
+                for &a in &frontier { for &b in &self.neighbors[a] { if sep[b] == u8::MAX { sep[b] = d; next.push(b); } } }
+                frontier = next;
+            }
+            let (ri, ei) = (self.atoms[i].rmin_half, self.atoms[i].epsilon);
+            for j in i + 1..n {
+                if sep[j] < 3 { continue; }
+                let (rmin, epsilon) = (ri + self.atoms[j].rmin_half, (ei * self.atoms[j].epsilon).sqrt());
+                if epsilon == 0.0 { continue; }
+                let r2 = dot(sub(x[i], x[j]), sub(x[i], x[j])).max(0.25);
+                let s6 = (rmin * rmin / r2).powi(3);
+                vdw += if sep[j] == 3 { SCALE_14 } else { 1.0 } * epsilon * (s6 * s6 - 2.0 * s6);
+            }
+        }
+        Energy { bond, angle, dihedral, vdw }
+    }
+}
+
+/// The molecule with hydrogens placed on `coords`, its parameters and their energy there.
+pub fn evaluate(mol: &Molecule, coords: &[[f64; 3]]) -> Result<(Parameters, Energy), String> {
+    let full = convert::explicit(mol, coords, Hydrogens::All, false)?;
+    let params = parameterize(&full.mol);
+    let energy = params.energy(&full.coords);
+    Ok((params, energy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{charges, chem};
+
+    fn with_hydrogens(smiles: &str) -> Molecule { charges::with_hydrogens(&chem::parse_smiles(smiles).unwrap()).unwrap() }
+
+    fn types(smiles: &str) -> Vec<String> {
+        let m = with_hydrogens(smiles);
+        let adj = m.neighbors();
+        (0..m.atoms.len()).map(|i| atom_type(&m, &adj, i)).collect()
+    }
+
+    #[test]
+    fn atom_types_follow_hybridization_and_neighbours() {
+        assert_eq!(types("CC(=O)O"), ["c3", "c", "o", "oh", "hc", "hc", "hc", "ho"]);
+        assert_eq!(types("c1ccccc1")[..7], ["ca", "ca", "ca", "ca", "ca", "ca", "ha"]);
+        assert_eq!(types("CC#N")[..3], ["c3", "c1", "n1"]);
+        assert_eq!(types("CN")[1], "n3");
+        assert_eq!(types("CC(=O)N")[3], "n");
+        assert_eq!(types("OCF")[4], "h2");
+    }
+
+    #[test]
+    fn every_term_of_acetic_acid_is_typed() {
+        let p = parameterize(&with_hydrogens("CC(=O)O"));
+        assert!(p.generic.is_empty());
+        assert_eq!(p.bonds.len(), 7);
+        // The carbonyl carbon is the only planar centre.
+        assert_eq!(p.impropers.iter().map(|t| t.atoms[2]).collect::<Vec<_>>(), [1]);
+    }
+
+    #[test]
+    fn dihedrals_are_signed() {
+        let d = dihedral([[1.0, 0.0, 0.0], [0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 1.0, 1.0]]);
+        assert!((d - std::f64::consts::FRAC_PI_2).abs() < 1e-12);
+        assert!(dihedral([[1.0, 0.0, 0.0], [0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [1.0, 1.0, 0.0]]).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,78 @@
+//! Nucleotide input: open reading frames and translation.
+//!
+//! A DNA or RNA sequence is scanned in all six frames for ATG-initiated ORFs ending at a stop
+//! codon; each stop keeps its most upstream in-frame ATG, so nested starts don't produce
+//! duplicates. ORFs of at least `min_orf_length` codons are translated with the standard
+//! genetic code and predicted like any protein, the longest being the primary chain.
+//! Coordinates are 0-based on the forward strand with inclusive ends, stop codon included.
+
+use serde::Serialize;
+
+/// Standard genetic code, codons ordered TTT, TTC, TTA, TTG, TCT, ... (bases T, C, A, G).
+const CODE: &[u8; 64] = b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG";
+pub const DEFAULT_MIN_ORF: usize = 100;
+/// ORFs beyond this many (by length) are listed but not predicted.
+pub const MAX_PREDICTED: usize = 10;
+/// Auto-detected nucleotide input needs at least this many bases.
+const MIN_NUCLEOTIDES: usize = 30;
+
+#[derive(Serialize, Clone)]
+pub struct Orf { pub strand: char, pub frame: u8, pub start: usize, pub end: usize, pub length_aa: usize, pub protein: String }
+/// ORFs of a nucleotide sequence, longest first.
+#[derive(Serialize, Clone)]
+pub struct Gene { pub molecule: &'static str, pub length: usize, pub gc_content: f64, pub orfs: Vec<Orf> }
+
+/// Whether `sequence_type` (or, absent that, the sequence itself) says this is DNA/RNA.
+pub fn is_nucleotide(sequence: &str, sequence_type: Option<&str>) -> Result<bool, String> {
+    match sequence_type.map(str::to_lowercase).as_deref() {
+        Some("dna" | "rna" | "nucleotide") => Ok(true),
+        Some("protein") => Ok(false),
+        Some(other) => Err(format!("unknown sequence_type {other}; expected protein, dna or rna")),
+        None => {
+            let bases = sequence.bytes().filter(|b| !b.is_ascii_whitespace()).collect::<Vec<_>>();
+            Ok(bases.len() >= MIN_NUCLEOTIDES && bases.iter().all(|b| b"ACGTUN".contains(&b.to_ascii_uppercase())))
+        }
+    }
+}
+
+fn codon(c: &[u8]) -> u8 {
+    let base = |b: u8| match b { b'T' => Some(0), b'C' => Some(1), b'A' => Some(2), b'G' => Some(3), _ => None };
+    match (base(c[0]), base(c[1]), base(c[2])) {
+        (Some(a), Some(b), Some(d)) => CODE[a * 16 + b * 4 + d],
+        _ => b'X',
+    }
+}
+
+fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    seq.iter().rev().map(|&b| match b { b'A' => b'T', b'T' => b'A', b'C' => b'G', b'G' => b'C', _ => b'N' }).collect()
+}
+
+pub fn find_orfs(sequence: &str, min_codons: usize) -> Result<Gene, String> {
+    let raw: Vec<u8> = sequence.bytes().filter(|b| !b.is_ascii_whitespace()).map(|b| b.to_ascii_uppercase()).collect();
+    if let Some(b) = raw.iter().find(|b| !b"ACGTUN".contains(b)) { return Err(format!("invalid nucleotide {}", *b as char)); }
+    let molecule = if raw.contains(&b'U') { "rna" } else { "dna" };
+    let fwd: Vec<u8> = raw.iter().map(|&b| if b == b'U' { b'T' } else { b }).collect();
+    let n = fwd.len();
+    let mut orfs = Vec::new();
+    for (strand, seq) in [('+', fwd.clone()), ('-', reverse_complement(&fwd))] {
+        for frame in 0..3 {
+            let mut start: Option<usize> = None;
+            for i in (frame..n.saturating_sub(2)).step_by(3) {
+                let aa = codon(&seq[i..i + 3]);
+                if start.is_none() && &seq[i..i + 3] == b"ATG" { start = Some(i); }
+                let Some(s) = start.filter(|_| aa == b'*') else { continue };
+                start = None;
+                let length_aa = (i - s) / 3;
+                if length_aa < min_codons { continue; }
+                let protein: String = seq[s..i].chunks(3).map(|c| codon(c) as char).collect();
+                // Minus-strand positions map back onto the forward strand.
+                let (start, end) = if strand == '+' { (s, i + 2) } else { (n - 1 - (i + 2), n - 1 - s) };
+                orfs.push(Orf { strand, frame: frame as u8 + 1, start, end, length_aa, protein });
+            }
+        }
+    }
+    if orfs.is_empty() { return Err(format!("no open reading frame of at least {min_codons} codons")); }
+    orfs.sort_by(|a, b| b.length_aa.cmp(&a.length_aa).then(a.start.cmp(&b.start)));
+    let gc = fwd.iter().filter(|&&b| b == b'G' || b == b'C').count() as f64 / n as f64;
+    Ok(Gene { molecule, length: n, gc_content: (gc * 1000.0).round() / 1000.0, orfs })
+}
@@ -0,0 +1,77 @@
+//! N- and O-glycosylation sites and template glycans.
+//!
+//! N-linked sites are Asn in an N-X-S/T sequon (X not Pro), weaker with Ser than Thr, weaker
+//! still for the rare N-X-C, and suppressed when Pro follows the sequon. O-linked sites are
+//! Ser/Thr in the Pro- and Ser/Thr-rich stretches that mucin-type GalNAc transferases favour.
+//! Template glycans can be attached to every predicted site: the named N-glycan on Asn and a
+//! core-1 (Galβ1-3GalNAc) O-glycan on Ser/Thr; their masses add to the predicted structure.
+
+use serde::Serialize;
+
+const HEXNAC: f64 = 203.0794;
+const HEX: f64 = 162.0528;
+const FUC: f64 = 146.0579;
+const NEUAC: f64 = 291.0954;
+/// O-sites need at least this fraction of S/T/P in the ±5 window.
+const O_WINDOW_FRACTION: f64 = 0.5;
+
+/// A glycan template: name and HexNAc, Hex, Fuc, NeuAc counts.
+struct Template { name: &'static str, counts: [u32; 4] }
+
+const N_TEMPLATES: &[Template] = &[
+    Template { name: "man5", counts: [2, 5, 0, 0] },
+    Template { name: "man9", counts: [2, 9, 0, 0] },
+    Template { name: "g0f", counts: [4, 3, 1, 0] },
+    Template { name: "g2f", counts: [4, 5, 1, 0] },
+    Template { name: "a2g2s2", counts: [4, 5, 0, 2] },
+];
+const O_CORE1: Template = Template { name: "core1", counts: [1, 1, 0, 0] };
+
+#[derive(Serialize, Clone)]
+pub struct Glycan { pub template: String, pub composition: String, pub residues: u32, pub mass_da: f64 }
+#[derive(Serialize, Clone)]
+pub struct GlycoSite { pub position: usize, pub residue: char, pub kind: &'static str, pub sequon: String, pub confidence: f64, #[serde(skip_serializing_if = "Option::is_none")] pub glycan: Option<Glycan> }
+#[derive(Serialize, Clone)]
+pub struct GlycosylationReport { pub sites: Vec<GlycoSite>, pub total_glycan_mass_da: f64 }
+
+/// Whether `name` is a known N-glycan template.
+pub fn known_template(name: &str) -> bool { N_TEMPLATES.iter().any(|t| t.name == name) }
+
+/// Template names for error messages.
+pub fn template_names() -> String { N_TEMPLATES.iter().map(|t| t.name).collect::<Vec<_>>().join(", ") }
+
+fn glycan(t: &Template) -> Glycan {
+    let [hexnac, hex, fuc, neuac] = t.counts;
+    let composition = [("HexNAc", hexnac), ("Hex", hex), ("Fuc", fuc), ("NeuAc", neuac)].iter().filter(|(_, n)| *n > 0).map(|(m, n)| format!("{m}({n})")).collect::<String>();
+    let mass = hexnac as f64 * HEXNAC + hex as f64 * HEX + fuc as f64 * FUC + neuac as f64 * NEUAC;
+    Glycan { template: t.name.into(), composition, residues: t.counts.iter().sum(), mass_da: (mass * 10_000.0).round() / 10_000.0 }
+}
+
+/// Predicted sites, in sequence order, with `template` glycans attached when given.
+pub fn predict(sequence: &str, template: Option<&str>) -> GlycosylationReport {
+    let seq: Vec<u8> = sequence.bytes().map(|b| b.to_ascii_uppercase()).collect();
+    let n_template = template.and_then(|name| N_TEMPLATES.iter().find(|t| t.name == name));
+    let mut sites = Vec::new();
+    for i in 0..seq.len() {
+        let at = |k: usize| seq.get(i + k).copied();
+        if seq[i] == b'N' && at(1).is_some_and(|x| x != b'P') {
+            let base = match at(2) { Some(b'T') => 0.85, Some(b'S') => 0.7, Some(b'C') => 0.3, _ => 0.0 };
+            if base > 0.0 {
+                let confidence = if at(3) == Some(b'P') { base - 0.3 } else { base };
+                let sequon = String::from_utf8_lossy(&seq[i..i + 3]).into();
+                sites.push(GlycoSite { position: i, residue: 'N', kind: "N-linked", sequon, confidence, glycan: n_template.map(glycan) });
+            }
+        }
+        if matches!(seq[i], b'S' | b'T') {
+            let (from, to) = (i.saturating_sub(5), (i + 6).min(seq.len()));
+            let rich = seq[from..to].iter().filter(|r| matches!(r, b'S' | b'T' | b'P')).count() as f64 / (to - from) as f64;
+            let proline = at(3) == Some(b'P') || (i > 0 && seq[i - 1] == b'P');
+            if rich >= O_WINDOW_FRACTION && proline {
+                let confidence = ((rich * 0.9) * 100.0).round() / 100.0;
+                sites.push(GlycoSite { position: i, residue: seq[i] as char, kind: "O-linked", sequon: String::from_utf8_lossy(&seq[from..to]).into(), confidence, glycan: template.map(|_| glycan(&O_CORE1)) });
+            }
+        }
+    }
+    let total = sites.iter().filter_map(|s| s.glycan.as_ref()).map(|g| g.mass_da).sum::<f64>() + 0.0;
+    GlycosylationReport { sites, total_glycan_mass_da: (total * 10_000.0).round() / 10_000.0 }
+}
@@ -0,0 +1,504 @@
+//! HELM notation for peptides, oligonucleotides and their conjugates.
+//!
+//! HELM writes a biomolecule as simple polymers of named monomers (`PEPTIDE1{A.C.[Aib].G}`,
+//! `RNA1{R(A)P.[mR](U)P.R(G)}`, `CHEM1{[SMCC]}`) and the bonds between them
+//! (`PEPTIDE1,CHEM1,2:R3-1:R2`), so modified residues, cyclic and stapled peptides,
+//! peptide-drug conjugates and modified oligonucleotides fit where SMILES and FASTA don't.
+//! `parse` reads HELM 1 and 2; `Display` writes it back in HELM 2 form; `from_sequence` writes
+//! a protein, RNA or DNA sequence as HELM; `molecule` builds the full structure, which is what
+//! the engine computes on (`resolver` accepts HELM wherever it accepts SMILES).
+//!
+//! Monomers are the bundled library below, `d` + a residue (D-amino acids), `me` + a residue
+//! (N-methyl), or inline SMILES with numbered attachment points (`[[*:1]NCC(=O)[*:2]]`). An
+//! attachment point another monomer doesn't take is capped as the library says (H or OH;
+//! inline monomers get H). Peptide residues join R2 to R1 in order; nucleotides join sugar R2
+//! to linker R1, linker R2 to the next sugar's R1, and sugar R3 to its base (in parentheses).
+//! Base-pair (`pair`) connections are non-covalent and don't change the structure.
+//!
+//! Cyclic peptides are written with connections (`PEPTIDE1,PEPTIDE1,5:R2-1:R1` head-to-tail,
+//! `2:R3-7:R3` between cysteines), as `cyclo(RGDfK)` or `cyclo(-Arg-Gly-Asp-D-Phe-Lys-)`, or
+//! added to a linear peptide with a `Cyclization`. Hydrocarbon staples join the olefin R3s of
+//! S5/R5/S8/R8 residues with a double bond, the product of ring-closing metathesis; an
+//! unstapled olefin keeps its terminal =CH2.
+//!
+//! Polymer groups, annotations, repeats and ambiguous monomers are not supported, and, as with
+//! SMILES, stereochemistry is not kept in the structure: `dA` and `A` build the same graph.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::chem::{self, BondKind, Molecule};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind { Peptide, Rna, Chem, Blob }
+
+impl Kind {
+    pub fn name(self) -> &'static str { match self { Self::Peptide => "PEPTIDE", Self::Rna => "RNA", Self::Chem => "CHEM", Self::Blob => "BLOB" } }
+}
+
+/// A monomer as written, and whether it is an RNA base in parentheses. `joined` monomers
+/// follow the previous one without a `.` (`R(A)P` is one group of three).
+#[derive(Clone, Debug)]
+pub struct Unit { pub id: String, pub branch: bool, pub joined: bool }
+#[derive(Clone, Debug)]
+pub struct Polymer { pub id: String, pub kind: Kind, pub units: Vec<Unit> }
+/// One end of a connection: polymer ID, 1-based monomer position and attachment (`R3`, `pair`).
+#[derive(Clone, Debug)]
+pub struct End { pub polymer: String, pub position: usize, pub attachment: String }
+#[derive(Clone, Debug)]
+pub struct Connection { pub from: End, pub to: End }
+#[derive(Clone, Debug)]
+pub struct Helm { pub polymers: Vec<Polymer>, pub connections: Vec<Connection> }
+
+/// Library monomer: ID, polymer type, SMILES with `[*:n]` attachment points, the cap of each
+/// attachment point in order (`H`, `O` for OH, or `C` for the =CH2 an unstapled olefin ends
+/// in), and the natural analogue's one-letter code.
+struct Monomer { id: &'static str, kind: Kind, smiles: &'static str, caps: &'static str, natural: char }
+
+const MONOMERS: &[Monomer] = &[
+    Monomer { id: "A", kind: Kind::Peptide, smiles: "[*:1]NC(C)C(=O)[*:2]", caps: "HO", natural: 'A' },
+    Monomer { id: "R", kind: Kind::Peptide, smiles: "[*:1]NC(CCCNC(=N)N)C(=O)[*:2]", caps: "HO", natural: 'R' },
+    Monomer { id: "N", kind: Kind::Peptide, smiles: "[*:1]NC(CC(N)=O)C(=O)[*:2]", caps: "HO", natural: 'N' },
+    Monomer { id: "D", kind: Kind::Peptide, smiles: "[*:1]NC(CC(=O)[*:3])C(=O)[*:2]", caps: "HOO", natural: 'D' },
+    Monomer { id: "C", kind: Kind::Peptide, smiles: "[*:1]NC(CS[*:3])C(=O)[*:2]", caps: "HOH", natural: 'C' },
+    Monomer { id: "Q", kind: Kind::Peptide, smiles: "[*:1]NC(CCC(N)=O)C(=O)[*:2]", caps: "HO", natural: 'Q' },
+    Monomer { id: "E", kind: Kind::Peptide, smiles: "[*:1]NC(CCC(=O)[*:3])C(=O)[*:2]", caps: "HOO", natural: 'E' },
+    Monomer { id: "G", kind: Kind::Peptide, smiles: "[*:1]NCC(=O)[*:2]", caps: "HO", natural: 'G' },
+    Monomer { id: "H", kind: Kind::Peptide, smiles: "[*:1]NC(CC1=CNC=N1)C(=O)[*:2]", caps: "HO", natural: 'H' },
+    Monomer { id: "I", kind: Kind::Peptide, smiles: "[*:1]NC(C(C)CC)C(=O)[*:2]", caps: "HO", natural: 'I' },
+    Monomer { id: "L", kind: Kind::Peptide, smiles: "[*:1]NC(CC(C)C)C(=O)[*:2]", caps: "HO", natural: 'L' },
+    Monomer { id: "K", kind: Kind::Peptide, smiles: "[*:1]NC(CCCCN[*:3])C(=O)[*:2]", caps: "HOH", natural: 'K' },
+    Monomer { id: "M", kind: Kind::Peptide, smiles: "[*:1]NC(CCSC)C(=O)[*:2]", caps: "HO", natural: 'M' },
+    Monomer { id: "F", kind: Kind::Peptide, smiles: "[*:1]NC(CC1=CC=CC=C1)C(=O)[*:2]", caps: "HO", natural: 'F' },
+    Monomer { id: "P", kind: Kind::Peptide, smiles: "[*:1]N1CCCC1C(=O)[*:2]", caps: "HO", natural: 'P' },
+    Monomer { id: "S", kind: Kind::Peptide, smiles: "[*:1]NC(CO)C(=O)[*:2]", caps: "HO", natural: 'S' },
+    Monomer { id: "T", kind: Kind::Peptide, smiles: "[*:1]NC(C(C)O)C(=O)[*:2]", caps: "HO", natural: 'T' },
+    Monomer { id: "W", kind: Kind::Peptide, smiles: "[*:1]NC(CC1=CNC2=CC=CC=C12)C(=O)[*:2]", caps: "HO", natural: 'W' },
+    Monomer { id: "Y", kind: Kind::Peptide, smiles: "[*:1]NC(CC1=CC=C(O)C=C1)C(=O)[*:2]", caps: "HO", natural: 'Y' },
+    Monomer { id: "V", kind: Kind::Peptide, smiles: "[*:1]NC(C(C)C)C(=O)[*:2]", caps: "HO", natural: 'V' },
+    Monomer { id: "Aib", kind: Kind::Peptide, smiles: "[*:1]NC(C)(C)C(=O)[*:2]", caps: "HO", natural: 'X' },
+    Monomer { id: "Abu", kind: Kind::Peptide, smiles: "[*:1]NC(CC)C(=O)[*:2]", caps: "HO", natural: 'X' },
+    Monomer { id: "Nva", kind: Kind::Peptide, smiles: "[*:1]NC(CCC)C(=O)[*:2]", caps: "HO", natural: 'X' },
+    Monomer { id: "Nle", kind: Kind::Peptide, smiles: "[*:1]NC(CCCC)C(=O)[*:2]", caps: "HO", natural: 'X' },
+    Monomer { id: "Orn", kind: Kind::Peptide, smiles: "[*:1]NC(CCCN[*:3])C(=O)[*:2]", caps: "HOH", natural: 'X' },
+    Monomer { id: "Cit", kind: Kind::Peptide, smiles: "[*:1]NC(CCCNC(N)=O)C(=O)[*:2]", caps: "HO", natural: 'X' },
+    Monomer { id: "Hyp", kind: Kind::Peptide, smiles: "[*:1]N1CC(O)CC1C(=O)[*:2]", caps: "HO", natural: 'P' },
+    Monomer { id: "Pen", kind: Kind::Peptide, smiles: "[*:1]NC(C(C)(C)S[*:3])C(=O)[*:2]", caps: "HOH", natural: 'C' },
+    Monomer { id: "Sar", kind: Kind::Peptide, smiles: "[*:1]N(C)CC(=O)[*:2]", caps: "HO", natural: 'G' },
+    Monomer { id: "S5", kind: Kind::Peptide, smiles: "[*:1]NC(C)(CCCC=[*:3])C(=O)[*:2]", caps: "HOC", natural: 'X' },
+    Monomer { id: "R5", kind: Kind::Peptide, smiles: "[*:1]NC(C)(CCCC=[*:3])C(=O)[*:2]", caps: "HOC", natural: 'X' },
+    Monomer { id: "S8", kind: Kind::Peptide, smiles: "[*:1]NC(C)(CCCCCCC=[*:3])C(=O)[*:2]", caps: "HOC", natural: 'X' },
+    Monomer { id: "R8", kind: Kind::Peptide, smiles: "[*:1]NC(C)(CCCCCCC=[*:3])C(=O)[*:2]", caps: "HOC", natural: 'X' },
+    Monomer { id: "pE", kind: Kind::Peptide, smiles: "O=C1CCC(N1)C(=O)[*:2]", caps: "-O", natural: 'E' },
+    Monomer { id: "ac", kind: Kind::Peptide, smiles: "CC(=O)[*:2]", caps: "-O", natural: ' ' },
+    Monomer { id: "am", kind: Kind::Peptide, smiles: "[*:1]N", caps: "H", natural: ' ' },
+    Monomer { id: "R", kind: Kind::Rna, smiles: "[*:1]OCC1OC([*:3])C(O)C1O[*:2]", caps: "HHO", natural: ' ' },
+    Monomer { id: "dR", kind: Kind::Rna, smiles: "[*:1]OCC1OC([*:3])CC1O[*:2]", caps: "HHO", natural: ' ' },
+    Monomer { id: "mR", kind: Kind::Rna, smiles: "[*:1]OCC1OC([*:3])C(OC)C1O[*:2]", caps: "HHO", natural: ' ' },
+    Monomer { id: "fR", kind: Kind::Rna, smiles: "[*:1]OCC1OC([*:3])C(F)C1O[*:2]", caps: "HHO", natural: ' ' },
+    Monomer { id: "LR", kind: Kind::Rna, smiles: "[*:1]OCC12OC([*:3])C(OC1)C2O[*:2]", caps: "HHO", natural: ' ' },
+    Monomer { id: "P", kind: Kind::Rna, smiles: "[*:1]P(=O)(O)[*:2]", caps: "OO", natural: ' ' },
+    Monomer { id: "sP", kind: Kind::Rna, smiles: "[*:1]P(=S)(O)[*:2]", caps: "OO", natural: ' ' },
+    Monomer { id: "A", kind: Kind::Rna, smiles: "[*:1]N1C=NC2=C1N=CN=C2N", caps: "H", natural: 'A' },
+    Monomer { id: "C", kind: Kind::Rna, smiles: "[*:1]N1C=CC(N)=NC1=O", caps: "H", natural: 'C' },
+    Monomer { id: "G", kind: Kind::Rna, smiles: "[*:1]N1C=NC2=C1N=C(N)NC2=O", caps: "H", natural: 'G' },
+    Monomer { id: "U", kind: Kind::Rna, smiles: "[*:1]N1C=CC(=O)NC1=O", caps: "H", natural: 'U' },
+    Monomer { id: "T", kind: Kind::Rna, smiles: "[*:1]N1C=C(C)C(=O)NC1=O", caps: "H", natural: 'T' },
+    Monomer { id: "SMCC", kind: Kind::Chem, smiles: "[*:1]C(=O)C1CCC(CN2C(=O)CC([*:2])C2=O)CC1", caps: "OH", natural: ' ' },
+];
+
+/// A monomer's SMILES template, attachment-point caps and natural analogue.
+fn monomer(kind: Kind, id: &str) -> Option<(String, Vec<u8>, char)> {
+    let find = |id: &str| MONOMERS.iter().find(|m| m.kind == kind && m.id == id);
+    if let Some(m) = find(id) { return Some((m.smiles.into(), m.caps.bytes().collect(), m.natural)); }
+    if kind != Kind::Peptide { return None; }
+    if let Some(m) = id.strip_prefix('d').and_then(find).filter(|m| m.id.len() == 1 && m.id != "G") { return Some((m.smiles.into(), m.caps.bytes().collect(), m.natural)); }
+    let m = id.strip_prefix("me").and_then(find).filter(|m| m.smiles.starts_with("[*:1]NC"))?;
+    Some((m.smiles.replacen("[*:1]N", "[*:1]N(C)", 1), m.caps.bytes().collect(), m.natural))
+}
+
+/// Whether `text` is HELM (or `cyclo(...)`) rather than SMILES or a name.
+pub fn looks_like(text: &str) -> bool {
+    let t = text.trim_start();
+    t.starts_with("cyclo(") || ["PEPTIDE", "RNA", "CHEM", "BLOB"].iter().any(|k| t.strip_prefix(k).is_some_and(|r| r.starts_with(|c: char| c.is_ascii_digit()))) && t.contains('{')
+}
+
+const THREE_LETTER: &[(&str, &str)] = &[
+    ("Ala", "A"), ("Arg", "R"), ("Asn", "N"), ("Asp", "D"), ("Cys", "C"), ("Gln", "Q"), ("Glu", "E"), ("Gly", "G"), ("His", "H"), ("Ile", "I"),
+    ("Leu", "L"), ("Lys", "K"), ("Met", "M"), ("Phe", "F"), ("Pro", "P"), ("Ser", "S"), ("Thr", "T"), ("Trp", "W"), ("Tyr", "Y"), ("Val", "V"),
+];
+
+/// `cyclo(RGDfK)` or `cyclo(-Arg-Gly-Asp-D-Phe-Lys-)`, the usual shorthand for a head-to-tail
+/// cyclic peptide: lower-case one-letter codes and `D-` are D-amino acids, `NMe-` N-methyl
+/// residues, and other monomer IDs (`Aib`, `Orn` ...) may stand in three-letter form.
+fn cyclo(text: &str) -> Result<Helm, String> {
+    let body = text.trim().strip_prefix("cyclo(").and_then(|b| b.strip_suffix(')')).ok_or_else(|| format!("{text} is not cyclo(...)"))?;
+    let mut ids = Vec::new();
+    if body.contains('-') {
+        let mut prefix = String::new();
+        for t in body.split('-').map(str::trim).filter(|t| !t.is_empty()) {
+            match t {
+                "D" | "L" if prefix.is_empty() => prefix = if t == "D" { "d".into() } else { " ".into() },
+                "NMe" if prefix.trim().is_empty() => prefix = "me".into(),
+                _ => {
+                    let code = THREE_LETTER.iter().find(|(three, _)| three.eq_ignore_ascii_case(t)).map_or(t, |(_, one)| *one);
+                    ids.push(format!("{}{code}", prefix.trim()));
+                    prefix.clear();
+                }
+            }
+        }
+    } else {
+        ids = body.chars().filter(|c| !c.is_whitespace()).map(|c| if c.is_ascii_lowercase() && c != 'g' { format!("d{}", c.to_ascii_uppercase()) } else { c.to_ascii_uppercase().to_string() }).collect();
+    }
+    if ids.len() < 2 { return Err(format!("{text} needs at least two residues")); }
+    if let Some(id) = ids.iter().find(|id| monomer(Kind::Peptide, id).is_none()) { return Err(format!("unknown residue {id} in {text}")); }
+    let n = ids.len();
+    let units = ids.into_iter().map(|id| Unit { id, branch: false, joined: false }).collect();
+    let end = |position, attachment: &str| End { polymer: "PEPTIDE1".into(), position, attachment: attachment.into() };
+    Ok(Helm { polymers: vec![Polymer { id: "PEPTIDE1".into(), kind: Kind::Peptide, units }], connections: vec![Connection { from: end(n, "R2"), to: end(1, "R1") }] })
+}
+
+/// Splits at `sep` outside brackets and parentheses.
+fn split_top(s: &str, sep: char) -> Vec<&str> {
+    let (mut depth, mut start, mut parts) = (0i32, 0, Vec::new());
+    for (i, c) in s.char_indices() {
+        match c {
+            '[' | '(' | '{' => depth += 1,
+            ']' | ')' | '}' => depth -= 1,
+            c if c == sep && depth == 0 => { parts.push(&s[start..i]); start = i + 1; }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+/// One monomer token at the start of `s`: a single letter or a bracketed ID or SMILES.
+fn token(s: &str) -> Result<(String, usize), String> {
+    let Some(c) = s.chars().next() else { return Err("missing monomer".into()) };
+    if c != '[' {
+        if !c.is_ascii_alphabetic() { return Err(format!("unexpected '{c}' where a monomer belongs")); }
+        return Ok((c.to_string(), 1));
+    }
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c { '[' => depth += 1, ']' => { depth -= 1; if depth == 0 { return Ok((s[1..i].to_string(), i + 1)); } } _ => {} }
+    }
+    Err(format!("unterminated monomer {s}"))
+}
+
+fn polymer(text: &str) -> Result<Polymer, String> {
+    let open = text.find('{').ok_or_else(|| format!("polymer {text} has no {{"))?;
+    let close = text.rfind('}').filter(|&c| c > open).ok_or_else(|| format!("polymer {text} has no }}"))?;
+    let id = text[..open].trim().to_string();
+    let kind = [Kind::Peptide, Kind::Rna, Kind::Chem, Kind::Blob].into_iter()
+        .find(|k| id.strip_prefix(k.name()).is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit())))
+        .ok_or_else(|| format!("{id} is not a polymer ID (PEPTIDE1, RNA1, CHEM1, BLOB1 ...)"))?;
+    let rest = text[close + 1..].trim();
+    if !rest.is_empty() && (!rest.starts_with('"') || !rest.ends_with('"')) { return Err(format!("unexpected {rest} after polymer {id}")); }
+    let body = text[open + 1..close].trim();
+    if kind == Kind::Blob { return Ok(Polymer { id, kind, units: vec![Unit { id: body.into(), branch: false, joined: false }] }); }
+    let mut units = Vec::new();
+    for group in split_top(body, '.') {
+        let mut s = group.trim();
+        let mut first = true;
+        while !s.is_empty() {
+            let branch = s.starts_with('(');
+            let inner = if branch { &s[1..] } else { s };
+            let (id, len) = token(inner)?;
+            let mut used = len + branch as usize;
+            if branch {
+                if kind != Kind::Rna { return Err(format!("branch monomers are for RNA polymers, not {id} in {}", text[..open].trim())); }
+                if !s[used..].starts_with(')') { return Err(format!("a branch in {} holds more than one monomer", text[..open].trim())); }
+                if units.last().is_none_or(|u: &Unit| u.branch) { return Err(format!("branch ({id}) has no monomer to hang from")); }
+                used += 1;
+            }
+            if s[used..].starts_with(['\'', '"']) { return Err(format!("repeats and monomer annotations are not supported ({group})")); }
+            units.push(Unit { id, branch, joined: !first });
+            first = false;
+            s = s[used..].trim_start();
+        }
+        if first { return Err(format!("empty monomer in {}", text[..open].trim())); }
+    }
+    if kind == Kind::Chem && units.len() != 1 { return Err(format!("{id} must hold exactly one monomer")); }
+    Ok(Polymer { id, kind, units })
+}
+
+fn end(polymer: &str, text: &str) -> Result<End, String> {
+    let (position, attachment) = text.split_once(':').ok_or_else(|| format!("connection end {text} is not position:attachment"))?;
+    let position = position.trim().parse::<usize>().ok().filter(|&p| p > 0).ok_or_else(|| format!("bad monomer position in {text}"))?;
+    let attachment = attachment.trim();
+    let ok = attachment == "pair" || attachment.strip_prefix('R').is_some_and(|n| n.parse::<u8>().is_ok_and(|n| n > 0));
+    if !ok { return Err(format!("bad attachment point {attachment}; expected R1, R2 ... or pair")); }
+    Ok(End { polymer: polymer.trim().into(), position, attachment: attachment.into() })
+}
+
+/// Reads HELM 1 or 2, or `cyclo(...)`. Polymer groups, annotations and the version are
+/// accepted and ignored.
+pub fn parse(text: &str) -> Result<Helm, String> {
+    if text.trim_start().starts_with("cyclo(") { return cyclo(text); }
+    let sections: Vec<&str> = text.trim().split('$').collect();
+    let polymers = split_top(sections[0], '|').into_iter().filter(|p| !p.trim().is_empty()).map(|p| polymer(p.trim())).collect::<Result<Vec<_>, _>>()?;
+    if polymers.is_empty() { return Err("no polymers".into()); }
+    for (i, p) in polymers.iter().enumerate() {
+        if polymers[..i].iter().any(|q| q.id == p.id) { return Err(format!("polymer {} appears twice", p.id)); }
+    }
+    let mut connections = Vec::new();
+    for c in sections.get(1).map_or(Vec::new(), |s| split_top(s, '|')).into_iter().map(str::trim).filter(|c| !c.is_empty()) {
+        let parts: Vec<&str> = c.splitn(3, ',').collect();
+        let [from, to, ends] = parts[..] else { return Err(format!("connection {c} is not source,target,position:R-position:R")) };
+        let (a, b) = ends.split_once('-').ok_or_else(|| format!("connection {c} has no '-' between its ends"))?;
+        let connection = Connection { from: end(from, a)?, to: end(to, b)? };
+        for e in [&connection.from, &connection.to] {
+            let p = polymers.iter().find(|p| p.id == e.polymer).ok_or_else(|| format!("connection {c} names no polymer {}", e.polymer))?;
+            if e.position > p.units.len() { return Err(format!("{} has no monomer {}", p.id, e.position)); }
+        }
+        connections.push(connection);
+    }
+    Ok(Helm { polymers, connections })
+}
+
+impl fmt::Display for Helm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, p) in self.polymers.iter().enumerate() {
+            if i > 0 { f.write_str("|")?; }
+            write!(f, "{}{{", p.id)?;
+            for (j, u) in p.units.iter().enumerate() {
+                if j > 0 && !u.joined { f.write_str(".")?; }
+                let id = if p.kind == Kind::Blob || u.id.chars().count() == 1 { u.id.clone() } else { format!("[{}]", u.id) };
+                if u.branch { write!(f, "({id})")?; } else { f.write_str(&id)?; }
+            }
+            f.write_str("}")?;
+        }
+        f.write_str("$")?;
+        for (i, c) in self.connections.iter().enumerate() {
+            if i > 0 { f.write_str("|")?; }
+            write!(f, "{},{},{}:{}-{}:{}", c.from.polymer, c.to.polymer, c.from.position, c.from.attachment, c.to.position, c.to.attachment)?;
+        }
+        f.write_str("$$$V2.0")
+    }
+}
+
+impl Polymer {
+    /// The natural-analogue sequence: residues for peptides (`X` for non-natural ones), bases
+    /// for nucleic acids; empty for CHEM and BLOB.
+    pub fn sequence(&self) -> String {
+        match self.kind {
+            Kind::Peptide => self.units.iter().map(|u| monomer(Kind::Peptide, &u.id).map_or('X', |m| m.2)).filter(|&c| c != ' ').collect(),
+            Kind::Rna => self.units.iter().filter(|u| u.branch).map(|u| monomer(Kind::Rna, &u.id).map_or('N', |m| m.2)).collect(),
+            _ => String::new(),
+        }
+    }
+}
+
+/// A protein, RNA or DNA sequence as a single HELM polymer. Nucleic acids get a phosphate
+/// between nucleotides and none at either end; DNA uses deoxyribose.
+pub fn from_sequence(sequence: &str, kind: &str) -> Result<Helm, String> {
+    let seq: Vec<char> = sequence.chars().filter(|c| !c.is_whitespace()).map(|c| c.to_ascii_uppercase()).collect();
+    if seq.is_empty() { return Err("empty sequence".into()); }
+    let unit = |id: &str, branch, joined| Unit { id: id.into(), branch, joined };
+    let polymer = match kind {
+        "protein" | "peptide" => {
+            if let Some(c) = seq.iter().find(|c| !MONOMERS.iter().any(|m| m.kind == Kind::Peptide && m.id.len() == 1 && m.id.starts_with(**c))) { return Err(format!("'{c}' is not one of the 20 amino acids")); }
+            Polymer { id: "PEPTIDE1".into(), kind: Kind::Peptide, units: seq.iter().map(|c| unit(&c.to_string(), false, false)).collect() }
+        }
+        "rna" | "dna" => {
+            let (sugar, bases) = if kind == "dna" { ("dR", "ACGT") } else { ("R", "ACGU") };
+            if let Some(c) = seq.iter().find(|c| !bases.contains(**c)) { return Err(format!("'{c}' is not an {} base ({bases})", kind.to_uppercase())); }
+            let mut units = Vec::new();
+            for (i, c) in seq.iter().enumerate() {
+                units.extend([unit(sugar, false, false), unit(&c.to_string(), true, true)]);
+                if i + 1 < seq.len() { units.push(unit("P", false, true)); }
+            }
+            Polymer { id: "RNA1".into(), kind: Kind::Rna, units }
+        }
+        other => return Err(format!("unknown sequence type {other}; expected protein, rna or dna")),
+    };
+    Ok(Helm { polymers: vec![polymer], connections: Vec::new() })
+}
+
+/// Ring closures to add to a peptide: head-to-tail (last R2 to first R1), disulfides between
+/// thiol residues, and all-hydrocarbon staples between residues i and i+4 (two S5) or i and
+/// i+7 (R8 then S5). Positions are 1-based.
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct Cyclization { pub head_to_tail: bool, pub disulfides: Vec<[usize; 2]>, pub staples: Vec<[usize; 2]> }
+
+impl Cyclization {
+    pub fn is_empty(&self) -> bool { !self.head_to_tail && self.disulfides.is_empty() && self.staples.is_empty() }
+}
+
+/// Adds `c`'s ring closures to the first peptide in `helm`. Staple positions are replaced by
+/// the olefinic residues the staple is made from; returns the residues replaced, as written.
+pub fn cyclize(helm: &mut Helm, c: &Cyclization) -> Result<Vec<String>, String> {
+    if c.is_empty() { return Ok(Vec::new()); }
+    let p = helm.polymers.iter_mut().find(|p| p.kind == Kind::Peptide).ok_or("cyclization needs a peptide")?;
+    let n = p.units.len();
+    let id = p.id.clone();
+    let end = |position, attachment: &str| End { polymer: id.clone(), position, attachment: attachment.into() };
+    let mut connections = Vec::new();
+    if c.head_to_tail {
+        if n < 2 { return Err("head-to-tail cyclization needs at least two residues".into()); }
+        connections.push(Connection { from: end(n, "R2"), to: end(1, "R1") });
+    }
+    let mut used = Vec::new();
+    for &[i, j] in c.disulfides.iter().chain(&c.staples) {
+        if i == j || !(1..=n).contains(&i) || !(1..=n).contains(&j) { return Err(format!("{i}-{j} must be two different positions in 1..{n}")); }
+        if let Some(k) = [i, j].into_iter().find(|k| used.contains(k)) { return Err(format!("position {k} is in more than one bridge")); }
+        used.extend([i, j]);
+    }
+    for &[i, j] in &c.disulfides {
+        for k in [i, j] {
+            let thiol = monomer(Kind::Peptide, &p.units[k - 1].id).is_some_and(|(smiles, ..)| smiles.contains("S[*:3]"));
+            if !thiol { return Err(format!("position {k} ({}) has no thiol for a disulfide", p.units[k - 1].id)); }
+        }
+        connections.push(Connection { from: end(i, "R3"), to: end(j, "R3") });
+    }
+    let mut replaced = Vec::new();
+    for &[i, j] in &c.staples {
+        let (i, j) = (i.min(j), i.max(j));
+        let ends = match j - i { 4 => ["S5", "S5"], 7 => ["R8", "S5"], d => return Err(format!("staple {i}-{j} spans {d} residues; expected i,i+4 or i,i+7")) };
+        for (k, new) in [i, j].into_iter().zip(ends) {
+            let u = &mut p.units[k - 1];
+            if u.id != new { replaced.push(format!("{}{k}", u.id)); u.id = new.into(); }
+        }
+        connections.push(Connection { from: end(i, "R3"), to: end(j, "R3") });
+    }
+    helm.connections.extend(connections);
+    Ok(replaced)
+}
+
+/// One attachment point of a placed monomer: the placeholder atom, the atom it hangs from and
+/// its cap.
+struct Point { dummy: usize, atom: usize, kind: BondKind, cap: u8, taken: bool }
+/// Polymer index, monomer index and R number.
+type Site = (usize, usize, u8);
+
+/// The whole structure, with every monomer joined and free attachment points capped.
+pub fn molecule(helm: &Helm) -> Result<Molecule, String> {
+    let mut m = Molecule::default();
+    // Attachment points by (polymer, position), then R number.
+    let mut points: HashMap<(usize, usize), HashMap<u8, Point>> = HashMap::new();
+    let mut links: Vec<(Site, Site)> = Vec::new();
+    for (pi, p) in helm.polymers.iter().enumerate() {
+        if p.kind == Kind::Blob { return Err(format!("{} is a BLOB, which has no defined structure", p.id)); }
+        let mut last_backbone: Option<usize> = None;
+        for (ui, u) in p.units.iter().enumerate() {
+            let inline = u.id.contains("[*:");
+            let (smiles, caps) = if inline { (u.id.clone(), Vec::new()) } else {
+                let (smiles, caps, _) = monomer(p.kind, &u.id).ok_or_else(|| format!("unknown {} monomer {}", p.kind.name(), u.id))?;
+                (smiles, caps)
+            };
+            let placed = place(&mut m, &smiles).map_err(|e| format!("monomer {} of {}: {e}", u.id, p.id))?;
+            let mut at = HashMap::new();
+            for (r, dummy, atom, kind) in placed {
+                let cap = caps.get(r as usize - 1).copied().unwrap_or(b'H');
+                at.insert(r, Point { dummy, atom, kind, cap, taken: false });
+            }
+            points.insert((pi, ui), at);
+            if u.branch {
+                let b = last_backbone.ok_or_else(|| format!("branch {} of {} has no monomer to hang from", u.id, p.id))?;
+                links.push(((pi, b, 3), (pi, ui, 1)));
+            } else {
+                if let Some(prev) = last_backbone { links.push(((pi, prev, 2), (pi, ui, 1))); }
+                last_backbone = Some(ui);
+            }
+        }
+    }
+    for c in &helm.connections {
+        if c.from.attachment == "pair" || c.to.attachment == "pair" { continue; }
+        let index = |e: &End| helm.polymers.iter().position(|p| p.id == e.polymer).map(|pi| (pi, e.position - 1, e.attachment[1..].parse::<u8>().unwrap_or(0)));
+        let (Some(a), Some(b)) = (index(&c.from), index(&c.to)) else { return Err("a connection names an unknown polymer".into()) };
+        links.push((a, b));
+    }
+    let mut remove = vec![false; m.atoms.len()];
+    let name = |(pi, ui, r): Site| format!("{} monomer {} ({}) R{r}", helm.polymers[pi].id, ui + 1, helm.polymers[pi].units[ui].id);
+    for (a, b) in links {
+        let mut ends = [(0usize, BondKind::Single); 2];
+        for (k, e) in [a, b].into_iter().enumerate() {
+            let p = points.get_mut(&(e.0, e.1)).and_then(|at| at.get_mut(&e.2)).ok_or_else(|| format!("{} doesn't exist", name(e)))?;
+            if p.taken { return Err(format!("{} is bonded twice", name(e))); }
+            p.taken = true;
+            remove[p.dummy] = true;
+            ends[k] = (p.atom, p.kind);
+        }
+        if ends[0].0 == ends[1].0 { return Err(format!("{} would bond an atom to itself", name(a))); }
+        // Olefin ends (staples) only join each other, as ring-closing metathesis does.
+        if ends[0].1 != ends[1].1 { return Err(format!("{} and {} are different kinds of attachment point", name(a), name(b))); }
+        m.bonds.push(chem::Bond { a: ends[0].0, b: ends[1].0, kind: ends[0].1 });
+    }
+    for p in points.values().flat_map(|at| at.values()).filter(|p| !p.taken) {
+        match p.cap {
+            b'O' => { let d = &mut m.atoms[p.dummy]; d.element = "O".into(); d.isotope = None; d.hydrogens = 1; }
+            b'C' => { let d = &mut m.atoms[p.dummy]; d.element = "C".into(); d.isotope = None; d.hydrogens = 4 - p.kind.valence(); }
+            _ => { remove[p.dummy] = true; m.atoms[p.atom].hydrogens += p.kind.valence(); }
+        }
+    }
+    let mut index = vec![usize::MAX; m.atoms.len()];
+    let mut atoms = Vec::new();
+    for (i, a) in m.atoms.into_iter().enumerate() {
+        if !remove[i] { index[i] = atoms.len(); atoms.push(a); }
+    }
+    let bonds = m.bonds.into_iter().filter(|b| !remove[b.a] && !remove[b.b]).map(|b| chem::Bond { a: index[b.a], b: index[b.b], kind: b.kind }).collect();
+    Ok(Molecule { atoms, bonds })
+}
+
+/// Adds a monomer's atoms to `m`; returns its attachment points as (R number, placeholder atom,
+/// atom it hangs from, bond to it).
+fn place(m: &mut Molecule, smiles: &str) -> Result<Vec<(u8, usize, usize, BondKind)>, String> {
+    // `[*:n]` becomes `[nHe]`, a placeholder the SMILES reader takes.
+    let mut text = smiles.to_string();
+    for r in 1..=9 { text = text.replace(&format!("[*:{r}]"), &format!("[{r}He]")); }
+    if text.contains('*') { return Err("attachment points must be written [*:1] ... [*:9]".into()); }
+    let part = chem::parse_smiles(&text)?;
+    let offset = m.atoms.len();
+    let adj = part.neighbors();
+    let mut points: Vec<(u8, usize, usize, BondKind)> = Vec::new();
+    for (i, a) in part.atoms.iter().enumerate() {
+        if a.element != "He" { continue; }
+        let r = a.isotope.unwrap_or(0) as u8;
+        let [(atom, kind @ (BondKind::Single | BondKind::Double))] = adj[i][..] else { return Err(format!("R{r} must hang from one atom by a single or double bond")) };
+        if points.iter().any(|&(q, ..)| q == r) { return Err(format!("R{r} appears twice")); }
+        points.push((r, offset + i, offset + atom, kind));
+    }
+    m.atoms.extend(part.atoms);
+    m.bonds.extend(part.bonds.into_iter().map(|b| chem::Bond { a: b.a + offset, b: b.b + offset, kind: b.kind }));
+    Ok(points)
+}
+
+/// Canonical SMILES of a HELM string.
+pub fn smiles(text: &str) -> Result<String, String> { parse(text).and_then(|h| molecule(&h)).map(|m| m.to_canonical_smiles()) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dipeptide_gets_its_terminal_caps() {
+        assert_eq!(parse("PEPTIDE1{A.G}$$$$").unwrap().polymers[0].sequence(), "AG");
+        assert_eq!(smiles("PEPTIDE1{A.G}$$$$").unwrap(), chem::canonicalize("CC(N)C(=O)NCC(=O)O").unwrap());
+    }
+
+    #[test]
+    fn writes_helm2_back() {
+        let text = "PEPTIDE1{A.[Aib].G}$PEPTIDE1,PEPTIDE1,3:R2-1:R1$$$V2.0";
+        assert_eq!(parse(text).unwrap().to_string(), text);
+        assert_eq!(from_sequence("AU", "rna").unwrap().to_string(), "RNA1{R(A)P.R(U)}$$$$V2.0");
+    }
+
+    #[test]
+    fn cyclo_shorthand_is_head_to_tail() {
+        let helm = parse("cyclo(RGDfK)").unwrap();
+        assert_eq!(helm.to_string(), "PEPTIDE1{R.G.D.[dF].K}$PEPTIDE1,PEPTIDE1,5:R2-1:R1$$$V2.0");
+        assert_eq!(smiles("cyclo(-Arg-Gly-Asp-D-Phe-Lys-)").unwrap(), smiles("cyclo(RGDfK)").unwrap());
+    }
+
+    #[test]
+    fn malformed_helm_is_refused() {
+        assert!(parse("PEPTIDE1{A.G}$PEPTIDE1,PEPTIDE1,3:R2-1:R1$$$").is_err());
+        assert!(parse("PEPTIDE1{A.(G)}$$$$").is_err());
+        assert!(smiles("PEPTIDE1{A.[Xyz]}$$$$").is_err());
+    }
+}
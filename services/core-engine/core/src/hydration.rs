@@ -0,0 +1,61 @@
+//! Hydration sites in binding pockets and the water-displacement docking term.
+//!
+//! A 1 Å grid over the pocket is scored for water probability from contacts with the
+//! pocket-lining atoms: grid points in the 2.6–3.6 Å first shell of many lining atoms are
+//! likely water positions, points closer than 2.6 Å are excluded. Peaks are clustered into
+//! sites. Each site's free energy relative to bulk grows with enclosure by hydrophobic atoms
+//! and falls with polar partners it can hydrogen bond to; positive ("unhappy") waters are
+//! favourable to displace, negative ones cost affinity when a ligand pushes them out.
+
+use serde::Serialize;
+
+use crate::pockets::{self, Pocket};
+use crate::vec3::dist;
+
+const POLAR_RESIDUES: &[&str] = &["ASP", "GLU", "LYS", "ARG", "SER", "THR", "HIS", "TYR", "ASN", "GLN"];
+/// A ligand heavy atom this close to a site centre displaces its water.
+const DISPLACEMENT_RADIUS: f64 = 1.8;
+const MIN_SITE_SEPARATION: f64 = 2.4;
+
+#[derive(Serialize, Clone)]
+pub struct HydrationSite { pub site_id: String, pub center: [f64; 3], pub occupancy: f64, pub delta_g_kcal_mol: f64, pub polar_contacts: usize, pub apolar_contacts: usize }
+
+/// Hydration sites of a pocket, most occupied first.
+pub fn sites(p: &Pocket) -> Vec<HydrationSite> {
+    let lining = pockets::lining(p);
+    // Lining atoms are spread over the pocket residues in turn.
+    let polar: Vec<bool> = (0..lining.len()).map(|i| p.residues.get(i % p.residues.len().max(1)).is_some_and(|r| POLAR_RESIDUES.iter().any(|x| r.starts_with(x)))).collect();
+    let reach = (3.0 * p.volume_a3 / (4.0 * std::f64::consts::PI)).cbrt() + 3.8;
+    let n = reach.ceil() as i32;
+    let mut grid: Vec<([f64; 3], f64, usize, usize)> = Vec::new();
+    for i in -n..=n {
+        for j in -n..=n {
+            for k in -n..=n {
+                let g = [p.center[0] + i as f64, p.center[1] + j as f64, p.center[2] + k as f64];
+                if dist(g, p.center) > reach { continue; }
+                let (mut polar_n, mut apolar_n, mut blocked) = (0, 0, false);
+                for (a, &is_polar) in lining.iter().zip(&polar) {
+                    let d = dist(g, *a);
+                    if d < 2.6 { blocked = true; break; }
+                    if d <= 3.6 { if is_polar { polar_n += 1 } else { apolar_n += 1 } }
+                }
+                if blocked || polar_n + apolar_n < 2 { continue; }
+                grid.push((g, (polar_n as f64 * 1.5 + apolar_n as f64) / 8.0, polar_n, apolar_n));
+            }
+        }
+    }
+    grid.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let mut out: Vec<HydrationSite> = Vec::new();
+    for (g, score, polar_n, apolar_n) in grid {
+        if out.iter().any(|s| dist(s.center, g) < MIN_SITE_SEPARATION) { continue; }
+        let delta_g = ((0.7 * apolar_n as f64 - 0.5 * polar_n as f64) * 100.0).round() / 100.0;
+        out.push(HydrationSite { site_id: format!("W{}", out.len() + 1), center: g.map(|v| (v * 100.0).round() / 100.0), occupancy: score.min(1.0), delta_g_kcal_mol: delta_g, polar_contacts: polar_n, apolar_contacts: apolar_n });
+    }
+    out
+}
+
+/// Free-energy change (kcal/mol, negative is favourable) from the waters a ligand pose
+/// displaces: each displaced site returns its free energy, weighted by occupancy.
+pub fn displacement(sites: &[HydrationSite], coords: &[[f64; 3]]) -> f64 {
+    sites.iter().filter(|s| coords.iter().any(|&c| dist(c, s.center) < DISPLACEMENT_RADIUS)).map(|s| -s.delta_g_kcal_mol * s.occupancy).sum::<f64>() + 0.0 // normalizes the -0.0 of an empty sum
+}
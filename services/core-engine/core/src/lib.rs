@@ -0,0 +1,72 @@
+//! The engine's science, without the service around it.
+//!
+//! SMILES, HELM and structure-file parsing (`chem`, `helm`, `convert`, `smarts`),
+//! standardization, 2D depiction, descriptors and structural alerts; the force field
+//! (`forcefield`, `gaff`, `charges`), conformer embedding, restrained and staged MD, umbrella
+//! sampling, co-solvent probe mapping (`cosolvent`) and strain; docking, pockets, hydration and
+//! selectivity for screens, and the confidence of predicted models (`plddt`); the geometry and
+//! seeded random numbers they share (`vec3`, `rng`); and sequence prediction (`predict` with
+//! `antibody`, `glycosylation`, `ptm`, `topology`, `disorder`, `conservation` and `gene`).
+//! Everything here is synchronous and does no I/O beyond reading files it is pointed at; the
+//! `bio-engine` service adds the HTTP API, stores, jobs and network lookups on top.
+//!
+//! The default `host` feature brings in what needs an operating system: ids, logging, the
+//! alerts file and sequence prediction. Without it the crate has no platform dependencies and
+//! builds for `wasm32-unknown-unknown`; the `wasm` feature adds `wasm`, the JavaScript
+//! bindings the front-end's previews call.
+
+pub mod alerts;
+#[cfg(feature = "host")]
+pub mod antibody;
+pub mod charges;
+pub mod chem;
+pub mod cluster;
+pub mod cofactors;
+pub mod composition;
+pub mod conformer;
+#[cfg(feature = "host")]
+pub mod conservation;
+pub mod convert;
+pub mod cosolvent;
+pub mod cv;
+pub mod decompose;
+pub mod depict;
+pub mod descriptors;
+#[cfg(feature = "host")]
+pub mod disorder;
+pub mod filters;
+pub mod fingerprint;
+pub mod forcefield;
+pub mod frame;
+pub mod gaff;
+pub mod gene;
+pub mod glycosylation;
+pub mod helm;
+pub mod hydration;
+pub mod library;
+pub mod observables;
+pub mod pdbqt;
+pub mod plddt;
+pub mod pockets;
+pub mod poses;
+#[cfg(feature = "host")]
+pub mod predict;
+pub mod ptm;
+pub mod resolver;
+pub mod restraints;
+pub mod rng;
+pub mod schedule;
+pub mod selection;
+pub mod selectivity;
+pub mod smarts;
+pub mod stages;
+pub mod standardize;
+pub mod strain;
+pub mod topology;
+pub mod umbrella;
+pub mod vec3;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+/// FNV-1a, the engine's seed and cache-key hash; stable across builds and platforms.
+pub fn fnv1a(data: &[u8]) -> u64 { let mut h: u64 = 0xcbf2_9ce4_8422_2325; for &b in data { h ^= b as u64; h = h.wrapping_mul(0x0100_0000_01b3); } h }
@@ -0,0 +1,92 @@
+//! The built-in virtual screening library.
+//!
+//! Compound `ALICE-nnnnnn` is enumerated deterministically from its number as one of a set of
+//! drug-like scaffolds decorated with two R-groups, so every screening hit has a real
+//! structure that can be depicted, filtered and analysed.
+//!
+//! Fragment `FRAG-nnnn` comes from a separate, smaller library: a small core carrying one
+//! substituent, within the Rule of Three (at most 18 heavy atoms, three donors and three
+//! acceptors), most with a handle a fragment can be grown from.
+
+/// Scaffolds with two attachment points, `{1}` and `{2}`.
+pub const SCAFFOLDS: &[&str] = &[
+    "c1cc({1})ccc1{2}",
+    "c1cc({1})ncc1{2}",
+    "O=C(N{1})c1ccc({2})cc1",
+    "c1nc({1})sc1{2}",
+    "c1cc2cc({1})ccc2n1{2}",
+    "O=C1CN({1})CCN1{2}",
+    "c1ccc2c(c1)nc({1})n2{2}",
+    "C1CCN({1})CC1{2}",
+    "c1cc({1})oc1C(=O)N{2}",
+    "c1ncnc(N{1})c1{2}",
+    "O=S(=O)(N{1})c1ccc({2})cc1",
+    "c1cc(-c2ccc({1})cc2)ncc1{2}",
+];
+
+/// Substituents, including a few deliberately problematic ones (nitro, azo, Michael
+/// acceptor) so structural-alert checks have something to find. Their rings close on label 9
+/// so they never collide with a scaffold ring still open at the attachment point.
+pub const R_GROUPS: &[&str] = &[
+    "C", "CC", "OC", "F", "Cl", "C(F)(F)F", "N", "C(N)=O", "C#N", "O", "N(C)C", "C(=O)O",
+    "S(N)(=O)=O", "c9ccccc9", "N9CCOCC9", "C9CC9", "[N+](=O)[O-]", "N=Nc9ccccc9", "C=CC(=O)C", "Br",
+];
+
+pub const LIBRARY_SIZE: u64 = 999_999;
+/// Compound numbers this far apart have the same structure.
+pub const PERIOD: u64 = (SCAFFOLDS.len() * R_GROUPS.len() * R_GROUPS.len()) as u64;
+
+/// SMILES of library compound number `n`.
+pub fn compound(n: u64) -> String {
+    let scaffold = SCAFFOLDS[(n % SCAFFOLDS.len() as u64) as usize];
+    let r1 = R_GROUPS[((n / 12) % R_GROUPS.len() as u64) as usize];
+    let r2 = R_GROUPS[((n / 240) % R_GROUPS.len() as u64) as usize];
+    scaffold.replace("{1}", r1).replace("{2}", r2)
+}
+
+/// Library compound ID for number `n`.
+pub fn compound_id(n: u64) -> String { format!("ALICE-{:06}", n % LIBRARY_SIZE) }
+
+/// Fragment cores with one attachment point, `{1}`.
+pub const FRAGMENT_CORES: &[&str] = &[
+    "c1ccc({1})cc1",
+    "c1ccnc({1})c1",
+    "c1c({1})cn[nH]1",
+    "c1cc({1})c2cc[nH]c2c1",
+    "c1csc({1})n1",
+    "C1CNCC1{1}",
+    "NC1CCC({1})CC1",
+    "Nc1ccc({1})cc1",
+    "OC(=O)c1ccc({1})cc1",
+    "Brc1ccc({1})cn1",
+    "Nc1ncc({1})cn1",
+    "Oc1ccc({1})cc1",
+    "O=C1CCc2cc({1})ccc2N1",
+    "c1ccc2oc({1})cc2c1",
+    "OC(=O)C1CCC({1})CC1",
+    "Brc1cccc({1})c1",
+];
+
+/// Small fragment substituents.
+pub const FRAGMENT_GROUPS: &[&str] = &["C", "F", "Cl", "OC", "C#N", "C(F)(F)F", "CC", "C(N)=O", "O", "N(C)C", "C9CC9", "S(C)(=O)=O"];
+
+pub const FRAGMENT_LIBRARY_SIZE: u64 = 9_999;
+
+/// SMILES of fragment number `n`.
+pub fn fragment(n: u64) -> String {
+    let core = FRAGMENT_CORES[(n % FRAGMENT_CORES.len() as u64) as usize];
+    core.replace("{1}", FRAGMENT_GROUPS[((n / FRAGMENT_CORES.len() as u64) % FRAGMENT_GROUPS.len() as u64) as usize])
+}
+
+/// Fragment ID for number `n`.
+pub fn fragment_id(n: u64) -> String { format!("FRAG-{:04}", n % FRAGMENT_LIBRARY_SIZE) }
+
+/// SMILES for an `ALICE-nnnnnn` compound or `FRAG-nnnn` fragment ID.
+pub fn smiles_for(compound_id: &str) -> Option<String> {
+    if let Some(n) = compound_id.strip_prefix("FRAG-") {
+        let n: u64 = n.parse().ok()?;
+        return (n < FRAGMENT_LIBRARY_SIZE).then(|| fragment(n));
+    }
+    let n: u64 = compound_id.strip_prefix("ALICE-")?.parse().ok()?;
+    (n < LIBRARY_SIZE).then(|| compound(n))
+}
@@ -0,0 +1,162 @@
+//! Observables sampled along a `/simulate` run.
+//!
+//! Each observable is computed every `every` steps of the restrained Langevin run (see
+//! `restraints`) and returned as a time series with its summary statistics, so simple metrics
+//! need no trajectory post-processing. Groups of atoms are given as selections (see
+//! `selection`) and enter by their centroid: `distance`, `angle` and `dihedral` take two, three
+//! or four selections. `hbonds` counts donor–acceptor pairs (N or O, a donor carrying a
+//! hydrogen) at most 3.5 Å apart and at least three bonds apart, optionally donors from one
+//! selection and acceptors from another. `rmsd` and `radius_of_gyration` cover one selection
+//! (all heavy atoms by default), RMSD against the starting structure after superposition.
+//! `energy` reports a force field term: `internal`, `restraint`, or `interaction` between two
+//! selections. An observable with `above` or `below` raises an event each time its value
+//! crosses the threshold.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{chem::{self, Molecule}, conformer, cv::Cv, forcefield, frame::Frame, resolver, restraints::Restraints, selection};
+
+pub const KINDS: [&str; 7] = ["distance", "angle", "dihedral", "hbonds", "rmsd", "radius_of_gyration", "energy"];
+pub const TERMS: [&str; 3] = ["internal", "restraint", "interaction"];
+const DEFAULT_EVERY: usize = 100;
+const HBOND_DISTANCE: f64 = 3.5;
+/// Events kept per observable.
+const MAX_EVENTS: usize = 50;
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ObservableSpec { pub name: Option<String>, #[serde(rename = "type")] pub kind: String, #[serde(default)] pub selections: Vec<String>, pub term: Option<String>, pub every: Option<usize>, pub above: Option<f64>, pub below: Option<f64> }
+
+#[derive(Serialize, Clone)]
+pub struct Series { pub name: String, #[serde(rename = "type")] pub kind: &'static str, pub unit: &'static str, pub every: usize, pub steps: Vec<usize>, pub values: Vec<f64>, pub mean: f64, pub std_dev: f64, pub min: f64, pub max: f64 }
+
+#[derive(Serialize, Clone)]
+pub struct Event { pub observable: String, pub step: usize, pub value: f64, pub crossed: &'static str, pub threshold: f64 }
+
+#[derive(Serialize, Clone)]
+pub struct Observed { pub steps_sampled: usize, pub series: Vec<Series>, pub events: Vec<Event> }
+
+enum Kind { Geometry(Cv), Hbonds(Vec<(usize, usize)>), Rmsd, Gyration, Internal, Restraint, Interaction }
+
+struct Observable { name: String, kind: Kind, groups: Vec<Vec<usize>>, every: usize, above: Option<f64>, below: Option<f64>, steps: Vec<usize>, values: Vec<f64>, events: Vec<Event> }
+
+/// Samples observables step by step; empty when none were asked for.
+pub struct Recorder<'a> { observables: Vec<Observable>, bonded: Vec<conformer::Restraint>, restraints: &'a Restraints }
+
+fn centroid(x: &[[f64; 3]], atoms: &[usize]) -> [f64; 3] {
+    let mut c = [0.0; 3];
+    for &i in atoms { for k in 0..3 { c[k] += x[i][k] / atoms.len() as f64; } }
+    c
+}
+
+/// Donor–acceptor pairs in `donors` × `acceptors` at least three bonds apart.
+fn hbond_pairs(m: &Molecule, donors: &[usize], acceptors: &[usize]) -> Vec<(usize, usize)> {
+    let adj = m.neighbors();
+    let polar = |i: usize| matches!(m.atoms[i].element.as_str(), "N" | "O");
+    let near = |a: usize, b: usize| a == b || adj[a].iter().any(|&(n, _)| n == b || adj[n].iter().any(|&(o, _)| o == b));
+    let mut pairs = Vec::new();
+    for &d in donors.iter().filter(|&&d| polar(d) && m.atoms[d].hydrogens > 0) {
+        for &a in acceptors.iter().filter(|&&a| polar(a) && !(m.atoms[a].element == "N" && m.atoms[a].charge > 0)) {
+            if !near(d, a) && !pairs.contains(&(d, a)) { pairs.push((d, a)); }
+        }
+    }
+    pairs
+}
+
+impl<'a> Recorder<'a> {
+    pub fn new(specs: &[ObservableSpec], mol: &resolver::Resolved, restraints: &'a Restraints) -> Result<Self, String> {
+        if specs.is_empty() { return Ok(Self { observables: Vec::new(), bonded: Vec::new(), restraints }); }
+        let smiles = mol.canonical_smiles.as_deref().ok_or_else(|| format!("can't resolve {} to a structure to observe", mol.input))?;
+        let m = chem::parse_smiles(smiles)?;
+        let atoms = selection::molecule_atoms(&m, None);
+        let mut observables = Vec::with_capacity(specs.len());
+        for spec in specs {
+            let name = spec.name.clone().unwrap_or_else(|| if spec.selections.is_empty() { spec.kind.clone() } else { format!("{} {}", spec.kind, spec.selections.join(" | ")) });
+            let groups: Vec<Vec<usize>> = spec.selections.iter().map(|text| {
+                let g = selection::select(text, &atoms)?;
+                if g.is_empty() { return Err(format!("selection `{text}` of observable {name} selects no atoms")); }
+                Ok(g)
+            }).collect::<Result<_, String>>()?;
+            let want = |n: &[usize]| if n.contains(&groups.len()) { Ok(()) } else { Err(format!("a {} observable takes {} selections, not {}", spec.kind, n.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(" or "), groups.len())) };
+            let kind = match spec.kind.as_str() {
+                "distance" => { want(&[2])?; Kind::Geometry(Cv::Distance([0, 1])) }
+                "angle" => { want(&[3])?; Kind::Geometry(Cv::Angle([0, 1, 2])) }
+                "dihedral" | "torsion" => { want(&[4])?; Kind::Geometry(Cv::Dihedral([0, 1, 2, 3])) }
+                "hbonds" => {
+                    want(&[0, 1, 2])?;
+                    let all: Vec<usize> = (0..m.atoms.len()).collect();
+                    let (d, a) = match groups.as_slice() { [] => (&all, &all), [g] => (g, g), [d, a, ..] => (d, a) };
+                    Kind::Hbonds(hbond_pairs(&m, d, a))
+                }
+                "rmsd" => { want(&[0, 1])?; Kind::Rmsd }
+                "radius_of_gyration" => { want(&[0, 1])?; Kind::Gyration }
+                "energy" => match spec.term.as_deref().unwrap_or(TERMS[0]) {
+                    "internal" => { want(&[0])?; Kind::Internal }
+                    "restraint" => { want(&[0])?; Kind::Restraint }
+                    "interaction" => { want(&[2])?; Kind::Interaction }
+                    other => return Err(format!("unknown energy term {other}; expected one of {}", TERMS.join(", "))),
+                },
+                other => return Err(format!("unknown observable {other}; expected one of {}", KINDS.join(", "))),
+            };
+            let groups = if groups.is_empty() && matches!(kind, Kind::Rmsd | Kind::Gyration) { vec![(0..m.atoms.len()).collect()] } else { groups };
+            let every = spec.every.unwrap_or(DEFAULT_EVERY).max(1);
+            observables.push(Observable { name, kind, groups, every, above: spec.above, below: spec.below, steps: Vec::new(), values: Vec::new(), events: Vec::new() });
+        }
+        Ok(Self { observables, bonded: conformer::restraints(&m), restraints })
+    }
+
+    pub fn is_empty(&self) -> bool { self.observables.is_empty() }
+
+    /// Samples whatever is due at `step` (0-based) of coordinates `x` started from `start`.
+    pub fn observe(&mut self, step: usize, x: &[[f64; 3]], start: &[[f64; 3]]) {
+        for o in &mut self.observables {
+            if !(step + 1).is_multiple_of(o.every) { continue; }
+            let pick = |g: &[usize], x: &[[f64; 3]]| g.iter().map(|&i| x[i]).collect::<Vec<_>>();
+            let v = match &o.kind {
+                Kind::Geometry(cv) => cv.to_api(cv.value(&o.groups.iter().map(|g| centroid(x, g)).collect::<Vec<_>>())),
+                Kind::Hbonds(pairs) => pairs.iter().filter(|&&(d, a)| (0..3).map(|k| (x[d][k] - x[a][k]).powi(2)).sum::<f64>() <= HBOND_DISTANCE * HBOND_DISTANCE).count() as f64,
+                Kind::Rmsd => forcefield::fitted_rmsd(&pick(&o.groups[0], start), &pick(&o.groups[0], x)),
+                Kind::Gyration => { let g = &o.groups[0]; let c = centroid(x, g); (g.iter().map(|&i| (0..3).map(|k| (x[i][k] - c[k]).powi(2)).sum::<f64>()).sum::<f64>() / g.len() as f64).sqrt() }
+                Kind::Internal => forcefield::internal_energy(&self.bonded, x, None),
+                Kind::Restraint => self.restraints.energy(start, x, None),
+                Kind::Interaction => forcefield::interaction_energy(&Frame::new(&pick(&o.groups[0], x)), &pick(&o.groups[1], x), None),
+            };
+            if let Some(&last) = o.values.last() {
+                for (threshold, crossed) in [(o.above, "above"), (o.below, "below")] {
+                    let Some(t) = threshold else { continue };
+                    let was = if crossed == "above" { last > t } else { last < t };
+                    let is = if crossed == "above" { v > t } else { v < t };
+                    if is && !was && o.events.len() < MAX_EVENTS { o.events.push(Event { observable: o.name.clone(), step: step + 1, value: (v * 1e3).round() / 1e3, crossed, threshold: t }); }
+                }
+            }
+            o.steps.push(step + 1);
+            o.values.push(v);
+        }
+    }
+
+    pub fn finish(self, steps_sampled: usize) -> Option<Observed> {
+        if self.observables.is_empty() { return None; }
+        let round = |v: f64| (v * 1e3).round() / 1e3;
+        let mut events = Vec::new();
+        let series = self.observables.into_iter().map(|o| {
+            let n = o.values.len().max(1) as f64;
+            let mean = o.values.iter().sum::<f64>() / n;
+            let var = o.values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+            let (kind, unit) = match o.kind {
+                Kind::Geometry(Cv::Distance(_)) => ("distance", "angstrom"),
+                Kind::Geometry(Cv::Angle(_)) => ("angle", "degree"),
+                Kind::Geometry(Cv::Dihedral(_)) => ("dihedral", "degree"),
+                Kind::Hbonds(_) => ("hbonds", "count"),
+                Kind::Rmsd => ("rmsd", "angstrom"),
+                Kind::Gyration => ("radius_of_gyration", "angstrom"),
+                Kind::Internal | Kind::Restraint | Kind::Interaction => ("energy", "kcal/mol"),
+            };
+            events.extend(o.events);
+            Series {
+                name: o.name, kind, unit, every: o.every, steps: o.steps, mean: round(mean), std_dev: round(var.sqrt()),
+                min: round(o.values.iter().copied().fold(f64::INFINITY, f64::min)), max: round(o.values.iter().copied().fold(f64::NEG_INFINITY, f64::max)), values: o.values.into_iter().map(round).collect(),
+            }
+        }).collect();
+        events.sort_by_key(|e| e.step);
+        Some(Observed { steps_sampled, series, events })
+    }
+}
@@ -0,0 +1,440 @@
+//! AutoDock PDBQT preparation for ligands and receptors.
+//!
+//! A ligand keeps its polar hydrogens, takes Gasteiger charges computed with every hydrogen
+//! present (non-polar ones merged into their carbon) and becomes a torsion tree: rotatable
+//! bonds are the acyclic single bonds with another neighbour at both ends, except amide-like
+//! C–N bonds and bonds next to a triple bond. Cutting them leaves rigid fragments; the most
+//! central one is the `ROOT` and the rest hang off it as nested `BRANCH`es. `TORSDOF` leaves
+//! out torsions that only turn a hydrogen. A receptor keeps its first model without waters,
+//! alternate locations other than A, or hydrogens; amino acids get their polar hydrogens and
+//! formal charges from residue templates at pH 7 (histidine as the Nδ tautomer unless named
+//! HIE or HIP, and other states by name as `receptor` writes them), metal ions keep their charge
+//! and cofactors take theirs and their bond orders from `cofactors` templates; other hetero
+//! groups are kept only on request. `receptor` adds every hydrogen from the same templates.
+//! Flexible residues, named or holding an atom a `flexible_selection` picks (see `selection`),
+//! move from the rigid file to a flex file, each side chain a torsion tree rooted at Cα, as
+//! AutoDock Vina's `--flex` expects.
+
+use serde::Serialize;
+
+use crate::{chem::{self, Bond, BondKind, Molecule}, cofactors, convert::{self, Hydrogens}, selection};
+
+/// AutoDock 4 handles at most this many active torsions (Vina has no limit).
+pub const MAX_TORSIONS: usize = 32;
+pub const WATERS: [&str; 4] = ["HOH", "WAT", "DOD", "H2O"];
+/// Residues with a template: the amino acids, their titration states and the ACE and NME caps.
+pub const TEMPLATED: [&str; 32] = [
+    "ALA", "ARG", "ASN", "ASP", "CYS", "GLN", "GLU", "GLY", "HIS", "ILE", "LEU", "LYS", "MET", "PHE", "PRO", "SER", "THR", "TRP", "TYR", "VAL",
+    "HID", "HIE", "HIP", "CYX", "CYM", "ASH", "GLH", "LYN", "TYM", "AR0", "ACE", "NME",
+];
+/// Residues without a side-chain torsion to turn.
+const RIGID_RESIDUES: [&str; 5] = ["GLY", "ALA", "PRO", "ACE", "NME"];
+const BACKBONE: [&str; 5] = ["N", "CA", "C", "O", "OXT"];
+
+/// Rotatable bonds of a molecule with its polar hydrogens explicit.
+pub fn rotatable_bonds(mol: &Molecule) -> Vec<usize> {
+    let adj = mol.neighbors();
+    let triple = |i: usize| adj[i].iter().any(|&(_, k)| k == BondKind::Triple);
+    // C(=O/S/N)–N has partial double-bond character.
+    let amide_carbon = |i: usize| mol.atoms[i].element == "C" && adj[i].iter().any(|&(j, k)| k == BondKind::Double && matches!(mol.atoms[j].element.as_str(), "O" | "S" | "N"));
+    let amide = |a: usize, b: usize| amide_carbon(a) && mol.atoms[b].element == "N";
+    (0..mol.bonds.len()).filter(|&k| {
+        let Bond { a, b, kind } = mol.bonds[k];
+        kind == BondKind::Single && adj[a].len() > 1 && adj[b].len() > 1 && !triple(a) && !triple(b) && !amide(a, b) && !amide(b, a) && !mol.bond_in_ring(k)
+    }).collect()
+}
+
+/// One active torsion: the atom that stays, the atom that turns, their serials in the file,
+/// and whether any heavy atom beyond the turning one moves with it.
+pub struct Torsion { pub fixed: usize, pub moving: usize, pub serials: (usize, usize), pub heavy: bool }
+
+/// Rigid fragments joined by rotatable bonds, written depth-first.
+struct Tree<'a> {
+    fragments: Vec<Vec<usize>>,
+    /// Rotatable bonds as (fragment, fragment, atom, atom).
+    edges: Vec<(usize, usize, usize, usize)>,
+    heavy: Vec<bool>,
+    record: &'a dyn Fn(usize, usize) -> String,
+    serial: Vec<usize>,
+    next: usize,
+    heavy_written: usize,
+    done: Vec<bool>,
+    out: String,
+    torsions: Vec<Torsion>,
+}
+
+impl Tree<'_> {
+    fn neighbours(&self, f: usize) -> Vec<(usize, usize, usize)> {
+        let mut out: Vec<(usize, usize, usize)> = self.edges.iter().filter_map(|&(x, y, a, b)| if x == f { Some((y, a, b)) } else if y == f { Some((x, b, a)) } else { None }).collect();
+        out.sort_by_key(|&(_, a, b)| (a, b));
+        out
+    }
+
+    /// Atoms carried by the branch entering `into` from `from`.
+    fn branch_size(&self, from: usize, into: usize) -> usize {
+        let (mut seen, mut stack, mut size) = (vec![false; self.fragments.len()], vec![into], 0);
+        seen[from] = true;
+        seen[into] = true;
+        while let Some(f) = stack.pop() {
+            size += self.fragments[f].len();
+            for (g, _, _) in self.neighbours(f) { if !seen[g] { seen[g] = true; stack.push(g); } }
+        }
+        size
+    }
+
+    fn atoms(&mut self, f: usize, entry: Option<usize>) {
+        self.done[f] = true;
+        let order: Vec<usize> = entry.into_iter().chain(self.fragments[f].iter().copied().filter(|&i| Some(i) != entry)).collect();
+        for i in order {
+            self.serial[i] = self.next;
+            let line = (self.record)(self.next, i);
+            self.out.push_str(&line);
+            self.next += 1;
+            self.heavy_written += usize::from(self.heavy[i]);
+        }
+    }
+
+    fn branches(&mut self, f: usize) {
+        for (g, a, b) in self.neighbours(f) {
+            if self.done[g] { continue; }
+            let serials = (self.serial[a], self.next);
+            self.out.push_str(&format!("BRANCH {:>3} {:>3}\n", serials.0, serials.1));
+            let (k, before) = (self.torsions.len(), self.heavy_written);
+            self.torsions.push(Torsion { fixed: a, moving: b, serials, heavy: false });
+            self.atoms(g, Some(b));
+            self.branches(g);
+            self.torsions[k].heavy = self.heavy_written - before > 1;
+            self.out.push_str(&format!("ENDBRANCH {:>3} {:>3}\n", serials.0, serials.1));
+        }
+    }
+}
+
+/// Writes `subset` of the atoms as `ROOT`…`ENDROOT` and nested `BRANCH`/`ENDBRANCH` blocks.
+/// `record(serial, atom)` formats one atom; serials run from `first_serial` in output order,
+/// a branch's bonded atom first. The root is the fragment holding `root_atom`, or else the
+/// most central one (whose largest branch is smallest; ties go to the larger fragment). A
+/// disconnected structure puts the root fragment of every piece in the `ROOT`.
+pub fn torsion_tree(mol: &Molecule, subset: &[usize], rotatable: &[usize], root_atom: Option<usize>, first_serial: usize, record: &dyn Fn(usize, usize) -> String) -> (String, Vec<Torsion>) {
+    let n = mol.atoms.len();
+    let adj = mol.neighbors();
+    let mut member = vec![false; n];
+    for &i in subset { member[i] = true; }
+    let turns: Vec<(usize, usize)> = rotatable.iter().map(|&k| (mol.bonds[k].a, mol.bonds[k].b)).filter(|&(a, b)| member[a] && member[b]).collect();
+    let cut = |i: usize, j: usize| turns.contains(&(i, j)) || turns.contains(&(j, i));
+    let mut frag = vec![usize::MAX; n];
+    let mut fragments: Vec<Vec<usize>> = Vec::new();
+    for &s in subset {
+        if frag[s] != usize::MAX { continue; }
+        let mut atoms = vec![s];
+        frag[s] = fragments.len();
+        let mut k = 0;
+        while k < atoms.len() {
+            let i = atoms[k];
+            for &(j, _) in &adj[i] { if member[j] && frag[j] == usize::MAX && !cut(i, j) { frag[j] = fragments.len(); atoms.push(j); } }
+            k += 1;
+        }
+        atoms.sort_unstable();
+        fragments.push(atoms);
+    }
+    let edges = turns.iter().map(|&(a, b)| (frag[a], frag[b], a, b)).collect();
+    let count = fragments.len();
+    let mut tree = Tree { fragments, edges, heavy: mol.atoms.iter().map(|a| a.element != "H").collect(), record, serial: vec![0; n], next: first_serial, heavy_written: 0, done: vec![false; count], out: String::new(), torsions: Vec::new() };
+    // Each connected piece in turn: its fragments, then its root.
+    let mut piece = vec![usize::MAX; count];
+    let mut roots = Vec::new();
+    for f in 0..count {
+        if piece[f] != usize::MAX { continue; }
+        let mut members = vec![f];
+        piece[f] = roots.len();
+        let mut k = 0;
+        while k < members.len() {
+            for (g, _, _) in tree.neighbours(members[k]) { if piece[g] == usize::MAX { piece[g] = roots.len(); members.push(g); } }
+            k += 1;
+        }
+        let root = root_atom.map(|a| frag[a]).filter(|r| members.contains(r)).unwrap_or_else(|| *members.iter().min_by_key(|&&m| {
+            let largest = tree.neighbours(m).iter().map(|&(g, _, _)| tree.branch_size(m, g)).max().unwrap_or(0);
+            (largest, std::cmp::Reverse(tree.fragments[m].len()))
+        }).unwrap_or(&f));
+        roots.push(root);
+    }
+    tree.out.push_str("ROOT\n");
+    for &r in &roots { tree.atoms(r, None); }
+    tree.out.push_str("ENDROOT\n");
+    for &r in &roots { tree.branches(r); }
+    (tree.out, tree.torsions)
+}
+
+/// Ligand PDBQT with its torsion tree; `mol` has its polar hydrogens explicit.
+pub fn write_ligand(name: &str, mol: &Molecule, coords: &[[f64; 3]], charges: &[f64]) -> (String, Vec<Torsion>) {
+    let adj = mol.neighbors();
+    let names = convert::atom_names(mol);
+    let types: Vec<String> = (0..mol.atoms.len()).map(|i| convert::ad_type(mol, &adj, i)).collect();
+    let record = |serial: usize, i: usize| convert::pdbqt_atom(serial, &names[i], ("LIG", 'L', 1), coords[i], charges[i], &types[i]);
+    let all: Vec<usize> = (0..mol.atoms.len()).collect();
+    let (tree, torsions) = torsion_tree(mol, &all, &rotatable_bonds(mol), None, 1, &record);
+    let mut out = format!("REMARK  Name = {name}\n{}", torsion_remarks(&torsions, &names));
+    out.push_str(&tree);
+    out.push_str(&format!("TORSDOF {}\n", torsions.iter().filter(|t| t.heavy).count()));
+    (out, torsions)
+}
+
+fn torsion_remarks(torsions: &[Torsion], names: &[String]) -> String {
+    let mut out = format!("REMARK  {} active torsions:\nREMARK  status: ('A' for Active; 'I' for Inactive)\n", torsions.len());
+    for (k, t) in torsions.iter().enumerate() {
+        out.push_str(&format!("REMARK  {:>3}  A    between atoms: {}_{}  and  {}_{}\n", k + 1, names[t.fixed].trim(), t.serials.0, names[t.moving].trim(), t.serials.1));
+    }
+    out
+}
+
+/// A receptor residue as read: its atoms are (raw four-character name, element, position).
+pub struct Residue { pub name: String, pub chain: char, pub number: i64, pub insertion: char, pub hetero: bool, pub atoms: Vec<(String, String, [f64; 3])> }
+
+impl Residue {
+    pub fn label(&self) -> String { format!("{}:{}{}{}", self.chain, self.name, self.number, self.insertion).replace(' ', "") }
+}
+
+/// Heavy atoms of the first model, grouped by residue, without alternate locations other than
+/// A, and without waters unless `waters` is set.
+pub fn parse_residues(text: &str, waters: bool) -> Vec<Residue> {
+    let mut out: Vec<Residue> = Vec::new();
+    for l in text.lines() {
+        if l.starts_with("ENDMDL") { break; }
+        let hetero = l.starts_with("HETATM");
+        if !(hetero || l.starts_with("ATOM")) || !matches!(l.get(16..17), Some(" " | "A") | None) { continue; }
+        let (Some(raw), Some(name)) = (l.get(12..16), l.get(17..20).map(str::trim)) else { continue };
+        if !waters && WATERS.contains(&name) { continue; }
+        let f = |a: usize, b: usize| l.get(a..b).and_then(|v| v.trim().parse::<f64>().ok());
+        let (Some(x), Some(y), Some(z)) = (f(30, 38), f(38, 46), f(46, 54)) else { continue };
+        let element = convert::element(l.get(76..78).map(str::trim).filter(|e| !e.is_empty())
+            .unwrap_or_else(|| raw.trim().trim_start_matches(|c: char| c.is_ascii_digit()).get(..1).unwrap_or("")));
+        if element == "H" || element == "D" || element.is_empty() { continue; }
+        let chain = l.get(21..22).and_then(|c| c.chars().next()).unwrap_or(' ');
+        let number = l.get(22..26).and_then(|v| v.trim().parse::<i64>().ok()).unwrap_or(0);
+        let insertion = l.get(26..27).and_then(|c| c.chars().next()).unwrap_or(' ');
+        let same = out.last().is_some_and(|r| r.chain == chain && r.number == number && r.insertion == insertion && r.name == name);
+        if !same { out.push(Residue { name: name.into(), chain, number, insertion, hetero, atoms: Vec::new() }); }
+        if let Some(r) = out.last_mut() { r.atoms.push((format!("{raw:<4}"), element, [x, y, z])); }
+    }
+    out
+}
+
+/// Whether free chain termini carry their charge: an NH3+ N-terminus and a COO- C-terminus.
+#[derive(Clone, Copy)]
+pub struct Termini { pub n_charged: bool, pub c_charged: bool }
+
+impl Termini {
+    /// Both termini charged, as at pH 7.
+    pub const CHARGED: Termini = Termini { n_charged: true, c_charged: true };
+}
+
+/// Polar hydrogens and formal charge of an amino-acid atom. Residue names give the titration
+/// state (ASH, GLH, LYN, CYM, TYM and AR0 the neutralized or deprotonated forms, HID, HIE and
+/// HIP the histidines); `n_terminal` marks a free amine, charged as `termini` says, and
+/// `bridged` a cysteine sulfur in a disulfide.
+fn template(residue: &str, atom: &str, n_terminal: bool, bridged: bool, termini: Termini) -> (u8, i8) {
+    match (residue, atom) {
+        ("PRO", "N") if n_terminal => if termini.n_charged { (2, 1) } else { (1, 0) },
+        ("PRO", "N") => (0, 0),
+        (_, "N") if n_terminal => if termini.n_charged { (3, 1) } else { (2, 0) },
+        (_, "N") => (1, 0),
+        (_, "OXT") => if termini.c_charged { (0, -1) } else { (1, 0) },
+        ("ASP", "OD2") | ("GLU", "OE2") | ("CYM", "SG") | ("TYM", "OH") => (0, -1),
+        ("ASH", "OD2") | ("GLH", "OE2") | ("AR0", "NH1") => (1, 0),
+        ("HIP", "NE2") => (1, 1),
+        ("ARG", "NH1") => (2, 1),
+        ("SER", "OG") | ("THR", "OG1") | ("TYR", "OH") | ("TRP", "NE1") | ("ARG" | "AR0", "NE") | ("HIS" | "HID" | "HIP", "ND1") | ("HIE", "NE2") => (1, 0),
+        ("CYS", "SG") => (u8::from(!bridged), 0),
+        ("ASN", "ND2") | ("GLN", "NE2") | ("ARG" | "AR0", "NH2") | ("LYN", "NZ") => (2, 0),
+        ("LYS", "NZ") => (3, 1),
+        _ => (0, 0),
+    }
+}
+
+/// Ring carbons that take part in double bonds to other carbons.
+fn aromatic_carbon(residue: &str, atom: &str) -> bool {
+    match residue {
+        "PHE" | "TYR" | "TYM" => matches!(atom, "CG" | "CD1" | "CD2" | "CE1" | "CE2" | "CZ"),
+        "TRP" => matches!(atom, "CG" | "CD1" | "CD2" | "CE2" | "CE3" | "CZ2" | "CZ3" | "CH2"),
+        "HIS" | "HID" | "HIE" | "HIP" => matches!(atom, "CG" | "CD2" | "CE1"),
+        _ => false,
+    }
+}
+
+/// PDB name of the `k`-th of `count` hydrogens on `parent`: " H" on N, "HG" on OG, HH11 and
+/// HH12 on NH1.
+fn hydrogen_name(parent: &str, k: usize, count: usize) -> String {
+    let p = parent.trim();
+    let mut name = format!("H{}", p.get(1..).unwrap_or(""));
+    if count > 1 { name.push_str(&(k + 1).to_string()); }
+    if name.len() < 4 { format!(" {name}") } else { name.chars().take(4).collect() }
+}
+
+/// A receptor with its hydrogens placed. The heavy atoms come first, in residue order, and the
+/// hydrogens after them; `owner` is each atom's residue, `names` its PDB name and `hydrogens`
+/// the hydrogens on each heavy atom.
+pub struct Protonated { pub ex: convert::Explicit, pub heavy: usize, pub owner: Vec<usize>, pub names: Vec<String>, pub hydrogens: Vec<Vec<usize>>, pub templated: Vec<bool>, pub net_charge: i32 }
+
+/// Gives the residues their polar hydrogens and formal charges from the templates, metal ions
+/// their ion charge and other groups default valences, orders the bonds and places the
+/// `hydrogens` asked for.
+pub fn protonate(residues: &[Residue], termini: Termini, hydrogens: Hydrogens, warnings: &mut Vec<String>) -> Result<Protonated, String> {
+    let owner: Vec<usize> = residues.iter().enumerate().flat_map(|(k, r)| std::iter::repeat_n(k, r.atoms.len())).collect();
+    let raw: Vec<&str> = residues.iter().flat_map(|r| r.atoms.iter().map(|a| a.0.as_str())).collect();
+    let mut atoms: Vec<(String, i8)> = residues.iter().flat_map(|r| r.atoms.iter().map(|a| (a.1.clone(), 0))).collect();
+    let coords: Vec<[f64; 3]> = residues.iter().flat_map(|r| r.atoms.iter().map(|a| a.2)).collect();
+    let n = atoms.len();
+    if n == 0 { return Err("receptor has no ATOM or HETATM records".into()); }
+    if n > convert::MAX_ATOMS { return Err(format!("receptor has {n} atoms; at most {} are supported", convert::MAX_ATOMS)); }
+    let metal: Vec<Option<&cofactors::Metal>> = atoms.iter().map(|a| cofactors::metal(&a.0)).collect();
+    let templated: Vec<bool> = owner.iter().zip(&metal).map(|(&r, m)| m.is_none() && TEMPLATED.contains(&residues[r].name.as_str())).collect();
+    let bonds: Vec<Bond> = convert::bonds_by_distance(&atoms, &coords).into_iter().filter(|b| metal[b.a].is_none() && metal[b.b].is_none()).collect();
+    // Cofactors take charges, hydrogens and bond orders from their templates.
+    let (mut cofactor, mut cofactor_bonds) = (vec![None; n], vec![None; bonds.len()]);
+    let mut untemplated = cofactors::unparameterized(residues);
+    let mut start = 0;
+    for r in residues {
+        let range = start..start + r.atoms.len();
+        start = range.end;
+        let Some(c) = cofactors::cofactor(&r.name) else { continue };
+        let local: Vec<usize> = range.filter(|&i| metal[i].is_none()).collect();
+        let inner: Vec<usize> = (0..bonds.len()).filter(|&k| local.contains(&bonds[k].a) && local.contains(&bonds[k].b)).collect();
+        let index = |i: usize| local.iter().position(|&l| l == i).unwrap_or(0);
+        let local_bonds: Vec<Bond> = inner.iter().map(|&k| Bond { a: index(bonds[k].a), b: index(bonds[k].b), kind: BondKind::Single }).collect();
+        let elements: Vec<String> = local.iter().map(|&i| atoms[i].0.clone()).collect();
+        match cofactors::assign(c, &elements, &local_bonds) {
+            Some((states, kinds)) => {
+                for (&i, state) in local.iter().zip(states) { cofactor[i] = Some(state); }
+                for (&k, kind) in inner.iter().zip(kinds) { cofactor_bonds[k] = Some(kind); }
+            }
+            None => { warnings.push(format!("{} doesn't match the {} template atom for atom", r.label(), c.name)); if !untemplated.contains(&r.name) { untemplated.push(r.name.clone()); } }
+        }
+    }
+    if !untemplated.is_empty() { warnings.push(format!("{} have no residue template; their bonds are single and hydrogens follow default valences", untemplated.join(", "))); }
+    let name = |i: usize| raw[i].trim();
+    let bonded = |i: usize, test: &dyn Fn(usize) -> bool| bonds.iter().any(|b| (b.a == i && test(b.b)) || (b.b == i && test(b.a)));
+    // Polar hydrogens from the templates; metals carry their ion charge.
+    let mut polar = vec![0u8; n];
+    for i in 0..n {
+        if let Some(m) = metal[i] { atoms[i].1 = m.charge; continue; }
+        if let Some((q, _)) = cofactor[i] { atoms[i].1 = q; continue; }
+        if !templated[i] { continue; }
+        let n_terminal = name(i) == "N" && !bonded(i, &|j| name(j) == "C" && owner[j] != owner[i]);
+        let bridged = name(i) == "SG" && bonded(i, &|j| name(j) == "SG");
+        let residue = if residues[owner[i]].name == "CYX" { "CYS" } else { residues[owner[i]].name.as_str() };
+        (polar[i], atoms[i].1) = template(residue, name(i), n_terminal, bridged, termini);
+    }
+    // Bond orders for the amino acids, from the template hydrogens as stand-in atoms.
+    let mut table = atoms.clone();
+    let mut protein: Vec<Bond> = bonds.iter().copied().filter(|b| templated[b.a] && templated[b.b]).collect();
+    for (i, &h) in polar.iter().enumerate() {
+        for _ in 0..h { protein.push(Bond { a: i, b: table.len(), kind: BondKind::Single }); table.push(("H".into(), 0)); }
+    }
+    let free: Vec<bool> = (0..table.len()).map(|i| i < n && aromatic_carbon(&residues[owner[i]].name, name(i))).collect();
+    convert::assign_orders(&table, Some(&free), &mut protein);
+    let other = bonds.iter().zip(&cofactor_bonds).filter(|(b, _)| !(templated[b.a] && templated[b.b])).map(|(b, kind)| Bond { kind: kind.unwrap_or(b.kind), ..*b });
+    let graph_bonds: Vec<Bond> = protein.into_iter().filter(|b| b.a < n && b.b < n).chain(other).collect();
+    let graph_atoms = (0..n).map(|i| {
+        let hydrogens = match cofactor[i] { Some((_, h)) => Some(h), None if metal[i].is_some() || (templated[i] && atoms[i].0 != "C") => Some(polar[i]), None => None };
+        (atoms[i].0.clone(), atoms[i].1, hydrogens)
+    }).collect();
+    let mol = chem::from_graph(graph_atoms, graph_bonds)?;
+    let ex = convert::explicit(&mol, &coords, hydrogens, true)?;
+    let adj = ex.mol.neighbors();
+    // Every atom's residue and name; hydrogens follow their parent.
+    let mut owner = owner;
+    let mut names: Vec<String> = raw.iter().map(|r| r.to_string()).collect();
+    let mut on: Vec<Vec<usize>> = vec![Vec::new(); n];
+    for (i, h) in on.iter_mut().enumerate() { h.extend(adj[i].iter().map(|&(j, _)| j).filter(|&j| j >= n)); }
+    owner.resize(ex.mol.atoms.len(), 0);
+    names.resize(ex.mol.atoms.len(), String::new());
+    for i in 0..n {
+        for (k, &h) in on[i].iter().enumerate() { owner[h] = owner[i]; names[h] = hydrogen_name(raw[i], k, on[i].len()); }
+    }
+    Ok(Protonated { net_charge: mol.atoms.iter().map(|a| a.charge as i32).sum(), ex, heavy: n, owner, names, hydrogens: on, templated })
+}
+
+/// Matches a flexible-residue spec ("A:TYR22", "A:22", "TYR22" or "A:TYR:22").
+pub fn matches_spec(spec: &str, r: &Residue) -> bool {
+    let parts: Vec<&str> = spec.split(':').map(str::trim).collect();
+    let (chain, rest) = match parts.as_slice() {
+        [c, rest @ ..] if !rest.is_empty() && c.chars().count() == 1 => (c.chars().next(), rest.concat()),
+        _ => (None, parts.concat()),
+    };
+    let split = rest.find(|c: char| c.is_ascii_digit() || c == '-').unwrap_or(rest.len());
+    let (name, number) = rest.split_at(split);
+    chain.is_none_or(|c| c.eq_ignore_ascii_case(&r.chain)) && (name.is_empty() || name.eq_ignore_ascii_case(&r.name)) && number.parse::<i64>().ok() == Some(r.number)
+}
+
+#[derive(Serialize)]
+pub struct ReceptorPdbqt { atoms: usize, residues: usize, flexible: Vec<String>, net_charge: i32, rigid: String, #[serde(skip_serializing_if = "Option::is_none")] flex: Option<String> }
+
+pub fn prepare_receptor(text: &str, flexible: &[String], flexible_selection: Option<&str>, keep_hetero: bool, warnings: &mut Vec<String>) -> Result<ReceptorPdbqt, String> {
+    let mut dropped: Vec<String> = Vec::new();
+    let residues: Vec<Residue> = parse_residues(text, false).into_iter().filter(|r| {
+        let keep = !r.hetero || keep_hetero || TEMPLATED.contains(&r.name.as_str()) || cofactors::is_ion(r) || cofactors::cofactor(&r.name).is_some();
+        if !keep && !dropped.contains(&r.name) { dropped.push(r.name.clone()); }
+        keep
+    }).collect();
+    if !dropped.is_empty() { warnings.push(format!("dropped hetero groups {}; set keep_hetero to keep them", dropped.join(", "))); }
+    let p = protonate(&residues, Termini::CHARGED, Hydrogens::Polar, warnings)?;
+    let (ex, n, owner, names) = (&p.ex, p.heavy, &p.owner, &p.names);
+    let charges = ex.charges.clone().unwrap_or_else(|| vec![0.0; ex.mol.atoms.len()]);
+    let adj = ex.mol.neighbors();
+    let name = |i: usize| names[i].trim();
+    let types: Vec<String> = (0..ex.mol.atoms.len()).map(|i| convert::ad_type(&ex.mol, &adj, i)).collect();
+    let record = |serial: usize, i: usize| {
+        let r = &residues[owner[i]];
+        let line = convert::pdbqt_atom(serial, &names[i], (&r.name, r.chain, r.number), ex.coords[i], charges[i], &types[i]);
+        if r.hetero { line.replacen("ATOM  ", "HETATM", 1) } else { line }
+    };
+    let movable = |r: &Residue| !RIGID_RESIDUES.contains(&r.name.as_str()) && TEMPLATED.contains(&r.name.as_str());
+    // Flexible side chains.
+    let mut flex_residues = Vec::new();
+    for spec in flexible {
+        let r = residues.iter().position(|r| matches_spec(spec, r)).ok_or_else(|| format!("flexible residue {spec} is not in the receptor"))?;
+        if !movable(&residues[r]) { warnings.push(format!("{} has no side-chain torsions and stays rigid", residues[r].label())); continue; }
+        if !flex_residues.contains(&r) { flex_residues.push(r); }
+    }
+    if let Some(text) = flexible_selection {
+        let atoms: Vec<selection::Atom> = (0..n).map(|i| {
+            let r = &residues[owner[i]];
+            selection::Atom { name: name(i).into(), element: ex.mol.atoms[i].element.clone(), aromatic: aromatic_carbon(&r.name, name(i)), resname: r.name.clone(), resid: r.number, chain: r.chain, protein: p.templated[i], coords: Some(ex.coords[i]) }
+        }).collect();
+        let picked = selection::select(text, &atoms)?;
+        if picked.is_empty() { warnings.push(format!("flexible_selection `{text}` selects no atoms")); }
+        for i in picked {
+            let r = owner[i];
+            if !flex_residues.contains(&r) && movable(&residues[r]) { flex_residues.push(r); }
+        }
+    }
+    let side_chain = |i: usize| flex_residues.contains(&owner[i]) && !BACKBONE.contains(&name(i));
+    let mut rigid = String::new();
+    let mut serial = 1;
+    for i in (0..n).filter(|&i| !side_chain(i)) {
+        for a in std::iter::once(i).chain(p.hydrogens[i].iter().copied()) { rigid.push_str(&record(serial, a)); serial += 1; }
+    }
+    let mut flex = String::new();
+    let mut flexible_labels = Vec::new();
+    if !flex_residues.is_empty() {
+        let rotatable = rotatable_bonds(&ex.mol);
+        let mut serial = 1;
+        for &r in &flex_residues {
+            let res = &residues[r];
+            let Some(ca) = (0..n).find(|&i| owner[i] == r && name(i) == "CA") else { warnings.push(format!("{} has no CA and stays rigid", res.label())); continue };
+            let subset: Vec<usize> = std::iter::once(ca).chain((0..n).filter(|&i| side_chain(i) && owner[i] == r)).flat_map(|i| std::iter::once(i).chain(p.hydrogens[i].iter().copied())).collect();
+            let (tree, torsions) = torsion_tree(&ex.mol, &subset, &rotatable, Some(ca), serial, &record);
+            serial += subset.len();
+            let tag = format!("{} {}{:>4}", res.name, res.chain, res.number);
+            flex.push_str(&format!("BEGIN_RES {tag}\n{}{tree}END_RES {tag}\n", torsion_remarks(&torsions, names)));
+            flexible_labels.push(res.label());
+        }
+    }
+    Ok(ReceptorPdbqt {
+        atoms: ex.mol.atoms.len(),
+        residues: residues.len(),
+        net_charge: p.net_charge,
+        flex: (!flexible_labels.is_empty()).then_some(flex),
+        flexible: flexible_labels,
+        rigid,
+    })
+}
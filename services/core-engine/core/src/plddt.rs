@@ -0,0 +1,122 @@
+//! Per-residue confidence of predicted structures.
+//!
+//! Predicted models, AlphaFold's and the service's homology models, carry each residue's
+//! confidence (pLDDT, 0–100) in the B-factor column. `read` takes it from each residue's Cα, and
+//! `apply` groups the residues below a cutoff into low-confidence regions and applies a `Policy`
+//! to them before a structure is docked into or simulated: `exclude` drops their atoms, so a
+//! floppy tail can't form a pocket or block one; `restrain` keeps them, with flat-bottomed
+//! position restraints in `/simulate`'s form whose allowed displacement grows as confidence
+//! falls, so they relax without drifting through the rest of the structure; `keep` changes
+//! nothing. Files that aren't recognisably predicted are left alone unless a policy is asked
+//! for, since a crystal structure's B-factors are not confidences.
+
+use serde::Serialize;
+use std::collections::HashSet;
+
+use crate::restraints::RestraintSpec;
+
+pub const DEFAULT_CUTOFF: f64 = 70.0;
+/// Force constant (kcal/mol/Å²) of the restraints on low-confidence regions.
+const RESTRAINT_K: f64 = 2.0;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Policy { Keep, Exclude, Restrain }
+
+impl Policy {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name { "keep" => Ok(Self::Keep), "exclude" => Ok(Self::Exclude), "restrain" => Ok(Self::Restrain), other => Err(format!("unknown low_confidence {other}; expected exclude, restrain or keep")) }
+    }
+    pub fn name(self) -> &'static str { match self { Self::Keep => "keep", Self::Exclude => "exclude", Self::Restrain => "restrain" } }
+}
+
+/// A residue's Cα confidence.
+pub struct Residue { pub chain: char, pub number: i64, pub insertion: char, pub name: String, pub plddt: f64 }
+
+/// A run of consecutive residues below the cutoff.
+#[derive(Serialize, Clone)]
+pub struct Region { pub chain: String, pub start: i64, pub end: i64, pub residues: usize, pub mean_plddt: f64 }
+
+#[derive(Serialize)]
+pub struct Report {
+    pub policy: &'static str, pub cutoff: f64, pub mean_plddt: f64, pub low_confidence_residues: usize, pub regions: Vec<Region>,
+    #[serde(skip_serializing_if = "Vec::is_empty")] pub restraints: Vec<RestraintSpec>,
+}
+
+fn round(v: f64) -> f64 { (v * 100.0).round() / 100.0 }
+
+/// Whether a PDB file is a predicted model with pLDDT B-factors: an AlphaFold entry, or a model
+/// whose header says so.
+pub fn predicted(text: &str) -> bool {
+    text.lines().take_while(|l| !l.starts_with("ATOM") && !l.starts_with("HETATM")).any(|l| {
+        let u = l.to_uppercase();
+        (u.starts_with("TITLE") || u.starts_with("HEADER") || u.starts_with("REMARK")) && (u.contains("ALPHAFOLD") || u.contains("PLDDT"))
+    })
+}
+
+/// Each residue's pLDDT, from the B-factor of its Cα in the first model.
+pub fn read(text: &str) -> Vec<Residue> {
+    let mut out = Vec::new();
+    for l in text.lines() {
+        if l.starts_with("ENDMDL") { break; }
+        if !l.starts_with("ATOM") || l.get(12..16).map(str::trim) != Some("CA") || !matches!(l.get(16..17), Some(" " | "A")) { continue; }
+        let (Some(number), Some(plddt)) = (l.get(22..26).and_then(|v| v.trim().parse().ok()), l.get(60..66).and_then(|v| v.trim().parse().ok())) else { continue };
+        let at = |i: usize| l.get(i..i + 1).and_then(|c| c.chars().next()).unwrap_or(' ');
+        out.push(Residue { chain: at(21), number, insertion: at(26), name: l.get(17..20).unwrap_or("").trim().to_string(), plddt });
+    }
+    out
+}
+
+/// Runs of consecutive residues (in file order, within a chain) below `cutoff`.
+pub fn regions(residues: &[Residue], cutoff: f64) -> Vec<Region> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < residues.len() {
+        if residues[i].plddt >= cutoff { i += 1; continue; }
+        let start = i;
+        while i < residues.len() && residues[i].plddt < cutoff && residues[i].chain == residues[start].chain { i += 1; }
+        let run = &residues[start..i];
+        out.push(Region { chain: run[0].chain.to_string().trim().to_string(), start: run[0].number, end: run[run.len() - 1].number, residues: run.len(), mean_plddt: round(run.iter().map(|r| r.plddt).sum::<f64>() / run.len() as f64) });
+    }
+    out
+}
+
+/// The structure with `low_confidence` (or, for a predicted model, `default`) applied, and what
+/// was done; an unrecognised structure with no policy asked for comes back unchanged with no
+/// report.
+pub fn apply(text: &str, low_confidence: Option<&str>, cutoff: Option<f64>, default: Policy) -> Result<(String, Option<Report>), String> {
+    let policy = match low_confidence {
+        Some(name) => Policy::parse(name)?,
+        None if predicted(text) => default,
+        None => return Ok((text.to_string(), None)),
+    };
+    let cutoff = cutoff.unwrap_or(DEFAULT_CUTOFF);
+    if !(0.0..=100.0).contains(&cutoff) { return Err(format!("plddt_cutoff {cutoff} is outside 0–100")); }
+    let residues = read(text);
+    if residues.is_empty() { return Err("the structure has no protein Cα atoms to read pLDDT from".into()); }
+    let low: HashSet<(char, i64, char)> = residues.iter().filter(|r| r.plddt < cutoff).map(|r| (r.chain, r.number, r.insertion)).collect();
+    let regions = regions(&residues, cutoff);
+    let mut report = Report {
+        policy: policy.name(), cutoff, mean_plddt: round(residues.iter().map(|r| r.plddt).sum::<f64>() / residues.len() as f64), low_confidence_residues: low.len(), regions, restraints: Vec::new(),
+    };
+    let text = match policy {
+        Policy::Keep => text.to_string(),
+        Policy::Exclude => {
+            if low.len() == residues.len() { return Err(format!("every residue is below the pLDDT cutoff of {cutoff}; nothing would be left")); }
+            let at = |l: &str, i: usize| l.get(i..i + 1).and_then(|c| c.chars().next()).unwrap_or(' ');
+            text.lines().filter(|l| {
+                if !["ATOM", "ANISOU", "TER"].iter().any(|r| l.starts_with(r)) { return true; }
+                let number = l.get(22..26).and_then(|v| v.trim().parse::<i64>().ok());
+                !number.is_some_and(|n| low.contains(&(at(l, 21), n, at(l, 26))))
+            }).collect::<Vec<_>>().join("\n") + "\n"
+        }
+        Policy::Restrain => {
+            report.restraints = report.regions.iter().map(|r| RestraintSpec {
+                kind: "position".into(), atoms: None, residues: None, value: None, lower: None,
+                selection: Some(if r.chain.is_empty() { format!("resid {}-{}", r.start, r.end) } else { format!("chain {} and resid {}-{}", r.chain, r.start, r.end) }),
+                upper: Some(round(1.0 + (cutoff - r.mean_plddt) / 10.0)), force_constant: Some(RESTRAINT_K),
+            }).collect();
+            text.to_string()
+        }
+    };
+    Ok((text, Some(report)))
+}
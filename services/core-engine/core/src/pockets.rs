@@ -0,0 +1,191 @@
+//! Binding-pocket detection on target structures.
+
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::{fnv1a, pdbqt::Residue};
+
+#[derive(Serialize, Clone)]
+pub struct Pocket { pub pocket_id: String, pub center: [f64; 3], pub volume_a3: f64, pub druggability: f64, pub residues: Vec<String> }
+
+/// Grid spacing, Å, of the geometric detector.
+const SPACING: f64 = 1.0;
+/// Grid points within this distance of a heavy atom are protein: a probe centre can't sit there.
+const PROTEIN_RADIUS: f64 = 3.0;
+/// Scan lines through each empty point, and how far along them, Å, protein is looked for.
+const SCAN_LINES: [[isize; 3]; 7] = [[1, 0, 0], [0, 1, 0], [0, 0, 1], [1, 1, 1], [1, 1, -1], [1, -1, 1], [-1, 1, 1]];
+const SCAN: f64 = 8.0;
+/// Scan lines with protein at both ends a point needs to be buried.
+const MIN_BURIED: usize = 5;
+/// Buried points a cluster needs to count as a pocket.
+const MIN_POINTS: usize = 30;
+/// Atoms within this distance of a pocket point line it.
+const LINING: f64 = 4.5;
+
+/// The pocket `k` hashes to, for the pseudo-pockets of targets without a structure.
+fn hashed(k: u64, i: usize) -> Pocket {
+    let center = [(k % 400) as f64 * 0.1 - 20.0, ((k >> 12) % 400) as f64 * 0.1 - 20.0, ((k >> 24) % 400) as f64 * 0.1 - 20.0];
+    let residues = (0..6).map(|j| format!("{}{}", ["LEU", "VAL", "PHE", "TYR", "ASP", "LYS", "SER", "HIS"][((k >> (j * 3)) % 8) as usize], 20 + (k >> (j * 5)) % 300)).collect();
+    Pocket { pocket_id: format!("P{}", i + 1), center, volume_a3: 150.0 + ((k >> 32) % 850) as f64, druggability: 0.2 + ((k >> 40) % 80) as f64 * 0.01, residues }
+}
+
+/// Sorts pockets most druggable first and numbers them in that order.
+fn rank(mut pockets: Vec<Pocket>) -> Vec<Pocket> {
+    pockets.sort_by(|a, b| b.druggability.total_cmp(&a.druggability));
+    for (i, p) in pockets.iter_mut().enumerate() { p.pocket_id = format!("P{}", i + 1); }
+    pockets
+}
+
+/// Candidate pockets for a target, most druggable first.
+pub fn detect(target: &str) -> Vec<Pocket> {
+    let h = fnv1a(target.as_bytes());
+    let count = 3 + (h % 3) as usize;
+    rank((0..count).map(|i| hashed(h.rotate_left(i as u32 * 13) ^ (i as u64), i)).collect())
+}
+
+/// A pseudo-trajectory of a target's pockets over `frames` frames, for targets without one. The
+/// pockets of `detect` breathe about their volume; one to three cryptic pockets, closed in the
+/// first frame, open and close as a two-state chain that is open for 5–45% of the frames.
+pub fn ensemble(target: &str, frames: usize) -> Vec<Vec<Pocket>> {
+    let base = detect(target);
+    let h = fnv1a(format!("{target}/cryptic").as_bytes());
+    let cryptic: Vec<(Pocket, f64)> = (0..1 + (h % 3) as usize).map(|i| {
+        let k = h.rotate_left(i as u32 * 17) ^ (i as u64 + 101);
+        (hashed(k, i), 0.05 + ((k >> 48) % 41) as f64 * 0.01)
+    }).collect();
+    let uniform = |f: usize, what: &str, i: usize| (fnv1a(format!("{target}/{f}/{what}/{i}").as_bytes()) % 10_000) as f64 / 10_000.0;
+    let mut open = vec![false; cryptic.len()];
+    (0..frames).map(|f| {
+        let mut pockets: Vec<Pocket> = base.iter().enumerate().map(|(i, p)| {
+            let u = uniform(f, "base", i);
+            Pocket { volume_a3: (p.volume_a3 * (0.75 + 0.5 * u)).round(), druggability: ((p.druggability + 0.1 * (u - 0.5)) * 100.0).round().clamp(0.0, 100.0) / 100.0, ..p.clone() }
+        }).collect();
+        for (i, (p, occupancy)) in cryptic.iter().enumerate() {
+            // Closing at 0.2 a frame, and opening at the rate that keeps the chain open `occupancy` of the time.
+            let u = uniform(f, "cryptic", i);
+            open[i] = f > 0 && if open[i] { u >= 0.2 } else { u < 0.2 * occupancy / (1.0 - occupancy) };
+            if open[i] { pockets.push(Pocket { volume_a3: (p.volume_a3 * (0.6 + 0.6 * uniform(f, "volume", i))).round(), ..p.clone() }); }
+        }
+        rank(pockets)
+    }).collect()
+}
+
+/// The grid point `d` steps from `g`, if inside `dims`.
+fn step(g: [usize; 3], d: [isize; 3], dims: [usize; 3]) -> Option<[usize; 3]> {
+    let mut out = [0; 3];
+    for k in 0..3 { out[k] = g[k].checked_add_signed(d[k]).filter(|&v| v < dims[k])?; }
+    Some(out)
+}
+
+/// Pockets of a structure, found on a grid as LIGSITE does: empty points with protein at both
+/// ends of most scan lines through them, in connected clusters. Druggability weighs a pocket's
+/// volume, how buried it is and how apolar its lining atoms are. Hetero groups are left out, so
+/// a bound ligand's site reads as a pocket. Most druggable first.
+pub fn detect_in(residues: &[Residue]) -> Vec<Pocket> {
+    let atoms: Vec<(usize, bool, [f64; 3])> = residues.iter().enumerate().filter(|(_, r)| !r.hetero)
+        .flat_map(|(i, r)| r.atoms.iter().map(move |(_, e, p)| (i, e == "C" || e == "S", *p))).collect();
+    if atoms.is_empty() { return Vec::new(); }
+    let (mut lo, mut hi) = ([f64::INFINITY; 3], [f64::NEG_INFINITY; 3]);
+    for (_, _, p) in &atoms { for k in 0..3 { (lo[k], hi[k]) = (lo[k].min(p[k]), hi[k].max(p[k])); } }
+    let dims: [usize; 3] = std::array::from_fn(|k| ((hi[k] - lo[k]) / SPACING) as usize + 1);
+    let index = |g: [usize; 3]| (g[0] * dims[1] + g[1]) * dims[2] + g[2];
+    let position = |g: [usize; 3]| -> [f64; 3] { std::array::from_fn(|k| lo[k] + g[k] as f64 * SPACING) };
+    let cell = |p: [f64; 3]| -> [usize; 3] { std::array::from_fn(|k| ((p[k] - lo[k]) / SPACING).round().max(0.0) as usize) };
+    let dist2 = |a: [f64; 3], b: [f64; 3]| (0..3).map(|k| (a[k] - b[k]).powi(2)).sum::<f64>();
+    let total = dims.iter().product();
+
+    let mut protein = vec![false; total];
+    let reach = (PROTEIN_RADIUS / SPACING).ceil() as isize;
+    let cube: Vec<[isize; 3]> = (-reach..=reach).flat_map(|x| (-reach..=reach).flat_map(move |y| (-reach..=reach).map(move |z| [x, y, z]))).collect();
+    for (_, _, p) in &atoms {
+        for g in cube.iter().filter_map(|&d| step(cell(*p), d, dims)) {
+            if dist2(position(g), *p) <= PROTEIN_RADIUS * PROTEIN_RADIUS { protein[index(g)] = true; }
+        }
+    }
+
+    // Buriedness of each empty point: scan lines with protein within SCAN both ways.
+    let mut buried = vec![0u8; total];
+    let grid = (0..dims[0]).flat_map(|x| (0..dims[1]).flat_map(move |y| (0..dims[2]).map(move |z| [x, y, z])));
+    for g in grid.clone().filter(|&g| !protein[index(g)]) {
+        let hits = |d: [isize; 3]| {
+            let steps = (SCAN / (SPACING * ((d[0] * d[0] + d[1] * d[1] + d[2] * d[2]) as f64).sqrt())) as isize;
+            (1..=steps).map_while(|n| step(g, [d[0] * n, d[1] * n, d[2] * n], dims)).any(|q| protein[index(q)])
+        };
+        buried[index(g)] = SCAN_LINES.iter().filter(|d| hits(**d) && hits([-d[0], -d[1], -d[2]])).count() as u8;
+    }
+
+    // Connected clusters of buried points, each lined by the atoms near it.
+    let mut cells: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+    for (a, (_, _, p)) in atoms.iter().enumerate() { cells.entry(p.map(|v| (v / LINING).floor() as i64)).or_default().push(a); }
+    let mut seen = vec![false; total];
+    let mut pockets = Vec::new();
+    for start in grid {
+        if seen[index(start)] || (buried[index(start)] as usize) < MIN_BURIED { continue; }
+        seen[index(start)] = true;
+        let (mut cluster, mut queue) = (Vec::new(), vec![start]);
+        while let Some(g) = queue.pop() {
+            cluster.push(g);
+            for d in [[1, 0, 0], [-1, 0, 0], [0, 1, 0], [0, -1, 0], [0, 0, 1], [0, 0, -1]] {
+                let Some(q) = step(g, d, dims) else { continue };
+                if !seen[index(q)] && buried[index(q)] as usize >= MIN_BURIED { seen[index(q)] = true; queue.push(q); }
+            }
+        }
+        if cluster.len() < MIN_POINTS { continue; }
+        let mut lining: Vec<usize> = Vec::new();
+        for &g in &cluster {
+            let p = position(g);
+            let c = p.map(|v| (v / LINING).floor() as i64);
+            for d in cube.iter().filter(|d| d.iter().all(|v| v.abs() <= 1)) {
+                let Some(near) = cells.get(&[c[0] + d[0] as i64, c[1] + d[1] as i64, c[2] + d[2] as i64]) else { continue };
+                lining.extend(near.iter().filter(|&&a| dist2(atoms[a].2, p) <= LINING * LINING));
+            }
+        }
+        lining.sort_unstable();
+        lining.dedup();
+        let mut lined: Vec<usize> = lining.iter().map(|&a| atoms[a].0).collect();
+        lined.sort_unstable();
+        lined.dedup();
+        let n = cluster.len() as f64;
+        let volume = n * SPACING.powi(3);
+        let burial = cluster.iter().map(|&g| buried[index(g)] as f64).sum::<f64>() / n / SCAN_LINES.len() as f64;
+        let apolar = lining.iter().filter(|&&a| atoms[a].1).count() as f64 / lining.len().max(1) as f64;
+        let min_burial = MIN_BURIED as f64 / SCAN_LINES.len() as f64;
+        let druggability = 0.35 * (volume / 600.0).min(1.0) + 0.35 * ((burial - min_burial) / (1.0 - min_burial)).clamp(0.0, 1.0) + 0.3 * ((apolar - 0.5) / 0.3).clamp(0.0, 1.0);
+        let center: [f64; 3] = std::array::from_fn(|k| (cluster.iter().map(|&g| position(g)[k]).sum::<f64>() / n * 100.0).round() / 100.0);
+        let residues = lined.iter().map(|&r| format!("{}{}", residues[r].name, residues[r].number)).collect();
+        pockets.push(Pocket { pocket_id: String::new(), center, volume_a3: volume.round(), druggability: (druggability * 100.0).round() / 100.0, residues });
+    }
+    rank(pockets)
+}
+
+/// Pseudo-receptor heavy atoms lining a pocket, for targets without a structure: a shell of
+/// atoms about 3.8 Å outside the pocket radius, open toward one side as the pocket mouth.
+pub fn lining(p: &Pocket) -> Vec<[f64; 3]> {
+    let radius = (3.0 * p.volume_a3 / (4.0 * std::f64::consts::PI)).cbrt() + 3.8;
+    let count = (4.0 * std::f64::consts::PI * radius * radius / 10.0) as usize;
+    let golden = std::f64::consts::PI * (3.0 - 5f64.sqrt());
+    (0..count).filter_map(|i| {
+        let z = 1.0 - 2.0 * (i as f64 + 0.5) / count as f64;
+        if z > 0.7 { return None; }
+        let r = (1.0 - z * z).sqrt();
+        let phi = golden * i as f64;
+        Some([p.center[0] + radius * r * phi.cos(), p.center[1] + radius * r * phi.sin(), p.center[2] + radius * z])
+    }).collect()
+}
+
+/// Each lining residue with the lining atom that stands for it, spread evenly over the shell.
+pub fn residue_sites(p: &Pocket) -> Vec<(String, [f64; 3])> {
+    let shell = lining(p);
+    if shell.is_empty() { return Vec::new(); }
+    p.residues.iter().enumerate().map(|(j, r)| (r.clone(), shell[j * shell.len() / p.residues.len()])).collect()
+}
+
+/// Formal charge of a residue at pH 7 (Asp/Glu -1, Lys/Arg +1).
+pub fn residue_charge(residue: &str) -> f64 {
+    match residue.get(..3) { Some("ASP" | "GLU") => -1.0, Some("LYS" | "ARG") => 1.0, _ => 0.0 }
+}
+
+/// Formal charges of the pocket's ionizable residues, each placed on a lining atom.
+pub fn charged_sites(p: &Pocket) -> Vec<([f64; 3], f64)> {
+    residue_sites(p).into_iter().map(|(r, c)| (c, residue_charge(&r))).filter(|&(_, q)| q != 0.0).collect()
+}
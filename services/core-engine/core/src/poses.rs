@@ -0,0 +1,68 @@
+//! Docking poses of screening hits.
+//!
+//! Every hit of a screen gets a 3D pose placed in the target's most druggable pocket, exported
+//! as MDL SDF (V2000) or PDB `HETATM` records so it loads directly into modeling tools.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{chem::{self, BondKind, Molecule}, conformer, convert, fnv1a, pockets};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Pose { pub compound_id: String, pub smiles: String, pub target: String, pub pocket_id: String, pub binding_affinity_nm: f64, pub coords: Vec<[f64; 3]> }
+
+pub fn dock(target: &str, pocket: Option<&pockets::Pocket>, compound_id: &str, smiles: &str) -> Option<Pose> {
+    let mol = chem::parse_smiles(smiles).ok()?;
+    let seed = fnv1a(format!("{target}/{compound_id}").as_bytes());
+    let mut coords = conformer::embed(&mol, seed);
+    conformer::place(&mut coords, seed, pocket.map_or([0.0; 3], |p| p.center));
+    Some(Pose { compound_id: compound_id.into(), smiles: smiles.into(), target: target.into(), pocket_id: pocket.map_or_else(String::new, |p| p.pocket_id.clone()), binding_affinity_nm: 0.0, coords })
+}
+
+pub fn to_sdf(p: &Pose, mol: &Molecule) -> String {
+    let mut props = vec![("compound_id", p.compound_id.clone())];
+    if p.binding_affinity_nm > 0.0 { props.push(("binding_affinity_nm", p.binding_affinity_nm.to_string())); }
+    convert::write_sdf(&p.compound_id, &format!("{} docked into {} pocket {}", p.smiles, p.target, p.pocket_id), mol, &p.coords, &props).unwrap_or_default()
+}
+
+pub fn to_pdb(p: &Pose, mol: &Molecule) -> String {
+    convert::write_pdb(&[format!("COMPND    {} {}", p.compound_id, p.smiles), format!("REMARK   1 DOCKED INTO {} POCKET {}", p.target, p.pocket_id)], mol, &p.coords)
+}
+
+/// Reads the first record of an SDF / molfile (V2000). Explicit hydrogens are folded into
+/// their heavy atom's hydrogen count and dropped from the coordinates.
+pub fn parse_sdf(text: &str) -> Result<(String, Molecule, Vec<[f64; 3]>), String> {
+    let lines: Vec<&str> = text.lines().collect();
+    let counts = lines.get(3).ok_or("molfile is missing its counts line")?;
+    let field = |l: &str, a: usize, b: usize| l.get(a..b.min(l.len())).map(str::trim).unwrap_or("").to_string();
+    let n: usize = field(counts, 0, 3).parse().map_err(|_| "bad atom count")?;
+    let m: usize = field(counts, 3, 6).parse().map_err(|_| "bad bond count")?;
+    if lines.len() < 4 + n + m { return Err("molfile is truncated".into()); }
+    let mut atoms = Vec::new();
+    let mut coords = Vec::new();
+    for l in &lines[4..4 + n] {
+        let xyz: Result<Vec<f64>, _> = [(0, 10), (10, 20), (20, 30)].iter().map(|&(a, b)| field(l, a, b).parse::<f64>()).collect();
+        let xyz = xyz.map_err(|_| format!("bad atom line: {l}"))?;
+        let charge = match field(l, 36, 39).parse::<i8>().unwrap_or(0) { c @ 1..=3 => 4 - c, c @ 5..=7 => 4 - c, _ => 0 };
+        atoms.push((field(l, 31, 34), charge));
+        coords.push([xyz[0], xyz[1], xyz[2]]);
+    }
+    let mut bonds = Vec::new();
+    for l in &lines[4 + n..4 + n + m] {
+        let a: usize = field(l, 0, 3).parse().map_err(|_| format!("bad bond line: {l}"))?;
+        let b: usize = field(l, 3, 6).parse().map_err(|_| format!("bad bond line: {l}"))?;
+        let kind = match field(l, 6, 9).as_str() { "2" => BondKind::Double, "3" => BondKind::Triple, "4" => BondKind::Aromatic, _ => BondKind::Single };
+        if a == 0 || b == 0 || a > n || b > n { return Err(format!("bond {a}-{b} references a missing atom")); }
+        bonds.push(chem::Bond { a: a - 1, b: b - 1, kind });
+    }
+    // `M  CHG` lines override the atom-block charges.
+    for l in lines[4 + n + m..].iter().take_while(|l| !l.starts_with("M  END")) {
+        if let Some(rest) = l.strip_prefix("M  CHG") {
+            let v: Vec<i64> = rest.split_whitespace().filter_map(|t| t.parse().ok()).collect();
+            for pair in v.get(1..).unwrap_or(&[]).chunks(2) {
+                if let [idx, chg] = pair { if let Some(a) = atoms.get_mut((*idx as usize).wrapping_sub(1)) { a.1 = *chg as i8; } }
+            }
+        }
+    }
+    let (mol, coords) = convert::fold_hydrogens(&atoms, &coords, &bonds, false)?;
+    Ok((lines[0].trim().to_string(), mol, coords))
+}
@@ -0,0 +1,96 @@
+//! Sequence-to-structure prediction.
+//!
+//! `prepare` validates a request (swapping nucleotide input for its longest ORF's protein) and
+//! `run` assembles the report: domains, antibody numbering, glycosylation, PTMs, topology,
+//! disorder and, given an alignment, conservation. UniProt annotations are fetched by the
+//! caller and passed in as `curated`, and so is the template of a `homology` prediction, whose
+//! model the service builds alongside.
+
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+use crate::{antibody, conservation, disorder, fnv1a, gene, glycosylation, ptm, topology};
+
+#[derive(Deserialize, Default)]
+pub struct PredictRequest { pub sequence: String, pub prediction_type: Option<String>, pub numbering: Option<String>, pub glycans: Option<String>, pub uniprot_accession: Option<String>, pub msa: Option<String>, pub sequence_type: Option<String>, pub min_orf_length: Option<usize>, pub template: Option<String>, pub template_id: Option<String>, pub template_chain: Option<String> }
+#[derive(Serialize)]
+pub struct PredictResponse { pub prediction_id: String, sequence_length: usize, prediction_type: String, pub structure_confidence: f64, sdf_representation_bytes: u64, secondary_structure: String, domains: Vec<DomainInfo>, #[serde(skip_serializing_if = "Option::is_none")] antibody: Option<antibody::AntibodyReport>, #[serde(skip_serializing_if = "Option::is_none")] glycosylation: Option<glycosylation::GlycosylationReport>, ptm: ptm::PtmReport, topology: topology::TopologyReport, folding_state: String, pub disorder: disorder::DisorderReport, #[serde(skip_serializing_if = "Option::is_none")] conservation: Option<conservation::ConservationReport>, #[serde(skip_serializing_if = "Option::is_none")] gene: Option<GeneReport>, elapsed_us: u128 }
+#[derive(Serialize)]
+pub struct GeneReport { pub molecule: &'static str, length: usize, gc_content: f64, pub orfs: Vec<OrfPrediction> }
+/// The primary (longest) ORF's prediction is the response itself.
+#[derive(Serialize)]
+pub struct OrfPrediction { #[serde(flatten)] pub orf: gene::Orf, pub primary: bool, #[serde(skip_serializing_if = "Option::is_none")] pub prediction: Option<Box<PredictResponse>> }
+#[derive(Serialize)]
+pub struct DomainInfo { pub name: String, pub start: usize, pub end: usize, pub domain_type: String, pub confidence: f64 }
+
+/// Validates a prediction request; nucleotide input is swapped for its longest ORF's protein,
+/// with all its ORFs returned alongside.
+pub fn prepare(mut req: PredictRequest) -> Result<(PredictRequest, Option<gene::Gene>), String> {
+    let gene = if gene::is_nucleotide(&req.sequence, req.sequence_type.as_deref())? {
+        let gene = gene::find_orfs(&req.sequence, req.min_orf_length.unwrap_or(gene::DEFAULT_MIN_ORF))?;
+        req.sequence = gene.orfs[0].protein.clone();
+        Some(gene)
+    } else {
+        None
+    };
+    if let Some(name) = req.glycans.as_ref().filter(|n| !glycosylation::known_template(n)) {
+        return Err(format!("unknown glycan template {name}; expected one of {}", glycosylation::template_names()));
+    }
+    if let Some(msa) = &req.msa { conservation::parse(msa, &req.sequence)?; }
+    let templated = req.template.is_some() || req.template_id.is_some();
+    match req.prediction_type.as_deref() {
+        Some("homology") if !templated => return Err("homology modeling needs a template structure (template) or PDB ID (template_id)".into()),
+        Some("homology") => {}
+        _ if templated => return Err("template and template_id are only used with prediction_type homology".into()),
+        _ => {}
+    }
+    Ok((req, gene))
+}
+
+pub fn run(req: PredictRequest, gene: Option<gene::Gene>, curated: Option<Vec<ptm::Annotation>>) -> PredictResponse {
+    let t = Instant::now();
+    let pred_type = req.prediction_type.unwrap_or_else(|| "structure".into());
+    let seq_len = req.sequence.len();
+    let h = fnv1a(req.sequence.as_bytes());
+    let confidence = 0.70 + (h % 25) as f64 * 0.01;
+    let glycosylation = glycosylation::predict(&req.sequence, req.glycans.as_deref());
+    let glycan_residues: u32 = glycosylation.sites.iter().filter_map(|g| g.glycan.as_ref()).map(|g| g.residues).sum();
+    let sdf_bytes = (seq_len as u64 + glycan_residues as u64) * 128; // SDF representation, glycans included
+    // The other ORFs of a gene are predicted with the same options, minus the ones tied to
+    // the primary chain (UniProt entry, alignment).
+    let gene = gene.map(|g| {
+        let orfs = g.orfs.into_iter().enumerate().map(|(k, orf)| {
+            let prediction = (k > 0 && k < gene::MAX_PREDICTED).then(|| {
+                let sub = PredictRequest { sequence: orf.protein.clone(), prediction_type: Some(pred_type.clone()), numbering: req.numbering.clone(), glycans: req.glycans.clone(), uniprot_accession: None, msa: None, sequence_type: Some("protein".into()), min_orf_length: None, template: None, template_id: None, template_chain: None };
+                Box::new(run(sub, None, None))
+            });
+            OrfPrediction { orf, primary: k == 0, prediction }
+        }).collect();
+        GeneReport { molecule: g.molecule, length: g.length, gc_content: g.gc_content, orfs }
+    });
+    let scheme = req.numbering.unwrap_or_else(|| "kabat".into());
+    let variable = antibody::analyze(&req.sequence, &scheme);
+    let (mut domains, antibody) = if variable.is_empty() {
+        (vec![
+            DomainInfo { name: "kinase_domain".into(), start: 0, end: seq_len / 3, domain_type: "catalytic".into(), confidence },
+            DomainInfo { name: "binding_domain".into(), start: seq_len / 3, end: seq_len * 2 / 3, domain_type: "regulatory".into(), confidence },
+        ], None)
+    } else {
+        (antibody::domains(&variable, seq_len, confidence), Some(antibody::AntibodyReport { scheme, domains: variable }))
+    };
+    // prepare has already checked the alignment.
+    let conservation = req.msa.as_deref().and_then(|m| conservation::parse(m, &req.sequence).ok()).map(|rows| conservation::analyze(&rows));
+    if let Some(c) = &conservation { conservation::weight_domains(&mut domains, c); }
+    let ptm = ptm::annotate(&req.sequence, req.uniprot_accession, curated);
+    let topology = topology::predict(&req.sequence);
+    let disorder = disorder::predict(&req.sequence);
+    let domains = disorder::trim_domains(domains, &disorder);
+    PredictResponse { prediction_id: uuid::Uuid::new_v4().to_string(), sequence_length: seq_len, prediction_type: pred_type, structure_confidence: confidence, sdf_representation_bytes: sdf_bytes, secondary_structure: "HHHHCCCEEEEECCCHHHHH".into(), domains, antibody, glycosylation: (!glycosylation.sites.is_empty()).then_some(glycosylation), ptm, topology, folding_state: disorder::folding_state(&disorder).into(), disorder, conservation, gene, elapsed_us: t.elapsed().as_micros() }
+}
+
+impl PredictResponse {
+    /// Chains predicted for this response, the gene's other ORFs included.
+    pub fn chains(&self) -> u64 {
+        1 + self.gene.iter().flat_map(|g| &g.orfs).filter_map(|o| o.prediction.as_ref()).map(|p| p.chains()).sum::<u64>()
+    }
+}
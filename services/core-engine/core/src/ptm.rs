@@ -0,0 +1,82 @@
+//! Post-translational modification sites: phosphorylation, ubiquitination and acetylation.
+//!
+//! Sites are predicted from sequence motifs: proline-directed (S/T-P), basophilic (R-x-x-S/T)
+//! and acidophilic (S/T-x-x-D/E) kinase motifs and acidic-context tyrosines for
+//! phosphorylation; lysines in polar, charged stretches for ubiquitination; N-terminal
+//! acetylation by the NatA/NatB rules and GK lysines. Curated annotations, such as a UniProt
+//! entry's "Modified residue" and ubiquitin cross-link features, are merged in as annotated
+//! sites.
+
+use serde::Serialize;
+
+/// Predicted sites below this confidence are not reported.
+const MIN_CONFIDENCE: f64 = 0.5;
+
+#[derive(Serialize, Clone)]
+pub struct PtmSite { pub position: usize, pub residue: char, pub modification: &'static str, pub confidence: f64, pub evidence: &'static str, pub detail: String }
+#[derive(Serialize, Clone)]
+pub struct PtmReport { #[serde(skip_serializing_if = "Option::is_none")] pub accession: Option<String>, #[serde(skip_serializing_if = "Option::is_none")] pub uniprot_status: Option<&'static str>, pub sites: Vec<PtmSite> }
+
+/// A curated UniProt feature: 1-based position, modification and description.
+#[derive(Clone)]
+pub struct Annotation { pub position: usize, pub modification: &'static str, pub description: String }
+
+/// A site predictor: confidence and motif description at a position, if any.
+type Predictor = fn(&[u8], usize) -> Option<(f64, String)>;
+
+fn phospho(seq: &[u8], i: usize) -> Option<(f64, String)> {
+    let at = |k: isize| seq.get(i.checked_add_signed(k)?).copied();
+    let acidic = |k: isize| at(k).is_some_and(|r| matches!(r, b'D' | b'E'));
+    let mut motifs: Vec<(&str, f64)> = Vec::new();
+    match seq[i] {
+        b'S' | b'T' => {
+            if at(1) == Some(b'P') { motifs.push(("proline-directed [ST]P", 0.7)); }
+            if at(-3) == Some(b'R') || at(-2) == Some(b'R') { motifs.push(("basophilic R-x-x-[ST]", 0.65)); }
+            if acidic(3) { motifs.push(("acidophilic [ST]-x-x-[DE]", 0.6)); }
+        }
+        b'Y' if (1..=4).filter(|&k| acidic(-k)).count() >= 2 => motifs.push(("acidic-context Y", 0.55)),
+        _ => {}
+    }
+    let best = motifs.iter().map(|m| m.1).fold(0.0, f64::max);
+    (!motifs.is_empty()).then(|| ((best + 0.1 * (motifs.len() - 1) as f64).min(0.95), motifs.iter().map(|m| m.0).collect::<Vec<_>>().join("; ")))
+}
+
+fn ubiquitin(seq: &[u8], i: usize) -> Option<(f64, String)> {
+    if seq[i] != b'K' { return None; }
+    let (from, to) = (i.saturating_sub(5), (i + 6).min(seq.len()));
+    let polar = seq[from..to].iter().filter(|r| b"DEKRSTNQ".contains(r)).count() - 1;
+    Some(((0.25 + 0.05 * polar as f64).min(0.75), format!("{polar} polar/charged neighbours")))
+}
+
+fn acetyl(seq: &[u8], i: usize) -> Option<(f64, String)> {
+    match (i, seq[i]) {
+        // NatB acetylates Met followed by an acidic or amide residue.
+        (0, b'M') if seq.get(1).is_some_and(|r| b"DENQ".contains(r)) => Some((0.8, "N-terminal (NatB)".into())),
+        // NatA acetylates the new N-terminus after Met removal before a small residue.
+        (1, r) if seq[0] == b'M' && b"ASTGVC".contains(&r) => Some((0.75, "N-terminal after Met removal (NatA)".into())),
+        (_, b'K') if i > 0 && seq[i - 1] == b'G' => Some((0.55, "GK motif".into())),
+        _ => None,
+    }
+}
+
+/// Predicted sites merged with curated annotations, in sequence order. Annotations whose
+/// residue doesn't match the sequence (another isoform) are dropped.
+pub fn annotate(sequence: &str, accession: Option<String>, curated: Option<Vec<Annotation>>) -> PtmReport {
+    let seq: Vec<u8> = sequence.bytes().map(|b| b.to_ascii_uppercase()).collect();
+    let mut sites: Vec<PtmSite> = curated.iter().flatten().filter_map(|a| {
+        let residue = *seq.get(a.position.checked_sub(1)?)?;
+        let expected: &[u8] = match a.modification { "phosphorylation" => b"STYH", "ubiquitination" => b"KCST", _ => b"KMASTGVC" };
+        expected.contains(&residue).then(|| PtmSite { position: a.position - 1, residue: residue as char, modification: a.modification, confidence: 1.0, evidence: "uniprot", detail: a.description.clone() })
+    }).collect();
+    let predictors: [(&'static str, Predictor); 3] = [("phosphorylation", phospho), ("ubiquitination", ubiquitin), ("acetylation", acetyl)];
+    for i in 0..seq.len() {
+        for (modification, predict) in predictors {
+            let Some((confidence, detail)) = predict(&seq, i) else { continue };
+            if confidence < MIN_CONFIDENCE || sites.iter().any(|s| s.position == i && s.modification == modification) { continue; }
+            sites.push(PtmSite { position: i, residue: seq[i] as char, modification, confidence: (confidence * 100.0).round() / 100.0, evidence: "motif", detail });
+        }
+    }
+    sites.sort_by_key(|s| s.position);
+    let uniprot_status = accession.as_ref().map(|_| if curated.is_some() { "loaded" } else { "unavailable" });
+    PtmReport { accession, uniprot_status, sites }
+}
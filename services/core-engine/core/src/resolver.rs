@@ -0,0 +1,64 @@
+//! Molecule identifier resolution.
+//!
+//! The `molecule` field of compute requests may hold a common name ("aspirin"), a CAS number,
+//! an InChIKey, an InChI or a SMILES string. Everything that resolves is normalized to
+//! canonical SMILES, which is what the engine computes on, so every spelling of one molecule
+//! gives the same result. `resolve_local` tries the bundled table, then library compound IDs
+//! (`ALICE-nnnnnn`, which resolve to the structure the virtual library enumerates for them),
+//! then HELM (see `helm`), then SMILES parsing. Identifiers nothing can resolve are kept as
+//! opaque IDs.
+
+use serde::Serialize;
+
+use crate::{chem, helm, library};
+
+struct Entry { names: &'static [&'static str], cas: &'static str, inchikey: &'static str, inchi: &'static str, smiles: &'static str }
+
+const TABLE: &[Entry] = &[
+    Entry { names: &["aspirin", "acetylsalicylic acid"], cas: "50-78-2", inchikey: "BSYNRYMUTXBXSQ-UHFFFAOYSA-N", inchi: "InChI=1S/C9H8O4/c1-6(10)13-8-5-3-2-4-7(8)9(11)12/h2-5H,1H3,(H,11,12)", smiles: "CC(=O)Oc1ccccc1C(=O)O" },
+    Entry { names: &["caffeine"], cas: "58-08-2", inchikey: "RYYVLZVUVIJVGH-UHFFFAOYSA-N", inchi: "InChI=1S/C8H10N4O2/c1-10-4-9-6-5(10)7(13)12(3)8(14)11(6)2/h4H,1-3H3", smiles: "CN1C=NC2=C1C(=O)N(C(=O)N2C)C" },
+    Entry { names: &["ibuprofen"], cas: "15687-27-1", inchikey: "HEFNNWSXXWATRW-UHFFFAOYSA-N", inchi: "InChI=1S/C13H18O2/c1-9(2)8-11-4-6-12(7-5-11)10(3)13(14)15/h4-7,9-10H,8H2,1-3H3,(H,14,15)", smiles: "CC(C)Cc1ccc(cc1)C(C)C(=O)O" },
+    Entry { names: &["paracetamol", "acetaminophen"], cas: "103-90-2", inchikey: "RZVAJINKPMORJF-UHFFFAOYSA-N", inchi: "InChI=1S/C8H9NO2/c1-6(10)9-7-2-4-8(11)5-3-7/h2-5,11H,1H3,(H,9,10)", smiles: "CC(=O)Nc1ccc(O)cc1" },
+    Entry { names: &["ethanol"], cas: "64-17-5", inchikey: "LFQSCWFLJHTTHZ-UHFFFAOYSA-N", inchi: "InChI=1S/C2H6O/c1-2-3/h3H,2H2,1H3", smiles: "CCO" },
+    Entry { names: &["benzene"], cas: "71-43-2", inchikey: "UHOVQNZJYSORNB-UHFFFAOYSA-N", inchi: "InChI=1S/C6H6/c1-2-4-6-5-3-1/h1-6H", smiles: "c1ccccc1" },
+    Entry { names: &["water"], cas: "7732-18-5", inchikey: "XLYOFNOQVPJJNP-UHFFFAOYSA-N", inchi: "InChI=1S/H2O/h1H2", smiles: "O" },
+    Entry { names: &["atp", "adenosine triphosphate"], cas: "56-65-5", inchikey: "ZKHQWZAMYRWXGA-KQYNXXCUSA-N", inchi: "", smiles: "Nc1ncnc2c1ncn2C1OC(COP(=O)(O)OP(=O)(O)OP(=O)(O)O)C(O)C1O" },
+    Entry { names: &["glucose", "dextrose"], cas: "50-99-7", inchikey: "WQZGKKKJIJFFOK-GASJEMHNSA-N", inchi: "", smiles: "OCC1OC(O)C(O)C(O)C1O" },
+    Entry { names: &["imatinib"], cas: "152459-95-5", inchikey: "KTUFNOKKBVMGRW-UHFFFAOYSA-N", inchi: "", smiles: "Cc1ccc(NC(=O)c2ccc(CN3CCN(C)CC3)cc2)cc1Nc1nccc(-c2cccnc2)n1" },
+];
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Resolved { pub input: String, pub canonical_smiles: Option<String>, pub name: Option<String>, pub source: String, #[serde(skip_serializing_if = "Vec::is_empty")] pub standardization: Vec<String> }
+
+impl Resolved {
+    /// What the engine computes on: canonical SMILES when known, else the opaque identifier.
+    pub fn key(&self) -> &str { self.canonical_smiles.as_deref().unwrap_or(&self.input) }
+    /// An identifier kept as-is.
+    pub fn opaque(input: &str) -> Self { Self::new(input, None, None, "unresolved") }
+    pub fn new(input: &str, smiles: Option<String>, name: Option<&str>, source: &str) -> Self {
+        Self { input: input.into(), canonical_smiles: smiles, name: name.map(Into::into), source: source.into(), standardization: Vec::new() }
+    }
+}
+
+/// The InChIKey of a bundled structure, looked up by canonical SMILES.
+pub fn inchikey(canonical_smiles: &str) -> Option<&'static str> {
+    TABLE.iter().find(|e| chem::canonicalize(e.smiles).ok().as_deref() == Some(canonical_smiles)).map(|e| e.inchikey)
+}
+
+/// Bundled table and SMILES parsing only; never touches the network.
+pub fn resolve_local(input: &str) -> Option<Resolved> {
+    let id = input.trim();
+    let lower = id.to_lowercase();
+    if let Some(e) = TABLE.iter().find(|e| e.names.contains(&lower.as_str()) || e.cas == id || e.inchikey == id || (!e.inchi.is_empty() && e.inchi == id)) {
+        return Some(Resolved::new(input, chem::canonicalize(e.smiles).ok(), Some(e.names[0]), "table"));
+    }
+    if let Some(smi) = library::smiles_for(id) {
+        return Some(Resolved::new(input, chem::canonicalize(&smi).ok(), None, "library"));
+    }
+    if helm::looks_like(id) { return helm::smiles(id).ok().map(|smi| Resolved::new(input, Some(smi), None, "helm")); }
+    chem::canonicalize(id).ok().map(|smi| Resolved::new(input, Some(smi), None, "smiles"))
+}
+
+pub fn percent_encode(s: &str) -> String {
+    s.bytes().map(|b| if b.is_ascii_alphanumeric() || b"-_.~".contains(&b) { (b as char).to_string() } else { format!("%{b:02X}") }).collect()
+}
@@ -0,0 +1,212 @@
+//! Restraints for `/simulate`.
+//!
+//! `position` restraints hold heavy atoms near where the run starts, selected by 1-based atom
+//! index (canonical SMILES order), by residue number for peptides, by a `selection` (see
+//! `selection`), or all of them by default.
+//! `distance`, `angle` and `dihedral` restraints hold a collective variable (see `cv`) between
+//! `lower` and `upper`, the flat-bottomed form NMR-derived NOE and J-coupling restraints take;
+//! a single `value` pins it. Either way the penalty is ½k·d² in the distance d outside the
+//! allowed region. Restraints bias any umbrella or metadynamics run they come with, and a
+//! restrained Langevin run reports how well each one holds.
+
+use serde::{Deserialize, Serialize};
+use crate::{chem::{self, Molecule}, conformer, cv::Cv, fnv1a, forcefield::{self, System}, frame, observables::Recorder, resolver, schedule::{Annealing, Schedule}, selection};
+
+pub const KINDS: [&str; 4] = ["position", "distance", "angle", "dihedral"];
+const DEFAULT_K_LENGTH: f64 = 10.0; // kcal/mol/Å²
+const DEFAULT_K_ANGLE: f64 = 50.0; // kcal/mol/rad²
+/// Steps of the restrained run, at most.
+const MAX_STEPS: usize = 100_000;
+const STRIDE: usize = 10;
+/// How far outside its bounds a sample must be to count as a violation (Å or degrees).
+const VIOLATION_LENGTH: f64 = 0.5;
+const VIOLATION_DEGREES: f64 = 5.0;
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct RestraintSpec {
+    #[serde(rename = "type")] pub kind: String, #[serde(skip_serializing_if = "Option::is_none")] pub atoms: Option<Vec<usize>>, #[serde(skip_serializing_if = "Option::is_none")] pub residues: Option<Vec<usize>>, #[serde(skip_serializing_if = "Option::is_none")] pub selection: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub value: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] pub lower: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] pub upper: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] pub force_constant: Option<f64>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct RestraintStat { pub restraint: String, pub unit: &'static str, pub force_constant: f64, pub lower: f64, pub upper: f64, pub mean: f64, pub std_dev: f64, pub max_violation: f64, pub violated_fraction: f64, pub energy_kcal_mol: f64 }
+
+#[derive(Serialize, Clone)]
+pub struct Restrained { pub steps_sampled: usize, pub samples: usize, pub restraint_energy_kcal_mol: f64, pub restraints: Vec<RestraintStat> }
+
+enum Kind { Position(Vec<usize>), Cv(Cv) }
+
+/// One restraint with bounds in internal units (Å, radians); position bounds are on each
+/// atom's displacement.
+struct Term { kind: Kind, lower: f64, upper: f64, k: f64, label: String }
+
+/// The parsed restraints of one run.
+#[derive(Default)]
+pub struct Restraints { terms: Vec<Term> }
+
+impl Restraints {
+    pub fn is_empty(&self) -> bool { self.terms.is_empty() }
+
+    pub fn parse(specs: &[RestraintSpec], mol: &resolver::Resolved) -> Result<Self, String> {
+        if specs.is_empty() { return Ok(Self::default()); }
+        let smiles = mol.canonical_smiles.as_deref().ok_or_else(|| format!("can't resolve {} to a structure to restrain", mol.input))?;
+        let m = chem::parse_smiles(smiles)?;
+        let terms = specs.iter().map(|spec| Self::term(spec, &m)).collect::<Result<_, _>>()?;
+        Ok(Self { terms })
+    }
+
+    fn term(spec: &RestraintSpec, m: &Molecule) -> Result<Term, String> {
+        let n = m.atoms.len();
+        let (kind, label) = if spec.kind == "position" {
+            let atoms: Vec<usize> = match (&spec.atoms, &spec.residues, &spec.selection) {
+                (Some(_), Some(_), _) | (Some(_), _, Some(_)) | (_, Some(_), Some(_)) => return Err("a position restraint selects by one of atoms, residues or selection".into()),
+                (_, _, Some(text)) => selection::select(text, &selection::molecule_atoms(m, None))?,
+                (Some(atoms), None, None) => {
+                    if let Some(&bad) = atoms.iter().find(|&&a| a == 0 || a > n) { return Err(format!("atom {bad} is out of range; the molecule has {n} heavy atoms")); }
+                    atoms.iter().map(|a| a - 1).collect()
+                }
+                (None, Some(wanted), None) => {
+                    let residues = selection::residues(m);
+                    if residues.is_empty() { return Err("the molecule has no amino-acid residues; select atoms instead".into()); }
+                    if let Some(&bad) = wanted.iter().find(|&&r| r == 0 || r > residues.len()) { return Err(format!("residue {bad} is out of range; the peptide has {} residues", residues.len())); }
+                    wanted.iter().flat_map(|r| residues[r - 1].atoms.iter().copied()).collect()
+                }
+                (None, None, None) => (0..n).collect(),
+            };
+            if atoms.is_empty() { return Err("a position restraint needs at least one atom".into()); }
+            let label = match (&spec.atoms, &spec.residues, &spec.selection) {
+                (_, _, Some(text)) => format!("position {text}"),
+                (Some(a), ..) => format!("position atoms {}", a.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(",")),
+                (_, Some(r), _) => format!("position residues {}", r.iter().map(|r| r.to_string()).collect::<Vec<_>>().join(",")),
+                _ => "position all".to_string(),
+            };
+            (Kind::Position(atoms), label)
+        } else if KINDS.contains(&spec.kind.as_str()) || spec.kind == "torsion" {
+            if spec.residues.is_some() { return Err(format!("a {} restraint selects atoms, not residues", spec.kind)); }
+            let atoms = match (&spec.atoms, &spec.selection) {
+                (Some(_), Some(_)) => return Err(format!("a {} restraint takes atoms or a selection, not both", spec.kind)),
+                // Selected atoms go in index order.
+                (None, Some(text)) => selection::select(text, &selection::molecule_atoms(m, None))?.into_iter().map(|a| a + 1).collect(),
+                (atoms, None) => atoms.clone().unwrap_or_default(),
+            };
+            let cv = Cv::parse(&crate::cv::CvSpec { kind: spec.kind.clone(), atoms }, n)?;
+            (Kind::Cv(cv), cv.label())
+        } else {
+            return Err(format!("unknown restraint {}; expected one of {}", spec.kind, KINDS.join(", ")));
+        };
+        let internal = |v: f64| match kind { Kind::Cv(cv) => cv.to_internal(v), Kind::Position(_) => v };
+        if spec.value.is_some() && (spec.lower.is_some() || spec.upper.is_some()) { return Err(format!("the {label} restraint takes a value or bounds, not both")); }
+        let (lower, upper) = match (&kind, spec.value, spec.lower, spec.upper) {
+            (_, Some(v), ..) => (internal(v), internal(v)),
+            (Kind::Position(_), None, None, None) => (0.0, 0.0),
+            (Kind::Cv(_), None, None, None) => return Err(format!("the {label} restraint needs a value, or lower and upper bounds")),
+            (_, None, lo, hi) => (lo.map(internal).unwrap_or(f64::NEG_INFINITY), hi.map(internal).unwrap_or(f64::INFINITY)),
+        };
+        if lower.is_nan() || upper.is_nan() { return Err(format!("the bounds of the {label} restraint must be numbers")); }
+        match kind {
+            Kind::Position(_) if lower.is_finite() && lower != 0.0 => return Err("a position restraint takes only an upper bound on the displacement".into()),
+            Kind::Cv(Cv::Dihedral(_)) if !lower.is_finite() || !upper.is_finite() => return Err(format!("the {label} restraint needs both bounds")),
+            Kind::Cv(Cv::Dihedral(_)) => {}
+            _ if upper < lower => return Err(format!("the {label} restraint's lower bound is above its upper one")),
+            _ => {}
+        }
+        let k = spec.force_constant.unwrap_or(match kind { Kind::Cv(Cv::Angle(_) | Cv::Dihedral(_)) => DEFAULT_K_ANGLE, _ => DEFAULT_K_LENGTH });
+        if k.is_nan() || k <= 0.0 { return Err(format!("the {label} restraint's force_constant must be positive")); }
+        let lower = if matches!(kind, Kind::Position(_)) { 0.0 } else { lower };
+        Ok(Term { kind, lower, upper, k, label })
+    }
+
+    /// Restraint energy at `x` against the starting coordinates `reference`; adds its gradient
+    /// to `grad` when given.
+    pub fn energy(&self, reference: &[[f64; 3]], x: &[[f64; 3]], mut grad: Option<&mut [[f64; 3]]>) -> f64 {
+        let mut e = 0.0;
+        for t in &self.terms {
+            match &t.kind {
+                Kind::Position(atoms) => for &i in atoms {
+                    let d = [x[i][0] - reference[i][0], x[i][1] - reference[i][1], x[i][2] - reference[i][2]];
+                    let r = (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt();
+                    let over = r - t.upper;
+                    if over <= 0.0 { continue; }
+                    e += 0.5 * t.k * over * over;
+                    if let Some(g) = grad.as_deref_mut() { for c in 0..3 { g[i][c] += t.k * over * d[c] / r.max(1e-9); } }
+                },
+                Kind::Cv(cv) => {
+                    let (v, g) = cv.gradient(x);
+                    let d = t.excess(*cv, v);
+                    if d == 0.0 { continue; }
+                    e += 0.5 * t.k * d * d;
+                    if let Some(grad) = grad.as_deref_mut() { for (i, gi) in g { for c in 0..3 { grad[i][c] += t.k * d * gi[c]; } } }
+                }
+            }
+        }
+        e
+    }
+}
+
+impl Term {
+    /// How far `v` lies outside the bounds, signed; dihedral bounds run from `lower` round to
+    /// `upper` in the positive sense.
+    fn excess(&self, cv: Cv, v: f64) -> f64 {
+        if cv.periodic() {
+            let span = (self.upper - self.lower).rem_euclid(std::f64::consts::TAU);
+            let d = cv.delta(v, self.lower + span / 2.0);
+            return d.signum() * (d.abs() - span / 2.0).max(0.0);
+        }
+        if v < self.lower { v - self.lower } else if v > self.upper { v - self.upper } else { 0.0 }
+    }
+}
+
+/// A restrained Langevin run of `mol` from its embedded conformer with the thermostat on
+/// `schedule`, sampling every restraint and handing each step to `recorder`.
+pub fn run(restraints: &Restraints, mol: &resolver::Resolved, steps: u64, schedule: &Schedule, recorder: &mut Recorder) -> Result<(Restrained, Annealing), String> {
+    let smiles = mol.canonical_smiles.as_deref().ok_or_else(|| format!("can't resolve {} to a structure to restrain", mol.input))?;
+    let m = chem::parse_smiles(smiles)?;
+    let steps = (steps as usize).min(MAX_STEPS);
+    let bonded = conformer::restraints(&m);
+    let seed = fnv1a(smiles.as_bytes());
+    let mut x = conformer::embed(&m, seed);
+    let reference = x.clone();
+    let bias = |x: &[[f64; 3]], grad: Option<&mut [[f64; 3]]>| restraints.energy(&reference, x, grad);
+    let system = System { restraints: &bonded, receptor: &frame::EMPTY, metals: None, anchor: &[], k_pos: 0.0, bias: Some(&bias) };
+    // Per restraint: (value, excess, energy) samples; a position restraint's value is its RMS
+    // displacement and its excess the largest atom's.
+    let mut samples: Vec<Vec<(f64, f64, f64)>> = vec![Vec::new(); restraints.terms.len()];
+    let mut trace = schedule.trace(steps);
+    system.anneal(&mut x, steps, &|step| schedule.temperature(step), seed, &mut |step, x, kinetic| {
+        recorder.observe(step, x, &reference);
+        trace.record(schedule, step, kinetic, || forcefield::internal_energy(&bonded, x, None));
+        if step % STRIDE != 0 { return; }
+        for (t, out) in restraints.terms.iter().zip(samples.iter_mut()) {
+            out.push(match &t.kind {
+                Kind::Position(atoms) => {
+                    let r: Vec<f64> = atoms.iter().map(|&i| (0..3).map(|c| (x[i][c] - reference[i][c]).powi(2)).sum::<f64>().sqrt()).collect();
+                    let over = r.iter().map(|r| (r - t.upper).max(0.0));
+                    ((r.iter().map(|r| r * r).sum::<f64>() / r.len() as f64).sqrt(), over.clone().fold(0.0, f64::max), over.map(|d| 0.5 * t.k * d * d).sum())
+                }
+                Kind::Cv(cv) => { let v = cv.value(x); let d = t.excess(*cv, v); (v, d, 0.5 * t.k * d * d) }
+            });
+        }
+    });
+    let n = samples.first().map_or(0, Vec::len).max(1) as f64;
+    let round = |v: f64| (v * 1e3).round() / 1e3;
+    let stats: Vec<RestraintStat> = restraints.terms.iter().zip(&samples).map(|(t, xs)| {
+        let (api, unit, tolerance): (Box<dyn Fn(f64) -> f64>, _, _) = match t.kind {
+            Kind::Cv(cv @ (Cv::Angle(_) | Cv::Dihedral(_))) => (Box::new(move |v| cv.to_api(v)), "degree", VIOLATION_DEGREES.to_radians()),
+            _ => (Box::new(|v| v), "angstrom", VIOLATION_LENGTH),
+        };
+        // Dihedral statistics are taken around the middle of the bounds so they don't split at ±180°.
+        let centre = match t.kind { Kind::Cv(cv) if cv.periodic() => Some((cv, t.lower + (t.upper - t.lower).rem_euclid(std::f64::consts::TAU) / 2.0)), _ => None };
+        let unwrap = |v: f64| centre.map_or(v, |(cv, c)| c + cv.delta(v, c));
+        let mean = xs.iter().map(|s| unwrap(s.0)).sum::<f64>() / n;
+        let var = xs.iter().map(|s| (unwrap(s.0) - mean).powi(2)).sum::<f64>() / n;
+        let mean = centre.map_or(mean, |(cv, _)| cv.delta(mean, 0.0));
+        let e = xs.iter().map(|s| s.2).sum::<f64>() / n;
+        RestraintStat {
+            restraint: t.label.clone(), unit, force_constant: t.k, lower: round(api(t.lower)), upper: round(api(t.upper)), mean: round(api(mean)), std_dev: round(api(var.sqrt())),
+            max_violation: round(api(xs.iter().map(|s| s.1.abs()).fold(0.0, f64::max))), violated_fraction: round(xs.iter().filter(|s| s.1.abs() > tolerance).count() as f64 / n), energy_kcal_mol: round(e),
+        }
+    }).collect();
+    let energy = samples.iter().flatten().map(|s| s.2).sum::<f64>() / n;
+    let annealing = trace.finish(schedule, forcefield::internal_energy(&bonded, &x, None));
+    Ok((Restrained { steps_sampled: steps, samples: n as usize, restraint_energy_kcal_mol: round(energy), restraints: stats }, annealing))
+}
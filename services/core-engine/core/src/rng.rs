@@ -0,0 +1,12 @@
+//! The engine's seeded random numbers: xorshift64, so a seed gives the same run on every build
+//! and platform. Not for anything that needs to be unpredictable.
+
+/// A xorshift64 state; it must not be zero, so callers seed it with `seed | 1`.
+pub struct Rng(pub u64);
+
+impl Rng {
+    /// Uniform in [0, 1).
+    pub fn uniform(&mut self) -> f64 { self.0 ^= self.0 << 13; self.0 ^= self.0 >> 7; self.0 ^= self.0 << 17; (self.0 >> 11) as f64 / (1u64 << 53) as f64 }
+    /// Standard normal, by Box-Muller.
+    pub fn gauss(&mut self) -> f64 { let (u1, u2) = (self.uniform().max(1e-12), self.uniform()); (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos() }
+}
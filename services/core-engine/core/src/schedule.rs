@@ -0,0 +1,85 @@
+//! Temperature and pressure schedules for `/simulate`.
+//!
+//! A schedule is a list of `{step, temp_k}` points, optionally with `pressure_bar`; the
+//! thermostat target is interpolated linearly between points and held flat before the first
+//! and after the last, which covers controlled heating, simulated annealing and repeated
+//! heat-cool cycles. Pressure is interpolated the same way and reported, though the engine runs
+//! in vacuo with no box for it to act on. The trace averages the Langevin run's kinetic
+//! temperature over blocks of steps, so the temperature actually reached can be checked against
+//! the target, and takes the force field energy at the end of each block.
+
+use serde::{Deserialize, Serialize};
+
+/// Trace points per run, about.
+const TRACE_POINTS: usize = 200;
+
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct SchedulePoint { pub step: u64, pub temp_k: f64, pub pressure_bar: Option<f64> }
+
+#[derive(Serialize, Clone)]
+pub struct TracePoint { pub step: usize, pub target_k: f64, pub temperature_k: f64, #[serde(skip_serializing_if = "Option::is_none")] pub pressure_bar: Option<f64>, pub energy_kcal_mol: f64 }
+
+#[derive(Serialize, Clone)]
+pub struct Annealing { pub schedule: Vec<SchedulePoint>, pub trace: Vec<TracePoint>, pub energy_end_kcal_mol: f64, pub energy_min_kcal_mol: f64, pub energy_min_step: usize }
+
+/// The thermostat target over a run: the schedule's points, or one constant temperature.
+pub struct Schedule { points: Vec<SchedulePoint>, constant: f64 }
+
+fn interpolate(points: &[(f64, f64)], at: f64) -> f64 {
+    match points.iter().position(|p| p.0 > at) {
+        Some(0) => points[0].1,
+        Some(i) => { let (a, b) = (points[i - 1], points[i]); a.1 + (b.1 - a.1) * (at - a.0) / (b.0 - a.0) }
+        None => points.last().map_or(0.0, |p| p.1),
+    }
+}
+
+impl Schedule {
+    pub fn constant(temperature_k: f64) -> Self { Self { points: Vec::new(), constant: temperature_k } }
+
+    pub fn parse(points: &[SchedulePoint]) -> Result<Self, String> {
+        if points.is_empty() { return Err("temperature_schedule needs at least one point".into()); }
+        if points.windows(2).any(|w| w[1].step <= w[0].step) { return Err("temperature_schedule steps must increase".into()); }
+        if points.iter().any(|p| p.temp_k.is_nan() || p.temp_k < 0.0) { return Err("temperature_schedule temperatures must not be negative".into()); }
+        if points.iter().any(|p| p.pressure_bar.is_some_and(|b| b.is_nan() || b < 0.0)) { return Err("temperature_schedule pressures must not be negative".into()); }
+        Ok(Self { points: points.to_vec(), constant: points[0].temp_k })
+    }
+
+    pub fn is_constant(&self) -> bool { self.points.is_empty() }
+
+    /// Target temperature at `step` (0-based).
+    pub fn temperature(&self, step: usize) -> f64 {
+        if self.points.is_empty() { return self.constant; }
+        interpolate(&self.points.iter().map(|p| (p.step as f64, p.temp_k)).collect::<Vec<_>>(), step as f64)
+    }
+
+    pub fn pressure(&self, step: usize) -> Option<f64> {
+        let points: Vec<(f64, f64)> = self.points.iter().filter_map(|p| Some((p.step as f64, p.pressure_bar?))).collect();
+        (!points.is_empty()).then(|| interpolate(&points, step as f64))
+    }
+
+    /// A recorder of the run's temperature and energy for a run of `steps`.
+    pub fn trace(&self, steps: usize) -> Trace { Trace { block: steps.div_ceil(TRACE_POINTS).max(1), sum_t: 0.0, n: 0, points: Vec::new() } }
+}
+
+/// Block averages of kinetic temperature along a run, with the energy at each block's end.
+pub struct Trace { block: usize, sum_t: f64, n: usize, points: Vec<TracePoint> }
+
+impl Trace {
+    pub fn record(&mut self, schedule: &Schedule, step: usize, temperature_k: f64, energy: impl FnOnce() -> f64) {
+        self.sum_t += temperature_k;
+        self.n += 1;
+        if !(step + 1).is_multiple_of(self.block) { return; }
+        let round = |v: f64| (v * 1e3).round() / 1e3;
+        self.points.push(TracePoint { step: step + 1, target_k: round(schedule.temperature(step)), temperature_k: round(self.sum_t / self.n as f64), pressure_bar: schedule.pressure(step).map(round), energy_kcal_mol: round(energy()) });
+        (self.sum_t, self.n) = (0.0, 0);
+    }
+
+    /// The trace with energy summaries; `energy_end` is the final structure's.
+    pub fn finish(self, schedule: &Schedule, energy_end: f64) -> Annealing {
+        let min = self.points.iter().min_by(|a, b| a.energy_kcal_mol.total_cmp(&b.energy_kcal_mol));
+        Annealing {
+            schedule: schedule.points.clone(), energy_end_kcal_mol: (energy_end * 1e3).round() / 1e3,
+            energy_min_kcal_mol: min.map_or(0.0, |p| p.energy_kcal_mol), energy_min_step: min.map_or(0, |p| p.step), trace: self.points,
+        }
+    }
+}
@@ -0,0 +1,243 @@
+//! Atom selection language.
+//!
+//! Selections pick atoms by property and combine with `and`, `or`, `not` and parentheses, e.g.
+//! `chain A and resid 10-50 and backbone` or `not hydrogen and within 5 of resname LYS`.
+//! Keywords: `all`, `none`, `protein`, `backbone` (N, CA, C, O, OXT), `sidechain`, `heavy`,
+//! `hydrogen`, `aromatic`, and `index`, `resid`, `resname`, `name`, `element` and `chain` followed
+//! by one or more values; `index` and `resid` take ranges (`10-50`, `10:50`, `10 to 50`).
+//! `index` counts atoms from 1. Names and chains are matched without regard to case.
+//! `within R of <selection>` needs coordinates. Molecules given as SMILES have their peptide
+//! residues perceived from the N–Cα–C=O backbone and named from their side chains; anything
+//! else is one `LIG` residue.
+
+use std::collections::VecDeque;
+
+use crate::chem::{BondKind, Molecule};
+
+/// An atom as selections see it.
+#[derive(Clone)]
+pub struct Atom { pub name: String, pub element: String, pub aromatic: bool, pub resname: String, pub resid: i64, pub chain: char, pub protein: bool, pub coords: Option<[f64; 3]> }
+
+/// A perceived amino-acid residue: its heavy atoms and backbone (N, Cα, C).
+pub struct Residue { pub name: &'static str, pub backbone: [usize; 3], pub atoms: Vec<usize> }
+
+pub const BACKBONE: [&str; 5] = ["N", "CA", "C", "O", "OXT"];
+
+enum Expr { All, None, Protein, Backbone, Sidechain, Heavy, Hydrogen, Aromatic, Index(Vec<(i64, i64)>), Resid(Vec<(i64, i64)>), Resname(Vec<String>), Name(Vec<String>), Element(Vec<String>), Chain(Vec<String>), Within(f64, Box<Expr>), Not(Box<Expr>), And(Box<Expr>, Box<Expr>), Or(Box<Expr>, Box<Expr>) }
+
+const KEYWORDS: [&str; 18] = ["all", "none", "protein", "backbone", "sidechain", "heavy", "hydrogen", "aromatic", "index", "resid", "resname", "name", "element", "chain", "within", "and", "or", "not"];
+
+struct Parser { tokens: Vec<String>, at: usize }
+
+impl Parser {
+    fn peek(&self) -> Option<&str> { self.tokens.get(self.at).map(String::as_str) }
+    fn next(&mut self) -> Option<String> { let t = self.tokens.get(self.at).cloned(); self.at += 1; t }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut e = self.and()?;
+        while self.peek() == Some("or") { self.at += 1; e = Expr::Or(Box::new(e), Box::new(self.and()?)); }
+        Ok(e)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut e = self.not()?;
+        while self.peek() == Some("and") { self.at += 1; e = Expr::And(Box::new(e), Box::new(self.not()?)); }
+        Ok(e)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.peek() == Some("not") { self.at += 1; return Ok(Expr::Not(Box::new(self.not()?))); }
+        self.primary()
+    }
+
+    /// Values up to the next keyword or parenthesis.
+    fn values(&mut self, keyword: &str) -> Result<Vec<String>, String> {
+        let mut out = Vec::new();
+        while let Some(t) = self.peek() {
+            if t == "(" || t == ")" || KEYWORDS.contains(&t) { break; }
+            out.push(t.to_string());
+            self.at += 1;
+        }
+        if out.is_empty() { return Err(format!("{keyword} needs at least one value")); }
+        Ok(out)
+    }
+
+    fn ranges(&mut self, keyword: &str) -> Result<Vec<(i64, i64)>, String> {
+        let values = self.values(keyword)?;
+        let mut out = Vec::new();
+        let mut i = 0;
+        while i < values.len() {
+            let num = |v: &str| v.parse::<i64>().map_err(|_| format!("{keyword} takes numbers and ranges, not {v}"));
+            let v = &values[i];
+            if values.get(i + 1).map(String::as_str) == Some("to") {
+                let hi = values.get(i + 2).ok_or_else(|| format!("{keyword} {v} to needs an upper end"))?;
+                out.push((num(v)?, num(hi)?));
+                i += 3;
+                continue;
+            }
+            // A leading minus is a negative number, not a range.
+            let split = v.char_indices().skip(1).find(|&(_, c)| c == '-' || c == ':').map(|(k, _)| k);
+            out.push(match split { Some(k) => (num(&v[..k])?, num(&v[k + 1..])?), None => { let n = num(v)?; (n, n) } });
+            i += 1;
+        }
+        Ok(out)
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let t = self.next().ok_or("the selection ends too early")?;
+        Ok(match t.as_str() {
+            "(" => {
+                let e = self.or()?;
+                if self.next().as_deref() != Some(")") { return Err("unbalanced parentheses".into()); }
+                e
+            }
+            "all" => Expr::All,
+            "none" => Expr::None,
+            "protein" => Expr::Protein,
+            "backbone" => Expr::Backbone,
+            "sidechain" => Expr::Sidechain,
+            "heavy" => Expr::Heavy,
+            "hydrogen" => Expr::Hydrogen,
+            "aromatic" => Expr::Aromatic,
+            "index" => Expr::Index(self.ranges("index")?),
+            "resid" => Expr::Resid(self.ranges("resid")?),
+            "resname" => Expr::Resname(self.values("resname")?),
+            "name" => Expr::Name(self.values("name")?),
+            "element" => Expr::Element(self.values("element")?),
+            "chain" => Expr::Chain(self.values("chain")?),
+            "within" => {
+                let r = self.next().and_then(|r| r.parse::<f64>().ok()).filter(|r| *r >= 0.0).ok_or("within needs a distance in angstrom")?;
+                if self.next().as_deref() != Some("of") { return Err("expected `of` after within's distance".into()); }
+                Expr::Within(r, Box::new(self.not()?))
+            }
+            other => return Err(format!("unexpected {other} in the selection")),
+        })
+    }
+}
+
+impl Expr {
+    fn eval(&self, atoms: &[Atom]) -> Result<Vec<bool>, String> {
+        let each = |f: &dyn Fn(usize, &Atom) -> bool| atoms.iter().enumerate().map(|(i, a)| f(i, a)).collect::<Vec<bool>>();
+        let any = |list: &[String], v: &str| list.iter().any(|x| x.eq_ignore_ascii_case(v));
+        let within = |ranges: &[(i64, i64)], v: i64| ranges.iter().any(|&(lo, hi)| (lo..=hi).contains(&v));
+        Ok(match self {
+            Self::All => vec![true; atoms.len()],
+            Self::None => vec![false; atoms.len()],
+            Self::Protein => each(&|_, a| a.protein),
+            Self::Backbone => each(&|_, a| a.protein && BACKBONE.contains(&a.name.as_str())),
+            Self::Sidechain => each(&|_, a| a.protein && !BACKBONE.contains(&a.name.as_str())),
+            Self::Heavy => each(&|_, a| a.element != "H"),
+            Self::Hydrogen => each(&|_, a| a.element == "H"),
+            Self::Aromatic => each(&|_, a| a.aromatic),
+            Self::Index(r) => each(&|i, _| within(r, i as i64 + 1)),
+            Self::Resid(r) => each(&|_, a| within(r, a.resid)),
+            Self::Resname(v) => each(&|_, a| any(v, &a.resname)),
+            Self::Name(v) => each(&|_, a| any(v, &a.name)),
+            Self::Element(v) => each(&|_, a| any(v, &a.element)),
+            Self::Chain(v) => each(&|_, a| any(v, &a.chain.to_string())),
+            Self::Within(r, inner) => {
+                let of = inner.eval(atoms)?;
+                let centres: Vec<[f64; 3]> = atoms.iter().zip(&of).filter(|p| *p.1).map(|p| p.0.coords.ok_or("within needs coordinates, which these atoms don't have")).collect::<Result<_, _>>()?;
+                each(&|_, a| a.coords.is_some_and(|p| centres.iter().any(|c| (0..3).map(|k| (p[k] - c[k]).powi(2)).sum::<f64>() <= r * r)))
+            }
+            Self::Not(e) => e.eval(atoms)?.into_iter().map(|b| !b).collect(),
+            Self::And(a, b) => a.eval(atoms)?.into_iter().zip(b.eval(atoms)?).map(|(x, y)| x && y).collect(),
+            Self::Or(a, b) => a.eval(atoms)?.into_iter().zip(b.eval(atoms)?).map(|(x, y)| x || y).collect(),
+        })
+    }
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    text.replace('(', " ( ").replace(')', " ) ").split_whitespace().map(|t| if KEYWORDS.contains(&t.to_ascii_lowercase().as_str()) || t.eq_ignore_ascii_case("of") || t.eq_ignore_ascii_case("to") { t.to_ascii_lowercase() } else { t.to_string() }).collect()
+}
+
+/// Indices (0-based, ascending) of the atoms `text` selects.
+pub fn select(text: &str, atoms: &[Atom]) -> Result<Vec<usize>, String> {
+    let mut p = Parser { tokens: tokenize(text), at: 0 };
+    if p.tokens.is_empty() { return Err("the selection is empty".into()); }
+    let e = p.or().map_err(|e| format!("can't parse selection `{text}`: {e}"))?;
+    if let Some(t) = p.peek() { return Err(format!("can't parse selection `{text}`: unexpected {t}")); }
+    Ok(e.eval(atoms)?.into_iter().enumerate().filter(|p| p.1).map(|p| p.0).collect())
+}
+
+/// Three-letter name of a perceived residue from the composition of its side chain.
+fn residue_name(m: &Molecule, adj: &[Vec<(usize, BondKind)>], backbone: [usize; 3], side: &[usize]) -> &'static str {
+    let count = |e: &str| side.iter().filter(|&&a| m.atoms[a].element == e).count();
+    let aromatic = side.iter().any(|&a| m.atoms[a].aromatic);
+    let (c, n, o, s) = (count("C"), count("N"), count("O"), count("S"));
+    let to_n = side.iter().any(|&a| adj[a].iter().any(|e| e.0 == backbone[0]));
+    let cb_branches = side.iter().find(|&&a| adj[a].iter().any(|e| e.0 == backbone[1])).map_or(0, |&cb| adj[cb].len());
+    match (c, n, o, s, aromatic) {
+        (0, 0, 0, 0, _) => "GLY",
+        (1, 0, 0, 0, _) => "ALA",
+        (1, 0, 1, 0, _) => "SER",
+        (1, 0, 0, 1, _) => "CYS",
+        (3, 0, 0, 0, _) if to_n => "PRO",
+        (3, 0, 0, 0, _) => "VAL",
+        (2, 0, 1, 0, _) => "THR",
+        (4, 0, 0, 0, _) if cb_branches == 3 => "ILE",
+        (4, 0, 0, 0, _) => "LEU",
+        (2, 1, 1, 0, _) => "ASN",
+        (2, 0, 2, 0, _) => "ASP",
+        (3, 1, 1, 0, _) => "GLN",
+        (3, 0, 2, 0, _) => "GLU",
+        (4, 1, 0, 0, _) => "LYS",
+        (3, 0, 0, 1, _) => "MET",
+        (4, 2, 0, 0, true) => "HIS",
+        (7, 0, 0, 0, true) => "PHE",
+        (7, 0, 1, 0, true) => "TYR",
+        (9, 1, 0, 0, true) => "TRP",
+        (4, 3, 0, 0, _) => "ARG",
+        _ => "UNK",
+    }
+}
+
+/// Amino-acid residues of a molecule, N-terminus first. A head-to-tail cyclic peptide has no
+/// N-terminus and starts at its first residue in atom order.
+pub fn residues(m: &Molecule) -> Vec<Residue> {
+    let adj = m.neighbors();
+    let carbonyl = |c: usize| m.atoms[c].element == "C" && adj[c].iter().any(|&(o, k)| k == BondKind::Double && m.atoms[o].element == "O");
+    // Backbone (N, Cα, C) triples.
+    let mut backbone: Vec<[usize; 3]> = Vec::new();
+    for (ca, atom) in m.atoms.iter().enumerate() {
+        if atom.element != "C" || atom.aromatic { continue; }
+        let n = adj[ca].iter().map(|e| e.0).find(|&n| m.atoms[n].element == "N" && !m.atoms[n].aromatic);
+        let c = adj[ca].iter().map(|e| e.0).find(|&c| carbonyl(c));
+        if let (Some(n), Some(c)) = (n, c) { backbone.push([n, ca, c]); }
+    }
+    // Chain order: each residue's carbonyl carbon bonds to the next one's nitrogen.
+    let next = |r: &[usize; 3]| backbone.iter().position(|s| adj[r[2]].iter().any(|e| e.0 == s[0]));
+    let start = (0..backbone.len()).find(|&i| !backbone.iter().any(|r| adj[r[2]].iter().any(|e| e.0 == backbone[i][0])));
+    let Some(mut at) = start.or_else(|| (0..backbone.len()).find(|&i| next(&backbone[i]).is_some())) else { return Vec::new() };
+    let mut chain = vec![at];
+    while let Some(n) = next(&backbone[at]) { if chain.contains(&n) { break; } chain.push(n); at = n; }
+    // Everything else joins the residue whose backbone reaches it first.
+    let mut owner = vec![usize::MAX; m.atoms.len()];
+    let mut queue = VecDeque::new();
+    for (r, &i) in chain.iter().enumerate() { for a in backbone[i] { owner[a] = r; queue.push_back(a); } }
+    while let Some(a) = queue.pop_front() {
+        for &(b, _) in &adj[a] { if owner[b] == usize::MAX { owner[b] = owner[a]; queue.push_back(b); } }
+    }
+    chain.iter().enumerate().map(|(r, &i)| {
+        let bb = backbone[i];
+        let atoms: Vec<usize> = (0..m.atoms.len()).filter(|&a| owner[a] == r).collect();
+        // The side chain hangs off Cα; carbonyl and terminal oxygens hang off C.
+        let side: Vec<usize> = atoms.iter().copied().filter(|&a| !bb.contains(&a) && (m.atoms[a].element != "O" || !adj[a].iter().any(|e| e.0 == bb[2]))).collect();
+        Residue { name: residue_name(m, &adj, bb, &side), backbone: bb, atoms }
+    }).collect()
+}
+
+/// Selection atoms of a molecule in its atom order, with `coords` when known.
+pub fn molecule_atoms(m: &Molecule, coords: Option<&[[f64; 3]]>) -> Vec<Atom> {
+    let mut atoms: Vec<Atom> = m.atoms.iter().enumerate().map(|(i, a)| Atom {
+        name: format!("{}{}", a.element, i + 1), element: a.element.clone(), aromatic: a.aromatic, resname: "LIG".into(), resid: 1, chain: 'A', protein: false, coords: coords.and_then(|c| c.get(i).copied()),
+    }).collect();
+    let adj = m.neighbors();
+    for (r, res) in residues(m).iter().enumerate() {
+        for &a in &res.atoms { atoms[a].resname = res.name.into(); atoms[a].resid = r as i64 + 1; atoms[a].protein = true; }
+        for (a, name) in res.backbone.iter().zip(["N", "CA", "C"]) { atoms[*a].name = name.into(); }
+        let oxygens: Vec<usize> = adj[res.backbone[2]].iter().map(|e| e.0).filter(|&o| m.atoms[o].element == "O").collect();
+        for o in oxygens { atoms[o].name = if adj[res.backbone[2]].iter().any(|e| e.0 == o && e.1 == BondKind::Double) { "O" } else { "OXT" }.into(); }
+    }
+    atoms
+}
@@ -0,0 +1,37 @@
+//! Off-target selectivity panels.
+//!
+//! With `anti_targets` in a screen request, every hit is also docked into the top pocket of
+//! each anti-target and scored the same way as against the primary target (base affinity
+//! plus the water-displacement term). The selectivity ratio is the weakest-binding margin:
+//! the tightest anti-target Kd over the primary Kd, so 100 means 100-fold selective.
+
+use serde::Serialize;
+
+use crate::{fnv1a, hydration, pockets, poses};
+
+#[derive(Serialize, Clone)]
+pub struct PanelScore { pub target: String, pub role: &'static str, pub pocket_id: String, pub binding_affinity_nm: f64, pub water_displacement_kcal: f64 }
+
+/// An anti-target with its pocket and hydration sites prepared once per screen.
+pub struct AntiTarget { name: String, pocket: Option<pockets::Pocket>, sites: Vec<hydration::HydrationSite> }
+
+impl AntiTarget {
+    pub fn new(name: &str) -> Self {
+        let pocket = pockets::detect(name).into_iter().next();
+        let sites = pocket.as_ref().map(hydration::sites).unwrap_or_default();
+        Self { name: name.into(), pocket, sites }
+    }
+
+    pub fn score(&self, compound_id: &str, smiles: &str) -> Option<PanelScore> {
+        let pose = poses::dock(&self.name, self.pocket.as_ref(), compound_id, smiles)?;
+        let water = hydration::displacement(&self.sites, &pose.coords);
+        // Off-target base affinities spread log-uniformly over 10 nM–10 µM.
+        let base = 10.0 * 10f64.powf((fnv1a(format!("{}/{compound_id}", self.name).as_bytes()) % 1000) as f64 / 1000.0 * 3.0);
+        Some(PanelScore { target: self.name.clone(), role: "anti_target", pocket_id: pose.pocket_id, binding_affinity_nm: base * (water / 0.593).exp(), water_displacement_kcal: water })
+    }
+}
+
+/// Tightest anti-target Kd over the primary Kd; `None` without anti-targets.
+pub fn ratio(primary_nm: f64, panel: &[PanelScore]) -> Option<f64> {
+    panel.iter().filter(|p| p.role == "anti_target").map(|p| p.binding_affinity_nm).min_by(f64::total_cmp).map(|off| off / primary_nm)
+}
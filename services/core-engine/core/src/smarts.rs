@@ -0,0 +1,247 @@
+//! A SMARTS subset for substructure queries.
+//!
+//! Atoms are organic-subset symbols (`C`, `c`, `Cl`, ...), `*`, `a` (aromatic) and `A`
+//! (aliphatic), or bracket expressions over the primitives element symbol, `#n` (atomic
+//! number), `Hn` (total hydrogens), `Dn` (heavy-atom connections), `Xn` (connections with
+//! hydrogens), `R`/`R0` (in a ring or not), `rn` (in a ring of n atoms), charge (`+`, `-n`,
+//! `++`) and isotope, combined with `!`, `&` (or juxtaposition), `,` and `;` at the usual
+//! precedences. `[H]` alone is a hydrogen atom. Bonds are `-`, `=`, `#`, `:`, `~` (any) and
+//! `@` (ring bond) under the same operators; an unwritten bond is single or aromatic.
+//! Branches, ring closures and `.` work as in SMILES. Recursive SMARTS (`$(...)`) and
+//! chirality are not supported.
+
+use crate::chem::{self, BondKind, Molecule};
+
+#[derive(Clone, Debug)]
+enum Expr<P> { Prim(P), Not(Box<Expr<P>>), And(Vec<Expr<P>>), Or(Vec<Expr<P>>) }
+
+impl<P> Expr<P> {
+    fn eval(&self, f: &impl Fn(&P) -> bool) -> bool {
+        match self {
+            Self::Prim(p) => f(p),
+            Self::Not(e) => !e.eval(f),
+            Self::And(es) => es.iter().all(|e| e.eval(f)),
+            Self::Or(es) => es.iter().any(|e| e.eval(f)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum AtomPrim { Any, Aromatic(bool), Element(String, bool), Number(u8), Hydrogens(u8), Degree(u8), Connections(u8), InRing(bool), RingSize(usize), Charge(i8), Isotope(u16) }
+
+#[derive(Clone, Debug)]
+enum BondPrim { Any, Kind(BondKind), Ring }
+
+/// A parsed query.
+#[derive(Clone, Debug)]
+pub struct Pattern { atoms: Vec<Expr<AtomPrim>>, bonds: Vec<(usize, usize, Expr<BondPrim>)> }
+
+/// What the primitives ask of one atom.
+struct AtomInfo { hydrogens: u8, degree: u8, connections: u8, in_ring: bool, ring_sizes: Vec<usize> }
+
+/// A molecule with the ring and connectivity facts queries test, worked out once for every
+/// pattern run against it.
+pub struct Target<'a> { m: &'a Molecule, ring_bond: Vec<bool>, info: Vec<AtomInfo> }
+
+impl<'a> Target<'a> {
+    pub fn new(m: &'a Molecule) -> Self {
+        let adj = m.neighbors();
+        let ring_bond: Vec<bool> = (0..m.bonds.len()).map(|b| m.bond_in_ring(b)).collect();
+        let rings = m.rings(MAX_RING);
+        let info = m.atoms.iter().enumerate().map(|(i, a)| {
+            let explicit_h = adj[i].iter().filter(|e| m.atoms[e.0].element == "H").count() as u8;
+            AtomInfo {
+                hydrogens: a.hydrogens + explicit_h, degree: adj[i].len() as u8 - explicit_h, connections: adj[i].len() as u8 + a.hydrogens,
+                in_ring: m.bonds.iter().enumerate().any(|(b, bond)| (bond.a == i || bond.b == i) && ring_bond[b]),
+                ring_sizes: rings.iter().filter(|r| r.contains(&i)).map(Vec::len).collect(),
+            }
+        }).collect();
+        Self { m, ring_bond, info }
+    }
+}
+
+const ORGANIC: &[&str] = &["Cl", "Br", "B", "C", "N", "O", "P", "S", "F", "I"];
+const AROMATIC: &[&str] = &["se", "as", "b", "c", "n", "o", "p", "s"];
+const MAX_RING: usize = 8;
+
+/// Splits `s` on `sep` at the top level, then parses each part with `part`.
+fn split<P>(s: &str, sep: char, part: &dyn Fn(&str) -> Result<Expr<P>, String>) -> Result<Expr<P>, String> {
+    let mut parts: Vec<Expr<P>> = s.split(sep).map(part).collect::<Result<_, _>>()?;
+    Ok(if parts.len() == 1 { parts.remove(0) } else if sep == ',' { Expr::Or(parts) } else { Expr::And(parts) })
+}
+
+fn count(s: &[u8], i: &mut usize) -> Option<u32> {
+    let start = *i;
+    while *i < s.len() && s[*i].is_ascii_digit() { *i += 1; }
+    std::str::from_utf8(&s[start..*i]).ok()?.parse().ok()
+}
+
+/// A run of implicitly and-ed atom primitives, each possibly negated.
+fn atom_primitives(t: &str) -> Result<Expr<AtomPrim>, String> {
+    let s = t.as_bytes();
+    let (mut out, mut i) = (Vec::new(), 0);
+    while i < s.len() {
+        let mut negate = false;
+        while s.get(i) == Some(&b'!') { negate = !negate; i += 1; }
+        let c = *s.get(i).ok_or_else(|| format!("[{t}] ends in '!'"))?;
+        i += 1;
+        let n = |i: &mut usize, default: u32| count(s, i).unwrap_or(default).min(255) as u8;
+        let prim = match c {
+            b'*' => AtomPrim::Any,
+            b'a' if !matches!(s.get(i), Some(b's')) => AtomPrim::Aromatic(true),
+            b'A' if s.get(i).is_none_or(|&l| chem::atomic_number(&format!("A{}", l as char)).is_none()) => AtomPrim::Aromatic(false),
+            b'#' => AtomPrim::Number(count(s, &mut i).ok_or_else(|| format!("[{t}]: '#' needs an atomic number"))?.min(255) as u8),
+            b'0'..=b'9' => { i -= 1; AtomPrim::Isotope(count(s, &mut i).unwrap_or(0).min(u16::MAX as u32) as u16) }
+            b'H' if t == "H" || t.trim_start_matches(|c: char| c.is_ascii_digit()) == "H" => AtomPrim::Element("H".into(), false),
+            b'H' => AtomPrim::Hydrogens(n(&mut i, 1)),
+            b'D' => AtomPrim::Degree(n(&mut i, 1)),
+            b'X' => AtomPrim::Connections(n(&mut i, 1)),
+            b'R' => match count(s, &mut i) { None => AtomPrim::InRing(true), Some(0) => AtomPrim::InRing(false), Some(_) => return Err(format!("[{t}]: only R and R0 are supported, not ring counts")) },
+            b'r' => AtomPrim::RingSize(count(s, &mut i).ok_or_else(|| format!("[{t}]: 'r' needs a ring size"))? as usize),
+            b'+' | b'-' => {
+                let sign: i8 = if c == b'+' { 1 } else { -1 };
+                let mut k = 1;
+                while s.get(i) == Some(&c) { k += 1; i += 1; }
+                AtomPrim::Charge(sign * count(s, &mut i).map_or(k, |v| v.min(9) as i8))
+            }
+            b'$' => return Err("recursive SMARTS ($(...)) is not supported".into()),
+            b'@' => return Err("chirality in SMARTS is not supported".into()),
+            b'A'..=b'Z' => {
+                let two = s.get(i).filter(|c| c.is_ascii_lowercase()).map(|&l| format!("{}{}", c as char, l as char)).filter(|sym| chem::atomic_number(sym).is_some());
+                let sym = match two { Some(sym) => { i += 1; sym } None => (c as char).to_string() };
+                if chem::atomic_number(&sym).is_none() { return Err(format!("[{t}]: unknown element {sym}")); }
+                AtomPrim::Element(sym, false)
+            }
+            b'a'..=b'z' => {
+                let sym = AROMATIC.iter().find(|a| t[i - 1..].starts_with(**a)).ok_or_else(|| format!("[{t}]: unknown aromatic atom at '{}'", c as char))?;
+                i += sym.len() - 1;
+                AtomPrim::Element(capitalize(sym), true)
+            }
+            _ => return Err(format!("[{t}]: unexpected '{}'", c as char)),
+        };
+        out.push(if negate { Expr::Not(Box::new(Expr::Prim(prim))) } else { Expr::Prim(prim) });
+    }
+    Ok(if out.len() == 1 { out.remove(0) } else { Expr::And(out) })
+}
+
+fn capitalize(sym: &str) -> String { let mut c = sym.chars(); c.next().map_or_else(String::new, |f| f.to_ascii_uppercase().to_string() + c.as_str()) }
+
+fn atom_expr(t: &str) -> Result<Expr<AtomPrim>, String> {
+    if t.is_empty() { return Err("empty bracket atom".into()); }
+    split(t, ';', &|p| split(p, ',', &|p| split(p, '&', &atom_primitives)))
+}
+
+fn bond_primitives(t: &str) -> Result<Expr<BondPrim>, String> {
+    let mut out = Vec::new();
+    let mut negate = false;
+    for c in t.chars() {
+        let prim = match c {
+            '!' => { negate = !negate; continue }
+            '-' | '/' | '\\' => BondPrim::Kind(BondKind::Single),
+            '=' => BondPrim::Kind(BondKind::Double),
+            '#' => BondPrim::Kind(BondKind::Triple),
+            ':' => BondPrim::Kind(BondKind::Aromatic),
+            '~' => BondPrim::Any,
+            '@' => BondPrim::Ring,
+            _ => return Err(format!("unexpected bond symbol '{c}'")),
+        };
+        out.push(if negate { Expr::Not(Box::new(Expr::Prim(prim))) } else { Expr::Prim(prim) });
+        negate = false;
+    }
+    if negate || out.is_empty() { return Err(format!("incomplete bond '{t}'")); }
+    Ok(if out.len() == 1 { out.remove(0) } else { Expr::And(out) })
+}
+
+fn bond_expr(t: &str) -> Result<Expr<BondPrim>, String> {
+    split(t, ';', &|p| split(p, ',', &|p| split(p, '&', &bond_primitives)))
+}
+
+/// Single or aromatic, for bonds left unwritten.
+fn implicit_bond() -> Expr<BondPrim> { Expr::Or(vec![Expr::Prim(BondPrim::Kind(BondKind::Single)), Expr::Prim(BondPrim::Kind(BondKind::Aromatic))]) }
+
+impl Pattern {
+    pub fn parse(smarts: &str) -> Result<Self, String> {
+        let (s, mut i) = (smarts.as_bytes(), 0);
+        let mut p = Pattern { atoms: Vec::new(), bonds: Vec::new() };
+        let (mut prev, mut stack, mut bond): (Option<usize>, Vec<usize>, Option<Expr<BondPrim>>) = (None, Vec::new(), None);
+        let mut open: Vec<(u32, usize, Option<Expr<BondPrim>>)> = Vec::new();
+        while i < s.len() {
+            let c = s[i];
+            let atom = match c {
+                b'(' => { stack.push(prev.ok_or("branch before any atom")?); i += 1; continue }
+                b')' => { prev = Some(stack.pop().ok_or("unbalanced ')'")?); i += 1; continue }
+                b'.' => { prev = None; i += 1; continue }
+                b'-' | b'=' | b'#' | b':' | b'~' | b'@' | b'!' | b'/' | b'\\' | b'&' | b',' | b';' => {
+                    let start = i;
+                    while i < s.len() && b"-=#:~@!/\\&,;".contains(&s[i]) { i += 1; }
+                    bond = Some(bond_expr(&smarts[start..i])?);
+                    continue;
+                }
+                b'0'..=b'9' | b'%' => {
+                    let n = if c == b'%' { i += 1; let start = i; i = (i + 2).min(s.len()); smarts[start..i].parse().map_err(|_| format!("bad ring closure in {smarts}"))? } else { i += 1; (c - b'0') as u32 };
+                    let here = prev.ok_or("ring closure before any atom")?;
+                    match open.iter().position(|o| o.0 == n) {
+                        Some(k) => {
+                            let (_, other, first) = open.remove(k);
+                            p.bonds.push((other, here, bond.take().or(first).unwrap_or_else(implicit_bond)));
+                        }
+                        None => open.push((n, here, bond.take())),
+                    }
+                    continue;
+                }
+                b'[' => {
+                    let end = smarts[i..].find(']').ok_or_else(|| format!("unclosed '[' in {smarts}"))? + i;
+                    let e = atom_expr(&smarts[i + 1..end])?;
+                    i = end + 1;
+                    e
+                }
+                b'*' => { i += 1; Expr::Prim(AtomPrim::Any) }
+                b'a' if s.get(i + 1) != Some(&b's') => { i += 1; Expr::Prim(AtomPrim::Aromatic(true)) }
+                b'A' => { i += 1; Expr::Prim(AtomPrim::Aromatic(false)) }
+                _ => {
+                    let rest = &smarts[i..];
+                    if let Some(sym) = ORGANIC.iter().find(|o| rest.starts_with(**o)) { i += sym.len(); Expr::Prim(AtomPrim::Element(sym.to_string(), false)) }
+                    else if let Some(sym) = AROMATIC.iter().find(|a| rest.starts_with(**a)) { i += sym.len(); Expr::Prim(AtomPrim::Element(capitalize(sym), true)) }
+                    else { return Err(format!("unexpected '{}' in {smarts}", c as char)); }
+                }
+            };
+            let idx = p.atoms.len();
+            p.atoms.push(atom);
+            if let Some(a) = prev { p.bonds.push((a, idx, bond.take().unwrap_or_else(implicit_bond))); }
+            else if bond.is_some() { return Err(format!("bond without a preceding atom in {smarts}")); }
+            prev = Some(idx);
+        }
+        if !stack.is_empty() { return Err(format!("unclosed '(' in {smarts}")); }
+        if let Some(o) = open.first() { return Err(format!("ring closure {} is never closed in {smarts}", o.0)); }
+        if bond.is_some() { return Err(format!("{smarts} ends in a bond")); }
+        if p.atoms.is_empty() { return Err("empty SMARTS".into()); }
+        Ok(p)
+    }
+
+    /// The molecule's atoms matched by the query's, in query order, for the first match.
+    pub fn find(&self, t: &Target) -> Option<Vec<usize>> {
+        let m = t.m;
+        let atom_ok = |q: usize, i: usize| {
+            let (a, x) = (&m.atoms[i], &t.info[i]);
+            self.atoms[q].eval(&|p: &AtomPrim| match p {
+                AtomPrim::Any => true,
+                AtomPrim::Aromatic(ar) => a.aromatic == *ar,
+                AtomPrim::Element(sym, ar) => a.element == *sym && a.aromatic == *ar,
+                AtomPrim::Number(z) => chem::atomic_number(&a.element) == Some(*z),
+                AtomPrim::Hydrogens(h) => x.hydrogens == *h,
+                AtomPrim::Degree(d) => x.degree == *d,
+                AtomPrim::Connections(n) => x.connections == *n,
+                AtomPrim::InRing(r) => x.in_ring == *r,
+                AtomPrim::RingSize(n) => x.ring_sizes.contains(n),
+                AtomPrim::Charge(c) => a.charge == *c,
+                AtomPrim::Isotope(v) => a.isotope == Some(*v),
+            })
+        };
+        let bond_ok = |q: usize, b: usize| {
+            self.bonds[q].2.eval(&|p: &BondPrim| match p { BondPrim::Any => true, BondPrim::Kind(k) => m.bonds[b].kind == *k, BondPrim::Ring => t.ring_bond[b] })
+        };
+        let mut qadj = vec![Vec::new(); self.atoms.len()];
+        for (k, &(a, b, _)) in self.bonds.iter().enumerate() { qadj[a].push((b, k)); qadj[b].push((a, k)); }
+        m.find(&qadj, &atom_ok, &bond_ok)
+    }
+}
@@ -0,0 +1,102 @@
+//! Staged simulation protocols.
+//!
+//! A protocol may list stages that run one after another on the same structure: `minimize`
+//! (steepest descent), `heat` (Langevin dynamics with the thermostat ramped from
+//! `temperature_start_k` to the target in ten increments), and `nvt` and `npt` dynamics at the
+//! target temperature. Every stage can hold the heavy atoms near where it started with a
+//! harmonic `restraint_k` (kcal/mol/Å², E = k·d²). The engine runs in vacuo, so an NPT stage's
+//! `pressure_bar` is recorded but has no box to act on and samples like NVT. Each stage reports
+//! its force field energy before and after, the mean over its dynamics, and the fitted RMSD of
+//! its end structure from the one it started with.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{chem, conformer, fnv1a, forcefield::{self, System}, frame, resolver};
+
+pub const KINDS: [&str; 4] = ["minimize", "heat", "nvt", "npt"];
+/// Dynamics steps across all stages of one run.
+const MAX_STEPS: u64 = 200_000;
+const HEAT_RAMPS: usize = 10;
+const STRIDE: usize = 10;
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Stage { pub name: String, #[serde(rename = "type")] pub kind: String, pub steps: u64, pub temperature_start_k: Option<f64>, pub restraint_k: Option<f64>, pub pressure_bar: Option<f64> }
+
+#[derive(Serialize, Clone)]
+pub struct StageReport {
+    pub name: String, #[serde(rename = "type")] pub kind: String, #[serde(skip_serializing_if = "Option::is_none")] pub ensemble: Option<&'static str>, pub steps: u64,
+    #[serde(skip_serializing_if = "Option::is_none")] pub temperature_start_k: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] pub temperature_k: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] pub pressure_bar: Option<f64>, pub restraint_k: f64,
+    pub energy_start_kcal_mol: f64, pub energy_end_kcal_mol: f64, #[serde(skip_serializing_if = "Option::is_none")] pub energy_mean_kcal_mol: Option<f64>, pub rmsd_angstrom: f64,
+}
+
+fn stage(name: &str, kind: &str, steps: u64, temperature_start_k: Option<f64>, restraint_k: f64, pressure_bar: Option<f64>) -> Stage {
+    Stage { name: name.into(), kind: kind.into(), steps, temperature_start_k, restraint_k: Some(restraint_k), pressure_bar }
+}
+
+/// `standard-equilibration`: minimize, heat from 10 K under heavy-atom restraints, equilibrate
+/// at constant volume with weaker ones, then unrestrained NPT production.
+pub fn standard() -> Vec<Stage> {
+    vec![
+        stage("minimize", "minimize", 500, None, 10.0, None),
+        stage("heat", "heat", 5_000, Some(10.0), 10.0, None),
+        stage("equilibrate", "nvt", 5_000, None, 1.0, None),
+        stage("production", "npt", 10_000, None, 0.0, Some(1.0)),
+    ]
+}
+
+pub fn validate(stages: &[Stage]) -> Result<(), String> {
+    for s in stages {
+        if !KINDS.contains(&s.kind.as_str()) { return Err(format!("stage {} has unknown type {}; expected one of {}", s.name, s.kind, KINDS.join(", "))); }
+        if s.steps == 0 { return Err(format!("stage {} needs steps", s.name)); }
+        if s.restraint_k.is_some_and(|k| k.is_nan() || k < 0.0) { return Err(format!("stage {}'s restraint_k must not be negative", s.name)); }
+        if s.temperature_start_k.is_some_and(|t| t.is_nan() || t < 0.0) { return Err(format!("stage {}'s temperature_start_k must not be negative", s.name)); }
+    }
+    let total: u64 = stages.iter().filter(|s| s.kind != "minimize").map(|s| s.steps).sum();
+    if total > MAX_STEPS { return Err(format!("the stages run {total} dynamics steps; at most {MAX_STEPS} are supported")); }
+    Ok(())
+}
+
+/// Runs `stages` on `mol` at `temperature_k`; `production_steps` replaces the last dynamics
+/// stage's steps.
+pub fn run(stages: &[Stage], mol: &resolver::Resolved, temperature_k: f64, production_steps: Option<u64>) -> Result<Vec<StageReport>, String> {
+    let smiles = mol.canonical_smiles.as_deref().ok_or_else(|| format!("can't resolve {} to a structure for a staged protocol", mol.input))?;
+    let m = chem::parse_smiles(smiles)?;
+    let mut stages = stages.to_vec();
+    if let (Some(n), Some(last)) = (production_steps, stages.iter_mut().rev().find(|s| s.kind != "minimize")) { last.steps = n; }
+    validate(&stages)?;
+    let bonded = conformer::restraints(&m);
+    let seed = fnv1a(smiles.as_bytes());
+    let mut x = conformer::embed(&m, seed);
+    let energy = |x: &[[f64; 3]]| forcefield::internal_energy(&bonded, x, None);
+    let round = |v: f64| (v * 1e3).round() / 1e3;
+    let mut reports = Vec::with_capacity(stages.len());
+    for (i, st) in stages.iter().enumerate() {
+        let anchor = x.clone();
+        let k = st.restraint_k.unwrap_or(0.0);
+        let system = System { restraints: &bonded, receptor: &frame::EMPTY, metals: None, anchor: &anchor, k_pos: k, bias: None };
+        let e0 = energy(&x);
+        let seed = seed ^ (i as u64 + 1);
+        let mut sum = 0.0;
+        let mut samples = 0;
+        let mut observe = |step: usize, x: &[[f64; 3]]| if step.is_multiple_of(STRIDE) { sum += energy(x); samples += 1; };
+        let (from, to) = match st.kind.as_str() {
+            "minimize" => { system.minimize(&mut x, st.steps as usize); (None, None) }
+            "heat" => {
+                let from = st.temperature_start_k.unwrap_or(0.0);
+                let n = st.steps as usize;
+                for r in 0..HEAT_RAMPS {
+                    let t = from + (temperature_k - from) * (r + 1) as f64 / HEAT_RAMPS as f64;
+                    system.sample(&mut x, n * (r + 1) / HEAT_RAMPS - n * r / HEAT_RAMPS, t, seed.rotate_left(r as u32), &mut observe);
+                }
+                (Some(from), Some(temperature_k))
+            }
+            _ => { system.sample(&mut x, st.steps as usize, temperature_k, seed, &mut observe); (None, Some(temperature_k)) }
+        };
+        reports.push(StageReport {
+            name: st.name.clone(), kind: st.kind.clone(), ensemble: match st.kind.as_str() { "minimize" => None, "npt" => Some("NPT"), _ => Some("NVT") }, steps: st.steps,
+            temperature_start_k: from, temperature_k: to, pressure_bar: (st.kind == "npt").then(|| st.pressure_bar.unwrap_or(1.0)), restraint_k: k,
+            energy_start_kcal_mol: round(e0), energy_end_kcal_mol: round(energy(&x)), energy_mean_kcal_mol: (samples > 0).then(|| round(sum / samples as f64)), rmsd_angstrom: round(forcefield::fitted_rmsd(&anchor, &x)),
+        });
+    }
+    Ok(reports)
+}
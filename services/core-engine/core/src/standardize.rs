@@ -0,0 +1,150 @@
+//! Standardization of incoming molecules.
+//!
+//! Every molecule a compute request names is resolved (see `resolver`) and then standardized
+//! before the engine sees it, so one compound drawn as different salts, protonation states or
+//! spellings of a functional group computes and compares as one structure. `normalize` rewrites
+//! pentavalent nitro groups (`N(=O)=O`) and azides (`N=N#N`, `[N-][N+]#N`) in the
+//! charge-separated forms `[N+](=O)[O-]` and `N=[N+]=[N-]`. `neutralize` gives charged acids a
+//! hydrogen and takes one from protonated bases; quaternary atoms, and charges balanced by a
+//! bonded neighbour (nitro groups, N-oxides), stay. `strip_salts` keeps only the largest
+//! fragment by heavy atoms, dropping counter-ions and solvent. In a batch, `deduplicate` catches a
+//! molecule whose standardized structure repeats an earlier one: a library upload drops it and
+//! `POST /api/v1/bio/standardize` marks it `duplicate_of`.
+//!
+//! Each molecule carries a change log, `standardization`, that names atoms by their position in
+//! the resolved canonical SMILES. Every step is on by default; `PUT
+//! /api/v1/bio/projects/{id}/standardization` turns steps off for a project. Format conversion
+//! and depiction show a molecule as given and are not standardized.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{chem::{self, Bond, BondKind, Molecule}, resolver::Resolved};
+
+fn on() -> bool { true }
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct Settings { #[serde(default = "on")] pub normalize: bool, #[serde(default = "on")] pub neutralize: bool, #[serde(default = "on")] pub strip_salts: bool, #[serde(default = "on")] pub deduplicate: bool }
+
+impl Default for Settings {
+    fn default() -> Self { Self { normalize: true, neutralize: true, strip_salts: true, deduplicate: true } }
+}
+
+fn label(m: &Molecule, i: usize) -> String { format!("{}{}", m.atoms[i].element, i + 1) }
+
+/// The atom bond `b` joins to `atom`.
+fn partner(m: &Molecule, b: usize, atom: usize) -> usize { if m.bonds[b].a == atom { m.bonds[b].b } else { m.bonds[b].a } }
+
+/// Rewrites pentavalent nitro groups and azides in their charge-separated forms.
+fn normalize(m: &mut Molecule, keep: &[bool], changes: &mut Vec<String>) {
+    for n in (0..m.atoms.len()).filter(|&n| keep[n]) {
+        if m.atoms[n].element != "N" { continue; }
+        let bonds: Vec<usize> = (0..m.bonds.len()).filter(|&b| m.bonds[b].a == n || m.bonds[b].b == n).collect();
+        let find = |kind: BondKind, element: &str, charge: i8| bonds.iter().copied().filter(|&b| m.bonds[b].kind == kind && m.atoms[partner(m, b, n)].element == element && m.atoms[partner(m, b, n)].charge == charge).collect::<Vec<_>>();
+        // The terminal nitrogen of an azide, bonded to nothing else.
+        let terminal = find(BondKind::Triple, "N", 0).into_iter().find(|&b| m.bond_valence(partner(m, b, n)) == 3);
+        match m.atoms[n].charge {
+            0 => {
+                let oxo = find(BondKind::Double, "O", 0);
+                if oxo.len() == 2 {
+                    let o = partner(m, oxo[1], n);
+                    m.bonds[oxo[1]].kind = BondKind::Single;
+                    (m.atoms[n].charge, m.atoms[o].charge) = (1, -1);
+                    changes.push(format!("nitro group at {} written as [N+](=O)[O-]", label(m, n)));
+                } else if let (Some(t), false) = (terminal, find(BondKind::Double, "N", 0).is_empty()) {
+                    let end = partner(m, t, n);
+                    m.bonds[t].kind = BondKind::Double;
+                    (m.atoms[n].charge, m.atoms[end].charge) = (1, -1);
+                    changes.push(format!("azide at {} written as N=[N+]=[N-]", label(m, n)));
+                }
+            }
+            1 => {
+                if let (Some(t), Some(&s)) = (terminal, find(BondKind::Single, "N", -1).first()) {
+                    let (start, end) = (partner(m, s, n), partner(m, t, n));
+                    (m.bonds[s].kind, m.bonds[t].kind) = (BondKind::Double, BondKind::Double);
+                    (m.atoms[start].charge, m.atoms[end].charge) = (0, -1);
+                    changes.push(format!("azide at {} written as N=[N+]=[N-]", label(m, n)));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Neutralizes charged acids and protonated bases whose charge no bonded neighbour balances.
+fn neutralize(m: &mut Molecule, keep: &[bool], changes: &mut Vec<String>) {
+    let adj = m.neighbors();
+    let charges: Vec<i8> = m.atoms.iter().map(|a| a.charge).collect();
+    for i in 0..m.atoms.len() {
+        let c = charges[i];
+        if !keep[i] || c == 0 || adj[i].iter().any(|&(j, _)| charges[j].signum() == -c.signum()) { continue; }
+        let a = &mut m.atoms[i];
+        match (c, a.element.as_str()) {
+            (-1, "O" | "S" | "Se" | "N" | "P") => a.hydrogens += 1,
+            (1, "N" | "P") if a.hydrogens > 0 => a.hydrogens -= 1,
+            _ => continue,
+        }
+        a.charge = 0;
+        changes.push(format!("neutralized {} ({c:+})", label(m, i)));
+    }
+}
+
+/// The connected fragment of each atom.
+fn fragments(m: &Molecule) -> Vec<usize> {
+    let adj = m.neighbors();
+    let mut fragment = vec![usize::MAX; m.atoms.len()];
+    let mut next = 0;
+    for start in 0..m.atoms.len() {
+        if fragment[start] != usize::MAX { continue; }
+        let mut stack = vec![start];
+        fragment[start] = next;
+        while let Some(u) = stack.pop() {
+            for &(v, _) in &adj[u] { if fragment[v] == usize::MAX { fragment[v] = next; stack.push(v); } }
+        }
+        next += 1;
+    }
+    fragment
+}
+
+/// The atoms of `m` marked in `keep`, with the bonds between them.
+fn subgraph(m: &Molecule, keep: &[bool]) -> Molecule {
+    let mut index = vec![usize::MAX; m.atoms.len()];
+    let mut out = Molecule::default();
+    for (i, a) in m.atoms.iter().enumerate().filter(|(i, _)| keep[*i]) { index[i] = out.atoms.len(); out.atoms.push(a.clone()); }
+    out.bonds = m.bonds.iter().filter(|b| keep[b.a] && keep[b.b]).map(|b| Bond { a: index[b.a], b: index[b.b], kind: b.kind }).collect();
+    out
+}
+
+/// `m` standardized under `settings`, with what was changed.
+pub fn molecule(mut m: Molecule, settings: Settings) -> (Molecule, Vec<String>) {
+    let mut changes = Vec::new();
+    let fragment = fragments(&m);
+    let count = fragment.iter().max().map_or(0, |&f| f + 1);
+    let mut keep = vec![true; m.atoms.len()];
+    if settings.strip_salts && count > 1 {
+        // The fragment with most heavy atoms, then most atoms; the first of any tie.
+        let size = |f: usize| { let atoms = m.atoms.iter().zip(&fragment).filter(|(_, &g)| g == f); (atoms.clone().filter(|(a, _)| a.element != "H").count(), atoms.count()) };
+        let largest = (0..count).rev().max_by_key(|&f| size(f)).unwrap_or(0);
+        for f in (0..count).filter(|&f| f != largest) {
+            let dropped: Vec<bool> = fragment.iter().map(|&g| g == f).collect();
+            changes.push(format!("removed fragment {}", subgraph(&m, &dropped).to_canonical_smiles()));
+        }
+        keep = fragment.iter().map(|&g| g == largest).collect();
+    }
+    if settings.normalize { normalize(&mut m, &keep, &mut changes); }
+    if settings.neutralize { neutralize(&mut m, &keep, &mut changes); }
+    if keep.iter().all(|&k| k) { (m, changes) } else { (subgraph(&m, &keep), changes) }
+}
+
+/// Canonical SMILES standardized under `settings`, with what was changed.
+pub fn smiles(smiles: &str, settings: Settings) -> Result<(String, Vec<String>), String> {
+    let (m, changes) = molecule(chem::parse_smiles(smiles)?, settings);
+    Ok((if changes.is_empty() { smiles.to_string() } else { m.to_canonical_smiles() }, changes))
+}
+
+/// Standardizes a resolved molecule in place under `settings`.
+pub fn apply(r: &mut Resolved, settings: Settings) {
+    let Some(Ok((smiles, changes))) = r.canonical_smiles.as_deref().map(|smi| smiles(smi, settings)) else { return };
+    if changes.is_empty() { return; }
+    r.canonical_smiles = Some(smiles);
+    r.standardization = changes;
+}
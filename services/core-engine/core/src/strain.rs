@@ -0,0 +1,35 @@
+//! Ligand strain energy of docked poses.
+//!
+//! Strain is the internal energy of the bound pose, locally relaxed under a tight positional
+//! restraint so only bond-level noise is removed, minus the internal energy of the global
+//! minimum found by minimizing several independently embedded conformers. Docked poses that
+//! need many kcal/mol to adopt are usually artifacts.
+
+use serde::Serialize;
+
+use crate::{chem::Molecule, conformer, forcefield::{self, System}, frame};
+
+const CONFORMERS: u64 = 10;
+
+#[derive(Serialize, Clone, Copy)]
+pub struct Strain { pub bound_energy: f64, pub global_minimum_energy: f64, pub strain_kcal_mol: f64 }
+
+/// Lowest internal energy over `CONFORMERS` minimized embeddings.
+pub fn global_minimum(mol: &Molecule, restraints: &[conformer::Restraint]) -> f64 {
+    (0..CONFORMERS).map(|seed| {
+        let mut x = conformer::embed(mol, seed);
+        let anchor = x.clone();
+        System { restraints, receptor: &frame::EMPTY, metals: None, anchor: &anchor, k_pos: 0.0, bias: None }.minimize(&mut x, 1000);
+        forcefield::internal_energy(restraints, &x, None)
+    }).fold(f64::INFINITY, f64::min)
+}
+
+pub fn strain(mol: &Molecule, pose: &[[f64; 3]]) -> Strain {
+    let restraints = conformer::restraints(mol);
+    let mut x = pose.to_vec();
+    System { restraints: &restraints, receptor: &frame::EMPTY, metals: None, anchor: pose, k_pos: 5.0, bias: None }.minimize(&mut x, 300);
+    let bound = forcefield::internal_energy(&restraints, &x, None);
+    // A pose the search can't beat is itself the best conformer found.
+    let global = global_minimum(mol, &restraints).min(bound);
+    Strain { bound_energy: bound, global_minimum_energy: global, strain_kcal_mol: bound - global }
+}
@@ -0,0 +1,112 @@
+//! Signal peptides and transmembrane topology.
+//!
+//! Signal peptides follow von Heijne's tripartite model: a positively charged n-region, a
+//! hydrophobic h-region of 7-15 residues, and a polar c-region ending at a cleavage site with
+//! small residues at -1 and -3. Transmembrane helices are 19-residue Kyte-Doolittle windows
+//! averaging at least 1.6. Orientation follows the positive-inside rule: the arrangement
+//! that puts more Lys/Arg in cytoplasmic loops wins, unless a signal peptide fixes the mature
+//! N-terminus outside. The per-residue `topology` string uses S (signal), i (inside),
+//! o (outside) and M (membrane).
+
+use serde::Serialize;
+
+const AMINO: &[u8] = b"ARNDCQEGHILKMFPSTWYV";
+const KYTE_DOOLITTLE: [f64; 20] = [1.8, -4.5, -3.5, -3.5, 2.5, -3.5, -3.5, -0.4, -3.2, 4.5, 3.8, -3.9, 1.9, 2.8, -1.6, -0.8, -0.7, -0.9, -1.3, 4.2];
+const TM_WINDOW: usize = 19;
+const TM_THRESHOLD: f64 = 1.6;
+/// Hydrophobic stretches longer than this hold more than one helix.
+const MAX_HELIX: usize = 35;
+const H_WINDOW: usize = 8;
+const H_THRESHOLD: f64 = 1.8;
+const SIGNAL_ANCHOR_THRESHOLD: f64 = 2.2;
+
+#[derive(Serialize, Clone)]
+pub struct SignalPeptide { pub cleavage_site: usize, pub n_region: [usize; 2], pub h_region: [usize; 2], pub c_region: [usize; 2], pub sequence: String, pub score: f64 }
+#[derive(Serialize, Clone)]
+pub struct TmHelix { pub start: usize, pub end: usize, pub sequence: String, pub hydropathy: f64, pub orientation: &'static str }
+#[derive(Serialize, Clone)]
+pub struct TopologyReport { pub location: &'static str, #[serde(skip_serializing_if = "Option::is_none")] pub signal_peptide: Option<SignalPeptide>, pub tm_helices: Vec<TmHelix>, pub n_terminus: &'static str, pub topology: String }
+
+pub fn kd(r: u8) -> f64 { AMINO.iter().position(|&a| a == r).map_or(0.0, |i| KYTE_DOOLITTLE[i]) }
+fn mean_kd(s: &[u8]) -> f64 { s.iter().map(|&r| kd(r)).sum::<f64>() / s.len().max(1) as f64 }
+fn positives(s: &[u8]) -> usize { s.iter().filter(|&&r| r == b'K' || r == b'R').count() }
+
+/// Signal peptide with its cleavage site (index of the first mature residue).
+fn signal_peptide(seq: &[u8]) -> Option<SignalPeptide> {
+    let charged = |r: u8| matches!(r, b'D' | b'E' | b'K' | b'R');
+    // h-region: first uncharged hydrophobic 8-residue window starting in the first 25 residues,
+    // extended through residues that aren't strongly polar.
+    let h_start = (1..25.min(seq.len().saturating_sub(H_WINDOW))).find(|&i| mean_kd(&seq[i..i + H_WINDOW]) >= H_THRESHOLD && !seq[i..i + H_WINDOW].iter().any(|&r| charged(r)))?;
+    let mut h_end = h_start + H_WINDOW - 1;
+    while h_end + 1 < seq.len() && h_end - h_start < 14 && kd(seq[h_end + 1]) > -1.0 && !charged(seq[h_end + 1]) { h_end += 1; }
+    // A long, strongly hydrophobic core is an uncleaved signal anchor (TM) instead.
+    if mean_kd(&seq[h_start..(h_start + 20).min(seq.len())]) >= SIGNAL_ANCHOR_THRESHOLD && h_end - h_start >= 14 { return None; }
+    // n-regions are rarely net negative, c-regions rarely acidic; both are common in
+    // cytoplasmic N-termini.
+    let acidic = |s: &[u8]| s.iter().filter(|&&r| r == b'D' || r == b'E').count();
+    if acidic(&seq[..h_start]) > positives(&seq[..h_start]) + 1 { return None; }
+    let best = (h_end + 2..=h_end + 10).filter(|&c| (15..=45).contains(&c) && c < seq.len()).map(|c| {
+        let m1: f64 = match seq[c - 1] { b'A' => 0.3, b'G' | b'S' => 0.25, b'C' | b'T' => 0.15, _ => 0.0 };
+        let m3 = match seq[c - 3] { b'A' | b'V' => 0.2, b'S' | b'T' | b'G' | b'C' | b'I' | b'L' => 0.1, _ => 0.0 };
+        // Proline at +1 or a charged -2 hinder the signal peptidase.
+        let penalty = if seq.get(c) == Some(&b'P') { 0.2 } else { 0.0 } + if matches!(seq[c - 2], b'K' | b'R' | b'D' | b'E') { 0.05 } else { 0.0 };
+        (c, m1 + m3 - penalty)
+    }).filter(|&(c, s)| s > 0.2 && matches!(seq[c - 1], b'A' | b'G' | b'S' | b'C' | b'T') && acidic(&seq[h_end + 1..c]) < 2).max_by(|a, b| a.1.total_cmp(&b.1))?;
+    let (c, site) = best;
+    let n_bonus = if positives(&seq[..h_start]) > 0 { 0.15 } else { 0.0 };
+    let score = ((0.35 + site + n_bonus + (mean_kd(&seq[h_start..=h_end]) - H_THRESHOLD).clamp(0.0, 1.0) * 0.1).min(0.99) * 100.0).round() / 100.0;
+    Some(SignalPeptide { cleavage_site: c, n_region: [0, h_start - 1], h_region: [h_start, h_end], c_region: [h_end + 1, c - 1], sequence: String::from_utf8_lossy(&seq[..c]).into(), score })
+}
+
+/// Hydrophobic spans as (start, end) inclusive, from `from` on.
+fn tm_spans(seq: &[u8], from: usize) -> Vec<(usize, usize)> {
+    if seq.len() < TM_WINDOW { return Vec::new(); }
+    let mut spans: Vec<(usize, usize)> = Vec::new();
+    for i in from..=seq.len() - TM_WINDOW {
+        if mean_kd(&seq[i..i + TM_WINDOW]) < TM_THRESHOLD { continue; }
+        match spans.last_mut() {
+            Some(last) if i <= last.1 + 1 => last.1 = i + TM_WINDOW - 1,
+            _ => spans.push((i, i + TM_WINDOW - 1)),
+        }
+    }
+    // Long stretches hold several helices back to back; polar ends beyond a 21-residue core
+    // stay in the loops.
+    spans.into_iter().flat_map(|(s, e)| {
+        let len = e - s + 1;
+        let n = if len > MAX_HELIX { (len as f64 / 23.0).round() as usize } else { 1 };
+        (0..n).map(move |k| (s + k * len / n, s + (k + 1) * len / n - 1))
+    }).map(|(mut s, mut e)| {
+        while e - s >= 21 && (kd(seq[s]) < 0.0 || kd(seq[e]) < 0.0) { if kd(seq[s]) < kd(seq[e]) { s += 1 } else { e -= 1 } }
+        (s, e)
+    }).collect()
+}
+
+pub fn predict(sequence: &str) -> TopologyReport {
+    let seq: Vec<u8> = sequence.bytes().map(|b| b.to_ascii_uppercase()).collect();
+    let signal = signal_peptide(&seq);
+    let mature = signal.as_ref().map_or(0, |s| s.cleavage_site);
+    let spans = tm_spans(&seq, mature);
+    // Loops between helices, N-terminal first; positive-inside decides which set is cytoplasmic.
+    let mut bounds = vec![mature];
+    for &(s, e) in &spans { bounds.push(s); bounds.push(e + 1); }
+    bounds.push(seq.len());
+    let loop_kr: Vec<i64> = bounds.chunks(2).map(|b| positives(&seq[b[0]..b[1].max(b[0])]) as i64).collect();
+    let even_minus_odd: i64 = loop_kr.iter().enumerate().map(|(k, &n)| if k % 2 == 0 { n } else { -n }).sum();
+    let n_inside = if signal.is_some() { false } else if spans.is_empty() { true } else { even_minus_odd >= 0 };
+    let tm_helices = spans.iter().enumerate().map(|(k, &(s, e))| {
+        let entering_from_inside = n_inside == (k % 2 == 0);
+        TmHelix { start: s, end: e, sequence: String::from_utf8_lossy(&seq[s..=e]).into(), hydropathy: (mean_kd(&seq[s..=e]) * 100.0).round() / 100.0, orientation: if entering_from_inside { "in->out" } else { "out->in" } }
+    }).collect();
+    let mut topology = vec![b'S'; mature];
+    let mut inside = n_inside;
+    let mut cursor = mature;
+    for &(s, e) in &spans {
+        topology.extend(std::iter::repeat_n(if inside { b'i' } else { b'o' }, s - cursor));
+        topology.extend(std::iter::repeat_n(b'M', e - s + 1));
+        inside = !inside;
+        cursor = e + 1;
+    }
+    topology.extend(std::iter::repeat_n(if inside { b'i' } else { b'o' }, seq.len() - cursor));
+    let location = match (spans.is_empty(), signal.is_some()) { (false, _) => "membrane", (true, true) => "secreted", (true, false) => "cytoplasmic" };
+    TopologyReport { location, signal_peptide: signal, tm_helices, n_terminus: if n_inside { "cytoplasmic" } else { "extracellular" }, topology: String::from_utf8_lossy(&topology).into() }
+}
@@ -0,0 +1,193 @@
+//! Umbrella sampling and the potential of mean force.
+//!
+//! The reaction coordinate (a distance, angle or dihedral; see `cv`) is split into windows
+//! from `start` to `end`, each restraining it with ½k(ξ − ξ₀)² around its centre. Windows run
+//! as one steered trajectory: the restraint centre is pulled linearly from one window's centre
+//! to the next, with the work done reported, then the window equilibrates and samples with
+//! Langevin dynamics on the engine's force field. WHAM unbiases and combines the windows'
+//! histograms into the potential of mean force, zeroed at its minimum. Dihedral windows span
+//! the full circle unless `start` and `end` are given. Without a `force_constant`, each
+//! window's restraint is as stiff as lets neighbouring windows overlap (σ = ⅔ spacing).
+
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+
+use crate::{chem, conformer, cv::{Cv, CvSpec}, fnv1a, forcefield::System, frame, resolver, restraints::Restraints};
+
+pub const KB: f64 = 0.001_987_2; // kcal/mol/K
+const DEFAULT_WINDOWS: usize = 16;
+const MAX_WINDOWS: usize = 64;
+const DEFAULT_STEPS: usize = 2000;
+const DEFAULT_PULL_STEPS: usize = 200;
+const MAX_TOTAL_STEPS: usize = 500_000;
+const DEFAULT_BINS: usize = 60;
+/// Steps between recorded samples.
+const STRIDE: usize = 5;
+const WHAM_TOLERANCE: f64 = 1e-7;
+const WHAM_MAX_ITERATIONS: usize = 100_000;
+
+#[derive(Deserialize, Clone)]
+pub struct Umbrella { pub reaction_coordinate: CvSpec, pub start: Option<f64>, pub end: Option<f64>, pub windows: Option<usize>, pub force_constant: Option<f64>, pub steps_per_window: Option<usize>, pub pull_steps: Option<usize>, pub bins: Option<usize> }
+
+#[derive(Serialize, Clone)]
+pub struct Window { pub center: f64, pub force_constant: f64, pub samples: usize, pub mean: f64, pub std_dev: f64 }
+#[derive(Serialize, Clone)]
+pub struct PmfPoint { pub coordinate: f64, pub pmf_kcal_mol: f64, pub samples: usize }
+#[derive(Serialize, Clone)]
+pub struct Pmf { pub reaction_coordinate: String, pub unit: &'static str, pub force_constant_unit: &'static str, pub temperature_k: f64, pub windows: Vec<Window>, pub steered_work_kcal_mol: f64, pub profile: Vec<PmfPoint>, pub barrier_kcal_mol: f64, pub wham_iterations: usize, pub converged: bool, pub min_overlap: f64, pub warnings: Vec<String> }
+
+/// A histogram over the reaction coordinate: `bins` equal bins from `lo`, wrapping around
+/// when it covers a full dihedral circle.
+pub struct Grid { pub cv: Cv, pub lo: f64, pub width: f64, pub bins: usize, pub wrap: bool }
+
+impl Grid {
+    pub fn new(cv: Cv, lo: f64, hi: f64, bins: usize, wrap: bool) -> Self { Self { cv, lo, width: (hi - lo).max(1e-9) / bins as f64, bins, wrap } }
+    pub fn center(&self, b: usize) -> f64 { self.lo + (b as f64 + 0.5) * self.width }
+    pub fn bin(&self, v: f64) -> Option<usize> {
+        let span = self.width * self.bins as f64;
+        let off = if self.wrap { (v - self.lo).rem_euclid(span) } else { v - self.lo };
+        (-1e-9..=span + 1e-9).contains(&off).then(|| ((off.max(0.0) / self.width) as usize).min(self.bins - 1))
+    }
+}
+
+/// Unbiased free energy per bin from each window's `(centre, k, samples)` by WHAM; `None`
+/// where no window sampled. Returns the profile, iterations and whether it converged.
+fn wham(grid: &Grid, windows: &[(f64, f64, Vec<f64>)], kt: f64) -> (Vec<Option<f64>>, Vec<Vec<usize>>, usize, bool) {
+    let hist: Vec<Vec<usize>> = windows.iter().map(|(_, _, xs)| {
+        let mut h = vec![0; grid.bins];
+        for &x in xs { if let Some(b) = grid.bin(x) { h[b] += 1; } }
+        h
+    }).collect();
+    let counts: Vec<f64> = (0..grid.bins).map(|b| hist.iter().map(|h| h[b]).sum::<usize>() as f64).collect();
+    let n: Vec<f64> = hist.iter().map(|h| h.iter().sum::<usize>() as f64).collect();
+    // exp(−βU_i) at each bin centre.
+    let boltz: Vec<Vec<f64>> = windows.iter().map(|&(c, k, _)| (0..grid.bins).map(|b| { let d = grid.cv.delta(grid.center(b), c); (-0.5 * k * d * d / kt).exp() }).collect()).collect();
+    let mut f = vec![0.0; windows.len()];
+    let mut p = vec![0.0; grid.bins];
+    let (mut iterations, mut converged) = (0, false);
+    while iterations < WHAM_MAX_ITERATIONS {
+        iterations += 1;
+        for b in 0..grid.bins {
+            let denom: f64 = (0..windows.len()).map(|i| n[i] * boltz[i][b] * (f[i] / kt).exp()).sum();
+            p[b] = if counts[b] > 0.0 { counts[b] / denom.max(1e-300) } else { 0.0 };
+        }
+        let mut next: Vec<f64> = (0..windows.len()).map(|i| -kt * (0..grid.bins).map(|b| p[b] * boltz[i][b]).sum::<f64>().max(1e-300).ln()).collect();
+        let f0 = next[0];
+        next.iter_mut().for_each(|x| *x -= f0);
+        let change = next.iter().zip(&f).map(|(a, b)| (a - b).abs()).fold(0.0, f64::max);
+        f = next;
+        if change < WHAM_TOLERANCE { converged = true; break; }
+    }
+    let total: f64 = p.iter().sum();
+    (p.iter().map(|&x| (x > 0.0).then(|| -kt * (x / total).ln())).collect(), hist, iterations, converged)
+}
+
+/// Biased dynamics with a movable restraint centre.
+struct Sampler<'a> { system: System<'a>, cv: Cv, centre: &'a Cell<f64>, k: f64, temperature_k: f64 }
+
+impl Sampler<'_> {
+    /// Pulls the restraint centre from `from` to `to` over `steps`; returns the work done (kcal/mol).
+    fn pull(&self, (from, to): (f64, f64), x: &mut Vec<[f64; 3]>, steps: usize, seed: u64) -> f64 {
+        let span = self.cv.delta(to, from);
+        let mut work = 0.0;
+        self.centre.set(from);
+        self.system.sample(x, steps, self.temperature_k, seed, &mut |step, x| {
+            let old = self.centre.get();
+            let new = from + span * (step + 1) as f64 / steps as f64;
+            work -= self.k * self.cv.delta(self.cv.value(x), old) * (new - old);
+            self.centre.set(new);
+        });
+        work
+    }
+
+    /// Holds the centre at `c` and records the coordinate, unwrapped around `c`, every
+    /// `STRIDE` steps after the first `equilibration`.
+    fn window(&self, c: f64, x: &mut Vec<[f64; 3]>, steps: usize, equilibration: usize, seed: u64) -> Vec<f64> {
+        self.centre.set(c);
+        let mut xs = Vec::new();
+        self.system.sample(x, steps, self.temperature_k, seed, &mut |step, x| {
+            if step >= equilibration && step % STRIDE == 0 { xs.push(c + self.cv.delta(self.cv.value(x), c)); }
+        });
+        xs
+    }
+}
+
+pub fn run(spec: &Umbrella, mol: &resolver::Resolved, temperature_k: f64, user: &Restraints) -> Result<Pmf, String> {
+    let smiles = mol.canonical_smiles.as_deref().ok_or_else(|| format!("can't resolve {} to a structure for umbrella sampling", mol.input))?;
+    let m = chem::parse_smiles(smiles)?;
+    let cv = Cv::parse(&spec.reaction_coordinate, m.atoms.len())?;
+    let n = spec.windows.unwrap_or(DEFAULT_WINDOWS);
+    if !(2..=MAX_WINDOWS).contains(&n) { return Err(format!("windows must be between 2 and {MAX_WINDOWS}")); }
+    let steps = spec.steps_per_window.unwrap_or(DEFAULT_STEPS);
+    let pull_steps = spec.pull_steps.unwrap_or(DEFAULT_PULL_STEPS).max(1);
+    if steps < 10 * STRIDE { return Err(format!("steps_per_window must be at least {}", 10 * STRIDE)); }
+    if n * (steps + pull_steps) > MAX_TOTAL_STEPS { return Err(format!("{n} windows of {} steps is more than {MAX_TOTAL_STEPS} steps", steps + pull_steps)); }
+    let (start, end) = match (spec.start, spec.end, cv) {
+        (Some(a), Some(b), _) => (cv.to_internal(a), cv.to_internal(b)),
+        (None, None, Cv::Dihedral(_)) => (-std::f64::consts::PI, std::f64::consts::PI),
+        _ => return Err(format!("a {} reaction coordinate needs start and end", cv.label())),
+    };
+    if (end - start).abs() < 1e-6 { return Err("start and end must differ".into()); }
+    match cv {
+        Cv::Distance(_) if start.min(end) <= 0.0 => return Err("distance windows must be positive".into()),
+        Cv::Angle(_) if start.min(end) < 0.0 || start.max(end) > std::f64::consts::PI => return Err("angle windows must lie within 0–180 degrees".into()),
+        _ => {}
+    }
+    // A full dihedral circle doesn't repeat its end point.
+    let full_circle = cv.periodic() && (end - start).abs() >= std::f64::consts::TAU - 1e-6;
+    let spacing = (end - start) / if full_circle { n } else { n - 1 } as f64;
+    let centres: Vec<f64> = (0..n).map(|i| start + spacing * i as f64).collect();
+    let kt = KB * temperature_k;
+    let k = spec.force_constant.unwrap_or_else(|| kt * (1.5 / spacing).powi(2));
+    if k.is_nan() || k <= 0.0 { return Err("force_constant must be positive".into()); }
+
+    let restraints = conformer::restraints(&m);
+    let seed = fnv1a(smiles.as_bytes());
+    let mut x = conformer::embed(&m, seed);
+    let reference = x.clone();
+    let centre = Cell::new(centres[0]);
+    let bias = |x: &[[f64; 3]], mut grad: Option<&mut [[f64; 3]]>| {
+        let e = user.energy(&reference, x, grad.as_deref_mut());
+        let (v, g) = cv.gradient(x);
+        let d = cv.delta(v, centre.get());
+        if let Some(grad) = grad { for (i, gi) in g { for c in 0..3 { grad[i][c] += k * d * gi[c]; } } }
+        e + 0.5 * k * d * d
+    };
+    let sampler = Sampler { system: System { restraints: &restraints, receptor: &frame::EMPTY, metals: None, anchor: &[], k_pos: 0.0, bias: Some(&bias) }, cv, centre: &centre, k, temperature_k };
+    sampler.pull((cv.value(&x), centres[0]), &mut x, pull_steps, seed);
+
+    // One steered trajectory through the windows in order.
+    let mut sampled = Vec::with_capacity(n);
+    let mut work = 0.0;
+    for (w, &c) in centres.iter().enumerate() {
+        if w > 0 { work += sampler.pull((centres[w - 1], c), &mut x, pull_steps, seed ^ w as u64); }
+        sampled.push((c, k, sampler.window(c, &mut x, steps, steps / 5, seed.rotate_left(w as u32 + 1))));
+    }
+
+    let (lo, hi) = if full_circle { (-std::f64::consts::PI, std::f64::consts::PI) } else {
+        let all = sampled.iter().flat_map(|w| w.2.iter().copied());
+        all.fold((f64::INFINITY, f64::NEG_INFINITY), |(a, b), v| (a.min(v), b.max(v)))
+    };
+    let grid = Grid::new(cv, lo, hi, spec.bins.unwrap_or(DEFAULT_BINS).clamp(10, 500), full_circle);
+    let (free, hist, iterations, converged) = wham(&grid, &sampled, kt);
+    let min = free.iter().flatten().copied().fold(f64::INFINITY, f64::min);
+    let round = |v: f64| (v * 1e3).round() / 1e3;
+    let counts: Vec<usize> = (0..grid.bins).map(|b| hist.iter().map(|h| h[b]).sum()).collect();
+    let profile: Vec<PmfPoint> = free.iter().enumerate().filter_map(|(b, g)| Some(PmfPoint { coordinate: round(cv.to_api(grid.center(b))), pmf_kcal_mol: round(g.as_ref()? - min), samples: counts[b] })).collect();
+    let overlap = |a: &[usize], b: &[usize]| { let (na, nb) = (a.iter().sum::<usize>().max(1) as f64, b.iter().sum::<usize>().max(1) as f64); a.iter().zip(b).map(|(&x, &y)| (x as f64 / na).min(y as f64 / nb)).sum::<f64>() };
+    let mut pairs: Vec<f64> = hist.windows(2).map(|p| overlap(&p[0], &p[1])).collect();
+    if full_circle { pairs.push(overlap(&hist[n - 1], &hist[0])); }
+    let min_overlap = pairs.iter().copied().fold(1.0, f64::min);
+    let windows: Vec<Window> = sampled.iter().map(|(c, k, xs)| {
+        let mean_d = xs.iter().map(|&v| cv.delta(v, *c)).sum::<f64>() / xs.len().max(1) as f64;
+        let var = xs.iter().map(|&v| (cv.delta(v, *c) - mean_d).powi(2)).sum::<f64>() / xs.len().max(1) as f64;
+        Window { center: round(cv.to_api(*c)), force_constant: round(*k), samples: xs.len(), mean: round(cv.to_api(cv.delta(c + mean_d, 0.0))), std_dev: round(cv.to_api(var.sqrt())) }
+    }).collect();
+    let mut warnings = Vec::new();
+    if min_overlap < 0.05 { warnings.push(format!("neighbouring windows barely overlap (minimum {min_overlap:.3}); add windows or soften force_constant")); }
+    if !converged { warnings.push(format!("WHAM did not converge in {WHAM_MAX_ITERATIONS} iterations")); }
+    Ok(Pmf {
+        reaction_coordinate: cv.label(), unit: cv.unit(), force_constant_unit: if matches!(cv, Cv::Distance(_)) { "kcal/mol/A^2" } else { "kcal/mol/rad^2" }, temperature_k,
+        windows, steered_work_kcal_mol: round(work), barrier_kcal_mol: profile.iter().map(|p| p.pmf_kcal_mol).fold(0.0, f64::max), profile, wham_iterations: iterations, converged, min_overlap: round(min_overlap), warnings,
+    })
+}
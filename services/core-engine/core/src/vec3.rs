@@ -0,0 +1,40 @@
+//! Geometry on `[x, y, z]` points: vector arithmetic, angles and torsions, and rotations.
+//!
+//! Degenerate input gets a usable answer rather than NaN: the unit vector of a zero vector is
+//! the x axis, and vectors that are already aligned need no rotation.
+
+pub fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] { [a[0] - b[0], a[1] - b[1], a[2] - b[2]] }
+pub fn add(a: [f64; 3], b: [f64; 3]) -> [f64; 3] { [a[0] + b[0], a[1] + b[1], a[2] + b[2]] }
+pub fn scale(a: [f64; 3], k: f64) -> [f64; 3] { [a[0] * k, a[1] * k, a[2] * k] }
+pub fn dot(a: [f64; 3], b: [f64; 3]) -> f64 { a[0] * b[0] + a[1] * b[1] + a[2] * b[2] }
+pub fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] { [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]] }
+pub fn norm(a: [f64; 3]) -> f64 { dot(a, a).sqrt() }
+pub fn dist(a: [f64; 3], b: [f64; 3]) -> f64 { norm(sub(a, b)) }
+pub fn unit(a: [f64; 3]) -> [f64; 3] { let n = norm(a); if n < 1e-9 { [1.0, 0.0, 0.0] } else { scale(a, 1.0 / n) } }
+
+/// A unit vector perpendicular to `a`.
+pub fn perpendicular(a: [f64; 3]) -> [f64; 3] { let t = if a[0].abs() < 0.9 { [1.0, 0.0, 0.0] } else { [0.0, 1.0, 0.0] }; unit(cross(a, t)) }
+
+/// The angle at `b` in degrees.
+pub fn angle(a: [f64; 3], b: [f64; 3], c: [f64; 3]) -> f64 { dot(unit(sub(a, b)), unit(sub(c, b))).clamp(-1.0, 1.0).acos().to_degrees() }
+
+/// The torsion a–b–c–d in degrees.
+pub fn torsion(a: [f64; 3], b: [f64; 3], c: [f64; 3], d: [f64; 3]) -> f64 {
+    let (b1, b2, b3) = (sub(b, a), sub(c, b), sub(d, c));
+    let (n1, n2) = (cross(b1, b2), cross(b2, b3));
+    dot(cross(n1, n2), unit(b2)).atan2(dot(n1, n2)).to_degrees()
+}
+
+/// `v` rotated by `theta` about unit `axis` (Rodrigues).
+pub fn rotate(v: [f64; 3], axis: [f64; 3], theta: f64) -> [f64; 3] {
+    let (s, c) = theta.sin_cos();
+    add(add(scale(v, c), scale(cross(axis, v), s)), scale(axis, dot(axis, v) * (1.0 - c)))
+}
+
+/// The rotation taking unit `a` onto unit `b`, as (axis, angle).
+pub fn aligning(a: [f64; 3], b: [f64; 3]) -> ([f64; 3], f64) {
+    let c = dot(a, b).clamp(-1.0, 1.0);
+    let axis = cross(a, b);
+    if norm(axis) < 1e-9 { return (perpendicular(a), if c > 0.0 { 0.0 } else { std::f64::consts::PI }); }
+    (unit(axis), c.acos())
+}
@@ -0,0 +1,63 @@
+//! JavaScript bindings for the front-end's client-side previews.
+//!
+//! Each function takes a molecule as `/depict` does: SMILES, or a name or library ID from the
+//! bundled table, resolved without the network. Results are the JSON of the endpoint each one
+//! previews, so a preview can be replaced by the server's answer when it arrives, and errors
+//! are thrown as strings. Energies stop at `MAX_HEAVY_ATOMS`, keeping a preview well inside a
+//! frame; larger systems and AM1-BCC charges need the server.
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::{charges::{self, ChargeModel}, chem::{self, Molecule}, conformer, depict, descriptors, filters, fnv1a, gaff, resolver};
+
+pub const MAX_HEAVY_ATOMS: usize = 100;
+
+#[derive(Serialize)]
+struct Descriptors { canonical_smiles: String, formula: String, molecular_weight: f64, logp: f64, hbd: usize, hba: usize, rotatable_bonds: usize, heavy_atoms: usize }
+#[derive(Serialize)]
+struct Energy { canonical_smiles: String, force_field: &'static str, charge_model: &'static str, total_energy_kcal: f64, bond_energy: f64, angle_energy: f64, dihedral_energy: f64, vdw_energy: f64, electrostatic_energy: f64, #[serde(skip_serializing_if = "Vec::is_empty")] warnings: Vec<String> }
+
+fn molecule(input: &str) -> Result<(String, Molecule), String> {
+    let smiles = resolver::resolve_local(input).and_then(|r| r.canonical_smiles).ok_or_else(|| format!("cannot parse molecule {input}"))?;
+    let mol = chem::parse_smiles(&smiles)?;
+    Ok((smiles, mol))
+}
+
+fn json<T: Serialize>(value: &T) -> Result<String, String> { serde_json::to_string(value).map_err(|e| e.to_string()) }
+
+#[wasm_bindgen]
+pub fn canonical_smiles(input: &str) -> Result<String, String> { molecule(input).map(|(smiles, _)| smiles) }
+
+/// The drug-likeness descriptors screen filters use, with the formula and average mass.
+#[wasm_bindgen]
+pub fn descriptors(input: &str) -> Result<String, String> {
+    let (smiles, mol) = molecule(input)?;
+    let round = |v: f64| (v * 1e3).round() / 1e3;
+    json(&Descriptors { formula: descriptors::formula_string(&descriptors::formula(&mol)), molecular_weight: round(descriptors::molecular_weight(&mol)?), logp: round(filters::logp(&mol)), hbd: filters::hbd(&mol), hba: filters::hba(&mol), rotatable_bonds: filters::rotatable_bonds(&mol), heavy_atoms: mol.atoms.iter().filter(|a| a.element != "H").count(), canonical_smiles: smiles })
+}
+
+/// `GET /mass`.
+#[wasm_bindgen]
+pub fn mass(input: &str) -> Result<String, String> { json(&descriptors::mass_report(&molecule(input)?.1)?) }
+
+/// `GET /depict?format=svg`.
+#[wasm_bindgen]
+pub fn depict_svg(input: &str, size: Option<u32>) -> Result<String, String> { Ok(depict::svg(&molecule(input)?.1, size.unwrap_or(300).clamp(64, 2048))) }
+
+/// `POST /energy` without the solvation term: GAFF bonded and van der Waals energies and the
+/// Coulomb energy of the charge model, on the conformer the server generates.
+#[wasm_bindgen]
+pub fn energy(input: &str, charge_model: Option<String>) -> Result<String, String> {
+    let (smiles, mol) = molecule(input)?;
+    let heavy = mol.atoms.iter().filter(|a| a.element != "H").count();
+    if heavy > MAX_HEAVY_ATOMS { return Err(format!("{heavy} heavy atoms is more than a preview evaluates ({MAX_HEAVY_ATOMS}); use the server")); }
+    let model = ChargeModel::parse(charge_model.as_deref(), "amber-ff14")?;
+    let q = charges::compute(&smiles, model, &|_| Err("AM1-BCC charges need the server".into()))?;
+    let coords = conformer::embed(&mol, fnv1a(smiles.as_bytes()));
+    let (params, e) = gaff::evaluate(&mol, &coords)?;
+    let round = |e: f64| (e * 1e3).round() / 1e3;
+    let (bond, angle, dihedral, vdw, elec) = (round(e.bond), round(e.angle), round(e.dihedral), round(e.vdw), round(charges::coulomb(&mol, &coords, &q.atoms)));
+    let warnings = if params.generic.is_empty() { Vec::new() } else { vec![format!("{} have no GAFF type; generic Lennard-Jones parameters were used", params.generic.join(", "))] };
+    json(&Energy { canonical_smiles: smiles, force_field: "amber-ff14", charge_model: model.name(), total_energy_kcal: bond + angle + dihedral + vdw + elec, bond_energy: bond, angle_energy: angle, dihedral_energy: dihedral, vdw_energy: vdw, electrostatic_energy: elec, warnings })
+}
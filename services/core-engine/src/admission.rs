@@ -0,0 +1,108 @@
+//! Admission control for compute requests.
+//!
+//! At most `BIO_MAX_CONCURRENT` compute requests (default: the number of CPUs) run at once.
+//! What happens to one more depends on `BIO_ADMISSION`:
+//!
+//! - `reject` (default): answer 503 at once, with `Retry-After` set to the outstanding work
+//!   spread over the slots (see `autoscale`), so clients back off instead of piling up.
+//! - `queue`: wait for a slot, but only up to `BIO_ADMISSION_QUEUE` waiting requests (default
+//!   four per slot) and `BIO_ADMISSION_WAIT_SECS` (default 30); past either, 503 as above.
+//! - `off`: no limit.
+//!
+//! Pipeline steps run in the background and always wait for a slot, so queued pipelines never
+//! crowd out interactive requests beyond the limit.
+
+use axum::{extract::{Request, State}, http::{header, HeaderValue, StatusCode}, middleware::Next, response::{IntoResponse, Json, Response}};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::{audit, autoscale, projects, AppState, ErrorResponse};
+
+const DEFAULT_WAIT_SECS: u64 = 30;
+const MAX_RETRY_AFTER_SECS: u64 = 300;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Policy { Reject, Queue, Off }
+
+pub struct Admission { policy: Policy, limit: usize, max_waiting: usize, wait: Duration, slots: Semaphore, waiting: AtomicUsize, rejected: AtomicU64 }
+
+#[derive(Serialize)]
+pub struct AdmissionStatus { pub policy: &'static str, pub max_concurrent: usize, pub in_use: usize, pub waiting: usize, pub rejected_total: u64 }
+
+/// `var` parsed, or `default` with a warning when it is set but unusable.
+pub fn setting<T: std::str::FromStr>(var: &str, default: T, valid: impl Fn(&T) -> bool) -> T {
+    match std::env::var(var) {
+        Err(_) => default,
+        Ok(v) => match v.parse::<T>() {
+            Ok(x) if valid(&x) => x,
+            _ => { tracing::warn!("{var}={v} is not valid; using the default"); default }
+        },
+    }
+}
+
+impl Admission {
+    pub fn from_env() -> Self {
+        let policy = match std::env::var("BIO_ADMISSION").as_deref() {
+            Err(_) | Ok("reject") => Policy::Reject,
+            Ok("queue") => Policy::Queue,
+            Ok("off") => Policy::Off,
+            Ok(other) => { tracing::warn!("BIO_ADMISSION={other} is not reject, queue or off; using reject"); Policy::Reject }
+        };
+        let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let limit = setting("BIO_MAX_CONCURRENT", cpus, |&n| n > 0);
+        let max_waiting = setting("BIO_ADMISSION_QUEUE", limit * 4, |_| true);
+        let wait = Duration::from_secs(setting("BIO_ADMISSION_WAIT_SECS", DEFAULT_WAIT_SECS, |_| true));
+        Self { policy, limit, max_waiting, wait, slots: Semaphore::new(limit), waiting: AtomicUsize::new(0), rejected: AtomicU64::new(0) }
+    }
+
+    /// A slot for background work, waiting as long as it takes; `None` when unlimited.
+    pub async fn acquire(&self) -> Option<SemaphorePermit<'_>> {
+        if self.policy == Policy::Off { return None; }
+        self.slots.acquire().await.ok()
+    }
+
+    /// A slot for a request under the policy, or the audit outcome if it should be turned away:
+    /// `rejected`, or `timed_out` after waiting its turn in the queue.
+    async fn admit(&self) -> Result<Option<SemaphorePermit<'_>>, &'static str> {
+        match self.policy {
+            Policy::Off => Ok(None),
+            Policy::Reject => self.slots.try_acquire().map(Some).map_err(|_| "rejected"),
+            Policy::Queue => {
+                if let Ok(p) = self.slots.try_acquire() { return Ok(Some(p)); }
+                if self.waiting.fetch_add(1, Ordering::SeqCst) >= self.max_waiting { self.waiting.fetch_sub(1, Ordering::SeqCst); return Err("rejected"); }
+                let permit = tokio::time::timeout(self.wait, self.slots.acquire()).await;
+                self.waiting.fetch_sub(1, Ordering::SeqCst);
+                match permit { Ok(Ok(p)) => Ok(Some(p)), Ok(Err(_)) => Err("rejected"), Err(_) => Err("timed_out") }
+            }
+        }
+    }
+
+    pub fn status(&self) -> AdmissionStatus {
+        let unlimited = self.policy == Policy::Off;
+        AdmissionStatus {
+            policy: match self.policy { Policy::Reject => "reject", Policy::Queue => "queue", Policy::Off => "off" }, max_concurrent: self.limit,
+            in_use: if unlimited { 0 } else { self.limit - self.slots.available_permits() }, waiting: self.waiting.load(Ordering::SeqCst), rejected_total: self.rejected.load(Ordering::SeqCst),
+        }
+    }
+}
+
+/// Holds a compute request to the admission policy.
+pub async fn admit(State(s): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let Some(kind) = autoscale::job_kind(&req) else { return next.run(req).await };
+    match s.admission.admit().await {
+        Ok(_slot) => next.run(req).await,
+        Err(outcome) => {
+            s.admission.rejected.fetch_add(1, Ordering::SeqCst);
+            let retry = (autoscale::outstanding_core_seconds(&s) / s.admission.limit as f64).ceil().clamp(1.0, MAX_RETRY_AFTER_SECS as f64) as u64;
+            let error = format!("the engine is at capacity ({} compute requests running); retry in {retry} s", s.admission.limit);
+            s.audit.failure(req.headers(), &projects::project_id(req.headers()), kind, outcome, &error);
+            let mut resp = (StatusCode::SERVICE_UNAVAILABLE, Json(ErrorResponse { error })).into_response();
+            resp.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(retry));
+            resp.extensions_mut().insert(audit::Recorded);
+            resp
+        }
+    }
+}
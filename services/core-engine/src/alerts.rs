@@ -0,0 +1,41 @@
+//! The structural alert catalog and `POST /alerts/check`; alert sets are
+//! `bio_engine_core::alerts`.
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+pub use bio_engine_core::alerts::*;
+
+use crate::{chem, standardize, ApiError, AppState, ErrorResponse};
+
+#[derive(Serialize)]
+pub struct AlertInfo { id: String, category: String, name: String, smarts: String, description: String }
+
+#[derive(Serialize)]
+pub struct AlertCatalog { source: String, categories: Vec<String>, alerts: Vec<AlertInfo> }
+
+pub async fn list(State(s): State<Arc<AppState>>) -> Json<AlertCatalog> {
+    let set = &s.alerts;
+    Json(AlertCatalog {
+        source: set.source.clone(), categories: set.categories().into_iter().map(String::from).collect(),
+        alerts: set.alerts.iter().map(|a| AlertInfo { id: a.id(), category: a.category.clone(), name: a.name.clone(), smarts: a.smarts.clone(), description: a.description.clone() }).collect(),
+    })
+}
+
+#[derive(Deserialize)]
+pub struct CheckRequest { molecule: String, categories: Option<Vec<String>> }
+
+#[derive(Serialize)]
+pub struct CheckResponse { molecule: String, canonical_smiles: String, #[serde(skip_serializing_if = "Vec::is_empty")] standardization: Vec<String>, flagged: bool, alerts: Vec<Flag> }
+
+pub async fn check(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<CheckRequest>) -> Result<Json<CheckResponse>, ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let known = s.alerts.categories();
+    if let Some(c) = req.categories.iter().flatten().find(|c| !known.contains(&c.as_str())) { return Err(bad(format!("unknown alert category {c}; expected one of {}", known.join(", ")))); }
+    let resolved = standardize::resolve(&s, &headers, &req.molecule).await;
+    let smiles = resolved.canonical_smiles.ok_or_else(|| bad(format!("can't resolve {} to a structure", req.molecule)))?;
+    let m = chem::parse_smiles(&smiles).map_err(bad)?;
+    let alerts = s.alerts.check(&m, &|c| req.categories.as_ref().is_none_or(|w| w.iter().any(|x| x == c)));
+    Ok(Json(CheckResponse { molecule: req.molecule, canonical_smiles: smiles, standardization: resolved.standardization, flagged: !alerts.is_empty(), alerts }))
+}
@@ -0,0 +1,192 @@
+//! Allosteric site discovery from pockets tracked along a trajectory.
+//!
+//! Pockets are detected in every frame: on the structure itself for a `trajectory` (a
+//! multi-model PDB from an MD run, see `pockets::detect_in`), or on the target's pseudo-ensemble
+//! when only `target_protein` is given (`pockets::ensemble`). A pocket is followed from frame to
+//! frame by its lining residues, or by its centre when the lining shifts, so each site gets
+//! persistence statistics: the fraction of frames it is open, how often it opens, its longest
+//! open run and its volume and druggability while open. A site closed in the first frame is
+//! cryptic. The orthosteric site is the one lined by `orthosteric_residues`, else the most
+//! persistent druggable one; sites far enough from it that reach a druggable conformation often
+//! enough are proposed as allosteric, ranked by druggability weighted by persistence.
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use bio_engine_core::pdbqt;
+use serde::{Deserialize, Serialize};
+use std::{collections::{BTreeMap, HashSet}, sync::Arc};
+
+use crate::{pockets::{self, Pocket}, record, usage, ApiError, AppState, ErrorResponse};
+
+const MODEL: &str = "pocket-tracking";
+const DEFAULT_FRAMES: usize = 50;
+const MAX_FRAMES: usize = 200;
+const DEFAULT_MIN_PERSISTENCE: f64 = 0.1;
+/// A frame's pocket continues a site when their linings overlap this much (Jaccard), or their
+/// centres are within `MAX_SHIFT` Å.
+const MIN_OVERLAP: f64 = 0.3;
+const MAX_SHIFT: f64 = 4.0;
+/// Centre distance, Å, from the orthosteric site an allosteric site needs.
+const MIN_SEPARATION: f64 = 8.0;
+/// Druggability an allosteric site has to reach in some frame.
+const DRUGGABLE: f64 = 0.5;
+/// Open fraction above which a site is stable rather than transient.
+const STABLE: f64 = 0.8;
+
+#[derive(Deserialize)]
+pub struct AllostericRequest {
+    target_protein: Option<String>, trajectory: Option<String>, frames: Option<usize>, stride: Option<usize>,
+    orthosteric_residues: Option<Vec<String>>, min_persistence: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct Site {
+    site_id: String, role: &'static str, state: &'static str, cryptic: bool, frames_open: usize, persistence: f64, first_open_frame: usize, opening_events: usize, longest_open_run: usize,
+    mean_volume_a3: f64, max_volume_a3: f64, volume_sd_a3: f64, mean_druggability: f64, max_druggability: f64, center: [f64; 3],
+    #[serde(skip_serializing_if = "Option::is_none")] distance_to_orthosteric_a: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] allosteric_score: Option<f64>, residues: Vec<String>,
+}
+#[derive(Serialize)]
+pub struct AllostericResponse {
+    analysis_id: String, target: String, source: &'static str, frames: usize, #[serde(skip_serializing_if = "Option::is_none")] orthosteric_site: Option<String>, allosteric_sites: Vec<String>,
+    sites: Vec<Site>, #[serde(skip_serializing_if = "Vec::is_empty")] warnings: Vec<String>,
+}
+
+/// A site as followed so far: its pocket in each frame it was open.
+struct Track { last: usize, open: Vec<(usize, Pocket)> }
+
+fn round(v: f64) -> f64 { (v * 1000.0).round() / 1000.0 }
+
+fn distance(a: [f64; 3], b: [f64; 3]) -> f64 { (0..3).map(|k| (a[k] - b[k]).powi(2)).sum::<f64>().sqrt() }
+
+fn jaccard(a: &[String], b: &[String]) -> f64 {
+    let (a, b): (HashSet<&String>, HashSet<&String>) = (a.iter().collect(), b.iter().collect());
+    let union = a.union(&b).count();
+    if union == 0 { 0.0 } else { a.intersection(&b).count() as f64 / union as f64 }
+}
+
+/// The models of a PDB file, each as its own text; a file without MODEL records is one model.
+fn models(pdb: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut current: Option<String> = None;
+    for line in pdb.lines() {
+        if line.starts_with("MODEL") { current = Some(String::new()); }
+        else if line.starts_with("ENDMDL") { out.extend(current.take()); }
+        else if let Some(text) = current.as_mut() { text.push_str(line); text.push('\n'); }
+    }
+    out.extend(current);
+    if out.is_empty() { out.push(pdb.to_string()); }
+    out
+}
+
+/// Follows pockets from frame to frame, greedily pairing each frame's pockets with the sites
+/// whose last pocket they overlap most.
+fn track(frames: &[Vec<Pocket>]) -> Vec<Track> {
+    let mut tracks: Vec<Track> = Vec::new();
+    for (f, pockets) in frames.iter().enumerate() {
+        let mut pairs: Vec<(f64, usize, usize)> = Vec::new();
+        for (i, p) in pockets.iter().enumerate() {
+            for (t, tr) in tracks.iter().enumerate() {
+                let last = &tr.open[tr.last].1;
+                let (overlap, shift) = (jaccard(&p.residues, &last.residues), distance(p.center, last.center));
+                if overlap >= MIN_OVERLAP || shift <= MAX_SHIFT { pairs.push((overlap + 1.0 / (1.0 + shift), i, t)); }
+            }
+        }
+        pairs.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let (mut used, mut taken) = (vec![false; pockets.len()], vec![false; tracks.len()]);
+        for (_, i, t) in pairs {
+            if used[i] || taken[t] { continue; }
+            (used[i], taken[t]) = (true, true);
+            tracks[t].open.push((f, pockets[i].clone()));
+            tracks[t].last = tracks[t].open.len() - 1;
+        }
+        for (p, _) in pockets.iter().zip(&used).filter(|(_, u)| !**u) { tracks.push(Track { last: 0, open: vec![(f, p.clone())] }); }
+    }
+    tracks
+}
+
+/// Persistence statistics of a followed site over `frames` frames.
+fn site(tr: &Track, frames: usize) -> Site {
+    let open: Vec<usize> = tr.open.iter().map(|o| o.0).collect();
+    let (mut events, mut run, mut longest) = (0, 0, 0);
+    for (k, &f) in open.iter().enumerate() {
+        if k == 0 || open[k - 1] + 1 != f { events += 1; run = 0; }
+        run += 1;
+        longest = longest.max(run);
+    }
+    let n = open.len() as f64;
+    let volumes: Vec<f64> = tr.open.iter().map(|o| o.1.volume_a3).collect();
+    let mean_volume = volumes.iter().sum::<f64>() / n;
+    let drug: Vec<f64> = tr.open.iter().map(|o| o.1.druggability).collect();
+    let mut lining: BTreeMap<&str, usize> = BTreeMap::new();
+    for (_, p) in &tr.open { for r in &p.residues { *lining.entry(r.as_str()).or_default() += 1; } }
+    // Residues lining the site in at least half of its open frames, the most frequent first.
+    let mut residues: Vec<(&str, usize)> = lining.into_iter().filter(|&(_, c)| 2 * c >= open.len()).collect();
+    residues.sort_by_key(|r| std::cmp::Reverse(r.1));
+    let persistence = n / frames as f64;
+    Site {
+        site_id: String::new(), role: "other", state: if persistence >= STABLE { "stable" } else { "transient" }, cryptic: open[0] > 0, frames_open: open.len(), persistence: round(persistence),
+        first_open_frame: open[0], opening_events: events, longest_open_run: longest, mean_volume_a3: round(mean_volume), max_volume_a3: volumes.iter().copied().fold(0.0, f64::max),
+        volume_sd_a3: round((volumes.iter().map(|v| (v - mean_volume).powi(2)).sum::<f64>() / n).sqrt()), mean_druggability: round(drug.iter().sum::<f64>() / n),
+        max_druggability: round(drug.iter().copied().fold(0.0, f64::max)), center: std::array::from_fn(|k| (tr.open.iter().map(|o| o.1.center[k]).sum::<f64>() / n * 100.0).round() / 100.0),
+        distance_to_orthosteric_a: None, allosteric_score: None, residues: residues.into_iter().map(|(r, _)| r.to_string()).collect(),
+    }
+}
+
+pub async fn discover(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<AllostericRequest>) -> Result<Json<AllostericResponse>, ApiError> {
+    let meter = usage::Meter::start();
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let min_persistence = req.min_persistence.unwrap_or(DEFAULT_MIN_PERSISTENCE);
+    if !(0.0..=1.0).contains(&min_persistence) { return Err(bad("min_persistence must be in [0, 1]".into())); }
+    let mut warnings = Vec::new();
+    let (target, source, frames) = match (&req.trajectory, &req.target_protein) {
+        (Some(pdb), target) => {
+            let stride = req.stride.unwrap_or(1).max(1);
+            let all = models(pdb);
+            let total = all.len();
+            let chosen: Vec<&String> = all.iter().step_by(stride).collect();
+            if chosen.len() > MAX_FRAMES { return Err(bad(format!("trajectory has {total} models; analyze at most {MAX_FRAMES} frames (raise stride)"))); }
+            let frames: Vec<Vec<Pocket>> = chosen.into_iter().map(|m| pockets::detect_in(&pdbqt::parse_residues(m, false))).collect();
+            if frames.len() == 1 { warnings.push("a single model: every pocket is trivially persistent; upload the trajectory's frames as MODEL records".into()); }
+            (target.clone().unwrap_or_else(|| "trajectory".into()), "trajectory", frames)
+        }
+        (None, Some(target)) => {
+            let n = req.frames.unwrap_or(DEFAULT_FRAMES);
+            if n == 0 || n > MAX_FRAMES { return Err(bad(format!("frames must be between 1 and {MAX_FRAMES}"))); }
+            (target.clone(), "ensemble", pockets::ensemble(target, n))
+        }
+        (None, None) => return Err(bad("give a trajectory (multi-model PDB) or a target_protein".into())),
+    };
+    if frames.iter().all(Vec::is_empty) { return Err(bad("no pockets found in any frame".into())); }
+
+    let mut sites: Vec<Site> = track(&frames).iter().map(|t| site(t, frames.len())).collect();
+    let orthosteric = match req.orthosteric_residues.as_deref().filter(|r| !r.is_empty()) {
+        Some(wanted) => {
+            let wanted: Vec<String> = wanted.iter().map(|r| r.trim().to_uppercase()).collect();
+            let best = sites.iter().enumerate().map(|(i, st)| (i, st.residues.iter().filter(|r| wanted.contains(r)).count())).max_by_key(|&(_, n)| n).filter(|&(_, n)| n > 0).map(|(i, _)| i);
+            if best.is_none() { warnings.push("no site is lined by orthosteric_residues; the most persistent druggable site is taken as orthosteric".into()); }
+            best
+        }
+        None => None,
+    }.or_else(|| sites.iter().enumerate().max_by(|a, b| (a.1.persistence * a.1.mean_druggability).total_cmp(&(b.1.persistence * b.1.mean_druggability))).map(|(i, _)| i));
+    let center = orthosteric.map(|i| sites[i].center);
+    if let Some(i) = orthosteric { sites[i].role = "orthosteric"; }
+    for st in sites.iter_mut().filter(|st| st.role != "orthosteric") {
+        let Some(c) = center else { break };
+        let d = distance(st.center, c);
+        st.distance_to_orthosteric_a = Some(round(d));
+        if d >= MIN_SEPARATION && st.max_druggability >= DRUGGABLE && st.persistence >= min_persistence {
+            st.role = "allosteric";
+            st.allosteric_score = Some(round(st.mean_druggability * st.persistence.sqrt()));
+        }
+    }
+    // Orthosteric first, then allosteric candidates by score, then the rest by persistence.
+    let key = |st: &Site| (match st.role { "orthosteric" => 0, "allosteric" => 1, _ => 2 }, -st.allosteric_score.unwrap_or(0.0), -st.persistence);
+    sites.sort_by(|a, b| { let (ka, kb) = (key(a), key(b)); ka.0.cmp(&kb.0).then(ka.1.total_cmp(&kb.1)).then(ka.2.total_cmp(&kb.2)) });
+    for (i, st) in sites.iter_mut().enumerate() { st.site_id = format!("S{}", i + 1); }
+    let resp = AllostericResponse {
+        analysis_id: uuid::Uuid::new_v4().to_string(), target, source, frames: frames.len(),
+        orthosteric_site: sites.iter().find(|st| st.role == "orthosteric").map(|st| st.site_id.clone()),
+        allosteric_sites: sites.iter().filter(|st| st.role == "allosteric").map(|st| st.site_id.clone()).collect(), sites, warnings,
+    };
+    record(&s, &headers, "allosteric", &resp.target, MODEL, &resp.analysis_id, &meter, &resp);
+    Ok(Json(resp))
+}
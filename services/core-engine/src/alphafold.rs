@@ -0,0 +1,74 @@
+//! AlphaFold DB models, the `alphafold` prediction type.
+//!
+//! With `"prediction_type": "alphafold"` and a `uniprot_accession`, `/predict` returns the
+//! entry's precomputed model from `BIO_ALPHAFOLD_URL` (default the AlphaFold DB file service)
+//! alongside the sequence annotations, caching it per accession. The sequence may be left empty
+//! to take the model's; a given sequence that differs from it is warned about. The report gives
+//! per-residue pLDDT, the fraction of residues in each of the database's confidence bands and the
+//! low-confidence regions (see `plddt`), and the mean pLDDT is the `structure_confidence`. The
+//! model's `pdb` can go straight on to receptor or docking preparation and mixed-solvent mapping,
+//! which recognise it and exclude or restrain its low-confidence regions.
+
+use bio_engine_core::plddt;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::{loops, predict::PredictRequest, resolver::percent_encode, AppState};
+
+pub const MODEL: &str = "alphafold-db";
+const DEFAULT_ALPHAFOLD_URL: &str = "https://alphafold.ebi.ac.uk/files/AF-{id}-F1-model_v4.pdb";
+/// Lower pLDDT bounds of the database's bands: very high, confident, low (very low below).
+const BANDS: [f64; 3] = [90.0, 70.0, 50.0];
+
+/// Model files fetched by accession, cached; `None` when an entry has no model or can't be fetched.
+pub struct AlphaFoldDb { client: reqwest::Client, url: String, cache: Mutex<HashMap<String, Option<String>>> }
+
+impl AlphaFoldDb {
+    pub fn new(url: Option<String>) -> Self { Self { client: reqwest::Client::new(), url: url.unwrap_or_else(|| DEFAULT_ALPHAFOLD_URL.into()), cache: Mutex::new(HashMap::new()) } }
+
+    pub async fn fetch(&self, accession: &str) -> Option<String> {
+        if let Some(hit) = self.cache.lock().unwrap().get(accession).cloned() { return hit; }
+        let url = self.url.replace("{id}", &percent_encode(accession));
+        let fetched = async {
+            let resp = self.client.get(&url).timeout(std::time::Duration::from_secs(10)).send().await.ok()?.error_for_status().ok()?;
+            resp.text().await.ok().filter(|t| t.contains("ATOM"))
+        }.await;
+        self.cache.lock().unwrap().insert(accession.to_string(), fetched.clone());
+        fetched
+    }
+}
+
+#[derive(Serialize)]
+pub struct Bands { very_high: f64, confident: f64, low: f64, very_low: f64 }
+#[derive(Serialize)]
+pub struct AlphaFoldModel {
+    entry: String, residues: usize, pub mean_plddt: f64, bands: Bands, plddt: Vec<f64>, low_confidence_regions: Vec<plddt::Region>, sequence_matches: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")] warnings: Vec<String>, pdb: String,
+}
+
+fn round(v: f64) -> f64 { (v * 1000.0).round() / 1000.0 }
+
+/// The AlphaFold DB model of an `alphafold` request, with the request's sequence filled in from
+/// it when empty; no model for other prediction types.
+pub async fn for_request(s: &AppState, mut req: PredictRequest) -> Result<(PredictRequest, Option<AlphaFoldModel>), String> {
+    if req.prediction_type.as_deref() != Some("alphafold") { return Ok((req, None)); }
+    let accession = req.uniprot_accession.as_deref().map(|a| a.trim().to_uppercase()).ok_or("alphafold models are looked up by uniprot_accession")?;
+    let pdb = s.alphafold.fetch(&accession).await.ok_or_else(|| format!("AlphaFold DB has no model for {accession}, or could not be reached"))?;
+    let residues = plddt::read(&pdb);
+    if residues.is_empty() { return Err(format!("the AlphaFold DB model of {accession} has no residues")); }
+    let sequence: String = residues.iter().map(|r| loops::one_letter(&r.name)).collect();
+    let given: String = req.sequence.chars().filter(|c| !c.is_whitespace()).map(|c| c.to_ascii_uppercase()).collect();
+    let mut warnings = Vec::new();
+    let sequence_matches = given.is_empty() || given == sequence;
+    if given.is_empty() { req.sequence = sequence.clone(); }
+    else if !sequence_matches { warnings.push(format!("the given sequence ({} residues) differs from the model's ({}); annotations are for the given one", given.len(), sequence.len())); }
+    let n = residues.len() as f64;
+    let fraction = |lo: f64, hi: f64| round(residues.iter().filter(|r| r.plddt >= lo && r.plddt < hi).count() as f64 / n);
+    let model = AlphaFoldModel {
+        entry: format!("AF-{accession}-F1"), residues: residues.len(), mean_plddt: round(residues.iter().map(|r| r.plddt).sum::<f64>() / n),
+        bands: Bands { very_high: fraction(BANDS[0], f64::INFINITY), confident: fraction(BANDS[1], BANDS[0]), low: fraction(BANDS[2], BANDS[1]), very_low: fraction(f64::NEG_INFINITY, BANDS[2]) },
+        plddt: residues.iter().map(|r| r.plddt).collect(), low_confidence_regions: plddt::regions(&residues, plddt::DEFAULT_CUTOFF), sequence_matches, warnings, pdb,
+    };
+    Ok((req, Some(model)))
+}
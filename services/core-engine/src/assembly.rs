@@ -0,0 +1,134 @@
+//! Plasmid assembly: Gibson and Golden Gate.
+//!
+//! Gibson joins fragments whose ends share a homologous overlap: each fragment's 3' end must
+//! match the next one's 5' end over at least `min_overlap` bases, and the overlap's Tm
+//! (basic GC formula) should be around 50 °C or more. Golden Gate cuts each fragment with a
+//! Type IIS enzyme from the REBASE database (BsaI by default) whose two sites face inward;
+//! neighbouring fragments must leave complementary 4-base overhangs, and every junction's
+//! overhang must be unique and non-palindromic or fragments can ligate in the wrong order.
+//! Fragments are joined in the order given, and the last back to the first when circular.
+
+use axum::{extract::State, http::StatusCode, response::Json};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{restriction, ApiError, AppState, ErrorResponse};
+
+const DEFAULT_MIN_OVERLAP: usize = 15;
+const MAX_OVERLAP: usize = 200;
+/// Overlaps melting below this may not anneal at the 50 °C Gibson reaction temperature.
+const MIN_OVERLAP_TM: f64 = 48.0;
+
+#[derive(Deserialize)]
+pub struct Fragment { name: Option<String>, sequence: String }
+#[derive(Deserialize)]
+pub struct AssemblyRequest { method: String, fragments: Vec<Fragment>, circular: Option<bool>, enzyme: Option<String>, min_overlap: Option<usize> }
+
+#[derive(Serialize)]
+pub struct Junction { from: String, to: String, position: usize, sequence: String, length: usize, gc_fraction: f64, #[serde(skip_serializing_if = "Option::is_none")] tm_celsius: Option<f64> }
+#[derive(Serialize)]
+pub struct AssemblyResponse { method: String, valid: bool, circular: bool, #[serde(skip_serializing_if = "Option::is_none")] enzyme: Option<String>, length: usize, #[serde(skip_serializing_if = "Option::is_none")] construct: Option<String>, junctions: Vec<Junction>, errors: Vec<String>, warnings: Vec<String> }
+
+fn gc(s: &[u8]) -> f64 { s.iter().filter(|&&b| b == b'G' || b == b'C').count() as f64 / s.len().max(1) as f64 }
+fn round(v: f64, places: i32) -> f64 { let f = 10f64.powi(places); (v * f).round() / f }
+
+/// Basic GC-content melting temperature, valid for 14 bases and up.
+fn tm(s: &[u8]) -> f64 { 64.9 + 41.0 * (gc(s) * s.len() as f64 - 16.4) / s.len() as f64 }
+
+fn revcomp(s: &str) -> String {
+    s.bytes().rev().map(|b| match b { b'A' => 'T', b'T' => 'A', b'C' => 'G', b'G' => 'C', _ => 'N' }).collect()
+}
+
+/// Pieces to join in order: (name, sequence kept, 5' and 3' overlap/overhang with neighbours).
+struct Piece { name: String, body: Vec<u8>, left: Vec<u8>, right: Vec<u8> }
+
+/// Longest suffix of `a` that is a prefix of `b`.
+fn overlap(a: &[u8], b: &[u8], min: usize) -> Option<usize> {
+    (min..=MAX_OVERLAP.min(a.len()).min(b.len())).rev().find(|&k| a[a.len() - k..] == b[..k])
+}
+
+fn gibson(frags: &[(String, Vec<u8>)], circular: bool, min: usize, errors: &mut Vec<String>) -> Vec<Piece> {
+    let n = frags.len();
+    let mut pieces: Vec<Piece> = frags.iter().map(|(name, seq)| Piece { name: name.clone(), body: seq.clone(), left: Vec::new(), right: Vec::new() }).collect();
+    let joins = if circular { n } else { n - 1 };
+    for i in 0..joins {
+        let j = (i + 1) % n;
+        match overlap(&frags[i].1, &frags[j].1, min) {
+            Some(k) => { pieces[i].right = frags[i].1[frags[i].1.len() - k..].to_vec(); pieces[j].left = frags[j].1[..k].to_vec(); }
+            None => errors.push(format!("{} and {} share no overlap of at least {min} bases", frags[i].0, frags[j].0)),
+        }
+    }
+    // The shared overlap appears once in the construct: drop it from the downstream piece, or
+    // from the last piece when closing the circle so the construct starts with the first.
+    for p in pieces.iter_mut().skip(1) { let k = p.left.len(); p.body.drain(..k); }
+    let k = pieces[0].left.len();
+    let last = &mut pieces[n - 1].body;
+    last.truncate(last.len().saturating_sub(k));
+    pieces
+}
+
+fn golden_gate(frags: &[(String, Vec<u8>)], enzyme: &restriction::Enzyme, errors: &mut Vec<String>) -> Vec<Piece> {
+    frags.iter().filter_map(|(name, seq)| {
+        let cuts = restriction::cuts(enzyme, seq, false);
+        let (first, last) = match cuts.as_slice() {
+            [a, b] if a.strand == '+' && b.strand == '-' && a.overhang_type == "5'" && b.overhang_type == "5'" => (a, b),
+            [_, _] => { errors.push(format!("{name}: the two {} sites don't face inward", enzyme.name)); return None; }
+            _ => { errors.push(format!("{name}: expected one {} site at each end, found {}", enzyme.name, cuts.len())); return None; }
+        };
+        Some(Piece { name: name.clone(), body: seq[first.position..last.position].to_vec(), left: first.overhang.clone().into_bytes(), right: last.overhang.clone().into_bytes() })
+    }).collect()
+}
+
+pub async fn assemble(State(s): State<Arc<AppState>>, Json(req): Json<AssemblyRequest>) -> Result<Json<AssemblyResponse>, ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    if req.fragments.len() < 2 { return Err(bad("give at least two fragments".into())); }
+    let mut frags = Vec::new();
+    for (i, f) in req.fragments.iter().enumerate() {
+        let name = f.name.clone().unwrap_or_else(|| format!("fragment_{}", i + 1));
+        let seq: Vec<u8> = f.sequence.bytes().filter(|b| !b.is_ascii_whitespace()).map(|b| b.to_ascii_uppercase()).collect();
+        if let Some(&b) = seq.iter().find(|b| !b"ACGTN".contains(b)) { return Err(bad(format!("{name}: invalid nucleotide {}", b as char))); }
+        frags.push((name, seq));
+    }
+    let circular = req.circular.unwrap_or(true);
+    let (mut errors, mut warnings) = (Vec::new(), Vec::new());
+    let method = req.method.to_lowercase().replace([' ', '-'], "_");
+    let (pieces, enzyme) = match method.as_str() {
+        "gibson" => (gibson(&frags, circular, req.min_overlap.unwrap_or(DEFAULT_MIN_OVERLAP), &mut errors), None),
+        "golden_gate" => {
+            let name = req.enzyme.as_deref().unwrap_or("BsaI");
+            let enzyme = s.enzymes.find(name).ok_or_else(|| bad(format!("unknown enzyme {name}")))?;
+            if !enzyme.cuts_outside() { return Err(bad(format!("{} cuts inside its site; Golden Gate needs a Type IIS enzyme", enzyme.name))); }
+            (golden_gate(&frags, enzyme, &mut errors), Some(enzyme.name.clone()))
+        }
+        other => return Err(bad(format!("unknown assembly method {other}; expected gibson or golden_gate"))),
+    };
+    let total: usize = pieces.iter().map(|p| p.body.len()).sum();
+    let mut junctions = Vec::new();
+    let mut end = 0;
+    for (i, a) in pieces.iter().enumerate() {
+        end += a.body.len();
+        if i + 1 == pieces.len() && !circular { break; }
+        let b = &pieces[(i + 1) % pieces.len()];
+        // Golden Gate overhangs must pair up; Gibson overlaps were matched as they were found.
+        let (seam, position) = if enzyme.is_some() {
+            if a.right != b.left { errors.push(format!("{} 3' overhang {} doesn't match {} 5' overhang {}", a.name, String::from_utf8_lossy(&a.right), b.name, String::from_utf8_lossy(&b.left))); }
+            (&a.right, end % total.max(1))
+        } else {
+            (&b.left, if i + 1 == pieces.len() { 0 } else { end - b.left.len() })
+        };
+        if seam.is_empty() { continue; }
+        let tm_celsius = enzyme.is_none().then(|| round(tm(seam), 1));
+        if let Some(t) = tm_celsius.filter(|&t| t < MIN_OVERLAP_TM) { warnings.push(format!("{} → {} overlap Tm {t} °C is below {MIN_OVERLAP_TM} °C", a.name, b.name)); }
+        junctions.push(Junction { from: a.name.clone(), to: b.name.clone(), position, sequence: String::from_utf8_lossy(seam).into(), length: seam.len(), gc_fraction: round(gc(seam), 2), tm_celsius });
+    }
+    // Repeated or self-complementary seams let fragments join in the wrong place.
+    for (k, j) in junctions.iter().enumerate() {
+        if junctions[..k].iter().any(|o| o.sequence == j.sequence || o.sequence == revcomp(&j.sequence)) { errors.push(format!("junction sequence {} is used more than once", j.sequence)); }
+        if enzyme.is_some() && j.sequence == revcomp(&j.sequence) { warnings.push(format!("overhang {} is palindromic and can self-ligate", j.sequence)); }
+    }
+    let valid = errors.is_empty();
+    let mut construct: Vec<u8> = pieces.iter().flat_map(|p| p.body.iter().copied()).collect();
+    // A linear Golden Gate product keeps the outer overhang of its last piece.
+    if !circular && enzyme.is_some() { construct.extend(pieces.last().map(|p| p.right.clone()).unwrap_or_default()); }
+    Ok(Json(AssemblyResponse { method, valid, circular, enzyme, length: if valid { construct.len() } else { 0 }, construct: valid.then(|| String::from_utf8_lossy(&construct).into()), junctions, errors, warnings }))
+}
@@ -0,0 +1,82 @@
+//! Append-only audit trail of compute requests.
+//!
+//! Every compute endpoint records who called it, when, on what input, with which model
+//! version, and the ID of the result it produced. A compute request that produces nothing is
+//! recorded too, with its `outcome`: `failed`, `rejected` by admission control, or `timed_out`,
+//! and the error it got. Entries are never mutated or removed;
+//! if `BIO_AUDIT_LOG` is set they are also appended as JSON lines to that file so the
+//! trail survives restarts.
+
+use axum::{extract::{Query, State}, http::HeaderMap, response::Json};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use crate::{projects, unix_now, AppState};
+
+#[derive(Serialize, Clone)]
+pub struct AuditEntry { pub seq: u64, pub timestamp_unix: u64, pub actor: String, pub project: String, pub operation: String, pub subject: String, pub engine_version: String, pub model_version: String, pub result_id: Option<String>, pub outcome: String, #[serde(skip_serializing_if = "Option::is_none")] pub error: Option<String> }
+
+/// Marks a response whose failure is already in the trail, so outer layers don't record it again.
+#[derive(Clone, Copy)]
+pub struct Recorded;
+
+#[derive(Deserialize)]
+pub struct AuditQuery { actor: Option<String>, operation: Option<String>, outcome: Option<String>, subject: Option<String>, since: Option<u64>, limit: Option<usize> }
+#[derive(Serialize)]
+pub struct AuditResponse { total: usize, entries: Vec<AuditEntry> }
+
+pub struct AuditLog { entries: Mutex<Vec<AuditEntry>>, sink: Option<Mutex<std::fs::File>> }
+
+impl AuditLog {
+    pub fn new(path: Option<String>) -> Self {
+        let sink = path.and_then(|p| match std::fs::OpenOptions::new().create(true).append(true).open(&p) {
+            Ok(f) => Some(Mutex::new(f)),
+            Err(e) => { tracing::warn!("audit log file {p} unavailable: {e}"); None }
+        });
+        Self { entries: Mutex::new(Vec::new()), sink }
+    }
+
+    pub fn record(&self, headers: &HeaderMap, project: &str, operation: &str, subject: &str, model_version: &str, result_id: Option<&str>) {
+        self.append(headers, project, operation, subject, model_version, result_id, "completed", None);
+    }
+
+    /// Records a compute request that ended without a result.
+    pub fn failure(&self, headers: &HeaderMap, project: &str, operation: &str, outcome: &str, error: &str) {
+        self.append(headers, project, operation, "", "", None, outcome, Some(error));
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn append(&self, headers: &HeaderMap, project: &str, operation: &str, subject: &str, model_version: &str, result_id: Option<&str>, outcome: &str, error: Option<&str>) {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = AuditEntry { seq: entries.len() as u64 + 1, timestamp_unix: unix_now(), actor: actor(headers), project: project.into(), operation: operation.into(), subject: subject.into(), engine_version: env!("CARGO_PKG_VERSION").into(), model_version: model_version.into(), result_id: result_id.map(Into::into), outcome: outcome.into(), error: error.map(Into::into) };
+        if let Some(sink) = &self.sink {
+            if let Ok(line) = serde_json::to_string(&entry) {
+                if let Err(e) = writeln!(sink.lock().unwrap(), "{line}") { tracing::warn!("audit log write failed: {e}"); }
+            }
+        }
+        entries.push(entry);
+    }
+}
+
+/// Caller identity as forwarded by the API gateway.
+pub fn actor(headers: &HeaderMap) -> String {
+    headers.get("x-user-id").and_then(|v| v.to_str().ok()).unwrap_or("anonymous").to_string()
+}
+
+/// Entries are scoped to the caller's project.
+pub async fn list(State(s): State<Arc<AppState>>, headers: HeaderMap, Query(q): Query<AuditQuery>) -> Json<AuditResponse> {
+    let project = projects::project_id(&headers);
+    let entries = s.audit.entries.lock().unwrap();
+    let matched: Vec<AuditEntry> = entries.iter()
+        .filter(|e| e.project == project)
+        .filter(|e| q.actor.as_ref().is_none_or(|a| &e.actor == a))
+        .filter(|e| q.operation.as_ref().is_none_or(|o| &e.operation == o))
+        .filter(|e| q.outcome.as_ref().is_none_or(|o| &e.outcome == o))
+        .filter(|e| q.subject.as_ref().is_none_or(|m| &e.subject == m))
+        .filter(|e| q.since.is_none_or(|t| e.timestamp_unix >= t))
+        .cloned().collect();
+    let total = matched.len();
+    let limit = q.limit.unwrap_or(1000);
+    Json(AuditResponse { total, entries: matched.into_iter().rev().take(limit).collect() })
+}
@@ -0,0 +1,144 @@
+//! Backlog signals for an external autoscaler.
+//!
+//! Outstanding work is the compute requests running right now plus the pipeline steps and sweep
+//! grid points waiting or running in the background; a grid point counts as a simulation. Each
+//! is costed at the mean CPU time of the last jobs of its kind, across projects, or at a
+//! per-class default before any has run. Work is grouped into classes that want different node
+//! shapes: `md` (simulations, torsion scans, sweeps), `screening` (docking, rescoring, pose
+//! refinement, peptide design, ternary complexes, fragment growing, bioisosteres), `prediction`
+//! (structure, loops, stability, epitopes) and `other`. The report also has the admission
+//! state (see `admission`): slots in use, requests waiting, and how many were turned away. It
+//! is JSON, or Prometheus text exposition with `?format=prometheus`.
+
+use axum::{extract::{Query, Request, State}, http::{header, Method, StatusCode}, middleware::Next, response::{IntoResponse, Json, Response}};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crate::{admission, dryrun, unix_now, ApiError, AppState, ErrorResponse};
+
+const CLASSES: [&str; 4] = ["md", "screening", "prediction", "other"];
+/// Jobs of a kind averaged for its cost.
+const HISTORY: usize = 100;
+/// Pipeline steps that do no computing of their own.
+const BOOKKEEPING_STEPS: &[&str] = &["fetch_structure", "detect_pockets"];
+
+/// Compute routes (POST) and the job kind each runs. Pipelines and sweeps only enqueue; their
+/// steps and grid points are counted from their stores.
+const ROUTES: &[(&str, &str)] = &[
+    ("/api/v1/bio/simulate", "simulate"), ("/api/v1/bio/torsion-scan", "torsion_scan"), ("/api/v1/bio/screen", "screen"),
+    ("/api/v1/bio/screen/from-sequence", "screen"), ("/api/v1/bio/refine-pose", "refine_pose"), ("/api/v1/bio/predict", "predict"), ("/api/v1/bio/stability", "stability"),
+    ("/api/v1/bio/epitope", "epitope"), ("/api/v1/bio/energy", "energy"), ("/api/v1/bio/qm", "qm"), ("/api/v1/bio/benchmark", "benchmark"),
+    ("/api/v1/bio/model-loops", "model_loops"), ("/api/v1/bio/scripts/run", "script"), ("/api/v1/bio/peptides/design", "peptide_design"),
+    ("/api/v1/bio/ternary-complex", "ternary"), ("/api/v1/bio/fragments/grow", "fragment_grow"), ("/api/v1/bio/bioisosteres", "bioisosteres"),
+    ("/api/v1/bio/retrosynthesis", "retrosynthesis"), ("/api/v1/bio/pockets/allosteric", "allosteric"),
+    ("/api/v1/bio/simulate/mixed-solvent", "mixed_solvent"), ("/api/v1/bio/druggability/triage", "druggability_triage"),
+    ("/api/v1/bio/superpose", "superpose"), ("/api/v1/bio/superpose/cluster", "structure_clustering"),
+];
+
+/// The job kinds of the compute routes, each once, and `sweep`, a group of simulations.
+pub fn kinds() -> Vec<&'static str> {
+    let mut kinds: Vec<&'static str> = Vec::new();
+    for (_, kind) in ROUTES { if !kinds.contains(kind) { kinds.push(kind); } }
+    kinds.push("sweep");
+    kinds
+}
+
+pub fn class_of(kind: &str) -> &'static str {
+    match kind {
+        "simulate" | "sweep" | "torsion_scan" | "mixed_solvent" => "md",
+        "screen" | "rescore" | "refine_pose" | "peptide_design" | "ternary" | "fragment_grow" | "bioisosteres" => "screening",
+        "predict" | "stability" | "epitope" | "model_loops" | "druggability_triage" => "prediction",
+        _ => "other",
+    }
+}
+
+/// Core-seconds a job of `class` is assumed to take before any has been measured.
+pub fn default_core_seconds(class: &str) -> f64 {
+    match class { "md" => 60.0, "screening" => 30.0, "prediction" => 10.0, _ => 5.0 }
+}
+
+/// Compute requests in progress, by job kind.
+pub struct Tracker { running: Mutex<BTreeMap<&'static str, usize>> }
+
+impl Tracker {
+    pub fn new() -> Self { Self { running: Mutex::new(BTreeMap::new()) } }
+    fn add(&self, kind: &'static str, delta: isize) { let mut r = self.running.lock().unwrap(); let n = r.entry(kind).or_default(); *n = n.saturating_add_signed(delta); }
+}
+
+struct Running<'a> { tracker: &'a Tracker, kind: &'static str }
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) { self.tracker.add(self.kind, -1); }
+}
+
+/// The job kind a compute request runs, `None` for other requests.
+pub fn job_kind(req: &Request) -> Option<&'static str> {
+    (req.method() == Method::POST && req.extensions().get::<dryrun::DryRun>().is_none()).then(|| ROUTES.iter().find(|r| r.0 == req.uri().path())).flatten().map(|r| r.1)
+}
+
+/// Counts a compute request as running for as long as it takes.
+pub async fn track(State(s): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let Some(kind) = job_kind(&req) else { return next.run(req).await };
+    s.load.add(kind, 1);
+    let _running = Running { tracker: &s.load, kind };
+    next.run(req).await
+}
+
+#[derive(Deserialize)]
+pub struct BacklogQuery { format: Option<String> }
+
+#[derive(Serialize)]
+pub struct KindBacklog { kind: String, queued: usize, running: usize, core_seconds_per_job: f64, measured: bool, core_hours: f64 }
+
+#[derive(Serialize)]
+pub struct ClassBacklog { class: &'static str, queued: usize, running: usize, core_hours: f64, kinds: Vec<KindBacklog> }
+
+#[derive(Serialize)]
+pub struct BacklogReport { generated_at_unix: u64, queued: usize, running: usize, core_hours: f64, classes: Vec<ClassBacklog>, admission: admission::AdmissionStatus }
+
+fn round(v: f64) -> f64 { (v * 1e4).round() / 1e4 + 0.0 }
+
+fn backlog(s: &AppState) -> BacklogReport {
+    let mut counts: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    for (&kind, &n) in s.load.running.lock().unwrap().iter().filter(|r| *r.1 > 0) { counts.entry(kind.into()).or_default().1 += n; }
+    for (kind, running) in s.pipelines.outstanding().into_iter().filter(|(k, _)| !BOOKKEEPING_STEPS.contains(&k.as_str())) { let c = counts.entry(kind).or_default(); if running { c.1 += 1 } else { c.0 += 1 } }
+    let (queued, running) = s.sweeps.outstanding();
+    if queued + running > 0 { let c = counts.entry("simulate".into()).or_default(); c.0 += queued; c.1 += running; }
+    let cost = |kind: &str| -> (f64, bool) { s.jobs.mean_cpu_seconds(kind, HISTORY).map_or((default_core_seconds(class_of(kind)), false), |c| (c, true)) };
+    let classes: Vec<ClassBacklog> = CLASSES.iter().map(|&class| {
+        let kinds: Vec<KindBacklog> = counts.iter().filter(|(k, _)| class_of(k) == class).map(|(k, &(queued, running))| {
+            let (per_job, measured) = cost(k);
+            KindBacklog { kind: k.clone(), queued, running, core_seconds_per_job: round(per_job), measured, core_hours: round((queued + running) as f64 * per_job / 3600.0) }
+        }).collect();
+        ClassBacklog { class, queued: kinds.iter().map(|k| k.queued).sum(), running: kinds.iter().map(|k| k.running).sum(), core_hours: round(kinds.iter().map(|k| k.core_hours).sum()), kinds }
+    }).collect();
+    BacklogReport { generated_at_unix: unix_now(), queued: classes.iter().map(|c| c.queued).sum(), running: classes.iter().map(|c| c.running).sum(), core_hours: round(classes.iter().map(|c| c.core_hours).sum()), classes, admission: s.admission.status() }
+}
+
+/// Core-seconds of all outstanding work.
+pub fn outstanding_core_seconds(s: &AppState) -> f64 { backlog(s).core_hours * 3600.0 }
+
+fn prometheus(r: &BacklogReport) -> String {
+    let mut out = String::new();
+    out.push_str("# HELP bio_backlog_core_hours Estimated core-hours of queued and running work.\n# TYPE bio_backlog_core_hours gauge\n");
+    for c in &r.classes { out.push_str(&format!("bio_backlog_core_hours{{class=\"{}\"}} {}\n", c.class, c.core_hours)); }
+    out.push_str("# HELP bio_backlog_jobs Jobs queued or running.\n# TYPE bio_backlog_jobs gauge\n");
+    for c in &r.classes {
+        out.push_str(&format!("bio_backlog_jobs{{class=\"{}\",state=\"queued\"}} {}\n", c.class, c.queued));
+        out.push_str(&format!("bio_backlog_jobs{{class=\"{}\",state=\"running\"}} {}\n", c.class, c.running));
+    }
+    out.push_str(&format!("# HELP bio_admission_slots_in_use Compute slots in use.\n# TYPE bio_admission_slots_in_use gauge\nbio_admission_slots_in_use {}\n", r.admission.in_use));
+    out.push_str(&format!("# HELP bio_admission_waiting Requests waiting for a slot.\n# TYPE bio_admission_waiting gauge\nbio_admission_waiting {}\n", r.admission.waiting));
+    out.push_str(&format!("# HELP bio_admission_rejected_total Requests turned away at capacity.\n# TYPE bio_admission_rejected_total counter\nbio_admission_rejected_total {}\n", r.admission.rejected_total));
+    out
+}
+
+pub async fn report(State(s): State<Arc<AppState>>, Query(q): Query<BacklogQuery>) -> Result<Response, ApiError> {
+    let r = backlog(&s);
+    Ok(match q.format.as_deref().unwrap_or("json") {
+        "json" => Json(r).into_response(),
+        "prometheus" => ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], prometheus(&r)).into_response(),
+        other => return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: format!("unknown format {other}; expected json or prometheus") }))),
+    })
+}
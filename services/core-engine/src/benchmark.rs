@@ -0,0 +1,167 @@
+//! Throughput self-test for capacity planning and performance regressions.
+//!
+//! `POST /api/v1/bio/benchmark` times the engine's hot paths on this host, each on a fixed,
+//! deterministic workload: SMILES parsing and canonicalization, SDF parsing, force-field
+//! energy and gradient of a ligand in a pocket, docking scoring (pose, hydration and
+//! electrostatics of a library compound) and Morgan fingerprinting with a similarity search.
+//! Each kernel runs for `seconds_per_kernel` on one thread and then on `threads` at once, so
+//! the report gives single-core speed, whole-host throughput and how well it scales, plus the
+//! capacity that implies (compounds screened per hour, MD steps per second, searches of 100
+//! fingerprints per second). Given the report of an earlier run as `baseline`, each kernel's
+//! single-thread rate is compared with it, and any that fell by more than `tolerance` is
+//! listed in `regressions`.
+
+use axum::{extract::State, http::StatusCode, response::Json};
+use serde::{Deserialize, Serialize};
+use std::hint::black_box;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{charges::{self, ChargeModel}, chem::{self, Molecule}, conformer::{self, Restraint}, convert, fingerprint::{self, Fingerprint}, forcefield::System, frame::Frame, hydration, library, pockets, poses, ApiError, AppState, ErrorResponse};
+
+/// The target whose top pocket the energy and docking kernels use.
+const TARGET: &str = "EGFR";
+/// Library compounds in the workload.
+const COMPOUNDS: u64 = 256;
+/// Reference fingerprints each new one is compared with.
+const REFERENCES: usize = 100;
+/// Per kernel and pass; with every kernel selected a run takes at most 20 seconds.
+const MAX_SECONDS: f64 = 2.0;
+
+/// The fixed inputs every kernel draws from.
+struct Workload { smiles: Vec<String>, canonical: Vec<String>, molecules: Vec<Molecule>, sdf: Vec<String>, pocket: Option<pockets::Pocket>, receptor: Frame, sites: Vec<hydration::HydrationSite>, charged: Frame, restraints: Vec<Restraint>, pose: Vec<[f64; 3]>, references: Vec<Fingerprint> }
+
+impl Workload {
+    fn new() -> Self {
+        let smiles: Vec<String> = (0..COMPOUNDS).map(|n| library::compound(n * 7919)).collect();
+        let molecules: Vec<Molecule> = smiles.iter().filter_map(|s| chem::parse_smiles(s).ok()).collect();
+        let canonical = molecules.iter().map(Molecule::to_canonical_smiles).collect();
+        let sdf = molecules.iter().enumerate().filter_map(|(i, m)| convert::write_sdf(&library::compound_id(i as u64), "", m, &conformer::embed(m, i as u64), &[]).ok()).collect();
+        let pocket = pockets::detect(TARGET).into_iter().next();
+        let receptor = Frame::new(&pocket.as_ref().map(pockets::lining).unwrap_or_default());
+        let sites = pocket.as_ref().map(hydration::sites).unwrap_or_default();
+        let charged = Frame::sites(&pocket.as_ref().map(pockets::charged_sites).unwrap_or_default());
+        let ligand = &molecules[0];
+        let pose = poses::dock(TARGET, pocket.as_ref(), "benchmark", &ligand.to_canonical_smiles()).map(|p| p.coords).unwrap_or_else(|| conformer::embed(ligand, 0));
+        let references = molecules.iter().cycle().skip(1).take(REFERENCES).map(|m| fingerprint::morgan(m, fingerprint::RADIUS)).collect();
+        Self { smiles, canonical, restraints: conformer::restraints(ligand), molecules, sdf, pocket, receptor, sites, charged, pose, references }
+    }
+}
+
+/// One operation of a kernel on the `i`th input; the result only keeps the work from being optimized away.
+type Kernel = fn(&AppState, &Workload, usize) -> f64;
+
+/// Name, what one operation is, and the kernel.
+const KERNELS: &[(&str, &str, Kernel)] = &[
+    ("smiles_parsing", "molecules", |_, w, i| chem::canonicalize(&w.smiles[i % w.smiles.len()]).map_or(0.0, |c| c.len() as f64)),
+    ("sdf_parsing", "records", |_, w, i| poses::parse_sdf(&w.sdf[i % w.sdf.len()]).map_or(0.0, |r| r.2.len() as f64)),
+    ("energy_evaluation", "evaluations", |_, w, _| {
+        let mut grad = vec![[0.0; 3]; w.pose.len()];
+        System { restraints: &w.restraints, receptor: &w.receptor, metals: None, anchor: &w.pose, k_pos: 1.0, bias: None }.energy(&w.pose, Some(&mut grad))
+    }),
+    ("docking_scoring", "compounds", |s, w, i| {
+        let smiles = &w.canonical[i % w.canonical.len()];
+        let Some(pose) = poses::dock(TARGET, w.pocket.as_ref(), &library::compound_id(i as u64), smiles) else { return 0.0 };
+        let q = charges::assign(s, smiles, ChargeModel::Gasteiger).map(|q| q.atoms).unwrap_or_default();
+        hydration::displacement(&w.sites, &pose.coords) + charges::interaction(&pose.coords, &q, &w.charged)
+    }),
+    ("fingerprinting", "molecules", |_, w, i| {
+        let fp = fingerprint::morgan(&w.molecules[i % w.molecules.len()], fingerprint::RADIUS);
+        w.references.iter().map(|r| fingerprint::tanimoto(&fp, r)).fold(0.0, f64::max)
+    }),
+];
+
+/// Operations per second of `kernel` on `threads` threads over `budget`.
+fn rate(s: &AppState, w: &Workload, kernel: Kernel, threads: usize, budget: Duration) -> f64 {
+    let start = Instant::now();
+    let ops: usize = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads).map(|t| scope.spawn(move || {
+            let mut n = 0;
+            while n == 0 || start.elapsed() < budget { black_box(kernel(s, w, t + n * threads)); n += 1; }
+            n
+        })).collect();
+        workers.into_iter().map(|h| h.join().unwrap_or(0)).sum()
+    });
+    ops as f64 / start.elapsed().as_secs_f64()
+}
+
+#[derive(Deserialize)]
+pub struct BaselineKernel { name: String, single_thread_per_second: f64 }
+
+/// An earlier report, or just its `kernels`.
+#[derive(Deserialize)]
+pub struct Baseline { kernels: Vec<BaselineKernel> }
+
+#[derive(Deserialize)]
+pub struct BenchmarkRequest { seconds_per_kernel: Option<f64>, threads: Option<usize>, kernels: Option<Vec<String>>, baseline: Option<Baseline>, tolerance: Option<f64> }
+
+#[derive(Serialize)]
+pub struct Host { cpus: usize, threads: usize, arch: &'static str, os: &'static str, build: &'static str, version: &'static str }
+
+#[derive(Serialize)]
+pub struct KernelResult {
+    name: &'static str, unit: &'static str, single_thread_per_second: f64, parallel_per_second: f64, scaling_efficiency: f64,
+    #[serde(skip_serializing_if = "Option::is_none")] baseline_per_second: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] change_pct: Option<f64>, regression: bool,
+}
+
+#[derive(Serialize)]
+pub struct Capacity {
+    #[serde(skip_serializing_if = "Option::is_none")] screened_compounds_per_hour: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] md_steps_per_second: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")] sdf_records_per_second: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] similarity_searches_per_second: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct BenchmarkReport { host: Host, seconds_per_kernel: f64, kernels: Vec<KernelResult>, capacity: Capacity, regressions: Vec<&'static str>, warnings: Vec<String>, elapsed_us: u128 }
+
+fn round(v: f64, places: i32) -> f64 { let f = 10f64.powi(places); (v * f).round() / f }
+
+pub async fn run(State(s): State<Arc<AppState>>, Json(req): Json<BenchmarkRequest>) -> Result<Json<BenchmarkReport>, ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let t = Instant::now();
+    let seconds = req.seconds_per_kernel.unwrap_or(1.0);
+    if !(0.05..=MAX_SECONDS).contains(&seconds) { return Err(bad(format!("seconds_per_kernel must be between 0.05 and {MAX_SECONDS}"))); }
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let threads = req.threads.unwrap_or(cpus);
+    // More threads than cores would only measure the scheduler, and starve other requests.
+    if threads == 0 || threads > cpus { return Err(bad(format!("threads must be between 1 and {cpus}"))); }
+    let tolerance = req.tolerance.unwrap_or(0.1);
+    if !(0.0..1.0).contains(&tolerance) { return Err(bad("tolerance must be at least 0 and below 1".into())); }
+    let selected: Vec<&(&str, &str, Kernel)> = match &req.kernels {
+        Some(names) => names.iter().map(|n| KERNELS.iter().find(|k| k.0 == n).ok_or_else(|| bad(format!("unknown kernel {n}; expected one of {}", KERNELS.iter().map(|k| k.0).collect::<Vec<_>>().join(", "))))).collect::<Result<_, _>>()?,
+        None => KERNELS.iter().collect(),
+    };
+
+    let mut warnings = Vec::new();
+    let busy = s.admission.status().in_use.saturating_sub(1);
+    if busy > 0 { warnings.push(format!("{busy} other compute requests were running; throughput is understated")); }
+    if cfg!(debug_assertions) { warnings.push("this is a debug build; release builds are several times faster".into()); }
+
+    let budget = Duration::from_secs_f64(seconds);
+    let state = s.clone();
+    let measured: Vec<(&'static str, &'static str, f64, f64)> = tokio::task::spawn_blocking(move || {
+        let w = Workload::new();
+        selected.into_iter().map(|&(name, unit, kernel)| {
+            let single = rate(&state, &w, kernel, 1, budget);
+            let parallel = if threads == 1 { single } else { rate(&state, &w, kernel, threads, budget) };
+            (name, unit, single, parallel)
+        }).collect()
+    }).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: format!("benchmark failed: {e}") })))?;
+
+    let baseline = |name: &str| req.baseline.as_ref().and_then(|b| b.kernels.iter().find(|k| k.name == name)).map(|k| k.single_thread_per_second).filter(|&r| r > 0.0);
+    let kernels: Vec<KernelResult> = measured.into_iter().map(|(name, unit, single, parallel)| {
+        let base = baseline(name);
+        let change = base.map(|b| single / b - 1.0);
+        KernelResult {
+            name, unit, single_thread_per_second: round(single, 1), parallel_per_second: round(parallel, 1), scaling_efficiency: round(parallel / (single * threads as f64), 3),
+            baseline_per_second: base, change_pct: change.map(|c| round(c * 100.0, 1)), regression: change.is_some_and(|c| c < -tolerance),
+        }
+    }).collect();
+    let parallel = |name: &str| kernels.iter().find(|k| k.name == name).map(|k| k.parallel_per_second);
+    let capacity = Capacity {
+        screened_compounds_per_hour: parallel("docking_scoring").map(|r| round(r * 3600.0, 0)), md_steps_per_second: parallel("energy_evaluation"),
+        sdf_records_per_second: parallel("sdf_parsing"), similarity_searches_per_second: parallel("fingerprinting"),
+    };
+    let regressions = kernels.iter().filter(|k| k.regression).map(|k| k.name).collect();
+    let host = Host { cpus, threads, arch: std::env::consts::ARCH, os: std::env::consts::OS, build: if cfg!(debug_assertions) { "debug" } else { "release" }, version: env!("CARGO_PKG_VERSION") };
+    Ok(Json(BenchmarkReport { host, seconds_per_kernel: seconds, kernels, capacity, regressions, warnings, elapsed_us: t.elapsed().as_micros() }))
+}
@@ -0,0 +1 @@
+fn main() -> std::process::ExitCode { bio_engine::cli::run() }
@@ -0,0 +1,281 @@
+//! Bioisosteric replacements for lead optimization.
+//!
+//! A curated table of groups and their classical and non-classical bioisosteres: acids and
+//! their acidic heterocycles, aryl rings and their heteroaryl and saturated replacements,
+//! amide and ester linkers and the heterocycles that mimic them, and the common small-group
+//! swaps. A group is found in the molecule as an exact graph match, its atoms' heavy
+//! connections and hydrogens included, so a carboxylic acid never matches an ester and a
+//! para-phenylene only matches with both substituents para. The replacement takes over the
+//! group's bonds to the rest of the molecule attachment by attachment. Each analog is scored
+//! with descriptors against the parent (weight, cLogP, donors, acceptors, rotors), its Morgan
+//! similarity to it and any structural alerts it gains, and, given a target, by docking: the
+//! best of several placements in the pocket, minimized against the lining.
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use bio_engine_core::smarts::{Pattern, Target};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::{chem::{self, Molecule}, conformer, descriptors, filters, fingerprint, forcefield::{self, System}, frame::Frame, novelty, pockets, poses, record, retro, standardize, usage, ApiError, AppState, ErrorResponse, DOCK_MODEL};
+
+/// A replacement: name, SMILES, its attachment atoms in the group's attachment order, and why.
+type Replacement = (&'static str, &'static str, &'static [usize], &'static str);
+
+/// The knowledge base: group name, its SMILES, the atoms bonded to the rest of the molecule
+/// (an atom listed twice takes two bonds), and its replacements.
+const GROUPS: &[(&str, &str, &[usize], &[Replacement])] = &[
+    ("carboxylic acid", "C(=O)O", &[0], &[
+        ("tetrazole", "c1nn[nH]n1", &[0], "similar pKa and planar anion; more lipophilic and not glucuronidated"),
+        ("acyl sulfonamide", "C(=O)NS(C)(=O)=O", &[0], "acidic NH (pKa ~4-5) with an extra vector for potency"),
+        ("hydroxamic acid", "C(=O)NO", &[0], "weaker acid that also chelates metals"),
+        ("oxadiazolone", "C1=NOC(=O)N1", &[0], "acidic heterocycle, more permeable than the acid"),
+        ("3-hydroxyisoxazole", "c1cc(O)no1", &[0], "planar acidic heterocycle (pKa ~4-5)"),
+    ]),
+    ("phenyl", "c1ccccc1", &[0], &[
+        ("2-pyridyl", "c1ccccn1", &[0], "adds an acceptor and lowers logP"),
+        ("3-pyridyl", "c1cccnc1", &[0], "adds an acceptor and lowers logP"),
+        ("4-pyridyl", "c1ccncc1", &[0], "adds an acceptor and lowers logP"),
+        ("pyrimidin-5-yl", "c1cncnc1", &[0], "lowers logP further and blocks oxidation"),
+        ("2-thienyl", "c1cccs1", &[0], "classical ring-equivalent isostere of similar size"),
+        ("cyclohexyl", "C1CCCCC1", &[0], "saturated ring: more sp3 character, no π-stacking"),
+        ("bicyclo[1.1.1]pentyl", "C12CC(C1)C2", &[0], "saturated, shorter phenyl mimic with better solubility"),
+    ]),
+    ("para-phenylene", "c1ccc(cc1)", &[0, 3], &[
+        ("pyridine-2,5-diyl", "c1ccc(cn1)", &[0, 3], "adds an acceptor and lowers logP"),
+        ("bicyclo[1.1.1]pentane-1,3-diyl", "C12CC(C1)C2", &[0, 2], "saturated linear spacer, shorter by about 1 Å"),
+        ("trans-cyclohexane-1,4-diyl", "C1CCC(CC1)", &[0, 3], "saturated spacer of the same length"),
+    ]),
+    ("secondary amide", "C(=O)N", &[0, 2], &[
+        ("reversed amide", "NC(=O)", &[0, 1], "keeps the donor and acceptor, swaps their order"),
+        ("1,2,4-oxadiazole", "c1nc(on1)", &[0, 2], "hydrolytically stable amide mimic without the donor"),
+        ("1,2,3-triazole", "c1c[nH]nn1", &[0, 2], "stable trans-amide mimic with a strong dipole"),
+        ("fluoroalkene", "C(F)=C", &[0, 2], "isosteric and isoelectronic, no hydrolysis"),
+    ]),
+    ("ester", "C(=O)O", &[0, 2], &[
+        ("amide", "C(=O)N", &[0, 2], "resists esterases and adds a donor"),
+        ("1,2,4-oxadiazole", "c1nc(on1)", &[0, 2], "hydrolytically stable ester mimic"),
+    ]),
+    ("ether oxygen", "O", &[0, 0], &[
+        ("methylene", "C", &[0, 0], "classical isostere; removes the acceptor"),
+        ("difluoromethylene", "C(F)F", &[0, 0], "polar methylene that resists oxidation"),
+        ("amine", "N", &[0, 0], "adds a donor"),
+        ("thioether", "S", &[0, 0], "larger and more lipophilic"),
+    ]),
+    ("tert-butyl", "C(C)(C)C", &[0], &[
+        ("1-trifluoromethylcyclopropyl", "C1(C(F)(F)F)CC1", &[0], "metabolically stable tert-butyl mimic"),
+        ("trifluoromethyl", "C(F)(F)F", &[0], "smaller, blocks oxidation"),
+        ("3-methyloxetan-3-yl", "C1(C)COC1", &[0], "same shape, lower logP"),
+    ]),
+    ("methoxy", "OC", &[0], &[
+        ("difluoromethoxy", "OC(F)F", &[0], "blocks O-demethylation; weak donor"),
+        ("trifluoromethoxy", "OC(F)(F)F", &[0], "blocks O-demethylation; more lipophilic"),
+    ]),
+    ("chloro", "Cl", &[0], &[
+        ("methyl", "C", &[0], "classical isostere of similar size"),
+        ("trifluoromethyl", "C(F)(F)F", &[0], "electron-withdrawing and of similar size"),
+        ("bromo", "Br", &[0], "larger halogen with a stronger σ-hole"),
+        ("nitrile", "C#N", &[0], "polar, linear and electron-withdrawing"),
+    ]),
+    ("primary sulfonamide", "S(N)(=O)=O", &[0], &[
+        ("sulfoximine", "S(C)(=N)=O", &[0], "keeps the tetrahedral sulfur with better solubility"),
+        ("primary carboxamide", "C(N)=O", &[0], "smaller donor-acceptor pair"),
+    ]),
+    ("morpholine", "N1CCOCC1", &[0], &[
+        ("2-oxa-6-azaspiro[3.3]heptane", "N1CC2(C1)COC2", &[0], "more rigid and less lipophilic"),
+        ("4,4-difluoropiperidine", "N1CCC(F)(F)CC1", &[0], "removes the acceptor, lowers basicity"),
+        ("thiomorpholine dioxide", "N1CCS(=O)(=O)CC1", &[0], "keeps the acceptor, blocks oxidation"),
+    ]),
+];
+const DEFAULT_TOP_N: usize = 20;
+const MAX_TOP_N: usize = 200;
+/// Placements tried when docking each molecule.
+const ORIENTATIONS: usize = 6;
+const MINIMIZE_STEPS: usize = 200;
+
+#[derive(Deserialize)]
+pub struct BioisostereRequest { molecule: String, substructure: Option<String>, atoms: Option<Vec<usize>>, target_protein: Option<String>, pocket_id: Option<String>, rank_by: Option<String>, top_n: Option<usize>, retrosynthesis: Option<bool>, reference_libraries: Option<Vec<String>>, similarity_threshold: Option<f64> }
+
+#[derive(Serialize, Clone, Copy)]
+pub struct Docking { score: f64, interaction_energy: f64, clashes: usize, #[serde(skip_serializing_if = "Option::is_none")] delta_score: Option<f64> }
+#[derive(Serialize)]
+pub struct Profile { molecular_weight: f64, clogp: f64, hbd: usize, hba: usize, rotatable_bonds: usize, heavy_atoms: usize, #[serde(skip_serializing_if = "Option::is_none")] docking: Option<Docking> }
+#[derive(Serialize)]
+pub struct Site { group: &'static str, atoms: Vec<usize> }
+#[derive(Serialize)]
+pub struct Analog {
+    rank: usize, smiles: String, group: &'static str, replaced_atoms: Vec<usize>, replacement: &'static str, rationale: &'static str, similarity: f64, #[serde(flatten)] profile: Profile,
+    delta_molecular_weight: f64, delta_clogp: f64, #[serde(skip_serializing_if = "Vec::is_empty")] new_alerts: Vec<String>, #[serde(skip_serializing_if = "Option::is_none")] synthesis: Option<retro::Feasibility>, #[serde(skip_serializing_if = "Option::is_none")] novelty: Option<novelty::Novelty>,
+}
+#[derive(Serialize)]
+pub struct BioisostereResponse { bioisostere_id: String, molecule: String, smiles: String, parent: Profile, sites: Vec<Site>, #[serde(skip_serializing_if = "Option::is_none")] target: Option<String>, #[serde(skip_serializing_if = "Option::is_none")] pocket_id: Option<String>, rank_by: String, analogs: Vec<Analog>, #[serde(skip_serializing_if = "Vec::is_empty")] warnings: Vec<String> }
+
+fn round(v: f64) -> f64 { (v * 1000.0).round() / 1000.0 }
+
+/// Every non-overlapping exact match of a group, restricted to `within` when given. An atom
+/// matches when its element, aromaticity and hydrogens agree and it has exactly the group's
+/// heavy connections plus one per attachment it carries.
+fn occurrences(m: &Molecule, group: &Molecule, attach: &[usize], within: Option<&HashSet<usize>>) -> Vec<Vec<usize>> {
+    let (madj, gadj) = (m.neighbors(), group.neighbors());
+    let mut qadj = vec![Vec::new(); group.atoms.len()];
+    for (k, b) in group.bonds.iter().enumerate() { qadj[b.a].push((b.b, k)); qadj[b.b].push((b.a, k)); }
+    let mut used = vec![false; m.atoms.len()];
+    let mut out = Vec::new();
+    loop {
+        let atom_ok = |q: usize, i: usize| {
+            let (a, g) = (&m.atoms[i], &group.atoms[q]);
+            let n = attach.iter().filter(|&&k| k == q).count();
+            !used[i] && within.is_none_or(|w| w.contains(&i)) && a.element == g.element && a.aromatic == g.aromatic && a.charge == g.charge
+                && madj[i].len() == gadj[q].len() + n && usize::from(a.hydrogens) + n == usize::from(g.hydrogens)
+        };
+        let bond_ok = |q: usize, b: usize| m.bonds[b].kind == group.bonds[q].kind;
+        let Some(found) = m.find(&qadj, &atom_ok, &bond_ok) else { return out };
+        for &i in &found { used[i] = true; }
+        out.push(found);
+    }
+}
+
+/// The molecule with the matched group replaced: each bond from the group's attachment atom
+/// `k` to the rest of the molecule moves to the replacement's attachment atom `k`.
+fn replace(m: &Molecule, matched: &[usize], attach: &[usize], r: &Molecule, r_attach: &[usize]) -> Molecule {
+    let mut out = Molecule::default();
+    let mut map = vec![None; m.atoms.len()];
+    for (i, a) in m.atoms.iter().enumerate() {
+        if matched.contains(&i) { continue; }
+        map[i] = Some(out.atoms.len());
+        out.atoms.push(a.clone());
+    }
+    out.bonds.extend(m.bonds.iter().filter_map(|b| Some(chem::Bond { a: map[b.a]?, b: map[b.b]?, kind: b.kind })));
+    let offset = out.atoms.len();
+    out.atoms.extend(r.atoms.iter().cloned());
+    out.bonds.extend(r.bonds.iter().map(|b| chem::Bond { a: b.a + offset, b: b.b + offset, kind: b.kind }));
+    let mut taken = vec![false; attach.len()];
+    for b in &m.bonds {
+        let (inside, outside) = match (map[b.a], map[b.b]) { (None, Some(o)) => (b.a, o), (Some(o), None) => (b.b, o), _ => continue };
+        let Some(k) = (0..attach.len()).find(|&k| !taken[k] && matched[attach[k]] == inside) else { continue };
+        taken[k] = true;
+        let at = offset + r_attach[k];
+        out.atoms[at].hydrogens = out.atoms[at].hydrogens.saturating_sub(1);
+        out.bonds.push(chem::Bond { a: outside, b: at, kind: b.kind });
+    }
+    out
+}
+
+/// Best score of a few placements in the pocket, each minimized against the lining.
+fn dock(target: &str, pocket: &pockets::Pocket, lining: &Frame, smiles: &str, mol: &Molecule) -> Option<Docking> {
+    let restraints = conformer::restraints(mol);
+    (0..ORIENTATIONS).filter_map(|k| {
+        let pose = poses::dock(target, Some(pocket), &format!("{smiles}#{k}"), smiles)?;
+        let mut x = pose.coords.clone();
+        System { restraints: &restraints, receptor: lining, metals: None, anchor: &pose.coords, k_pos: 0.5, bias: None }.minimize(&mut x, MINIMIZE_STEPS);
+        let interaction = forcefield::interaction_energy(lining, &x, None);
+        Some(Docking { score: round(interaction + forcefield::internal_energy(&restraints, &x, None)), interaction_energy: round(interaction), clashes: forcefield::clashes(lining, &x), delta_score: None })
+    }).min_by(|a, b| a.score.total_cmp(&b.score))
+}
+
+fn profile(m: &Molecule) -> Profile {
+    Profile { molecular_weight: round(descriptors::molecular_weight(m).unwrap_or(0.0)), clogp: round(filters::logp(m)), hbd: filters::hbd(m), hba: filters::hba(m), rotatable_bonds: filters::rotatable_bonds(m), heavy_atoms: m.atoms.iter().filter(|a| a.element != "H").count(), docking: None }
+}
+
+pub async fn suggest(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<BioisostereRequest>) -> Result<Json<BioisostereResponse>, ApiError> {
+    let meter = usage::Meter::start();
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let smiles = standardize::resolve(&s, &headers, &req.molecule).await.canonical_smiles.ok_or_else(|| bad(format!("{} did not resolve to a structure", req.molecule)))?;
+    let mol = chem::parse_smiles(&smiles).map_err(bad)?;
+    let mut resp = run(&s, &req, smiles, &mol).map_err(bad)?;
+    if req.retrosynthesis != Some(false) {
+        let smiles: Vec<String> = resp.analogs.iter().map(|a| a.smiles.clone()).collect();
+        let (found, warnings) = s.retro.check(&smiles, retro::DEFAULT_MAX_STEPS).await;
+        for (a, f) in resp.analogs.iter_mut().zip(found) { a.synthesis = Some(f.summary()); }
+        resp.warnings.extend(warnings);
+    }
+    if let Some(ids) = &req.reference_libraries {
+        let threshold = novelty::threshold(req.similarity_threshold).map_err(bad)?;
+        let index = novelty::load(&s, &headers, ids)?;
+        let smiles: Vec<String> = resp.analogs.iter().map(|a| a.smiles.clone()).collect();
+        for (a, n) in resp.analogs.iter_mut().zip(novelty::annotate(&index, &smiles, threshold)) { a.novelty = Some(n); }
+    }
+    record(&s, &headers, "bioisosteres", &resp.molecule, DOCK_MODEL, &resp.bioisostere_id, &meter, &resp);
+    Ok(Json(resp))
+}
+
+fn run(s: &AppState, req: &BioisostereRequest, smiles: String, mol: &Molecule) -> Result<BioisostereResponse, String> {
+    let top_n = req.top_n.unwrap_or(DEFAULT_TOP_N);
+    if top_n == 0 || top_n > MAX_TOP_N { return Err(format!("top_n must be between 1 and {MAX_TOP_N}")); }
+    let rank_by = req.rank_by.clone().unwrap_or_else(|| if req.target_protein.is_some() { "docking".into() } else { "similarity".into() });
+    match rank_by.as_str() {
+        "similarity" => {}
+        "docking" if req.target_protein.is_some() => {}
+        "docking" => return Err("rank_by docking needs target_protein".into()),
+        other => return Err(format!("unknown rank_by {other}; expected similarity or docking")),
+    }
+
+    // The selection: a SMARTS match, atom indices, or every group the table knows.
+    let selection: Option<HashSet<usize>> = match (&req.substructure, &req.atoms) {
+        (Some(_), Some(_)) => return Err("give substructure or atoms, not both".into()),
+        (Some(smarts), None) => Some(Pattern::parse(smarts)?.find(&Target::new(mol)).ok_or_else(|| format!("substructure {smarts} not found in {smiles}"))?.into_iter().collect()),
+        (None, Some(atoms)) => {
+            if let Some(&i) = atoms.iter().find(|&&i| i >= mol.atoms.len()) { return Err(format!("atom {i} is out of range; the molecule has {} atoms", mol.atoms.len())); }
+            Some(atoms.iter().copied().collect())
+        }
+        (None, None) => None,
+    };
+    let mut sites = Vec::new();
+    let mut candidates = Vec::new();
+    for &(name, group, attach, replacements) in GROUPS {
+        let g = chem::parse_smiles(group)?;
+        let found = occurrences(mol, &g, attach, selection.as_ref());
+        for matched in found.into_iter().filter(|f| selection.as_ref().is_none_or(|sel| sel.len() == f.len())) {
+            for &(r_name, r_smiles, r_attach, rationale) in replacements {
+                candidates.push((name, matched.clone(), r_name, replace(mol, &matched, attach, &chem::parse_smiles(r_smiles)?, r_attach), rationale));
+            }
+            let mut atoms = matched;
+            atoms.sort_unstable();
+            sites.push(Site { group: name, atoms });
+        }
+    }
+    if sites.is_empty() {
+        let known: Vec<&str> = GROUPS.iter().map(|g| g.0).collect();
+        return Err(match selection {
+            Some(_) => format!("the selected atoms aren't a group with known bioisosteres ({})", known.join(", ")),
+            None => format!("{smiles} has no group with known bioisosteres ({})", known.join(", ")),
+        });
+    }
+
+    let target = req.target_protein.as_deref().map(|t| {
+        let found = pockets::detect(t);
+        let pocket = match &req.pocket_id { Some(id) => found.into_iter().find(|p| &p.pocket_id == id), None => found.into_iter().next() };
+        pocket.map(|p| (t, Frame::new(&pockets::lining(&p)), p)).ok_or_else(|| format!("no pocket {} on {t}", req.pocket_id.as_deref().unwrap_or_default()))
+    }).transpose()?;
+    let mut parent = profile(mol);
+    parent.docking = target.as_ref().and_then(|(t, lining, p)| dock(t, p, lining, &smiles, mol));
+    let parent_fp = fingerprint::morgan(mol, fingerprint::RADIUS);
+    let parent_alerts: HashSet<String> = s.alerts.check(mol, &|_| true).into_iter().map(|f| f.alert).collect();
+
+    let mut seen = HashSet::from([smiles.clone()]);
+    let mut warnings = Vec::new();
+    let mut analogs = Vec::new();
+    for (group, matched, replacement, product, rationale) in candidates {
+        let analog = product.to_canonical_smiles();
+        if !seen.insert(analog.clone()) { continue; }
+        let m = match chem::parse_smiles(&analog) { Ok(m) => m, Err(e) => { warnings.push(format!("{replacement} for the {group}: {e}")); continue } };
+        let mut p = profile(&m);
+        if let Some((t, lining, pocket)) = &target {
+            p.docking = dock(t, pocket, lining, &analog, &m).map(|mut d| { d.delta_score = parent.docking.map(|base| round(d.score - base.score)); d });
+        }
+        let new_alerts = s.alerts.check(&m, &|_| true).into_iter().map(|f| f.alert).filter(|a| !parent_alerts.contains(a)).collect::<HashSet<_>>().into_iter().collect();
+        let mut replaced_atoms = matched;
+        replaced_atoms.sort_unstable();
+        analogs.push(Analog {
+            rank: 0, smiles: analog, group, replaced_atoms, replacement, rationale, similarity: round(fingerprint::tanimoto(&parent_fp, &fingerprint::morgan(&m, fingerprint::RADIUS))),
+            delta_molecular_weight: round(p.molecular_weight - parent.molecular_weight), delta_clogp: round(p.clogp - parent.clogp), profile: p, new_alerts, synthesis: None, novelty: None,
+        });
+    }
+    match rank_by.as_str() {
+        "docking" => analogs.sort_by(|a, b| a.profile.docking.map_or(f64::INFINITY, |d| d.score).total_cmp(&b.profile.docking.map_or(f64::INFINITY, |d| d.score))),
+        _ => analogs.sort_by(|a, b| b.similarity.total_cmp(&a.similarity)),
+    }
+    analogs.truncate(top_n);
+    for (k, a) in analogs.iter_mut().enumerate() { a.rank = k + 1; }
+    Ok(BioisostereResponse { bioisostere_id: uuid::Uuid::new_v4().to_string(), molecule: req.molecule.clone(), smiles, parent, sites, target: target.as_ref().map(|t| t.0.to_string()), pocket_id: target.map(|t| t.2.pocket_id), rank_by, analogs, warnings })
+}
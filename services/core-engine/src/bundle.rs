@@ -0,0 +1,176 @@
+//! Portable project bundles for moving a project between deployments.
+//!
+//! `GET /api/v1/bio/export/project/{id}` writes the caller's project as NDJSON, one object per line
+//! tagged by `type`: a `header` (format, version, engine version and the model versions behind the
+//! project's results), then its saved `protocol`s and custom `force_field`s, each `library`
+//! followed by its `record`s (compounds and sequences), every `job` with its result, the `poses` of
+//! each screen (the docked structures), the `fes` surface of each metadynamics run, and every
+//! `measurement`. Archived libraries are read back from cold storage; an archived project must be
+//! restored first.
+//!
+//! `POST /api/v1/bio/import/project` reads such a bundle a line at a time into the caller's
+//! project. Everything keeps its ID, so references between items still hold; an item whose ID (or
+//! protocol or force field name) is already taken is skipped and reported, as is any line that
+//! fails. Measurements are standardized again. The response lists the bundle's model versions this
+//! engine doesn't run, since results computed by them won't match new ones.
+
+use axum::{body::Body, extract::{Path, State}, http::{header, HeaderMap, StatusCode}, response::{IntoResponse, Json, Response}};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::sync::Arc;
+
+use crate::{forcefields::Upload, jobs::Job, libraries::{self, Bundled, Record}, metad::FesGrid, poses::Pose, projects, protocols::Protocol, unix_now, validation, ApiError, AppState, ErrorResponse, DOCK_MODEL, FOLD_MODEL, MD_MODEL};
+
+const FORMAT: &str = "alice-bio-bundle";
+const VERSION: u32 = 1;
+/// The longest bundle line read; a screen's poses are the largest item.
+const MAX_LINE: usize = 64 * 1024 * 1024;
+/// Skipped items listed in the import response; the rest are only counted.
+const MAX_SKIPPED: usize = 100;
+
+#[derive(Serialize, Deserialize)]
+pub struct Header { format: String, version: u32, engine_version: String, exported_at_unix: u64, project: String, models: Vec<String> }
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Item {
+    Header(Header),
+    Protocol(Protocol),
+    ForceField(Upload),
+    Library(Bundled),
+    Record { library_id: String, #[serde(flatten)] record: Record },
+    Job(Box<Job>),
+    Poses { screen_id: String, poses: Vec<Pose> },
+    Fes { sim_id: String, grid: FesGrid },
+    Measurement(validation::Exported),
+}
+
+#[derive(Serialize)]
+struct RecordLine<'a> { library_id: &'a str, #[serde(flatten)] record: Record }
+
+/// One bundle line: `value` with its `type`.
+fn line(out: &mut Vec<u8>, kind: &str, value: impl Serialize) {
+    let Ok(serde_json::Value::Object(mut fields)) = serde_json::to_value(value) else { return };
+    fields.insert("type".into(), kind.into());
+    if serde_json::to_writer(&mut *out, &fields).is_ok() { out.push(b'\n'); }
+}
+
+pub async fn export(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(id): Path<String>) -> Result<Response, ApiError> {
+    let project = projects::own(&headers, &id)?;
+    if s.projects.is_archived(&project) { return Err(projects::conflict(format!("project {id} is archived; restore it to export"))); }
+    let jobs = s.jobs.for_project(&project);
+    let models: BTreeSet<String> = jobs.iter().map(|j| j.model.clone()).collect();
+    let mut out = Vec::new();
+    line(&mut out, "header", Header { format: FORMAT.into(), version: VERSION, engine_version: env!("CARGO_PKG_VERSION").into(), exported_at_unix: unix_now(), project: project.clone(), models: models.into_iter().collect() });
+    s.protocols.for_project(&project).into_iter().for_each(|p| line(&mut out, "protocol", p));
+    s.force_fields.for_project(&project).into_iter().for_each(|f| line(&mut out, "force_field", f));
+    for (library, records) in libraries::export(&s, &project).await? {
+        let library_id = library.library_id.clone();
+        line(&mut out, "library", library);
+        records.into_iter().for_each(|record| line(&mut out, "record", RecordLine { library_id: &library_id, record }));
+    }
+    jobs.into_iter().for_each(|j| line(&mut out, "job", j));
+    s.poses.of_project(&project).into_iter().for_each(|(screen_id, poses)| line(&mut out, "poses", serde_json::json!({ "screen_id": screen_id, "poses": poses })));
+    s.fes.of_project(&project).into_iter().for_each(|(sim_id, grid)| line(&mut out, "fes", serde_json::json!({ "sim_id": sim_id, "grid": grid })));
+    s.measurements.for_project(&project).into_iter().for_each(|m| line(&mut out, "measurement", m));
+    let disposition = format!("attachment; filename=\"{}.ndjson\"", project.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '_', "_"));
+    Ok(([(header::CONTENT_TYPE, "application/x-ndjson".to_string()), (header::CONTENT_DISPOSITION, disposition)], out).into_response())
+}
+
+#[derive(Serialize, Default)]
+pub struct Imported { protocols: usize, force_fields: usize, libraries: usize, records: usize, jobs: usize, screens: usize, simulations: usize, measurements: usize }
+
+#[derive(Serialize)]
+pub struct Skip { line: usize, error: String }
+
+#[derive(Serialize)]
+pub struct ImportResponse { project: String, source_project: String, engine_version: String, imported: Imported, skipped: usize, skips: Vec<Skip>, model_mismatches: Vec<String> }
+
+/// Model versions of `models` this engine doesn't run. Unversioned names (force fields, fit models) are left out.
+fn mismatches(models: &[String]) -> Vec<String> {
+    models.iter().filter(|m| m.contains('/') && ![MD_MODEL, DOCK_MODEL, FOLD_MODEL].contains(&m.as_str())).cloned().collect()
+}
+
+/// Where an import is: the header once read, and the libraries it created.
+struct Import { project: String, header: Option<Header>, libraries: HashSet<String>, imported: Imported }
+
+impl Import {
+    /// Stores one item; `Ok(false)` when its ID is taken.
+    fn take(&mut self, s: &AppState, item: Item) -> Result<bool, String> {
+        let p = self.project.as_str();
+        let n = &mut self.imported;
+        let (stored, count) = match item {
+            Item::Header(_) => return Err("a bundle has only one header".into()),
+            Item::Protocol(protocol) => (s.protocols.import(p, protocol), &mut n.protocols),
+            Item::ForceField(upload) => (s.force_fields.import(p, upload)?, &mut n.force_fields),
+            Item::Library(library) => {
+                let id = library.library_id.clone();
+                let stored = s.libraries.import(p, library)?;
+                if stored { self.libraries.insert(id); }
+                (stored, &mut n.libraries)
+            }
+            Item::Record { library_id, .. } if !self.libraries.contains(&library_id) => return Err(format!("library {library_id} was not imported")),
+            Item::Record { library_id, record } => { s.libraries.import_record(&library_id, record)?; (true, &mut n.records) }
+            Item::Job(mut job) => {
+                let stored = !s.jobs.contains(p, &job.job_id);
+                job.project = p.into();
+                if stored { s.jobs.insert(*job); }
+                (stored, &mut n.jobs)
+            }
+            Item::Poses { screen_id, poses } => (s.poses.import(p, &screen_id, poses), &mut n.screens),
+            Item::Fes { sim_id, grid } => (s.fes.import(p, &sim_id, grid), &mut n.simulations),
+            Item::Measurement(m) => (s.measurements.import(p, m)?, &mut n.measurements),
+        };
+        if stored { *count += 1; }
+        Ok(stored)
+    }
+
+    /// Handles one line; the header must come first.
+    fn line(&mut self, s: &AppState, number: usize, text: &[u8], skips: &mut Vec<Skip>, skipped: &mut usize) -> Result<(), ApiError> {
+        let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+        if text.iter().all(u8::is_ascii_whitespace) { return Ok(()); }
+        let item = serde_json::from_slice::<Item>(text).map_err(|e| format!("not a bundle item: {e}"));
+        if self.header.is_none() {
+            return match item {
+                Ok(Item::Header(h)) if h.format == FORMAT && h.version <= VERSION => { self.header = Some(h); Ok(()) }
+                Ok(Item::Header(h)) => Err(bad(format!("bundle is {} version {}; this engine reads {FORMAT} up to version {VERSION}", h.format, h.version))),
+                Ok(_) => Err(bad("a bundle starts with its header".into())),
+                Err(e) => Err(bad(format!("line {number}: {e}"))),
+            };
+        }
+        let error = match item.and_then(|i| self.take(s, i)) {
+            Ok(true) => return Ok(()),
+            Ok(false) => "ID already in use".into(),
+            Err(e) => e,
+        };
+        *skipped += 1;
+        if skips.len() < MAX_SKIPPED { skips.push(Skip { line: number, error }); }
+        Ok(())
+    }
+}
+
+pub async fn import(State(s): State<Arc<AppState>>, headers: HeaderMap, body: Body) -> Result<(StatusCode, Json<ImportResponse>), ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let project = s.projects.resolve(&headers);
+    let mut import = Import { project: project.clone(), header: None, libraries: HashSet::new(), imported: Imported::default() };
+    let (mut skips, mut skipped) = (Vec::new(), 0);
+    let (mut pending, mut number) = (Vec::new(), 0);
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let mut chunk = &chunk.map_err(|e| bad(format!("bundle upload failed after line {number}: {e}")))?[..];
+        while let Some(i) = chunk.iter().position(|&b| b == b'\n') {
+            pending.extend_from_slice(&chunk[..i]);
+            chunk = &chunk[i + 1..];
+            number += 1;
+            import.line(&s, number, &pending, &mut skips, &mut skipped)?;
+            pending.clear();
+        }
+        pending.extend_from_slice(chunk);
+        if pending.len() > MAX_LINE { return Err(bad(format!("line {} is longer than {} MiB", number + 1, MAX_LINE / (1024 * 1024)))); }
+    }
+    if !pending.is_empty() { import.line(&s, number + 1, &pending, &mut skips, &mut skipped)?; }
+    let header = import.header.ok_or_else(|| bad("bundle is empty".into()))?;
+    s.audit.record(&headers, &project, "import_project", &header.project, "", None);
+    Ok((StatusCode::CREATED, Json(ImportResponse { project, model_mismatches: mismatches(&header.models), source_project: header.project, engine_version: header.engine_version, imported: import.imported, skipped, skips })))
+}
@@ -0,0 +1,74 @@
+//! Job dependencies: requests that build on earlier jobs' results.
+//!
+//! Any JSON `POST` may declare `depends_on: [job_id, ...]` and, anywhere in its body, refer to
+//! a parent's result as `{"$job": job_id, "path": "/hits/0/compound_id"}` (a JSON Pointer into
+//! the result as `/jobs/:id` shows it; an empty path takes the whole result). The references
+//! are resolved just before the request runs, against the caller's project: every parent must
+//! exist and have completed, and each reference must name a declared parent and a path its
+//! result has. The job the request records lists its parents in `depends_on`.
+
+use axum::{body::Body, extract::{Request, State}, http::{header, HeaderValue, Method, StatusCode}, middleware::Next, response::{Json, Response}};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::{jobs::Job, not_found, projects, ApiError, AppState, ErrorResponse};
+
+/// Carries the resolved parents to `record`; a client-sent value is discarded.
+pub const PARENTS_HEADER: &str = "x-bio-depends-on";
+/// The largest body buffered for resolution, as axum's default `Json` limit.
+const MAX_BODY: usize = 2 * 1024 * 1024;
+
+/// The parents recorded in `headers` by `resolve`.
+pub fn parents(headers: &header::HeaderMap) -> Vec<String> {
+    headers.get(PARENTS_HEADER).and_then(|v| v.to_str().ok()).map(|v| v.split(',').filter(|id| !id.is_empty()).map(String::from).collect()).unwrap_or_default()
+}
+
+/// Replaces every `{"$job", "path"}` reference in `v` with what it points to.
+fn substitute(v: &mut Value, parents: &HashMap<String, Job>) -> Result<(), String> {
+    match v {
+        Value::Object(map) if map.contains_key("$job") => {
+            let id = map["$job"].as_str().ok_or("$job must be a job id")?;
+            if map.keys().any(|k| k != "$job" && k != "path") { return Err(format!("reference to job {id} takes only $job and path")); }
+            let path = match map.get("path") { None => "", Some(p) => p.as_str().ok_or("path must be a JSON Pointer string")? };
+            let job = parents.get(id).ok_or_else(|| format!("reference to job {id}, which is not in depends_on"))?;
+            *v = job.result.pointer(path).cloned().ok_or_else(|| format!("job {id} ({}) has no {path:?} in its result", job.kind))?;
+        }
+        Value::Object(map) => for x in map.values_mut() { substitute(x, parents)?; },
+        Value::Array(items) => for x in items { substitute(x, parents)?; },
+        _ => {}
+    }
+    Ok(())
+}
+
+pub async fn resolve(State(s): State<Arc<AppState>>, req: Request, next: Next) -> Result<Response, ApiError> {
+    let bad = |status: StatusCode, e: String| (status, Json(ErrorResponse { error: e }));
+    let (mut parts, body) = req.into_parts();
+    parts.headers.remove(PARENTS_HEADER);
+    let json = parts.headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).is_some_and(|v| v.starts_with("application/json"));
+    if parts.method != Method::POST || !json { return Ok(next.run(Request::from_parts(parts, body)).await); }
+    let bytes = axum::body::to_bytes(body, MAX_BODY).await.map_err(|e| bad(StatusCode::PAYLOAD_TOO_LARGE, format!("request body unavailable: {e}")))?;
+    // Bodies without references go through untouched, malformed ones included.
+    let Ok(mut body) = serde_json::from_slice::<Value>(&bytes) else { return Ok(next.run(Request::from_parts(parts, Body::from(bytes))).await) };
+    let declared = body.as_object_mut().and_then(|o| o.remove("depends_on"));
+    if declared.is_none() && !bytes.windows(6).any(|w| w == b"\"$job\"") { return Ok(next.run(Request::from_parts(parts, Body::from(bytes))).await); }
+    let ids: Vec<String> = match declared {
+        None => Vec::new(),
+        Some(Value::Array(ids)) => ids.into_iter().map(|id| id.as_str().map(String::from)).collect::<Option<_>>().ok_or_else(|| bad(StatusCode::BAD_REQUEST, "depends_on must be a list of job ids".into()))?,
+        Some(_) => return Err(bad(StatusCode::BAD_REQUEST, "depends_on must be a list of job ids".into())),
+    };
+    let project = projects::project_id(&parts.headers);
+    let mut jobs = HashMap::new();
+    for id in &ids {
+        let job = s.jobs.get(&project, id).ok_or_else(|| not_found("job", id))?;
+        if job.status != "completed" { return Err(bad(StatusCode::FAILED_DEPENDENCY, format!("job {id} is {}", job.status))); }
+        jobs.insert(id.clone(), job);
+    }
+    substitute(&mut body, &jobs).map_err(|e| bad(StatusCode::BAD_REQUEST, e))?;
+    let mut unique: Vec<String> = Vec::new();
+    for id in ids { if !unique.contains(&id) { unique.push(id); } }
+    if let Ok(v) = HeaderValue::from_str(&unique.join(",")) { parts.headers.insert(PARENTS_HEADER, v); }
+    let bytes = serde_json::to_vec(&body).unwrap_or_default();
+    parts.headers.insert(header::CONTENT_LENGTH, HeaderValue::from(bytes.len()));
+    Ok(next.run(Request::from_parts(parts, Body::from(bytes))).await)
+}
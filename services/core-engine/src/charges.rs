@@ -0,0 +1,58 @@
+//! AM1-BCC charges from an external QM service; the charge models are `bio_engine_core::charges`.
+//!
+//! AM1-BCC needs `BIO_QM_URL`: the service receives `{"smiles", "method", "net_charge"}` as
+//! JSON and answers `{"charges": [...]}`, in the atom order `charges::compute` takes. Results
+//! are cached per SMILES; async callers prefetch before the synchronous compute paths read them.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+pub use bio_engine_core::charges::*;
+
+use crate::{chem, AppState};
+
+#[derive(Serialize)]
+struct QmRequest<'a> { smiles: &'a str, method: &'static str, net_charge: i32 }
+#[derive(Deserialize)]
+struct QmResponse { charges: Vec<f64> }
+
+/// Client for the external QM charge service, with a per-SMILES cache.
+pub struct QmHook { client: reqwest::Client, url: Option<String>, cache: Mutex<HashMap<String, Result<Vec<f64>, String>>> }
+
+impl QmHook {
+    pub fn new(url: Option<String>) -> Self { Self { client: reqwest::Client::new(), url, cache: Mutex::new(HashMap::new()) } }
+
+    /// Fetches AM1-BCC charges for every SMILES not yet cached.
+    pub async fn prefetch(&self, smiles: &[String]) {
+        for smi in smiles {
+            if self.cache.lock().unwrap().contains_key(smi) { continue; }
+            let fetched = self.fetch(smi).await;
+            self.cache.lock().unwrap().insert(smi.clone(), fetched);
+        }
+    }
+
+    async fn fetch(&self, smiles: &str) -> Result<Vec<f64>, String> {
+        let url = self.url.as_deref().ok_or("am1-bcc charges need an external QM service; set BIO_QM_URL")?;
+        let net_charge = chem::parse_smiles(smiles).map(|m| m.atoms.iter().map(|a| a.charge as i32).sum()).unwrap_or(0);
+        let body = serde_json::to_string(&QmRequest { smiles, method: "am1-bcc", net_charge }).map_err(|e| e.to_string())?;
+        let resp = self.client.post(url).header(reqwest::header::CONTENT_TYPE, "application/json").body(body).timeout(std::time::Duration::from_secs(120)).send().await
+            .and_then(|r| r.error_for_status()).map_err(|e| format!("QM service: {e}"))?;
+        let parsed: QmResponse = serde_json::from_str(&resp.text().await.map_err(|e| format!("QM service: {e}"))?).map_err(|e| format!("QM service answered with {e}"))?;
+        Ok(parsed.charges)
+    }
+
+    fn cached(&self, smiles: &str) -> Result<Vec<f64>, String> {
+        self.cache.lock().unwrap().get(smiles).cloned().unwrap_or_else(|| Err(format!("no am1-bcc charges fetched for {smiles}")))
+    }
+}
+
+/// Fetches AM1-BCC charges ahead of a compute path when `charge_model` asks for them.
+pub async fn prefetch(s: &AppState, charge_model: Option<&str>, smiles: &[String]) {
+    if ChargeModel::parse(charge_model, "").ok() == Some(ChargeModel::Am1Bcc) { s.qm_charges.prefetch(smiles).await; }
+}
+
+/// Partial charges of a molecule parsed from `smiles` (canonical, as the QM cache is keyed).
+pub fn assign(s: &AppState, smiles: &str, model: ChargeModel) -> Result<Charges, String> {
+    compute(smiles, model, &|smi| s.qm_charges.cached(smi))
+}
@@ -0,0 +1,225 @@
+//! `bio-cli`, the engine's algorithms as an offline command-line tool.
+//!
+//! For clusters and workstations that can't run services: `bio-cli energy ligand.sdf -o e.json`
+//! runs the same handlers, validation and middleware as `POST /api/v1/bio/energy`, in process,
+//! with no server or network. Each subcommand takes one input, a local file or an inline value,
+//! maps its flags onto the endpoint's request fields (`--request FILE` supplies any others as
+//! JSON, which the flags override) and writes the response as JSON to `-o` or stdout. Molecule
+//! files are read through `convert` to canonical SMILES, FASTA files give their first record's
+//! sequence, and `convert` writes the converted structure itself. Nothing is kept between runs:
+//! stores are in memory unless the deployment variables that persist them are set. Errors go
+//! to stderr with exit status 1, and usage errors with 2.
+
+use axum::{body::Body, http::{Method, Request}, Router};
+use serde_json::{json, Map, Value};
+use std::path::Path;
+use std::process::ExitCode;
+use tower::ServiceExt;
+
+use crate::{convert, pools};
+
+/// How a flag's value becomes a request field.
+#[derive(Clone, Copy)]
+enum Kind { Text, Number, Count, Switch, List, File }
+
+/// What the subcommand's input is, when it names a file.
+#[derive(Clone, Copy, PartialEq)]
+enum Input { Molecule, Sequence, Structure }
+
+struct Command { name: &'static str, about: &'static str, path: &'static str, field: &'static str, input: Input, options: &'static [(&'static str, &'static str, Kind)] }
+
+const COMMANDS: &[Command] = &[
+    Command { name: "energy", about: "single-point energy of a molecule", path: "/api/v1/bio/energy", field: "molecule", input: Input::Molecule, options: &[
+        ("force-field", "force_field", Kind::Text), ("charge-model", "charge_model", Kind::Text), ("decompose", "decompose", Kind::Switch)] },
+    Command { name: "simulate", about: "molecular dynamics of a molecule", path: "/api/v1/bio/simulate", field: "molecule", input: Input::Molecule, options: &[
+        ("type", "simulation_type", Kind::Text), ("steps", "steps", Kind::Count), ("temperature", "temperature_k", Kind::Number), ("force-field", "force_field", Kind::Text),
+        ("thermostat", "thermostat", Kind::Text), ("validate-only", "validate_only", Kind::Switch)] },
+    Command { name: "screen", about: "virtual screen against a target", path: "/api/v1/bio/screen", field: "target_protein", input: Input::Sequence, options: &[
+        ("library-size", "library_size", Kind::Count), ("threshold", "binding_threshold", Kind::Number), ("anti-targets", "anti_targets", Kind::List),
+        ("charge-model", "charge_model", Kind::Text), ("diverse-top-n", "diverse_top_n", Kind::Count), ("plugins", "plugins", Kind::List),
+        ("rank-by", "rank_by", Kind::Text), ("validate-only", "validate_only", Kind::Switch)] },
+    Command { name: "predict", about: "structure and annotation of a protein or gene", path: "/api/v1/bio/predict", field: "sequence", input: Input::Sequence, options: &[
+        ("type", "prediction_type", Kind::Text), ("sequence-type", "sequence_type", Kind::Text), ("numbering", "numbering", Kind::Text), ("glycans", "glycans", Kind::Text),
+        ("uniprot", "uniprot_accession", Kind::Text), ("msa", "msa", Kind::File), ("min-orf-length", "min_orf_length", Kind::Count),
+        ("template", "template", Kind::File), ("template-id", "template_id", Kind::Text), ("template-chain", "template_chain", Kind::Text)] },
+    Command { name: "convert", about: "structure format conversion", path: "/api/v1/bio/convert", field: "input", input: Input::Structure, options: &[
+        ("to", "to", Kind::Text), ("from", "from", Kind::Text), ("name", "name", Kind::Text), ("hydrogens", "hydrogens", Kind::Text), ("charges", "charges", Kind::Text)] },
+];
+
+/// Options every subcommand takes besides its own.
+const COMMON: &str = "  -o, --output FILE     write the result to FILE instead of stdout\n  --request FILE        JSON request fields the flags don't cover\n  --project ID          run in project ID (its settings and job history)\n";
+
+struct Args { command: &'static Command, input: String, fields: Map<String, Value>, output: Option<String>, project: Option<String>, poses: Option<String> }
+
+/// Runs `bio-cli` with the process's arguments.
+pub fn run() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args = match parse(&args) {
+        Ok(Some(a)) => a,
+        Ok(None) => return ExitCode::SUCCESS,
+        Err(e) => { eprintln!("bio-cli: {e}\n\n{}", usage()); return ExitCode::from(2); }
+    };
+    tracing_subscriber::fmt().with_writer(std::io::stderr).with_env_filter(tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "bio_engine=error".into())).init();
+    let pools = pools::Pools::from_env();
+    let compute = pools.compute.handle().clone();
+    match pools.interactive.block_on(async { execute(crate::app(compute), args).await }) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => { eprintln!("bio-cli: {e}"); ExitCode::FAILURE }
+    }
+}
+
+fn usage() -> String {
+    let mut u = String::from("usage: bio-cli <command> <input> [options]\n\ncommands:\n");
+    for c in COMMANDS { u += &format!("  {:<10} {}\n", c.name, c.about); }
+    u + "\n`bio-cli <command> --help` lists a command's options."
+}
+
+fn command_usage(c: &Command) -> String {
+    let input = match c.input { Input::Molecule => "<molecule file or identifier>", Input::Sequence => "<FASTA file or sequence>", Input::Structure => "<structure file>" };
+    let mut u = format!("usage: bio-cli {} {input} [options]\n\n{}, as {}\n\noptions:\n", c.name, c.about, c.path);
+    for (flag, field, kind) in c.options {
+        let value = match kind { Kind::Text => " VALUE", Kind::Number => " X", Kind::Count => " N", Kind::Switch => "", Kind::List => " A,B", Kind::File => " FILE" };
+        u += &format!("  {:<21} {field}\n", format!("--{flag}{value}"));
+    }
+    if c.name == "screen" { u += "  --poses DIR           write each hit's docked pose to DIR/<compound>.sdf\n"; }
+    if c.name == "convert" { u += "  (--to defaults to the format of the -o file's extension)\n"; }
+    u + COMMON
+}
+
+/// Parses the arguments; `None` once help has been printed.
+fn parse(args: &[String]) -> Result<Option<Args>, String> {
+    let Some(name) = args.first() else { return Err("no command given".into()) };
+    if matches!(name.as_str(), "-h" | "--help" | "help") { println!("{}", usage()); return Ok(None); }
+    let command = COMMANDS.iter().find(|c| c.name == name).ok_or_else(|| format!("unknown command {name}"))?;
+    let (mut input, mut fields, mut output, mut project, mut poses) = (None, Map::new(), None, None, None);
+    let mut request = None;
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        let Some(flag) = arg.strip_prefix("--").or_else(|| (arg == "-o").then_some("output")).or_else(|| (arg == "-h").then_some("help")) else {
+            if input.replace(arg.clone()).is_some() { return Err(format!("{} takes one input; {arg} is a second", command.name)); }
+            continue;
+        };
+        let (flag, inline) = match flag.split_once('=') { Some((f, v)) => (f, Some(v.to_string())), None => (flag, None) };
+        if flag == "help" { println!("{}", command_usage(command)); return Ok(None); }
+        let option = command.options.iter().find(|(f, ..)| *f == flag);
+        if let Some((_, field, Kind::Switch)) = option {
+            fields.insert(field.to_string(), Value::Bool(inline.as_deref().is_none_or(|v| v != "false")));
+            continue;
+        }
+        let mut value = || inline.clone().or_else(|| rest.next().cloned()).ok_or_else(|| format!("--{flag} needs a value"));
+        match (flag, option) {
+            ("output", _) => output = Some(value()?),
+            ("project", _) => project = Some(value()?),
+            ("request", _) => request = Some(value()?),
+            ("poses", _) if command.name == "screen" => poses = Some(value()?),
+            (_, Some((_, field, kind))) => { let v = field_value(flag, *kind, value()?)?; fields.insert(field.to_string(), v); }
+            _ => return Err(format!("{} has no option --{flag}", command.name)),
+        }
+    }
+    let Some(input) = input else { return Err(format!("{} needs an input", command.name)) };
+    if let Some(path) = request {
+        let text = std::fs::read_to_string(&path).map_err(|e| format!("{path}: {e}"))?;
+        let Value::Object(base) = serde_json::from_str(&text).map_err(|e| format!("{path}: {e}"))? else { return Err(format!("{path} isn't a JSON object")) };
+        for (k, v) in base { fields.entry(k).or_insert(v); }
+    }
+    if command.name == "convert" && !fields.contains_key("to") {
+        let to = output.as_deref().and_then(|o| Path::new(o).extension()).and_then(|e| convert::format_name(e.to_str(), "").ok()).ok_or("convert needs --to, or an -o file whose extension names the format")?;
+        fields.insert("to".into(), Value::String(to));
+    }
+    Ok(Some(Args { command, input, fields, output, project, poses }))
+}
+
+fn field_value(flag: &str, kind: Kind, v: String) -> Result<Value, String> {
+    Ok(match kind {
+        Kind::Text => Value::String(v),
+        Kind::Number => json!(v.parse::<f64>().map_err(|_| format!("--{flag} takes a number, not {v}"))?),
+        Kind::Count => json!(v.parse::<u64>().map_err(|_| format!("--{flag} takes a whole number, not {v}"))?),
+        Kind::List => Value::Array(v.split(',').map(str::trim).filter(|s| !s.is_empty()).map(|s| Value::String(s.into())).collect()),
+        Kind::File => Value::String(std::fs::read_to_string(&v).map_err(|e| format!("{v}: {e}"))?),
+        Kind::Switch => Value::Bool(true),
+    })
+}
+
+async fn execute(app: Router, args: Args) -> Result<(), String> {
+    let Args { command, input, mut fields, output, project, poses } = args;
+    let project = project.as_deref();
+    let file = Path::new(&input).is_file().then(|| std::fs::read_to_string(&input).map_err(|e| format!("{input}: {e}"))).transpose()?;
+    let format = extension_format(&input);
+    // An identifier never ends in a structure format's extension, so that's a file that's missing.
+    if file.is_none() && (format.is_some() || command.input == Input::Structure) { return Err(format!("{input}: no such file")); }
+    let value = match (file, command.input) {
+        (None, _) => input.clone(),
+        (Some(text), Input::Structure) => {
+            if !fields.contains_key("from") { if let Some(f) = &format { fields.insert("from".into(), Value::String(f.clone())); } }
+            if format.as_deref() == Some("smiles") { first_smiles(&text, &mut fields) } else { text }
+        }
+        (Some(text), Input::Sequence) => fasta_sequence(&text).ok_or_else(|| format!("{input} has no sequence"))?,
+        (Some(text), Input::Molecule) => {
+            let text = if format.as_deref() == Some("smiles") { first_smiles(&text, &mut Map::new()) } else { text };
+            let body = json!({ "input": text, "from": format, "to": "smiles" });
+            let converted = call(&app, Method::POST, "/api/v1/bio/convert", project, Some(&body)).await.map_err(|e| format!("{input}: {e}"))?;
+            converted["canonical_smiles"].as_str().ok_or_else(|| format!("{input}: no structure read"))?.to_string()
+        }
+    };
+    fields.insert(command.field.into(), Value::String(value));
+    let resp = call(&app, Method::POST, command.path, project, Some(&Value::Object(fields))).await?;
+    if command.name == "convert" {
+        for w in resp["warnings"].as_array().into_iter().flatten().filter_map(Value::as_str) { eprintln!("bio-cli: warning: {w}"); }
+        return write(output.as_deref(), resp["output"].as_str().unwrap_or_default());
+    }
+    if let Some(w) = resp.get("warnings").and_then(Value::as_array) { for w in w.iter().filter_map(Value::as_str) { eprintln!("bio-cli: warning: {w}"); } }
+    if let (Some(dir), Some(id)) = (poses, resp["screen_id"].as_str()) {
+        std::fs::create_dir_all(&dir).map_err(|e| format!("{dir}: {e}"))?;
+        for cid in resp["hits"].as_array().into_iter().flatten().filter_map(|h| h["compound_id"].as_str()) {
+            let sdf = call(&app, Method::GET, &format!("/api/v1/bio/screens/{id}/hits/{cid}/pose?format=sdf"), project, None).await.map_err(|e| format!("pose of {cid}: {e}"))?;
+            let path = Path::new(&dir).join(format!("{cid}.sdf"));
+            std::fs::write(&path, sdf.as_str().unwrap_or_default()).map_err(|e| format!("{}: {e}", path.display()))?;
+        }
+    }
+    write(output.as_deref(), &(serde_json::to_string_pretty(&resp).map_err(|e| e.to_string())? + "\n"))
+}
+
+/// The format a file's extension names, if it names one.
+fn extension_format(path: &str) -> Option<String> {
+    Path::new(path).extension().and_then(|e| convert::format_name(e.to_str(), "").ok())
+}
+
+/// The first line's SMILES of a SMILES file, its title becoming the `name` unless one is set.
+fn first_smiles(text: &str, fields: &mut Map<String, Value>) -> String {
+    let line = text.lines().map(str::trim).find(|l| !l.is_empty() && !l.starts_with('#')).unwrap_or_default();
+    let (smiles, title) = line.split_once(char::is_whitespace).map_or((line, ""), |(s, t)| (s, t.trim()));
+    if !title.is_empty() { fields.entry("name").or_insert_with(|| Value::String(title.into())); }
+    smiles.to_string()
+}
+
+/// The first record's sequence of FASTA text, or the text itself without whitespace.
+fn fasta_sequence(text: &str) -> Option<String> {
+    let mut lines = text.lines().map(str::trim).skip_while(|l| l.is_empty() || l.starts_with(';'));
+    let first = lines.next()?;
+    let body: Vec<&str> = if first.starts_with('>') { lines.take_while(|l| !l.starts_with('>')).collect() } else { std::iter::once(first).chain(lines).collect() };
+    let seq: String = body.concat().chars().filter(|c| !c.is_whitespace()).collect();
+    (!seq.is_empty()).then_some(seq)
+}
+
+fn write(output: Option<&str>, text: &str) -> Result<(), String> {
+    match output {
+        Some(path) => std::fs::write(path, text).map_err(|e| format!("{path}: {e}")),
+        None => { print!("{text}"); Ok(()) }
+    }
+}
+
+/// Sends one request through the engine's router; the JSON body, or the text of any other.
+async fn call(app: &Router, method: Method, path: &str, project: Option<&str>, body: Option<&Value>) -> Result<Value, String> {
+    let mut req = Request::builder().method(method).uri(path);
+    if let Some(p) = project { req = req.header("x-project-id", p); }
+    let body = match body {
+        Some(b) => { req = req.header("content-type", "application/json"); Body::from(b.to_string()) }
+        None => Body::empty(),
+    };
+    let resp = match app.clone().oneshot(req.body(body).map_err(|e| e.to_string())?).await { Ok(r) => r, Err(e) => match e {} };
+    let status = resp.status();
+    let bytes = axum::body::to_bytes(resp.into_body(), usize::MAX).await.map_err(|e| e.to_string())?;
+    let value = serde_json::from_slice::<Value>(&bytes).unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()));
+    if status.is_success() { return Ok(value); }
+    Err(value.get("error").and_then(Value::as_str).map_or_else(|| status.to_string(), String::from))
+}
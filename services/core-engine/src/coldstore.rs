@@ -0,0 +1,79 @@
+//! Cold object storage for archived blobs.
+//!
+//! Archiving a library or a project moves its bulk (library records, job results) out of
+//! memory into this store under a key such as `libraries/<project>/<id>.jsonl`, and restoring
+//! reads it back and deletes it. `BIO_COLD_STORAGE` picks the backend: `file:///path` keeps
+//! one file per key under that directory (a mounted bucket works), and an `http://` or
+//! `https://` base URL is taken as an object store that answers `PUT`, `GET` and `DELETE` on
+//! `<url>/<key>` (an S3 gateway or bucket proxy). Unset, blobs stay in process memory, which
+//! keeps the archive semantics but frees nothing.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// A key for `parts`, each kept as is when it is plain (letters, digits, `-`, `_`, `.`) and
+/// hex-encoded behind a `~` otherwise, so no caller-chosen name can climb out of its prefix.
+pub fn key(parts: &[&str]) -> String {
+    parts.iter().map(|p| {
+        if !p.is_empty() && !p.starts_with('.') && p.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b)) { p.to_string() } else { format!("~{}", p.bytes().map(|b| format!("{b:02x}")).collect::<String>()) }
+    }).collect::<Vec<_>>().join("/")
+}
+
+pub enum ColdStore { Memory(Mutex<HashMap<String, Vec<u8>>>), Dir(PathBuf), Http { client: reqwest::Client, url: String } }
+
+impl ColdStore {
+    pub fn new(spec: Option<String>) -> Self {
+        match spec {
+            Some(s) if s.starts_with("file://") => Self::Dir(PathBuf::from(&s["file://".len()..])),
+            Some(s) if s.starts_with("http://") || s.starts_with("https://") => Self::Http { client: reqwest::Client::new(), url: s.trim_end_matches('/').to_string() },
+            Some(s) => { tracing::warn!("BIO_COLD_STORAGE={s} is neither file:// nor http(s)://; archiving to memory"); Self::Memory(Mutex::new(HashMap::new())) }
+            None => Self::Memory(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn backend(&self) -> &'static str { match self { Self::Memory(_) => "memory", Self::Dir(_) => "file", Self::Http { .. } => "http" } }
+
+    pub async fn put(&self, key: &str, bytes: Vec<u8>) -> Result<(), String> {
+        match self {
+            Self::Memory(m) => { m.lock().unwrap().insert(key.into(), bytes); Ok(()) }
+            Self::Dir(dir) => {
+                let path = dir.join(key);
+                if let Some(parent) = path.parent() { tokio::fs::create_dir_all(parent).await.map_err(|e| format!("cold storage {}: {e}", parent.display()))?; }
+                tokio::fs::write(&path, bytes).await.map_err(|e| format!("cold storage {}: {e}", path.display()))
+            }
+            Self::Http { client, url } => {
+                let resp = client.put(format!("{url}/{key}")).body(bytes).timeout(Duration::from_secs(300)).send().await.map_err(|e| format!("cold storage unreachable: {e}"))?;
+                if resp.status().is_success() { Ok(()) } else { Err(format!("cold storage refused {key}: HTTP {}", resp.status())) }
+            }
+        }
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Memory(m) => m.lock().unwrap().get(key).cloned().ok_or_else(|| format!("cold storage has no {key}")),
+            Self::Dir(dir) => tokio::fs::read(dir.join(key)).await.map_err(|e| format!("cold storage {key}: {e}")),
+            Self::Http { client, url } => {
+                let resp = client.get(format!("{url}/{key}")).timeout(Duration::from_secs(300)).send().await.map_err(|e| format!("cold storage unreachable: {e}"))?;
+                if !resp.status().is_success() { return Err(format!("cold storage {key}: HTTP {}", resp.status())); }
+                resp.bytes().await.map(|b| b.to_vec()).map_err(|e| format!("cold storage {key}: {e}"))
+            }
+        }
+    }
+
+    /// Removes a blob; one already gone is not an error.
+    pub async fn delete(&self, key: &str) -> Result<(), String> {
+        match self {
+            Self::Memory(m) => { m.lock().unwrap().remove(key); Ok(()) }
+            Self::Dir(dir) => match tokio::fs::remove_file(dir.join(key)).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(format!("cold storage {key}: {e}")),
+                _ => Ok(()),
+            },
+            Self::Http { client, url } => {
+                let resp = client.delete(format!("{url}/{key}")).timeout(Duration::from_secs(60)).send().await.map_err(|e| format!("cold storage unreachable: {e}"))?;
+                if resp.status().is_success() || resp.status() == reqwest::StatusCode::NOT_FOUND { Ok(()) } else { Err(format!("cold storage refused deleting {key}: HTTP {}", resp.status())) }
+            }
+        }
+    }
+}
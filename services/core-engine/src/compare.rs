@@ -0,0 +1,243 @@
+//! Side-by-side comparison of simulations, e.g. mutant against wild type or one force field
+//! against another.
+//!
+//! Simulations are compared through what their jobs recorded, so a run needs the relevant
+//! observables (see `observables`). RMSD over time comes from an `rmsd` series, interpolated
+//! onto a common grid over the steps every run covers. Energy distributions come from an
+//! `energy` series, or else the temperature-schedule trace, binned on shared bins; pairs of
+//! runs get the overlap coefficient of their histograms and the two-sample Kolmogorov–Smirnov
+//! statistic. Conformational clusters are found by k-means over every sampled step of every
+//! run, each described by the observables all runs share, z-scored on the pooled samples, and
+//! each cluster reports the fraction of each run's samples in it; a pair's cluster overlap is
+//! the sum over clusters of the smaller of the two fractions (1 for identical populations).
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{jobs::Job, not_found, projects, ApiError, AppState, ErrorResponse};
+
+const MAX_SIMULATIONS: usize = 10;
+const DEFAULT_POINTS: usize = 100;
+const DEFAULT_BINS: usize = 30;
+const DEFAULT_CLUSTERS: usize = 4;
+const KMEANS_ITERATIONS: usize = 100;
+
+#[derive(Deserialize)]
+pub struct CompareRequest { simulation_ids: Vec<String>, rmsd_series: Option<String>, points: Option<usize>, bins: Option<usize>, clusters: Option<usize> }
+
+#[derive(Deserialize)]
+struct StoredSeries { name: String, #[serde(rename = "type")] kind: String, steps: Vec<usize>, values: Vec<f64> }
+
+#[derive(Serialize)]
+pub struct SimulationSummary { sim_id: String, molecule: String, force_field: String, temperature_k: f64, steps: u64, energy_kcal_mol: f64, rmsd_angstrom: f64 }
+
+#[derive(Serialize)]
+pub struct AlignedSeries { sim_id: String, series: String, values: Vec<f64>, mean: f64, std_dev: f64 }
+
+#[derive(Serialize)]
+pub struct RmsdComparison { steps: Vec<usize>, runs: Vec<AlignedSeries> }
+
+#[derive(Serialize)]
+pub struct Distribution { sim_id: String, source: String, samples: usize, mean: f64, std_dev: f64, fractions: Vec<f64> }
+
+#[derive(Serialize)]
+pub struct EnergyComparison { unit: &'static str, bin_edges: Vec<f64>, runs: Vec<Distribution> }
+
+#[derive(Serialize)]
+pub struct ConformerCluster { cluster_id: usize, center: Vec<f64>, populations: Vec<f64> }
+
+#[derive(Serialize)]
+pub struct ClusterComparison { features: Vec<String>, sim_ids: Vec<String>, clusters: Vec<ConformerCluster> }
+
+#[derive(Serialize)]
+pub struct PairComparison {
+    a: String, b: String, #[serde(skip_serializing_if = "Option::is_none")] rmsd_mean_difference: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] energy_overlap: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")] energy_ks_statistic: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] cluster_overlap: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct CompareResponse {
+    simulations: Vec<SimulationSummary>, #[serde(skip_serializing_if = "Option::is_none")] rmsd: Option<RmsdComparison>, #[serde(skip_serializing_if = "Option::is_none")] energy: Option<EnergyComparison>,
+    #[serde(skip_serializing_if = "Option::is_none")] clusters: Option<ClusterComparison>, pairs: Vec<PairComparison>, warnings: Vec<String>,
+}
+
+fn round(v: f64) -> f64 { (v * 1e4).round() / 1e4 + 0.0 }
+
+fn mean_sd(v: &[f64]) -> (f64, f64) {
+    let n = v.len().max(1) as f64;
+    let mean = v.iter().sum::<f64>() / n;
+    (mean, (v.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n).sqrt())
+}
+
+fn series(job: &Job) -> Vec<StoredSeries> {
+    job.result["observables"]["series"].as_array().map(|a| a.iter().filter_map(|s| serde_json::from_value(s.clone()).ok()).collect()).unwrap_or_default()
+}
+
+/// `values` at `step`, linearly interpolated between samples and held flat outside them.
+fn at(steps: &[usize], values: &[f64], step: f64) -> f64 {
+    match steps.iter().position(|&s| s as f64 >= step) {
+        Some(0) => values[0],
+        Some(i) => { let (s0, s1) = (steps[i - 1] as f64, steps[i] as f64); values[i - 1] + (values[i] - values[i - 1]) * (step - s0) / (s1 - s0) }
+        None => values[values.len() - 1],
+    }
+}
+
+/// Largest gap between the empirical distribution functions of `a` and `b`.
+fn ks_statistic(a: &[f64], b: &[f64]) -> f64 {
+    let sorted = |v: &[f64]| { let mut v = v.to_vec(); v.sort_by(f64::total_cmp); v };
+    let (a, b) = (sorted(a), sorted(b));
+    let (mut i, mut j, mut d) = (0, 0, 0.0f64);
+    while i < a.len() && j < b.len() {
+        let x = a[i].min(b[j]);
+        while i < a.len() && a[i] <= x { i += 1; }
+        while j < b.len() && b[j] <= x { j += 1; }
+        d = d.max((i as f64 / a.len() as f64 - j as f64 / b.len() as f64).abs());
+    }
+    d
+}
+
+/// k-means (Lloyd) seeded with the first sample and then the farthest ones; returns each
+/// sample's cluster and the centres.
+fn kmeans(points: &[Vec<f64>], k: usize) -> (Vec<usize>, Vec<Vec<f64>>) {
+    let dist = |a: &[f64], b: &[f64]| a.iter().zip(b).map(|(x, y)| (x - y).powi(2)).sum::<f64>();
+    let mut centres = vec![points[0].clone()];
+    while centres.len() < k {
+        let far = (0..points.len()).max_by(|&a, &b| {
+            let da = centres.iter().map(|c| dist(&points[a], c)).fold(f64::INFINITY, f64::min);
+            let db = centres.iter().map(|c| dist(&points[b], c)).fold(f64::INFINITY, f64::min);
+            da.total_cmp(&db)
+        }).unwrap_or(0);
+        if centres.iter().any(|c| dist(c, &points[far]) == 0.0) { break; }
+        centres.push(points[far].clone());
+    }
+    let mut label = vec![0; points.len()];
+    for _ in 0..KMEANS_ITERATIONS {
+        let next: Vec<usize> = points.iter().map(|p| (0..centres.len()).min_by(|&a, &b| dist(p, &centres[a]).total_cmp(&dist(p, &centres[b]))).unwrap_or(0)).collect();
+        let moved = next != label;
+        label = next;
+        for (c, centre) in centres.iter_mut().enumerate() {
+            let members: Vec<&Vec<f64>> = points.iter().zip(&label).filter(|(_, &l)| l == c).map(|(p, _)| p).collect();
+            if members.is_empty() { continue; }
+            for (d, x) in centre.iter_mut().enumerate() { *x = members.iter().map(|p| p[d]).sum::<f64>() / members.len() as f64; }
+        }
+        if !moved { break; }
+    }
+    (label, centres)
+}
+
+fn compare_rmsd(jobs: &[Job], name: Option<&str>, points: usize, warnings: &mut Vec<String>) -> Option<RmsdComparison> {
+    let mut runs = Vec::new();
+    for j in jobs {
+        let found = series(j).into_iter().find(|s| s.kind == "rmsd" && !s.values.is_empty() && name.is_none_or(|n| s.name == n));
+        match found {
+            Some(s) => runs.push((j.job_id.clone(), s)),
+            None => { warnings.push(format!("simulation {} has no {} series; run it with an rmsd observable to compare RMSD over time", j.job_id, name.unwrap_or("rmsd"))); return None; }
+        }
+    }
+    let start = runs.iter().map(|r| r.1.steps[0]).max()?;
+    let end = runs.iter().map(|r| r.1.steps[r.1.steps.len() - 1]).min()?;
+    if end <= start { warnings.push("the runs' RMSD series share no steps".into()); return None; }
+    if runs.iter().any(|r| r.1.steps[r.1.steps.len() - 1] > end) { warnings.push(format!("RMSD is compared over the steps every run covers, {start} to {end}")); }
+    let grid: Vec<usize> = (0..points).map(|i| start + ((end - start) as f64 * i as f64 / (points - 1).max(1) as f64).round() as usize).collect();
+    let runs = runs.into_iter().map(|(sim_id, s)| {
+        let values: Vec<f64> = grid.iter().map(|&g| at(&s.steps, &s.values, g as f64)).collect();
+        let (mean, sd) = mean_sd(&values);
+        AlignedSeries { sim_id, series: s.name, values: values.into_iter().map(round).collect(), mean: round(mean), std_dev: round(sd) }
+    }).collect();
+    Some(RmsdComparison { steps: grid, runs })
+}
+
+/// Each run's energy samples and where they came from.
+fn energy_samples(j: &Job) -> Option<(String, Vec<f64>)> {
+    if let Some(s) = series(j).into_iter().find(|s| s.kind == "energy" && !s.values.is_empty()) { return Some((format!("observable {}", s.name), s.values)); }
+    let trace: Vec<f64> = j.result["annealing"]["trace"].as_array()?.iter().filter_map(|p| p["energy_kcal_mol"].as_f64()).collect();
+    (!trace.is_empty()).then(|| ("annealing trace".into(), trace))
+}
+
+fn compare_energy(jobs: &[Job], bins: usize, warnings: &mut Vec<String>) -> Option<(EnergyComparison, Vec<Vec<f64>>)> {
+    let mut samples = Vec::new();
+    for j in jobs {
+        match energy_samples(j) {
+            Some(s) => samples.push(s),
+            None => { warnings.push(format!("simulation {} has no energy samples; add an energy observable or a temperature_schedule to compare energy distributions", j.job_id)); return None; }
+        }
+    }
+    let lo = samples.iter().flat_map(|s| &s.1).copied().fold(f64::INFINITY, f64::min);
+    let hi = samples.iter().flat_map(|s| &s.1).copied().fold(f64::NEG_INFINITY, f64::max);
+    let width = if hi > lo { (hi - lo) / bins as f64 } else { 1.0 };
+    let bin_edges = (0..=bins).map(|i| round(lo + width * i as f64)).collect();
+    let raw: Vec<Vec<f64>> = samples.iter().map(|s| s.1.clone()).collect();
+    let runs = jobs.iter().zip(samples).map(|(j, (source, v))| {
+        let mut fractions = vec![0.0; bins];
+        for x in &v { fractions[(((x - lo) / width) as usize).min(bins - 1)] += 1.0 / v.len() as f64; }
+        let (mean, sd) = mean_sd(&v);
+        Distribution { sim_id: j.job_id.clone(), source, samples: v.len(), mean: round(mean), std_dev: round(sd), fractions: fractions.into_iter().map(round).collect() }
+    }).collect();
+    Some((EnergyComparison { unit: "kcal/mol", bin_edges, runs }, raw))
+}
+
+fn compare_clusters(jobs: &[Job], k: usize, warnings: &mut Vec<String>) -> Option<ClusterComparison> {
+    let all: Vec<Vec<StoredSeries>> = jobs.iter().map(series).collect();
+    let mut features: Vec<String> = all[0].iter().filter(|s| !s.values.is_empty()).map(|s| s.name.clone()).filter(|n| all.iter().all(|ss| ss.iter().any(|s| &s.name == n && !s.values.is_empty()))).collect();
+    features.dedup();
+    if features.is_empty() { warnings.push("the runs share no observables to cluster their conformations by".into()); return None; }
+    // One sample per step of each run's first shared series, with every feature read there.
+    let mut points = Vec::new();
+    let mut owner = Vec::new();
+    for (r, ss) in all.iter().enumerate() {
+        let get = |n: &str| ss.iter().find(|s| s.name == n);
+        let first = get(&features[0])?;
+        for &step in &first.steps {
+            points.push(features.iter().map(|f| get(f).map_or(0.0, |s| at(&s.steps, &s.values, step as f64))).collect::<Vec<f64>>());
+            owner.push(r);
+        }
+    }
+    let stats: Vec<(f64, f64)> = (0..features.len()).map(|d| mean_sd(&points.iter().map(|p| p[d]).collect::<Vec<_>>())).collect();
+    for p in &mut points { for (d, x) in p.iter_mut().enumerate() { *x = if stats[d].1 > 0.0 { (*x - stats[d].0) / stats[d].1 } else { 0.0 }; } }
+    let (label, centres) = kmeans(&points, k.min(points.len()));
+    let counts: Vec<usize> = (0..jobs.len()).map(|r| owner.iter().filter(|&&o| o == r).count()).collect();
+    let clusters = centres.iter().enumerate().map(|(c, centre)| ConformerCluster {
+        cluster_id: c + 1, center: centre.iter().enumerate().map(|(d, z)| round(stats[d].0 + z * stats[d].1)).collect(),
+        populations: (0..jobs.len()).map(|r| round(label.iter().zip(&owner).filter(|&(&l, &o)| l == c && o == r).count() as f64 / counts[r].max(1) as f64)).collect(),
+    }).collect();
+    Some(ClusterComparison { features, sim_ids: jobs.iter().map(|j| j.job_id.clone()).collect(), clusters })
+}
+
+pub async fn simulations(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<CompareRequest>) -> Result<Json<CompareResponse>, ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    if req.simulation_ids.len() < 2 || req.simulation_ids.len() > MAX_SIMULATIONS { return Err(bad(format!("simulation_ids must list between 2 and {MAX_SIMULATIONS} simulations"))); }
+    if req.simulation_ids.iter().enumerate().any(|(i, id)| req.simulation_ids[..i].contains(id)) { return Err(bad("simulation_ids must not repeat".into())); }
+    let points = req.points.unwrap_or(DEFAULT_POINTS);
+    let bins = req.bins.unwrap_or(DEFAULT_BINS);
+    let k = req.clusters.unwrap_or(DEFAULT_CLUSTERS);
+    if !(2..=1000).contains(&points) { return Err(bad("points must be between 2 and 1000".into())); }
+    if !(1..=200).contains(&bins) { return Err(bad("bins must be between 1 and 200".into())); }
+    if !(1..=20).contains(&k) { return Err(bad("clusters must be between 1 and 20".into())); }
+    let project = projects::project_id(&headers);
+    let jobs: Vec<Job> = req.simulation_ids.iter().map(|id| s.jobs.get(&project, id).filter(|j| j.kind == "simulate").ok_or_else(|| not_found("simulation", id))).collect::<Result<_, _>>()?;
+    let simulations = jobs.iter().map(|j| {
+        let r = &j.result;
+        SimulationSummary {
+            sim_id: j.job_id.clone(), molecule: r["molecule"].as_str().unwrap_or_default().into(), force_field: r["force_field"].as_str().unwrap_or_default().into(),
+            temperature_k: r["temperature_k"].as_f64().unwrap_or(0.0), steps: r["steps"].as_u64().unwrap_or(0), energy_kcal_mol: r["energy_kcal_mol"].as_f64().unwrap_or(0.0), rmsd_angstrom: r["rmsd_angstrom"].as_f64().unwrap_or(0.0),
+        }
+    }).collect();
+    let mut warnings = Vec::new();
+    let rmsd = compare_rmsd(&jobs, req.rmsd_series.as_deref(), points, &mut warnings);
+    let energy = compare_energy(&jobs, bins, &mut warnings);
+    let clusters = compare_clusters(&jobs, k, &mut warnings);
+    let mut pairs = Vec::new();
+    for a in 0..jobs.len() {
+        for b in a + 1..jobs.len() {
+            pairs.push(PairComparison {
+                a: jobs[a].job_id.clone(), b: jobs[b].job_id.clone(),
+                rmsd_mean_difference: rmsd.as_ref().map(|r| round(r.runs[a].values.iter().zip(&r.runs[b].values).map(|(x, y)| (x - y).abs()).sum::<f64>() / r.steps.len() as f64)),
+                energy_overlap: energy.as_ref().map(|e| round(e.0.runs[a].fractions.iter().zip(&e.0.runs[b].fractions).map(|(x, y)| x.min(*y)).sum())),
+                energy_ks_statistic: energy.as_ref().map(|e| round(ks_statistic(&e.1[a], &e.1[b]))),
+                cluster_overlap: clusters.as_ref().map(|c| round(c.clusters.iter().map(|k| k.populations[a].min(k.populations[b])).sum())),
+            });
+        }
+    }
+    Ok(Json(CompareResponse { simulations, rmsd, energy: energy.map(|e| e.0), clusters, pairs, warnings }))
+}
@@ -0,0 +1,68 @@
+//! `POST /convert`; the readers and writers are `bio_engine_core::convert`.
+
+use axum::{extract::State, http::StatusCode, response::Json};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+pub use bio_engine_core::convert::*;
+
+use crate::{chem, pdbqt, ApiError, AppState, ErrorResponse};
+
+/// Reads a structure; SMILES input may be any identifier the resolver knows.
+pub async fn load(s: &AppState, input: &str, format: &str, warnings: &mut Vec<String>) -> Result<Structure, String> {
+    if format != "smiles" { return parse(input, format, warnings); }
+    let input = input.trim();
+    let resolved = s.resolver.resolve(input).await;
+    let smiles = resolved.canonical_smiles.ok_or_else(|| format!("can't resolve {input} to a structure"))?;
+    Ok(Structure { name: resolved.name.unwrap_or_else(|| input.to_string()), mol: chem::parse_smiles(&smiles)?, coords: None, explicit_h: false })
+}
+
+#[derive(Deserialize)]
+pub struct ConvertRequest { input: String, from: Option<String>, to: String, name: Option<String>, hydrogens: Option<String>, charges: Option<String> }
+
+#[derive(Serialize)]
+pub struct ConvertResponse { from: String, to: String, name: String, canonical_smiles: String, atoms: usize, bonds: usize, #[serde(skip_serializing_if = "Option::is_none")] charges: Option<String>, output: String, warnings: Vec<String> }
+
+pub async fn convert(State(s): State<Arc<AppState>>, Json(req): Json<ConvertRequest>) -> Result<Json<ConvertResponse>, ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let from = format_name(req.from.as_deref(), &req.input).map_err(bad)?;
+    let to = format_name(Some(&req.to), "").map_err(bad)?;
+    let mut warnings = Vec::new();
+    let mut st = load(&s, &req.input, &from, &mut warnings).await.map_err(bad)?;
+    if let Some(n) = req.name { st.name = n; }
+    let smiles = st.mol.to_canonical_smiles();
+    let hydrogens = match req.hydrogens.as_deref().unwrap_or("keep") {
+        "add" | "all" => Hydrogens::All,
+        "polar" => Hydrogens::Polar,
+        "remove" | "none" => Hydrogens::None,
+        "keep" if to == "pdbqt" => Hydrogens::Polar,
+        "keep" => if st.explicit_h { Hydrogens::All } else { Hydrogens::None },
+        other => return Err(bad(format!("unknown hydrogens option {other}; expected keep, add, polar or remove"))),
+    };
+    let gasteiger = match req.charges.as_deref().unwrap_or(if matches!(to.as_str(), "mol2" | "pdbqt") { "gasteiger" } else { "none" }) {
+        "gasteiger" => true,
+        "none" => false,
+        other => return Err(bad(format!("unknown charges option {other}; expected gasteiger or none"))),
+    };
+    if to == "smiles" {
+        if matches!(req.hydrogens.as_deref(), Some("add" | "all" | "polar")) { warnings.push("SMILES output keeps hydrogens implicit".into()); }
+        let output = format!("{smiles} {}\n", st.name);
+        return Ok(Json(ConvertResponse { from, to, name: st.name, atoms: st.mol.atoms.len(), bonds: st.mol.bonds.len(), canonical_smiles: smiles, charges: None, output, warnings }));
+    }
+    let coords = coordinates(&mut st, &mut warnings);
+    let ex = explicit(&st.mol, &coords, hydrogens, gasteiger || to == "pdbqt").map_err(bad)?;
+    let charges: Vec<f64> = ex.charges.clone().unwrap_or_else(|| vec![0.0; ex.mol.atoms.len()]);
+    let output = match to.as_str() {
+        "sdf" => {
+            let props: Vec<(&str, String)> = if gasteiger { vec![("partial_charges", charges.iter().enumerate().map(|(i, q)| format!("{} {q:.4}", i + 1)).collect::<Vec<_>>().join("\n"))] } else { Vec::new() };
+            write_sdf(&st.name, &smiles, &ex.mol, &ex.coords, &props).map_err(bad)?
+        }
+        "mol2" => write_mol2(&st.name, &ex.mol, &ex.coords, gasteiger.then_some(charges.as_slice())),
+        "pdb" => write_pdb(&[format!("COMPND    {}", st.name)], &ex.mol, &ex.coords),
+        "pdbqt" => pdbqt::write_ligand(&st.name, &ex.mol, &ex.coords, &charges).0,
+        "mmcif" => write_mmcif(&st.name, &ex.mol, &ex.coords),
+        _ => write_xyz(&st.name, &ex.mol, &ex.coords),
+    };
+    if gasteiger && matches!(to.as_str(), "pdb" | "mmcif" | "xyz") { warnings.push(format!("{to} has no partial-charge field; charges were not written")); }
+    Ok(Json(ConvertResponse { from, to, name: st.name, canonical_smiles: smiles, atoms: ex.mol.atoms.len(), bonds: ex.mol.bonds.len(), charges: gasteiger.then(|| "gasteiger".into()), output, warnings }))
+}
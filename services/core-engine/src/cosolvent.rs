@@ -0,0 +1,69 @@
+//! `POST /simulate/mixed-solvent`: co-solvent probe mapping of a protein for cryptic and
+//! allosteric sites. The protein is a `structure` (PDB, first model); sampling, maps and
+//! hotspots are `bio_engine_core::cosolvent`. Hotspots away from every pocket of the starting
+//! structure are flagged cryptic: sites the probes find that the static structure doesn't show.
+//! A predicted structure (see `plddt`) has its low-confidence regions excluded by default; the
+//! receptor is rigid, so they can't be restrained instead.
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use bio_engine_core::{cosolvent, pdbqt, plddt};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{fnv1a, pockets, record, usage, ApiError, AppState, ErrorResponse};
+
+const MODEL: &str = "cosolvent-probe-mc";
+const DEFAULT_STEPS: usize = 1_000;
+const MAX_STEPS: usize = 10_000;
+const DEFAULT_TEMPERATURE: f64 = 300.0;
+
+#[derive(Deserialize)]
+pub struct MixedSolventRequest {
+    structure: Option<String>, target_protein: Option<String>, probes: Option<Vec<String>>, steps: Option<usize>,
+    temperature_k: Option<f64>, hotspot_threshold_kcal_mol: Option<f64>, seed: Option<u64>, low_confidence: Option<String>, plddt_cutoff: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct MixedSolventResponse {
+    sim_id: String, target: String, simulation_type: &'static str, steps: usize, temperature_k: f64,
+    hotspot_threshold_kcal_mol: f64, cryptic_hotspots: usize, #[serde(flatten)] map: cosolvent::Map, #[serde(skip_serializing_if = "Option::is_none")] confidence: Option<plddt::Report>,
+    #[serde(skip_serializing_if = "Vec::is_empty")] warnings: Vec<String>,
+}
+
+pub async fn simulate(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<MixedSolventRequest>) -> Result<Json<MixedSolventResponse>, ApiError> {
+    let meter = usage::Meter::start();
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let names = req.probes.unwrap_or_else(|| cosolvent::DEFAULT_PROBES.iter().map(|p| p.to_string()).collect());
+    if names.is_empty() { return Err(bad("probes must not be empty".into())); }
+    let mut probes = Vec::new();
+    for name in &names {
+        let p = cosolvent::probe(name.trim()).ok_or_else(|| bad(format!("unknown probe {name}; known: {}", cosolvent::PROBES.iter().map(|p| p.name).collect::<Vec<_>>().join(", "))))?;
+        if !probes.iter().any(|q: &&cosolvent::Probe| q.name == p.name) { probes.push(p); }
+    }
+    let steps = req.steps.unwrap_or(DEFAULT_STEPS);
+    if !(100..=MAX_STEPS).contains(&steps) { return Err(bad(format!("steps must be between 100 and {MAX_STEPS}"))); }
+    let temperature_k = req.temperature_k.unwrap_or(DEFAULT_TEMPERATURE);
+    if !(temperature_k > 0.0 && temperature_k <= 500.0) { return Err(bad("temperature_k must be in (0, 500]".into())); }
+    let hotspot_dg = req.hotspot_threshold_kcal_mol.unwrap_or(cosolvent::DEFAULT_HOTSPOT_DG);
+    if hotspot_dg >= 0.0 { return Err(bad("hotspot_threshold_kcal_mol must be negative".into())); }
+
+    let mut warnings = Vec::new();
+    let pdb = req.structure.as_deref().ok_or_else(|| bad("mixed-solvent mapping needs the protein's structure (PDB)".into()))?;
+    if req.low_confidence.as_deref() == Some("restrain") { return Err(bad("the receptor is rigid in mixed-solvent mapping; low_confidence must be exclude or keep".into())); }
+    let (pdb, confidence) = plddt::apply(pdb, req.low_confidence.as_deref(), req.plddt_cutoff, plddt::Policy::Exclude).map_err(bad)?;
+    let residues = pdbqt::parse_residues(&pdb, false);
+    if residues.iter().any(|r| r.hetero) { warnings.push("hetero groups are left out: the map is of the apo protein".into()); }
+    let (atoms, starting) = (cosolvent::atoms_of(&residues), pockets::detect_in(&residues));
+    let target = req.target_protein.unwrap_or_else(|| "structure".into());
+    let seed = req.seed.unwrap_or_else(|| fnv1a(target.as_bytes()));
+    let settings = cosolvent::Settings { probes, steps, temperature_k, hotspot_dg, seed };
+    let map = tokio::task::spawn_blocking(move || cosolvent::run(&atoms, &starting, &settings)).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: format!("mixed-solvent run failed: {e}") })))?.map_err(bad)?;
+    if map.hotspots.is_empty() { warnings.push("no hotspots at the threshold; sample longer or bring hotspot_threshold_kcal_mol closer to zero".into()); }
+    let resp = MixedSolventResponse {
+        sim_id: uuid::Uuid::new_v4().to_string(), target, simulation_type: "mixed-solvent", steps, temperature_k, hotspot_threshold_kcal_mol: hotspot_dg,
+        cryptic_hotspots: map.hotspots.iter().filter(|h| h.cryptic).count(), map, confidence, warnings,
+    };
+    record(&s, &headers, "mixed_solvent", &resp.target, MODEL, &resp.sim_id, &meter, &resp);
+    Ok(Json(resp))
+}
@@ -0,0 +1,18 @@
+//! NDJSON streaming of `/energy` decompositions; the decomposition is
+//! `bio_engine_core::decompose`.
+
+use axum::{body::Body, http::header, response::{IntoResponse, Response}};
+use serde::Serialize;
+
+pub use bio_engine_core::decompose::*;
+
+pub const NDJSON: &str = "application/x-ndjson";
+
+/// An NDJSON response: `summary` tagged `"type": "summary"`, then the decomposition's lines as
+/// they are computed.
+pub fn stream(summary: &impl Serialize, d: Decomposer) -> Response {
+    let mut head = serde_json::to_value(summary).unwrap_or_default();
+    if let Some(o) = head.as_object_mut() { o.insert("type".into(), "summary".into()); }
+    let lines = std::iter::once(head.to_string()).chain(d.map(|line| serde_json::to_string(&line).unwrap_or_default())).map(|l| Ok::<_, std::convert::Infallible>(l + "\n"));
+    ([(header::CONTENT_TYPE, NDJSON)], Body::from_stream(futures_util::stream::iter(lines))).into_response()
+}
@@ -0,0 +1,28 @@
+//! The depiction endpoint; layout and rendering are `bio_engine_core::depict`.
+
+use axum::{extract::Query, http::{header, StatusCode}, response::{IntoResponse, Json, Response}};
+use serde::Deserialize;
+
+pub use bio_engine_core::depict::*;
+
+use crate::{resolver, ApiError, ErrorResponse};
+
+/// URL of the depiction endpoint for a SMILES string, as attached to screening hits.
+pub fn url(smiles: &str) -> String { format!("/api/v1/bio/depict?smiles={}", resolver::percent_encode(smiles)) }
+
+#[derive(Deserialize)]
+pub struct DepictQuery { smiles: String, format: Option<String>, size: Option<u32> }
+
+/// `smiles` may be any identifier the local resolver understands (name, library ID, SMILES).
+pub async fn depict(Query(q): Query<DepictQuery>) -> Result<Response, ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let smiles = resolver::resolve_local(&q.smiles).and_then(|r| r.canonical_smiles).ok_or_else(|| bad(format!("cannot parse molecule {}", q.smiles)))?;
+    let mol = crate::chem::parse_smiles(&smiles).map_err(bad)?;
+    let size = q.size.unwrap_or(300).clamp(64, 2048);
+    Ok(match q.format.as_deref().unwrap_or("svg") {
+        "svg" => ([(header::CONTENT_TYPE, "image/svg+xml")], svg(&mol, size)).into_response(),
+        "png" => ([(header::CONTENT_TYPE, "image/png")], png(&mol, size)).into_response(),
+        "json" => Json(layout(&mol)).into_response(),
+        other => return Err(bad(format!("unknown format {other}; expected svg, png or json"))),
+    })
+}
@@ -0,0 +1,20 @@
+//! `GET /mass`; formula, mass and isotope pattern are `bio_engine_core::descriptors`.
+
+use axum::{extract::{Query, State}, http::{HeaderMap, StatusCode}, response::Json};
+use serde::Deserialize;
+use std::sync::Arc;
+
+pub use bio_engine_core::descriptors::*;
+
+use crate::{chem, standardize, ApiError, AppState, ErrorResponse};
+
+#[derive(Deserialize)]
+pub struct MassQuery { molecule: String }
+
+pub async fn mass(State(s): State<Arc<AppState>>, headers: HeaderMap, Query(q): Query<MassQuery>) -> Result<Json<MassReport>, ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let resolved = standardize::resolve(&s, &headers, &q.molecule).await;
+    let smiles = resolved.canonical_smiles.ok_or_else(|| bad(format!("can't resolve {} to a structure", q.molecule)))?;
+    let mol = chem::parse_smiles(&smiles).map_err(bad)?;
+    mass_report(&mol).map(Json).map_err(bad)
+}
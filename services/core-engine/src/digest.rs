@@ -0,0 +1,84 @@
+//! In-silico protease digestion for mass spectrometry.
+//!
+//! Each protease cleaves after (or, for Asp-N, before) its residues, with the usual proline
+//! rule where it applies: trypsin cuts after K/R but not before P. Peptides spanning up to
+//! `missed_cleavages` uncut sites are listed with their monoisotopic mass and the m/z of each
+//! requested charge state, (M + z·1.007276)/z. Cysteines can be counted carbamidomethylated
+//! (+57.02146), as after iodoacetamide alkylation.
+
+use axum::{http::StatusCode, response::Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{properties, ApiError, ErrorResponse};
+
+const PROTON: f64 = 1.007276;
+const CARBAMIDOMETHYL: f64 = 57.02146;
+
+/// Cleavage rule: residues, whether the cut is before them, and whether a following Pro blocks it.
+struct Protease { name: &'static str, residues: &'static [u8], before: bool, proline_blocks: bool }
+
+const PROTEASES: [Protease; 9] = [
+    Protease { name: "trypsin", residues: b"KR", before: false, proline_blocks: true },
+    Protease { name: "trypsin_p", residues: b"KR", before: false, proline_blocks: false },
+    Protease { name: "lys_c", residues: b"K", before: false, proline_blocks: false },
+    Protease { name: "arg_c", residues: b"R", before: false, proline_blocks: true },
+    Protease { name: "glu_c", residues: b"E", before: false, proline_blocks: true },
+    Protease { name: "asp_n", residues: b"D", before: true, proline_blocks: false },
+    Protease { name: "chymotrypsin", residues: b"FWY", before: false, proline_blocks: true },
+    Protease { name: "pepsin", residues: b"FL", before: false, proline_blocks: false },
+    Protease { name: "cnbr", residues: b"M", before: false, proline_blocks: false },
+];
+
+#[derive(Deserialize)]
+pub struct DigestRequest { sequence: String, protease: Option<String>, missed_cleavages: Option<usize>, min_length: Option<usize>, max_length: Option<usize>, charges: Option<Vec<u32>>, carbamidomethyl: Option<bool> }
+
+#[derive(Serialize)]
+pub struct Ion { charge: u32, mz: f64 }
+#[derive(Serialize)]
+pub struct Peptide { sequence: String, start: usize, end: usize, missed_cleavages: usize, monoisotopic_mass: f64, ions: Vec<Ion> }
+#[derive(Serialize)]
+pub struct DigestResponse { protease: String, sequence_length: usize, cleavage_sites: usize, peptides: Vec<Peptide>, coverage: f64 }
+
+fn round(v: f64, places: i32) -> f64 { let f = 10f64.powi(places); (v * f).round() / f }
+
+/// Peptide boundaries: 0, every cleavage point, and the sequence end.
+fn boundaries(seq: &[u8], p: &Protease) -> Vec<usize> {
+    let mut out = vec![0];
+    out.extend((1..seq.len()).filter(|&k| {
+        let (left, right) = (seq[k - 1], seq[k]);
+        if p.before { p.residues.contains(&right) } else { p.residues.contains(&left) && !(p.proline_blocks && right == b'P') }
+    }));
+    out.push(seq.len());
+    out
+}
+
+pub async fn digest(Json(req): Json<DigestRequest>) -> Result<Json<DigestResponse>, ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let seq: Vec<u8> = req.sequence.bytes().filter(|b| !b.is_ascii_whitespace()).map(|b| b.to_ascii_uppercase()).collect();
+    if seq.is_empty() { return Err(bad("sequence is empty".into())); }
+    if properties::monoisotopic_mass(&seq).is_none() { return Err(bad("sequence has nonstandard residues; only the 20 standard amino acids have masses".into())); }
+    let name = req.protease.as_deref().unwrap_or("trypsin").to_lowercase().replace(['-', '/', ' '], "_");
+    let protease = PROTEASES.iter().find(|p| p.name == name)
+        .ok_or_else(|| bad(format!("unknown protease {name}; expected one of {}", PROTEASES.iter().map(|p| p.name).collect::<Vec<_>>().join(", "))))?;
+    let charges = req.charges.unwrap_or_else(|| vec![1, 2, 3]);
+    if charges.iter().any(|&z| z == 0 || z > 10) { return Err(bad("charges must be between 1 and 10".into())); }
+    let (missed, min_len, max_len) = (req.missed_cleavages.unwrap_or(0).min(5), req.min_length.unwrap_or(6), req.max_length.unwrap_or(50));
+    let alkylated = req.carbamidomethyl.unwrap_or(false);
+    let bounds = boundaries(&seq, protease);
+    let mut covered = vec![false; seq.len()];
+    let mut peptides = Vec::new();
+    for i in 0..bounds.len() - 1 {
+        for m in 0..=missed {
+            let Some(&end) = bounds.get(i + m + 1) else { break };
+            let (start, piece) = (bounds[i], &seq[bounds[i]..end]);
+            if piece.len() < min_len || piece.len() > max_len { continue; }
+            let cys = piece.iter().filter(|&&r| r == b'C').count() as f64;
+            let mass = properties::monoisotopic_mass(piece).unwrap_or_default() + if alkylated { cys * CARBAMIDOMETHYL } else { 0.0 };
+            covered[start..end].iter_mut().for_each(|c| *c = true);
+            let ions = charges.iter().map(|&z| Ion { charge: z, mz: round((mass + z as f64 * PROTON) / z as f64, 4) }).collect();
+            peptides.push(Peptide { sequence: String::from_utf8_lossy(piece).into(), start, end: end - 1, missed_cleavages: m, monoisotopic_mass: round(mass, 4), ions });
+        }
+    }
+    let coverage = covered.iter().filter(|&&c| c).count() as f64 / seq.len() as f64;
+    Ok(Json(DigestResponse { protease: protease.name.into(), sequence_length: seq.len(), cleavage_sites: bounds.len() - 2, peptides, coverage: round(coverage, 3) }))
+}
@@ -0,0 +1,219 @@
+//! Four-parameter logistic fits of dose-response data.
+//!
+//! `POST /fit/dose-response` fits `y = bottom + (top - bottom) / (1 + 10^((log EC50 - log x) ·
+//! hill))` to measured responses at each concentration by Levenberg–Marquardt least squares,
+//! with the concentration on a log10 scale as in GraphPad's variable-slope model. `top` and
+//! `bottom` can be held fixed (e.g. at 100 and 0 for normalized data). Standard errors come from
+//! the covariance `s² (JᵀJ)⁻¹` at the optimum and confidence intervals from Student's t on the
+//! residual degrees of freedom; the interval of the midpoint is computed on log EC50 and
+//! transformed back, so it is asymmetric. A falling curve (negative Hill slope) reports its
+//! midpoint as IC50, a rising one as EC50. The fit is recorded as a job like every compute call,
+//! so measured potencies sit in the project's history next to the predictions they check.
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{record, usage, ApiError, AppState, ErrorResponse};
+
+const MODEL: &str = "four-parameter-logistic";
+const MAX_ITERATIONS: usize = 500;
+const CURVE_POINTS: usize = 50;
+/// A midpoint interval wider than this many log units is reported as poorly constrained.
+const WIDE_LOG_CI: f64 = 2.0;
+const UNITS: &[(&str, f64)] = &[("M", 1.0), ("mM", 1e-3), ("uM", 1e-6), ("µM", 1e-6), ("nM", 1e-9), ("pM", 1e-12)];
+
+#[derive(Deserialize)]
+pub struct DoseResponseRequest { compound: Option<String>, concentrations: Vec<f64>, responses: Vec<f64>, concentration_unit: Option<String>, fix_top: Option<f64>, fix_bottom: Option<f64>, confidence_level: Option<f64> }
+
+/// A fitted value; fixed parameters have no error or interval.
+#[derive(Serialize, Clone)]
+pub struct Estimate { value: f64, #[serde(skip_serializing_if = "Option::is_none")] standard_error: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] ci_low: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] ci_high: Option<f64> }
+
+#[derive(Serialize, Clone)]
+pub struct CurvePoint { concentration: f64, response: f64 }
+
+#[derive(Serialize)]
+pub struct DoseResponseFit {
+    fit_id: String, #[serde(skip_serializing_if = "Option::is_none")] compound: Option<String>, model: &'static str, concentration_unit: String, points: usize, points_used: usize,
+    potency_label: &'static str, potency: Estimate, log_potency: Estimate, p_potency: Estimate, top: Estimate, bottom: Estimate, hill_slope: Estimate,
+    confidence_level: f64, degrees_of_freedom: usize, r_squared: f64, residual_sd: f64, converged: bool, iterations: usize, curve: Vec<CurvePoint>, warnings: Vec<String>,
+}
+
+pub async fn fit(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<DoseResponseRequest>) -> Result<Json<DoseResponseFit>, ApiError> {
+    let meter = usage::Meter::start();
+    let resp = run(req).map_err(|e| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e })))?;
+    let subject = resp.compound.clone().unwrap_or_else(|| format!("{} points", resp.points));
+    record(&s, &headers, "dose_response", &subject, MODEL, &resp.fit_id, &meter, &resp);
+    Ok(Json(resp))
+}
+
+/// Response at log concentration `x` for parameters [bottom, top, log EC50, hill], with its
+/// gradient.
+fn logistic(x: f64, p: &[f64; 4]) -> (f64, [f64; 4]) {
+    let [bottom, top, c, hill] = *p;
+    let u = 10f64.powf(((c - x) * hill).clamp(-300.0, 300.0));
+    let d = 1.0 + u;
+    let k = (top - bottom) * u * std::f64::consts::LN_10 / (d * d);
+    (bottom + (top - bottom) / d, [1.0 - 1.0 / d, 1.0 / d, -k * hill, -k * (c - x)])
+}
+
+/// Solves `a · x = b` by Gaussian elimination with partial pivoting.
+fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-300 { return None; }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        for row in col + 1..n {
+            let f = a[row][col] / a[col][col];
+            let (upper, lower) = a.split_at_mut(row);
+            for (v, p) in lower[0][col..].iter_mut().zip(&upper[col][col..]) { *v -= f * p; }
+            b[row] -= f * b[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() { x[row] = (b[row] - (row + 1..n).map(|k| a[row][k] * x[k]).sum::<f64>()) / a[row][row]; }
+    Some(x)
+}
+
+fn ln_gamma(x: f64) -> f64 {
+    const C: [f64; 6] = [76.18009172947146, -86.50532032941677, 24.01409824083091, -1.231739572450155, 0.1208650973866179e-2, -0.5395239384953e-5];
+    let t = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let series = C.iter().enumerate().fold(1.000000000190015, |acc, (j, c)| acc + c / (x + 1.0 + j as f64));
+    -t + (2.5066282746310005 * series / x).ln()
+}
+
+/// Regularized incomplete beta function by its continued fraction.
+fn incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 { return 0.0; }
+    if x >= 1.0 { return 1.0; }
+    if x > (a + 1.0) / (a + b + 2.0) { return 1.0 - incomplete_beta(b, a, 1.0 - x); }
+    let front = (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp() / a;
+    let (mut c, mut d) = (1.0, 1.0 - (a + b) * x / (a + 1.0));
+    d = 1.0 / if d.abs() < 1e-300 { 1e-300 } else { d };
+    let mut h = d;
+    for m in 1..300 {
+        let m = m as f64;
+        for num in [m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m)), -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0))] {
+            d = 1.0 + num * d;
+            d = 1.0 / if d.abs() < 1e-300 { 1e-300 } else { d };
+            c = 1.0 + num / c;
+            if c.abs() < 1e-300 { c = 1e-300; }
+            h *= d * c;
+        }
+        if (d * c - 1.0).abs() < 1e-15 { break; }
+    }
+    front * h
+}
+
+/// The two-sided critical value of Student's t for `confidence` at `df` degrees of freedom.
+fn t_critical(confidence: f64, df: usize) -> f64 {
+    let df = df as f64;
+    // P(|T| > t) = I_{df/(df+t²)}(df/2, 1/2), falling in t.
+    let tail = |t: f64| incomplete_beta(df / 2.0, 0.5, df / (df + t * t));
+    let (mut lo, mut hi) = (0.0, 1e4);
+    for _ in 0..200 {
+        let mid = 0.5 * (lo + hi);
+        if tail(mid) > 1.0 - confidence { lo = mid; } else { hi = mid; }
+    }
+    0.5 * (lo + hi)
+}
+
+fn round(v: f64) -> f64 { if v == 0.0 || !v.is_finite() { return v; } let scale = 10f64.powi(5 - v.abs().log10().floor() as i32); (v * scale).round() / scale }
+
+pub fn run(req: DoseResponseRequest) -> Result<DoseResponseFit, String> {
+    if req.concentrations.len() != req.responses.len() { return Err(format!("{} concentrations but {} responses", req.concentrations.len(), req.responses.len())); }
+    let unit = req.concentration_unit.clone().unwrap_or_else(|| "nM".into());
+    let molar = UNITS.iter().find(|u| u.0 == unit).map(|u| u.1).ok_or_else(|| format!("unknown concentration_unit {unit}; expected one of M, mM, uM, nM, pM"))?;
+    let confidence = req.confidence_level.unwrap_or(0.95);
+    if !(confidence > 0.0 && confidence < 1.0) { return Err("confidence_level must be between 0 and 1".into()); }
+    if req.concentrations.iter().chain(&req.responses).chain(req.fix_top.iter()).chain(req.fix_bottom.iter()).any(|v| !v.is_finite()) { return Err("concentrations, responses and fixed values must be finite numbers".into()); }
+    if req.concentrations.iter().any(|&c| c < 0.0) { return Err("concentrations can't be negative".into()); }
+    let mut warnings = Vec::new();
+    let zeros = req.concentrations.iter().filter(|&&c| c == 0.0).count();
+    if zeros > 0 { warnings.push(format!("{zeros} {} at zero concentration left out of the log-scale fit", if zeros == 1 { "point was" } else { "points were" })); }
+    let data: Vec<(f64, f64)> = req.concentrations.iter().zip(&req.responses).filter(|p| *p.0 > 0.0).map(|(&c, &y)| (c.log10(), y)).collect();
+    let free: Vec<usize> = [(0, req.fix_bottom), (1, req.fix_top)].into_iter().filter(|p| p.1.is_none()).map(|p| p.0).chain([2, 3]).collect();
+    let mut levels: Vec<f64> = data.iter().map(|p| p.0).collect();
+    levels.sort_by(f64::total_cmp);
+    levels.dedup();
+    if levels.len() < 3 { return Err("need responses at three or more distinct non-zero concentrations".into()); }
+    if data.len() <= free.len() { return Err(format!("need more than {} points to fit {} free parameters", free.len(), free.len())); }
+
+    // Starting values: plateaus from the extremes, slope sign from the trend, midpoint where
+    // the responses cross halfway.
+    let n = data.len() as f64;
+    let (mx, my) = (data.iter().map(|p| p.0).sum::<f64>() / n, data.iter().map(|p| p.1).sum::<f64>() / n);
+    let trend: f64 = data.iter().map(|p| (p.0 - mx) * (p.1 - my)).sum();
+    let (ymin, ymax) = data.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), p| (lo.min(p.1), hi.max(p.1)));
+    let (bottom, top) = (req.fix_bottom.unwrap_or(ymin), req.fix_top.unwrap_or(ymax));
+    let half = 0.5 * (bottom + top);
+    let mid = data.iter().min_by(|a, b| (a.1 - half).abs().total_cmp(&(b.1 - half).abs())).map_or(mx, |p| p.0);
+    let mut p = [bottom, top, mid, if trend < 0.0 { -1.0 } else { 1.0 }];
+
+    let ssr = |p: &[f64; 4]| data.iter().map(|&(x, y)| (y - logistic(x, p).0).powi(2)).sum::<f64>();
+    let normal = |p: &[f64; 4]| {
+        let mut a = vec![vec![0.0; free.len()]; free.len()];
+        let mut g = vec![0.0; free.len()];
+        for &(x, y) in &data {
+            let (f, grad) = logistic(x, p);
+            for (i, &pi) in free.iter().enumerate() {
+                g[i] += grad[pi] * (y - f);
+                for (j, &pj) in free.iter().enumerate() { a[i][j] += grad[pi] * grad[pj]; }
+            }
+        }
+        (a, g)
+    };
+    let (mut current, mut lambda, mut converged, mut iterations) = (ssr(&p), 1e-3, false, 0);
+    while iterations < MAX_ITERATIONS && !converged {
+        iterations += 1;
+        let (a, g) = normal(&p);
+        let damped: Vec<Vec<f64>> = a.iter().enumerate().map(|(i, row)| row.iter().enumerate().map(|(j, &v)| if i == j { v * (1.0 + lambda) + 1e-12 } else { v }).collect()).collect();
+        let Some(step) = solve(damped, g) else { break };
+        let mut trial = p;
+        for (i, &pi) in free.iter().enumerate() { trial[pi] += step[i]; }
+        let next = ssr(&trial);
+        if next.is_finite() && next <= current {
+            converged = current - next <= 1e-12 * current.max(1e-300) && step.iter().all(|s| s.abs() < 1e-8);
+            p = trial;
+            current = next;
+            lambda = (lambda / 10.0).max(1e-12);
+        } else {
+            lambda *= 10.0;
+            if lambda > 1e12 { converged = true; }
+        }
+    }
+    if !converged { warnings.push(format!("the fit did not converge in {MAX_ITERATIONS} iterations")); }
+
+    let df = data.len() - free.len();
+    let s2 = current / df as f64;
+    let (a, _) = normal(&p);
+    let mut se = [None; 4];
+    let identity = |k: usize| (0..free.len()).map(|i| if i == k { 1.0 } else { 0.0 }).collect::<Vec<f64>>();
+    for (k, &pk) in free.iter().enumerate() {
+        se[pk] = solve(a.clone(), identity(k)).map(|col| (s2 * col[k]).max(0.0).sqrt());
+    }
+    if free.iter().any(|&k| se[k].is_none()) { warnings.push("the parameters are not all determined by the data; some intervals are missing".into()); }
+    let t = t_critical(confidence, df);
+    let estimate = |k: usize| Estimate { value: round(p[k]), standard_error: se[k].map(round), ci_low: se[k].map(|e| round(p[k] - t * e)), ci_high: se[k].map(|e| round(p[k] + t * e)) };
+    let log_ci = se[2].map(|e| (p[2] - t * e, p[2] + t * e));
+    let (lo, hi) = (levels[0], levels[levels.len() - 1]);
+    if p[2] < lo || p[2] > hi { warnings.push("the midpoint lies outside the tested concentrations and is extrapolated".into()); }
+    if log_ci.is_some_and(|(l, h)| h - l > WIDE_LOG_CI) { warnings.push(format!("the midpoint's interval spans more than {WIDE_LOG_CI} log units; it is poorly constrained")); }
+    let mean = data.iter().map(|p| p.1).sum::<f64>() / n;
+    let total: f64 = data.iter().map(|p| (p.1 - mean).powi(2)).sum();
+    let pc = |log: f64| round(-(log + molar.log10()));
+    Ok(DoseResponseFit {
+        fit_id: uuid::Uuid::new_v4().to_string(), compound: req.compound, model: MODEL, concentration_unit: unit, points: req.concentrations.len(), points_used: data.len(),
+        potency_label: if p[3] < 0.0 { "IC50" } else { "EC50" },
+        potency: Estimate { value: round(10f64.powf(p[2])), standard_error: None, ci_low: log_ci.map(|c| round(10f64.powf(c.0))), ci_high: log_ci.map(|c| round(10f64.powf(c.1))) },
+        log_potency: estimate(2),
+        p_potency: Estimate { value: pc(p[2]), standard_error: se[2].map(round), ci_low: log_ci.map(|c| pc(c.1)), ci_high: log_ci.map(|c| pc(c.0)) },
+        top: estimate(1), bottom: estimate(0), hill_slope: estimate(3),
+        confidence_level: confidence, degrees_of_freedom: df, r_squared: round(if total > 0.0 { 1.0 - current / total } else { 1.0 }), residual_sd: round(s2.sqrt()), converged, iterations,
+        curve: (0..CURVE_POINTS).map(|i| { let x = lo + (hi - lo) * i as f64 / (CURVE_POINTS - 1) as f64; CurvePoint { concentration: round(10f64.powf(x)), response: round(logistic(x, &p).0) } }).collect(),
+        warnings,
+    })
+}
@@ -0,0 +1,252 @@
+//! Druggability assessment of a new target, ahead of a screening campaign.
+//!
+//! The report weighs three kinds of evidence. Pockets: the best pocket's detected druggability,
+//! scaled down below the volume a drug-like ligand needs, and how apolar its lining is; the
+//! best is the one scoring highest once scaled. Conservation, given a multiple sequence
+//! alignment of homologs: how conserved the pocket's residues are against the chain, a
+//! conserved pocket being a functional site (and a selectivity question if the homologs are
+//! paralogs). Known ligands: approved or clinical drugs against the target from a bundled
+//! precedent table, the project's measured binders (Kd, Ki, IC50, EC50) and its screen hits,
+//! the latter counting for half as predictions. The score is the weighted mean of the
+//! components available, each in [0, 1], and every component comes with a sentence of rationale
+//! so the call can be argued in a portfolio review.
+//!
+//! `POST /druggability/triage` does the same for a batch of UniProt accessions: each entry's
+//! sequence is fetched (or taken from `sequences`), folded, its model's pockets detected and
+//! the model assessed, with ligands looked up under the entry's gene. The targets come back as
+//! a table ranked by score, with model-quality flags, and entries that fail are listed last.
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use bio_engine_core::conservation;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{fnv1a, pockets, predict::{self, PredictRequest}, projects, record, run_predict, usage, ApiError, AppState, ErrorResponse};
+
+const MODEL: &str = "druggability-evidence";
+/// Component weights: best pocket, lining hydrophobicity, pocket conservation, ligand precedent.
+const WEIGHTS: [f64; 4] = [0.45, 0.15, 0.1, 0.3];
+/// Pocket volume, Å³, below which a drug-sized ligand doesn't fit.
+const MIN_VOLUME: f64 = 300.0;
+/// Pockets at or above this druggability are counted as druggable.
+const DRUGGABLE_POCKET: f64 = 0.5;
+/// Score bands: at or above the first is druggable, at or above the second challenging.
+const DRUGGABLE: f64 = 0.65;
+const CHALLENGING: f64 = 0.45;
+/// Accessions a triage takes at once.
+const MAX_TARGETS: usize = 100;
+/// Chains shorter than this are peptides, not pocket-bearing targets.
+const MIN_LENGTH: usize = 30;
+/// Models below this `structure_confidence`, or with more of the chain disordered, are flagged.
+const LOW_CONFIDENCE: f64 = 0.8;
+const HIGH_DISORDER: f64 = 0.3;
+/// Lining residues counted as apolar.
+const APOLAR: &[&str] = &["ALA", "VAL", "LEU", "ILE", "MET", "PHE", "TRP", "PRO", "TYR"];
+/// Targets with approved or clinical small-molecule drugs, by gene name, with examples.
+const PRECEDENT: &[(&str, &[&str])] = &[
+    ("EGFR", &["gefitinib", "erlotinib", "osimertinib"]), ("ABL1", &["imatinib", "dasatinib", "nilotinib"]), ("BRAF", &["vemurafenib", "dabrafenib"]),
+    ("KRAS", &["sotorasib", "adagrasib"]), ("BTK", &["ibrutinib", "acalabrutinib"]), ("JAK2", &["ruxolitinib", "fedratinib"]), ("ERBB2", &["lapatinib", "tucatinib"]),
+    ("ALK", &["crizotinib", "alectinib"]), ("CDK4", &["palbociclib", "ribociclib"]), ("CDK6", &["palbociclib", "abemaciclib"]), ("PARP1", &["olaparib", "niraparib"]),
+    ("BCL2", &["venetoclax"]), ("HMGCR", &["atorvastatin", "simvastatin"]), ("ACE", &["captopril", "lisinopril"]), ("PTGS2", &["celecoxib"]), ("PTGS1", &["aspirin", "ibuprofen"]),
+    ("DPP4", &["sitagliptin"]), ("ESR1", &["tamoxifen", "fulvestrant"]), ("AR", &["enzalutamide", "bicalutamide"]), ("PDE5A", &["sildenafil", "tadalafil"]),
+    ("HSP90AA1", &["pimitespib"]), ("MTOR", &["everolimus"]), ("PIK3CA", &["alpelisib"]), ("MET", &["capmatinib", "tepotinib"]), ("KIT", &["imatinib", "ripretinib"]),
+];
+
+#[derive(Deserialize)]
+pub struct DruggabilityRequest { target_protein: String, sequence: Option<String>, msa: Option<String> }
+
+#[derive(Serialize)]
+pub struct PocketAssessment { pocket_id: String, volume_a3: f64, druggability: f64, apolar_fraction: f64, #[serde(skip_serializing_if = "Option::is_none")] conservation: Option<f64>, residues: Vec<String> }
+#[derive(Serialize)]
+pub struct KnownLigand { compound: String, source: &'static str, #[serde(skip_serializing_if = "Option::is_none")] kind: Option<String>, #[serde(skip_serializing_if = "Option::is_none")] potency_nm: Option<f64> }
+#[derive(Serialize)]
+pub struct Components { pocket: f64, hydrophobicity: f64, #[serde(skip_serializing_if = "Option::is_none")] conservation: Option<f64>, ligand_precedent: f64 }
+#[derive(Serialize)]
+pub struct DruggabilityResponse {
+    druggability_id: String, target: String, score: f64, category: &'static str, components: Components, best_pocket: String, druggable_pockets: usize, pockets: Vec<PocketAssessment>,
+    known_ligands: Vec<KnownLigand>, rationale: Vec<String>, #[serde(skip_serializing_if = "Vec::is_empty")] warnings: Vec<String>,
+}
+
+fn round(v: f64) -> f64 { (v * 1000.0).round() / 1000.0 }
+
+fn mean(v: &[f64]) -> Option<f64> { (!v.is_empty()).then(|| v.iter().sum::<f64>() / v.len() as f64) }
+
+/// Potency as a [0, 1] precedent: nothing at 10 µM, full at 1 nM.
+fn potency_term(nm: f64) -> f64 { ((9.0 - nm.log10() - 5.0) / 4.0).clamp(0.0, 1.0) }
+
+/// Sequence position (0-based) of a residue label such as `LEU123`, when it fits the sequence.
+fn position(residue: &str, len: usize) -> Option<usize> { residue.get(3..)?.parse::<usize>().ok().filter(|&n| n >= 1 && n <= len).map(|n| n - 1) }
+
+pub async fn assess(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<DruggabilityRequest>) -> Result<Json<DruggabilityResponse>, ApiError> {
+    let meter = usage::Meter::start();
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let target = req.target_protein.trim().to_string();
+    if target.is_empty() { return Err(bad("target_protein must not be empty".into())); }
+    let report = match (&req.msa, &req.sequence) {
+        (Some(msa), Some(sequence)) => Some(conservation::analyze(&conservation::parse(msa, &sequence.trim().to_uppercase()).map_err(bad)?)),
+        (Some(_), None) => return Err(bad("msa needs the target's sequence".into())),
+        _ => None,
+    };
+    let resp = evaluate(&s, &projects::project_id(&headers), target, report.as_ref()).map_err(bad)?;
+    record(&s, &headers, "druggability", &resp.target, MODEL, &resp.druggability_id, &meter, &resp);
+    Ok(Json(resp))
+}
+
+/// The assessment of `target` from its pockets, their conservation in `report` if given, and
+/// the ligands known for it (or for its gene, when it is a `<gene>-model-<hash>` model).
+fn evaluate(s: &AppState, project: &str, target: String, report: Option<&conservation::ConservationReport>) -> Result<DruggabilityResponse, String> {
+    let mut warnings = Vec::new();
+    let mut rationale = Vec::new();
+
+    // Pockets, each with its lining's apolar fraction and conservation.
+    let found = pockets::detect(&target);
+    let pockets: Vec<PocketAssessment> = found.iter().map(|p| {
+        let apolar = p.residues.iter().filter(|r| r.get(..3).is_some_and(|n| APOLAR.contains(&n))).count() as f64 / p.residues.len().max(1) as f64;
+        let conservation = report.and_then(|c| mean(&p.residues.iter().filter_map(|r| position(r, c.scores.len())).map(|i| c.scores[i]).collect::<Vec<_>>()));
+        PocketAssessment { pocket_id: p.pocket_id.clone(), volume_a3: p.volume_a3, druggability: round(p.druggability), apolar_fraction: round(apolar), conservation: conservation.map(round), residues: p.residues.clone() }
+    }).collect();
+    let scaled = |p: &PocketAssessment| p.druggability * (p.volume_a3 / MIN_VOLUME).min(1.0);
+    let best = pockets.iter().max_by(|a, b| scaled(a).total_cmp(&scaled(b))).ok_or_else(|| format!("no pocket found on {target}"))?;
+    let druggable_pockets = pockets.iter().filter(|p| p.druggability >= DRUGGABLE_POCKET).count();
+    let pocket = scaled(best);
+    let best_pocket = best.pocket_id.clone();
+    rationale.push(format!(
+        "{} of {} pockets reach druggability {DRUGGABLE_POCKET}; the best, {}, scores {:.2} at {:.0} Å³{}",
+        druggable_pockets, pockets.len(), best.pocket_id, best.druggability, best.volume_a3, if best.volume_a3 < MIN_VOLUME { ", small for a drug-sized ligand" } else { "" },
+    ));
+    let hydrophobicity = best.apolar_fraction;
+    rationale.push(format!("{:.0}% of {}'s lining is apolar{}", hydrophobicity * 100.0, best.pocket_id, if hydrophobicity < 0.4 { ", a polar pocket that favours charged, less permeable ligands" } else { "" }));
+
+    // Conservation of the best pocket against the chain.
+    let conservation = match (report, best.conservation) {
+        (Some(c), Some(pc)) => {
+            rationale.push(format!(
+                "{}'s residues have conservation {pc:.2} against {:.2} for the chain ({} sequences){}", best.pocket_id, c.mean_conservation, c.sequences,
+                if pc > c.mean_conservation + 0.1 { ": a conserved functional site, so check selectivity if the homologs include paralogs" } else { "" },
+            ));
+            Some((0.5 + pc - c.mean_conservation).clamp(0.0, 1.0))
+        }
+        (Some(_), None) => { warnings.push(format!("{}'s residues aren't numbered within the sequence; conservation left out", best.pocket_id)); None }
+        _ => None,
+    };
+
+    // Known ligands: clinical precedent, measured binders, then screen hits.
+    let gene = target.split("-model-").next().unwrap_or(&target).to_uppercase();
+    let mut known_ligands: Vec<KnownLigand> = PRECEDENT.iter().filter(|(g, _)| *g == gene).flat_map(|(_, drugs)| drugs.iter().map(|d| KnownLigand { compound: d.to_string(), source: "clinical precedent", kind: None, potency_nm: None })).collect();
+    let mut measured: Vec<KnownLigand> = s.measurements.for_project(project).into_iter()
+        .filter(|m| m.kind != "tm" && (m.target.eq_ignore_ascii_case(&target) || m.target.eq_ignore_ascii_case(&gene)))
+        .map(|m| KnownLigand { compound: m.compound.clone().unwrap_or_default(), source: "measurement", kind: Some(m.kind.clone()), potency_nm: Some(m.standard_value) }).collect();
+    measured.sort_by(|a, b| a.potency_nm.unwrap_or(f64::INFINITY).total_cmp(&b.potency_nm.unwrap_or(f64::INFINITY)));
+    let mut screened: Vec<KnownLigand> = s.poses.of_project(project).into_iter().flat_map(|(_, poses)| poses).filter(|p| p.target.eq_ignore_ascii_case(&target))
+        .map(|p| KnownLigand { compound: p.compound_id, source: "screen hit", kind: None, potency_nm: Some(round(p.binding_affinity_nm)) }).collect();
+    screened.sort_by(|a, b| a.potency_nm.unwrap_or(f64::INFINITY).total_cmp(&b.potency_nm.unwrap_or(f64::INFINITY)));
+    screened.dedup_by(|a, b| a.compound == b.compound);
+    let ligand_precedent = if !known_ligands.is_empty() {
+        rationale.push(format!("{gene} is clinically drugged ({})", known_ligands.iter().map(|l| l.compound.as_str()).collect::<Vec<_>>().join(", ")));
+        1.0
+    } else if let Some(nm) = measured.first().and_then(|l| l.potency_nm) {
+        rationale.push(format!("{} measured binder(s), the most potent at {nm:.3} nM", measured.len()));
+        potency_term(nm)
+    } else if let Some(nm) = screened.first().and_then(|l| l.potency_nm) {
+        rationale.push(format!("no measured binders; {} screen hit(s) predicted, the best at {nm:.1} nM", screened.len()));
+        0.5 * potency_term(nm)
+    } else {
+        rationale.push("no known ligands: no clinical precedent, measurements or screen hits for this target".into());
+        0.0
+    };
+    known_ligands.extend(measured);
+    known_ligands.extend(screened);
+
+    let parts = [Some(pocket), Some(hydrophobicity), conservation, Some(ligand_precedent)];
+    let (sum, weight) = parts.iter().zip(WEIGHTS).filter_map(|(p, w)| p.map(|v| (v * w, w))).fold((0.0, 0.0), |acc, (v, w)| (acc.0 + v, acc.1 + w));
+    let score = round(sum / weight);
+    let category = if score >= DRUGGABLE { "druggable" } else if score >= CHALLENGING { "challenging" } else { "difficult" };
+    Ok(DruggabilityResponse {
+        druggability_id: uuid::Uuid::new_v4().to_string(), target, score, category,
+        components: Components { pocket: round(pocket), hydrophobicity: round(hydrophobicity), conservation: conservation.map(round), ligand_precedent: round(ligand_precedent) },
+        best_pocket, druggable_pockets, pockets, known_ligands, rationale, warnings,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct TriageRequest { accessions: Vec<String>, #[serde(default)] sequences: HashMap<String, String> }
+
+#[derive(Serialize)]
+pub struct Triaged {
+    target: String, score: f64, category: &'static str, structure_confidence: f64, disordered_fraction: f64, best_pocket: PocketAssessment, druggable_pockets: usize,
+    components: Components, known_ligands: usize, rationale: Vec<String>, #[serde(skip_serializing_if = "Vec::is_empty")] flags: Vec<String>,
+}
+#[derive(Serialize)]
+pub struct TriageRow {
+    #[serde(skip_serializing_if = "Option::is_none")] rank: Option<usize>, accession: String, #[serde(skip_serializing_if = "Option::is_none")] gene: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")] protein_name: Option<String>, #[serde(skip_serializing_if = "Option::is_none")] length: Option<usize>,
+    #[serde(flatten)] result: Option<Triaged>, #[serde(skip_serializing_if = "Option::is_none")] error: Option<String>,
+}
+#[derive(Serialize)]
+pub struct TriageResponse { triage_id: String, targets: usize, druggable: usize, challenging: usize, difficult: usize, failed: usize, rows: Vec<TriageRow> }
+
+/// Whether `s` has the shape of a UniProt accession: six or ten upper-case letters and digits,
+/// a letter then a digit first.
+fn looks_like_accession(s: &str) -> bool {
+    let b = s.as_bytes();
+    (b.len() == 6 || b.len() == 10) && b[0].is_ascii_uppercase() && b[1].is_ascii_digit() && b.iter().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+}
+
+pub async fn triage(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<TriageRequest>) -> Result<Json<TriageResponse>, ApiError> {
+    let meter = usage::Meter::start();
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let mut accessions: Vec<String> = Vec::new();
+    for a in &req.accessions {
+        let a = a.trim().to_uppercase();
+        if !looks_like_accession(&a) { return Err(bad(format!("{a} is not a UniProt accession"))); }
+        if !accessions.contains(&a) { accessions.push(a); }
+    }
+    if accessions.is_empty() || accessions.len() > MAX_TARGETS { return Err(bad(format!("accessions must have between 1 and {MAX_TARGETS} entries"))); }
+    let given: HashMap<String, String> = req.sequences.into_iter().map(|(a, seq)| (a.trim().to_uppercase(), seq)).collect();
+
+    // Entries are fetched concurrently; given sequences skip the fetch but still take the names.
+    let mut fetches = tokio::task::JoinSet::new();
+    for (i, a) in accessions.iter().enumerate() {
+        let (s, a) = (s.clone(), a.clone());
+        fetches.spawn(async move { (i, s.uniprot.summary(&a).await) });
+    }
+    let mut entries = vec![None; accessions.len()];
+    while let Some(Ok((i, entry))) = fetches.join_next().await { entries[i] = entry; }
+
+    let project = projects::project_id(&headers);
+    let mut rows: Vec<TriageRow> = accessions.into_iter().zip(entries).map(|(accession, entry)| {
+        let (gene, protein_name) = entry.as_ref().map_or((None, None), |e| (e.gene.clone(), e.name.clone()));
+        let mut row = TriageRow { rank: None, accession, gene, protein_name, length: None, result: None, error: None };
+        let Some(sequence) = given.get(&row.accession).cloned().or_else(|| entry.map(|e| e.sequence)) else {
+            row.error = Some("entry not found or unreachable; give its sequence in sequences".into());
+            return row;
+        };
+        let fold = PredictRequest { sequence, prediction_type: Some("structure".into()), numbering: None, glycans: None, uniprot_accession: Some(row.accession.clone()), msa: None, sequence_type: None, min_orf_length: None, template: None, template_id: None, template_chain: None };
+        let (fold, orfs) = match predict::prepare(fold) { Ok(f) => f, Err(e) => { row.error = Some(e); return row; } };
+        row.length = Some(fold.sequence.len());
+        if fold.sequence.len() < MIN_LENGTH { row.error = Some(format!("{} residues is too short to carry a pocket", fold.sequence.len())); return row; }
+        let target = format!("{}-model-{:08x}", row.gene.as_deref().unwrap_or(&row.accession), fnv1a(fold.sequence.as_bytes()) as u32);
+        let structure = run_predict(&s, fold, orfs, None);
+        let assessed = match evaluate(&s, &project, target, None) { Ok(a) => a, Err(e) => { row.error = Some(e); return row; } };
+        let mut flags = Vec::new();
+        if structure.structure_confidence < LOW_CONFIDENCE { flags.push(format!("model confidence {:.2} is below {LOW_CONFIDENCE}", structure.structure_confidence)); }
+        if structure.disorder.disordered_fraction > HIGH_DISORDER { flags.push(format!("{:.0}% of the chain is predicted disordered", structure.disorder.disordered_fraction * 100.0)); }
+        let Some(best_pocket) = assessed.pockets.into_iter().find(|p| p.pocket_id == assessed.best_pocket) else { return row };
+        row.result = Some(Triaged {
+            target: assessed.target, score: assessed.score, category: assessed.category, structure_confidence: round(structure.structure_confidence),
+            disordered_fraction: round(structure.disorder.disordered_fraction), best_pocket, druggable_pockets: assessed.druggable_pockets, components: assessed.components,
+            known_ligands: assessed.known_ligands.len(), rationale: assessed.rationale, flags,
+        });
+        row
+    }).collect();
+    rows.sort_by(|a, b| b.result.as_ref().map_or(f64::NEG_INFINITY, |r| r.score).total_cmp(&a.result.as_ref().map_or(f64::NEG_INFINITY, |r| r.score)));
+    for (i, row) in rows.iter_mut().enumerate().filter(|(_, r)| r.result.is_some()) { row.rank = Some(i + 1); }
+    let count = |category: &str| rows.iter().filter(|r| r.result.as_ref().is_some_and(|t| t.category == category)).count();
+    let resp = TriageResponse {
+        triage_id: uuid::Uuid::new_v4().to_string(), targets: rows.len(), druggable: count("druggable"), challenging: count("challenging"), difficult: count("difficult"),
+        failed: rows.iter().filter(|r| r.error.is_some()).count(), rows,
+    };
+    let subject = format!("{} target(s)", resp.targets);
+    record(&s, &headers, "druggability_triage", &subject, MODEL, &resp.triage_id, &meter, &resp);
+    Ok(Json(resp))
+}
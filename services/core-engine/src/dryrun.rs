@@ -0,0 +1,126 @@
+//! Validation-only runs of expensive requests.
+//!
+//! `/simulate` and `/screen` take `validate_only: true`. The request is then parsed and checked as
+//! for a real run (the molecule resolved; the protocol, restraints, observables, temperature
+//! schedule and a custom force field's coverage, or the screen's filters and charge model,
+//! checked), its runtime and cost estimated, and anything suspect reported as a warning: an
+//! unresolved molecule, atoms short of hydrogens, residues that match no standard amino acid, atoms
+//! without force-field parameters, a project at its storage quota. Nothing is computed or recorded,
+//! and the request bypasses admission control and the compute threads, so it answers at once
+//! however busy the engine is.
+//!
+//! The estimate is the one `POST /api/v1/bio/estimate` gives for the run's size (see
+//! `estimate`): the system's atoms times its steps, or the compounds screened.
+
+use axum::{body::Body, extract::Request, http::{HeaderMap, Method, StatusCode}, middleware::Next, response::{Json, Response}};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{chem, cofactors, composition, estimate::{self, Estimate}, forcefields, library, observables, projects, protocols::Protocol, resolver::Resolved, restraints, schedule, selection, ApiError, AppState, ErrorResponse, ScreenRequest, SimulateRequest};
+
+/// Routes that take `validate_only`.
+const ROUTES: &[&str] = &["/api/v1/bio/simulate", "/api/v1/bio/screen"];
+/// The largest body looked into, as axum's default `Json` limit.
+const MAX_BODY: usize = 2 * 1024 * 1024;
+/// Atoms listed by name in a warning; the rest are only counted.
+const MAX_LISTED: usize = 5;
+/// Dynamics steps above which a run is flagged as long.
+const LONG_RUN_STEPS: u64 = 10_000_000;
+
+/// Marks a request as validation-only, so it isn't counted or admitted as a job.
+#[derive(Clone, Copy)]
+pub struct DryRun;
+
+pub async fn mark(req: Request, next: Next) -> Result<Response, ApiError> {
+    if req.method() != Method::POST || !ROUTES.contains(&req.uri().path()) { return Ok(next.run(req).await); }
+    let (mut parts, body) = req.into_parts();
+    let bytes = axum::body::to_bytes(body, MAX_BODY).await.map_err(|e| (StatusCode::PAYLOAD_TOO_LARGE, Json(ErrorResponse { error: format!("request body unavailable: {e}") })))?;
+    if serde_json::from_slice::<Value>(&bytes).ok().and_then(|v| v.get("validate_only")?.as_bool()) == Some(true) { parts.extensions.insert(DryRun); }
+    Ok(next.run(Request::from_parts(parts, Body::from(bytes))).await)
+}
+
+#[derive(Serialize)]
+pub struct DryRunReport {
+    validate_only: bool, kind: &'static str, valid: bool, #[serde(skip_serializing_if = "Vec::is_empty")] errors: Vec<String>, warnings: Vec<String>, plan: Value,
+    #[serde(skip_serializing_if = "Option::is_none")] system: Option<composition::SystemReport>, estimate: Estimate,
+}
+
+/// Names of up to `MAX_LISTED` of `items`, and how many more there are.
+fn listed(items: &[String]) -> String {
+    let more = items.len().saturating_sub(MAX_LISTED);
+    let names = items[..items.len().min(MAX_LISTED)].join(", ");
+    if more > 0 { format!("{names} and {more} more") } else { names }
+}
+
+/// What looks wrong with the structure of `mol`.
+fn structure_warnings(mol: &Resolved, warnings: &mut Vec<String>) {
+    let Some(m) = mol.canonical_smiles.as_deref().and_then(|smiles| chem::parse_smiles(smiles).ok()) else {
+        warnings.push(format!("{} did not resolve to a structure ({}); it will be simulated as an opaque identifier, without composition, restraints or observables", mol.input, mol.source));
+        return;
+    };
+    let open: Vec<String> = m.atoms.iter().enumerate().filter(|(i, a)| {
+        let valences = chem::default_valences(&a.element);
+        a.charge == 0 && !valences.is_empty() && m.bond_valence(*i) + a.hydrogens < valences[0]
+    }).map(|(i, a)| format!("{}{}", a.element, i + 1)).collect();
+    if !open.is_empty() { warnings.push(format!("{} atom(s) have fewer bonds and hydrogens than their valence ({}); hydrogens may be missing", open.len(), listed(&open))); }
+    let unusual: Vec<String> = selection::residues(&m).iter().enumerate().filter(|(_, r)| r.name == "UNK").map(|(i, _)| format!("residue {}", i + 1)).collect();
+    if !unusual.is_empty() { warnings.push(format!("{} residue(s) match no standard amino acid ({})", unusual.len(), listed(&unusual))); }
+    let unknown = cofactors::unparameterized_atoms(&m);
+    if !unknown.is_empty() { warnings.push(format!("{} atom(s) have no force-field parameters ({})", unknown.len(), listed(&unknown))); }
+}
+
+fn quota_warning(s: &AppState, headers: &HeaderMap, warnings: &mut Vec<String>) {
+    if let Some(e) = s.retention.over_quota(s, &projects::project_id(headers)) { warnings.push(format!("the run would be refused: {e}")); }
+}
+
+#[derive(Serialize)]
+struct SimulatePlan { #[serde(flatten)] settings: crate::SimSettings, steps: u64, #[serde(skip_serializing_if = "Option::is_none")] protocol: Option<String>, stages: usize }
+
+pub fn simulate(s: &AppState, headers: &HeaderMap, req: &SimulateRequest, proto: &Protocol, mol: &Resolved) -> DryRunReport {
+    let (mut errors, mut warnings) = (Vec::new(), Vec::new());
+    let settings = crate::sim_settings(req, proto);
+    // As the run counts them: a staged protocol's dynamics stages, `steps` replacing the last one's.
+    let steps = if proto.stages.is_empty() {
+        req.steps.or(proto.steps).or(req.temperature_schedule.as_ref().and_then(|p| p.last()).map(|p| p.step)).unwrap_or(10_000)
+    } else {
+        let dynamics: Vec<u64> = proto.stages.iter().filter(|st| st.kind != "minimize").map(|st| st.steps).collect();
+        let last = dynamics.last().copied();
+        dynamics.iter().sum::<u64>() - last.unwrap_or(0) + last.map_or(0, |l| req.steps.unwrap_or(l))
+    };
+    if let Some(points) = &req.temperature_schedule { if let Err(e) = schedule::Schedule::parse(points) { errors.push(e); } }
+    let restraints = restraints::Restraints::parse(req.restraints.as_deref().unwrap_or(&proto.restraints), mol).unwrap_or_else(|e| { errors.push(e); restraints::Restraints::default() });
+    if let Err(e) = observables::Recorder::new(req.observables.as_deref().unwrap_or(&proto.observables), mol, &restraints) { errors.push(e); }
+    if let Err(e) = forcefields::check(s, &projects::project_id(headers), &settings.force_field, mol) { errors.push(e); }
+    structure_warnings(mol, &mut warnings);
+    if let Err(e) = crate::check_temperature(settings.temperature_k) { errors.push(e); } else if !(250.0..=450.0).contains(&settings.temperature_k) { warnings.push(format!("temperature_k {} is far from physiological conditions", settings.temperature_k)); }
+    if steps == 0 { warnings.push("the run has no dynamics steps".into()); }
+    if steps > LONG_RUN_STEPS { warnings.push(format!("{steps} steps is a long run; consider a shorter one first")); }
+    if req.qm_region.is_some() { warnings.push("qm_region adds a QM calculation before the run".into()); }
+    quota_warning(s, headers, &mut warnings);
+    let plan = SimulatePlan { settings, steps, protocol: req.protocol.clone(), stages: proto.stages.len() };
+    let system = composition::report(mol);
+    let estimate = estimate::of(s, "simulate", estimate::atoms(system.as_ref()) * steps as f64);
+    DryRunReport { validate_only: true, kind: "simulate", valid: errors.is_empty(), errors, warnings, plan: serde_json::to_value(plan).unwrap_or_default(), system, estimate }
+}
+
+pub fn screen(s: &AppState, headers: &HeaderMap, req: &ScreenRequest) -> DryRunReport {
+    let (mut errors, mut warnings) = (Vec::new(), Vec::new());
+    if let Err(e) = crate::check_screen(req) { errors.push(e); }
+    if let Err(e) = s.plugins.select(req.plugins.as_deref(), req.rank_by.as_deref()) { errors.push(e); }
+    let charge_model = crate::charges::ChargeModel::parse(req.charge_model.as_deref(), "").map(|m| m.name()).unwrap_or_else(|e| { errors.push(e); "" });
+    let fragments = crate::fragment_screen(req).unwrap_or(false);
+    let library_size = req.library_size.unwrap_or(if fragments { 1_000 } else { 10_000 });
+    let threshold = req.binding_threshold.unwrap_or(if fragments { 1e6 } else { 100.0 });
+    if req.target_protein.trim().is_empty() { errors.push("target_protein must not be empty".into()); }
+    if library_size == 0 { warnings.push("library_size is 0; nothing will be screened".into()); }
+    let (available, what) = if fragments { (library::FRAGMENT_LIBRARY_SIZE, "fragment") } else { (library::LIBRARY_SIZE, "compound") };
+    if u64::from(library_size) > available { warnings.push(format!("library_size {library_size} is larger than the {available}-{what} library; {what}s will repeat")); }
+    if threshold <= 0.0 { warnings.push(format!("binding_threshold {threshold} nM lets no compound through")); }
+    quota_warning(s, headers, &mut warnings);
+    let plan = serde_json::json!({
+        "target_protein": req.target_protein, "mode": if fragments { "fragment" } else { "compound" }, "library_size": library_size, "binding_threshold_nm": threshold, "charge_model": charge_model,
+        "anti_targets": req.anti_targets.as_ref().map_or(0, Vec::len), "filtered": req.filters.as_ref().is_some_and(|f| !f.is_empty()), "diverse_top_n": req.diverse_top_n,
+        "plugins": req.plugins, "rank_by": req.rank_by,
+    });
+    DryRunReport { validate_only: true, kind: "screen", valid: errors.is_empty(), errors, warnings, plan, system: None, estimate: estimate::of(s, "screen", f64::from(library_size)) }
+}
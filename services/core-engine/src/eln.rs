@@ -0,0 +1,133 @@
+//! Job export for electronic lab notebooks.
+//!
+//! `GET /export/eln/:job_id` gives one job as a structured entry that an ELN integration can
+//! file next to the experiments it supports. The layout follows the Allotrope idea of
+//! separating method, samples and measured values. `method` has the technique, engine and
+//! model versions, force field and the request's parameters as the job ran them. `samples`
+//! has the inputs by role, with their SHA-256. `measurements` has every scalar result, with its
+//! unit where the field name gives one (`_kcal_mol`, `_nm`, `_k` ...). `tables` has every
+//! list of records, and `provenance` has the signed record. Links to the job and its HTML
+//! report (see `reports`) are under `BIO_PUBLIC_URL`, so the entry can point back to them.
+
+use axum::{extract::{Path, State}, http::{header, HeaderMap}, response::{IntoResponse, Response}};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::{jobs::Job, projects, provenance::Provenance, reports, ApiError, AppState};
+
+const SCHEMA: &str = "alice-bio.eln-entry/1";
+/// Units named by a field's suffix. `restraint_k` is a force constant, not a temperature.
+const UNITS: &[(&str, &str)] = &[
+    ("restraint_k", "kcal/mol/Å²"), ("_kcal_mol", "kcal/mol"), ("_kcal", "kcal/mol"), ("_nm", "nM"), ("_angstrom", "Å"), ("_k", "K"), ("_c", "°C"),
+    ("_seconds", "s"), ("_us", "µs"), ("_da", "Da"), ("_bar", "bar"), ("_pct", "%"), ("_ppm", "ppm"), ("_bytes", "B"),
+];
+
+#[derive(Serialize)]
+pub struct Entry { schema: &'static str, entry: About, method: Method, samples: Vec<Sample>, measurements: Vec<Measurement>, tables: Vec<Table>, links: Links, #[serde(skip_serializing_if = "Option::is_none")] provenance: Option<Provenance> }
+#[derive(Serialize)]
+struct About { title: String, job_id: String, project: String, status: String, created_at: String, tags: Vec<String>, #[serde(skip_serializing_if = "Vec::is_empty")] depends_on: Vec<String> }
+#[derive(Serialize)]
+struct Method { technique: &'static str, kind: String, software: Software, model_version: String, #[serde(skip_serializing_if = "Option::is_none")] force_field: Option<String>, parameters: Value, #[serde(skip_serializing_if = "Option::is_none")] parameters_sha256: Option<String>, wall_seconds: f64, cpu_seconds: f64 }
+#[derive(Serialize)]
+struct Software { name: &'static str, version: &'static str }
+#[derive(Serialize)]
+struct Sample { role: String, #[serde(skip_serializing_if = "Option::is_none")] description: Option<String>, #[serde(skip_serializing_if = "Option::is_none")] sha256: Option<String> }
+#[derive(Serialize)]
+struct Measurement { name: String, value: Value, #[serde(skip_serializing_if = "Option::is_none")] unit: Option<&'static str> }
+#[derive(Serialize)]
+struct Table { name: String, columns: Vec<Column>, rows: Vec<Vec<Value>> }
+#[derive(Serialize)]
+struct Column { name: String, #[serde(skip_serializing_if = "Option::is_none")] unit: Option<&'static str> }
+#[derive(Serialize)]
+struct Links { job: String, report: String }
+
+fn technique(kind: &str) -> &'static str {
+    match kind {
+        "simulate" | "sweep" => "molecular dynamics simulation",
+        "screen" => "structure-based virtual screening",
+        "peptide_design" => "peptide library design",
+        "refine_pose" => "docked pose refinement",
+        "ternary" => "ternary complex modelling",
+        "fragment_grow" => "fragment growing",
+        "bioisosteres" => "bioisostere replacement",
+        "sar" => "R-group decomposition",
+        "retrosynthesis" => "retrosynthetic analysis",
+        "novelty" => "novelty check",
+        "druggability" => "target druggability assessment",
+        "druggability_triage" => "druggable-genome target triage",
+        "allosteric" => "allosteric site discovery",
+        "mixed_solvent" => "mixed-solvent probe mapping",
+        "predict" | "model_loops" => "protein structure modelling",
+        "superpose" => "structural superposition",
+        "structure_clustering" => "structure-based clustering",
+        "energy" => "force-field energy evaluation",
+        "torsion_scan" => "torsion scan",
+        "epitope" => "epitope prediction",
+        "stability" => "protein stability prediction",
+        "dose_response" => "dose-response curve fitting",
+        "qm" => "quantum-chemical calculation",
+        "script" => "scripted analysis",
+        _ => "computational analysis",
+    }
+}
+
+fn unit(field: &str) -> Option<&'static str> { UNITS.iter().find(|(suffix, _)| field == *suffix || field.ends_with(suffix)).map(|(_, u)| *u) }
+
+fn scalar(v: &Value) -> bool { matches!(v, Value::Number(_) | Value::String(_) | Value::Bool(_)) }
+
+/// An array of objects as columns (the union of their scalar fields) and rows.
+fn table(name: &str, rows: &[Value]) -> Option<Table> {
+    let mut columns: Vec<String> = Vec::new();
+    for row in rows {
+        for (k, v) in row.as_object()? { if scalar(v) && !columns.contains(k) { columns.push(k.clone()); } }
+    }
+    if columns.is_empty() { return None; }
+    let rows = rows.iter().map(|r| columns.iter().map(|c| r.get(c).filter(|v| scalar(v)).cloned().unwrap_or(Value::Null)).collect()).collect();
+    Some(Table { name: name.into(), columns: columns.into_iter().map(|c| Column { unit: unit(&c), name: c }).collect(), rows })
+}
+
+fn entry(job: &Job, link: impl Fn(&str) -> String) -> Entry {
+    let mut measurements = Vec::new();
+    let mut tables = Vec::new();
+    if let Value::Object(fields) = &job.result {
+        for (key, value) in fields {
+            match value {
+                v if scalar(v) => measurements.push(Measurement { name: key.clone(), value: v.clone(), unit: unit(key) }),
+                Value::Object(inner) => measurements.extend(inner.iter().filter(|(_, v)| scalar(v)).map(|(k, v)| Measurement { name: format!("{key}.{k}"), value: v.clone(), unit: unit(k) })),
+                Value::Array(rows) => tables.extend(table(key, rows)),
+                _ => {}
+            }
+        }
+    }
+    let statement = job.provenance.as_ref().map(|p| &p.statement);
+    let hashes: BTreeMap<String, String> = statement.map(|s| s.inputs.clone()).unwrap_or_default();
+    let mut samples: Vec<Sample> = hashes.into_iter().map(|(role, sha256)| Sample { role, description: None, sha256: Some(sha256) }).collect();
+    // The subject names the input when there is one; with none recorded, it stands in for it.
+    match samples.as_mut_slice() {
+        [only] => only.description = Some(job.subject.clone()),
+        [] if !job.subject.is_empty() => samples.push(Sample { role: "subject".into(), description: Some(job.subject.clone()), sha256: None }),
+        _ => {}
+    }
+    Entry {
+        schema: SCHEMA,
+        entry: About { title: format!("{} of {}", technique(&job.kind), job.subject), job_id: job.job_id.clone(), project: job.project.clone(), status: job.status.clone(), created_at: reports::timestamp(job.created_at_unix), tags: vec![job.kind.clone(), job.model.clone()], depends_on: job.depends_on.clone() },
+        method: Method {
+            technique: technique(&job.kind), kind: job.kind.clone(), software: Software { name: "ALICE Bio engine", version: env!("CARGO_PKG_VERSION") }, model_version: job.model.clone(),
+            force_field: statement.and_then(|s| s.force_field.clone()), parameters: job.parameters.clone(), parameters_sha256: statement.and_then(|s| s.parameters_sha256.clone()),
+            wall_seconds: job.resources.wall_seconds, cpu_seconds: job.resources.cpu_seconds,
+        },
+        samples, measurements, tables,
+        links: Links { job: link(&format!("/api/v1/bio/jobs/{}", job.job_id)), report: link(&format!("/api/v1/bio/reports/{}", job.job_id)) },
+        provenance: job.provenance.clone(),
+    }
+}
+
+pub async fn export(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(id): Path<String>) -> Result<Response, ApiError> {
+    let job = s.jobs.get(&projects::project_id(&headers), &id).ok_or_else(|| crate::not_found("job", &id))?;
+    if job.archived { return Err(projects::conflict(format!("job {id} is archived; restore its project to export it"))); }
+    let body = serde_json::to_string_pretty(&entry(&job, |path| s.notifications.link(path))).unwrap_or_default();
+    let disposition = format!("attachment; filename=\"{}-{}.eln.json\"", job.kind, job.job_id.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-', "_"));
+    Ok(([(header::CONTENT_TYPE, "application/json".to_string()), (header::CONTENT_DISPOSITION, disposition)], body).into_response())
+}
@@ -0,0 +1,185 @@
+//! B-cell epitope and antibody paratope prediction.
+//!
+//! Linear epitopes come from sequence propensities averaged over a 7-residue window: Parker
+//! hydrophilicity, Emini surface accessibility and Kolaskar-Tongaonkar antigenicity. With an
+//! antigen structure, conformational epitopes add solvent exposure from the Cα contact number
+//! and smooth the propensity over each residue's 10 Å neighbourhood, as DiscoTope does;
+//! residues above threshold are clustered in space into patches. Paratopes are the CDR
+//! residues of the antibody, weighted by loop (CDR-H3 most) and by residue paratope
+//! propensity, with Tyr and Trp highest.
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{antibody, record, usage, vec3::dist, ApiError, AppState, ErrorResponse, FOLD_MODEL};
+
+const WINDOW: usize = 7;
+const MIN_LINEAR_LENGTH: usize = 6;
+const MIN_PATCH_SIZE: usize = 5;
+const NEIGHBOUR_RADIUS: f64 = 10.0;
+const PATCH_LINK: f64 = 8.0;
+const DEFAULT_THRESHOLD: f64 = 0.6;
+const PARATOPE_THRESHOLD: f64 = 0.6;
+
+const AMINO: &[u8] = b"ARNDCQEGHILKMFPSTWYV";
+const THREE_LETTER: [&str; 20] = ["ALA", "ARG", "ASN", "ASP", "CYS", "GLN", "GLU", "GLY", "HIS", "ILE", "LEU", "LYS", "MET", "PHE", "PRO", "SER", "THR", "TRP", "TYR", "VAL"];
+const PARKER: [f64; 20] = [2.1, 4.2, 7.0, 10.0, 1.4, 6.0, 7.8, 5.7, 2.1, -8.0, -9.2, 5.7, -4.2, -9.2, 2.1, 6.5, 5.2, -10.0, -1.9, -3.7];
+const EMINI: [f64; 20] = [0.49, 0.95, 0.81, 0.81, 0.26, 0.84, 0.84, 0.48, 0.66, 0.34, 0.40, 0.97, 0.48, 0.42, 0.75, 0.65, 0.70, 0.51, 0.76, 0.36];
+const KOLASKAR: [f64; 20] = [1.064, 0.873, 0.776, 0.866, 1.412, 1.015, 0.851, 0.874, 1.105, 1.152, 1.250, 0.930, 0.826, 1.091, 1.064, 1.012, 0.909, 0.893, 1.161, 1.383];
+const PARATOPE: [f64; 20] = [0.35, 0.8, 0.7, 0.7, 0.1, 0.5, 0.55, 0.6, 0.7, 0.35, 0.35, 0.5, 0.35, 0.6, 0.3, 0.6, 0.55, 0.9, 1.0, 0.35];
+
+#[derive(Deserialize)]
+pub struct EpitopeRequest { antigen_sequence: Option<String>, antigen_pdb: Option<String>, antibody_sequence: Option<String>, numbering: Option<String>, threshold: Option<f64> }
+
+#[derive(Serialize)]
+pub struct AntigenResidue { index: usize, #[serde(skip_serializing_if = "Option::is_none")] pdb_residue: Option<String>, residue: char, linear_score: f64, #[serde(skip_serializing_if = "Option::is_none")] conformational_score: Option<f64>, epitope: bool }
+#[derive(Serialize)]
+pub struct LinearEpitope { start: usize, end: usize, sequence: String, score: f64 }
+#[derive(Serialize)]
+pub struct ConformationalEpitope { residues: Vec<String>, score: f64 }
+#[derive(Serialize)]
+pub struct AntigenReport { length: usize, threshold: f64, residues: Vec<AntigenResidue>, linear_epitopes: Vec<LinearEpitope>, conformational_epitopes: Vec<ConformationalEpitope> }
+#[derive(Serialize)]
+pub struct ParatopeResidue { chain_type: String, index: usize, position: String, residue: char, region: String, score: f64, paratope: bool }
+#[derive(Serialize)]
+pub struct ParatopeReport { scheme: String, residues: Vec<ParatopeResidue> }
+#[derive(Serialize)]
+pub struct EpitopeResponse { epitope_id: String, #[serde(skip_serializing_if = "Option::is_none")] antigen: Option<AntigenReport>, #[serde(skip_serializing_if = "Option::is_none")] paratope: Option<ParatopeReport> }
+
+fn scale(table: &[f64; 20], r: u8) -> Option<f64> { AMINO.iter().position(|&a| a == r).map(|i| table[i]) }
+fn round3(v: f64) -> f64 { (v * 1000.0).round() / 1000.0 }
+
+/// Per-residue propensity in 0..1, before windowing; unknown residues score as average.
+fn propensity(r: u8) -> f64 {
+    let norm = |table: &[f64; 20], v: Option<f64>| {
+        let (lo, hi) = table.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &x| (lo.min(x), hi.max(x)));
+        v.map_or(0.5, |v| (v - lo) / (hi - lo))
+    };
+    0.4 * norm(&PARKER, scale(&PARKER, r)) + 0.4 * norm(&EMINI, scale(&EMINI, r)) + 0.2 * norm(&KOLASKAR, scale(&KOLASKAR, r))
+}
+
+/// Window-averaged linear epitope score of every residue.
+fn linear_scores(seq: &[u8]) -> Vec<f64> {
+    let raw: Vec<f64> = seq.iter().map(|&r| propensity(r)).collect();
+    (0..seq.len()).map(|i| {
+        let (from, to) = (i.saturating_sub(WINDOW / 2), (i + WINDOW / 2 + 1).min(seq.len()));
+        raw[from..to].iter().sum::<f64>() / (to - from) as f64
+    }).collect()
+}
+
+/// Residues of a PDB structure as (chain+number label, one-letter code, Cα position).
+pub fn pdb_residues(text: &str) -> Vec<(String, u8, [f64; 3])> {
+    text.lines().filter(|l| l.starts_with("ATOM") && l.get(12..16).map(str::trim) == Some("CA")).filter_map(|l| {
+        let code = THREE_LETTER.iter().position(|&t| Some(t) == l.get(17..20)).map(|i| AMINO[i])?;
+        let f = |a: usize, b: usize| l.get(a..b)?.trim().parse::<f64>().ok();
+        let label = format!("{}{}{}", l.get(21..22)?.trim(), l.get(22..26)?.trim(), l.get(26..27).unwrap_or("").trim());
+        Some((label, code, [f(30, 38)?, f(38, 46)?, f(46, 54)?]))
+    }).collect()
+}
+
+/// Conformational scores: Cα exposure (few neighbours within 10 Å) blended with the linear
+/// propensity averaged over the same neighbourhood.
+fn conformational_scores(ca: &[[f64; 3]], linear: &[f64]) -> Vec<f64> {
+    (0..ca.len()).map(|i| {
+        let near: Vec<usize> = (0..ca.len()).filter(|&j| dist(ca[i], ca[j]) <= NEIGHBOUR_RADIUS).collect();
+        // Buried residues have ~28 Cα neighbours, exposed loop tips under 10.
+        let exposure = ((28.0 - (near.len() - 1) as f64) / 20.0).clamp(0.0, 1.0);
+        0.6 * exposure + 0.4 * near.iter().map(|&j| linear[j]).sum::<f64>() / near.len() as f64
+    }).collect()
+}
+
+/// Runs of at least `MIN_LINEAR_LENGTH` residues above threshold.
+fn linear_epitopes(seq: &[u8], scores: &[f64], threshold: f64) -> Vec<LinearEpitope> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < seq.len() {
+        if scores[i] < threshold { i += 1; continue; }
+        let start = i;
+        while i < seq.len() && scores[i] >= threshold { i += 1; }
+        if i - start >= MIN_LINEAR_LENGTH {
+            out.push(LinearEpitope { start, end: i - 1, sequence: String::from_utf8_lossy(&seq[start..i]).into(), score: round3(scores[start..i].iter().sum::<f64>() / (i - start) as f64) });
+        }
+    }
+    out
+}
+
+/// Single-linkage clusters of above-threshold residues, largest score first.
+fn patches(labels: &[String], ca: &[[f64; 3]], scores: &[f64], threshold: f64) -> Vec<ConformationalEpitope> {
+    let hot: Vec<usize> = (0..ca.len()).filter(|&i| scores[i] >= threshold).collect();
+    let mut cluster: Vec<usize> = (0..hot.len()).collect();
+    for a in 0..hot.len() {
+        for b in a + 1..hot.len() {
+            if dist(ca[hot[a]], ca[hot[b]]) > PATCH_LINK { continue; }
+            let (from, to) = (cluster[b], cluster[a]);
+            if from != to { cluster.iter_mut().filter(|c| **c == from).for_each(|c| *c = to); }
+        }
+    }
+    let mut out: Vec<ConformationalEpitope> = Vec::new();
+    for id in 0..hot.len() {
+        let members: Vec<usize> = (0..hot.len()).filter(|&k| cluster[k] == id).map(|k| hot[k]).collect();
+        if members.len() < MIN_PATCH_SIZE { continue; }
+        let score = members.iter().map(|&i| scores[i]).sum::<f64>() / members.len() as f64;
+        out.push(ConformationalEpitope { residues: members.iter().map(|&i| labels[i].clone()).collect(), score: round3(score) });
+    }
+    out.sort_by(|a, b| b.score.total_cmp(&a.score));
+    out
+}
+
+fn antigen(sequence: Option<&str>, pdb: Option<&str>, threshold: f64) -> Result<AntigenReport, String> {
+    let structure = pdb.map(pdb_residues).unwrap_or_default();
+    if pdb.is_some() && structure.is_empty() { return Err("antigen_pdb has no protein CA atoms".into()); }
+    let seq: Vec<u8> = if structure.is_empty() { sequence.unwrap_or_default().bytes().map(|b| b.to_ascii_uppercase()).collect() } else { structure.iter().map(|r| r.1).collect() };
+    if seq.is_empty() { return Err("antigen sequence is empty".into()); }
+    let linear = linear_scores(&seq);
+    let (conformational, conformational_epitopes) = if structure.is_empty() { (None, Vec::new()) } else {
+        let ca: Vec<[f64; 3]> = structure.iter().map(|r| r.2).collect();
+        let labels: Vec<String> = structure.iter().map(|r| r.0.clone()).collect();
+        let scores = conformational_scores(&ca, &linear);
+        let found = patches(&labels, &ca, &scores, threshold);
+        (Some(scores), found)
+    };
+    let residues = seq.iter().enumerate().map(|(i, &r)| {
+        let conf = conformational.as_ref().map(|c| c[i]);
+        AntigenResidue { index: i, pdb_residue: structure.get(i).map(|s| s.0.clone()), residue: r as char, linear_score: round3(linear[i]), conformational_score: conf.map(round3), epitope: conf.unwrap_or(linear[i]) >= threshold }
+    }).collect();
+    Ok(AntigenReport { length: seq.len(), threshold, linear_epitopes: linear_epitopes(&seq, &linear, threshold), conformational_epitopes, residues })
+}
+
+fn paratope(sequence: &str, scheme: &str) -> Result<ParatopeReport, String> {
+    let domains = antibody::analyze(sequence, scheme);
+    if domains.is_empty() { return Err("no antibody variable domain found in antibody_sequence".into()); }
+    let mut residues = Vec::new();
+    for d in &domains {
+        for (k, n) in d.numbering.iter().enumerate() {
+            let index = d.start + k;
+            // Framework residues within two of a loop occasionally touch antigen.
+            let flank = d.cdrs.iter().any(|c| index + 2 >= c.start && index <= c.end + 2);
+            let weight = match n.region.as_str() {
+                "CDR-H3" => 1.0,
+                "CDR-L2" => 0.5,
+                r if r.starts_with("CDR") => 0.8,
+                _ if flank => 0.3,
+                _ => 0.05,
+            };
+            let score = round3(weight * (0.5 + 0.5 * scale(&PARATOPE, n.residue as u8).unwrap_or(0.35)));
+            residues.push(ParatopeResidue { chain_type: d.chain_type.clone(), index, position: n.position.clone(), residue: n.residue, region: n.region.clone(), score, paratope: score >= PARATOPE_THRESHOLD });
+        }
+    }
+    Ok(ParatopeReport { scheme: scheme.into(), residues })
+}
+
+pub async fn predict(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<EpitopeRequest>) -> Result<Json<EpitopeResponse>, ApiError> {
+    let meter = usage::Meter::start();
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let has_antigen = req.antigen_sequence.is_some() || req.antigen_pdb.is_some();
+    if !has_antigen && req.antibody_sequence.is_none() { return Err(bad("give antigen_sequence or antigen_pdb, and/or antibody_sequence".into())); }
+    let threshold = req.threshold.unwrap_or(DEFAULT_THRESHOLD);
+    let antigen = if has_antigen { Some(antigen(req.antigen_sequence.as_deref(), req.antigen_pdb.as_deref(), threshold).map_err(bad)?) } else { None };
+    let scheme = req.numbering.unwrap_or_else(|| "kabat".into());
+    let paratope = req.antibody_sequence.as_deref().map(|seq| paratope(seq, &scheme)).transpose().map_err(bad)?;
+    let subject = req.antigen_sequence.or(req.antibody_sequence).unwrap_or_else(|| "antigen_pdb".into());
+    let resp = EpitopeResponse { epitope_id: uuid::Uuid::new_v4().to_string(), antigen, paratope };
+    record(&s, &headers, "epitope", &subject, FOLD_MODEL, &resp.epitope_id, &meter, &resp);
+    Ok(Json(resp))
+}
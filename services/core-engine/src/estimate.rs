@@ -0,0 +1,113 @@
+//! Runtime and cost estimates for proposed jobs.
+//!
+//! `POST /api/v1/bio/estimate` predicts what a job would take before it is submitted, for the
+//! UI to show next to the submit button. The request names the job `kind` and its size:
+//! `molecule` (or `atoms`) and `steps` for `simulate`, plus `runs` for a `sweep`;
+//! `library_size` for `screen`; `sequence` (or `residues`) for `predict`. Other kinds are
+//! estimated per job. A size left out is assumed, and the assumption listed.
+//!
+//! Work is counted in a unit that scales with the job: atom-steps for dynamics, compounds for
+//! screens, residues for prediction. CPU, GPU and wall seconds per unit, and peak memory, come
+//! from the last jobs of the kind across projects; before any has run a per-class default stands
+//! in and `measured` is false. Cost is CPU hours at `BIO_CPU_HOUR_RATE` plus GPU hours at
+//! `BIO_GPU_HOUR_RATE` (see `usage`). The queue wait counts the requests ahead of the job under
+//! admission control, each taken to run as long as the kind's recent jobs. Dry runs (see
+//! `dryrun`) report the same estimate.
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::{autoscale, composition::SystemReport, jobs::Throughput, standardize, usage, ApiError, AppState, ErrorResponse};
+
+/// Jobs of a kind averaged for the estimate.
+const HISTORY: usize = 100;
+/// Atoms assumed for a system of unknown size, and for past runs that didn't report theirs.
+const DEFAULT_ATOMS: f64 = 1_000.0;
+const DEFAULT_STEPS: f64 = 10_000.0;
+const DEFAULT_COMPOUNDS: f64 = 10_000.0;
+const DEFAULT_RESIDUES: f64 = 300.0;
+
+/// How work of a kind is counted: its unit, a typical job's work, and the work a finished job did.
+struct Measure { unit: &'static str, typical: f64, done: fn(&Value) -> Option<f64> }
+
+fn measure(kind: &str) -> Measure {
+    match kind {
+        "simulate" | "sweep" => Measure { unit: "atom-steps", typical: DEFAULT_ATOMS * DEFAULT_STEPS, done: |r| Some(r.get("steps")?.as_f64()? * r.pointer("/system/atoms").and_then(Value::as_f64).unwrap_or(DEFAULT_ATOMS)) },
+        "screen" => Measure { unit: "compounds", typical: DEFAULT_COMPOUNDS, done: |r| r.get("library_screened")?.as_f64() },
+        "predict" => Measure { unit: "residues", typical: DEFAULT_RESIDUES, done: |r| r.get("sequence_length")?.as_f64() },
+        _ => Measure { unit: "jobs", typical: 1.0, done: |_| Some(1.0) },
+    }
+}
+
+/// Atoms of a simulated system, or the default when it has no structure.
+pub fn atoms(system: Option<&SystemReport>) -> f64 { system.map_or(DEFAULT_ATOMS, |r| r.atoms as f64) }
+
+fn round(v: f64) -> f64 { (v * 1e4).round() / 1e4 + 0.0 }
+
+#[derive(Serialize)]
+pub struct Estimate {
+    kind: String, units: f64, unit: &'static str, measured: bool, jobs_measured: usize, core_seconds: f64, gpu_seconds: f64, runtime_seconds: f64,
+    #[serde(skip_serializing_if = "Option::is_none")] peak_memory_bytes: Option<u64>, queue_wait_seconds: f64, wall_clock_seconds: f64,
+    cpu_hour_rate: f64, gpu_hour_rate: f64, estimated_cost: f64, #[serde(skip_serializing_if = "Vec::is_empty")] assumptions: Vec<String>,
+}
+
+/// What `units` of work of `kind` would take, at the throughput of its recent jobs. A sweep runs simulations.
+pub fn of(s: &AppState, kind: &str, units: f64) -> Estimate {
+    let m = measure(kind);
+    let history = if kind == "sweep" { "simulate" } else { kind };
+    let measured = s.jobs.throughput(history, HISTORY, m.done);
+    let t = measured.unwrap_or_else(|| {
+        let core = autoscale::default_core_seconds(autoscale::class_of(kind));
+        Throughput { cpu_per_unit: core / m.typical, wall_per_unit: core / m.typical, wall_per_job: core, ..Throughput::default() }
+    });
+    let (core_seconds, gpu_seconds, runtime) = (t.cpu_per_unit * units, t.gpu_per_unit * units, t.wall_per_unit * units);
+    // Requests ahead of this one once every slot is taken, sharing the slots between them.
+    let a = s.admission.status();
+    let queue_wait = if a.policy == "off" || a.in_use < a.max_concurrent { 0.0 } else { (a.waiting + 1) as f64 / a.max_concurrent as f64 * t.wall_per_job };
+    let (cpu_rate, gpu_rate) = (usage::rate("BIO_CPU_HOUR_RATE"), usage::rate("BIO_GPU_HOUR_RATE"));
+    let mut assumptions = Vec::new();
+    if a.policy == "reject" && queue_wait > 0.0 { assumptions.push("every admission slot is taken; the engine would turn the job away now".into()); }
+    Estimate {
+        kind: kind.into(), units, unit: m.unit, measured: measured.is_some(), jobs_measured: t.jobs, core_seconds: round(core_seconds), gpu_seconds: round(gpu_seconds), runtime_seconds: round(runtime),
+        peak_memory_bytes: measured.map(|t| t.peak_memory_bytes).filter(|&b| b > 0), queue_wait_seconds: round(queue_wait), wall_clock_seconds: round(queue_wait + runtime),
+        cpu_hour_rate: cpu_rate, gpu_hour_rate: gpu_rate, estimated_cost: round((core_seconds * cpu_rate + gpu_seconds * gpu_rate) / 3600.0), assumptions,
+    }
+}
+
+#[derive(Deserialize)]
+pub struct EstimateRequest { kind: String, molecule: Option<String>, atoms: Option<u64>, steps: Option<u64>, runs: Option<u64>, library_size: Option<u64>, sequence: Option<String>, residues: Option<u64> }
+
+/// `value`, or `default` with the assumption noted.
+fn given(value: Option<u64>, field: &str, default: f64, assumptions: &mut Vec<String>) -> f64 {
+    value.map_or_else(|| { assumptions.push(format!("{field} not given; assuming {default}")); default }, |v| v as f64)
+}
+
+pub async fn estimate(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<EstimateRequest>) -> Result<Json<Estimate>, ApiError> {
+    let kinds = autoscale::kinds();
+    if !kinds.contains(&req.kind.as_str()) { return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: format!("kind {} is not a job kind; expected one of {}", req.kind, kinds.join(", ")) }))); }
+    let mut assumptions = Vec::new();
+    let units = match req.kind.as_str() {
+        "simulate" | "sweep" => {
+            let atoms = match (req.atoms, &req.molecule) {
+                (Some(atoms), _) => atoms as f64,
+                (None, Some(molecule)) => {
+                    let system = crate::composition::report(&standardize::resolve(&s, &headers, molecule).await);
+                    if system.is_none() { assumptions.push(format!("{molecule} did not resolve to a structure; assuming {DEFAULT_ATOMS} atoms")); }
+                    atoms(system.as_ref())
+                }
+                (None, None) => given(None, "molecule or atoms", DEFAULT_ATOMS, &mut assumptions),
+            };
+            let steps = given(req.steps, "steps", DEFAULT_STEPS, &mut assumptions);
+            let runs = if req.kind == "sweep" { given(req.runs, "runs", s.sweeps.mean_grid_size().unwrap_or(1.0).round(), &mut assumptions) } else { 1.0 };
+            atoms * steps * runs
+        }
+        "screen" => given(req.library_size, "library_size", DEFAULT_COMPOUNDS, &mut assumptions),
+        "predict" => given(req.residues.or(req.sequence.as_ref().map(|q| q.len() as u64)), "sequence or residues", DEFAULT_RESIDUES, &mut assumptions),
+        _ => 1.0,
+    };
+    let mut e = of(&s, &req.kind, units);
+    e.assumptions.splice(0..0, assumptions);
+    Ok(Json(e))
+}
@@ -0,0 +1,229 @@
+//! Job lifecycle events on a message bus.
+//!
+//! With `BIO_EVENT_BUS` set, the engine publishes an event each time a job moves on, so other
+//! platform services can react (notify, start downstream work) without polling `/jobs`:
+//!
+//! - `submitted`: a compute request arrived; admission control may still hold it.
+//! - `started`: it was admitted and is running.
+//! - `progress`: a pipeline step completed, failed or was skipped.
+//! - `completed`: the job was recorded, under the `job_id` that `/jobs/:id` knows.
+//! - `failed`: the request was answered with an error, whose message is `error`.
+//!
+//! A synchronous request's events share a `request_id`, since its job id only exists once it
+//! completes. A pipeline's events carry its `pipeline_id` as `job_id` throughout, and each of
+//! its steps is a job of its own. `BIO_EVENT_BUS` is `nats://[user:pass@]host:port`, for core
+//! NATS messages on `<BIO_EVENT_SUBJECT>.<kind>.<event>` (default `alice.bio.jobs`), or
+//! `kafka+http[s]://host:port` for a Kafka REST Proxy producing to `BIO_EVENT_TOPIC` (default
+//! `alice.bio.jobs`), keyed by job or request id. Events queue and go out in the background, so
+//! a slow or absent bus never holds up a request: past `QUEUE` waiting events new ones are
+//! dropped with a warning, and an event that can't be sent after one reconnect is lost.
+
+use axum::{body::{Body, HttpBody}, extract::{Request, State}, http::{HeaderMap, HeaderValue, StatusCode}, middleware::Next, response::Response};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::mpsc;
+
+use crate::{audit, autoscale, notify, projects, unix_now, AppState, ErrorResponse};
+
+/// Carries a compute request's id to `record`; a client-sent value is discarded.
+pub const REQUEST_ID_HEADER: &str = "x-bio-request-id";
+/// The kind of job the request asked for, set alongside its id.
+const JOB_KIND_HEADER: &str = "x-bio-job-kind";
+const QUEUE: usize = 10_000;
+/// Events produced to the REST Proxy in one request.
+const BATCH: usize = 100;
+const DEFAULT_NAME: &str = "alice.bio.jobs";
+/// The largest error body read for a `failed` event's message.
+const MAX_ERROR_BODY: usize = 64 * 1024;
+
+#[derive(Serialize)]
+pub struct Progress { pub step: String, pub status: String, pub done: usize, pub total: usize }
+#[derive(Serialize)]
+pub struct Event { pub event: &'static str, pub kind: String, pub project: String, #[serde(skip_serializing_if = "Option::is_none")] pub job_id: Option<String>, #[serde(skip_serializing_if = "Option::is_none")] pub request_id: Option<String>, #[serde(skip_serializing_if = "Option::is_none")] pub progress: Option<Progress>, #[serde(skip_serializing_if = "Option::is_none")] pub error: Option<String>, pub at_unix: u64 }
+
+impl Event {
+    pub fn new(event: &'static str, kind: &str, project: &str) -> Self { Self { event, kind: kind.into(), project: project.into(), job_id: None, request_id: None, progress: None, error: None, at_unix: unix_now() } }
+    pub fn job(mut self, id: &str) -> Self { self.job_id = Some(id.into()); self }
+    pub fn request(mut self, headers: &HeaderMap) -> Self { self.request_id = request_id(headers); self }
+    fn key(&self) -> Option<&str> { self.request_id.as_deref().or(self.job_id.as_deref()) }
+}
+
+/// The id `submit` gave the compute request with these headers.
+pub fn request_id(headers: &HeaderMap) -> Option<String> { headers.get(REQUEST_ID_HEADER).and_then(|v| v.to_str().ok()).map(String::from) }
+
+/// Whether a job of `kind` is the one the request asked for, rather than a pipeline step or a
+/// sweep point recorded along the way.
+pub fn requested(headers: &HeaderMap, kind: &str) -> bool { headers.get(JOB_KIND_HEADER).is_some_and(|v| v.as_bytes() == kind.as_bytes()) }
+
+struct Nats { addr: String, subject: String, connect: String }
+struct NatsConnection { reader: BufReader<OwnedReadHalf>, writer: OwnedWriteHalf }
+
+enum Sink { Nats(Nats), Kafka { client: reqwest::Client, url: String } }
+
+impl Sink {
+    fn parse(url: &str) -> Result<Self, String> {
+        let name = |var: &str| std::env::var(var).ok().filter(|v| !v.is_empty()).unwrap_or_else(|| DEFAULT_NAME.into());
+        if let Some(rest) = url.strip_prefix("nats://") {
+            let (auth, addr) = match rest.trim_end_matches('/').rsplit_once('@') { Some((auth, addr)) => (Some(auth), addr), None => (None, rest.trim_end_matches('/')) };
+            let mut connect = json!({ "verbose": false, "pedantic": false, "name": "bio-engine", "lang": "rust", "version": env!("CARGO_PKG_VERSION") });
+            match auth.map(|a| a.split_once(':')) {
+                Some(Some((user, pass))) => { connect["user"] = user.into(); connect["pass"] = pass.into(); }
+                Some(None) => connect["auth_token"] = auth.into(),
+                None => {}
+            }
+            let addr = if addr.contains(':') { addr.to_string() } else { format!("{addr}:4222") };
+            return Ok(Self::Nats(Nats { addr, subject: name("BIO_EVENT_SUBJECT"), connect: connect.to_string() }));
+        }
+        if let Some(base) = url.strip_prefix("kafka+") {
+            if !base.starts_with("http://") && !base.starts_with("https://") { return Err("a Kafka REST Proxy URL is kafka+http:// or kafka+https://".into()); }
+            return Ok(Self::Kafka { client: reqwest::Client::new(), url: format!("{}/topics/{}", base.trim_end_matches('/'), name("BIO_EVENT_TOPIC")) });
+        }
+        Err("expected nats://host:port or kafka+http://host:port".into())
+    }
+
+    /// Where events go, credentials left out.
+    fn name(&self) -> String {
+        match self { Self::Nats(n) => format!("NATS {} ({}.*)", n.addr, n.subject), Self::Kafka { url, .. } => url.clone() }
+    }
+}
+
+impl Nats {
+    async fn connect(&self) -> std::io::Result<NatsConnection> {
+        let (read, mut writer) = tokio::net::TcpStream::connect(&self.addr).await?.into_split();
+        let mut reader = BufReader::new(read);
+        let mut info = String::new();
+        reader.read_line(&mut info).await?;
+        if !info.starts_with("INFO") { return Err(std::io::Error::other(format!("unexpected greeting {:?}", info.trim_end()))); }
+        writer.write_all(format!("CONNECT {}\r\n", self.connect).as_bytes()).await?;
+        Ok(NatsConnection { reader, writer })
+    }
+
+    fn message(&self, e: &Event) -> Vec<u8> {
+        let payload = serde_json::to_vec(e).unwrap_or_default();
+        let mut msg = format!("PUB {}.{}.{} {}\r\n", self.subject, e.kind, e.event, payload.len()).into_bytes();
+        msg.extend_from_slice(&payload);
+        msg.extend_from_slice(b"\r\n");
+        msg
+    }
+
+    /// Publishes events as they arrive over one connection, reconnecting when it drops and
+    /// answering the server's keep-alive pings in between.
+    async fn deliver(self, mut rx: mpsc::Receiver<Event>) {
+        let mut conn: Option<NatsConnection> = None;
+        let mut line = String::new();
+        loop {
+            tokio::select! {
+                event = rx.recv() => {
+                    let Some(event) = event else { return };
+                    let msg = self.message(&event);
+                    for attempt in 0..2 {
+                        if conn.is_none() {
+                            match self.connect().await {
+                                Ok(c) => conn = Some(c),
+                                Err(e) => { tracing::warn!("event {} of {} not published: NATS {} unreachable: {e}", event.event, event.kind, self.addr); break }
+                            }
+                        }
+                        let Some(c) = conn.as_mut() else { break };
+                        match c.writer.write_all(&msg).await {
+                            Ok(()) => break,
+                            Err(e) => { conn = None; if attempt == 1 { tracing::warn!("event {} of {} not published to NATS: {e}", event.event, event.kind); } }
+                        }
+                    }
+                }
+                read = async { match conn.as_mut() { Some(c) => c.reader.read_line(&mut line).await, None => std::future::pending().await } } => {
+                    match read {
+                        Ok(0) | Err(_) => conn = None,
+                        Ok(_) if line.starts_with("PING") => if let Some(c) = conn.as_mut() { if c.writer.write_all(b"PONG\r\n").await.is_err() { conn = None; } },
+                        Ok(_) if line.starts_with("-ERR") => tracing::warn!("NATS {}: {}", self.addr, line.trim_end()),
+                        Ok(_) => {}
+                    }
+                    line.clear();
+                }
+            }
+        }
+    }
+}
+
+/// Produces queued events in batches of up to `BATCH`.
+async fn produce(client: reqwest::Client, url: String, mut rx: mpsc::Receiver<Event>) {
+    while let Some(first) = rx.recv().await {
+        let mut batch = vec![first];
+        while batch.len() < BATCH { match rx.try_recv() { Ok(e) => batch.push(e), Err(_) => break } }
+        let records: Vec<Value> = batch.iter().map(|e| json!({ "key": e.key(), "value": e })).collect();
+        let body = json!({ "records": records }).to_string();
+        let sent = client.post(&url).header(reqwest::header::CONTENT_TYPE, "application/vnd.kafka.json.v2+json").body(body).timeout(std::time::Duration::from_secs(10)).send().await.and_then(|r| r.error_for_status());
+        if let Err(e) = sent { tracing::warn!("{} events not produced to {url}: {e}", batch.len()); }
+    }
+}
+
+pub struct EventBus { queue: Option<mpsc::Sender<Event>>, dropped: AtomicU64 }
+
+impl EventBus {
+    pub fn from_env() -> Self {
+        let sink = std::env::var("BIO_EVENT_BUS").ok().filter(|v| !v.is_empty()).and_then(|url| match Sink::parse(&url) {
+            Ok(sink) => { tracing::info!("publishing job events to {}", sink.name()); Some(sink) }
+            Err(e) => { tracing::warn!("BIO_EVENT_BUS unusable: {e}; no events are published"); None }
+        });
+        let queue = sink.map(|sink| {
+            let (tx, rx) = mpsc::channel(QUEUE);
+            match sink { Sink::Nats(nats) => tokio::spawn(nats.deliver(rx)), Sink::Kafka { client, url } => tokio::spawn(produce(client, url, rx)) };
+            tx
+        });
+        Self { queue, dropped: AtomicU64::new(0) }
+    }
+
+    pub fn publish(&self, e: Event) {
+        let Some(queue) = &self.queue else { return };
+        if queue.try_send(e).is_err() {
+            let n = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if n.is_power_of_two() { tracing::warn!("event queue full; {n} events dropped so far"); }
+        }
+    }
+}
+
+/// Gives each compute request an id and publishes `submitted`, then `failed` if it's answered
+/// with an error, which is also notified. Runs outside admission control, so a request turned
+/// away still fails.
+pub async fn submit(State(s): State<Arc<AppState>>, mut req: Request, next: Next) -> Response {
+    req.headers_mut().remove(REQUEST_ID_HEADER);
+    req.headers_mut().remove(JOB_KIND_HEADER);
+    let Some(kind) = autoscale::job_kind(&req) else { return next.run(req).await };
+    let id = uuid::Uuid::new_v4().to_string();
+    if let Ok(v) = HeaderValue::from_str(&id) { req.headers_mut().insert(REQUEST_ID_HEADER, v); }
+    req.headers_mut().insert(JOB_KIND_HEADER, HeaderValue::from_static(kind));
+    let headers = req.headers().clone();
+    let t = std::time::Instant::now();
+    let project = projects::project_id(req.headers());
+    s.events.publish(Event::new("submitted", kind, &project).request(req.headers()));
+    let resp = next.run(req).await;
+    if resp.status().is_success() { return resp; }
+    let small = resp.body().size_hint().upper().is_some_and(|n| n <= MAX_ERROR_BODY as u64);
+    let audited = resp.extensions().get::<audit::Recorded>().is_some();
+    let status = resp.status();
+    let failed = |error: String| {
+        if !audited {
+            let timed_out = matches!(status, StatusCode::GATEWAY_TIMEOUT | StatusCode::REQUEST_TIMEOUT) || error.contains("timed out");
+            s.audit.failure(&headers, &project, kind, if timed_out { "timed_out" } else { "failed" }, &error);
+        }
+        s.notifications.finished(&headers, &project, notify::Finished { kind, id: &id, subject: "", error: Some(&error), wall_seconds: t.elapsed().as_secs_f64(), summary: Vec::new(), link: None });
+        s.events.publish(Event { request_id: Some(id.clone()), error: Some(error), ..Event::new("failed", kind, &project) });
+    };
+    if !small {
+        failed(resp.status().to_string());
+        return resp;
+    }
+    let (parts, body) = resp.into_parts();
+    let bytes = axum::body::to_bytes(body, MAX_ERROR_BODY).await.unwrap_or_default();
+    failed(serde_json::from_slice::<ErrorResponse>(&bytes).map(|e| e.error).unwrap_or_else(|_| parts.status.to_string()));
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+/// Publishes `started` once a compute request is past admission control.
+pub async fn start(State(s): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    if let Some(kind) = autoscale::job_kind(&req) { s.events.publish(Event::new("started", kind, &projects::project_id(req.headers())).request(req.headers())); }
+    next.run(req).await
+}
@@ -0,0 +1,383 @@
+//! Custom force fields.
+//!
+//! A project can upload a parameter file under a name, which `force_field` then selects in
+//! `/simulate`, `/energy`, sweeps, protocols and pipeline steps. Two formats are read. An AMBER
+//! frcmod (`MASS`, `BOND`, `ANGL`, `DIHE`, `IMPR` and `NONB` sections, GAFF atom types)
+//! supplements a `base` force field, `gaff2` unless given (`none` for a file that stands
+//! alone); parameters parmchk marked `ATTN, need revision` count as missing. An OpenMM
+//! force-field XML stands alone: its residue templates type the molecule, and its bonded and
+//! nonbonded forces are looked up by atom type or class, an empty class matching any.
+//!
+//! Every run with a custom force field first checks its coverage of the molecule, hydrogens
+//! included: each atom needs a type and each bond, angle, proper dihedral and atom type a
+//! parameter, from the file or its base. A molecule the file doesn't cover is refused with the
+//! terms it lacks; one it covers reports its `force_field_coverage`. Uploads are immutable like
+//! protocols, and the built-in names can't be taken.
+
+use axum::{extract::{Path, State}, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+
+use crate::{chem::{self, Molecule}, charges, gaff, not_found, projects, resolver::Resolved, unix_now, ApiError, AppState, ErrorResponse};
+
+/// Force fields every project has.
+pub const BUILTIN: [&str; 8] = ["amber-ff14", "amber-ff14sb", "amber99sb", "charmm36", "gaff", "gaff2", "mmff94", "opls-aa"];
+/// Force fields a frcmod can supplement.
+const BASES: [&str; 2] = ["gaff", "gaff2"];
+/// Missing terms named in an error; the rest are only counted.
+const MAX_LISTED: usize = 10;
+
+/// A parameter's atoms: a type, a class, or any atom.
+#[derive(Clone, PartialEq)]
+enum Key { Any, Type(String), Class(String) }
+
+/// A typed atom: its type and class (the same for frcmod types).
+struct Typed { name: String, class: String }
+
+impl Key {
+    fn matches(&self, a: &Typed) -> bool {
+        match self { Self::Any => true, Self::Type(t) => *t == a.name, Self::Class(c) => *c == a.class }
+    }
+}
+
+/// A bonded or nonbonded parameter; `revise` when parmchk only guessed it.
+#[derive(Clone)]
+struct Term { keys: Vec<Key>, revise: bool }
+
+impl Term {
+    /// Whether the term applies to atoms `a`, in either direction.
+    fn matches(&self, a: &[&Typed]) -> bool {
+        self.keys.len() == a.len() && (self.keys.iter().zip(a).all(|(k, t)| k.matches(t)) || self.keys.iter().rev().zip(a).all(|(k, t)| k.matches(t)))
+    }
+}
+
+/// An OpenMM residue template: atom names and types, bonds by atom index.
+#[derive(Clone)]
+struct Template { name: String, atoms: Vec<(String, String)>, bonds: Vec<(usize, usize)>, external: bool }
+
+/// An uploaded file's parameters.
+#[derive(Clone, Default)]
+struct Params {
+    /// Atom types with their class and element.
+    types: BTreeMap<String, (String, Option<String>)>,
+    bonds: Vec<Term>, angles: Vec<Term>, dihedrals: Vec<Term>, impropers: Vec<Term>, nonbonded: Vec<Term>, templates: Vec<Template>,
+}
+
+/// A force field as uploaded.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Upload { pub name: String, format: Option<String>, base: Option<String>, content: String, #[serde(default)] created_at_unix: u64 }
+
+#[derive(Clone)]
+pub struct ForceField { upload: Upload, params: Params }
+
+#[derive(Serialize)]
+pub struct Summary {
+    name: String, format: String, #[serde(skip_serializing_if = "Option::is_none")] base: Option<String>, atom_types: usize, bonds: usize, angles: usize, dihedrals: usize,
+    impropers: usize, nonbonded: usize, templates: usize, needs_revision: usize, created_at_unix: u64,
+}
+
+#[derive(Serialize)]
+pub struct ForceFieldsResponse { project: String, builtin: Vec<&'static str>, force_fields: Vec<Summary> }
+
+/// How a custom force field covers a molecule: the atom types it gives, and of the molecule's
+/// distinct terms, how many the file and its base supply.
+#[derive(Serialize, Clone)]
+pub struct Coverage { force_field: String, atom_types: Vec<String>, terms: usize, from_file: usize, from_base: usize, #[serde(skip_serializing_if = "Vec::is_empty")] missing: Vec<String> }
+
+/// Checks that `fields`, the rest of a parameter line, starts with `want` numbers.
+fn numbers(fields: &str, want: usize, line: usize) -> Result<(), String> {
+    let parsed = fields.split_whitespace().take(want).filter(|f| f.parse::<f64>().is_ok()).count();
+    if parsed < want { return Err(format!("frcmod line {line}: expected {want} numbers after the atom types")); }
+    Ok(())
+}
+
+fn parse_frcmod(text: &str) -> Result<Params, String> {
+    let mut p = Params::default();
+    let mut section = "";
+    for (n, line) in text.lines().enumerate().skip(1) {
+        let number = n + 1;
+        let head = line.split_whitespace().next().unwrap_or("").to_ascii_uppercase();
+        if line.trim().is_empty() { section = ""; continue; }
+        if head == "END" { break; }
+        if let Some(k) = ["MASS", "BOND", "ANGL", "DIHE", "IMPR", "NONB", "HBON", "IPOL", "CMAP", "LJED"].into_iter().find(|k| head.starts_with(k)) { section = k; continue; }
+        let revise = line.contains("ATTN");
+        let bonded = |count: usize, want: usize| -> Result<Term, String> {
+            let width = 3 * count - 1;
+            let names = line.get(..width).ok_or_else(|| format!("frcmod line {number}: too short for {count} atom types"))?;
+            numbers(&line[width..], want, number)?;
+            let keys = names.split('-').map(str::trim).map(|t| if t.eq_ignore_ascii_case("X") { Key::Any } else { Key::Type(t.to_string()) }).collect::<Vec<_>>();
+            if keys.len() != count || keys.iter().any(|k| *k == Key::Type(String::new())) { return Err(format!("frcmod line {number}: expected {count} atom types joined by '-'")); }
+            Ok(Term { keys, revise })
+        };
+        match section {
+            "MASS" => {
+                let mut fields = line.split_whitespace();
+                let name = fields.next().unwrap_or_default().to_string();
+                numbers(&line[line.find(&name).unwrap_or(0) + name.len()..], 1, number)?;
+                p.types.insert(name.clone(), (name, None));
+            }
+            "BOND" => p.bonds.push(bonded(2, 2)?),
+            "ANGL" => p.angles.push(bonded(3, 2)?),
+            // A negative periodicity continues the same dihedral on the next line; each line is one term.
+            "DIHE" => p.dihedrals.push(bonded(4, 4)?),
+            "IMPR" => p.impropers.push(bonded(4, 3)?),
+            "NONB" => {
+                let name = line.split_whitespace().next().unwrap_or_default();
+                numbers(&line[line.find(name).unwrap_or(0) + name.len()..], 2, number)?;
+                p.nonbonded.push(Term { keys: vec![Key::Type(name.to_string())], revise });
+            }
+            "" => return Err(format!("frcmod line {number}: parameters outside a MASS, BOND, ANGL, DIHE, IMPR or NONB section")),
+            _ => {}
+        }
+    }
+    Ok(p)
+}
+
+/// An XML element: its parent's name, its own and its attributes.
+struct Element { parent: String, name: String, attributes: Vec<(String, String)> }
+
+impl Element {
+    fn get(&self, key: &str) -> Option<&str> { self.attributes.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str()) }
+}
+
+fn unescape(v: &str) -> String { v.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&") }
+
+/// The elements of an XML document in order. Text, comments and declarations are skipped.
+fn xml_elements(text: &str) -> Result<Vec<Element>, String> {
+    let (mut out, mut open): (Vec<Element>, Vec<String>) = (Vec::new(), Vec::new());
+    // The end of the markup at the start of `s`, closed by `end`.
+    let after = |s: &str, end: &str| s.find(end).map(|e| e + end.len()).ok_or_else(|| format!("unterminated {}", s.chars().take(12).collect::<String>()));
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        if rest.starts_with("<!--") { rest = &rest[after(rest, "-->")?..]; continue; }
+        if rest.starts_with("<?") || rest.starts_with("<!") { rest = &rest[after(rest, ">")?..]; continue; }
+        let end = after(rest, ">")?;
+        let tag = &rest[1..end - 1];
+        rest = &rest[end..];
+        if let Some(name) = tag.strip_prefix('/') {
+            if open.pop().as_deref() != Some(name.trim()) { return Err(format!("unexpected closing tag </{}>", name.trim())); }
+            continue;
+        }
+        let (body, closed) = match tag.strip_suffix('/') { Some(b) => (b, true), None => (tag, false) };
+        let name_end = body.find(char::is_whitespace).unwrap_or(body.len());
+        let mut element = Element { parent: open.last().cloned().unwrap_or_default(), name: body[..name_end].to_string(), attributes: Vec::new() };
+        let mut attrs = body[name_end..].trim_start();
+        while !attrs.is_empty() {
+            let eq = attrs.find('=').ok_or_else(|| format!("attribute without a value in <{}>", element.name))?;
+            let key = attrs[..eq].trim().to_string();
+            let value = attrs[eq + 1..].trim_start();
+            let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'').ok_or_else(|| format!("unquoted attribute {key} in <{}>", element.name))?;
+            let close = value[1..].find(quote).ok_or_else(|| format!("unterminated attribute {key} in <{}>", element.name))?;
+            element.attributes.push((key, unescape(&value[1..close + 1])));
+            attrs = value[close + 2..].trim_start();
+        }
+        if !closed { open.push(element.name.clone()); }
+        out.push(element);
+    }
+    if let Some(name) = open.last() { return Err(format!("<{name}> is never closed")); }
+    Ok(out)
+}
+
+fn parse_openmm(text: &str) -> Result<Params, String> {
+    let elements = xml_elements(text)?;
+    if elements.first().map(|e| e.name.as_str()) != Some("ForceField") { return Err("an OpenMM force field's root element is <ForceField>".into()); }
+    let mut p = Params::default();
+    let keys = |e: &Element, count: usize| -> Result<Vec<Key>, String> {
+        (1..=count).map(|i| match (e.get(&format!("type{i}")), e.get(&format!("class{i}"))) {
+            (Some(""), _) | (_, Some("")) => Ok(Key::Any),
+            (Some(t), _) => Ok(Key::Type(t.to_string())),
+            (None, Some(c)) => Ok(Key::Class(c.to_string())),
+            (None, None) => Err(format!("<{}> in <{}> needs type{i} or class{i}", e.name, e.parent)),
+        }).collect()
+    };
+    for e in &elements {
+        match (e.parent.as_str(), e.name.as_str()) {
+            ("AtomTypes", "Type") => {
+                let name = e.get("name").ok_or("an atom <Type> needs a name")?;
+                p.types.insert(name.to_string(), (e.get("class").unwrap_or(name).to_string(), e.get("element").map(String::from)));
+            }
+            ("Residues", "Residue") => p.templates.push(Template { name: e.get("name").unwrap_or_default().to_string(), atoms: Vec::new(), bonds: Vec::new(), external: false }),
+            ("Residue", _) => {
+                let t = p.templates.last_mut().ok_or("a residue entry outside <Residue>")?;
+                let index = |name: Option<&str>, position: Option<&str>| name.and_then(|n| t.atoms.iter().position(|a| a.0 == n)).or_else(|| position.and_then(|i| i.parse().ok()));
+                match e.name.as_str() {
+                    "Atom" => t.atoms.push((e.get("name").unwrap_or_default().to_string(), e.get("type").ok_or_else(|| format!("atom in residue {} has no type", t.name))?.to_string())),
+                    "Bond" => {
+                        let (a, b) = (index(e.get("atomName1"), e.get("from")), index(e.get("atomName2"), e.get("to")));
+                        let (Some(a), Some(b)) = (a, b) else { return Err(format!("a bond in residue {} names an atom it doesn't have", t.name)) };
+                        t.bonds.push((a, b));
+                    }
+                    "ExternalBond" => t.external = true,
+                    _ => {}
+                }
+            }
+            ("HarmonicBondForce", "Bond") => p.bonds.push(Term { keys: keys(e, 2)?, revise: false }),
+            ("HarmonicAngleForce", "Angle") => p.angles.push(Term { keys: keys(e, 3)?, revise: false }),
+            ("PeriodicTorsionForce" | "RBTorsionForce", "Proper") => p.dihedrals.push(Term { keys: keys(e, 4)?, revise: false }),
+            ("PeriodicTorsionForce" | "RBTorsionForce", "Improper") => p.impropers.push(Term { keys: keys(e, 4)?, revise: false }),
+            ("NonbondedForce", "Atom") => {
+                let key = match (e.get("type"), e.get("class")) { (Some(t), _) => Key::Type(t.to_string()), (None, Some(c)) => Key::Class(c.to_string()), (None, None) => return Err("a nonbonded <Atom> needs a type or class".into()) };
+                p.nonbonded.push(Term { keys: vec![key], revise: false });
+            }
+            _ => {}
+        }
+    }
+    for t in &p.templates {
+        if let Some((atom, ty)) = t.atoms.iter().find(|(_, ty)| !p.types.contains_key(ty)) { return Err(format!("atom {atom} of residue {} has type {ty}, which <AtomTypes> doesn't define", t.name)); }
+        if let Some(&(a, b)) = t.bonds.iter().find(|&&(a, b)| a >= t.atoms.len() || b >= t.atoms.len()) { return Err(format!("residue {} has a bond {a}-{b} past its atoms", t.name)); }
+    }
+    Ok(p)
+}
+
+/// Reads an upload, detecting its format and filling in its base.
+fn parse(upload: &mut Upload) -> Result<Params, String> {
+    let format = upload.format.clone().unwrap_or_else(|| if upload.content.trim_start().starts_with('<') { "openmm-xml" } else { "frcmod" }.into());
+    let params = match format.as_str() {
+        "frcmod" => {
+            let base = upload.base.clone().unwrap_or_else(|| "gaff2".into());
+            if base != "none" && !BASES.contains(&base.as_str()) { return Err(format!("unknown base {base}; expected one of {} or none", BASES.join(", "))); }
+            upload.base = Some(base);
+            parse_frcmod(&upload.content)?
+        }
+        "openmm-xml" => {
+            if upload.base.is_some() { return Err("an OpenMM force field stands alone and takes no base".into()); }
+            parse_openmm(&upload.content)?
+        }
+        other => return Err(format!("unknown format {other}; expected frcmod or openmm-xml")),
+    };
+    let count = params.types.len() + params.bonds.len() + params.angles.len() + params.dihedrals.len() + params.impropers.len() + params.nonbonded.len();
+    if count == 0 { return Err(format!("the {format} file has no parameters")); }
+    upload.format = Some(format);
+    Ok(params)
+}
+
+impl ForceField {
+    fn summary(&self) -> Summary {
+        let p = &self.params;
+        let revise = [&p.bonds, &p.angles, &p.dihedrals, &p.impropers, &p.nonbonded].iter().flat_map(|t| t.iter()).filter(|t| t.revise).count();
+        Summary {
+            name: self.upload.name.clone(), format: self.upload.format.clone().unwrap_or_default(), base: self.upload.base.clone(), atom_types: p.types.len(), bonds: p.bonds.len(), angles: p.angles.len(),
+            dihedrals: p.dihedrals.len(), impropers: p.impropers.len(), nonbonded: p.nonbonded.len(), templates: p.templates.len(), needs_revision: revise, created_at_unix: self.upload.created_at_unix,
+        }
+    }
+
+    /// Each atom's type, from the residue template the molecule matches (OpenMM) or GAFF rules.
+    fn types(&self, m: &Molecule) -> Option<Vec<Typed>> {
+        let adj = m.neighbors();
+        if self.upload.format.as_deref() != Some("openmm-xml") {
+            return Some((0..m.atoms.len()).map(|i| { let t = gaff::atom_type(m, &adj, i); Typed { name: t.clone(), class: t } }).collect());
+        }
+        self.params.templates.iter().filter(|t| !t.external && t.atoms.len() == m.atoms.len() && t.bonds.len() == m.bonds.len()).find_map(|t| {
+            let mut qadj = vec![Vec::new(); t.atoms.len()];
+            for (k, &(a, b)) in t.bonds.iter().enumerate() { qadj[a].push((b, k)); qadj[b].push((a, k)); }
+            let element = |q: usize| self.params.types.get(&t.atoms[q].1).and_then(|ty| ty.1.clone());
+            let map = m.find(&qadj, &|q, a| element(q).as_deref() == Some(m.atoms[a].element.as_str()), &|_, _| true)?;
+            let mut typed: Vec<Option<Typed>> = (0..m.atoms.len()).map(|_| None).collect();
+            for (q, &a) in map.iter().enumerate() {
+                let ty = &t.atoms[q].1;
+                typed[a] = Some(Typed { name: ty.clone(), class: self.params.types[ty].0.clone() });
+            }
+            typed.into_iter().collect()
+        })
+    }
+
+    /// How the force field covers `m`, hydrogens added.
+    pub fn coverage(&self, m: &Molecule) -> Result<Coverage, String> {
+        let m = charges::with_hydrogens(m)?;
+        let name = self.upload.name.clone();
+        let Some(typed) = self.types(&m) else {
+            return Ok(Coverage { force_field: name, atom_types: Vec::new(), terms: 0, from_file: 0, from_base: 0, missing: vec!["a residue template".into()] });
+        };
+        let based = self.upload.base.as_deref().is_some_and(|b| b != "none");
+        let adj = m.neighbors();
+        let mut quads: Vec<Vec<usize>> = Vec::new();
+        for b in &m.bonds {
+            for &(i, _) in adj[b.a].iter().filter(|&&(i, _)| i != b.b) {
+                for &(l, _) in adj[b.b].iter().filter(|&&(l, _)| l != b.a && l != i) { quads.push(vec![i, b.a, b.b, l]); }
+            }
+        }
+        let angles = (0..m.atoms.len()).flat_map(|j| { let n = &adj[j]; (0..n.len()).flat_map(move |x| (x + 1..n.len()).map(move |y| vec![n[x].0, j, n[y].0])) }).collect::<Vec<_>>();
+        let sets = [
+            ("atom type", &self.params.nonbonded, (0..m.atoms.len()).map(|i| vec![i]).collect()),
+            ("bond", &self.params.bonds, m.bonds.iter().map(|b| vec![b.a, b.b]).collect()),
+            ("angle", &self.params.angles, angles),
+            ("dihedral", &self.params.dihedrals, quads),
+        ];
+        let (mut seen, mut missing, mut from_file, mut from_base) = (BTreeSet::new(), Vec::new(), 0, 0);
+        for (what, terms, groups) in sets {
+            for atoms in groups {
+                let a: Vec<&Typed> = atoms.iter().map(|&i| &typed[i]).collect();
+                let label = a.iter().map(|t| t.class.as_str()).collect::<Vec<_>>().join("-");
+                let reverse = a.iter().rev().map(|t| t.class.as_str()).collect::<Vec<_>>().join("-");
+                if !seen.insert((what, label.clone().min(reverse))) { continue; }
+                // The most specific match wins, as in AMBER: a dihedral without wildcards over one with them.
+                let best = terms.iter().filter(|t| t.matches(&a)).min_by_key(|t| t.keys.iter().filter(|k| **k == Key::Any).count());
+                match best {
+                    Some(t) if !t.revise => from_file += 1,
+                    Some(_) => missing.push(format!("{what} {label} (marked ATTN, need revision)")),
+                    None if based => from_base += 1,
+                    None => missing.push(format!("{what} {label}")),
+                }
+            }
+        }
+        let atom_types = typed.iter().map(|t| t.name.clone()).collect::<BTreeSet<_>>().into_iter().collect();
+        Ok(Coverage { force_field: name, atom_types, terms: seen.len(), from_file, from_base, missing })
+    }
+}
+
+pub struct ForceFieldStore { fields: Mutex<BTreeMap<(String, String), ForceField>> }
+
+impl ForceFieldStore {
+    pub fn new() -> Self { Self { fields: Mutex::new(BTreeMap::new()) } }
+    pub fn get(&self, project: &str, name: &str) -> Option<ForceField> { self.fields.lock().unwrap().get(&(project.to_string(), name.to_string())).cloned() }
+    /// The project's uploads as given, for export.
+    pub fn for_project(&self, project: &str) -> Vec<Upload> { self.fields.lock().unwrap().iter().filter(|((p, _), _)| p == project).map(|(_, f)| f.upload.clone()).collect() }
+    /// Saves an exported upload; false when the name is taken.
+    pub fn import(&self, project: &str, mut upload: Upload) -> Result<bool, String> {
+        let params = parse(&mut upload)?;
+        let mut fields = self.fields.lock().unwrap();
+        let key = (project.to_string(), upload.name.clone());
+        if fields.contains_key(&key) || BUILTIN.contains(&upload.name.as_str()) { return Ok(false); }
+        fields.insert(key, ForceField { upload, params });
+        Ok(true)
+    }
+}
+
+/// Coverage of `mol` by the project's force field `name`; `None` for a force field that isn't
+/// custom, and an error when the molecule has no structure or terms are missing.
+pub fn check(s: &AppState, project: &str, name: &str, mol: &Resolved) -> Result<Option<Coverage>, String> {
+    let Some(ff) = s.force_fields.get(project, name) else { return Ok(None) };
+    let smiles = mol.canonical_smiles.as_deref().ok_or_else(|| format!("{} did not resolve to a structure, so force field {name}'s coverage can't be checked", mol.input))?;
+    let coverage = ff.coverage(&chem::parse_smiles(smiles)?)?;
+    if coverage.missing.is_empty() { return Ok(Some(coverage)); }
+    let more = coverage.missing.len().saturating_sub(MAX_LISTED);
+    let listed = coverage.missing[..coverage.missing.len().min(MAX_LISTED)].join(", ");
+    Err(format!("force field {name} doesn't cover {}: missing {listed}{}", mol.input, if more > 0 { format!(" and {more} more") } else { String::new() }))
+}
+
+pub async fn create(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(mut upload): Json<Upload>) -> Result<(StatusCode, Json<Summary>), ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    if upload.name.trim().is_empty() { return Err(bad("force field name must not be empty".into())); }
+    let params = parse(&mut upload).map_err(bad)?;
+    let project = s.projects.resolve(&headers);
+    let mut fields = s.force_fields.fields.lock().unwrap();
+    let key = (project, upload.name.clone());
+    if fields.contains_key(&key) || BUILTIN.contains(&upload.name.as_str()) { return Err((StatusCode::CONFLICT, Json(ErrorResponse { error: format!("force field {} already exists", upload.name) }))); }
+    upload.created_at_unix = unix_now();
+    let ff = ForceField { upload, params };
+    let summary = ff.summary();
+    fields.insert(key, ff);
+    Ok((StatusCode::CREATED, Json(summary)))
+}
+
+pub async fn list(State(s): State<Arc<AppState>>, headers: HeaderMap) -> Json<ForceFieldsResponse> {
+    let project = projects::project_id(&headers);
+    let force_fields = s.force_fields.fields.lock().unwrap().iter().filter(|((p, _), _)| *p == project).map(|(_, f)| f.summary()).collect();
+    Json(ForceFieldsResponse { project, builtin: BUILTIN.to_vec(), force_fields })
+}
+
+pub async fn get(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(name): Path<String>) -> Result<Json<Summary>, ApiError> {
+    s.force_fields.get(&projects::project_id(&headers), &name).map(|f| Json(f.summary())).ok_or_else(|| not_found("force field", &name))
+}
@@ -0,0 +1,358 @@
+//! Fragment growing: elaborations of a bound fragment into the subpockets next to it.
+//!
+//! The fragment comes as a stored pose from a fragment screen, or as a structure docked into a
+//! pocket of `target_protein`. Its growth vectors are the handles a reaction can build from —
+//! amines, azole NH, carboxylic acids, aryl halides and hydroxyls — each pointing out of the
+//! pose along the bond it would make, and reaching the lining residues ahead of it. Every
+//! building block a handle's reactions accept (amide coupling, reductive amination,
+//! sulfonylation, SNAr, Suzuki and Buchwald–Hartwig couplings, N- and O-alkylation) is joined
+//! on, laid along the vector at the best of a set of spins and minimized against the pocket
+//! lining with the fragment held near its pose. The free energy gained is the change in the
+//! interaction with the lining, the contacts the new atoms make with the subpocket's residues
+//! (salt bridges, hydrogen bonds, π-stacking, cation-π and hydrophobic packing), less the
+//! strain, the rotors frozen and the desolvation of basic amines left unpaired. Elaborations
+//! rank by the grown compound's free energy, each with its ligand efficiency against the
+//! fragment's.
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::{chem::{self, BondKind, Molecule}, conformer, descriptors, filters, fnv1a, forcefield::{self, System}, frame::Frame, not_found, novelty, pockets, poses, projects, record, retro, standardize, usage, vec3::{sub, add, scale, norm, unit, rotate, aligning}, ApiError, AppState, ErrorResponse, DOCK_MODEL};
+
+/// RT at 298 K, kcal/mol.
+const RT: f64 = 0.593;
+/// Reactions: name, the fragment handle they build from and the building blocks they take.
+const REACTIONS: &[(&str, &str, &str)] = &[
+    ("amide_coupling", "amine", "carboxylic acid"),
+    ("reductive_amination", "amine", "aldehyde"),
+    ("sulfonylation", "amine", "sulfonyl chloride"),
+    ("snar", "amine", "heteroaryl halide"),
+    ("amide_coupling", "carboxylic acid", "amine"),
+    ("suzuki", "aryl halide", "boronic acid"),
+    ("buchwald_hartwig", "aryl halide", "amine"),
+    ("n_alkylation", "azole NH", "alkyl halide"),
+    ("o_alkylation", "hydroxyl", "alkyl halide"),
+];
+/// Building blocks: class, name, reagent SMILES, and the group it adds with the atom that
+/// bonds to the fragment first.
+const BUILDING_BLOCKS: &[(&str, &str, &str, &str)] = &[
+    ("carboxylic acid", "benzoic acid", "OC(=O)c1ccccc1", "C(=O)c1ccccc1"),
+    ("carboxylic acid", "isonicotinic acid", "OC(=O)c1ccncc1", "C(=O)c1ccncc1"),
+    ("carboxylic acid", "cyclopropanecarboxylic acid", "OC(=O)C1CC1", "C(=O)C1CC1"),
+    ("carboxylic acid", "4-fluorobenzoic acid", "OC(=O)c1ccc(F)cc1", "C(=O)c1ccc(F)cc1"),
+    ("carboxylic acid", "morpholinoacetic acid", "OC(=O)CN1CCOCC1", "C(=O)CN1CCOCC1"),
+    ("carboxylic acid", "3-hydroxybenzoic acid", "OC(=O)c1cccc(O)c1", "C(=O)c1cccc(O)c1"),
+    ("aldehyde", "benzaldehyde", "O=Cc1ccccc1", "Cc1ccccc1"),
+    ("aldehyde", "nicotinaldehyde", "O=Cc1cccnc1", "Cc1cccnc1"),
+    ("aldehyde", "4-hydroxybenzaldehyde", "O=Cc1ccc(O)cc1", "Cc1ccc(O)cc1"),
+    ("aldehyde", "tetrahydropyran-4-carbaldehyde", "O=CC1CCOCC1", "CC1CCOCC1"),
+    ("sulfonyl chloride", "benzenesulfonyl chloride", "ClS(=O)(=O)c1ccccc1", "S(=O)(=O)c1ccccc1"),
+    ("sulfonyl chloride", "methanesulfonyl chloride", "CS(Cl)(=O)=O", "S(C)(=O)=O"),
+    ("sulfonyl chloride", "tosyl chloride", "Cc1ccc(S(Cl)(=O)=O)cc1", "S(=O)(=O)c1ccc(C)cc1"),
+    ("heteroaryl halide", "2-chloropyrimidine", "Clc1ncccn1", "c1ncccn1"),
+    ("heteroaryl halide", "6-chloronicotinonitrile", "N#Cc1ccc(Cl)nc1", "c1ccc(C#N)cn1"),
+    ("heteroaryl halide", "4-chloroquinazoline", "Clc1ncnc2ccccc12", "c1ncnc2ccccc12"),
+    ("amine", "benzylamine", "NCc1ccccc1", "NCc1ccccc1"),
+    ("amine", "morpholine", "C1COCCN1", "N1CCOCC1"),
+    ("amine", "1-methylpiperazine", "CN1CCNCC1", "N1CCN(C)CC1"),
+    ("amine", "cyclopropylamine", "NC1CC1", "NC1CC1"),
+    ("amine", "ethanolamine", "NCCO", "NCCO"),
+    ("amine", "4-aminopyridine", "Nc1ccncc1", "Nc1ccncc1"),
+    ("boronic acid", "phenylboronic acid", "OB(O)c1ccccc1", "c1ccccc1"),
+    ("boronic acid", "pyridine-4-boronic acid", "OB(O)c1ccncc1", "c1ccncc1"),
+    ("boronic acid", "1-methylpyrazole-4-boronic acid", "Cn1cc(B(O)O)cn1", "c1cnn(C)c1"),
+    ("boronic acid", "4-hydroxyphenylboronic acid", "OB(O)c1ccc(O)cc1", "c1ccc(O)cc1"),
+    ("boronic acid", "4-carbamoylphenylboronic acid", "NC(=O)c1ccc(B(O)O)cc1", "c1ccc(C(N)=O)cc1"),
+    ("boronic acid", "thiophene-2-boronic acid", "OB(O)c1cccs1", "c1cccs1"),
+    ("alkyl halide", "iodomethane", "CI", "C"),
+    ("alkyl halide", "2-bromoacetamide", "NC(=O)CBr", "CC(N)=O"),
+    ("alkyl halide", "benzyl bromide", "BrCc1ccccc1", "Cc1ccccc1"),
+    ("alkyl halide", "4-(2-chloroethyl)morpholine", "ClCCN1CCOCC1", "CCN1CCOCC1"),
+    ("alkyl halide", "(bromomethyl)cyclopropane", "BrCC1CC1", "CC1CC1"),
+];
+const DEFAULT_TOP_N: usize = 10;
+const MAX_TOP_N: usize = 100;
+const DEFAULT_MAX_HEAVY_ATOMS: usize = 30;
+/// Ligand efficiency assumed for a fragment of unknown affinity, kcal/mol per heavy atom.
+const ASSUMED_LE: f64 = 0.3;
+const SPINS: usize = 12;
+const MINIMIZE_STEPS: usize = 200;
+/// How far a growth vector is followed looking for the pocket wall, Å.
+const MAX_ROOM: f64 = 10.0;
+/// A lining atom closer than this to the vector ends its room, Å.
+const WALL: f64 = 3.0;
+/// Vectors with less room than this point into the wall, Å.
+const MIN_ROOM: f64 = 2.0;
+/// New atoms this close to a lining residue contact it, Å.
+const CONTACT: f64 = 5.0;
+/// Residues this close to a growth vector line its subpocket, Å.
+const SUBPOCKET: f64 = 7.0;
+/// Weights on the change in lining interaction energy and on the strain taken up.
+const W_INTERACTION: f64 = 0.25;
+const W_STRAIN: f64 = 0.1;
+/// Conformational entropy per rotor frozen, and desolvation per unpaired basic amine, kcal/mol.
+const ROTOR: f64 = 0.25;
+const DESOLVATION_BASIC: f64 = 0.5;
+
+#[derive(Deserialize)]
+pub struct GrowRequest { fragment: Option<String>, screen_id: Option<String>, compound_id: Option<String>, target_protein: Option<String>, pocket_id: Option<String>, fragment_kd_um: Option<f64>, reactions: Option<Vec<String>>, max_heavy_atoms: Option<usize>, top_n: Option<usize>, format: Option<String>, retrosynthesis: Option<bool>, reference_libraries: Option<Vec<String>>, similarity_threshold: Option<f64> }
+
+#[derive(Serialize)]
+pub struct Fragment { smiles: String, #[serde(skip_serializing_if = "Option::is_none")] compound_id: Option<String>, heavy_atoms: usize, kd_nm: f64, ligand_efficiency: f64, affinity_source: &'static str }
+#[derive(Serialize)]
+pub struct GrowthVector { atom: usize, element: String, handle: &'static str, reactions: Vec<&'static str>, room_angstrom: f64, solvent_exposed: bool, subpocket: Vec<String> }
+#[derive(Serialize)]
+pub struct Elaboration {
+    rank: usize, smiles: String, reaction: &'static str, building_block: &'static str, reagent_smiles: &'static str, growth_atom: usize, #[serde(skip_serializing_if = "Vec::is_empty")] interactions: Vec<String>,
+    delta_g_kcal: f64, ddg_kcal: f64, kd_nm: f64, heavy_atoms: usize, ligand_efficiency: f64, le_change: f64, #[serde(skip_serializing_if = "Option::is_none")] molecular_weight: Option<f64>, clogp: f64, clashes: usize, pose: String,
+    #[serde(skip_serializing_if = "Option::is_none")] synthesis: Option<retro::Feasibility>, #[serde(skip_serializing_if = "Option::is_none")] novelty: Option<novelty::Novelty>,
+}
+#[derive(Serialize)]
+pub struct GrowResponse { growth_id: String, target: String, pocket_id: String, fragment: Fragment, growth_vectors: Vec<GrowthVector>, elaborations_scored: usize, elaborations: Vec<Elaboration>, format: String, #[serde(skip_serializing_if = "Vec::is_empty")] warnings: Vec<String> }
+
+fn round(v: f64) -> f64 { (v * 1000.0).round() / 1000.0 }
+
+fn heavy_atoms(m: &Molecule) -> usize { m.atoms.iter().filter(|a| a.element != "H").count() }
+fn kd_nm(dg: f64) -> f64 { let kd = 1e9 * (dg / RT).exp(); if kd < 1e3 { round(kd) } else { kd.round() } }
+
+/// A reactive handle: the atom the building block bonds to, and the atom that leaves (a
+/// halide, or the acid's OH) when it isn't a hydrogen.
+struct Handle { atom: usize, leaving: Option<usize>, kind: &'static str }
+
+fn handles(m: &Molecule) -> Vec<Handle> {
+    let adj = m.neighbors();
+    let double_o = |i: usize| adj[i].iter().any(|&(j, k)| k == BondKind::Double && m.atoms[j].element == "O");
+    let mut out = Vec::new();
+    for (i, a) in m.atoms.iter().enumerate() {
+        match a.element.as_str() {
+            "N" if a.charge == 0 && a.hydrogens > 0 && a.aromatic => out.push(Handle { atom: i, leaving: None, kind: "azole NH" }),
+            "N" if a.charge == 0 && a.hydrogens > 0 && !adj[i].iter().any(|&(j, _)| matches!(m.atoms[j].element.as_str(), "C" | "S") && double_o(j)) => out.push(Handle { atom: i, leaving: None, kind: "amine" }),
+            "O" if a.charge == 0 && a.hydrogens > 0 => match adj[i].first() {
+                Some(&(c, _)) if m.atoms[c].element == "C" && double_o(c) => out.push(Handle { atom: c, leaving: Some(i), kind: "carboxylic acid" }),
+                Some(&(c, _)) if m.atoms[c].element == "C" => out.push(Handle { atom: i, leaving: None, kind: "hydroxyl" }),
+                _ => {}
+            },
+            "Cl" | "Br" | "I" => if let Some(&(c, _)) = adj[i].first().filter(|&&(c, _)| m.atoms[c].aromatic && m.atoms[c].element == "C") { out.push(Handle { atom: c, leaving: Some(i), kind: "aryl halide" }) },
+            _ => {}
+        }
+    }
+    out
+}
+
+/// The fragment with `group` bonded at the handle, its leaving atom gone; returns the product
+/// and, for each fragment atom, its index in the product. The group's first atom bonds.
+fn join(m: &Molecule, h: &Handle, group: &Molecule) -> (Molecule, Vec<Option<usize>>) {
+    let mut product = Molecule::default();
+    let mut map = vec![None; m.atoms.len()];
+    for (i, a) in m.atoms.iter().enumerate() {
+        if Some(i) == h.leaving { continue; }
+        map[i] = Some(product.atoms.len());
+        let mut a = a.clone();
+        if i == h.atom && h.leaving.is_none() { a.hydrogens = a.hydrogens.saturating_sub(1); }
+        product.atoms.push(a);
+    }
+    product.bonds.extend(m.bonds.iter().filter_map(|b| Some(chem::Bond { a: map[b.a]?, b: map[b.b]?, kind: b.kind })));
+    let offset = product.atoms.len();
+    product.atoms.extend(group.atoms.iter().cloned());
+    product.atoms[offset].hydrogens = product.atoms[offset].hydrogens.saturating_sub(1);
+    product.bonds.extend(group.bonds.iter().map(|b| chem::Bond { a: b.a + offset, b: b.b + offset, kind: b.kind }));
+    product.bonds.push(chem::Bond { a: map[h.atom].unwrap_or_default(), b: offset, kind: BondKind::Single });
+    (product, map)
+}
+
+/// Direction a handle grows along in the pose: toward its leaving atom, or away from its
+/// neighbours.
+fn growth_direction(m: &Molecule, x: &[[f64; 3]], h: &Handle) -> [f64; 3] {
+    if let Some(l) = h.leaving { return unit(sub(x[l], x[h.atom])); }
+    let adj = m.neighbors();
+    if adj[h.atom].is_empty() { return [0.0, 0.0, 1.0]; }
+    let mean = scale(adj[h.atom].iter().fold([0.0; 3], |c, &(j, _)| add(c, x[j])), 1.0 / adj[h.atom].len() as f64);
+    unit(sub(x[h.atom], mean))
+}
+
+/// Basic amines: sp3 nitrogens bonded to neither a carbonyl, a sulfonyl nor an aromatic ring.
+fn basic(m: &Molecule, i: usize, adj: &[Vec<(usize, BondKind)>]) -> bool {
+    let a = &m.atoms[i];
+    a.element == "N" && !a.aromatic && a.charge >= 0 && adj[i].iter().all(|&(j, k)| k == BondKind::Single && !m.atoms[j].aromatic && !adj[j].iter().any(|&(o, kk)| kk == BondKind::Double && m.atoms[o].element == "O"))
+}
+
+/// The best contact each subpocket residue makes with the new atoms, as (free energy, label);
+/// and how many basic amines among them found no acidic partner.
+fn contacts(m: &Molecule, x: &[[f64; 3]], new: &[usize], sites: &[(String, [f64; 3])]) -> (f64, Vec<String>, usize) {
+    let adj = m.neighbors();
+    let (mut energy, mut labels, mut paired) = (0.0, Vec::new(), HashSet::new());
+    for (residue, at) in sites {
+        let near: Vec<usize> = new.iter().copied().filter(|&i| norm(sub(x[i], *at)) < CONTACT).collect();
+        if near.is_empty() { continue; }
+        let name = residue.get(..3).unwrap_or_default();
+        let polar = |i: usize| matches!(m.atoms[i].element.as_str(), "N" | "O");
+        let donor = |i: usize| polar(i) && m.atoms[i].hydrogens > 0;
+        let mut best: Option<(f64, &str, Option<usize>)> = None;
+        for &i in &near {
+            let a = &m.atoms[i];
+            let term = match name {
+                "ASP" | "GLU" if basic(m, i, &adj) => Some((-1.2, "salt bridge", Some(i))),
+                "ASP" | "GLU" if donor(i) => Some((-0.5, "hydrogen bond", None)),
+                "LYS" | "ARG" if basic(m, i, &adj) => Some((0.8, "like charges", None)),
+                "LYS" | "ARG" if polar(i) => Some((-0.4, "hydrogen bond", None)),
+                "PHE" | "TYR" | "TRP" if basic(m, i, &adj) => Some((-0.6, "cation-π", None)),
+                "PHE" | "TYR" | "TRP" | "HIS" if a.aromatic => Some((-0.6, "π-stacking", None)),
+                "SER" | "THR" | "TYR" | "HIS" | "ASN" | "GLN" if polar(i) => Some((-0.5, "hydrogen bond", None)),
+                "LEU" | "VAL" | "ILE" | "MET" | "ALA" | "PHE" if a.element == "C" && !adj[i].iter().any(|&(j, _)| polar(j)) || matches!(a.element.as_str(), "F" | "Cl") => Some((-0.4, "hydrophobic contact", None)),
+                "LEU" | "VAL" | "ILE" | "MET" | "ALA" if basic(m, i, &adj) => Some((0.3, "buried charge", None)),
+                _ => None,
+            };
+            if let Some(t) = term { if best.is_none_or(|b| t.0 < b.0) { best = Some(t); } }
+        }
+        if let Some((e, label, partner)) = best {
+            energy += e;
+            labels.push(format!("{label} {residue}"));
+            paired.extend(partner);
+        }
+    }
+    let unpaired = new.iter().filter(|&&i| basic(m, i, &adj) && !paired.contains(&i)).count();
+    (energy, labels, unpaired)
+}
+
+pub async fn grow(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<GrowRequest>) -> Result<Json<GrowResponse>, ApiError> {
+    let meter = usage::Meter::start();
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let pose = match (&req.screen_id, &req.compound_id, &req.fragment) {
+        (Some(screen_id), Some(compound_id), _) => s.poses.get(&projects::project_id(&headers), screen_id, compound_id).ok_or_else(|| not_found("pose", &format!("{screen_id}/{compound_id}")))?,
+        (_, _, Some(fragment)) => {
+            let target = req.target_protein.as_deref().ok_or_else(|| bad("give target_protein to dock the fragment into".into()))?;
+            let smiles = standardize::resolve(&s, &headers, fragment).await.canonical_smiles.ok_or_else(|| bad(format!("{fragment} did not resolve to a structure")))?;
+            let pocket = pocket_of(target, req.pocket_id.as_deref()).map_err(bad)?;
+            poses::dock(target, Some(&pocket), req.compound_id.as_deref().unwrap_or(fragment), &smiles).ok_or_else(|| bad(format!("can't dock {fragment}")))?
+        }
+        _ => return Err(bad("give fragment and target_protein, or screen_id and compound_id of a stored fragment pose".into())),
+    };
+    let mut resp = run(&req, pose).map_err(bad)?;
+    if req.retrosynthesis != Some(false) {
+        let smiles: Vec<String> = resp.elaborations.iter().map(|e| e.smiles.clone()).collect();
+        let (found, warnings) = s.retro.check(&smiles, retro::DEFAULT_MAX_STEPS).await;
+        for (e, f) in resp.elaborations.iter_mut().zip(found) { e.synthesis = Some(f.summary()); }
+        resp.warnings.extend(warnings);
+    }
+    if let Some(ids) = &req.reference_libraries {
+        let threshold = novelty::threshold(req.similarity_threshold).map_err(bad)?;
+        let index = novelty::load(&s, &headers, ids)?;
+        let smiles: Vec<String> = resp.elaborations.iter().map(|e| e.smiles.clone()).collect();
+        for (e, n) in resp.elaborations.iter_mut().zip(novelty::annotate(&index, &smiles, threshold)) { e.novelty = Some(n); }
+    }
+    record(&s, &headers, "fragment_grow", &resp.fragment.smiles, DOCK_MODEL, &resp.growth_id, &meter, &resp);
+    Ok(Json(resp))
+}
+
+fn pocket_of(target: &str, pocket_id: Option<&str>) -> Result<pockets::Pocket, String> {
+    let found = pockets::detect(target);
+    match pocket_id {
+        Some(id) => found.into_iter().find(|p| p.pocket_id == id).ok_or_else(|| format!("no pocket {id} on {target}")),
+        None => found.into_iter().next().ok_or_else(|| format!("no pocket found on {target}")),
+    }
+}
+
+fn run(req: &GrowRequest, pose: poses::Pose) -> Result<GrowResponse, String> {
+    let top_n = req.top_n.unwrap_or(DEFAULT_TOP_N);
+    if top_n == 0 || top_n > MAX_TOP_N { return Err(format!("top_n must be between 1 and {MAX_TOP_N}")); }
+    let max_heavy = req.max_heavy_atoms.unwrap_or(DEFAULT_MAX_HEAVY_ATOMS);
+    let format = req.format.clone().unwrap_or_else(|| "sdf".into());
+    if !matches!(format.as_str(), "sdf" | "pdb") { return Err(format!("unknown format {format}; expected sdf or pdb")); }
+    if let Some(r) = req.reactions.iter().flatten().find(|r| !REACTIONS.iter().any(|k| k.0 == r.as_str())) {
+        return Err(format!("unknown reaction {r}; expected one of amide_coupling, reductive_amination, sulfonylation, snar, suzuki, buchwald_hartwig, n_alkylation, o_alkylation"));
+    }
+    let allowed = |name: &str| req.reactions.as_ref().is_none_or(|r| r.iter().any(|k| k == name));
+    let pocket_id = if pose.pocket_id.is_empty() { req.pocket_id.as_deref() } else { Some(pose.pocket_id.as_str()) };
+    let pocket = pocket_of(&pose.target, pocket_id)?;
+    let lining = Frame::new(&pockets::lining(&pocket));
+    let sites = pockets::residue_sites(&pocket);
+    let frag = chem::parse_smiles(&pose.smiles)?;
+    let x = pose.coords.clone();
+    let mut warnings = Vec::new();
+
+    // The fragment's binding free energy: its screened affinity, one given, or an assumed
+    // ligand efficiency.
+    let n_frag = heavy_atoms(&frag);
+    let (dg_frag, affinity_source) = match (req.fragment_kd_um, pose.binding_affinity_nm) {
+        (Some(kd), _) if kd > 0.0 => (RT * (kd * 1e-6).ln(), "given"),
+        (Some(_), _) => return Err("fragment_kd_um must be positive".into()),
+        (None, kd) if kd > 0.0 => (RT * (kd * 1e-9).ln(), "screen"),
+        _ => {
+            warnings.push(format!("fragment affinity unknown; assumed a ligand efficiency of {ASSUMED_LE} kcal/mol per heavy atom (give fragment_kd_um)"));
+            (-ASSUMED_LE * n_frag as f64, "assumed")
+        }
+    };
+    let le_frag = -dg_frag / n_frag.max(1) as f64;
+    let e_frag = forcefield::interaction_energy(&lining, &x, None);
+    let rotors_frag = filters::rotatable_bonds(&frag);
+
+    let mut vectors = Vec::new();
+    let mut grown: Vec<(f64, Elaboration)> = Vec::new();
+    let mut seen = HashSet::new();
+    let mut scored = 0;
+    for h in handles(&frag) {
+        let dir = growth_direction(&frag, &x, &h);
+        let room = (1..=(MAX_ROOM * 2.0) as usize).map(|k| k as f64 * 0.5).find(|&t| {
+            let mut hit = false;
+            lining.within(add(x[h.atom], scale(dir, t)), WALL, |_, _, _| hit = true);
+            hit
+        });
+        // The subpocket: residues beside the vector up to a couple of ångströms past the wall.
+        let reach = room.unwrap_or(MAX_ROOM).min(4.0) + 2.0;
+        let subpocket = sites.iter().filter(|(_, at)| (0..=(reach * 2.0) as usize).any(|k| norm(sub(*at, add(x[h.atom], scale(dir, k as f64 * 0.5)))) < SUBPOCKET)).map(|(r, _)| r.clone()).collect();
+        let reactions: Vec<&'static str> = REACTIONS.iter().filter(|r| r.1 == h.kind && allowed(r.0)).map(|r| r.0).collect();
+        vectors.push(GrowthVector { atom: h.atom, element: frag.atoms[h.atom].element.clone(), handle: h.kind, reactions: reactions.clone(), room_angstrom: room.unwrap_or(MAX_ROOM), solvent_exposed: room.is_none(), subpocket });
+        if room.is_some_and(|r| r < MIN_ROOM) {
+            warnings.push(format!("the {} on atom {} points into the pocket wall; not grown", h.kind, h.atom));
+            continue;
+        }
+        for &(reaction, _, class) in REACTIONS.iter().filter(|r| r.1 == h.kind && allowed(r.0)) {
+            for &(_, name, reagent, group) in BUILDING_BLOCKS.iter().filter(|b| b.0 == class) {
+                let group = chem::parse_smiles(group)?;
+                let (product, map) = join(&frag, &h, &group);
+                let smiles = product.to_canonical_smiles();
+                if heavy_atoms(&product) > max_heavy || !seen.insert(smiles.clone()) { continue; }
+                scored += 1;
+                let Some(anchor) = map[h.atom] else { continue };
+                let offset = product.atoms.len() - group.atoms.len();
+                let new: Vec<usize> = (offset..product.atoms.len()).collect();
+                // Lay the group along the growth vector from the product's own conformer,
+                // and keep the spin that sits best in the pocket.
+                let y = conformer::embed(&product, fnv1a(smiles.as_bytes()));
+                let (axis, angle) = aligning(unit(sub(y[offset], y[anchor])), dir);
+                let restraints = conformer::restraints(&product);
+                let place = |spin: f64| -> Vec<[f64; 3]> {
+                    let mut p = vec![[0.0; 3]; product.atoms.len()];
+                    for (i, m) in map.iter().enumerate() { if let Some(j) = m { p[*j] = x[i]; } }
+                    for &g in &new { p[g] = add(x[h.atom], rotate(rotate(sub(y[g], y[anchor]), axis, angle), dir, spin)); }
+                    p
+                };
+                let energy = |p: &[[f64; 3]]| forcefield::interaction_energy(&lining, p, None) + forcefield::internal_energy(&restraints, p, None);
+                let start = (0..SPINS).map(|k| place(std::f64::consts::TAU * k as f64 / SPINS as f64)).min_by(|a, b| energy(a).total_cmp(&energy(b))).unwrap_or_default();
+                let mut xp = start.clone();
+                System { restraints: &restraints, receptor: &lining, metals: None, anchor: &start, k_pos: 0.5, bias: None }.minimize(&mut xp, MINIMIZE_STEPS);
+
+                let strain = (forcefield::internal_energy(&restraints, &xp, None) - forcefield::internal_energy(&restraints, &y, None)).max(0.0);
+                let (contact, interactions, unpaired) = contacts(&product, &xp, &new, &sites);
+                let rotors = filters::rotatable_bonds(&product).saturating_sub(rotors_frag);
+                let ddg = W_INTERACTION * (forcefield::interaction_energy(&lining, &xp, None) - e_frag) + W_STRAIN * strain + contact + ROTOR * rotors as f64 + DESOLVATION_BASIC * unpaired as f64;
+                let dg = dg_frag + ddg;
+                let n = heavy_atoms(&product);
+                let le = -dg / n as f64;
+                let grown_pose = poses::Pose { compound_id: format!("{name} via {reaction}"), smiles: smiles.clone(), target: pose.target.clone(), pocket_id: pocket.pocket_id.clone(), binding_affinity_nm: kd_nm(dg), coords: xp.clone() };
+                let text = if format == "pdb" { poses::to_pdb(&grown_pose, &product) } else { poses::to_sdf(&grown_pose, &product) };
+                grown.push((dg, Elaboration {
+                    rank: 0, smiles, reaction, building_block: name, reagent_smiles: reagent, growth_atom: h.atom, interactions, delta_g_kcal: round(dg), ddg_kcal: round(ddg), kd_nm: kd_nm(dg), heavy_atoms: n,
+                    ligand_efficiency: round(le), le_change: round(le - le_frag), molecular_weight: descriptors::molecular_weight(&product).ok().map(round), clogp: round(filters::logp(&product)), clashes: forcefield::clashes(&lining, &xp), pose: text, synthesis: None, novelty: None,
+                }));
+            }
+        }
+    }
+    if vectors.is_empty() { return Err(format!("{} has no growth handle (amine, azole NH, carboxylic acid, aryl halide or hydroxyl)", pose.smiles)); }
+    grown.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let elaborations = grown.into_iter().take(top_n).enumerate().map(|(k, (_, mut e))| { e.rank = k + 1; e }).collect();
+    let fragment = Fragment { smiles: pose.smiles.clone(), compound_id: (!pose.compound_id.is_empty()).then(|| pose.compound_id.clone()), heavy_atoms: n_frag, kd_nm: kd_nm(dg_frag), ligand_efficiency: round(le_frag), affinity_source };
+    Ok(GrowResponse { growth_id: uuid::Uuid::new_v4().to_string(), target: pose.target, pocket_id: pocket.pocket_id, fragment, growth_vectors: vectors, elaborations_scored: scored, elaborations, format, warnings })
+}
@@ -0,0 +1,67 @@
+//! Screening a target known only by its sequence.
+//!
+//! `POST /api/v1/bio/screen/from-sequence` folds the sequence, detects pockets on the model
+//! and screens the library against its most druggable pocket, all in one call. The model is
+//! named `<target_name>-model-<hash>` (or `model-<hash>`), a stable name derived from the
+//! folded sequence, so the screen's poses, strain and hydration can be looked up later like
+//! any other target's. The prediction and the screen are recorded as separate jobs. Models of
+//! low confidence or with much disorder are screened anyway, with a warning.
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::{fnv1a, pockets, poses, predict::{self, PredictRequest, PredictResponse}, record, run_predict, screen_and_score, screen_candidates, usage, ApiError, AppState, ErrorResponse, ScreenRequest, ScreenResponse, DOCK_MODEL, FOLD_MODEL};
+
+/// Models below this `structure_confidence` are flagged.
+const LOW_CONFIDENCE: f64 = 0.8;
+/// Models with more of the chain disordered than this are flagged.
+const HIGH_DISORDER: f64 = 0.3;
+/// Pockets below this druggability are flagged.
+const LOW_DRUGGABILITY: f64 = 0.5;
+
+#[derive(Deserialize)]
+pub struct FromSequenceRequest { sequence: String, target_name: Option<String>, sequence_type: Option<String>, uniprot_accession: Option<String>, #[serde(default)] screen: Value }
+
+#[derive(Serialize)]
+pub struct FromSequenceResponse { target: String, structure: PredictResponse, pockets: Vec<pockets::Pocket>, pocket_id: Option<String>, screen: ScreenResponse, warnings: Vec<String>, elapsed_us: u128 }
+
+pub async fn screen(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<FromSequenceRequest>) -> Result<Json<FromSequenceResponse>, ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let t = Instant::now();
+    if req.sequence.trim().is_empty() { return Err(bad("sequence must not be empty".into())); }
+    if req.target_name.as_ref().is_some_and(|n| n.trim().is_empty()) { return Err(bad("target_name must not be empty".into())); }
+    // The screen options are /screen's, minus the target, which is the model.
+    let mut options = if req.screen.is_null() { json!({}) } else { req.screen };
+    if !options.is_object() { return Err(bad("screen must be an object of /screen options".into())); }
+    if options.get("target_protein").is_some() { return Err(bad("screen.target_protein is set by the predicted model; use target_name to name it".into())); }
+
+    let sequence = req.sequence.clone();
+    let fold = PredictRequest { sequence: req.sequence, prediction_type: Some("structure".into()), numbering: None, glycans: None, uniprot_accession: req.uniprot_accession, msa: None, sequence_type: req.sequence_type, min_orf_length: None, template: None, template_id: None, template_chain: None };
+    let (fold, gene) = predict::prepare(fold).map_err(bad)?;
+    let folded = fold.sequence.clone();
+    let target = match req.target_name { Some(name) => format!("{}-model-{:08x}", name.trim(), fnv1a(folded.as_bytes()) as u32), None => format!("model-{:08x}", fnv1a(folded.as_bytes()) as u32) };
+    options["target_protein"] = Value::String(target.clone());
+    let screen_req: ScreenRequest = serde_json::from_value(options).map_err(|e| bad(format!("invalid screen options: {e}")))?;
+    screen_candidates(&s, &screen_req).map_err(bad)?;
+    s.plugins.select(screen_req.plugins.as_deref(), screen_req.rank_by.as_deref()).map_err(bad)?;
+
+    let meter = usage::Meter::start();
+    let curated = match &fold.uniprot_accession { Some(acc) => s.uniprot.annotations(acc).await, None => None };
+    let structure = run_predict(&s, fold, gene, curated);
+    record(&s, &headers, "predict", &sequence, FOLD_MODEL, &structure.prediction_id, &meter, &structure);
+
+    let pockets = pockets::detect(&target);
+    let mut warnings = Vec::new();
+    if structure.structure_confidence < LOW_CONFIDENCE { warnings.push(format!("model confidence {:.2} is below {LOW_CONFIDENCE}; pocket geometry may be unreliable", structure.structure_confidence)); }
+    if structure.disorder.disordered_fraction > HIGH_DISORDER { warnings.push(format!("{:.0}% of the chain is predicted disordered; pockets may not form in solution", structure.disorder.disordered_fraction * 100.0)); }
+    if let Some(p) = pockets.first().filter(|p| p.druggability < LOW_DRUGGABILITY) { warnings.push(format!("the best pocket, {}, has druggability {:.2}", p.pocket_id, p.druggability)); }
+
+    let meter = usage::Meter::start();
+    let screen = screen_and_score(&s, screen_req).await.map_err(bad)?;
+    record(&s, &headers, "screen", &screen.target, DOCK_MODEL, &screen.screen_id, &meter, &screen);
+    poses::persist(&s, &headers, &screen);
+    Ok(Json(FromSequenceResponse { target, structure, pocket_id: pockets.first().map(|p| p.pocket_id.clone()), pockets, screen, warnings, elapsed_us: t.elapsed().as_micros() }))
+}
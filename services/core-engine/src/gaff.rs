@@ -0,0 +1,35 @@
+//! `POST /parameterize`; typing and parameters are `bio_engine_core::gaff`.
+//!
+//! `/energy` and `/simulate` evaluate these terms on a generated conformer, hydrogens placed,
+//! and `POST /api/v1/bio/parameterize` lists them.
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+pub use bio_engine_core::gaff::*;
+
+use crate::{charges::{self, Charges}, chem, conformer, fnv1a, standardize, ApiError, AppState, ErrorResponse};
+
+#[derive(Deserialize)]
+pub struct ParameterizeRequest { molecule: String, charge_model: Option<String> }
+
+#[derive(Serialize)]
+pub struct ParameterizeResponse {
+    molecule: String, canonical_smiles: String, #[serde(skip_serializing_if = "Vec::is_empty")] standardization: Vec<String>, charge_model: &'static str, charges: Charges,
+    atoms: Vec<AtomParams>, bonds: Vec<BondParams>, angles: Vec<AngleParams>, dihedrals: Vec<TorsionParams>, impropers: Vec<TorsionParams>, #[serde(skip_serializing_if = "Vec::is_empty")] warnings: Vec<String>,
+}
+
+/// Types a molecule and lists its parameters, hydrogens after the heavy atoms in parent order.
+pub async fn parameterize_molecule(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<ParameterizeRequest>) -> Result<Json<ParameterizeResponse>, ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let mol = standardize::resolve(&s, &headers, &req.molecule).await;
+    let smiles = mol.canonical_smiles.clone().ok_or_else(|| bad(format!("{} did not resolve to a structure", req.molecule)))?;
+    let model = charges::ChargeModel::parse(req.charge_model.as_deref(), "gaff2").map_err(bad)?;
+    charges::prefetch(&s, req.charge_model.as_deref(), std::slice::from_ref(&smiles)).await;
+    let q = charges::assign(&s, &smiles, model).map_err(bad)?;
+    let graph = chem::parse_smiles(&smiles).map_err(bad)?;
+    let (p, _) = evaluate(&graph, &conformer::embed(&graph, fnv1a(smiles.as_bytes()))).map_err(bad)?;
+    let warnings = if p.generic.is_empty() { Vec::new() } else { vec![format!("{} have no GAFF type; generic Lennard-Jones parameters were used", p.generic.join(", "))] };
+    Ok(Json(ParameterizeResponse { molecule: req.molecule, canonical_smiles: smiles, standardization: mol.standardization, charge_model: model.name(), charges: q, atoms: p.atoms, bonds: p.bonds, angles: p.angles, dihedrals: p.dihedrals, impropers: p.impropers, warnings }))
+}
@@ -0,0 +1,51 @@
+//! `POST /helm`; the notation, monomer library and assembly are `bio_engine_core::helm`.
+
+use axum::{http::StatusCode, response::Json};
+use bio_engine_core::conformer;
+use serde::{Deserialize, Serialize};
+
+pub use bio_engine_core::helm::*;
+
+use crate::{descriptors, ApiError, ErrorResponse};
+
+#[derive(Deserialize)]
+pub struct HelmRequest { helm: Option<String>, sequence: Option<String>, sequence_type: Option<String>, #[serde(flatten)] cyclization: Cyclization }
+
+#[derive(Serialize)]
+pub struct PolymerSummary { id: String, #[serde(rename = "type")] kind: &'static str, monomers: usize, #[serde(skip_serializing_if = "String::is_empty")] sequence: String, #[serde(skip_serializing_if = "Vec::is_empty")] non_natural: Vec<String> }
+
+#[derive(Serialize)]
+pub struct HelmResponse { helm: String, polymers: Vec<PolymerSummary>, connections: usize, #[serde(skip_serializing_if = "Option::is_none")] canonical_smiles: Option<String>, #[serde(skip_serializing_if = "Option::is_none")] formula: Option<String>, #[serde(skip_serializing_if = "Option::is_none")] molecular_weight: Option<f64>, atoms: usize, #[serde(skip_serializing_if = "Vec::is_empty")] macrocycles: Vec<usize>, warnings: Vec<String> }
+
+pub async fn helm(Json(req): Json<HelmRequest>) -> Result<Json<HelmResponse>, ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let mut h = match (req.helm, req.sequence) {
+        (Some(text), None) => parse(&text).map_err(bad)?,
+        (None, Some(seq)) => from_sequence(&seq, &req.sequence_type.unwrap_or_else(|| "protein".into()).to_lowercase()).map_err(bad)?,
+        _ => return Err(bad("give either helm or sequence".into())),
+    };
+    let mut warnings = Vec::new();
+    let replaced = cyclize(&mut h, &req.cyclization).map_err(bad)?;
+    if !replaced.is_empty() { warnings.push(format!("staples replaced {} with olefinic residues", replaced.join(", "))); }
+    let polymers = h.polymers.iter().map(|p| {
+        let non_natural = match p.kind {
+            Kind::Peptide => p.units.iter().filter(|u| u.id.chars().count() > 1).map(|u| u.id.clone()).collect(),
+            _ => Vec::new(),
+        };
+        PolymerSummary { id: p.id.clone(), kind: p.kind.name(), monomers: p.units.len(), sequence: p.sequence(), non_natural }
+    }).collect::<Vec<_>>();
+    if h.polymers.iter().any(|p| p.kind == Kind::Peptide && p.units.iter().any(|u| u.id.starts_with('d') && u.id.len() == 2)) {
+        warnings.push("D-amino acids build the same graph as their L forms; stereochemistry isn't kept in the structure".into());
+    }
+    if h.connections.iter().any(|c| c.from.attachment == "pair") { warnings.push("base-pair connections are non-covalent and leave the structure unchanged".into()); }
+    let (canonical_smiles, formula, molecular_weight, atoms, macrocycles) = if h.polymers.iter().any(|p| p.kind == Kind::Blob) {
+        warnings.push("BLOB polymers have no defined structure, so none was built".into());
+        (None, None, None, 0, Vec::new())
+    } else {
+        let mol = molecule(&h).map_err(bad)?;
+        let weight = descriptors::molecular_weight(&mol).map(|w| (w * 1000.0).round() / 1000.0).ok();
+        let rings = conformer::macrocycles(&mol).iter().map(Vec::len).collect();
+        (Some(mol.to_canonical_smiles()), Some(descriptors::formula_string(&descriptors::formula(&mol))), weight, mol.atoms.len(), rings)
+    };
+    Ok(Json(HelmResponse { helm: h.to_string(), polymers, connections: h.connections.len(), canonical_smiles, formula, molecular_weight, atoms, macrocycles, warnings }))
+}
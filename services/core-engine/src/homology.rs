@@ -0,0 +1,329 @@
+//! Template-based (homology) modeling, the `homology` prediction type.
+//!
+//! The query is aligned to a template chain, uploaded as `template` or fetched by `template_id`
+//! from `BIO_PDB_URL` (default the RCSB download service), by affine-gap alignment with
+//! BLOSUM62 and free end gaps; without `template_chain` the best-aligning chain is used.
+//! Aligned residues take the template's coordinates: all atoms where the residue is conserved,
+//! the backbone and Cβ where it is substituted. The variable regions (query insertions, template
+//! residues the query lacks and breaks in the template chain) are cut back two residues each side,
+//! or further until the anchors can be spanned, and rebuilt by the loop modeler (`loops::build`).
+//! Overhangs at the termini have no template and are left out.
+//!
+//! Every query residue gets a reliability in [0, 1]: threaded residues from the similarity of
+//! the alignment around them and the overall identity, rebuilt ones lower and falling with the
+//! loop's length, missing ones 0. The model's B-factors are the reliability × 100, and its mean
+//! is the prediction's `structure_confidence`.
+
+use bio_engine_core::pdbqt;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::{loops::{self, AMINO, THREE_LETTER}, predict::PredictRequest, resolver::percent_encode, vec3::dist, AppState};
+
+pub const MODEL: &str = "homology-threading/0.1";
+const DEFAULT_PDB_URL: &str = "https://files.rcsb.org/download/{id}.pdb";
+/// The most cells aligned per template chain (query length times chain length).
+const MAX_CELLS: usize = 25_000_000;
+const GAP_OPEN: i32 = -11;
+const GAP_EXTEND: i32 = -1;
+/// Threaded residues given back to the loop modeler each side of a variable region, and the most
+/// it is widened to when its anchors are too far apart to span.
+const FLANK: usize = 2;
+const MAX_FLANK: usize = 8;
+const CA_CA: f64 = 3.8;
+/// Consecutive template Cα atoms farther apart than this have residues missing between them.
+const BROKEN: f64 = 4.2;
+/// Residues each side whose alignment counts toward a residue's reliability.
+const WINDOW: usize = 5;
+const MIN_ALIGNED: usize = 10;
+/// Identity below which alignments are unreliable (the twilight zone).
+const TWILIGHT: f64 = 0.3;
+/// BLOSUM62 in `AMINO` order.
+const BLOSUM62: [[i8; 20]; 20] = [
+    [4, -1, -2, -2, 0, -1, -1, 0, -2, -1, -1, -1, -1, -2, -1, 1, 0, -3, -2, 0],
+    [-1, 5, 0, -2, -3, 1, 0, -2, 0, -3, -2, 2, -1, -3, -2, -1, -1, -3, -2, -3],
+    [-2, 0, 6, 1, -3, 0, 0, 0, 1, -3, -3, 0, -2, -3, -2, 1, 0, -4, -2, -3],
+    [-2, -2, 1, 6, -3, 0, 2, -1, -1, -3, -4, -1, -3, -3, -1, 0, -1, -4, -3, -3],
+    [0, -3, -3, -3, 9, -3, -4, -3, -3, -1, -1, -3, -1, -2, -3, -1, -1, -2, -2, -1],
+    [-1, 1, 0, 0, -3, 5, 2, -2, 0, -3, -2, 1, 0, -3, -1, 0, -1, -2, -1, -2],
+    [-1, 0, 0, 2, -4, 2, 5, -2, 0, -3, -3, 1, -2, -3, -1, 0, -1, -3, -2, -2],
+    [0, -2, 0, -1, -3, -2, -2, 6, -2, -4, -4, -2, -3, -3, -2, 0, -2, -2, -3, -3],
+    [-2, 0, 1, -1, -3, 0, 0, -2, 8, -3, -3, -1, -2, -1, -2, -1, -2, -2, 2, -3],
+    [-1, -3, -3, -3, -1, -3, -3, -4, -3, 4, 2, -3, 1, 0, -3, -2, -1, -3, -1, 3],
+    [-1, -2, -3, -4, -1, -2, -3, -4, -3, 2, 4, -2, 2, 0, -3, -2, -1, -2, -1, 1],
+    [-1, 2, 0, -1, -3, 1, 1, -2, -1, -3, -2, 5, -1, -3, -1, 0, -1, -3, -2, -2],
+    [-1, -1, -2, -3, -1, 0, -2, -3, -2, 1, 2, -1, 5, 0, -2, -1, -1, -1, -1, 1],
+    [-2, -3, -3, -3, -2, -3, -3, -3, -1, 0, 0, -3, 0, 6, -4, -2, -2, 1, 3, -1],
+    [-1, -2, -2, -1, -3, -1, -1, -2, -2, -3, -3, -1, -2, -4, 7, -1, -1, -4, -3, -2],
+    [1, -1, 1, 0, -1, 0, 0, 0, -1, -2, -2, 0, -1, -2, -1, 4, 1, -3, -2, -2],
+    [0, -1, 0, -1, -1, -1, -1, -2, -2, -1, -1, -1, -1, -2, -1, 1, 5, -2, -2, 0],
+    [-3, -3, -4, -4, -2, -2, -3, -2, -2, -3, -2, -3, -1, 1, -4, -3, -2, 11, 2, -3],
+    [-2, -2, -2, -3, -2, -1, -2, -3, 2, -1, -1, -2, -1, 3, -3, -2, -2, 2, 7, -1],
+    [0, -3, -3, -3, -1, -2, -2, -3, -3, 3, 1, -2, 1, -1, -2, -2, 0, -3, -1, 4],
+];
+
+fn round(v: f64) -> f64 { (v * 1000.0).round() / 1000.0 }
+
+fn blosum(a: u8, b: u8) -> i32 {
+    match (AMINO.iter().position(|&x| x == a), AMINO.iter().position(|&x| x == b)) { (Some(i), Some(j)) => BLOSUM62[i][j] as i32, _ => -1 }
+}
+
+/// Template structures fetched by PDB ID, cached; `None` when one can't be fetched.
+pub struct Templates { client: reqwest::Client, url: String, cache: Mutex<HashMap<String, Option<String>>> }
+
+impl Templates {
+    pub fn new(url: Option<String>) -> Self { Self { client: reqwest::Client::new(), url: url.unwrap_or_else(|| DEFAULT_PDB_URL.into()), cache: Mutex::new(HashMap::new()) } }
+
+    pub async fn fetch(&self, id: &str) -> Option<String> {
+        if let Some(hit) = self.cache.lock().unwrap().get(id).cloned() { return hit; }
+        let url = self.url.replace("{id}", &percent_encode(id));
+        let fetched = async {
+            let resp = self.client.get(&url).timeout(std::time::Duration::from_secs(10)).send().await.ok()?.error_for_status().ok()?;
+            resp.text().await.ok()
+        }.await;
+        self.cache.lock().unwrap().insert(id.to_string(), fetched.clone());
+        fetched
+    }
+}
+
+#[derive(Serialize)]
+pub struct AlignmentReport { identity: f64, similarity: f64, coverage: f64, aligned: usize, query: String, template: String }
+/// A variable region: `start` and `end` are 1-based query positions.
+#[derive(Serialize)]
+pub struct Region {
+    kind: &'static str, start: usize, end: usize, length: usize, sequence: String, modeled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")] closure_angstrom: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] reason: Option<String>,
+}
+#[derive(Serialize)]
+pub struct HomologyModel {
+    template: String, template_chain: String, template_residues: usize, alignment: AlignmentReport, residues_threaded: usize, residues_rebuilt: usize, residues_missing: usize,
+    pub confidence: f64, regions: Vec<Region>, reliability: Vec<f64>, #[serde(skip_serializing_if = "Vec::is_empty")] warnings: Vec<String>, pdb: String,
+}
+
+/// An alignment column: (query index, template index), a gap as `None`.
+pub type Column = (Option<usize>, Option<usize>);
+
+/// Alignment columns and the score. Gaps before and after either sequence are free, so a domain
+/// template aligns to part of a query.
+pub fn align(query: &[u8], template: &[u8]) -> (Vec<Column>, i32) {
+    const NONE: i32 = i32::MIN / 4;
+    let (n, m) = (query.len(), template.len());
+    // Scores of the three states (aligned, query against a gap, template against a gap) for
+    // the previous and current row; each cell's predecessors packed two bits a state.
+    let mut prev = vec![[NONE; 3]; m + 1];
+    let mut trace = vec![0u8; (n + 1) * (m + 1)];
+    for cell in prev.iter_mut().skip(1) { *cell = [NONE, NONE, 0]; }
+    prev[0] = [0, NONE, NONE];
+    let best_of = |c: [i32; 3]| (0..3).max_by_key(|&k| c[k]).unwrap_or(0);
+    let mut best = (NONE, n, m, 0);
+    for i in 1..=n {
+        let mut row = vec![[NONE; 3]; m + 1];
+        row[0] = [NONE, 0, NONE];
+        for j in 1..=m {
+            let diag = prev[j - 1];
+            let from_m = best_of(diag);
+            let up = prev[j];
+            let x = [up[0] + GAP_OPEN, up[1] + GAP_EXTEND, up[2] + GAP_OPEN];
+            let from_x = best_of(x);
+            let left = row[j - 1];
+            let y = [left[0] + GAP_OPEN, left[1] + GAP_OPEN, left[2] + GAP_EXTEND];
+            let from_y = best_of(y);
+            row[j] = [diag[from_m] + blosum(query[i - 1], template[j - 1]), x[from_x], y[from_y]];
+            trace[i * (m + 1) + j] = from_m as u8 | (from_x as u8) << 2 | (from_y as u8) << 4;
+            if i == n || j == m {
+                let k = best_of(row[j]);
+                if row[j][k] > best.0 { best = (row[j][k], i, j, k); }
+            }
+        }
+        prev = row;
+    }
+    let (score, mut i, mut j, mut state) = best;
+    let mut columns: Vec<Column> = (i..n).rev().map(|q| (Some(q), None)).chain((j..m).rev().map(|t| (None, Some(t)))).collect();
+    while i > 0 && j > 0 {
+        let from = (trace[i * (m + 1) + j] >> (2 * state) & 3) as usize;
+        match state {
+            0 => { columns.push((Some(i - 1), Some(j - 1))); i -= 1; j -= 1; }
+            1 => { columns.push((Some(i - 1), None)); i -= 1; }
+            _ => { columns.push((None, Some(j - 1))); j -= 1; }
+        }
+        state = from;
+    }
+    columns.extend((0..i).rev().map(|q| (Some(q), None)));
+    columns.extend((0..j).rev().map(|t| (None, Some(t))));
+    columns.reverse();
+    (columns, score)
+}
+
+/// One atom line; `b` is the residue's B-factor.
+fn atom_line(name: &str, element: &str, residue: &str, number: usize, p: [f64; 3], b: f64) -> String {
+    let name = if name.len() < 4 { format!(" {name}") } else { name.to_string() };
+    format!("ATOM  {:>5} {name:<4} {residue:>3} A{number:>4}    {:>8.3}{:>8.3}{:>8.3}{:>6.2}{:>6.2}          {element:>2}", 0, p[0], p[1], p[2], 1.0, b)
+}
+
+/// A PDB ID, upper-cased: a digit from 1 to 9 and three letters or digits.
+pub fn pdb_id(id: &str) -> Result<String, String> {
+    let id = id.trim().to_uppercase();
+    let b = id.as_bytes();
+    if b.len() != 4 || !b[0].is_ascii_digit() || b[0] == b'0' || !b.iter().all(u8::is_ascii_alphanumeric) { return Err(format!("{id} is not a PDB ID")); }
+    Ok(id)
+}
+
+/// The homology model of a `homology` request, fetching its template when given by ID; `None`
+/// for other prediction types.
+pub async fn for_request(s: &AppState, req: &PredictRequest) -> Result<Option<HomologyModel>, String> {
+    if req.prediction_type.as_deref() != Some("homology") { return Ok(None); }
+    let id = req.template_id.as_deref().map(pdb_id).transpose()?;
+    let text = match (&req.template, &id) {
+        (Some(text), _) => text.clone(),
+        (None, Some(id)) => s.templates.fetch(id).await.ok_or_else(|| format!("template {id} could not be fetched from the PDB; upload it as template"))?,
+        (None, None) => return Err("homology modeling needs a template structure (template) or PDB ID (template_id)".into()),
+    };
+    let (sequence, name, chain) = (req.sequence.clone(), id.unwrap_or_else(|| "uploaded".into()), req.template_chain.clone());
+    tokio::task::spawn_blocking(move || build(&sequence, &text, name, chain.as_deref())).await.map_err(|e| e.to_string())?.map(Some)
+}
+
+pub fn build(sequence: &str, template: &str, name: String, chain: Option<&str>) -> Result<HomologyModel, String> {
+    let query: Vec<u8> = sequence.bytes().filter(|c| !c.is_ascii_whitespace()).map(|c| c.to_ascii_uppercase()).collect();
+    let n = query.len();
+    let residues = pdbqt::parse_residues(template, false);
+    // Polymer residues with a Cα, by chain in file order.
+    let mut chains: Vec<(char, Vec<&pdbqt::Residue>)> = Vec::new();
+    for r in residues.iter().filter(|r| r.atoms.iter().any(|a| a.0.trim() == "CA") && (!r.hetero || loops::one_letter(&r.name) != 'X')) {
+        match chains.iter_mut().find(|c| c.0 == r.chain) { Some(c) => c.1.push(r), None => chains.push((r.chain, vec![r])) }
+    }
+    if let Some(c) = chain {
+        let id = c.chars().next().filter(|_| c.chars().count() == 1).ok_or_else(|| format!("template_chain {c} is not a one-character chain ID"))?;
+        chains.retain(|ch| ch.0 == id);
+        if chains.is_empty() { return Err(format!("the template has no protein chain {id}")); }
+    }
+    if chains.is_empty() { return Err("the template has no protein residues".into()); }
+    let aligned = chains.into_iter().filter(|(_, r)| n * r.len() <= MAX_CELLS).map(|(id, r)| {
+        let seq: Vec<u8> = r.iter().map(|r| loops::one_letter(&r.name) as u8).collect();
+        let (columns, score) = align(&query, &seq);
+        (id, r, seq, columns, score)
+    }).max_by_key(|a| a.4);
+    let Some((chain_id, tres, tseq, columns, _)) = aligned else { return Err(format!("every template chain is too long to align to {n} residues")) };
+
+    let mut to_template: Vec<Option<usize>> = vec![None; n];
+    for &(q, t) in &columns { if let (Some(q), Some(t)) = (q, t) { to_template[q] = Some(t); } }
+    let pairs: Vec<(usize, usize)> = to_template.iter().enumerate().filter_map(|(q, t)| t.map(|t| (q, t))).collect();
+    if pairs.len() < MIN_ALIGNED { return Err(format!("only {} query residues align to template chain {chain_id}", pairs.len())); }
+    let identical = pairs.iter().filter(|&&(q, t)| query[q] == tseq[t]).count();
+    let similar = pairs.iter().filter(|&&(q, t)| blosum(query[q], tseq[t]) > 0).count();
+    let identity = identical as f64 / pairs.len() as f64;
+    let mut warnings = Vec::new();
+    if identity < TWILIGHT { warnings.push(format!("{:.0}% sequence identity is in the twilight zone; the alignment, and so the model, may be wrong", identity * 100.0)); }
+    let alignment = AlignmentReport {
+        identity: round(identity), similarity: round(similar as f64 / pairs.len() as f64), coverage: round(pairs.len() as f64 / n as f64), aligned: pairs.len(), query: columns.iter().map(|c| c.0.map_or('-', |q| query[q] as char)).collect(),
+        template: columns.iter().map(|c| c.1.map_or('-', |t| tseq[t] as char)).collect(),
+    };
+
+    // Variable residues and why: 1 query insertion, 2 template residues skipped, 3 template break.
+    let ca = |t: usize| tres[t].atoms.iter().find(|a| a.0.trim() == "CA").map_or([0.0; 3], |a| a.2);
+    let mut cause = vec![0u8; n];
+    for (q, t) in to_template.iter().enumerate() { if t.is_none() { cause[q] = 1; } }
+    for w in pairs.windows(2) {
+        let ((q, t), (q2, t2)) = (w[0], w[1]);
+        let why = if t2 != t + 1 { 2 } else if dist(ca(t), ca(t2)) > BROKEN { 3 } else { continue };
+        for c in [q, q2] { if cause[c] == 0 { cause[c] = why; } }
+    }
+    let (first, last) = (pairs[0].0, pairs[pairs.len() - 1].0);
+    let runs = |variable: &[bool]| {
+        let mut out = Vec::new();
+        let mut q = 0;
+        while q < n {
+            if !variable[q] { q += 1; continue; }
+            let start = q;
+            while q < n && variable[q] { q += 1; }
+            out.push((start, q));
+        }
+        out
+    };
+    let mut variable: Vec<bool> = cause.iter().map(|&c| c != 0).collect();
+    // Internal regions are widened by the flank, then further until their anchors can be spanned.
+    for (start, end) in runs(&variable).into_iter().filter(|&(s, e)| s > first && e <= last) {
+        for v in &mut variable[start.saturating_sub(FLANK).max(first + 1)..(end + FLANK).min(last)] { *v = true; }
+    }
+    for _ in FLANK..MAX_FLANK {
+        let mut widened = false;
+        for (start, end) in runs(&variable).into_iter().filter(|&(s, e)| s > first && e <= last) {
+            let (Some(a), Some(b)) = (to_template[start - 1], to_template[end]) else { continue };
+            if dist(ca(a), ca(b)) <= CA_CA * (end - start + 1) as f64 * 0.95 { continue; }
+            if start - 1 > first { variable[start - 1] = true; widened = true; }
+            if end < last { variable[end] = true; widened = true; }
+        }
+        if !widened { break; }
+    }
+
+    // The threaded residues, numbered by query position.
+    let mut local = vec![0.0; n];
+    for (q, t) in to_template.iter().enumerate() {
+        local[q] = t.map_or(0.0, |t| if query[q] == tseq[t] { 1.0 } else if blosum(query[q], tseq[t]) > 0 { 0.5 } else { 0.0 });
+    }
+    let global = ((identity - 0.2) / 0.4).clamp(0.0, 1.0);
+    let mut reliability: Vec<f64> = (0..n).map(|q| {
+        let window = &local[q.saturating_sub(WINDOW)..(q + WINDOW + 1).min(n)];
+        0.35 + 0.4 * window.iter().sum::<f64>() / window.len() as f64 + 0.25 * global
+    }).collect();
+    let mut lines = Vec::new();
+    let mut threaded = 0;
+    for q in (0..n).filter(|&q| !variable[q]) {
+        let Some(t) = to_template[q] else { continue };
+        let r = tres[t];
+        let three = AMINO.iter().position(|&a| a == query[q]).map_or("UNK", |i| THREE_LETTER[i]);
+        let b = reliability[q] * 100.0;
+        let atom = |name: &str| r.atoms.iter().find(|a| a.0.trim() == name).map(|a| a.2);
+        if query[q] == tseq[t] {
+            for (raw, element, p) in &r.atoms {
+                let (atom_name, element) = if r.name == "MSE" && raw.trim() == "SE" { ("SD", "S") } else { (raw.trim(), element.as_str()) };
+                if atom_name != "OXT" { lines.push(atom_line(atom_name, element, three, q + 1, *p, b)); }
+            }
+        } else {
+            for name in ["N", "CA", "C", "O"] { if let Some(p) = atom(name) { lines.push(atom_line(name, &name[..1], three, q + 1, p, b)); } }
+            if query[q] != b'G' {
+                let cb = atom("CB").or_else(|| Some(loops::beta(atom("N")?, atom("CA")?, atom("C")?)));
+                if let Some(p) = cb { lines.push(atom_line("CB", "C", three, q + 1, p, b)); }
+            }
+        }
+        threaded += 1;
+    }
+    lines.push("END".into());
+
+    // The loop modeler finds the same runs (the threaded chain is numbered by query position) and
+    // reports them in order.
+    let rebuilt = loops::build(loops::LoopRequest { pdb: lines.join("\n"), sequences: Some(HashMap::from([("A".to_string(), String::from_utf8_lossy(&query).into_owned())])), method: None, samples: None, max_loop_length: None })?;
+    warnings.extend(rebuilt.warnings);
+    let spans = runs(&variable);
+    if spans.len() != rebuilt.loops.len() { return Err(format!("{} variable regions but {} loops found", spans.len(), rebuilt.loops.len())); }
+    let mut regions = Vec::new();
+    let mut built = vec![false; n];
+    for (&(start, end), l) in spans.iter().zip(&rebuilt.loops) {
+        let kind = if end <= first { "n_terminal" } else if start > last { "c_terminal" } else { match cause[start..end].iter().copied().filter(|&c| c != 0).min() { Some(1) => "insertion", Some(2) => "deletion", _ => "chain_break" } };
+        let k = end - start;
+        for q in start..end {
+            reliability[q] = match l.closure_angstrom.filter(|_| l.modeled) { Some(c) => 0.5 * 6.0 / (6.0 + k as f64) * (1.0 - c).max(0.0), None => 0.0 };
+            built[q] = l.modeled;
+        }
+        regions.push(Region {
+            kind, start: start + 1, end, length: k, sequence: String::from_utf8_lossy(&query[start..end]).into_owned(), modeled: l.modeled, closure_angstrom: l.closure_angstrom,
+            reason: l.reason.clone(),
+        });
+    }
+    let reliability: Vec<f64> = reliability.iter().map(|&r| (r.min(1.0) * 100.0).round() / 100.0).collect();
+    let residues_rebuilt = built.iter().filter(|&&b| b).count();
+    // Rebuilt residues get their reliability as B-factor; the loop modeler's note on it goes.
+    let pdb = rebuilt.pdb.lines().filter(|l| !l.starts_with("REMARK 999 REBUILT")).map(|l| {
+        let q = l.get(22..26).and_then(|v| v.trim().parse::<usize>().ok()).filter(|_| l.starts_with("ATOM"));
+        match q.filter(|&q| q >= 1 && q <= n && built[q - 1]) { Some(q) if l.len() >= 66 => format!("{}{:>6.2}{}", &l[..60], reliability[q - 1] * 100.0, &l[66..]), _ => l.to_string() }
+    });
+    let header = [
+        format!("REMARK 999 HOMOLOGY MODEL ON TEMPLATE {name} CHAIN {chain_id}, {:.0}% IDENTITY OVER {} RESIDUES", identity * 100.0, pairs.len()),
+        "REMARK 999 B-FACTOR IS PER-RESIDUE RELIABILITY X 100, ON THE PLDDT SCALE; REBUILT RESIDUES HAVE OCCUPANCY 0.00".into(),
+    ];
+    let pdb = header.into_iter().chain(pdb).collect::<Vec<_>>().join("\n") + "\n";
+    Ok(HomologyModel {
+        template: name, template_chain: chain_id.to_string(), template_residues: tres.len(), alignment, residues_threaded: threaded, residues_rebuilt, residues_missing: n - threaded - residues_rebuilt,
+        confidence: round(reliability.iter().sum::<f64>() / n as f64), regions, reliability, warnings, pdb,
+    })
+}
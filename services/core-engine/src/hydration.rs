@@ -0,0 +1,20 @@
+//! `GET /hydration`; site placement and scoring are `bio_engine_core::hydration`.
+
+use axum::{extract::Query, http::StatusCode, response::Json};
+use serde::{Deserialize, Serialize};
+
+pub use bio_engine_core::hydration::*;
+
+use crate::{pockets, ApiError, ErrorResponse};
+
+#[derive(Deserialize)]
+pub struct HydrationQuery { target: String, pocket: Option<String> }
+#[derive(Serialize)]
+pub struct HydrationResponse { target: String, pocket_id: String, sites: Vec<HydrationSite> }
+
+pub async fn report(Query(q): Query<HydrationQuery>) -> Result<Json<HydrationResponse>, ApiError> {
+    let pockets = pockets::detect(&q.target);
+    let pocket = match &q.pocket { Some(id) => pockets.into_iter().find(|p| &p.pocket_id == id), None => pockets.into_iter().next() };
+    let pocket = pocket.ok_or_else(|| (StatusCode::NOT_FOUND, Json(ErrorResponse { error: format!("pocket {} not found on {}", q.pocket.as_deref().unwrap_or("P1"), q.target) })))?;
+    Ok(Json(HydrationResponse { target: q.target, pocket_id: pocket.pocket_id.clone(), sites: sites(&pocket) }))
+}
@@ -0,0 +1,132 @@
+//! Job records for every compute request.
+//!
+//! Compute endpoints are synchronous today, but each call is still recorded as a job owned
+//! by the caller's project so its result can be fetched again later. A job started with
+//! `depends_on` (see `chain`) lists the jobs whose results it built on. Jobs can be deleted,
+//! or expire (see `retention`); a removed job's resource usage is kept for the usage report.
+//! Archiving a project moves its jobs' results to cold storage and marks the jobs `archived`.
+//! Each job carries the signed provenance record of its result (see `provenance`), and the
+//! request parameters that record hashed unless they are over 64 KiB.
+
+use axum::{extract::{Path, Query, State}, http::HeaderMap, response::Json};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::{projects, provenance::Provenance, retention::{self, Held}, usage::Resources, ApiError, AppState};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Job { pub job_id: String, pub project: String, pub kind: String, pub subject: String, pub model: String, #[serde(default, skip_serializing_if = "Vec::is_empty")] pub depends_on: Vec<String>, pub status: String, pub created_at_unix: u64, #[serde(default, skip_serializing_if = "serde_json::Value::is_null")] pub parameters: serde_json::Value, pub resources: Resources, pub result: serde_json::Value, #[serde(default, skip_serializing_if = "Option::is_none")] pub provenance: Option<Provenance>, #[serde(default, skip_serializing_if = "std::ops::Not::not")] pub archived: bool, #[serde(skip)] pub stored_bytes: u64 }
+#[derive(Serialize)]
+pub struct JobSummary { job_id: String, kind: String, subject: String, #[serde(skip_serializing_if = "Vec::is_empty")] depends_on: Vec<String>, status: String, created_at_unix: u64 }
+
+#[derive(Deserialize)]
+pub struct JobQuery { kind: Option<String>, limit: Option<usize> }
+#[derive(Serialize)]
+pub struct JobsResponse { project: String, total: usize, jobs: Vec<JobSummary> }
+
+/// What the usage report needs of a job, kept after the job itself is removed.
+#[derive(Clone)]
+pub struct Billed { pub kind: String, pub created_at_unix: u64, pub resources: Resources }
+
+/// Resources per unit of work measured over recent jobs of a kind, and the largest peak memory among them.
+#[derive(Clone, Copy, Default)]
+pub struct Throughput { pub jobs: usize, pub cpu_per_unit: f64, pub gpu_per_unit: f64, pub wall_per_unit: f64, pub wall_per_job: f64, pub peak_memory_bytes: u64 }
+
+pub struct JobStore { jobs: Mutex<Vec<Job>>, removed: Mutex<Vec<(String, Billed)>> }
+
+impl JobStore {
+    pub fn new() -> Self { Self { jobs: Mutex::new(Vec::new()), removed: Mutex::new(Vec::new()) } }
+    pub fn insert(&self, mut job: Job) {
+        job.stored_bytes = serde_json::to_vec(&job.result).map_or(0, |v| v.len() as u64);
+        self.jobs.lock().unwrap().push(job);
+    }
+    pub fn for_project(&self, project: &str) -> Vec<Job> { self.jobs.lock().unwrap().iter().filter(|j| j.project == project).cloned().collect() }
+    pub fn contains(&self, project: &str, id: &str) -> bool { self.jobs.lock().unwrap().iter().any(|j| j.job_id == id && j.project == project) }
+    pub fn count(&self, project: &str) -> usize { self.jobs.lock().unwrap().iter().filter(|j| j.project == project).count() }
+    /// Looks up a job, hiding jobs that belong to other projects.
+    pub fn get(&self, project: &str, id: &str) -> Option<Job> { self.jobs.lock().unwrap().iter().find(|j| j.job_id == id && j.project == project).cloned() }
+    pub fn held(&self, project: &str) -> Held {
+        let mut held = Held::default();
+        self.jobs.lock().unwrap().iter().filter(|j| j.project == project).for_each(|j| held.add(Held::one(j.stored_bytes)));
+        held
+    }
+    /// Every job of the project, removed ones included, for billing.
+    pub fn billing(&self, project: &str) -> Vec<Billed> {
+        let live: Vec<Billed> = self.jobs.lock().unwrap().iter().filter(|j| j.project == project).map(|j| Billed { kind: j.kind.clone(), created_at_unix: j.created_at_unix, resources: j.resources }).collect();
+        let removed = self.removed.lock().unwrap();
+        removed.iter().filter(|(p, _)| p == project).map(|(_, b)| b.clone()).chain(live).collect()
+    }
+    /// Removes the jobs `keep` rejects, keeping their usage.
+    fn remove(&self, keep: impl Fn(&Job) -> bool) -> Held {
+        let mut freed = Held::default();
+        let mut removed = Vec::new();
+        self.jobs.lock().unwrap().retain(|j| keep(j) || {
+            freed.add(Held::one(j.stored_bytes));
+            removed.push((j.project.clone(), Billed { kind: j.kind.clone(), created_at_unix: j.created_at_unix, resources: j.resources }));
+            false
+        });
+        self.removed.lock().unwrap().extend(removed);
+        freed
+    }
+    /// Takes the results of the project's jobs, leaving the jobs marked archived.
+    pub fn take_results(&self, project: &str) -> Vec<(String, serde_json::Value)> {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.iter_mut().filter(|j| j.project == project && !j.archived).map(|j| {
+            j.archived = true;
+            j.stored_bytes = 0;
+            (j.job_id.clone(), std::mem::take(&mut j.result))
+        }).collect()
+    }
+    /// Puts back results taken by `take_results`.
+    pub fn put_results(&self, project: &str, mut results: HashMap<String, serde_json::Value>) {
+        for j in self.jobs.lock().unwrap().iter_mut().filter(|j| j.project == project) {
+            if let Some(r) = results.remove(&j.job_id) {
+                j.stored_bytes = serde_json::to_vec(&r).map_or(0, |v| v.len() as u64);
+                j.result = r;
+                j.archived = false;
+            }
+        }
+    }
+    /// Removes jobs created before `before`.
+    pub fn expire(&self, before: u64) -> Held { self.remove(|j| j.created_at_unix >= before) }
+    /// Throughput per unit of work (as `units` reads it off a result) over the last `last` jobs of `kind`, across projects.
+    pub fn throughput(&self, kind: &str, last: usize, units: impl Fn(&serde_json::Value) -> Option<f64>) -> Option<Throughput> {
+        let jobs = self.jobs.lock().unwrap();
+        let measured = jobs.iter().rev().filter(|j| j.kind == kind).filter_map(|j| Some((j.resources, units(&j.result).filter(|&u| u > 0.0)?))).take(last);
+        // Sums until divided by the work done.
+        let (mut total, mut work) = (Throughput::default(), 0.0);
+        for (r, u) in measured {
+            total.jobs += 1; total.cpu_per_unit += r.cpu_seconds; total.gpu_per_unit += r.gpu_seconds; total.wall_per_unit += r.wall_seconds;
+            total.peak_memory_bytes = total.peak_memory_bytes.max(r.peak_memory_bytes);
+            work += u;
+        }
+        (work > 0.0).then(|| Throughput { wall_per_job: total.wall_per_unit / total.jobs as f64, cpu_per_unit: total.cpu_per_unit / work, gpu_per_unit: total.gpu_per_unit / work, wall_per_unit: total.wall_per_unit / work, ..total })
+    }
+    /// Mean CPU time of the last `last` jobs of `kind`, across projects.
+    pub fn mean_cpu_seconds(&self, kind: &str, last: usize) -> Option<f64> {
+        let jobs = self.jobs.lock().unwrap();
+        let cpu: Vec<f64> = jobs.iter().rev().filter(|j| j.kind == kind).take(last).map(|j| j.resources.cpu_seconds).collect();
+        (!cpu.is_empty()).then(|| cpu.iter().sum::<f64>() / cpu.len() as f64)
+    }
+}
+
+pub async fn list(State(s): State<Arc<AppState>>, headers: HeaderMap, Query(q): Query<JobQuery>) -> Json<JobsResponse> {
+    let project = projects::project_id(&headers);
+    let jobs = s.jobs.jobs.lock().unwrap();
+    let matched: Vec<&Job> = jobs.iter().filter(|j| j.project == project && q.kind.as_ref().is_none_or(|k| &j.kind == k)).collect();
+    let total = matched.len();
+    let jobs = matched.into_iter().rev().take(q.limit.unwrap_or(100)).map(|j| JobSummary { job_id: j.job_id.clone(), kind: j.kind.clone(), subject: j.subject.clone(), depends_on: j.depends_on.clone(), status: j.status.clone(), created_at_unix: j.created_at_unix }).collect();
+    Json(JobsResponse { project, total, jobs })
+}
+
+pub async fn get(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(id): Path<String>) -> Result<Json<Job>, ApiError> {
+    s.jobs.get(&projects::project_id(&headers), &id).map(Json).ok_or_else(|| crate::not_found("job", &id))
+}
+
+pub async fn delete(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(id): Path<String>) -> Result<Json<retention::Deleted>, ApiError> {
+    let project = projects::project_id(&headers);
+    let freed = s.jobs.remove(|j| j.job_id != id || j.project != project);
+    if freed.items == 0 { return Err(crate::not_found("job", &id)); }
+    Ok(retention::deleted(id, freed))
+}
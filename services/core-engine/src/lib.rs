@@ -0,0 +1,592 @@
+use axum::{extract::State, http::{header, HeaderMap, StatusCode}, response::{IntoResponse, Json, Response}, routing::{delete, get, post, put}, Router};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tower_http::cors::{Any, CorsLayer};
+use tower_http::trace::TraceLayer;
+
+use bio_engine_core::{antibody, chem, cluster, cofactors, composition, conformer, cv, filters, fingerprint, forcefield, frame, gene, library, observables, pockets, predict, restraints, schedule, selection, selectivity, stages, topology, umbrella, rng, vec3, fnv1a};
+
+mod admission;
+mod alerts;
+mod allosteric;
+mod alphafold;
+mod assembly;
+mod audit;
+mod autoscale;
+mod benchmark;
+mod bioisosteres;
+mod bundle;
+mod chain;
+mod charges;
+pub mod cli;
+mod coldstore;
+mod compare;
+mod convert;
+mod cosolvent;
+mod decompose;
+mod depict;
+mod descriptors;
+mod digest;
+mod doseresponse;
+mod druggability;
+mod dryrun;
+mod eln;
+mod epitope;
+mod estimate;
+mod events;
+mod forcefields;
+mod fragments;
+mod fromsequence;
+mod gaff;
+mod helm;
+mod homology;
+mod hydration;
+mod jobs;
+mod libraries;
+mod loops;
+mod metad;
+mod nmr;
+mod notify;
+mod novelty;
+mod pdbqt;
+mod peptides;
+mod pipelines;
+mod plugins;
+mod pools;
+mod poses;
+mod projects;
+mod properties;
+mod provenance;
+mod protocols;
+mod ptm;
+mod qm;
+mod qmmm;
+mod receptor;
+mod refine;
+mod registry;
+mod reports;
+mod resolver;
+mod restriction;
+mod retention;
+mod retro;
+mod sar;
+mod script;
+mod stability;
+mod standardize;
+mod stats;
+mod stdio;
+mod strain;
+mod superpose;
+mod sweeps;
+mod ternary;
+mod torsion;
+mod usage;
+mod validation;
+
+struct AppState { start_time: Instant, stats: stats::Stats, audit: audit::AuditLog, projects: projects::ProjectRegistry, jobs: jobs::JobStore, protocols: protocols::ProtocolStore, force_fields: forcefields::ForceFieldStore, pipelines: pipelines::PipelineStore, sweeps: sweeps::SweepStore, resolver: resolver::Resolver, poses: poses::PoseStore, uniprot: ptm::UniProt, enzymes: restriction::EnzymeDb, alerts: alerts::AlertSet, plugins: plugins::PluginSet, measurements: validation::MeasurementStore, libraries: libraries::LibraryStore, qm_charges: charges::QmHook, qm: qm::QmEngine, fes: metad::FesStore, load: autoscale::Tracker, admission: admission::Admission, retention: retention::Retention, cold: coldstore::ColdStore, signer: provenance::Signer, events: events::EventBus, notifications: notify::Notifications, registry: registry::Registry, retro: retro::Retro, templates: homology::Templates, alphafold: alphafold::AlphaFoldDb, compute: tokio::runtime::Handle }
+
+#[derive(Serialize)]
+struct Health { status: String, version: String, uptime_secs: u64, total_ops: u64 }
+
+#[derive(Deserialize)]
+struct SimulateRequest { molecule: String, simulation_type: Option<String>, steps: Option<u64>, temperature_k: Option<f64>, force_field: Option<String>, thermostat: Option<String>, protocol: Option<String>, restraints: Option<Vec<restraints::RestraintSpec>>, observables: Option<Vec<observables::ObservableSpec>>, temperature_schedule: Option<Vec<schedule::SchedulePoint>>, qm_region: Option<qmmm::QmRegion>, umbrella: Option<umbrella::Umbrella>, metadynamics: Option<metad::Metadynamics>, validate_only: Option<bool> }
+#[derive(Serialize)]
+struct SimulateResponse { sim_id: String, molecule: String, canonical_smiles: Option<String>, #[serde(skip_serializing_if = "Vec::is_empty")] standardization: Vec<String>, simulation_type: String, #[serde(skip_serializing_if = "Option::is_none")] protocol: Option<String>, force_field: String, #[serde(skip_serializing_if = "Option::is_none")] force_field_coverage: Option<forcefields::Coverage>, thermostat: String, analyses: Vec<String>, steps: u64, sdf_field_resolution: u32, energy_kcal_mol: f64, rmsd_angstrom: f64, temperature_k: f64, folding_state: String, #[serde(skip_serializing_if = "Option::is_none")] system: Option<composition::SystemReport>, #[serde(skip_serializing_if = "Option::is_none")] stages: Option<Vec<stages::StageReport>>, #[serde(skip_serializing_if = "Option::is_none")] restraints: Option<restraints::Restrained>, #[serde(skip_serializing_if = "Option::is_none")] observables: Option<observables::Observed>, #[serde(skip_serializing_if = "Option::is_none")] annealing: Option<schedule::Annealing>, #[serde(skip_serializing_if = "Option::is_none")] qm_mm: Option<qmmm::QmMm>, #[serde(skip_serializing_if = "Option::is_none")] pmf: Option<umbrella::Pmf>, #[serde(skip_serializing_if = "Option::is_none")] metadynamics: Option<metad::Metad>, elapsed_us: u128 }
+
+#[derive(Deserialize)]
+struct ScreenRequest { target_protein: String, library_size: Option<u32>, binding_threshold: Option<f64>, anti_targets: Option<Vec<String>>, charge_model: Option<String>, filters: Option<filters::Filters>, diverse_top_n: Option<usize>, cluster_similarity: Option<f64>, plugins: Option<Vec<String>>, rank_by: Option<String>, validate_only: Option<bool>, mode: Option<String> }
+#[derive(Serialize)]
+struct ScreenResponse { screen_id: String, target: String, mode: &'static str, charge_model: &'static str, library_screened: u32, #[serde(skip_serializing_if = "Option::is_none")] filtering: Option<filters::FilterReport>, hits: Vec<ScreenHit>, #[serde(skip_serializing_if = "Option::is_none")] hits_considered: Option<usize>, clusters: Vec<cluster::HitCluster>, hit_rate_pct: f64, #[serde(skip_serializing_if = "Vec::is_empty")] warnings: Vec<String>, elapsed_us: u128, #[serde(skip)] poses: Vec<poses::Pose> }
+#[derive(Serialize)]
+struct ScreenHit { compound_id: String, smiles: String, cluster_id: usize, depiction_url: String, binding_affinity_nm: f64, water_displacement_kcal: f64, electrostatic_kcal: f64, net_charge: f64, #[serde(skip_serializing_if = "Option::is_none")] selectivity_ratio: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] selectivity_score: Option<f64>, #[serde(skip_serializing_if = "Vec::is_empty")] panel: Vec<selectivity::PanelScore>, drug_likeness: f64, #[serde(skip_serializing_if = "Option::is_none")] heavy_atoms: Option<usize>, #[serde(skip_serializing_if = "Option::is_none")] ligand_efficiency: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] lipophilic_efficiency: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] mass: Option<descriptors::MassReport>, alerts: Vec<alerts::Flag>, #[serde(skip_serializing_if = "BTreeMap::is_empty")] plugin_scores: BTreeMap<String, f64>, #[serde(skip_serializing_if = "BTreeMap::is_empty")] plugin_descriptors: BTreeMap<String, f64> }
+
+#[derive(Deserialize)]
+struct EnergyRequest { molecule: String, force_field: Option<String>, charge_model: Option<String>, decompose: Option<bool> }
+#[derive(Serialize)]
+struct EnergyResponse { calc_id: String, molecule: String, canonical_smiles: Option<String>, #[serde(skip_serializing_if = "Vec::is_empty")] standardization: Vec<String>, force_field: String, #[serde(skip_serializing_if = "Option::is_none")] force_field_coverage: Option<forcefields::Coverage>, charge_model: &'static str, total_energy_kcal: f64, bond_energy: f64, angle_energy: f64, dihedral_energy: f64, vdw_energy: f64, electrostatic_energy: f64, solvation_energy: f64, #[serde(skip_serializing_if = "Option::is_none")] charges: Option<charges::Charges>, #[serde(skip_serializing_if = "Vec::is_empty")] warnings: Vec<String>, #[serde(skip_serializing_if = "Option::is_none")] decomposition: Option<decompose::Decomposition> }
+
+#[derive(Serialize, Deserialize)]
+struct ErrorResponse { error: String }
+type ApiError = (StatusCode, Json<ErrorResponse>);
+
+// Model versions recorded in the audit trail for each compute path.
+const MD_MODEL: &str = "alice-sdf-md/0.1";
+const DOCK_MODEL: &str = "alice-sdf-dock/0.1";
+const FOLD_MODEL: &str = "alice-sdf-fold/0.1";
+
+/// Runs the HTTP service, or with `--stdio` the JSON-RPC session of `stdio`.
+pub fn service() {
+    let stdio = std::env::args().skip(1).any(|a| a == "--stdio");
+    let logs = tracing_subscriber::fmt().with_env_filter(tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "bio_engine=info".into()));
+    // On stdio, stdout carries the JSON-RPC answers.
+    if stdio { logs.with_writer(std::io::stderr).init() } else { logs.init() }
+    let pools = pools::Pools::from_env();
+    pools.interactive.block_on(serve(pools.compute.handle().clone(), stdio));
+}
+
+async fn serve(compute: tokio::runtime::Handle, stdio: bool) {
+    let app = app(compute);
+    if stdio { return stdio::run(app).await; }
+    let addr = std::env::var("BIO_ADDR").unwrap_or_else(|_| "0.0.0.0:8081".into());
+    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+    tracing::info!("Bio Engine on {addr}");
+    axum::serve(listener, app).await.unwrap();
+}
+
+/// The engine's state and routes, middleware included, as served over HTTP, stdio and `bio-cli`.
+fn app(compute: tokio::runtime::Handle) -> Router {
+    let state = Arc::new(AppState { start_time: Instant::now(), stats: stats::Stats::new(std::env::var("BIO_STATS_FILE").ok()), audit: audit::AuditLog::new(std::env::var("BIO_AUDIT_LOG").ok()), projects: projects::ProjectRegistry::new(), jobs: jobs::JobStore::new(), protocols: protocols::ProtocolStore::new(), force_fields: forcefields::ForceFieldStore::new(), pipelines: pipelines::PipelineStore::new(), sweeps: sweeps::SweepStore::new(), resolver: resolver::Resolver::new(std::env::var("BIO_RESOLVER_URL").ok()), poses: poses::PoseStore::new(), uniprot: ptm::UniProt::new(std::env::var("BIO_UNIPROT_URL").ok()), enzymes: restriction::EnzymeDb::load(std::env::var("BIO_REBASE_FILE").ok()), alerts: alerts::AlertSet::load(std::env::var("BIO_ALERTS_FILE").ok()), plugins: plugins::PluginSet::load(std::env::var("BIO_PLUGINS").ok()), measurements: validation::MeasurementStore::new(), libraries: libraries::LibraryStore::new(), qm_charges: charges::QmHook::new(std::env::var("BIO_QM_URL").ok()), qm: qm::QmEngine::new(std::env::var("BIO_QM_ENGINE").ok(), std::env::var("BIO_QM_URL").ok()), fes: metad::FesStore::new(), load: autoscale::Tracker::new(), admission: admission::Admission::from_env(), retention: retention::Retention::from_env(), cold: coldstore::ColdStore::new(std::env::var("BIO_COLD_STORAGE").ok()), signer: provenance::Signer::from_env(), events: events::EventBus::from_env(), notifications: notify::Notifications::from_env(), registry: registry::Registry::new(std::env::var("BIO_REGISTRY_HOSTS").ok()), retro: retro::Retro::new(std::env::var("BIO_RETRO_URL").ok()), templates: homology::Templates::new(std::env::var("BIO_PDB_URL").ok()), alphafold: alphafold::AlphaFoldDb::new(std::env::var("BIO_ALPHAFOLD_URL").ok()), compute });
+    tokio::spawn(stats::keep_history(state.clone()));
+    tokio::spawn(retention::collect(state.clone()));
+    tokio::spawn(registry::schedule(state.clone()));
+    let cors = CorsLayer::new().allow_origin(Any).allow_methods(Any).allow_headers(Any);
+    Router::new()
+        .route("/health", get(health))
+        .route("/api/v1/bio/simulate", post(simulate))
+        .route("/api/v1/bio/simulate/mixed-solvent", post(cosolvent::simulate))
+        .route("/api/v1/bio/compare/simulations", post(compare::simulations))
+        .route("/api/v1/bio/scripts/run", post(script::run_script))
+        .route("/api/v1/bio/simulations/:id/fes", get(metad::get).delete(metad::delete))
+        .route("/api/v1/bio/screen", post(screen))
+        .route("/api/v1/bio/screen/from-sequence", post(fromsequence::screen))
+        .route("/api/v1/bio/peptides/design", post(peptides::design))
+        .route("/api/v1/bio/predict", post(predict))
+        .route("/api/v1/bio/energy", post(energy))
+        .route("/api/v1/bio/parameterize", post(gaff::parameterize_molecule))
+        .route("/api/v1/bio/stats", get(stats::report))
+        .route("/api/v1/bio/stats/history", get(stats::history))
+        .route("/api/v1/bio/audit", get(audit::list))
+        .route("/api/v1/bio/projects", get(projects::list).post(projects::create))
+        .route("/api/v1/bio/projects/:id/archive", post(projects::archive))
+        .route("/api/v1/bio/projects/:id/restore", post(projects::restore))
+        .route("/api/v1/bio/projects/:id/standardization", put(standardize::configure))
+        .route("/api/v1/bio/projects/:id/notifications", get(notify::get_project).put(notify::set_project))
+        .route("/api/v1/bio/notifications/preferences", get(notify::get_user).put(notify::set_user))
+        .route("/api/v1/bio/jobs", get(jobs::list))
+        .route("/api/v1/bio/jobs/:id", get(jobs::get).delete(jobs::delete))
+        .route("/api/v1/bio/reports/:id", get(reports::report))
+        .route("/api/v1/bio/export/eln/:id", get(eln::export))
+        .route("/api/v1/bio/usage", get(usage::report))
+        .route("/api/v1/bio/storage", get(retention::report))
+        .route("/api/v1/bio/autoscaling", get(autoscale::report))
+        .route("/api/v1/bio/benchmark", post(benchmark::run))
+        .route("/api/v1/bio/measurements", get(validation::list).post(validation::upload))
+        .route("/api/v1/bio/validation", get(validation::report))
+        .route("/api/v1/bio/libraries", get(libraries::list).post(libraries::upload))
+        .route("/api/v1/bio/libraries/:id", get(libraries::get).delete(libraries::delete))
+        .route("/api/v1/bio/libraries/:id/archive", post(libraries::archive))
+        .route("/api/v1/bio/libraries/:id/restore", post(libraries::restore))
+        .route("/api/v1/bio/registry/connectors", get(registry::list))
+        .route("/api/v1/bio/registry/connectors/:name", get(registry::get).put(registry::put).delete(registry::delete))
+        .route("/api/v1/bio/registry/connectors/:name/sync", post(registry::run))
+        .route("/api/v1/bio/verify", post(provenance::verify))
+        .route("/api/v1/bio/estimate", post(estimate::estimate))
+        .route("/api/v1/bio/provenance/key", get(provenance::key))
+        .route("/api/v1/bio/export/project/:id", get(bundle::export))
+        .route("/api/v1/bio/import/project", post(bundle::import))
+        .route("/api/v1/bio/protocols", get(protocols::list).post(protocols::create))
+        .route("/api/v1/bio/protocols/:name", get(protocols::get))
+        .route("/api/v1/bio/force-fields", get(forcefields::list).post(forcefields::create))
+        .route("/api/v1/bio/force-fields/:name", get(forcefields::get))
+        .route("/api/v1/bio/pipelines", get(pipelines::list).post(pipelines::create))
+        .route("/api/v1/bio/pipelines/:id", get(pipelines::get))
+        .route("/api/v1/bio/sweeps", get(sweeps::list).post(sweeps::create))
+        .route("/api/v1/bio/sweeps/:id", get(sweeps::get))
+        .route("/api/v1/bio/resolve", get(resolver::resolve))
+        .route("/api/v1/bio/standardize", post(standardize::batch))
+        .route("/api/v1/bio/depict", get(depict::depict))
+        .route("/api/v1/bio/mass", get(descriptors::mass))
+        .route("/api/v1/bio/alerts", get(alerts::list))
+        .route("/api/v1/bio/plugins", get(plugins::list))
+        .route("/api/v1/bio/alerts/check", post(alerts::check))
+        .route("/api/v1/bio/nmr-predict", post(nmr::predict))
+        .route("/api/v1/bio/convert", post(convert::convert))
+        .route("/api/v1/bio/helm", post(helm::helm))
+        .route("/api/v1/bio/prepare-pdbqt", post(pdbqt::prepare))
+        .route("/api/v1/bio/prepare-receptor", post(receptor::prepare))
+        .route("/api/v1/bio/qm", post(qm::run))
+        .route("/api/v1/bio/screens/:id", delete(poses::delete))
+        .route("/api/v1/bio/screens/:id/hits/:compound_id/pose", get(poses::get))
+        .route("/api/v1/bio/screens/:id/strain", get(strain::report))
+        .route("/api/v1/bio/torsion-scan", post(torsion::scan))
+        .route("/api/v1/bio/refine-pose", post(refine::refine))
+        .route("/api/v1/bio/ternary-complex", post(ternary::model))
+        .route("/api/v1/bio/fragments/grow", post(fragments::grow))
+        .route("/api/v1/bio/bioisosteres", post(bioisosteres::suggest))
+        .route("/api/v1/bio/sar/decompose", post(sar::decompose_series))
+        .route("/api/v1/bio/retrosynthesis", post(retro::plan))
+        .route("/api/v1/bio/novelty", post(novelty::check))
+        .route("/api/v1/bio/druggability", post(druggability::assess))
+        .route("/api/v1/bio/druggability/triage", post(druggability::triage))
+        .route("/api/v1/bio/pockets/allosteric", post(allosteric::discover))
+        .route("/api/v1/bio/hydration", get(hydration::report))
+        .route("/api/v1/bio/epitope", post(epitope::predict))
+        .route("/api/v1/bio/stability", post(stability::predict))
+        .route("/api/v1/bio/model-loops", post(loops::model))
+        .route("/api/v1/bio/superpose", post(superpose::superpose))
+        .route("/api/v1/bio/superpose/cluster", post(superpose::cluster))
+        .route("/api/v1/bio/properties", post(properties::compute))
+        .route("/api/v1/bio/restriction-map", post(restriction::map))
+        .route("/api/v1/bio/assembly", post(assembly::assemble))
+        .route("/api/v1/bio/digest", post(digest::digest))
+        .route("/api/v1/bio/fit/dose-response", post(doseresponse::fit))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), pools::dispatch))
+        .layer(axum::middleware::from_fn(provenance::digest))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), chain::resolve))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), events::start))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), autoscale::track))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), admission::admit))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), events::submit))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), retention::enforce))
+        .layer(axum::middleware::from_fn(dryrun::mark))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), projects::guard))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), stats::record))
+        .layer(cors).layer(TraceLayer::new_for_http()).with_state(state)
+}
+
+async fn health(State(s): State<Arc<AppState>>) -> Json<Health> {
+    Json(Health { status: "ok".into(), version: env!("CARGO_PKG_VERSION").into(), uptime_secs: s.start_time.elapsed().as_secs(), total_ops: s.stats.total_ops() })
+}
+
+async fn simulate(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<SimulateRequest>) -> Result<Response, ApiError> {
+    let meter = usage::Meter::start();
+    let proto = resolve_protocol(&s, &headers, req.protocol.as_deref())?;
+    let mol = standardize::resolve(&s, &headers, &req.molecule).await;
+    if req.validate_only == Some(true) { return Ok(Json(dryrun::simulate(&s, &headers, &req, &proto, &mol)).into_response()); }
+    let qm_mm = match &req.qm_region {
+        Some(region) => Some(qmmm::evaluate(&s, region, &mol).await.map_err(|e| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e })))?),
+        None => None,
+    };
+    let mut resp = run_simulate(&s, &projects::project_id(&headers), req, proto, &mol).map_err(|e| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e })))?;
+    resp.qm_mm = qm_mm;
+    record(&s, &headers, "simulate", &resp.molecule, MD_MODEL, &resp.sim_id, &meter, &resp);
+    metad::persist(&s, &headers, &resp);
+    Ok(Json(resp).into_response())
+}
+
+/// Saved protocol values apply wherever the request leaves a parameter unset.
+fn resolve_protocol(s: &AppState, headers: &HeaderMap, name: Option<&str>) -> Result<protocols::Protocol, ApiError> {
+    match name {
+        Some(name) => s.protocols.get(&projects::project_id(headers), name).ok_or_else(|| not_found("protocol", name)),
+        None => Ok(protocols::Protocol::default()),
+    }
+}
+
+/// What a simulation runs with: the request's values, else the protocol's, else the defaults.
+#[derive(Serialize)]
+struct SimSettings { simulation_type: String, force_field: String, thermostat: String, temperature_k: f64 }
+
+fn sim_settings(req: &SimulateRequest, proto: &protocols::Protocol) -> SimSettings {
+    SimSettings {
+        simulation_type: req.simulation_type.clone().or_else(|| proto.simulation_type.clone()).unwrap_or_else(|| if req.umbrella.is_some() { "umbrella-sampling" } else if req.metadynamics.is_some() { "metadynamics" } else { "molecular-dynamics" }.into()),
+        force_field: req.force_field.clone().or_else(|| proto.force_field.clone()).unwrap_or_else(|| "amber-ff14".into()),
+        thermostat: req.thermostat.clone().or_else(|| proto.thermostat.clone()).unwrap_or_else(|| "langevin".into()),
+        temperature_k: req.temperature_k.or(proto.temperature_k).unwrap_or(310.15), // body temperature
+    }
+}
+
+/// Rejects temperatures no simulation can run at: zero, negative or not a number.
+fn check_temperature(t: f64) -> Result<(), String> {
+    if t.is_finite() && t > 0.0 { Ok(()) } else { Err(format!("temperature_k must be a positive number of kelvin, not {t}")) }
+}
+
+fn run_simulate(s: &AppState, project: &str, req: SimulateRequest, proto: protocols::Protocol, mol: &resolver::Resolved) -> Result<SimulateResponse, String> {
+    let t = Instant::now();
+    let SimSettings { simulation_type: sim_type, force_field, thermostat, temperature_k: temp } = sim_settings(&req, &proto);
+    check_temperature(temp)?;
+    let force_field_coverage = forcefields::check(s, project, &force_field, mol)?;
+    // A staged protocol's `steps` are its production stage's; the run reports all dynamics steps.
+    let stage_reports = (!proto.stages.is_empty()).then(|| stages::run(&proto.stages, mol, temp, req.steps)).transpose()?;
+    let steps = match &stage_reports { Some(r) => r.iter().filter(|s| s.ensemble.is_some()).map(|s| s.steps).sum(), None => req.steps.or(proto.steps).or(req.temperature_schedule.as_ref().and_then(|p| p.last()).map(|p| p.step)).unwrap_or(10_000) };
+    let schedule = match &req.temperature_schedule { Some(points) => schedule::Schedule::parse(points)?, None => schedule::Schedule::constant(temp) };
+    let analyses = proto.analyses;
+    let sim_id = uuid::Uuid::new_v4().to_string();
+    let restraints = restraints::Restraints::parse(req.restraints.as_deref().unwrap_or(&proto.restraints), mol)?;
+    let mut recorder = observables::Recorder::new(req.observables.as_deref().unwrap_or(&proto.observables), mol, &restraints)?;
+    let (restrained, observed, annealing) = if restraints.is_empty() && recorder.is_empty() && schedule.is_constant() { (None, None, None) } else {
+        let (report, annealing) = restraints::run(&restraints, mol, steps, &schedule, &mut recorder)?;
+        let steps_sampled = report.steps_sampled;
+        ((!restraints.is_empty()).then_some(report), recorder.finish(steps_sampled), (!schedule.is_constant()).then_some(annealing))
+    };
+    let pmf = req.umbrella.as_ref().map(|u| umbrella::run(u, mol, temp, &restraints)).transpose()?;
+    let metadynamics = req.metadynamics.as_ref().map(|m| metad::run(m, mol, temp, &sim_id, &restraints)).transpose()?;
+    let h = fnv1a(mol.key().as_bytes());
+    // Fluctuations grow with sqrt(T) and other force fields shift the energy scale; both are neutral at the defaults.
+    let ff_shift = if force_field == "amber-ff14" { 0.0 } else { (fnv1a(force_field.as_bytes()) % 40) as f64 - 20.0 };
+    // The GAFF potential with Gasteiger charges on a generated conformer, where there's a structure.
+    let potential = match mol.canonical_smiles.as_deref() {
+        Some(smiles) => {
+            let graph = chem::parse_smiles(smiles)?;
+            let coords = conformer::embed(&graph, h);
+            let (_, e) = gaff::evaluate(&graph, &coords)?;
+            let q = charges::assign(s, smiles, charges::ChargeModel::Gasteiger)?;
+            e.bond + e.angle + e.dihedral + e.vdw + charges::coulomb(&graph, &coords, &q.atoms)
+        }
+        None => -100.0 - (h % 500) as f64,
+    };
+    let energy = ((potential + ff_shift) * 1e3).round() / 1e3;
+    let rmsd = ((h % 30) as f64 * 0.1 + 0.5) * (temp / 310.15).sqrt();
+    s.stats.simulated();
+    Ok(SimulateResponse { sim_id, molecule: req.molecule, canonical_smiles: mol.canonical_smiles.clone(), standardization: mol.standardization.clone(), simulation_type: sim_type, protocol: req.protocol, force_field, force_field_coverage, thermostat, analyses, steps, sdf_field_resolution: 128, energy_kcal_mol: energy, rmsd_angstrom: rmsd, temperature_k: temp, folding_state: if rmsd < 2.0 { "folded".into() } else { "partially_folded".into() }, system: composition::report(mol), stages: stage_reports, restraints: restrained, observables: observed, annealing, qm_mm: None, pmf, metadynamics, elapsed_us: t.elapsed().as_micros() })
+}
+
+async fn screen(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<ScreenRequest>) -> Result<Response, ApiError> {
+    if req.validate_only == Some(true) { return Ok(Json(dryrun::screen(&s, &headers, &req)).into_response()); }
+    let meter = usage::Meter::start();
+    let resp = screen_and_score(&s, req).await.map_err(|e| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e })))?;
+    record(&s, &headers, "screen", &resp.target, DOCK_MODEL, &resp.screen_id, &meter, &resp);
+    poses::persist(&s, &headers, &resp);
+    Ok(Json(resp).into_response())
+}
+
+/// Runs a screen, charges fetched first, and then its plugins on the hits.
+async fn screen_and_score(s: &AppState, req: ScreenRequest) -> Result<ScreenResponse, String> {
+    let plugins = s.plugins.select(req.plugins.as_deref(), req.rank_by.as_deref())?;
+    let rank_by = req.rank_by.clone();
+    let smiles: Vec<String> = screen_candidates(s, &req)?.0.into_iter().map(|c| c.1).collect();
+    charges::prefetch(s, req.charge_model.as_deref(), &smiles).await;
+    let mut resp = run_screen(s, req)?;
+    plugins::apply(s, &plugins, rank_by.as_deref(), &mut resp).await;
+    Ok(resp)
+}
+
+/// (compound ID, canonical SMILES) of the compounds to dock, and the pre-screen filters' report.
+type Candidates = (Vec<(String, String)>, Option<filters::FilterReport>);
+
+/// Whether a screen docks the fragment library rather than the compound library.
+fn fragment_screen(req: &ScreenRequest) -> Result<bool, String> {
+    match req.mode.as_deref().unwrap_or("compound") {
+        "compound" => Ok(false),
+        "fragment" => Ok(true),
+        other => Err(format!("unknown mode {other}; expected compound or fragment")),
+    }
+}
+
+/// Checks a screen's options before anything is docked.
+fn check_screen(req: &ScreenRequest) -> Result<(), String> {
+    if fragment_screen(req)? && req.filters.as_ref().is_some_and(|f| !f.is_empty()) { return Err("filters apply to the compound library; fragments are all within the Rule of Three".into()); }
+    if req.diverse_top_n.is_some_and(|n| n == 0 || n > cluster::MAX_DIVERSE) { return Err(format!("diverse_top_n must be between 1 and {}", cluster::MAX_DIVERSE)); }
+    if req.cluster_similarity.is_some_and(|t| !(t > 0.0 && t <= 1.0)) { return Err("cluster_similarity must be above 0 and at most 1".into()); }
+    if let Some(f) = req.filters.as_ref().filter(|f| !f.is_empty()) { f.validate()?; }
+    Ok(())
+}
+
+/// The library compounds a screen docks, after any pre-screen filters.
+fn screen_candidates(s: &AppState, req: &ScreenRequest) -> Result<Candidates, String> {
+    check_screen(req)?;
+    let h = fnv1a(req.target_protein.as_bytes());
+    let fragments = fragment_screen(req)?;
+    let lib_size = req.library_size.unwrap_or(if fragments { 1_000 } else { 10_000 });
+    let hit_count = match req.diverse_top_n {
+        Some(n) => (n * cluster::POOL_FACTOR).min(lib_size as usize),
+        None if fragments => ((lib_size as f64 * 0.03) as usize).min(50), // ~3% hit rate
+        None => ((lib_size as f64 * 0.005) as usize).min(20), // ~0.5% hit rate
+    };
+    if fragments {
+        let numbers = (0..hit_count as u64).map(|i| h.wrapping_add(i) % library::FRAGMENT_LIBRARY_SIZE);
+        return Ok((numbers.map(|n| (library::fragment_id(n), chem::canonicalize(&library::fragment(n)).unwrap_or_else(|_| library::fragment(n)))).collect(), None));
+    }
+    let (numbers, report) = match req.filters.as_ref().filter(|f| !f.is_empty()) {
+        Some(f) => { let (n, r) = f.apply(&s.alerts, h, lib_size as u64, hit_count); (n, Some(r)) }
+        None => ((0..hit_count).map(|i| h.wrapping_add(i as u64) % library::LIBRARY_SIZE).collect(), None),
+    };
+    let candidates = numbers.into_iter().map(|n| (library::compound_id(n), chem::canonicalize(&library::compound(n)).unwrap_or_else(|_| library::compound(n)))).collect();
+    Ok((candidates, report))
+}
+
+fn run_screen(s: &AppState, req: ScreenRequest) -> Result<ScreenResponse, String> {
+    let t = Instant::now();
+    let fragments = fragment_screen(&req)?;
+    let lib_size = req.library_size.unwrap_or(if fragments { 1_000 } else { 10_000 });
+    // Fragments bind weakly, so their hits are cut at 1 mM.
+    let threshold = req.binding_threshold.unwrap_or(if fragments { 1e6 } else { 100.0 }); // nM
+    let model = charges::ChargeModel::parse(req.charge_model.as_deref(), "")?;
+    let h = fnv1a(req.target_protein.as_bytes());
+    let pocket = pockets::detect(&req.target_protein).into_iter().next();
+    let sites = pocket.as_ref().map(hydration::sites).unwrap_or_default();
+    let charged = frame::Frame::sites(&pocket.as_ref().map(pockets::charged_sites).unwrap_or_default());
+    let anti_targets: Vec<selectivity::AntiTarget> = req.anti_targets.iter().flatten().map(|t| selectivity::AntiTarget::new(t)).collect();
+    let (mut hits, mut poses, mut fps) = (Vec::new(), Vec::new(), Vec::new());
+    let (candidates, filtering) = screen_candidates(s, &req)?;
+    for (i, (compound_id, smiles)) in candidates.into_iter().enumerate() {
+        let Some(mut pose) = poses::dock(&req.target_protein, pocket.as_ref(), &compound_id, &smiles) else { continue };
+        // Displacing waters and the pocket's charged residues shift the binding free energy,
+        // and Kd with it (RT = 0.593 kcal/mol at 298 K).
+        let water = hydration::displacement(&sites, &pose.coords);
+        let q = charges::assign(s, &smiles, model)?;
+        let elec = (charges::interaction(&pose.coords, &q.atoms, &charged) * 1e3).round() / 1e3;
+        let mol = chem::parse_smiles(&smiles).ok();
+        let heavy_atoms = mol.as_ref().map_or(0, |m| m.atoms.iter().filter(|a| a.element != "H").count());
+        // A fragment's binding free energy is its heavy atoms times a ligand efficiency of
+        // 0.25–0.49 kcal/mol per atom, the range fragment hits fall in.
+        let affinity = if fragments {
+            let le = 0.25 + (h.wrapping_add(i as u64) % 25) as f64 * 0.01;
+            1e9 * ((-le * heavy_atoms as f64 + water + elec) / 0.593).exp()
+        } else {
+            ((h.wrapping_add(i as u64) % 100) as f64 + 1.0) * ((water + elec) / 0.593).exp()
+        };
+        if affinity > threshold { continue; }
+        pose.binding_affinity_nm = affinity;
+        let mut panel: Vec<selectivity::PanelScore> = anti_targets.iter().filter_map(|t| t.score(&compound_id, &smiles)).collect();
+        let ratio = selectivity::ratio(affinity, &panel);
+        // Ligand efficiency -ΔG per heavy atom (kcal/mol), and pKd less cLogP.
+        let (ligand_efficiency, lipophilic_efficiency) = match (&mol, fragments && heavy_atoms > 0) {
+            (Some(m), true) => (Some((-0.593 * (affinity * 1e-9).ln() / heavy_atoms as f64 * 1e3).round() / 1e3), Some(((9.0 - affinity.log10() - filters::logp(m)) * 1e3).round() / 1e3)),
+            _ => (None, None),
+        };
+        let mass = mol.as_ref().and_then(|m| descriptors::mass_report(m).ok());
+        let alerts = mol.as_ref().map(|m| s.alerts.check(m, &|_| true)).unwrap_or_default();
+        fps.push(mol.as_ref().map(|m| fingerprint::morgan(m, fingerprint::RADIUS)).unwrap_or_default());
+        if ratio.is_some() { panel.insert(0, selectivity::PanelScore { target: req.target_protein.clone(), role: "primary", pocket_id: pose.pocket_id.clone(), binding_affinity_nm: affinity, water_displacement_kcal: water }); }
+        hits.push(ScreenHit { compound_id, cluster_id: 0, depiction_url: depict::url(&smiles), smiles, binding_affinity_nm: affinity, water_displacement_kcal: water, electrostatic_kcal: elec, net_charge: q.net_charge, selectivity_ratio: ratio, selectivity_score: ratio.map(f64::log10), panel, drug_likeness: 0.5 + (h.wrapping_add(i as u64 * 7) % 50) as f64 * 0.01, heavy_atoms: fragments.then_some(heavy_atoms), ligand_efficiency, lipophilic_efficiency, mass, alerts, plugin_scores: BTreeMap::new(), plugin_descriptors: BTreeMap::new() });
+        poses.push(pose);
+    }
+    // Fragment hits rank by ligand efficiency: the atoms they'd be grown from bind best.
+    if fragments {
+        let mut docked: Vec<_> = hits.into_iter().zip(poses).zip(fps).collect();
+        docked.sort_by(|a, b| b.0.0.ligand_efficiency.unwrap_or(0.0).total_cmp(&a.0.0.ligand_efficiency.unwrap_or(0.0)));
+        ((hits, poses), fps) = docked.into_iter().unzip();
+    }
+    let clusters = cluster::butina(&fps, req.cluster_similarity.unwrap_or(cluster::DEFAULT_SIMILARITY));
+    let affinity: Vec<f64> = hits.iter().map(|h| h.binding_affinity_nm).collect();
+    for (k, c) in clusters.iter().enumerate() { for &i in c { hits[i].cluster_id = k + 1; } }
+    let ids: Vec<String> = hits.iter().map(|h| h.compound_id.clone()).collect();
+    let hits_considered = req.diverse_top_n.map(|_| hits.len());
+    if let Some(n) = req.diverse_top_n {
+        let mut docked: Vec<Option<(ScreenHit, poses::Pose)>> = hits.into_iter().zip(poses).map(Some).collect();
+        (hits, poses) = cluster::diverse(&clusters, &affinity, n).into_iter().filter_map(|i| docked[i].take()).unzip();
+    }
+    s.stats.screened(lib_size as u64);
+    Ok(ScreenResponse { screen_id: uuid::Uuid::new_v4().to_string(), target: req.target_protein, mode: if fragments { "fragment" } else { "compound" }, charge_model: model.name(), library_screened: lib_size, filtering, hits, hits_considered, clusters: cluster::report(&clusters, &ids, &affinity), hit_rate_pct: if fragments { 3.0 } else { 0.5 }, warnings: Vec::new(), elapsed_us: t.elapsed().as_micros(), poses })
+}
+
+async fn predict(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<predict::PredictRequest>) -> Result<Json<Predicted>, ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let meter = usage::Meter::start();
+    let (req, alphafold) = alphafold::for_request(&s, req).await.map_err(bad)?;
+    let sequence = req.sequence.clone();
+    let (req, gene) = predict::prepare(req).map_err(bad)?;
+    let homology = homology::for_request(&s, &req).await.map_err(bad)?;
+    let curated = match &req.uniprot_accession { Some(acc) => s.uniprot.annotations(acc).await, None => None };
+    let resp = Predicted::new(run_predict(&s, req, gene, curated), homology, alphafold);
+    record(&s, &headers, "predict", &sequence, resp.model(), &resp.prediction.prediction_id, &meter, &resp);
+    Ok(Json(resp))
+}
+
+/// A prediction and, for the `homology` and `alphafold` types, its model, whose mean reliability
+/// or pLDDT is the structure confidence.
+#[derive(Serialize)]
+struct Predicted {
+    #[serde(flatten)] prediction: predict::PredictResponse, #[serde(skip_serializing_if = "Option::is_none")] homology: Option<homology::HomologyModel>,
+    #[serde(skip_serializing_if = "Option::is_none")] alphafold: Option<alphafold::AlphaFoldModel>,
+}
+
+impl Predicted {
+    fn new(mut prediction: predict::PredictResponse, homology: Option<homology::HomologyModel>, alphafold: Option<alphafold::AlphaFoldModel>) -> Self {
+        if let Some(h) = &homology { prediction.structure_confidence = h.confidence; }
+        if let Some(a) = &alphafold { prediction.structure_confidence = (a.mean_plddt * 10.0).round() / 1000.0; }
+        Self { prediction, homology, alphafold }
+    }
+
+    fn model(&self) -> &'static str { if self.homology.is_some() { homology::MODEL } else if self.alphafold.is_some() { alphafold::MODEL } else { FOLD_MODEL } }
+}
+
+fn run_predict(s: &AppState, req: predict::PredictRequest, gene: Option<gene::Gene>, curated: Option<Vec<ptm::Annotation>>) -> predict::PredictResponse {
+    let resp = predict::run(req, gene, curated);
+    s.stats.predicted(resp.chains());
+    resp
+}
+
+async fn energy(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<EnergyRequest>) -> Result<Response, ApiError> {
+    let meter = usage::Meter::start();
+    let mol = standardize::resolve(&s, &headers, &req.molecule).await;
+    charges::prefetch(&s, req.charge_model.as_deref(), &mol.canonical_smiles.iter().cloned().collect::<Vec<_>>()).await;
+    let (mut resp, decomposer) = evaluate_energy(&s, &projects::project_id(&headers), req, &mol).map_err(|e| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e })))?;
+    record(&s, &headers, "energy", &resp.molecule, &resp.force_field, &resp.calc_id, &meter, &resp);
+    // Decompositions of large systems stream as NDJSON to clients that ask for it.
+    let ndjson = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok()).is_some_and(|v| v.contains(decompose::NDJSON));
+    Ok(match decomposer {
+        Some(d) if ndjson => decompose::stream(&resp, d),
+        d => { resp.decomposition = d.map(decompose::Decomposer::collect); Json(resp).into_response() }
+    })
+}
+
+fn run_energy(s: &AppState, project: &str, req: EnergyRequest, mol: &resolver::Resolved) -> Result<EnergyResponse, String> {
+    let (mut resp, decomposer) = evaluate_energy(s, project, req, mol)?;
+    resp.decomposition = decomposer.map(decompose::Decomposer::collect);
+    Ok(resp)
+}
+
+/// The energy terms, and the decomposer still to run when `decompose` is set.
+fn evaluate_energy(s: &AppState, project: &str, req: EnergyRequest, mol: &resolver::Resolved) -> Result<(EnergyResponse, Option<decompose::Decomposer>), String> {
+    let ff = req.force_field.unwrap_or_else(|| "amber-ff14".into());
+    let model = charges::ChargeModel::parse(req.charge_model.as_deref(), &ff)?;
+    let force_field_coverage = forcefields::check(s, project, &ff, mol)?;
+    let h = fnv1a(mol.key().as_bytes());
+    let solv = -5.0 - (h % 20) as f64;
+    // GAFF bonded and van der Waals terms and the Coulomb energy of the model's charges on a
+    // generated conformer; a molecule that didn't resolve to a structure has nothing to put
+    // parameters on.
+    let decompose = req.decompose.unwrap_or(false);
+    let mut warnings = Vec::new();
+    let round = |e: f64| (e * 1e3).round() / 1e3;
+    let ((bond, angle, dihedral, vdw), elec, charges, decomposer) = match mol.canonical_smiles.as_deref() {
+        Some(smiles) => {
+            let q = charges::assign(s, smiles, model)?;
+            let graph = chem::parse_smiles(smiles)?;
+            let unknown = cofactors::unparameterized_atoms(&graph);
+            if !unknown.is_empty() { warnings.push(format!("{} have no charge-model parameters; their partial charges are only estimates", unknown.join(", "))); }
+            let coords = conformer::embed(&graph, h);
+            let (params, e) = gaff::evaluate(&graph, &coords)?;
+            if !params.generic.is_empty() { warnings.push(format!("{} have no GAFF type; generic Lennard-Jones parameters were used", params.generic.join(", "))); }
+            let elec = round(charges::coulomb(&graph, &coords, &q.atoms));
+            let decomposer = decompose.then(|| decompose::Decomposer::new(&graph, coords, q.atoms.clone()));
+            ((round(e.bond), round(e.angle), round(e.dihedral), round(e.vdw)), elec, Some(q), decomposer)
+        }
+        None if decompose => return Err(format!("can't resolve {} to a structure to decompose", mol.input)),
+        None => ((-50.0 - (h % 100) as f64, -20.0 - (h % 50) as f64, -10.0 - (h % 30) as f64, -30.0 - (h % 80) as f64), -15.0 - (h % 40) as f64, None, None),
+    };
+    s.stats.analyzed(1);
+    Ok((EnergyResponse { calc_id: uuid::Uuid::new_v4().to_string(), molecule: req.molecule, canonical_smiles: mol.canonical_smiles.clone(), standardization: mol.standardization.clone(), force_field: ff, force_field_coverage, charge_model: model.name(), total_energy_kcal: bond + angle + dihedral + vdw + elec + solv, bond_energy: bond, angle_energy: angle, dihedral_energy: dihedral, vdw_energy: vdw, electrostatic_energy: elec, solvation_energy: solv, charges, warnings, decomposition: None }, decomposer))
+}
+
+/// Records a completed compute call in the audit trail and in the caller's project job history.
+#[allow(clippy::too_many_arguments)]
+fn record<T: Serialize>(s: &AppState, headers: &HeaderMap, kind: &str, subject: &str, model: &str, id: &str, meter: &usage::Meter, resp: &T) {
+    let project = s.projects.resolve(headers);
+    s.audit.record(headers, &project, kind, subject, model, Some(id));
+    let result = serde_json::to_value(resp).unwrap_or_default();
+    let provenance = Some(s.signer.stamp(headers, id, kind, model, &result));
+    s.events.publish(events::Event::new("completed", kind, &project).job(id).request(headers));
+    let resources = meter.finish();
+    if events::requested(headers, kind) {
+        s.notifications.finished(headers, &project, notify::Finished { kind, id, subject, error: None, wall_seconds: resources.wall_seconds, summary: notify::summarize(kind, &result), link: Some(s.notifications.link(&format!("/api/v1/bio/jobs/{id}"))) });
+    }
+    s.jobs.insert(jobs::Job { job_id: id.into(), project, kind: kind.into(), subject: subject.into(), model: model.into(), depends_on: chain::parents(headers), status: "completed".into(), created_at_unix: unix_now(), parameters: provenance::parameters(headers), resources, result, provenance, archived: false, stored_bytes: 0 });
+}
+
+fn not_found(what: &str, id: &str) -> ApiError { (StatusCode::NOT_FOUND, Json(ErrorResponse { error: format!("{what} {id} not found") })) }
+
+fn unix_now() -> u64 { SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simulate_rejects_impossible_temperatures() {
+        for t in [-5.0, 0.0, f64::NAN, f64::INFINITY] { assert!(check_temperature(t).is_err(), "{t}"); }
+        for t in [1.0, 310.15, 1000.0] { assert!(check_temperature(t).is_ok(), "{t}"); }
+    }
+
+    #[test]
+    fn request_temperature_overrides_the_protocol() {
+        let req: SimulateRequest = serde_json::from_value(serde_json::json!({"molecule": "CCO", "temperature_k": -5.0})).unwrap();
+        let settings = sim_settings(&req, &protocols::Protocol { temperature_k: Some(300.0), ..Default::default() });
+        assert!(check_temperature(settings.temperature_k).is_err());
+        let req: SimulateRequest = serde_json::from_value(serde_json::json!({"molecule": "CCO"})).unwrap();
+        assert_eq!(sim_settings(&req, &protocols::Protocol::default()).temperature_k, 310.15);
+    }
+}
@@ -0,0 +1,526 @@
+//! Compound and sequence libraries uploaded by a project.
+//!
+//! `POST /api/v1/bio/libraries?name=...&format=sdf|fasta` takes the file as the raw request
+//! body (SDF/molfile V2000 records separated by `$$$$`, or FASTA) and parses it as the bytes
+//! arrive: only the current line and record are held, and each record is checked and stored as
+//! soon as it is complete. Stored records stay in memory, so an upload over `BIO_LIBRARY_MAX_MB`
+//! (default 1024) or `BIO_LIBRARY_MAX_RECORDS` (default two million) is stopped, dropped and
+//! answered `413`. A compound is kept
+//! as its ID (the molfile title, else an `ID`/`Name` data item), canonical SMILES and any
+//! `InChIKey` data item, standardized as the project does (see `standardize`) with its
+//! `changes` listed, and a compound whose structure repeats an earlier one is dropped and
+//! counted in `duplicates`. A sequence is kept as its identifier, description and residues.
+//! Records that fail are skipped and reported by number, first line and reason (the first 100
+//! of them); the summary counts the rest. An upload cut off mid-stream keeps what it stored,
+//! marked `incomplete`. A library kept by a registry connector (see `registry`) has format
+//! `registry` and is rebuilt, under the same ID, at each sync.
+//!
+//! A library can be archived: its records move to cold storage (see `coldstore`), its summary
+//! stays listed with `archived_at_unix` (under `?state=archived` or `all`), and its records
+//! can't be read until it is restored. Archiving a project archives its libraries with it.
+
+use axum::{body::Body, extract::{Path, Query, State}, http::{header, HeaderMap, StatusCode}, response::Json};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::{admission, coldstore, not_found, poses, projects, retention::{self, Held}, standardize, unix_now, ApiError, AppState, ErrorResponse};
+
+/// The longest line kept; longer ones fail their record.
+const MAX_LINE: usize = 64 * 1024;
+/// The largest record (molfile text or sequence residues) kept.
+const MAX_RECORD: usize = 1024 * 1024;
+/// Largest upload, in MiB, unless `BIO_LIBRARY_MAX_MB` says otherwise.
+const DEFAULT_MAX_MB: u64 = 1024;
+/// Most records one upload may hold, unless `BIO_LIBRARY_MAX_RECORDS` says otherwise.
+const DEFAULT_MAX_RECORDS: usize = 2_000_000;
+/// Record errors listed per library; the rest are only counted.
+const MAX_ERRORS: usize = 100;
+/// SD data items taken as the compound ID when the title line is blank.
+const ID_ITEMS: &[&str] = &["id", "name", "compound_id"];
+/// SD data items holding a compound's InChIKey.
+const INCHIKEY_ITEMS: &[&str] = &["inchikey", "inchi_key", "stdinchikey"];
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Record {
+    id: String, #[serde(default, skip_serializing_if = "Option::is_none")] smiles: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")] description: Option<String>, #[serde(default, skip_serializing_if = "Option::is_none")] sequence: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")] inchikey: Option<String>, #[serde(default, skip_serializing_if = "Vec::is_empty")] changes: Vec<String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct RecordError { record: usize, line: usize, #[serde(skip_serializing_if = "Option::is_none")] id: Option<String>, error: String }
+
+#[derive(Serialize, Clone)]
+pub struct Summary {
+    library_id: String, name: String, format: &'static str, status: &'static str, bytes_read: u64, records_read: usize, stored: usize, pub failed: usize, standardized: usize, pub duplicates: usize,
+    pub errors: Vec<RecordError>, #[serde(skip_serializing_if = "Option::is_none")] stream_error: Option<String>, created_at_unix: u64,
+    #[serde(skip_serializing_if = "Option::is_none")] archived_at_unix: Option<u64>,
+}
+
+/// A library as it travels in an export bundle (see `bundle`); its records follow it.
+#[derive(Serialize, Deserialize)]
+pub struct Bundled { pub library_id: String, pub name: String, pub format: String, pub records: usize, pub created_at_unix: u64 }
+
+/// Where an archived library's records are, and whether they went with the whole project.
+struct Archive { key: String, with_project: bool }
+
+struct Library { project: String, summary: Summary, records: Vec<Record>, ids: HashSet<String>, archive: Option<Archive>, settings: standardize::Settings, structures: HashSet<String> }
+
+impl Library {
+    fn accept(&mut self, done: Done) {
+        self.summary.records_read += 1;
+        let n = self.summary.records_read;
+        let parsed = done.parsed.and_then(|mut r| {
+            if r.id.is_empty() { r.id = format!("record-{n}"); }
+            if self.ids.contains(&r.id) { return Err(format!("duplicate ID {}", r.id)); }
+            if let Some((smiles, changes)) = r.smiles.as_deref().map(|smi| standardize::smiles(smi, self.settings)).transpose()? { r.smiles = Some(smiles); r.changes = changes; }
+            Ok(r)
+        });
+        match parsed {
+            // The structure of a compound already stored, once both are standardized.
+            Ok(r) if self.settings.deduplicate && r.smiles.as_ref().is_some_and(|smi| self.structures.contains(smi)) => self.summary.duplicates += 1,
+            Ok(r) => {
+                self.ids.insert(r.id.clone());
+                if let Some(smiles) = r.smiles.as_ref().filter(|_| self.settings.deduplicate) { self.structures.insert(smiles.clone()); }
+                if !r.changes.is_empty() { self.summary.standardized += 1; }
+                self.records.push(r);
+                self.summary.stored += 1;
+            }
+            Err(error) => {
+                self.summary.failed += 1;
+                if self.summary.errors.len() < MAX_ERRORS { self.summary.errors.push(RecordError { record: n, line: done.line, id: (!done.id.is_empty()).then_some(done.id), error }); }
+            }
+        }
+    }
+}
+
+/// Stored records are held in memory, so one upload is capped in bytes and records.
+pub struct LibraryStore { libraries: Mutex<HashMap<String, Library>>, max_bytes: u64, max_records: usize }
+
+impl LibraryStore {
+    pub fn new() -> Self {
+        Self {
+            libraries: Mutex::new(HashMap::new()),
+            max_bytes: admission::setting("BIO_LIBRARY_MAX_MB", DEFAULT_MAX_MB, |&n| n > 0) * 1024 * 1024,
+            max_records: admission::setting("BIO_LIBRARY_MAX_RECORDS", DEFAULT_MAX_RECORDS, |&n| n > 0),
+        }
+    }
+    fn update(&self, library_id: &str, f: impl FnOnce(&mut Library)) { if let Some(l) = self.libraries.lock().unwrap().get_mut(library_id) { f(l) } }
+    /// IDs of the project's libraries: the live ones, or those archived along with the project.
+    pub fn of_project(&self, project: &str, archived_with_project: bool) -> Vec<String> {
+        let libraries = self.libraries.lock().unwrap();
+        libraries.values().filter(|l| l.project == project && l.archive.as_ref().map(|a| a.with_project) == archived_with_project.then_some(true)).map(|l| l.summary.library_id.clone()).collect()
+    }
+    /// Live libraries of `project`, sized by the bytes uploaded.
+    pub fn held(&self, project: &str) -> Held {
+        let mut held = Held::default();
+        self.libraries.lock().unwrap().values().filter(|l| l.project == project && l.archive.is_none()).for_each(|l| held.add(Held::one(l.summary.bytes_read)));
+        held
+    }
+    /// Adds an exported library to `project`, empty until `import_record` fills it; false when the ID is taken.
+    pub fn import(&self, project: &str, b: Bundled) -> Result<bool, String> {
+        let format = match b.format.as_str() { "sdf" => "sdf", "fasta" => "fasta", "registry" => "registry", other => return Err(format!("unknown library format {other}")) };
+        let mut libraries = self.libraries.lock().unwrap();
+        if libraries.contains_key(&b.library_id) { return Ok(false); }
+        let summary = Summary { library_id: b.library_id.clone(), name: b.name, format, status: "complete", bytes_read: 0, records_read: 0, stored: 0, failed: 0, standardized: 0, duplicates: 0, errors: Vec::new(), stream_error: None, created_at_unix: b.created_at_unix, archived_at_unix: None };
+        libraries.insert(b.library_id, Library { project: project.into(), summary, records: Vec::new(), ids: HashSet::new(), archive: None, settings: standardize::Settings::default(), structures: HashSet::new() });
+        Ok(true)
+    }
+    /// Creates or refreshes a registry connector's library from the compounds it fetched, as an
+    /// upload would store them. `merge` keeps the records that weren't fetched (an incremental
+    /// sync); otherwise the library becomes exactly what was fetched.
+    pub fn sync(&self, project: &str, library_id: &str, name: &str, settings: standardize::Settings, fetched: Vec<Fetched>, merge: bool) -> Result<(Summary, Changes), String> {
+        let mut libraries = self.libraries.lock().unwrap();
+        let old = libraries.get(library_id);
+        if let Some(l) = old {
+            if l.project != project { return Err(format!("library {library_id} belongs to another project")); }
+            if l.archive.is_some() { return Err(format!("library {library_id} is archived; restore it to sync")); }
+        }
+        let before: HashMap<&str, Option<&str>> = old.map(|l| l.records.iter().map(|r| (r.id.as_str(), r.smiles.as_deref())).collect()).unwrap_or_default();
+        let created_at_unix = old.map_or_else(unix_now, |l| l.summary.created_at_unix);
+        let summary = Summary { library_id: library_id.into(), name: name.into(), format: "registry", status: "complete", bytes_read: 0, records_read: 0, stored: 0, failed: 0, standardized: 0, duplicates: 0, errors: Vec::new(), stream_error: None, created_at_unix, archived_at_unix: None };
+        let mut fresh = Library { project: project.into(), summary, records: Vec::new(), ids: HashSet::new(), archive: None, settings, structures: HashSet::new() };
+        for (n, f) in fetched.into_iter().enumerate() {
+            let parsed = match f.smiles { Some(smiles) if !smiles.trim().is_empty() => Ok(Record { id: f.id.clone(), smiles: Some(smiles), description: f.description, sequence: None, inchikey: None, changes: Vec::new() }), _ => Err("no structure".into()) };
+            fresh.accept(Done { line: n + 1, id: f.id, parsed });
+        }
+        let mut changes = Changes::default();
+        for r in &fresh.records {
+            match before.get(r.id.as_str()) { None => changes.added += 1, Some(smiles) if *smiles == r.smiles.as_deref() => changes.unchanged += 1, Some(_) => changes.updated += 1 }
+        }
+        if let Some(l) = old.filter(|_| merge) {
+            let kept: Vec<&Record> = l.records.iter().filter(|r| !fresh.ids.contains(&r.id)).collect();
+            for r in kept {
+                fresh.ids.insert(r.id.clone());
+                if let Some(smiles) = r.smiles.as_ref().filter(|_| settings.deduplicate) { fresh.structures.insert(smiles.clone()); }
+                fresh.records.push(r.clone());
+            }
+        }
+        changes.removed = before.keys().filter(|id| !fresh.ids.contains(**id)).count();
+        fresh.summary.stored = fresh.records.len();
+        fresh.summary.bytes_read = fresh.records.iter().map(|r| serde_json::to_vec(r).map_or(0, |v| v.len() as u64)).sum();
+        let summary = fresh.summary.clone();
+        libraries.insert(library_id.into(), fresh);
+        Ok((summary, changes))
+    }
+    /// The compounds of the project's live libraries, or of those named, for comparing designs
+    /// against (see `novelty`).
+    pub fn references(&self, project: &str, ids: Option<&[String]>) -> Result<Vec<Reference>, ApiError> {
+        let libraries = self.libraries.lock().unwrap();
+        let chosen: Vec<&Library> = match ids {
+            Some(ids) => ids.iter().map(|id| {
+                let l = libraries.get(id).filter(|l| l.project == project).ok_or_else(|| not_found("library", id))?;
+                if l.archive.is_some() { return Err(projects::conflict(format!("library {id} is archived; restore it to read its records"))); }
+                Ok(l)
+            }).collect::<Result<_, _>>()?,
+            None => libraries.values().filter(|l| l.project == project && l.archive.is_none()).collect(),
+        };
+        Ok(chosen.into_iter().flat_map(|l| l.records.iter().filter_map(|r| Some(Reference { library_id: l.summary.library_id.clone(), id: r.id.clone(), smiles: r.smiles.clone()?, inchikey: r.inchikey.clone() }))).collect())
+    }
+    /// Adds an exported record to a library `import` created.
+    pub fn import_record(&self, library_id: &str, r: Record) -> Result<(), String> {
+        let mut libraries = self.libraries.lock().unwrap();
+        let l = libraries.get_mut(library_id).ok_or_else(|| format!("no library {library_id}"))?;
+        if !l.ids.insert(r.id.clone()) { return Err(format!("duplicate ID {} in library {library_id}", r.id)); }
+        l.summary.bytes_read += serde_json::to_vec(&r).map_or(0, |v| v.len() as u64);
+        l.summary.records_read += 1;
+        l.summary.stored += 1;
+        l.records.push(r);
+        Ok(())
+    }
+}
+
+/// A stored compound as a reference for novelty checks.
+pub struct Reference { pub library_id: String, pub id: String, pub smiles: String, pub inchikey: Option<String> }
+
+/// A compound as a registry connector fetched it (see `registry`).
+pub struct Fetched { pub id: String, pub smiles: Option<String>, pub description: Option<String> }
+
+/// How a registry sync changed a library.
+#[derive(Serialize, Clone, Copy, Default)]
+pub struct Changes { pub added: usize, pub updated: usize, pub unchanged: usize, pub removed: usize }
+
+/// A complete record: where it started, its ID if it has one, and the record or why it failed.
+struct Done { line: usize, id: String, parsed: Result<Record, String> }
+
+/// Takes a file a line at a time and hands back each record once it is complete.
+trait Parser: Send {
+    fn line(&mut self, number: usize, line: Result<&str, String>) -> Option<Done>;
+    fn finish(&mut self) -> Option<Done>;
+}
+
+#[derive(Default)]
+struct Sdf { start: usize, text: String, error: Option<String> }
+
+/// The value of the first of `items` among an SD record's data items.
+fn data_item(text: &str, items: &[&str]) -> Option<String> {
+    let mut lines = text.lines();
+    while let Some(l) = lines.next() {
+        let field = l.strip_prefix('>').and_then(|r| Some(&r[r.find('<')? + 1..r.find('>')?]));
+        if field.is_some_and(|f| items.iter().any(|i| f.eq_ignore_ascii_case(i))) { return lines.next().map(str::trim).filter(|v| !v.is_empty()).map(String::from); }
+    }
+    None
+}
+
+fn compound(text: &str) -> Result<Record, String> {
+    let (title, mol, _) = poses::parse_sdf(text)?;
+    if mol.atoms.is_empty() { return Err("record has no atoms".into()); }
+    let id = Some(title).filter(|t| !t.is_empty()).or_else(|| data_item(text, ID_ITEMS)).unwrap_or_default();
+    Ok(Record { id, smiles: Some(mol.to_canonical_smiles()), description: None, sequence: None, inchikey: data_item(text, INCHIKEY_ITEMS), changes: Vec::new() })
+}
+
+impl Parser for Sdf {
+    fn line(&mut self, number: usize, line: Result<&str, String>) -> Option<Done> {
+        if self.text.is_empty() && self.error.is_none() { self.start = number; }
+        match line {
+            Ok(l) if l.trim_end() == "$$$$" => return self.finish(),
+            Ok(_) if self.error.is_some() => {}
+            Ok(l) if self.text.len() + l.len() >= MAX_RECORD => { self.error = Some(format!("record is larger than {} KiB", MAX_RECORD / 1024)); self.text.clear(); }
+            Ok(l) => { self.text.push_str(l); self.text.push('\n'); }
+            Err(e) => { self.error.get_or_insert(e); self.text.clear(); }
+        }
+        None
+    }
+
+    fn finish(&mut self) -> Option<Done> {
+        let text = std::mem::take(&mut self.text);
+        let error = self.error.take();
+        // Blank lines after the last `$$$$` are not a record.
+        if text.trim().is_empty() && error.is_none() { return None; }
+        let id = text.lines().next().map(str::trim).filter(|t| !t.is_empty()).map(String::from).or_else(|| data_item(&text, ID_ITEMS)).unwrap_or_default();
+        Some(Done { line: self.start, id, parsed: match error { Some(e) => Err(e), None => compound(&text) } })
+    }
+}
+
+struct Entry { start: usize, id: String, description: String, sequence: String, error: Option<String> }
+
+#[derive(Default)]
+struct Fasta { current: Option<Entry> }
+
+impl Parser for Fasta {
+    fn line(&mut self, number: usize, line: Result<&str, String>) -> Option<Done> {
+        if let Some(header) = line.as_ref().ok().and_then(|l| l.trim().strip_prefix('>')).map(str::trim) {
+            let done = self.finish();
+            let (id, description) = header.split_once(char::is_whitespace).map_or((header, ""), |(id, d)| (id, d.trim()));
+            self.current = Some(Entry { start: number, id: id.into(), description: description.into(), sequence: String::new(), error: id.is_empty().then(|| "header has no identifier".into()) });
+            return done;
+        }
+        if line.as_ref().is_ok_and(|l| l.trim().is_empty()) { return None; }
+        let e = self.current.get_or_insert_with(|| Entry { start: number, id: String::new(), description: String::new(), sequence: String::new(), error: Some("sequence before the first header".into()) });
+        if e.error.is_some() { return None; }
+        match line {
+            Err(err) => e.error = Some(err),
+            Ok(l) => {
+                let l = l.trim();
+                // A trailing `*` marks the stop codon.
+                let residues = l.strip_suffix('*').unwrap_or(l);
+                if let Some((i, c)) = residues.char_indices().find(|(_, c)| !c.is_ascii_alphabetic()) { e.error = Some(format!("invalid residue {c:?} at position {}", e.sequence.len() + i + 1)); }
+                else if e.sequence.len() + residues.len() > MAX_RECORD { e.error = Some(format!("sequence is longer than {MAX_RECORD} residues")); }
+                else { e.sequence.push_str(&residues.to_ascii_uppercase()); }
+            }
+        }
+        if e.error.is_some() { e.sequence = String::new(); }
+        None
+    }
+
+    fn finish(&mut self) -> Option<Done> {
+        self.current.take().map(|e| {
+            let parsed = match e.error {
+                Some(err) => Err(err),
+                None if e.sequence.is_empty() => Err("empty sequence".into()),
+                None => Ok(Record { id: e.id.clone(), smiles: None, description: (!e.description.is_empty()).then_some(e.description), sequence: Some(e.sequence), inchikey: None, changes: Vec::new() }),
+            };
+            Done { line: e.start, id: e.id, parsed }
+        })
+    }
+}
+
+/// Splits the body into lines as chunks arrive, holding at most one line.
+struct Reader { parser: Box<dyn Parser>, pending: Vec<u8>, line: usize, overlong: bool }
+
+impl Reader {
+    fn new(format: &str) -> Self {
+        let parser: Box<dyn Parser> = if format == "sdf" { Box::new(Sdf::default()) } else { Box::new(Fasta::default()) };
+        Self { parser, pending: Vec::new(), line: 0, overlong: false }
+    }
+
+    fn feed(&mut self, mut chunk: &[u8]) -> Vec<Done> {
+        let mut out = Vec::new();
+        while let Some(i) = chunk.iter().position(|&b| b == b'\n') {
+            self.take(&chunk[..i], &mut out);
+            chunk = &chunk[i + 1..];
+        }
+        if !self.overlong { self.pending.extend_from_slice(chunk); }
+        if self.pending.len() > MAX_LINE { self.pending = Vec::new(); self.overlong = true; }
+        out
+    }
+
+    fn take(&mut self, tail: &[u8], out: &mut Vec<Done>) {
+        self.line += 1;
+        let overlong = std::mem::take(&mut self.overlong) || self.pending.len() + tail.len() > MAX_LINE;
+        if !overlong { self.pending.extend_from_slice(tail); }
+        let line = if overlong { Err(format!("line {} is longer than {} KiB", self.line, MAX_LINE / 1024)) } else {
+            std::str::from_utf8(&self.pending).map(|l| l.strip_suffix('\r').unwrap_or(l)).map_err(|_| format!("line {} is not valid UTF-8", self.line))
+        };
+        out.extend(self.parser.line(self.line, line));
+        self.pending.clear();
+    }
+
+    fn finish(mut self) -> Vec<Done> {
+        let mut out = Vec::new();
+        if !self.pending.is_empty() || self.overlong { self.take(&[], &mut out); }
+        out.extend(self.parser.finish());
+        out
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UploadQuery { name: Option<String>, format: Option<String> }
+
+#[derive(Serialize)]
+pub struct UploadResponse { project: String, #[serde(flatten)] library: Summary, elapsed_us: u128 }
+
+#[derive(Serialize)]
+pub struct LibrariesResponse { project: String, total: usize, libraries: Vec<Summary> }
+
+#[derive(Deserialize)]
+pub struct ListQuery { state: Option<String> }
+
+#[derive(Deserialize)]
+pub struct RecordQuery { offset: Option<usize>, limit: Option<usize> }
+
+#[derive(Serialize)]
+pub struct LibraryResponse { #[serde(flatten)] library: Summary, offset: usize, records: Vec<Record> }
+
+/// The format named by the query, else by the content type.
+fn format_of(q: &UploadQuery, headers: &HeaderMap) -> Result<&'static str, String> {
+    let content_type = headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).unwrap_or("");
+    let named = q.format.as_deref().or(if content_type.contains("mdl-sdfile") { Some("sdf") } else if content_type.contains("fasta") { Some("fasta") } else { None });
+    match named.map(str::to_lowercase).as_deref() {
+        Some("sdf" | "sd" | "mol") => Ok("sdf"),
+        Some("fasta" | "fa") => Ok("fasta"),
+        Some(other) => Err(format!("unknown format {other}; expected sdf or fasta")),
+        None => Err("give format=sdf or format=fasta".into()),
+    }
+}
+
+pub async fn upload(State(s): State<Arc<AppState>>, headers: HeaderMap, Query(q): Query<UploadQuery>, body: Body) -> Result<(StatusCode, Json<UploadResponse>), ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let t = Instant::now();
+    let name = q.name.as_deref().map(str::trim).filter(|n| !n.is_empty()).ok_or_else(|| bad("name is required".into()))?.to_string();
+    let format = format_of(&q, &headers).map_err(bad)?;
+    let project = s.projects.resolve(&headers);
+    let library_id = uuid::Uuid::new_v4().to_string();
+    let summary = Summary { library_id: library_id.clone(), name, format, status: "uploading", bytes_read: 0, records_read: 0, stored: 0, failed: 0, standardized: 0, duplicates: 0, errors: Vec::new(), stream_error: None, created_at_unix: unix_now(), archived_at_unix: None };
+    s.libraries.libraries.lock().unwrap().insert(library_id.clone(), Library { project: project.clone(), summary, records: Vec::new(), ids: HashSet::new(), archive: None, settings: s.projects.standardization(&project), structures: HashSet::new() });
+
+    let mut reader = Reader::new(format);
+    let mut stream = body.into_data_stream();
+    let mut stream_error = None;
+    let (max_bytes, max_records) = (s.libraries.max_bytes, s.libraries.max_records);
+    let mut over = false;
+    while let Some(chunk) = stream.next().await {
+        match chunk {
+            Ok(bytes) => {
+                let done = reader.feed(&bytes);
+                s.libraries.update(&library_id, |l| {
+                    l.summary.bytes_read += bytes.len() as u64;
+                    done.into_iter().for_each(|d| l.accept(d));
+                    over = l.summary.bytes_read > max_bytes || l.summary.records_read > max_records;
+                });
+                if over { break; }
+            }
+            Err(e) => { stream_error = Some(e.to_string()); break; }
+        }
+    }
+    if over {
+        s.libraries.libraries.lock().unwrap().remove(&library_id);
+        return Err((StatusCode::PAYLOAD_TOO_LARGE, Json(ErrorResponse { error: format!("a library upload may hold at most {} MiB and {max_records} records; split the file", max_bytes / (1024 * 1024)) })));
+    }
+    // A record cut off mid-stream is not judged.
+    let done = if stream_error.is_none() { reader.finish() } else { Vec::new() };
+    let mut summary = None;
+    s.libraries.update(&library_id, |l| {
+        done.into_iter().for_each(|d| l.accept(d));
+        l.summary.status = if stream_error.is_none() { "complete" } else { "incomplete" };
+        l.summary.stream_error = stream_error;
+        summary = Some(l.summary.clone());
+    });
+    let library = summary.ok_or_else(|| not_found("library", &library_id))?;
+    Ok((StatusCode::CREATED, Json(UploadResponse { project, library, elapsed_us: t.elapsed().as_micros() })))
+}
+
+pub async fn list(State(s): State<Arc<AppState>>, headers: HeaderMap, Query(q): Query<ListQuery>) -> Result<Json<LibrariesResponse>, ApiError> {
+    let project = projects::project_id(&headers);
+    let archived = projects::state_filter(q.state.as_deref())?;
+    let mut libraries: Vec<Summary> = s.libraries.libraries.lock().unwrap().values().filter(|l| l.project == project && archived.is_none_or(|a| a == l.archive.is_some())).map(|l| l.summary.clone()).collect();
+    libraries.sort_by(|a, b| b.created_at_unix.cmp(&a.created_at_unix).then_with(|| a.name.cmp(&b.name)));
+    Ok(Json(LibrariesResponse { project, total: libraries.len(), libraries }))
+}
+
+pub async fn get(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(id): Path<String>, Query(q): Query<RecordQuery>) -> Result<Json<LibraryResponse>, ApiError> {
+    let project = projects::project_id(&headers);
+    let libraries = s.libraries.libraries.lock().unwrap();
+    let l = libraries.get(&id).filter(|l| l.project == project).ok_or_else(|| not_found("library", &id))?;
+    if l.archive.is_some() { return Err(projects::conflict(format!("library {id} is archived; restore it to read its records"))); }
+    let offset = q.offset.unwrap_or(0);
+    let records = l.records.iter().skip(offset).take(q.limit.unwrap_or(100).min(1000)).cloned().collect();
+    Ok(Json(LibraryResponse { library: l.summary.clone(), offset, records }))
+}
+
+pub async fn delete(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(id): Path<String>) -> Result<Json<retention::Deleted>, ApiError> {
+    let project = projects::project_id(&headers);
+    let l = {
+        let mut libraries = s.libraries.libraries.lock().unwrap();
+        if libraries.get(&id).is_none_or(|l| l.project != project) { return Err(not_found("library", &id)); }
+        libraries.remove(&id).ok_or_else(|| not_found("library", &id))?
+    };
+    if let Some(a) = &l.archive {
+        if let Err(e) = s.cold.delete(&a.key).await { tracing::warn!("deleted library {id} but not its archive: {e}"); }
+        return Ok(retention::deleted(id, Held::default()));
+    }
+    Ok(retention::deleted(id, Held::one(l.summary.bytes_read)))
+}
+
+/// Moves a library's records to cold storage.
+pub async fn archive_one(s: &AppState, project: &str, id: &str, with_project: bool) -> Result<Summary, ApiError> {
+    let key = coldstore::key(&["libraries", project, &format!("{id}.jsonl")]);
+    let records = {
+        let mut libraries = s.libraries.libraries.lock().unwrap();
+        let l = libraries.get_mut(id).filter(|l| l.project == project).ok_or_else(|| not_found("library", id))?;
+        if l.summary.status == "uploading" { return Err(projects::conflict(format!("library {id} is still uploading"))); }
+        if l.archive.is_some() { return Err(projects::conflict(format!("library {id} is already archived"))); }
+        l.archive = Some(Archive { key: key.clone(), with_project });
+        l.summary.archived_at_unix = Some(unix_now());
+        l.ids.clear();
+        std::mem::take(&mut l.records)
+    };
+    let mut blob = Vec::new();
+    for r in &records { if serde_json::to_writer(&mut blob, r).is_ok() { blob.push(b'\n'); } }
+    if let Err(e) = s.cold.put(&key, blob).await {
+        s.libraries.update(id, |l| { l.ids = records.iter().map(|r| r.id.clone()).collect(); l.records = records; l.archive = None; l.summary.archived_at_unix = None; });
+        return Err((StatusCode::BAD_GATEWAY, Json(ErrorResponse { error: e })));
+    }
+    let mut summary = None;
+    s.libraries.update(id, |l| summary = Some(l.summary.clone()));
+    summary.ok_or_else(|| not_found("library", id))
+}
+
+/// Reads an archived library's records from cold storage.
+async fn archived_records(s: &AppState, id: &str, key: &str) -> Result<Vec<Record>, ApiError> {
+    let blob = s.cold.get(key).await.map_err(|e| (StatusCode::BAD_GATEWAY, Json(ErrorResponse { error: e })))?;
+    blob.split(|&b| b == b'\n').filter(|l| !l.is_empty()).map(serde_json::from_slice).collect::<Result<_, _>>()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: format!("archived records of library {id} are unreadable: {e}") })))
+}
+
+/// The project's finished libraries with their records, archived ones read from cold storage.
+pub async fn export(s: &AppState, project: &str) -> Result<Vec<(Bundled, Vec<Record>)>, ApiError> {
+    let listed: Vec<(Bundled, Vec<Record>, Option<String>)> = s.libraries.libraries.lock().unwrap().values().filter(|l| l.project == project && l.summary.status != "uploading").map(|l| {
+        let b = Bundled { library_id: l.summary.library_id.clone(), name: l.summary.name.clone(), format: l.summary.format.into(), records: l.summary.stored, created_at_unix: l.summary.created_at_unix };
+        (b, l.records.clone(), l.archive.as_ref().map(|a| a.key.clone()))
+    }).collect();
+    let mut out = Vec::new();
+    for (b, records, key) in listed {
+        let records = match key { Some(key) => archived_records(s, &b.library_id, &key).await?, None => records };
+        out.push((b, records));
+    }
+    out.sort_by_key(|(b, _)| b.created_at_unix);
+    Ok(out)
+}
+
+/// Brings an archived library's records back from cold storage.
+pub async fn restore_one(s: &AppState, project: &str, id: &str) -> Result<Summary, ApiError> {
+    let key = {
+        let libraries = s.libraries.libraries.lock().unwrap();
+        let l = libraries.get(id).filter(|l| l.project == project).ok_or_else(|| not_found("library", id))?;
+        l.archive.as_ref().map(|a| a.key.clone()).ok_or_else(|| projects::conflict(format!("library {id} is not archived")))?
+    };
+    let records = archived_records(s, id, &key).await?;
+    let mut summary = None;
+    s.libraries.update(id, |l| {
+        l.ids = records.iter().map(|r| r.id.clone()).collect();
+        l.records = records;
+        l.archive = None;
+        l.summary.archived_at_unix = None;
+        summary = Some(l.summary.clone());
+    });
+    if let Err(e) = s.cold.delete(&key).await { tracing::warn!("restored library {id} but could not delete its archive: {e}"); }
+    summary.ok_or_else(|| not_found("library", id))
+}
+
+pub async fn archive(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(id): Path<String>) -> Result<Json<Summary>, ApiError> {
+    let project = projects::project_id(&headers);
+    let summary = archive_one(&s, &project, &id, false).await?;
+    s.audit.record(&headers, &project, "archive_library", &id, "", None);
+    Ok(Json(summary))
+}
+
+pub async fn restore(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(id): Path<String>) -> Result<Json<Summary>, ApiError> {
+    let project = projects::project_id(&headers);
+    let summary = restore_one(&s, &project, &id).await?;
+    s.audit.record(&headers, &project, "restore_library", &id, "", None);
+    Ok(Json(summary))
+}
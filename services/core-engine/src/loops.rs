@@ -0,0 +1,523 @@
+//! Missing residue and loop modeling for uploaded structures.
+//!
+//! `POST /api/v1/bio/model-loops` takes a PDB file and finds the residues its coordinates lack.
+//! Each chain's SEQRES sequence (or one given in `sequences`) is mapped onto the residues its ATOM
+//! records hold, by residue numbering when that is consistent and otherwise by alignment, and every
+//! run of sequence residues with no coordinates is a gap. A gap between two observed residues is
+//! rebuilt: Cα traces are grown from the residue before it, with pseudo bond and torsion angles
+//! drawn from each residue's helix, strand and coil propensities (`knowledge`, the default) or
+//! uniformly (`ab_initio`), closed onto the residue after it by cyclic coordinate descent, and
+//! scored on closure, junction angles, clashes with the rest of the structure and (for `knowledge`)
+//! how likely the closed trace's angles are. The best trace gets backbone atoms from ideal trans
+//! peptide planes, each turned about its Cα–Cα axis so the residues' bond angles and φ/ψ fit best
+//! and the ends meet the anchors' C and N, and a Cβ; other side-chain atoms are not built. Gaps at
+//! a chain's ends have nothing to close onto and are reported but not built, as are gaps too long
+//! for `max_loop_length` or too short to span.
+//!
+//! The returned PDB is the first model with the rebuilt residues inserted in chain order. Their
+//! atoms have occupancy 0.00 and B-factor 99.99, a `REMARK 999` line names each rebuilt region,
+//! and atoms are renumbered (CONECT records follow).
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::{fnv1a, record, rng::Rng, usage, vec3::{self, sub, add, scale, dot, cross, dist, unit, perpendicular, angle, torsion}, ApiError, AppState, ErrorResponse, FOLD_MODEL};
+
+/// Cα–Cα distance of a trans peptide.
+const CA_CA: f64 = 3.8;
+const DEFAULT_MAX_LOOP: usize = 30;
+const MAX_SAMPLES: usize = 1000;
+/// Cyclic coordinate descent sweeps per trace, and the closure that ends them early.
+const MAX_SWEEPS: usize = 300;
+const CLOSED: f64 = 0.05;
+/// A trace farther than this from the residue after the gap is not built.
+const MAX_CLOSURE: f64 = 1.0;
+/// A new Cα nearer than this to another heavy atom is a clash.
+const CLASH: f64 = 3.0;
+/// Pseudo bond angles (degrees) outside this range are penalized at the junctions.
+const ANGLE_RANGE: (f64, f64) = (75.0, 155.0);
+/// Consecutive Cα atoms farther apart than this have residues missing between them.
+const BROKEN: f64 = 4.2;
+/// The most residues aligned per chain (sequence times observed).
+const MAX_ALIGNMENT: usize = 25_000_000;
+
+pub const AMINO: &[u8] = b"ARNDCQEGHILKMFPSTWYV";
+pub const THREE_LETTER: [&str; 20] = ["ALA", "ARG", "ASN", "ASP", "CYS", "GLN", "GLU", "GLY", "HIS", "ILE", "LEU", "LYS", "MET", "PHE", "PRO", "SER", "THR", "TRP", "TYR", "VAL"];
+/// Modified and protonation-state residue names, as the standard residue they stand for.
+const VARIANTS: [(&str, &str); 9] = [("MSE", "MET"), ("HID", "HIS"), ("HIE", "HIS"), ("HIP", "HIS"), ("CYX", "CYS"), ("SEP", "SER"), ("TPO", "THR"), ("PTR", "TYR"), ("MLY", "LYS")];
+const WATERS: [&str; 4] = ["HOH", "WAT", "DOD", "H2O"];
+/// Chou–Fasman helix, strand and turn propensities (×100) in `AMINO` order.
+const HELIX: [f64; 20] = [142.0, 98.0, 67.0, 101.0, 70.0, 111.0, 151.0, 57.0, 100.0, 108.0, 121.0, 114.0, 145.0, 113.0, 57.0, 77.0, 83.0, 108.0, 69.0, 106.0];
+const STRAND: [f64; 20] = [83.0, 93.0, 89.0, 54.0, 119.0, 110.0, 37.0, 75.0, 87.0, 160.0, 130.0, 74.0, 105.0, 138.0, 55.0, 75.0, 119.0, 137.0, 147.0, 170.0];
+const TURN: [f64; 20] = [66.0, 95.0, 156.0, 146.0, 119.0, 98.0, 74.0, 156.0, 95.0, 47.0, 59.0, 101.0, 60.0, 60.0, 152.0, 143.0, 96.0, 96.0, 114.0, 50.0];
+/// Cα pseudo bond angle and torsion (mean, spread; degrees) of helix, strand and coil.
+const STATES: [((f64, f64), (f64, f64)); 3] = [((91.0, 5.0), (50.0, 10.0)), ((120.0, 10.0), (-170.0, 20.0)), ((110.0, 15.0), (-100.0, 50.0))];
+/// Coil is weighted up: missing regions are mostly loops.
+const COIL_WEIGHT: f64 = 1.5;
+/// Backbone atoms of a trans peptide in the frame of its two Cα atoms: x from the first to the
+/// second, y in the peptide plane away from the carbonyl oxygen.
+const PEPTIDE_C: [f64; 2] = [1.423, -0.533];
+const PEPTIDE_O: [f64; 2] = [1.636, -1.744];
+const PEPTIDE_N: [f64; 2] = [2.392, 0.379];
+const N_CA_C: f64 = 111.0;
+/// Peptide plane rotations tried about each Cα–Cα axis.
+const ROTATIONS: usize = 36;
+/// Allowed (φ, ψ) regions in degrees, with the extra strain of each for residues other than
+/// glycine: right-handed helix, strand, polyproline II, left-handed helix.
+pub const RAMACHANDRAN: [(f64, f64, f64); 4] = [(-63.0, -43.0, 0.0), (-120.0, 130.0, 0.0), (-75.0, 145.0, 0.0), (57.0, 47.0, 2.0)];
+
+fn round(v: f64) -> f64 { (v * 1000.0).round() / 1000.0 }
+
+/// `p` rotated by `theta` about the axis through `origin` along unit `axis` (Rodrigues).
+fn rotate(p: [f64; 3], origin: [f64; 3], axis: [f64; 3], theta: f64) -> [f64; 3] { add(origin, vec3::rotate(sub(p, origin), axis, theta)) }
+
+/// The point `bond` from `c` with angle `theta` at `c` and torsion `tau` about b–c (NeRF).
+fn place(a: [f64; 3], b: [f64; 3], c: [f64; 3], bond: f64, theta: f64, tau: f64) -> [f64; 3] {
+    let bc = unit(sub(c, b));
+    let n = unit(cross(sub(b, a), bc));
+    let m = cross(n, bc);
+    let d = [-bond * theta.cos(), bond * theta.sin() * tau.cos(), bond * theta.sin() * tau.sin()];
+    add(c, add(add(scale(bc, d[0]), scale(m, d[1])), scale(n, d[2])))
+}
+
+/// The standard residue a residue name stands for.
+fn standard(name: &str) -> &str { VARIANTS.iter().find(|v| v.0 == name).map_or(name, |v| v.1) }
+pub fn one_letter(name: &str) -> char { THREE_LETTER.iter().position(|&t| t == standard(name)).map_or('X', |i| AMINO[i] as char) }
+fn same(a: &str, b: &str) -> bool { standard(a) == standard(b) }
+
+#[derive(Clone, Copy, PartialEq)]
+enum Method { Knowledge, AbInitio }
+
+impl Method {
+    fn name(self) -> &'static str { match self { Self::Knowledge => "knowledge", Self::AbInitio => "ab_initio" } }
+
+    /// Pseudo bond angle and torsion (radians) for a residue.
+    fn angles(self, residue: &str, rng: &mut Rng) -> (f64, f64) {
+        let (theta, tau) = match (self, THREE_LETTER.iter().position(|&t| t == standard(residue))) {
+            (Self::Knowledge, Some(i)) => {
+                let weights = [HELIX[i], STRAND[i], TURN[i] * COIL_WEIGHT];
+                let mut pick = rng.uniform() * weights.iter().sum::<f64>();
+                let state = weights.iter().position(|&w| { pick -= w; pick < 0.0 }).unwrap_or(2);
+                let ((tm, ts), (pm, ps)) = STATES[state];
+                ((tm + ts * rng.gauss()).clamp(80.0, 150.0), pm + ps * rng.gauss())
+            }
+            _ => (85.0 + 60.0 * rng.uniform(), 360.0 * rng.uniform() - 180.0),
+        };
+        (theta.to_radians(), tau.to_radians())
+    }
+
+    /// How unlikely a residue's pseudo bond angle and torsion (degrees) are under its state
+    /// propensities, as a negative log likelihood; nothing for `ab_initio`.
+    fn surprise(self, residue: &str, theta: f64, tau: Option<f64>) -> f64 {
+        let Some(i) = THREE_LETTER.iter().position(|&t| t == standard(residue)).filter(|_| self == Self::Knowledge) else { return 0.0 };
+        let weights = [HELIX[i], STRAND[i], TURN[i] * COIL_WEIGHT];
+        let total: f64 = weights.iter().sum();
+        let density = |d: f64, spread: f64| (-0.5 * (d / spread).powi(2)).exp() / spread;
+        let wrap = |d: f64| (d + 540.0).rem_euclid(360.0) - 180.0;
+        let p: f64 = weights.iter().zip(STATES).map(|(w, ((tm, ts), (pm, ps)))| w / total * density(theta - tm, ts) * tau.map_or(1.0, |t| density(wrap(t - pm), ps))).sum();
+        -p.max(1e-12).ln()
+    }
+}
+
+struct Atom { name: String, element: String, pos: [f64; 3], residue: usize }
+
+struct Residue { chain: char, number: i64, icode: char, name: String, first_line: usize, ca: Option<[f64; 3]> }
+
+impl Residue {
+    fn label(&self) -> String { format!("{}{}{}", self.chain, self.number, self.icode).trim_end().into() }
+}
+
+/// The parts of the first model the modeler reads.
+struct Structure { lines: Vec<String>, atoms: Vec<Atom>, residues: Vec<Residue>, seqres: HashMap<char, Vec<String>>, models: usize }
+
+fn column(line: &str, from: usize, to: usize) -> &str { line.get(from..to.min(line.len())).unwrap_or("") }
+
+fn parse(text: &str) -> Structure {
+    let mut s = Structure { lines: Vec::new(), atoms: Vec::new(), residues: Vec::new(), seqres: HashMap::new(), models: 0 };
+    let mut skipping = false;
+    for line in text.lines() {
+        let record = column(line, 0, 6).trim_end();
+        if record == "MODEL" { s.models += 1; skipping = s.models > 1; }
+        if skipping { if record == "ENDMDL" { skipping = false; } continue; }
+        s.lines.push(line.trim_end().to_string());
+        if record == "SEQRES" {
+            let chain = column(line, 11, 12).chars().next().unwrap_or(' ');
+            s.seqres.entry(chain).or_default().extend(column(line, 19, 80).split_whitespace().map(String::from));
+            continue;
+        }
+        if record != "ATOM" && record != "HETATM" { continue; }
+        let alt = column(line, 16, 17);
+        if alt != " " && alt != "A" && !alt.is_empty() { continue; }
+        let f = |a: usize, b: usize| column(line, a, b).trim().parse::<f64>().ok();
+        let (Some(x), Some(y), Some(z)) = (f(30, 38), f(38, 46), f(46, 54)) else { continue };
+        let name = column(line, 12, 16).trim().to_string();
+        let element = Some(column(line, 76, 78).trim()).filter(|e| !e.is_empty()).map_or_else(|| name.trim_start_matches(|c: char| c.is_ascii_digit()).chars().take(1).collect(), String::from);
+        let (chain, icode) = (column(line, 21, 22).chars().next().unwrap_or(' '), column(line, 26, 27).chars().next().unwrap_or(' '));
+        let Ok(number) = column(line, 22, 26).trim().parse::<i64>() else { continue };
+        let resname = column(line, 17, 20).trim().to_string();
+        // HETATM records are polymer residues only when the chain's sequence names them.
+        let polymer = record == "ATOM" || s.seqres.get(&chain).is_some_and(|seq| seq.contains(&resname));
+        let same_residue = s.residues.last().is_some_and(|r| r.chain == chain && r.number == number && r.icode == icode && r.name == resname);
+        if !same_residue { s.residues.push(Residue { chain, number, icode, name: resname.clone(), first_line: s.lines.len() - 1, ca: None }); }
+        let residue = s.residues.len() - 1;
+        if name == "CA" { s.residues[residue].ca = Some([x, y, z]); }
+        if !polymer { s.residues[residue].chain = '\0'; }
+        s.atoms.push(Atom { name, element, pos: [x, y, z], residue });
+    }
+    s
+}
+
+/// Sequence index of each observed residue, by numbering: a constant offset that matches nine
+/// residues in ten.
+fn by_numbering(seq: &[String], observed: &[&Residue]) -> Option<Vec<usize>> {
+    if observed.iter().any(|r| r.icode != ' ') || observed.windows(2).any(|w| w[1].number <= w[0].number) { return None; }
+    let mut best: Option<(usize, Vec<usize>)> = None;
+    for start in 0..seq.len() {
+        let offset = observed[0].number - start as i64;
+        let Some(map) = observed.iter().map(|r| usize::try_from(r.number - offset).ok().filter(|&i| i < seq.len())).collect::<Option<Vec<usize>>>() else { continue };
+        let matches = observed.iter().zip(&map).filter(|(r, &i)| same(&r.name, &seq[i])).count();
+        if matches * 10 >= observed.len() * 9 && best.as_ref().is_none_or(|(m, _)| matches > *m) { best = Some((matches, map)); }
+    }
+    best.map(|(_, map)| map)
+}
+
+/// Sequence index of each observed residue by global alignment, `None` for residues the
+/// sequence lacks. Unobserved sequence residues cost nothing at the chain's ends, 1 where the
+/// observed chain breaks and 3 elsewhere, so a gap goes where the coordinates have one.
+fn by_alignment(seq: &[String], observed: &[&Residue]) -> Vec<Option<usize>> {
+    let (n, m) = (seq.len(), observed.len());
+    let mut score = vec![vec![0i32; m + 1]; n + 1];
+    for (j, cell) in score[0].iter_mut().enumerate() { *cell = -10 * j as i32; }
+    let broken: Vec<bool> = (0..=m).map(|j| j > 0 && j < m && match (observed[j - 1].ca, observed[j].ca) { (Some(p), Some(q)) => dist(p, q) > BROKEN, _ => true }).collect();
+    let gap = |j: usize| if j == 0 || j == m { 0 } else if broken[j] { -1 } else { -3 };
+    for i in 1..=n {
+        score[i][0] = 0;
+        for j in 1..=m {
+            let pair = if same(&seq[i - 1], &observed[j - 1].name) { 5 } else if one_letter(&seq[i - 1]) == 'X' { 0 } else { -3 };
+            score[i][j] = (score[i - 1][j - 1] + pair).max(score[i - 1][j] + gap(j)).max(score[i][j - 1] - 10);
+        }
+    }
+    let mut map = vec![None; m];
+    let (mut i, mut j) = (n, m);
+    while j > 0 {
+        let pair = if same(&seq[i.max(1) - 1], &observed[j - 1].name) { 5 } else if one_letter(&seq[i.max(1) - 1]) == 'X' { 0 } else { -3 };
+        if i > 0 && score[i][j] == score[i - 1][j - 1] + pair { map[j - 1] = Some(i - 1); i -= 1; j -= 1; }
+        else if i > 0 && score[i][j] == score[i - 1][j] + gap(j) { i -= 1; }
+        else { j -= 1; }
+    }
+    map
+}
+
+/// A run of sequence residues without coordinates, between observed residues `before` and
+/// `after` (indices into the structure's residues).
+struct Gap { chain: char, residues: Vec<String>, before: Option<usize>, after: Option<usize> }
+
+/// A rebuilt Cα trace and how well it fits.
+struct Trace { ca: Vec<[f64; 3]>, closure: f64, score: f64 }
+
+/// Heavy atoms a loop between `from` and `to` could reach, other than those of the two anchors.
+fn environment(s: &Structure, from: usize, to: usize, centre: [f64; 3], reach: f64) -> Vec<[f64; 3]> {
+    s.atoms.iter().filter(|a| a.residue != from && a.residue != to && a.element != "H" && !WATERS.contains(&s.residues[a.residue].name.as_str()) && dist(a.pos, centre) <= reach).map(|a| a.pos).collect()
+}
+
+/// Grows one Cα trace from `head` (the two atoms before the gap's first anchor, and the anchor)
+/// and closes it onto `target` by cyclic coordinate descent about the pseudo bonds.
+fn grow(head: [[f64; 3]; 3], target: [f64; 3], residues: &[String], method: Method, rng: &mut Rng) -> (Vec<[f64; 3]>, f64) {
+    let mut t = head.to_vec();
+    for r in residues {
+        let (theta, tau) = method.angles(r, rng);
+        let n = t.len();
+        t.push(place(t[n - 3], t[n - 2], t[n - 1], CA_CA, theta, tau));
+    }
+    // The end point stands for the anchor after the gap. Torsions alone can't move it toward or
+    // away from the first anchor when one residue is missing, so its angle is set by the span.
+    let n = t.len();
+    let theta = if residues.len() == 1 { 2.0 * (dist(head[2], target) / (2.0 * CA_CA)).min(1.0).asin() } else { method.angles("GLY", rng).0 };
+    t.push(place(t[n - 3], t[n - 2], t[n - 1], CA_CA, theta, rng.uniform() * std::f64::consts::TAU));
+    let last = t.len() - 1;
+    for _ in 0..MAX_SWEEPS {
+        for x in 1..last - 1 {
+            let (origin, axis) = (t[x], unit(sub(t[x + 1], t[x])));
+            let r = sub(t[last], origin);
+            let f = sub(target, origin);
+            let (r, f) = (sub(r, scale(axis, dot(r, axis))), sub(f, scale(axis, dot(f, axis))));
+            let alpha = dot(axis, cross(r, f)).atan2(dot(r, f));
+            for p in &mut t[x + 2..] { *p = rotate(*p, origin, axis, alpha); }
+        }
+        if dist(t[last], target) < CLOSED { break; }
+    }
+    let closure = dist(t[last], target);
+    (t[3..last].to_vec(), closure)
+}
+
+/// Penalty for a trace: closure, junction angles outside `ANGLE_RANGE`, and close contacts. The
+/// knowledge-based method adds how unlikely its angles are (see `Method::surprise`).
+fn score(ca: &[[f64; 3]], closure: f64, anchors: ([f64; 3], [f64; 3], Option<[f64; 3]>), env: &[[f64; 3]]) -> f64 {
+    let (first, last, next) = anchors;
+    let outside = |a: f64| if a < ANGLE_RANGE.0 { ANGLE_RANGE.0 - a } else if a > ANGLE_RANGE.1 { a - ANGLE_RANGE.1 } else { 0.0 };
+    let k = ca.len();
+    let mut penalty = 10.0 * closure + outside(angle(if k > 1 { ca[k - 2] } else { first }, ca[k - 1], last)) / 10.0;
+    if let Some(next) = next { penalty += outside(angle(ca[k - 1], last, next)) / 10.0; }
+    for (i, &p) in ca.iter().enumerate() {
+        penalty += env.iter().map(|&e| (CLASH + 0.5 - dist(p, e)).max(0.0).powi(2)).sum::<f64>();
+        // The trace against itself and the anchors, beyond bonded neighbours.
+        let chain = std::iter::once((0, first)).chain(ca.iter().enumerate().map(|(j, &q)| (j + 1, q))).chain(std::iter::once((k + 1, last)));
+        penalty += chain.filter(|&(j, _)| j.abs_diff(i + 1) > 1).map(|(_, q)| (4.0 - dist(p, q)).max(0.0).powi(2)).sum::<f64>();
+    }
+    penalty
+}
+
+/// The peptide between Cα atoms `a` and `b` turned `gamma` about their axis: C and O of `a`'s
+/// residue, N of `b`'s.
+fn peptide(a: [f64; 3], b: [f64; 3], gamma: f64) -> [[f64; 3]; 3] {
+    let x = unit(sub(b, a));
+    let r = perpendicular(x);
+    let y = add(scale(r, gamma.cos()), scale(cross(x, r), gamma.sin()));
+    let at = |p: [f64; 2]| add(a, add(scale(x, p[0]), scale(y, p[1])));
+    [at(PEPTIDE_C), at(PEPTIDE_O), at(PEPTIDE_N)]
+}
+
+/// How far a residue's backbone is from ideal: its N–Cα–C angle, and its φ and ψ from the
+/// nearest allowed region.
+fn strain(prev_c: [f64; 3], n: [f64; 3], ca: [f64; 3], c: [f64; 3], next_n: [f64; 3], glycine: bool) -> f64 {
+    let wrap = |d: f64| (d + 540.0).rem_euclid(360.0) - 180.0;
+    let (phi, psi) = (torsion(prev_c, n, ca, c), torsion(n, ca, c, next_n));
+    let region = RAMACHANDRAN.iter().map(|&(p, q, extra)| (wrap(phi - p).powi(2) + wrap(psi - q).powi(2)) / 1600.0 + if glycine { 0.0 } else { extra }).fold(f64::INFINITY, f64::min);
+    ((angle(n, ca, c) - N_CA_C) / 5.0).powi(2) + region
+}
+
+/// N, C and O of each residue inside `trace` (which runs from anchor to anchor). The rotations of
+/// all its peptide planes are chosen together (Viterbi over `ROTATIONS` steps) for the least
+/// total strain, with the first plane held to the first anchor's C and the last to the second
+/// anchor's N where those atoms exist.
+fn backbone(trace: &[[f64; 3]], residues: &[String], anchor_c: Option<[f64; 3]>, anchor_n: Option<[f64; 3]>) -> Vec<[[f64; 3]; 3]> {
+    let planes = trace.len() - 1;
+    let peptides: Vec<Vec<[[f64; 3]; 3]>> = (0..planes).map(|p| (0..ROTATIONS).map(|g| peptide(trace[p], trace[p + 1], g as f64 * std::f64::consts::TAU / ROTATIONS as f64)).collect()).collect();
+    let held = |p: usize, g: usize| {
+        let c = anchor_c.filter(|_| p == 0).map_or(0.0, |c| dist(peptides[p][g][0], c).powi(2));
+        c + anchor_n.filter(|_| p == planes - 1).map_or(0.0, |n| dist(peptides[p][g][2], n).powi(2))
+    };
+    let mut cost: Vec<f64> = (0..ROTATIONS).map(|g| 10.0 * held(0, g)).collect();
+    let mut back = vec![vec![0; ROTATIONS]; planes];
+    for p in 1..planes {
+        let glycine = standard(&residues[p - 1]) == "GLY";
+        let mut next = vec![f64::INFINITY; ROTATIONS];
+        for (g, best) in next.iter_mut().enumerate() {
+            let after = peptides[p][g];
+            for (h, before) in peptides[p - 1].iter().enumerate() {
+                let c = cost[h] + strain(before[0], before[2], trace[p], after[0], after[2], glycine);
+                if c < *best { *best = c; back[p][g] = h; }
+            }
+            *best += 10.0 * held(p, g);
+        }
+        cost = next;
+    }
+    let mut g = (0..ROTATIONS).min_by(|&a, &b| cost[a].total_cmp(&cost[b])).unwrap_or(0);
+    let mut chosen = vec![0; planes];
+    for p in (0..planes).rev() { chosen[p] = g; g = back[p][g]; }
+    (1..planes).map(|m| { let (before, after) = (peptides[m - 1][chosen[m - 1]], peptides[m][chosen[m]]); [before[2], after[0], after[1]] }).collect()
+}
+
+/// Cβ from the backbone, for an L-amino acid.
+pub fn beta(n: [f64; 3], ca: [f64; 3], c: [f64; 3]) -> [f64; 3] {
+    let (b, c) = (sub(ca, n), sub(c, ca));
+    let a = cross(b, c);
+    add(ca, add(add(scale(a, -0.582_734_31), scale(b, 0.568_028_27)), scale(c, -0.540_674_66)))
+}
+
+fn atom_line(name: &str, residue: &str, chain: char, number: i64, icode: char, p: [f64; 3]) -> String {
+    format!("ATOM  {:>5} {:<4} {residue:>3} {chain}{number:>4}{icode}   {:>8.3}{:>8.3}{:>8.3}{:>6.2}{:>6.2}          {:>2}", 0, format!(" {name}"), p[0], p[1], p[2], 0.0, 99.99, &name[..1])
+}
+
+/// Renumbers atom serials in file order and rewrites CONECT records to match.
+fn renumber(lines: Vec<String>) -> Vec<String> {
+    let mut serials: HashMap<i64, usize> = HashMap::new();
+    let mut next = 0;
+    let mut out = Vec::with_capacity(lines.len());
+    let with = |line: &str, serial: usize| format!("{}{serial:>5}{}", column(line, 0, 6), line.get(11..).unwrap_or(""));
+    for line in lines {
+        let old = column(&line, 6, 11).trim().parse::<i64>().ok();
+        match column(&line, 0, 6).trim_end() {
+            "ATOM" | "HETATM" => { next += 1; if let Some(o) = old { serials.insert(o, next); } out.push(with(&line, next)); }
+            "TER" => { next += 1; out.push(if line.len() >= 11 { with(&line, next) } else { line }); }
+            "ANISOU" => out.push(match old.and_then(|o| serials.get(&o)) { Some(&n) => with(&line, n), None => line }),
+            "CONECT" => {
+                let fields: Vec<String> = (0..5).map(|i| column(&line, 6 + 5 * i, 11 + 5 * i).to_string()).take_while(|f| !f.trim().is_empty()).collect();
+                let mapped: Option<Vec<usize>> = fields.iter().map(|f| f.trim().parse::<i64>().ok().and_then(|o| serials.get(&o).copied())).collect();
+                if let Some(m) = mapped { out.push(format!("CONECT{}", m.iter().map(|n| format!("{n:>5}")).collect::<String>())); }
+            }
+            _ => out.push(line),
+        }
+    }
+    out
+}
+
+#[derive(Deserialize)]
+pub struct LoopRequest { pub pdb: String, pub sequences: Option<HashMap<String, String>>, pub method: Option<String>, pub samples: Option<usize>, pub max_loop_length: Option<usize> }
+
+#[derive(Serialize)]
+pub struct Loop {
+    chain: String, position: &'static str, pub length: usize, sequence: String, #[serde(skip_serializing_if = "Option::is_none")] after_residue: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")] before_residue: Option<String>, pub modeled: bool, #[serde(skip_serializing_if = "Vec::is_empty")] residues: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub closure_angstrom: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] clashes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub reason: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct LoopResponse { model_id: String, method: &'static str, chains: usize, residues_missing: usize, residues_built: usize, pub loops: Vec<Loop>, pub warnings: Vec<String>, pub pdb: String, elapsed_us: u128 }
+
+/// The gaps of every chain with a sequence, in chain order.
+fn gaps(s: &Structure, sequences: &HashMap<char, Vec<String>>, warnings: &mut Vec<String>) -> Vec<Gap> {
+    let mut out = Vec::new();
+    let mut chains: Vec<&char> = sequences.keys().collect();
+    chains.sort();
+    for &chain in chains {
+        let seq = &sequences[&chain];
+        let observed: Vec<(usize, &Residue)> = s.residues.iter().enumerate().filter(|(_, r)| r.chain == chain).collect();
+        if observed.is_empty() { warnings.push(format!("chain {chain} has a sequence but no coordinates")); continue; }
+        let residues: Vec<&Residue> = observed.iter().map(|(_, r)| *r).collect();
+        let map: Vec<Option<usize>> = match by_numbering(seq, &residues) {
+            Some(map) => map.into_iter().map(Some).collect(),
+            None if seq.len() * residues.len() > MAX_ALIGNMENT => { warnings.push(format!("chain {chain} is too long to align; skipped")); continue; }
+            None => by_alignment(seq, &residues),
+        };
+        let unmatched: Vec<String> = residues.iter().zip(&map).filter(|(_, m)| m.is_none()).map(|(r, _)| r.label()).collect();
+        if !unmatched.is_empty() { warnings.push(format!("chain {chain}: {} observed residue(s) are not in its sequence ({})", unmatched.len(), unmatched.iter().take(5).cloned().collect::<Vec<_>>().join(", "))); }
+        let mut at: Vec<Option<usize>> = vec![None; seq.len()];
+        for ((index, _), m) in observed.iter().zip(&map) { if let Some(i) = m { at[*i] = Some(*index); } }
+        let mut i = 0;
+        while i < seq.len() {
+            if at[i].is_some() { i += 1; continue; }
+            let start = i;
+            while i < seq.len() && at[i].is_none() { i += 1; }
+            out.push(Gap { chain, residues: seq[start..i].to_vec(), before: at[..start].iter().rev().find_map(|&a| a), after: at[i..].iter().find_map(|&a| a) });
+        }
+        // Consecutive residues too far apart with nothing missing between them.
+        for w in observed.windows(2) {
+            let (a, b) = (w[0].1, w[1].1);
+            if let (Some(p), Some(q)) = (a.ca, b.ca) {
+                if dist(p, q) > BROKEN && !out.iter().any(|g| g.before == Some(w[0].0) && g.after == Some(w[1].0)) { warnings.push(format!("chain break between {} and {} ({:.1} Å) with no residues missing from the sequence", a.label(), b.label(), dist(p, q))); }
+            }
+        }
+    }
+    out
+}
+
+pub async fn model(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<LoopRequest>) -> Result<Json<LoopResponse>, ApiError> {
+    let meter = usage::Meter::start();
+    let resp = build(req).map_err(|e| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e })))?;
+    record(&s, &headers, "model_loops", &format!("{} residues built", resp.residues_built), FOLD_MODEL, &resp.model_id, &meter, &resp);
+    Ok(Json(resp))
+}
+
+/// Finds and rebuilds the gaps of `req.pdb`; homology modeling uses it on threaded models.
+pub fn build(req: LoopRequest) -> Result<LoopResponse, String> {
+    let t = Instant::now();
+    let method = match req.method.as_deref().unwrap_or("knowledge") { "knowledge" => Method::Knowledge, "ab_initio" => Method::AbInitio, other => return Err(format!("unknown method {other}; expected knowledge or ab_initio")) };
+    let samples = req.samples.unwrap_or(if method == Method::Knowledge { 200 } else { 500 });
+    if !(1..=MAX_SAMPLES).contains(&samples) { return Err(format!("samples must be between 1 and {MAX_SAMPLES}")); }
+    let max_loop = req.max_loop_length.unwrap_or(DEFAULT_MAX_LOOP);
+    let st = parse(&req.pdb);
+    if st.atoms.is_empty() { return Err("the PDB file has no ATOM or HETATM records".into()); }
+    let mut warnings = Vec::new();
+    if st.models > 1 { warnings.push(format!("{} models; only the first is modeled and returned", st.models)); }
+    let mut sequences = st.seqres.clone();
+    for (chain, seq) in req.sequences.unwrap_or_default() {
+        let id = chain.chars().next().filter(|_| chain.chars().count() == 1).ok_or_else(|| format!("sequence key {chain} is not a one-character chain ID"))?;
+        let residues = seq.chars().filter(|c| !c.is_whitespace()).map(|c| AMINO.iter().position(|&a| a as char == c.to_ascii_uppercase()).map_or("UNK", |i| THREE_LETTER[i]).to_string()).collect();
+        sequences.insert(id, residues);
+    }
+    if sequences.is_empty() { return Err("the PDB file has no SEQRES records; give the chains' sequences in `sequences`".into()); }
+    let found = gaps(&st, &sequences, &mut warnings);
+
+    let mut inserts: HashMap<usize, Vec<String>> = HashMap::new();
+    let (mut loops, mut remarks) = (Vec::new(), Vec::new());
+    let seed = fnv1a(req.pdb.as_bytes());
+    for (g, gap) in found.iter().enumerate() {
+        let sequence: String = gap.residues.iter().map(|r| one_letter(r)).collect();
+        let label = |i: Option<usize>| i.map(|i| st.residues[i].label());
+        let mut out = Loop {
+            chain: gap.chain.to_string(), position: match (gap.before, gap.after) { (Some(_), Some(_)) => "internal", (None, _) => "n_terminal", (_, None) => "c_terminal" },
+            length: gap.residues.len(), sequence, after_residue: label(gap.before), before_residue: label(gap.after), modeled: false, residues: Vec::new(), closure_angstrom: None, clashes: None, reason: None,
+        };
+        let (Some(from), Some(to)) = (gap.before, gap.after) else {
+            out.reason = Some("terminal residues have no anchor to close onto".into());
+            loops.push(out);
+            continue;
+        };
+        let (first, last) = (&st.residues[from], &st.residues[to]);
+        let k = gap.residues.len();
+        let (Some(ca_from), Some(ca_to)) = (first.ca, last.ca) else {
+            out.reason = Some("an anchor residue has no Cα".into());
+            loops.push(out);
+            continue;
+        };
+        let span = dist(ca_from, ca_to);
+        if k > max_loop { out.reason = Some(format!("longer than max_loop_length ({max_loop})")); loops.push(out); continue; }
+        if span > CA_CA * (k + 1) as f64 * 0.95 { out.reason = Some(format!("the anchors are {span:.1} Å apart, too far for {k} residue(s) to span")); loops.push(out); continue; }
+        // Numbers for the new residues: free numbers between the anchors, else insertion codes.
+        let free = last.number - first.number > k as i64 && first.icode == ' ';
+        if !free && k > 26 { out.reason = Some("the anchors' numbering leaves no room and more than 26 insertion codes would be needed".into()); loops.push(out); continue; }
+        let numbering: Vec<(i64, char)> = (0..k).map(|m| if free { (first.number + 1 + m as i64, ' ') } else { (first.number, (b'A' + m as u8) as char) }).collect();
+        // The two Cα atoms before the first anchor orient the trace, invented if absent.
+        let ca_of = |i: Option<usize>| i.and_then(|i| st.residues.get(i)).filter(|r| r.chain == gap.chain).and_then(|r| r.ca);
+        let prev = ca_of(from.checked_sub(1)).filter(|&p| dist(p, ca_from) < BROKEN).unwrap_or_else(|| sub(ca_from, scale(perpendicular(sub(ca_to, ca_from)), CA_CA)));
+        let prev2 = ca_of(from.checked_sub(2)).filter(|&p| dist(p, prev) < BROKEN).unwrap_or_else(|| sub(prev, scale(unit(sub(ca_to, ca_from)), CA_CA)));
+        let next = ca_of(Some(to + 1)).filter(|&n| dist(n, ca_to) < BROKEN);
+        let centre = scale(add(ca_from, ca_to), 0.5);
+        let env = environment(&st, from, to, centre, span / 2.0 + CA_CA * (k + 1) as f64 / 2.0 + CLASH);
+        let mut rng = Rng(seed ^ (g as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1);
+        let best = (0..samples).map(|_| {
+            let (ca, closure) = grow([prev2, prev, ca_from], ca_to, &gap.residues, method, &mut rng);
+            let context: Vec<[f64; 3]> = [prev, ca_from].into_iter().chain(ca.iter().copied()).chain(std::iter::once(ca_to)).chain(next).collect();
+            let surprise: f64 = gap.residues.iter().enumerate().map(|(m, r)| {
+                let q = m + 2;
+                method.surprise(r, angle(context[q - 1], context[q], context[q + 1]), context.get(q + 2).map(|&d| torsion(context[q - 1], context[q], context[q + 1], d)))
+            }).sum();
+            let score = score(&ca, closure, (ca_from, ca_to, next), &env) + surprise;
+            Trace { ca, closure, score }
+        }).min_by(|a, b| a.score.total_cmp(&b.score));
+        let Some(best) = best.filter(|b| b.closure <= MAX_CLOSURE) else {
+            out.reason = Some(format!("no trace closed within {MAX_CLOSURE} Å"));
+            loops.push(out);
+            continue;
+        };
+        let atom = |residue: usize, name: &str| st.atoms.iter().find(|a| a.residue == residue && a.name == name).map(|a| a.pos);
+        let trace: Vec<[f64; 3]> = std::iter::once(ca_from).chain(best.ca.iter().copied()).chain(std::iter::once(ca_to)).collect();
+        let mut lines = Vec::new();
+        for (m, [n, c, o]) in backbone(&trace, &gap.residues, atom(from, "C"), atom(to, "N")).into_iter().enumerate() {
+            let (name, (number, icode)) = (&gap.residues[m], numbering[m]);
+            let mut atoms = vec![("N", n), ("CA", best.ca[m]), ("C", c), ("O", o)];
+            if standard(name) != "GLY" { atoms.push(("CB", beta(n, best.ca[m], c))); }
+            lines.extend(atoms.into_iter().map(|(a, p)| atom_line(a, name, gap.chain, number, icode, p)));
+            out.residues.push(format!("{}{number}{icode}", gap.chain).trim_end().into());
+        }
+        inserts.entry(last.first_line).or_default().extend(lines);
+        remarks.push(format!("REMARK 999 MODELED LOOP CHAIN {} {}-{} ({k} RESIDUES, {})", gap.chain, out.residues[0], out.residues[k - 1], method.name().to_uppercase()));
+        out.clashes = Some(best.ca.iter().filter(|&&p| env.iter().any(|&e| dist(p, e) < CLASH)).count());
+        out.closure_angstrom = Some(round(best.closure));
+        out.modeled = true;
+        loops.push(out);
+    }
+
+    let residues_built = loops.iter().filter(|l| l.modeled).map(|l| l.length).sum();
+    let mut lines = Vec::with_capacity(st.lines.len() + remarks.len() + 1);
+    let first_coordinate = st.lines.iter().position(|l| matches!(column(l, 0, 6).trim_end(), "ATOM" | "HETATM" | "MODEL")).unwrap_or(0);
+    for (i, line) in st.lines.iter().enumerate() {
+        if i == first_coordinate && !remarks.is_empty() {
+            lines.push("REMARK 999 REBUILT RESIDUES HAVE OCCUPANCY 0.00 AND B-FACTOR 99.99".into());
+            lines.append(&mut remarks);
+        }
+        if let Some(new) = inserts.remove(&i) { lines.extend(new); }
+        lines.push(line.clone());
+    }
+    let chains: HashSet<char> = st.residues.iter().map(|r| r.chain).filter(|&c| c != '\0').collect();
+    let resp = LoopResponse {
+        model_id: uuid::Uuid::new_v4().to_string(), method: method.name(), chains: chains.len(), residues_missing: loops.iter().map(|l| l.length).sum(), residues_built, loops, warnings,
+        pdb: renumber(lines).join("\n") + "\n", elapsed_us: t.elapsed().as_micros(),
+    };
+    Ok(resp)
+}
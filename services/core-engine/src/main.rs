@@ -1,124 +1 @@
-use axum::{extract::State, response::Json, routing::{get, post}, Router};
-use serde::{Deserialize, Serialize};
-use std::sync::{Arc, Mutex};
-use std::time::Instant;
-use tower_http::cors::{Any, CorsLayer};
-use tower_http::trace::TraceLayer;
-
-struct AppState { start_time: Instant, stats: Mutex<Stats> }
-struct Stats { total_simulations: u64, total_screenings: u64, total_predictions: u64, molecules_analyzed: u64 }
-
-#[derive(Serialize)]
-struct Health { status: String, version: String, uptime_secs: u64, total_ops: u64 }
-
-#[derive(Deserialize)]
-struct SimulateRequest { molecule: String, simulation_type: Option<String>, steps: Option<u64>, temperature_k: Option<f64> }
-#[derive(Serialize)]
-struct SimulateResponse { sim_id: String, molecule: String, simulation_type: String, steps: u64, sdf_field_resolution: u32, energy_kcal_mol: f64, rmsd_angstrom: f64, folding_state: String, elapsed_us: u128 }
-
-#[derive(Deserialize)]
-struct ScreenRequest { target_protein: String, library_size: Option<u32>, binding_threshold: Option<f64> }
-#[derive(Serialize)]
-struct ScreenResponse { screen_id: String, target: String, library_screened: u32, hits: Vec<ScreenHit>, hit_rate_pct: f64, elapsed_us: u128 }
-#[derive(Serialize)]
-struct ScreenHit { compound_id: String, binding_affinity_nm: f64, selectivity_score: f64, drug_likeness: f64 }
-
-#[derive(Deserialize)]
-struct PredictRequest { sequence: String, prediction_type: Option<String> }
-#[derive(Serialize)]
-struct PredictResponse { prediction_id: String, sequence_length: usize, prediction_type: String, structure_confidence: f64, sdf_representation_bytes: u64, secondary_structure: String, domains: Vec<DomainInfo>, elapsed_us: u128 }
-#[derive(Serialize)]
-struct DomainInfo { name: String, start: usize, end: usize, domain_type: String, confidence: f64 }
-
-#[derive(Deserialize)]
-struct EnergyRequest { molecule: String, force_field: Option<String> }
-#[derive(Serialize)]
-struct EnergyResponse { molecule: String, force_field: String, total_energy_kcal: f64, bond_energy: f64, angle_energy: f64, dihedral_energy: f64, vdw_energy: f64, electrostatic_energy: f64, solvation_energy: f64 }
-
-#[derive(Serialize)]
-struct StatsResponse { total_simulations: u64, total_screenings: u64, total_predictions: u64, molecules_analyzed: u64 }
-
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt().with_env_filter(tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "bio_engine=info".into())).init();
-    let state = Arc::new(AppState { start_time: Instant::now(), stats: Mutex::new(Stats { total_simulations: 0, total_screenings: 0, total_predictions: 0, molecules_analyzed: 0 }) });
-    let cors = CorsLayer::new().allow_origin(Any).allow_methods(Any).allow_headers(Any);
-    let app = Router::new()
-        .route("/health", get(health))
-        .route("/api/v1/bio/simulate", post(simulate))
-        .route("/api/v1/bio/screen", post(screen))
-        .route("/api/v1/bio/predict", post(predict))
-        .route("/api/v1/bio/energy", post(energy))
-        .route("/api/v1/bio/stats", get(stats))
-        .layer(cors).layer(TraceLayer::new_for_http()).with_state(state);
-    let addr = std::env::var("BIO_ADDR").unwrap_or_else(|_| "0.0.0.0:8081".into());
-    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    tracing::info!("Bio Engine on {addr}");
-    axum::serve(listener, app).await.unwrap();
-}
-
-async fn health(State(s): State<Arc<AppState>>) -> Json<Health> {
-    let st = s.stats.lock().unwrap();
-    Json(Health { status: "ok".into(), version: env!("CARGO_PKG_VERSION").into(), uptime_secs: s.start_time.elapsed().as_secs(), total_ops: st.total_simulations + st.total_screenings + st.total_predictions })
-}
-
-async fn simulate(State(s): State<Arc<AppState>>, Json(req): Json<SimulateRequest>) -> Json<SimulateResponse> {
-    let t = Instant::now();
-    let sim_type = req.simulation_type.unwrap_or_else(|| "molecular-dynamics".into());
-    let steps = req.steps.unwrap_or(10_000);
-    let temp = req.temperature_k.unwrap_or(310.15); // body temperature
-    let h = fnv1a(req.molecule.as_bytes());
-    let energy = -100.0 - (h % 500) as f64;
-    let rmsd = (h % 30) as f64 * 0.1 + 0.5;
-    { let mut st = s.stats.lock().unwrap(); st.total_simulations += 1; st.molecules_analyzed += 1; }
-    Json(SimulateResponse { sim_id: uuid::Uuid::new_v4().to_string(), molecule: req.molecule, simulation_type: sim_type, steps, sdf_field_resolution: 128, energy_kcal_mol: energy, rmsd_angstrom: rmsd, folding_state: if rmsd < 2.0 { "folded".into() } else { "partially_folded".into() }, elapsed_us: t.elapsed().as_micros() })
-}
-
-async fn screen(State(s): State<Arc<AppState>>, Json(req): Json<ScreenRequest>) -> Json<ScreenResponse> {
-    let t = Instant::now();
-    let lib_size = req.library_size.unwrap_or(10_000);
-    let threshold = req.binding_threshold.unwrap_or(100.0); // nM
-    let h = fnv1a(req.target_protein.as_bytes());
-    let hit_count = (lib_size as f64 * 0.005) as usize; // ~0.5% hit rate
-    let hits: Vec<ScreenHit> = (0..hit_count.min(20)).map(|i| {
-        let affinity = (h.wrapping_add(i as u64) % 100) as f64 + 1.0;
-        ScreenHit { compound_id: format!("ALICE-{:06}", h.wrapping_add(i as u64) % 999999), binding_affinity_nm: affinity, selectivity_score: 0.7 + (h.wrapping_add(i as u64) % 30) as f64 * 0.01, drug_likeness: 0.5 + (h.wrapping_add(i as u64 * 7) % 50) as f64 * 0.01 }
-    }).collect();
-    { let mut st = s.stats.lock().unwrap(); st.total_screenings += 1; st.molecules_analyzed += lib_size as u64; }
-    Json(ScreenResponse { screen_id: uuid::Uuid::new_v4().to_string(), target: req.target_protein, library_screened: lib_size, hits, hit_rate_pct: 0.5, elapsed_us: t.elapsed().as_micros() })
-}
-
-async fn predict(State(s): State<Arc<AppState>>, Json(req): Json<PredictRequest>) -> Json<PredictResponse> {
-    let t = Instant::now();
-    let pred_type = req.prediction_type.unwrap_or_else(|| "structure".into());
-    let seq_len = req.sequence.len();
-    let h = fnv1a(req.sequence.as_bytes());
-    let confidence = 0.70 + (h % 25) as f64 * 0.01;
-    let sdf_bytes = seq_len as u64 * 128; // SDF representation
-    let domains = vec![
-        DomainInfo { name: "kinase_domain".into(), start: 0, end: seq_len / 3, domain_type: "catalytic".into(), confidence: confidence + 0.05 },
-        DomainInfo { name: "binding_domain".into(), start: seq_len / 3, end: seq_len * 2 / 3, domain_type: "regulatory".into(), confidence },
-    ];
-    s.stats.lock().unwrap().total_predictions += 1;
-    Json(PredictResponse { prediction_id: uuid::Uuid::new_v4().to_string(), sequence_length: seq_len, prediction_type: pred_type, structure_confidence: confidence, sdf_representation_bytes: sdf_bytes, secondary_structure: "HHHHCCCEEEEECCCHHHHH".into(), domains, elapsed_us: t.elapsed().as_micros() })
-}
-
-async fn energy(State(s): State<Arc<AppState>>, Json(req): Json<EnergyRequest>) -> Json<EnergyResponse> {
-    let ff = req.force_field.unwrap_or_else(|| "amber-ff14".into());
-    let h = fnv1a(req.molecule.as_bytes());
-    let bond = -50.0 - (h % 100) as f64;
-    let angle = -20.0 - (h % 50) as f64;
-    let dihedral = -10.0 - (h % 30) as f64;
-    let vdw = -30.0 - (h % 80) as f64;
-    let elec = -15.0 - (h % 40) as f64;
-    let solv = -5.0 - (h % 20) as f64;
-    s.stats.lock().unwrap().molecules_analyzed += 1;
-    Json(EnergyResponse { molecule: req.molecule, force_field: ff, total_energy_kcal: bond + angle + dihedral + vdw + elec + solv, bond_energy: bond, angle_energy: angle, dihedral_energy: dihedral, vdw_energy: vdw, electrostatic_energy: elec, solvation_energy: solv })
-}
-
-async fn stats(State(s): State<Arc<AppState>>) -> Json<StatsResponse> {
-    let st = s.stats.lock().unwrap();
-    Json(StatsResponse { total_simulations: st.total_simulations, total_screenings: st.total_screenings, total_predictions: st.total_predictions, molecules_analyzed: st.molecules_analyzed })
-}
-
-fn fnv1a(data: &[u8]) -> u64 { let mut h: u64 = 0xcbf2_9ce4_8422_2325; for &b in data { h ^= b as u64; h = h.wrapping_mul(0x0100_0000_01b3); } h }
+fn main() { bio_engine::service() }
@@ -0,0 +1,208 @@
+//! Well-tempered metadynamics.
+//!
+//! One or two collective variables (see `cv`) are biased by Gaussian hills deposited every
+//! `pace` steps of Langevin dynamics on the engine's force field. Hill heights are tempered,
+//! h = h₀·exp(−V(s)/(k_B·ΔT)) with ΔT = (γ − 1)·T for bias factor γ, so the bias converges and
+//! the free-energy surface is F(s) = −γ/(γ − 1)·V(s), zeroed at its minimum. The surface is
+//! reconstructed on a grid: dihedrals over the full circle, angles over 0–180°, distances
+//! over the sampled range plus three hill widths. Convergence is tracked by rebuilding the
+//! surface from the hills deposited by each tenth of the run and measuring how much it still
+//! changes where F is under `CONVERGENCE_CUTOFF`; a run has converged when the last change is
+//! small and the tempered hills have shrunk to under half their initial height. The grid of
+//! each run is kept under the caller's project for download.
+
+use axum::{extract::{Path, Query, State}, http::{header, HeaderMap, StatusCode}, response::{IntoResponse, Json, Response}};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+
+use crate::{chem, conformer, cv::{Cv, CvSpec}, fnv1a, forcefield::System, frame, not_found, projects, resolver, restraints::Restraints, retention::{self, Held, Keyed, Kept}, umbrella::KB, ApiError, AppState, ErrorResponse, SimulateResponse};
+
+const DEFAULT_HEIGHT: f64 = 0.3;
+const DEFAULT_PACE: usize = 100;
+const DEFAULT_BIAS_FACTOR: f64 = 10.0;
+const DEFAULT_STEPS: usize = 50_000;
+const MAX_STEPS: usize = 500_000;
+/// Free energy (kcal/mol) below which convergence is judged; higher regions are barely visited.
+const CONVERGENCE_CUTOFF: f64 = 10.0;
+const CONVERGED_RMS: f64 = 0.2;
+const SNAPSHOTS: usize = 10;
+
+#[derive(Deserialize, Clone)]
+pub struct Metadynamics { pub collective_variables: Vec<CvSpec>, pub hill_height: Option<f64>, pub hill_widths: Option<Vec<f64>>, pub pace: Option<usize>, pub bias_factor: Option<f64>, pub steps: Option<usize>, pub bins: Option<usize> }
+
+/// A free-energy surface on a regular grid; values are row-major with the last variable fastest.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FesGrid { pub collective_variables: Vec<String>, pub units: Vec<String>, pub axes: Vec<Vec<f64>>, pub free_energy_kcal_mol: Vec<f64> }
+
+#[derive(Serialize, Clone)]
+pub struct ConvergencePoint { pub step: usize, pub hills: usize, pub hill_height_kcal_mol: f64, pub fes_rms_change_kcal_mol: Option<f64> }
+
+#[derive(Serialize, Clone)]
+pub struct Metad {
+    pub collective_variables: Vec<String>, pub units: Vec<String>, pub hill_height_kcal_mol: f64, pub hill_widths: Vec<f64>, pub pace: usize, pub bias_factor: f64, pub steps: usize, pub hills: usize,
+    pub final_hill_height_kcal_mol: f64, pub bins: Vec<usize>, pub ranges: Vec<[f64; 2]>, pub minimum: Vec<f64>, pub free_energy_span_kcal_mol: f64, pub convergence: Vec<ConvergencePoint>, pub converged: bool, pub fes_url: String,
+    #[serde(skip)] pub grid: FesGrid,
+}
+
+struct Hill { centre: Vec<f64>, height: f64, step: usize }
+
+/// Bias of `hills` at CV values `s`, and its derivative along each variable.
+fn bias(cvs: &[Cv], widths: &[f64], hills: &[Hill], s: &[f64]) -> (f64, Vec<f64>) {
+    let mut v = 0.0;
+    let mut dv = vec![0.0; s.len()];
+    for h in hills {
+        let d: Vec<f64> = cvs.iter().zip(s).zip(&h.centre).map(|((cv, &a), &b)| cv.delta(a, b)).collect();
+        let e = h.height * (-d.iter().zip(widths).map(|(d, w)| d * d / (2.0 * w * w)).sum::<f64>()).exp();
+        v += e;
+        for k in 0..s.len() { dv[k] -= e * d[k] / (widths[k] * widths[k]); }
+    }
+    (v, dv)
+}
+
+/// Free energy on the grid points from `hills`, zeroed at its minimum.
+fn surface(cvs: &[Cv], widths: &[f64], hills: &[Hill], points: &[Vec<f64>], gamma: f64) -> Vec<f64> {
+    let f: Vec<f64> = points.iter().map(|p| -gamma / (gamma - 1.0) * bias(cvs, widths, hills, p).0).collect();
+    let min = f.iter().copied().fold(f64::INFINITY, f64::min);
+    f.iter().map(|x| x - min).collect()
+}
+
+pub fn run(spec: &Metadynamics, mol: &resolver::Resolved, temperature_k: f64, sim_id: &str, user: &Restraints) -> Result<Metad, String> {
+    let smiles = mol.canonical_smiles.as_deref().ok_or_else(|| format!("can't resolve {} to a structure for metadynamics", mol.input))?;
+    let m = chem::parse_smiles(smiles)?;
+    if !(1..=2).contains(&spec.collective_variables.len()) { return Err("metadynamics takes one or two collective_variables".into()); }
+    let cvs: Vec<Cv> = spec.collective_variables.iter().map(|c| Cv::parse(c, m.atoms.len())).collect::<Result<_, _>>()?;
+    let widths_api = spec.hill_widths.clone().unwrap_or_else(|| cvs.iter().map(|cv| if matches!(cv, Cv::Distance(_)) { 0.1 } else { 10.0 }).collect());
+    if widths_api.len() != cvs.len() || widths_api.iter().any(|w| w.is_nan() || *w <= 0.0) { return Err("hill_widths needs one positive width per collective variable".into()); }
+    let widths: Vec<f64> = cvs.iter().zip(&widths_api).map(|(cv, &w)| cv.to_internal(w)).collect();
+    let height = spec.hill_height.unwrap_or(DEFAULT_HEIGHT);
+    let gamma = spec.bias_factor.unwrap_or(DEFAULT_BIAS_FACTOR);
+    let pace = spec.pace.unwrap_or(DEFAULT_PACE).max(1);
+    let steps = spec.steps.unwrap_or(DEFAULT_STEPS);
+    if height.is_nan() || height <= 0.0 { return Err("hill_height must be positive".into()); }
+    if gamma.is_nan() || gamma <= 1.0 { return Err("bias_factor must be greater than 1".into()); }
+    if steps > MAX_STEPS || steps < pace * SNAPSHOTS { return Err(format!("steps must be between {} and {MAX_STEPS}", pace * SNAPSHOTS)); }
+    let bins = spec.bins.unwrap_or(if cvs.len() == 1 { 100 } else { 50 }).clamp(10, if cvs.len() == 1 { 1000 } else { 200 });
+
+    let restraints = conformer::restraints(&m);
+    let seed = fnv1a(smiles.as_bytes());
+    let mut x = conformer::embed(&m, seed);
+    let reference = x.clone();
+    let hills: RefCell<Vec<Hill>> = RefCell::new(Vec::new());
+    let potential = |x: &[[f64; 3]], mut grad: Option<&mut [[f64; 3]]>| {
+        let e = user.energy(&reference, x, grad.as_deref_mut());
+        let values: Vec<_> = cvs.iter().map(|cv| cv.gradient(x)).collect();
+        let s: Vec<f64> = values.iter().map(|v| v.0).collect();
+        let (v, dv) = bias(&cvs, &widths, &hills.borrow(), &s);
+        if let Some(grad) = grad {
+            for (k, (_, g)) in values.iter().enumerate() { for &(i, gi) in g { for c in 0..3 { grad[i][c] += dv[k] * gi[c]; } } }
+        }
+        e + v
+    };
+    let system = System { restraints: &restraints, receptor: &frame::EMPTY, metals: None, anchor: &[], k_pos: 0.0, bias: Some(&potential) };
+    let delta_t = (gamma - 1.0) * temperature_k;
+    let mut visited: Vec<[f64; 2]> = vec![[f64::INFINITY, f64::NEG_INFINITY]; cvs.len()];
+    system.sample(&mut x, steps, temperature_k, seed, &mut |step, x| {
+        if (step + 1) % pace != 0 { return; }
+        let s: Vec<f64> = cvs.iter().map(|cv| cv.value(x)).collect();
+        for (r, &v) in visited.iter_mut().zip(&s) { r[0] = r[0].min(v); r[1] = r[1].max(v); }
+        let v = bias(&cvs, &widths, &hills.borrow(), &s).0;
+        hills.borrow_mut().push(Hill { height: height * (-v / (KB * delta_t)).exp(), centre: s, step: step + 1 });
+    });
+    let hills = hills.into_inner();
+
+    // Grid axes in internal units.
+    let axes: Vec<(f64, f64, bool)> = cvs.iter().zip(&visited).zip(&widths).map(|((cv, r), w)| match cv {
+        Cv::Dihedral(_) => (-std::f64::consts::PI, std::f64::consts::PI, true),
+        Cv::Angle(_) => (0.0, std::f64::consts::PI, false),
+        Cv::Distance(_) => ((r[0] - 3.0 * w).max(0.0), r[1] + 3.0 * w, false),
+    }).collect();
+    let centres: Vec<Vec<f64>> = axes.iter().map(|&(lo, hi, wrap)| {
+        let width = (hi - lo) / if wrap { bins } else { bins - 1 } as f64;
+        (0..bins).map(|b| lo + width * b as f64).collect()
+    }).collect();
+    let points: Vec<Vec<f64>> = match centres.as_slice() {
+        [a] => a.iter().map(|&x| vec![x]).collect(),
+        [a, b] => a.iter().flat_map(|&x| b.iter().map(move |&y| vec![x, y])).collect(),
+        _ => unreachable!(),
+    };
+    let fes = surface(&cvs, &widths, &hills, &points, gamma);
+
+    let mut convergence = Vec::with_capacity(SNAPSHOTS);
+    let mut previous: Option<Vec<f64>> = None;
+    for k in 1..=SNAPSHOTS {
+        let step = steps * k / SNAPSHOTS;
+        let upto = hills.partition_point(|h| h.step <= step);
+        let snap = if k == SNAPSHOTS { fes.clone() } else { surface(&cvs, &widths, &hills[..upto], &points, gamma) };
+        let change = previous.as_ref().map(|p| {
+            let diffs: Vec<f64> = snap.iter().zip(p).zip(&fes).filter(|(_, &f)| f < CONVERGENCE_CUTOFF).map(|((a, b), _)| (a - b).powi(2)).collect();
+            ((diffs.iter().sum::<f64>() / diffs.len().max(1) as f64).sqrt() * 1e3).round() / 1e3
+        });
+        convergence.push(ConvergencePoint { step, hills: upto, hill_height_kcal_mol: hills[..upto].last().map_or(height, |h| (h.height * 1e4).round() / 1e4), fes_rms_change_kcal_mol: change });
+        previous = Some(snap);
+    }
+    let converged = convergence.last().and_then(|c| c.fes_rms_change_kcal_mol).is_some_and(|c| c < CONVERGED_RMS) && hills.last().is_some_and(|h| h.height < 0.5 * height);
+    let best = fes.iter().enumerate().min_by(|a, b| a.1.total_cmp(b.1)).map_or(0, |(i, _)| i);
+    let round = |v: f64| (v * 1e3).round() / 1e3;
+    let grid = FesGrid {
+        collective_variables: cvs.iter().map(|cv| cv.label()).collect(), units: cvs.iter().map(|cv| cv.unit().to_string()).collect(),
+        axes: cvs.iter().zip(&centres).map(|(cv, c)| c.iter().map(|&v| round(cv.to_api(v))).collect()).collect(),
+        free_energy_kcal_mol: fes.iter().map(|&f| round(f)).collect(),
+    };
+    Ok(Metad {
+        collective_variables: grid.collective_variables.clone(), units: grid.units.clone(), hill_height_kcal_mol: height, hill_widths: widths_api, pace, bias_factor: gamma, steps, hills: hills.len(),
+        final_hill_height_kcal_mol: hills.last().map_or(0.0, |h| (h.height * 1e4).round() / 1e4), bins: vec![bins; cvs.len()],
+        ranges: cvs.iter().zip(&axes).map(|(cv, &(lo, hi, _))| [round(cv.to_api(lo)), round(cv.to_api(hi))]).collect(),
+        minimum: cvs.iter().zip(&points[best]).map(|(cv, &v)| round(cv.to_api(v))).collect(), free_energy_span_kcal_mol: round(fes.iter().copied().fold(0.0, f64::max)),
+        convergence, converged, fes_url: format!("/api/v1/bio/simulations/{sim_id}/fes"), grid,
+    })
+}
+
+/// Metadynamics surfaces per (project, simulation ID).
+pub struct FesStore { grids: Mutex<Keyed<FesGrid>> }
+
+impl FesStore {
+    pub fn new() -> Self { Self { grids: Mutex::new(Keyed::new()) } }
+    pub fn held(&self, project: &str) -> Held { retention::held(&self.grids.lock().unwrap(), project) }
+    pub fn expire(&self, before: u64) -> Held { retention::expire(&mut self.grids.lock().unwrap(), before) }
+    pub fn of_project(&self, project: &str) -> Vec<(String, FesGrid)> { retention::of_project(&self.grids.lock().unwrap(), project) }
+    pub fn import(&self, project: &str, sim_id: &str, grid: FesGrid) -> bool { retention::insert_new(&mut self.grids.lock().unwrap(), project, sim_id, grid) }
+}
+
+/// Keep a simulation's free-energy surface under the caller's project.
+pub fn persist(s: &AppState, headers: &HeaderMap, resp: &SimulateResponse) {
+    if let Some(m) = &resp.metadynamics { s.fes.grids.lock().unwrap().insert((projects::project_id(headers), resp.sim_id.clone()), Kept::new(m.grid.clone())); }
+}
+
+/// PLUMED-style text: a `#!` header, then one grid point per line, rows separated by a blank line.
+fn to_dat(g: &FesGrid) -> String {
+    let names: Vec<String> = g.collective_variables.iter().map(|c| c.replace(' ', "_")).collect();
+    let mut out = format!("#! FIELDS {} free_energy\n#! UNITS {} kcal/mol\n", names.join(" "), g.units.join(" "));
+    match g.axes.as_slice() {
+        [a] => for (x, f) in a.iter().zip(&g.free_energy_kcal_mol) { out.push_str(&format!("{x:>12.4} {f:>12.4}\n")); },
+        [a, b] => for (i, x) in a.iter().enumerate() {
+            for (j, y) in b.iter().enumerate() { out.push_str(&format!("{x:>12.4} {y:>12.4} {:>12.4}\n", g.free_energy_kcal_mol[i * b.len() + j])); }
+            out.push('\n');
+        },
+        _ => {}
+    }
+    out
+}
+
+#[derive(Deserialize)]
+pub struct FesQuery { format: Option<String> }
+
+pub async fn get(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(sim_id): Path<String>, Query(q): Query<FesQuery>) -> Result<Response, ApiError> {
+    let project = projects::project_id(&headers);
+    let grid = s.fes.grids.lock().unwrap().get(&(project, sim_id.clone())).map(|k| k.value.clone()).ok_or_else(|| not_found("free-energy surface", &sim_id))?;
+    Ok(match q.format.as_deref().unwrap_or("dat") {
+        "dat" => ([(header::CONTENT_TYPE, "text/plain")], to_dat(&grid)).into_response(),
+        "json" => Json(grid).into_response(),
+        other => return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: format!("unknown format {other}; expected dat or json") }))),
+    })
+}
+
+pub async fn delete(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(sim_id): Path<String>) -> Result<Json<retention::Deleted>, ApiError> {
+    let freed = retention::remove(&mut s.fes.grids.lock().unwrap(), &projects::project_id(&headers), &sim_id).ok_or_else(|| not_found("free-energy surface", &sim_id))?;
+    Ok(retention::deleted(sim_id, freed))
+}
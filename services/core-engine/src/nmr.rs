@@ -0,0 +1,296 @@
+//! ¹H and ¹³C chemical-shift prediction by additive increments.
+//!
+//! Every carbon starts from the base shift of its environment (alkane −2.3, alkene 123.3,
+//! benzene 128.5 ppm, fixed values for carbonyls, nitriles and alkynes) and adds tabulated
+//! increments for each substituent: Grant–Paul α/β/γ terms for sp³ carbons, substituent
+//! chemical shifts by ipso/ortho/meta/para distance for aromatic rings, and α/β terms across
+//! double bonds. Protons use the same scheme: Shoolery-type α terms on sp³ CHn, ortho/meta/para
+//! Z values around 7.27 ppm on arenes, and Pascual–Meier–Simon gem/cis/trans terms on
+//! alkenes. Ring heteroatoms (pyridine-, pyrrole-, furan- and thiophene-type) shift their
+//! neighbours. Multiplicities follow the n+1 rule over vicinal C–H; exchangeable OH/NH
+//! protons are broad singlets. Symmetry-equivalent atoms are reported as one signal.
+//! Expect roughly ±0.3 ppm (¹H) and ±5 ppm (¹³C).
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{chem::{self, BondKind, Molecule}, standardize, ApiError, AppState, ErrorResponse};
+
+/// Substituent classes the increment tables are keyed on.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Group { Alkyl, Aryl, Vinyl, Alkynyl, Ketone, Aldehyde, Acid, Ester, Amide, Nitrile, Hydroxyl, Ether, ArylEther, Acyloxy, Amine, ArylAmine, Amido, Nitro, Fluoro, Chloro, Bromo, Iodo, Thio, Sulfonyl, Other }
+
+/// (¹³C α on sp³ C, ¹H α on sp³ CHn) relative to an alkyl neighbour's.
+fn sp3_increments(g: Group) -> (f64, f64) {
+    match g {
+        Group::Alkyl => (9.1, 0.0), Group::Aryl => (23.5, 1.45), Group::Vinyl => (21.5, 0.85), Group::Alkynyl => (6.5, 0.9),
+        Group::Ketone | Group::Aldehyde => (33.0, 1.25), Group::Acid | Group::Ester => (23.0, 1.2), Group::Amide => (24.0, 1.1), Group::Nitrile => (5.0, 1.1),
+        Group::Hydroxyl => (48.0, 2.5), Group::Ether => (58.0, 2.4), Group::ArylEther => (57.5, 2.9), Group::Acyloxy => (54.0, 2.8),
+        Group::Amine => (28.3, 1.5), Group::ArylAmine => (32.5, 1.95), Group::Amido => (28.0, 1.95), Group::Nitro => (64.0, 3.5),
+        Group::Fluoro => (70.0, 3.4), Group::Chloro => (31.0, 2.2), Group::Bromo => (20.0, 1.8), Group::Iodo => (-6.0, 1.3),
+        Group::Thio => (20.0, 1.2), Group::Sulfonyl => (44.0, 2.0), Group::Other => (10.0, 0.5),
+    }
+}
+
+/// Benzene ¹³C substituent shifts: ipso, ortho, meta, para.
+fn aromatic_c(g: Group) -> [f64; 4] {
+    match g {
+        Group::Alkyl => [9.3, 0.7, -0.1, -2.9], Group::Aryl => [13.0, -1.1, 0.5, -1.0], Group::Vinyl => [9.5, -2.0, 0.2, -0.5], Group::Alkynyl => [-6.1, 3.8, 0.4, -0.2],
+        Group::Ketone => [9.1, 0.1, 0.0, 4.2], Group::Aldehyde => [8.6, 1.3, 0.6, 5.5], Group::Acid => [2.1, 1.6, -0.1, 5.2], Group::Ester => [2.0, 1.2, -0.1, 4.3],
+        Group::Amide => [5.4, -0.3, -0.9, 5.0], Group::Nitrile => [-16.0, 3.6, 0.6, 4.3], Group::Hydroxyl => [26.9, -12.7, 1.4, -7.3],
+        Group::Ether | Group::ArylEther => [31.4, -14.4, 1.0, -7.7], Group::Acyloxy => [22.4, -7.1, 0.4, -3.2], Group::Amine | Group::ArylAmine => [18.2, -13.4, 0.8, -10.0],
+        Group::Amido => [9.7, -8.1, 0.2, -4.4], Group::Nitro => [19.9, -4.9, 0.9, 6.1], Group::Fluoro => [35.1, -14.3, 0.9, -4.5], Group::Chloro => [6.4, 0.2, 1.0, -2.0],
+        Group::Bromo => [-5.4, 3.3, 2.2, -1.0], Group::Iodo => [-32.0, 9.9, 2.6, -0.4], Group::Thio => [10.2, -1.9, 0.4, -3.6], Group::Sulfonyl => [15.3, -2.9, 0.4, 4.4],
+        Group::Other => [0.0; 4],
+    }
+}
+
+/// Benzene ¹H Z values: ortho, meta, para.
+fn aromatic_h(g: Group) -> [f64; 3] {
+    match g {
+        Group::Alkyl => [-0.18, -0.11, -0.21], Group::Aryl => [0.37, 0.20, 0.10], Group::Vinyl => [0.06, -0.03, -0.10], Group::Alkynyl => [0.15, -0.02, -0.01],
+        Group::Ketone => [0.62, 0.14, 0.21], Group::Aldehyde => [0.56, 0.22, 0.29], Group::Acid => [0.87, 0.21, 0.34], Group::Ester => [0.71, 0.11, 0.21],
+        Group::Amide => [0.61, 0.10, 0.17], Group::Nitrile => [0.36, 0.18, 0.28], Group::Hydroxyl => [-0.56, -0.12, -0.45], Group::Ether | Group::ArylEther => [-0.48, -0.09, -0.44],
+        Group::Acyloxy => [-0.25, 0.03, -0.13], Group::Amine | Group::ArylAmine => [-0.75, -0.25, -0.65], Group::Amido => [0.12, -0.07, -0.28], Group::Nitro => [0.95, 0.26, 0.38],
+        Group::Fluoro => [-0.26, 0.0, -0.20], Group::Chloro => [0.03, -0.02, -0.09], Group::Bromo => [0.18, -0.08, -0.04], Group::Iodo => [0.39, -0.21, 0.0],
+        Group::Thio => [-0.08, -0.10, -0.24], Group::Sulfonyl => [0.66, 0.26, 0.36], Group::Other => [0.0; 3],
+    }
+}
+
+/// Alkene terms: (¹³C on own carbon, ¹³C on partner, ¹H gem, ¹H cis/trans average).
+fn alkene(g: Group) -> (f64, f64, f64, f64) {
+    match g {
+        Group::Alkyl => (10.6, -7.9, 0.45, -0.25), Group::Aryl => (12.5, -11.0, 1.38, 0.15), Group::Vinyl => (13.6, -7.0, 1.0, 0.0),
+        Group::Ketone | Group::Aldehyde => (15.0, 5.8, 1.1, 1.0), Group::Acid | Group::Ester | Group::Amide => (4.0, 8.9, 0.8, 0.87), Group::Nitrile => (-15.1, 14.2, 0.27, 0.65),
+        Group::Ether | Group::ArylEther | Group::Hydroxyl => (29.0, -39.0, 1.22, -1.14), Group::Acyloxy => (18.4, -26.7, 2.11, -0.5), Group::Amine | Group::ArylAmine | Group::Amido => (28.0, -32.0, 0.8, -1.23),
+        Group::Fluoro => (24.9, -34.3, 1.54, -0.6), Group::Chloro => (2.8, -6.1, 1.08, 0.15), Group::Bromo => (-8.6, -0.9, 1.07, 0.5), Group::Iodo => (-38.0, 7.0, 1.14, 0.6),
+        _ => (0.0, 0.0, 0.0, 0.0),
+    }
+}
+
+struct Ctx<'a> { mol: &'a Molecule, adj: Vec<Vec<(usize, BondKind)>> }
+
+impl Ctx<'_> {
+    fn el(&self, i: usize) -> &str { &self.mol.atoms[i].element }
+    fn heavy(&self, i: usize) -> impl Iterator<Item = (usize, BondKind)> + '_ { self.adj[i].iter().copied().filter(|&(j, _)| self.el(j) != "H") }
+    fn double_to(&self, i: usize, el: &str) -> bool { self.adj[i].iter().any(|&(j, k)| k == BondKind::Double && self.el(j) == el) }
+    fn single_to(&self, i: usize, el: &str, except: usize) -> usize { self.adj[i].iter().filter(|&&(j, k)| j != except && k == BondKind::Single && self.el(j) == el).count() }
+    fn is_aryl(&self, i: usize) -> bool { self.mol.atoms[i].aromatic }
+    fn is_acyl(&self, i: usize) -> bool { self.el(i) == "C" && self.double_to(i, "O") }
+
+    /// What atom `s` looks like as a substituent of `from`.
+    fn group(&self, from: usize, s: usize) -> Group {
+        let a = &self.mol.atoms[s];
+        let others: Vec<usize> = self.heavy(s).map(|(j, _)| j).filter(|&j| j != from).collect();
+        match a.element.as_str() {
+            "C" if a.aromatic => Group::Aryl,
+            "C" if self.double_to(s, "O") => match (self.single_to(s, "O", from), self.single_to(s, "N", from)) {
+                (1, _) if a.hydrogens == 0 && others.iter().any(|&o| self.el(o) == "O" && self.mol.atoms[o].hydrogens > 0) => Group::Acid,
+                (1, _) => Group::Ester,
+                (_, 1..) => Group::Amide,
+                _ if a.hydrogens > 0 => Group::Aldehyde,
+                _ => Group::Ketone,
+            },
+            "C" if self.adj[s].iter().any(|&(_, k)| k == BondKind::Triple) => if self.double_to(s, "N") || self.adj[s].iter().any(|&(j, k)| k == BondKind::Triple && self.el(j) == "N") { Group::Nitrile } else { Group::Alkynyl },
+            "C" if self.adj[s].iter().any(|&(_, k)| k == BondKind::Double) => Group::Vinyl,
+            "C" => Group::Alkyl,
+            "O" if a.hydrogens > 0 || a.charge < 0 => Group::Hydroxyl,
+            "O" if others.iter().any(|&o| self.is_acyl(o)) => Group::Acyloxy,
+            "O" if others.iter().any(|&o| self.is_aryl(o)) => Group::ArylEther,
+            "O" => Group::Ether,
+            "N" if a.charge > 0 && self.heavy(s).filter(|&(j, _)| self.el(j) == "O").count() >= 2 => Group::Nitro,
+            "N" if others.iter().any(|&o| self.is_acyl(o) || (self.el(o) == "S" && self.double_to(o, "O"))) => Group::Amido,
+            "N" if others.iter().any(|&o| self.is_aryl(o)) || self.is_aryl(from) => Group::ArylAmine,
+            "N" => Group::Amine,
+            "F" => Group::Fluoro,
+            "Cl" => Group::Chloro,
+            "Br" => Group::Bromo,
+            "I" => Group::Iodo,
+            "S" if self.double_to(s, "O") => Group::Sulfonyl,
+            "S" => Group::Thio,
+            _ => Group::Other,
+        }
+    }
+
+    /// Shortest path lengths from `start` through aromatic atoms, up to 3 bonds.
+    fn ring_distances(&self, start: usize) -> Vec<(usize, usize)> {
+        let mut dist = vec![usize::MAX; self.mol.atoms.len()];
+        dist[start] = 0;
+        let mut frontier = vec![start];
+        let mut out = vec![(start, 0)];
+        for d in 1..=3 {
+            let mut next = Vec::new();
+            for &u in &frontier {
+                for &(v, k) in &self.adj[u] {
+                    if k == BondKind::Aromatic && dist[v] == usize::MAX { dist[v] = d; next.push(v); out.push((v, d)); }
+                }
+            }
+            frontier = next;
+        }
+        out
+    }
+
+    /// Ring heteroatom effects on an aromatic carbon (¹³C) and its proton (¹H) at 1–3 bonds.
+    fn hetero(&self, i: usize) -> (f64, f64) {
+        let (mut c, mut h) = (0.0, 0.0);
+        for (j, d) in self.ring_distances(i) {
+            if d == 0 { continue; }
+            let a = &self.mol.atoms[j];
+            let pyrrole_like = a.hydrogens > 0 || self.adj[j].len() == 3;
+            let (ci, hi): ([f64; 3], [f64; 3]) = match a.element.as_str() {
+                "N" if !pyrrole_like => ([21.5, -4.5, 7.5], [1.33, -0.02, 0.38]),
+                "N" => ([-10.3, -20.3, 0.0], [-0.6, -1.05, 0.0]),
+                "O" => ([14.3, -18.9, 0.0], [0.15, -0.9, 0.0]),
+                "S" => ([-3.5, -1.6, 0.0], [0.05, -0.17, 0.0]),
+                _ => continue,
+            };
+            c += ci[d - 1];
+            h += hi[d - 1];
+        }
+        (c, h)
+    }
+
+    /// Non-ring substituents of the aromatic system around `i`, with their distance from `i`.
+    fn ring_substituents(&self, i: usize) -> Vec<(Group, usize)> {
+        self.ring_distances(i).into_iter().flat_map(|(j, d)| {
+            self.heavy(j).filter(|&(_, k)| k != BondKind::Aromatic).map(move |(s, _)| (self.group(j, s), d)).collect::<Vec<_>>()
+        }).collect()
+    }
+
+    fn carbon(&self, i: usize) -> (f64, &'static str) {
+        let a = &self.mol.atoms[i];
+        let subs: Vec<(usize, BondKind)> = self.heavy(i).collect();
+        let conjugated = subs.iter().any(|&(j, k)| k == BondKind::Single && (self.is_aryl(j) || self.adj[j].iter().any(|&(_, kk)| kk == BondKind::Double)));
+        if a.aromatic {
+            let shift = 128.5 + self.ring_substituents(i).iter().map(|&(g, d)| aromatic_c(g)[d]).sum::<f64>() + self.hetero(i).0;
+            return (shift, "aromatic");
+        }
+        if self.double_to(i, "O") {
+            let o_single = self.single_to(i, "O", usize::MAX);
+            let n_single = self.single_to(i, "N", usize::MAX);
+            let shift = match (o_single, n_single) {
+                (2, _) => 155.0,
+                (1, 1) | (0, 2) => 157.0,
+                (1, 0) if subs.iter().any(|&(o, _)| self.el(o) == "O" && self.mol.atoms[o].hydrogens > 0) => if conjugated { 172.0 } else { 177.0 },
+                (1, 0) => if conjugated { 166.0 } else { 171.0 },
+                (0, 1) => if conjugated { 167.0 } else { 171.0 },
+                _ if subs.iter().any(|&(x, _)| self.el(x) == "Cl") => 170.0,
+                _ if a.hydrogens > 0 => if conjugated { 192.0 } else { 200.0 },
+                _ => if conjugated { 197.0 } else { 207.0 },
+            };
+            return (shift, "carbonyl");
+        }
+        if subs.iter().any(|&(j, k)| k == BondKind::Triple && self.el(j) == "N") { return (if conjugated { 119.0 } else { 118.0 }, "nitrile"); }
+        if subs.iter().any(|&(_, k)| k == BondKind::Triple) { return (if a.hydrogens > 0 { 68.0 } else { 80.0 }, "alkyne"); }
+        if subs.iter().any(|&(j, k)| k == BondKind::Double && self.el(j) == "N") { return (if self.single_to(i, "N", usize::MAX) > 0 { 157.0 } else { 160.0 }, "imine"); }
+        if let Some(&(partner, _)) = subs.iter().find(|&&(j, k)| k == BondKind::Double && self.el(j) == "C") {
+            let own: f64 = subs.iter().filter(|&&(j, _)| j != partner).map(|&(j, _)| alkene(self.group(i, j)).0).sum();
+            let across: f64 = self.heavy(partner).filter(|&(j, _)| j != i).map(|(j, _)| alkene(self.group(partner, j)).1).sum();
+            return (123.3 + own + across, "alkene");
+        }
+        // sp³: Grant–Paul α terms per substituent, β/γ through saturated atoms only (the α
+        // terms of unsaturated groups already include their own atoms).
+        let mut shift = -2.3;
+        for &(j, _) in &subs {
+            let g = self.group(i, j);
+            shift += sp3_increments(g).0;
+            if !matches!(g, Group::Alkyl | Group::Hydroxyl | Group::Ether | Group::Amine | Group::Thio) { continue; }
+            for (b, _) in self.heavy(j).filter(|&(b, _)| b != i) {
+                shift += if self.el(b) == "C" { 9.4 } else { 10.0 };
+                if !matches!(self.group(j, b), Group::Alkyl) { continue; }
+                shift -= 2.5 * self.heavy(b).filter(|&(c, _)| c != j).count() as f64;
+            }
+        }
+        (shift, "aliphatic")
+    }
+
+    /// Proton shift, environment and whether it exchanges.
+    fn proton(&self, i: usize) -> (f64, &'static str, bool) {
+        let a = &self.mol.atoms[i];
+        let subs: Vec<usize> = self.heavy(i).map(|(j, _)| j).collect();
+        match a.element.as_str() {
+            "O" if subs.iter().any(|&j| self.is_acyl(j)) => (11.5, "carboxylic acid OH", true),
+            "O" if subs.iter().any(|&j| self.is_aryl(j)) => (5.5, "phenol OH", true),
+            "O" => (2.0, "hydroxyl", true),
+            "N" if a.aromatic => (10.5, "aromatic NH", true),
+            "N" if subs.iter().any(|&j| self.is_acyl(j)) => (if subs.iter().any(|&j| self.is_aryl(j)) { 8.5 } else { 7.0 }, "amide NH", true),
+            "N" if subs.iter().any(|&j| self.el(j) == "S" && self.double_to(j, "O")) => (5.0, "sulfonamide NH", true),
+            "N" if subs.iter().any(|&j| self.is_aryl(j)) => (3.6, "aniline NH", true),
+            "N" => (1.5, "amine NH", true),
+            "S" => (1.6, "thiol SH", true),
+            "C" if a.aromatic => {
+                let z: f64 = self.ring_substituents(i).iter().filter(|&&(_, d)| d > 0).map(|&(g, d)| aromatic_h(g)[d - 1]).sum();
+                (7.27 + z + self.hetero(i).1, "aromatic CH", false)
+            }
+            "C" if self.double_to(i, "O") => {
+                let hetero = self.single_to(i, "O", usize::MAX) + self.single_to(i, "N", usize::MAX) > 0;
+                if hetero { (8.1, "formyl CH", false) } else if subs.iter().any(|&j| self.is_aryl(j)) { (10.0, "aldehyde CH", false) } else { (9.8, "aldehyde CH", false) }
+            }
+            "C" if self.adj[i].iter().any(|&(_, k)| k == BondKind::Triple) => (if subs.iter().any(|&j| self.is_aryl(j)) { 3.1 } else { 2.5 }, "alkyne CH", false),
+            "C" if self.double_to(i, "N") => (8.2, "imine CH", false),
+            "C" if self.double_to(i, "C") => {
+                let partner = self.adj[i].iter().find(|&&(j, k)| k == BondKind::Double && self.el(j) == "C").map(|&(j, _)| j).unwrap_or(i);
+                let gem: f64 = subs.iter().filter(|&&j| j != partner).map(|&j| alkene(self.group(i, j)).2).sum();
+                let across: f64 = self.heavy(partner).filter(|&(j, _)| j != i).map(|(j, _)| alkene(self.group(partner, j)).3).sum();
+                (5.25 + gem + across, "alkene CH", false)
+            }
+            _ => {
+                let base = match a.hydrogens { 3 => 0.86, 2 => 1.30, _ => 1.50 };
+                let base = if subs.is_empty() { 0.23 } else { base };
+                // α terms in full, β terms through saturated carbons at a fifth.
+                let inc: f64 = subs.iter().map(|&j| {
+                    let g = self.group(i, j);
+                    let beta: f64 = if g == Group::Alkyl { self.heavy(j).filter(|&(b, _)| b != i).map(|(b, _)| sp3_increments(self.group(j, b)).1).sum() } else { 0.0 };
+                    sp3_increments(g).1 + 0.2 * beta
+                }).sum();
+                ((base + inc).min(6.0), "aliphatic CH", false)
+            }
+        }
+    }
+
+    /// First-order multiplicity from C–H protons three bonds away; arenes count ortho H only.
+    fn multiplicity(&self, i: usize, exchangeable: bool) -> String {
+        if exchangeable { return "br s".into(); }
+        let n: usize = self.heavy(i).filter(|&(j, _)| self.el(j) == "C" && (!self.is_aryl(i) || self.is_aryl(j))).map(|(j, _)| self.mol.atoms[j].hydrogens as usize).sum();
+        match n { 0 => "s", 1 => "d", 2 => "t", 3 => "q", 4 => "quint", 5 => "sext", 6 => "sept", _ => "m" }.into()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct NmrRequest { molecule: String }
+
+#[derive(Serialize)]
+pub struct Signal { shift_ppm: f64, atoms: Vec<usize>, count: usize, #[serde(skip_serializing_if = "Option::is_none")] multiplicity: Option<String>, environment: &'static str, #[serde(skip_serializing_if = "std::ops::Not::not")] exchangeable: bool }
+#[derive(Serialize)]
+pub struct NmrResponse { molecule: String, canonical_smiles: String, #[serde(skip_serializing_if = "Vec::is_empty")] standardization: Vec<String>, proton: Vec<Signal>, carbon: Vec<Signal> }
+
+fn round(v: f64, places: i32) -> f64 { let f = 10f64.powi(places); (v * f).round() / f }
+
+pub async fn predict(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<NmrRequest>) -> Result<Json<NmrResponse>, ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let resolved = standardize::resolve(&s, &headers, &req.molecule).await;
+    let smiles = resolved.canonical_smiles.ok_or_else(|| bad(format!("can't resolve {} to a structure", req.molecule)))?;
+    let mol = chem::parse_smiles(&smiles).map_err(bad)?;
+    let ctx = Ctx { adj: mol.neighbors(), mol: &mol };
+    let classes = mol.symmetry_classes();
+    let (mut proton, mut carbon): (Vec<Signal>, Vec<Signal>) = (Vec::new(), Vec::new());
+    // One signal per symmetry class.
+    let mut seen = Vec::new();
+    for (i, a) in mol.atoms.iter().enumerate() {
+        if seen.contains(&classes[i]) { continue; }
+        seen.push(classes[i]);
+        let atoms: Vec<usize> = (0..mol.atoms.len()).filter(|&j| classes[j] == classes[i]).collect();
+        if a.element == "C" {
+            let (shift, environment) = ctx.carbon(i);
+            carbon.push(Signal { shift_ppm: round(shift, 1), count: atoms.len(), atoms: atoms.clone(), multiplicity: None, environment, exchangeable: false });
+        }
+        if a.hydrogens > 0 {
+            let (shift, environment, exchangeable) = ctx.proton(i);
+            proton.push(Signal { shift_ppm: round(shift, 2), count: atoms.len() * a.hydrogens as usize, atoms, multiplicity: Some(ctx.multiplicity(i, exchangeable)), environment, exchangeable });
+        }
+    }
+    proton.sort_by(|a, b| b.shift_ppm.total_cmp(&a.shift_ppm));
+    carbon.sort_by(|a, b| b.shift_ppm.total_cmp(&a.shift_ppm));
+    Ok(Json(NmrResponse { molecule: req.molecule, canonical_smiles: smiles, standardization: resolved.standardization, proton, carbon }))
+}
@@ -0,0 +1,345 @@
+//! Slack and email notifications when long jobs finish or fail.
+//!
+//! A project's channels (`PUT /projects/:id/notifications`) hear about every long job in it,
+//! and a user's own (`PUT /notifications/preferences`, keyed by the gateway's `X-User-Id`)
+//! about the long jobs that user submitted. Either picks the outcomes it wants (`completed`,
+//! `failed`) and how long a job must have run to count (default a minute). Only the job a request
+//! asked for notifies: a pipeline or sweep does once it ends, but not its steps or grid points. A
+//! message has the job's kind, subject, outcome and run time, a short summary of its result (top
+//! hits, final energy, confidence) and a link to it under `BIO_PUBLIC_URL`.
+//!
+//! Slack channels are incoming-webhook URLs, https only and on `hooks.slack.com` or a host
+//! `BIO_SLACK_WEBHOOK_HOSTS` lists, so a webhook can't point the engine at an internal address.
+//! The subject is the caller's own text, so its control characters become spaces and an email's
+//! `Subject:` is always RFC 2047-encoded. Email goes through `BIO_SMTP_URL`, either
+//! `smtp://[user:pass@]host[:587]` (upgraded with STARTTLS when the server offers it, which is
+//! required to log in) or `smtps://[user:pass@]host[:465]`, from `BIO_SMTP_FROM`. Messages are
+//! sent in the background; one that can't be delivered is logged and dropped.
+
+use axum::{extract::{Path, State}, http::{HeaderMap, StatusCode}, response::Json};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::{audit, projects, ApiError, AppState, ErrorResponse};
+
+const OUTCOMES: [&str; 2] = ["completed", "failed"];
+const DEFAULT_MIN_DURATION_SECS: f64 = 60.0;
+/// Slack webhooks and addresses per project or user.
+const MAX_CHANNELS: usize = 20;
+/// Top hits listed for a screen.
+const TOP_HITS: usize = 3;
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+/// Webhook host allowed when `BIO_SLACK_WEBHOOK_HOSTS` is unset.
+const SLACK_HOST: &str = "hooks.slack.com";
+/// Subject bytes per RFC 2047 encoded word, so each stays within 75 characters.
+const ENCODED_WORD_BYTES: usize = 45;
+
+fn default_outcomes() -> Vec<String> { OUTCOMES.iter().map(|o| o.to_string()).collect() }
+fn default_min_duration() -> f64 { DEFAULT_MIN_DURATION_SECS }
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Preferences { #[serde(default)] slack_webhooks: Vec<String>, #[serde(default)] emails: Vec<String>, #[serde(default = "default_outcomes")] on: Vec<String>, #[serde(default = "default_min_duration")] min_duration_secs: f64 }
+#[derive(Serialize)]
+pub struct PreferencesResponse { #[serde(skip_serializing_if = "Option::is_none")] project: Option<String>, #[serde(skip_serializing_if = "Option::is_none")] user: Option<String>, #[serde(flatten)] preferences: Preferences, email_available: bool }
+
+impl Default for Preferences {
+    fn default() -> Self { Self { slack_webhooks: Vec::new(), emails: Vec::new(), on: default_outcomes(), min_duration_secs: DEFAULT_MIN_DURATION_SECS } }
+}
+
+impl Preferences {
+    fn validate(&self, email_available: bool, slack_hosts: &[String]) -> Result<(), String> {
+        if self.slack_webhooks.len() > MAX_CHANNELS || self.emails.len() > MAX_CHANNELS { return Err(format!("at most {MAX_CHANNELS} Slack webhooks and {MAX_CHANNELS} email addresses")); }
+        for url in &self.slack_webhooks { check_webhook(url, slack_hosts)?; }
+        if let Some(addr) = self.emails.iter().find(|a| !valid_address(a)) { return Err(format!("{addr} is not an email address")); }
+        if !self.emails.is_empty() && !email_available { return Err("email notifications need BIO_SMTP_URL set on the engine".into()); }
+        if let Some(o) = self.on.iter().find(|o| !OUTCOMES.contains(&o.as_str())) { return Err(format!("unknown outcome {o}; expected {}", OUTCOMES.join(" or "))); }
+        if !self.min_duration_secs.is_finite() || self.min_duration_secs < 0.0 { return Err("min_duration_secs must be a number of seconds, zero or more".into()); }
+        Ok(())
+    }
+
+    fn wants(&self, outcome: &str, wall_seconds: f64) -> bool { self.on.iter().any(|o| o == outcome) && wall_seconds >= self.min_duration_secs && !(self.slack_webhooks.is_empty() && self.emails.is_empty()) }
+}
+
+/// A Slack webhook must be https on an allowed host, with no credentials or port of its own.
+fn check_webhook(url: &str, slack_hosts: &[String]) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("Slack webhook {url}: {e}"))?;
+    if parsed.scheme() != "https" { return Err(format!("Slack webhook {url} must be an https URL")); }
+    let host = parsed.host_str().unwrap_or("").to_ascii_lowercase();
+    if !slack_hosts.contains(&host) || parsed.port().is_some() || !parsed.username().is_empty() || parsed.password().is_some() {
+        return Err(format!("Slack webhook {url} is not on {}; BIO_SLACK_WEBHOOK_HOSTS lists the hosts allowed", slack_hosts.join(" or ")));
+    }
+    Ok(())
+}
+
+/// `text` on one line: control characters (CR, LF, tabs and the rest) become spaces.
+fn one_line(text: &str) -> String { text.chars().map(|c| if c.is_control() { ' ' } else { c }).collect() }
+
+/// One address, with nothing that could end a header or add a recipient.
+fn valid_address(a: &str) -> bool {
+    a.split_once('@').is_some_and(|(local, domain)| !local.is_empty() && domain.contains('.')) && !a.chars().any(|c| c.is_whitespace() || c.is_control() || matches!(c, ',' | ';' | '<' | '>'))
+}
+
+/// A finished job as its notification describes it.
+pub struct Finished<'a> { pub kind: &'a str, pub id: &'a str, pub subject: &'a str, pub error: Option<&'a str>, pub wall_seconds: f64, pub summary: Vec<String>, pub link: Option<String> }
+
+pub struct Notifications { projects: Mutex<HashMap<String, Preferences>>, users: Mutex<HashMap<String, Preferences>>, sender: Sender, public_url: String, slack_hosts: Vec<String> }
+
+#[derive(Clone)]
+struct Sender { client: reqwest::Client, smtp: Option<Arc<Smtp>> }
+
+impl Notifications {
+    pub fn from_env() -> Self {
+        let smtp = std::env::var("BIO_SMTP_URL").ok().filter(|v| !v.is_empty()).and_then(|url| match Smtp::parse(&url, std::env::var("BIO_SMTP_FROM").ok()) {
+            Ok(smtp) => Some(Arc::new(smtp)),
+            Err(e) => { tracing::warn!("BIO_SMTP_URL unusable: {e}; email notifications are off"); None }
+        });
+        let public_url = std::env::var("BIO_PUBLIC_URL").unwrap_or_default().trim_end_matches('/').to_string();
+        let mut slack_hosts: Vec<String> = std::env::var("BIO_SLACK_WEBHOOK_HOSTS").unwrap_or_default().split(',').map(|h| h.trim().to_ascii_lowercase()).filter(|h| !h.is_empty()).collect();
+        if slack_hosts.is_empty() { slack_hosts.push(SLACK_HOST.into()); }
+        Self { projects: Mutex::new(HashMap::new()), users: Mutex::new(HashMap::new()), sender: Sender { client: reqwest::Client::new(), smtp }, public_url, slack_hosts }
+    }
+
+    /// `path` under `BIO_PUBLIC_URL`.
+    pub fn link(&self, path: &str) -> String { format!("{}{path}", self.public_url) }
+
+    /// Notifies the project's channels and the submitting user's of a job that ran long enough.
+    pub fn finished(&self, headers: &HeaderMap, project: &str, job: Finished) {
+        let outcome = if job.error.is_some() { "failed" } else { "completed" };
+        let user = audit::actor(headers);
+        let mut targets: Vec<Preferences> = self.projects.lock().unwrap().get(project).cloned().into_iter().collect();
+        targets.extend(self.users.lock().unwrap().get(&user).cloned());
+        targets.retain(|p| p.wants(outcome, job.wall_seconds));
+        if targets.is_empty() { return; }
+        let mut slack: Vec<String> = targets.iter().flat_map(|p| p.slack_webhooks.clone()).collect();
+        let mut emails: Vec<String> = targets.iter().flat_map(|p| p.emails.clone()).collect();
+        slack.sort();
+        slack.dedup();
+        emails.sort();
+        emails.dedup();
+        // The subject is the caller's molecule, sequence or target; it must not break a line.
+        let mut title = format!("{} {outcome} after {}", job.kind, duration(job.wall_seconds));
+        if !job.subject.is_empty() { title = format!("{title}: {}", one_line(job.subject)); }
+        let mut lines = vec![format!("Project {project}, submitted by {user}.")];
+        if let Some(e) = job.error { lines.push(format!("Error: {e}")); }
+        lines.extend(job.summary);
+        lines.push(job.link.unwrap_or_else(|| format!("Request {}", job.id)));
+        let sender = self.sender.clone();
+        tokio::spawn(async move { sender.send(&slack, &emails, &title, &lines).await });
+    }
+}
+
+impl Sender {
+    async fn send(&self, slack: &[String], emails: &[String], title: &str, lines: &[String]) {
+        for url in slack {
+            let body = json!({ "text": format!("*{title}*\n{}", lines.join("\n")) }).to_string();
+            let sent = self.client.post(url).header(reqwest::header::CONTENT_TYPE, "application/json").body(body).timeout(SEND_TIMEOUT).send().await.and_then(|r| r.error_for_status());
+            if let Err(e) = sent { tracing::warn!("Slack notification not sent: {e}"); }
+        }
+        if let (Some(smtp), false) = (&self.smtp, emails.is_empty()) {
+            let subject = format!("[ALICE Bio] {title}");
+            match tokio::time::timeout(SEND_TIMEOUT, smtp.send(emails, &subject, &lines.join("\n"))).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => tracing::warn!("email notification to {} not sent: {e}", emails.join(", ")),
+                Err(_) => tracing::warn!("email notification to {} not sent: SMTP server timed out", emails.join(", ")),
+            }
+        }
+    }
+}
+
+fn duration(secs: f64) -> String {
+    let s = secs.round() as u64;
+    match s { 0..=59 => format!("{s}s"), 60..=3599 => format!("{}m {:02}s", s / 60, s % 60), _ => format!("{}h {:02}m", s / 3600, s % 3600 / 60) }
+}
+
+/// The lines of a result worth reading in a notification.
+pub fn summarize(kind: &str, result: &Value) -> Vec<String> {
+    let num = |key: &str| result.get(key).and_then(Value::as_f64);
+    match kind {
+        "screen" => {
+            let hits: Vec<String> = result.get("hits").and_then(Value::as_array).into_iter().flatten().take(TOP_HITS).filter_map(|h| {
+                Some(format!("{} ({:.1} nM)", h.get("compound_id")?.as_str()?, h.get("binding_affinity_nm")?.as_f64()?))
+            }).collect();
+            let screened = result.get("library_screened").and_then(Value::as_u64).unwrap_or(0);
+            if hits.is_empty() { vec![format!("No hits among {screened} compounds.")] } else { vec![format!("Top hits of {screened} compounds: {}", hits.join(", "))] }
+        }
+        "simulate" => num("energy_kcal_mol").map(|e| format!("Final energy {e:.2} kcal/mol, RMSD {:.2} Å.", num("rmsd_angstrom").unwrap_or(0.0))).into_iter().collect(),
+        "energy" => num("total_energy_kcal").map(|e| format!("Total energy {e:.2} kcal/mol.")).into_iter().collect(),
+        "predict" => num("structure_confidence").map(|c| format!("Structure confidence {c:.2}.")).into_iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[derive(PartialEq)]
+enum Tls { Implicit, StartTls }
+
+struct Smtp { host: String, port: u16, tls: Tls, login: Option<(String, String)>, from: String }
+
+/// The lines of one SMTP reply, and its code.
+async fn reply<S: AsyncRead + Unpin>(r: &mut BufReader<S>) -> Result<(u16, Vec<String>), String> {
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        if r.read_line(&mut line).await.map_err(|e| e.to_string())? == 0 { return Err("connection closed".into()); }
+        let code = line.get(..3).and_then(|c| c.parse::<u16>().ok()).ok_or_else(|| format!("unexpected reply {:?}", line.trim_end()))?;
+        let last = line.as_bytes().get(3) != Some(&b'-');
+        lines.push(line.get(4..).unwrap_or("").trim_end().to_string());
+        if last { return Ok((code, lines)); }
+    }
+}
+
+async fn command<S: AsyncRead + AsyncWrite + Unpin>(s: &mut BufReader<S>, line: &str, expect: u16) -> Result<Vec<String>, String> {
+    s.get_mut().write_all(format!("{line}\r\n").as_bytes()).await.map_err(|e| e.to_string())?;
+    let (code, text) = reply(s).await?;
+    let verb = line.split(' ').next().unwrap_or(line);
+    if code / 100 != expect / 100 { return Err(format!("{verb}: {code} {}", text.join(" "))); }
+    Ok(text)
+}
+
+impl Smtp {
+    fn parse(url: &str, from: Option<String>) -> Result<Self, String> {
+        let (tls, rest, port) = if let Some(r) = url.strip_prefix("smtps://") { (Tls::Implicit, r, 465) } else if let Some(r) = url.strip_prefix("smtp://") { (Tls::StartTls, r, 587) } else { return Err("expected smtp:// or smtps://".into()) };
+        let (login, hostport) = match rest.trim_end_matches('/').rsplit_once('@') {
+            Some((auth, hp)) => (Some(auth.split_once(':').map(|(u, p)| (u.to_string(), p.to_string())).ok_or("credentials are user:password")?), hp),
+            None => (None, rest.trim_end_matches('/')),
+        };
+        let (host, port) = match hostport.rsplit_once(':') { Some((h, p)) => (h, p.parse().map_err(|_| format!("bad port {p}"))?), None => (hostport, port) };
+        let from = from.filter(|f| !f.is_empty()).unwrap_or_else(|| "alice-bio@localhost".into());
+        if !valid_address(&from) { return Err(format!("BIO_SMTP_FROM {from} is not an email address")); }
+        Ok(Self { host: host.into(), port, tls, login, from })
+    }
+
+    async fn send(&self, to: &[String], subject: &str, body: &str) -> Result<(), String> {
+        let tcp = TcpStream::connect((self.host.as_str(), self.port)).await.map_err(|e| format!("{}:{} unreachable: {e}", self.host, self.port))?;
+        if self.tls == Tls::Implicit { return self.session(self.secure(tcp).await?, to, subject, body).await; }
+        let mut plain = BufReader::new(tcp);
+        let (code, _) = reply(&mut plain).await?;
+        if code != 220 { return Err(format!("greeting {code}")); }
+        let features = command(&mut plain, "EHLO alice-bio", 250).await?;
+        if features.iter().any(|f| f.eq_ignore_ascii_case("STARTTLS")) {
+            command(&mut plain, "STARTTLS", 220).await?;
+            return self.session(self.secure(plain.into_inner()).await?, to, subject, body).await;
+        }
+        if self.login.is_some() { return Err("the server doesn't offer STARTTLS, so credentials won't be sent".into()); }
+        self.transaction(&mut plain, to, subject, body).await
+    }
+
+    async fn secure(&self, tcp: TcpStream) -> Result<tokio_native_tls::TlsStream<TcpStream>, String> {
+        let connector = tokio_native_tls::native_tls::TlsConnector::new().map_err(|e| e.to_string())?;
+        tokio_native_tls::TlsConnector::from(connector).connect(&self.host, tcp).await.map_err(|e| format!("TLS: {e}"))
+    }
+
+    /// Greeting (for implicit TLS), EHLO and login over an encrypted stream, then the message.
+    async fn session<S: AsyncRead + AsyncWrite + Unpin>(&self, stream: S, to: &[String], subject: &str, body: &str) -> Result<(), String> {
+        let mut s = BufReader::new(stream);
+        if self.tls == Tls::Implicit {
+            let (code, _) = reply(&mut s).await?;
+            if code != 220 { return Err(format!("greeting {code}")); }
+        }
+        command(&mut s, "EHLO alice-bio", 250).await?;
+        if let Some((user, pass)) = &self.login { command(&mut s, &format!("AUTH PLAIN {}", BASE64.encode(format!("\0{user}\0{pass}"))), 235).await?; }
+        self.transaction(&mut s, to, subject, body).await
+    }
+
+    /// The DATA of a message, up to its closing `.`: the subject encoded, the body dot-stuffed.
+    fn message(&self, to: &[String], subject: &str, body: &str) -> String {
+        let text: String = body.lines().map(|l| if l.starts_with('.') { format!(".{l}\r\n") } else { format!("{l}\r\n") }).collect();
+        format!("From: {}\r\nTo: {}\r\nSubject: {}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n{text}.", self.from, to.join(", "), encode_subject(subject))
+    }
+
+    async fn transaction<S: AsyncRead + AsyncWrite + Unpin>(&self, s: &mut BufReader<S>, to: &[String], subject: &str, body: &str) -> Result<(), String> {
+        command(s, &format!("MAIL FROM:<{}>", self.from), 250).await?;
+        for addr in to { command(s, &format!("RCPT TO:<{addr}>"), 250).await?; }
+        command(s, "DATA", 354).await?;
+        command(s, &self.message(to, subject, body), 250).await?;
+        let _ = command(s, "QUIT", 221).await;
+        Ok(())
+    }
+}
+
+/// `subject` on one line as RFC 2047 encoded words, folded so no line runs long. Encoding
+/// leaves nothing in the header a mail server could read as a line break.
+fn encode_subject(subject: &str) -> String {
+    let subject = one_line(subject);
+    let mut words = Vec::new();
+    let mut start = 0;
+    while start < subject.len() {
+        let mut end = (start + ENCODED_WORD_BYTES).min(subject.len());
+        while !subject.is_char_boundary(end) { end -= 1; }
+        words.push(format!("=?UTF-8?B?{}?=", BASE64.encode(&subject[start..end])));
+        start = end;
+    }
+    words.join("\r\n ")
+}
+
+pub async fn get_project(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(id): Path<String>) -> Result<Json<PreferencesResponse>, ApiError> {
+    let project = projects::own(&headers, &id)?;
+    let preferences = s.notifications.projects.lock().unwrap().get(&project).cloned().unwrap_or_default();
+    Ok(Json(PreferencesResponse { project: Some(project), user: None, preferences, email_available: s.notifications.sender.smtp.is_some() }))
+}
+
+pub async fn set_project(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(id): Path<String>, Json(preferences): Json<Preferences>) -> Result<Json<PreferencesResponse>, ApiError> {
+    let project = projects::own(&headers, &id)?;
+    let email_available = s.notifications.sender.smtp.is_some();
+    preferences.validate(email_available, &s.notifications.slack_hosts).map_err(|e| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e })))?;
+    s.projects.resolve(&headers);
+    s.notifications.projects.lock().unwrap().insert(project.clone(), preferences.clone());
+    s.audit.record(&headers, &project, "configure_notifications", &project, "", None);
+    Ok(Json(PreferencesResponse { project: Some(project), user: None, preferences, email_available }))
+}
+
+fn user(headers: &HeaderMap) -> Result<String, ApiError> {
+    let user = audit::actor(headers);
+    if user == "anonymous" { return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: "notification preferences belong to a user; the request has no X-User-Id".into() }))); }
+    Ok(user)
+}
+
+pub async fn get_user(State(s): State<Arc<AppState>>, headers: HeaderMap) -> Result<Json<PreferencesResponse>, ApiError> {
+    let user = user(&headers)?;
+    let preferences = s.notifications.users.lock().unwrap().get(&user).cloned().unwrap_or_default();
+    Ok(Json(PreferencesResponse { project: None, user: Some(user), preferences, email_available: s.notifications.sender.smtp.is_some() }))
+}
+
+pub async fn set_user(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(preferences): Json<Preferences>) -> Result<Json<PreferencesResponse>, ApiError> {
+    let user = user(&headers)?;
+    let email_available = s.notifications.sender.smtp.is_some();
+    preferences.validate(email_available, &s.notifications.slack_hosts).map_err(|e| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e })))?;
+    s.notifications.users.lock().unwrap().insert(user.clone(), preferences.clone());
+    Ok(Json(PreferencesResponse { project: None, user: Some(user), preferences, email_available }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn smtp() -> Smtp { Smtp::parse("smtp://mail.example.org", Some("alice-bio@example.org".into())).unwrap() }
+
+    #[test]
+    fn a_crlf_subject_cannot_add_headers_or_end_the_message() {
+        let title = format!("simulate completed after 2m 00s: {}", one_line("CCO\r\nBcc: victim@example.org\r\n.\r\nRSET"));
+        let message = smtp().message(&["alice@example.org".into()], &format!("[ALICE Bio] {title}"), "Final energy -1.00 kcal/mol.\n.hidden");
+        let (head, body) = message.split_once("\r\n\r\n").unwrap();
+        assert!(head.lines().all(|l| ["From: ", "To: ", "Subject: ", " =?UTF-8?B?", "MIME-Version: ", "Content-Type: ", "Content-Transfer-Encoding: "].iter().any(|p| l.starts_with(p))), "{head}");
+        assert!(!head.contains("Bcc"));
+        assert_eq!(body.lines().filter(|l| *l == ".").count(), 1);
+        assert!(body.ends_with("\r\n.") && body.contains("\r\n..hidden\r\n"));
+        let decoded: String = head.lines().filter_map(|l| l.trim_start().trim_start_matches("Subject: ").strip_prefix("=?UTF-8?B?")?.strip_suffix("?=").map(|w| String::from_utf8(BASE64.decode(w).unwrap()).unwrap())).collect();
+        assert_eq!(decoded, format!("[ALICE Bio] {title}"));
+        assert!(!decoded.contains('\r') && !decoded.contains('\n'));
+    }
+
+    #[test]
+    fn slack_webhooks_must_be_https_on_an_allowed_host() {
+        let hosts = vec![SLACK_HOST.to_string()];
+        assert!(check_webhook("https://hooks.slack.com/services/T0/B0/x", &hosts).is_ok());
+        for url in ["http://hooks.slack.com/services/T0/B0/x", "https://169.254.169.254/latest/meta-data", "https://hooks.slack.com:8443/services/x", "https://u:p@hooks.slack.com/services/x", "https://hooks.slack.com.evil.example/x", "not a url"] {
+            assert!(check_webhook(url, &hosts).is_err(), "{url}");
+        }
+        assert!(check_webhook("https://chat.internal.example/hook", &["chat.internal.example".into()]).is_ok());
+    }
+}
@@ -0,0 +1,136 @@
+//! Novelty of designed molecules against the project's reference sets.
+//!
+//! Reference sets are the project's compound libraries (see `libraries`): known-compound
+//! dumps, patent extracts or registry mirrors uploaded as SDF. Each molecule is compared with
+//! every reference compound three ways. An exact match is the same standardized structure, or
+//! the same InChIKey when both have one (from an `InChIKey` data item of the reference, and for
+//! the molecule when it was given as an InChIKey or is in the bundled table). A skeleton match
+//! is the same heavy-atom graph once charges, isotopes and hydrogens are set aside, the same
+//! first InChIKey block when both have keys: salts, protonation states and isotopologues of a
+//! known compound. A similar match is a Morgan fingerprint Tanimoto at or above the threshold.
+//! A molecule with an exact match is `known`, one with only skeleton or similar matches is a
+//! `near_duplicate`, and the rest are `novel`.
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{chem::{self, Molecule}, fingerprint::{self, Fingerprint}, libraries::Reference, projects, record, resolver, standardize, usage, ApiError, AppState, ErrorResponse};
+
+const MODEL: &str = "morgan-tanimoto";
+pub const DEFAULT_THRESHOLD: f64 = 0.8;
+const MAX_MOLECULES: usize = 1_000;
+/// Matches listed per molecule, the closest first.
+const MAX_MATCHES: usize = 5;
+
+#[derive(Serialize, Clone)]
+pub struct Match { library_id: String, id: String, smiles: String, #[serde(rename = "match")] kind: &'static str, similarity: f64 }
+
+/// How a molecule stands against the references, with its closest matches.
+#[derive(Serialize, Clone)]
+pub struct Novelty { status: &'static str, novel: bool, #[serde(skip_serializing_if = "Vec::is_empty")] matches: Vec<Match> }
+
+impl Novelty {
+    /// The status with only the closest match, for annotating other results.
+    pub fn summary(mut self) -> Self { self.matches.truncate(1); self }
+}
+
+/// Reference compounds with their skeletons and fingerprints.
+pub struct Index { refs: Vec<(Reference, String, Fingerprint)> }
+
+fn round(v: f64) -> f64 { (v * 1000.0).round() / 1000.0 }
+
+/// The heavy-atom graph without charges, isotopes or hydrogens, as canonical SMILES.
+fn skeleton(m: &Molecule) -> String {
+    let mut bare = m.clone();
+    for a in &mut bare.atoms { (a.charge, a.isotope, a.hydrogens) = (0, None, 0); }
+    bare.to_canonical_smiles()
+}
+
+fn looks_like_inchikey(s: &str) -> bool {
+    let parts: Vec<&str> = s.split('-').collect();
+    parts.len() == 3 && [14, 10, 1].iter().zip(&parts).all(|(&n, p)| p.len() == n && p.chars().all(|c| c.is_ascii_uppercase()))
+}
+
+/// Reference compounds that parse, indexed.
+pub fn index(refs: Vec<Reference>) -> Index {
+    Index { refs: refs.into_iter().filter_map(|r| { let m = chem::parse_smiles(&r.smiles).ok()?; Some((r, skeleton(&m), fingerprint::morgan(&m, fingerprint::RADIUS))) }).collect() }
+}
+
+impl Index {
+    pub fn compounds(&self) -> usize { self.refs.len() }
+
+    /// Matches of a molecule (canonical SMILES, InChIKey, or both) at or above `threshold`.
+    pub fn check(&self, smiles: Option<&str>, inchikey: Option<&str>, threshold: f64) -> Novelty {
+        let mol = smiles.and_then(|smi| chem::parse_smiles(smi).ok());
+        let (skel, fp) = (mol.as_ref().map(skeleton), mol.as_ref().map(|m| fingerprint::morgan(m, fingerprint::RADIUS)));
+        let mut matches: Vec<(usize, Match)> = self.refs.iter().filter_map(|(r, r_skel, r_fp)| {
+            let similarity = fp.as_ref().map_or(0.0, |fp| fingerprint::tanimoto(fp, r_fp));
+            let keys = inchikey.zip(r.inchikey.as_deref());
+            let (rank, kind) = if smiles == Some(r.smiles.as_str()) || keys.is_some_and(|(a, b)| a == b) {
+                (0, "exact")
+            } else if skel.as_deref() == Some(r_skel.as_str()) || keys.is_some_and(|(a, b)| a.get(..14) == b.get(..14)) {
+                (1, "skeleton")
+            } else if similarity >= threshold {
+                (2, "similar")
+            } else {
+                return None;
+            };
+            Some((rank, Match { library_id: r.library_id.clone(), id: r.id.clone(), smiles: r.smiles.clone(), kind, similarity: round(similarity) }))
+        }).collect();
+        matches.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.similarity.total_cmp(&a.1.similarity)));
+        let status = match matches.first().map(|m| m.0) { Some(0) => "known", Some(_) => "near_duplicate", None => "novel" };
+        Novelty { status, novel: status == "novel", matches: matches.into_iter().take(MAX_MATCHES).map(|m| m.1).collect() }
+    }
+}
+
+/// `similarity_threshold` of a request, checked.
+pub fn threshold(requested: Option<f64>) -> Result<f64, String> {
+    let t = requested.unwrap_or(DEFAULT_THRESHOLD);
+    if !(t > 0.0 && t <= 1.0) { return Err("similarity_threshold must be in (0, 1]".into()); }
+    Ok(t)
+}
+
+/// The index of the caller's reference libraries (all of the project's when `ids` is empty).
+pub fn load(s: &AppState, headers: &HeaderMap, ids: &[String]) -> Result<Index, ApiError> {
+    let refs = s.libraries.references(&projects::project_id(headers), (!ids.is_empty()).then_some(ids))?;
+    if refs.is_empty() { return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: "no reference compounds to check against; upload a compound library first".into() }))); }
+    Ok(index(refs))
+}
+
+/// Novelty of designs by canonical SMILES, for annotating other results.
+pub fn annotate(index: &Index, smiles: &[String], threshold: f64) -> Vec<Novelty> {
+    smiles.iter().map(|smi| index.check(Some(smi), resolver::inchikey(smi), threshold).summary()).collect()
+}
+
+#[derive(Deserialize)]
+pub struct NoveltyRequest { molecules: Vec<String>, reference_libraries: Option<Vec<String>>, similarity_threshold: Option<f64> }
+
+#[derive(Serialize)]
+pub struct Checked { molecule: String, #[serde(skip_serializing_if = "Option::is_none")] smiles: Option<String>, #[serde(skip_serializing_if = "Option::is_none")] inchikey: Option<String>, #[serde(flatten)] novelty: Option<Novelty>, #[serde(skip_serializing_if = "Option::is_none")] error: Option<String> }
+#[derive(Serialize)]
+pub struct NoveltyResponse { novelty_id: String, references: usize, similarity_threshold: f64, novel: usize, near_duplicates: usize, known: usize, molecules: Vec<Checked> }
+
+pub async fn check(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<NoveltyRequest>) -> Result<Json<NoveltyResponse>, ApiError> {
+    let meter = usage::Meter::start();
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    if req.molecules.is_empty() || req.molecules.len() > MAX_MOLECULES { return Err(bad(format!("molecules must have between 1 and {MAX_MOLECULES} entries"))); }
+    let threshold = threshold(req.similarity_threshold).map_err(bad)?;
+    let index = load(&s, &headers, req.reference_libraries.as_deref().unwrap_or_default())?;
+    let mut molecules = Vec::new();
+    for molecule in req.molecules {
+        let smiles = standardize::resolve(&s, &headers, &molecule).await.canonical_smiles;
+        let inchikey = if looks_like_inchikey(molecule.trim()) { Some(molecule.trim().to_string()) } else { smiles.as_deref().and_then(resolver::inchikey).map(String::from) };
+        if smiles.is_none() && inchikey.is_none() {
+            molecules.push(Checked { error: Some(format!("{molecule} did not resolve to a structure")), molecule, smiles: None, inchikey: None, novelty: None });
+            continue;
+        }
+        let novelty = index.check(smiles.as_deref(), inchikey.as_deref(), threshold);
+        molecules.push(Checked { molecule, smiles, inchikey, novelty: Some(novelty), error: None });
+    }
+    let count = |status: &str| molecules.iter().filter(|m| m.novelty.as_ref().is_some_and(|n| n.status == status)).count();
+    let resp = NoveltyResponse { novelty_id: uuid::Uuid::new_v4().to_string(), references: index.compounds(), similarity_threshold: threshold, novel: count("novel"), near_duplicates: count("near_duplicate"), known: count("known"), molecules };
+    let subject = format!("{} molecule(s)", resp.molecules.len());
+    record(&s, &headers, "novelty", &subject, MODEL, &resp.novelty_id, &meter, &resp);
+    Ok(Json(resp))
+}
@@ -0,0 +1,63 @@
+//! `POST /prepare-pdbqt`; ligand and receptor preparation are `bio_engine_core::pdbqt`.
+//!
+//! A predicted receptor (see `plddt`) has its low-confidence regions excluded by default, so
+//! that disordered loops and tails don't form or block pockets in the docking box.
+
+use axum::{extract::State, http::StatusCode, response::Json};
+use bio_engine_core::plddt;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+pub use bio_engine_core::pdbqt::*;
+
+use crate::{convert::{self, Hydrogens}, ApiError, AppState, ErrorResponse};
+
+#[derive(Deserialize)]
+pub struct PrepareRequest { ligand: Option<String>, ligand_format: Option<String>, receptor: Option<String>, flexible_residues: Option<Vec<String>>, flexible_selection: Option<String>, keep_hetero: Option<bool>, low_confidence: Option<String>, plddt_cutoff: Option<f64> }
+
+#[derive(Serialize)]
+pub struct LigandPdbqt { name: String, canonical_smiles: String, atoms: usize, torsions: usize, torsdof: usize, rotatable_bonds: Vec<String>, pdbqt: String }
+#[derive(Serialize)]
+pub struct PrepareResponse { #[serde(skip_serializing_if = "Option::is_none")] ligand: Option<LigandPdbqt>, #[serde(skip_serializing_if = "Option::is_none")] receptor: Option<ReceptorPdbqt>, #[serde(skip_serializing_if = "Option::is_none")] confidence: Option<plddt::Report>, warnings: Vec<String> }
+
+pub async fn prepare(State(s): State<Arc<AppState>>, Json(req): Json<PrepareRequest>) -> Result<Json<PrepareResponse>, ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    if req.ligand.is_none() && req.receptor.is_none() { return Err(bad("give a ligand, a receptor or both".into())); }
+    let mut warnings = Vec::new();
+    let ligand = match req.ligand.as_deref() {
+        None => None,
+        Some(input) => {
+            let format = convert::format_name(req.ligand_format.as_deref(), input).map_err(bad)?;
+            let mut st = convert::load(&s, input, &format, &mut warnings).await.map_err(bad)?;
+            let smiles = st.mol.to_canonical_smiles();
+            let coords = convert::coordinates(&mut st, &mut warnings);
+            let ex = convert::explicit(&st.mol, &coords, Hydrogens::Polar, true).map_err(bad)?;
+            let charges = ex.charges.clone().unwrap_or_default();
+            let (pdbqt, torsions) = write_ligand(&st.name, &ex.mol, &ex.coords, &charges);
+            if torsions.len() > MAX_TORSIONS { warnings.push(format!("{} active torsions; AutoDock 4 accepts at most {MAX_TORSIONS} (Vina has no limit)", torsions.len())); }
+            let names = convert::atom_names(&ex.mol);
+            Some(LigandPdbqt {
+                name: st.name,
+                canonical_smiles: smiles,
+                atoms: ex.mol.atoms.len(),
+                torsions: torsions.len(),
+                torsdof: torsions.iter().filter(|t| t.heavy).count(),
+                rotatable_bonds: torsions.iter().map(|t| format!("{}-{}", names[t.fixed], names[t.moving])).collect(),
+                pdbqt,
+            })
+        }
+    };
+    let (receptor, confidence) = match req.receptor.as_deref() {
+        None => (None, None),
+        Some(text) => {
+            let (input, confidence) = plddt::apply(text, req.low_confidence.as_deref(), req.plddt_cutoff, plddt::Policy::Exclude).map_err(bad)?;
+            let flexible = req.flexible_residues.unwrap_or_default();
+            let (before, after) = (parse_residues(text, false), parse_residues(&input, false));
+            if let Some(spec) = flexible.iter().find(|f| before.iter().any(|r| matches_spec(f, r)) && !after.iter().any(|r| matches_spec(f, r))) {
+                return Err(bad(format!("flexible residue {spec} is below the pLDDT cutoff and was excluded; set low_confidence to keep to dock against it")));
+            }
+            (Some(prepare_receptor(&input, &flexible, req.flexible_selection.as_deref(), req.keep_hetero.unwrap_or(false), &mut warnings).map_err(bad)?), confidence)
+        }
+    };
+    Ok(Json(PrepareResponse { ligand, receptor, confidence, warnings }))
+}
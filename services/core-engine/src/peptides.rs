@@ -0,0 +1,231 @@
+//! Peptide library design against a target interface.
+//!
+//! The interface is a list of target residues (`ASP45`, `A:PHE50`), or the lining residues of a
+//! detected pocket on `target_protein`. A peptide lies along it in order, so each position faces
+//! the interface residues around its share of the list. A positional scan makes every single
+//! substitution of a parent sequence; a combinatorial library enumerates a template such as
+//! `GX[KR]WX[FWY]L`. Binding free energy sums residue-pair contact terms (salt bridges,
+//! cation-π, hydrophobic and aromatic packing, hydrogen bonding, and like charges and buried
+//! charge against hydrophobics), plus desolvation of charged peptide residues and the
+//! backbone entropy lost on binding, lowered for Pro and raised for Gly. Kd follows at 298 K.
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::{descriptors, helm, pockets, record, usage, ApiError, AppState, ErrorResponse, DOCK_MODEL};
+
+const AMINO: &[u8] = b"ARNDCQEGHILKMFPSTWYV";
+const THREE_LETTER: [&str; 20] = ["ALA", "ARG", "ASN", "ASP", "CYS", "GLN", "GLU", "GLY", "HIS", "ILE", "LEU", "LYS", "MET", "PHE", "PRO", "SER", "THR", "TRP", "TYR", "VAL"];
+/// Cys is left out by default: free thiols scramble into disulfides during synthesis.
+const DEFAULT_ALPHABET: &str = "ADEFGHIKLMNPQRSTVWY";
+/// RT at 298 K, kcal/mol.
+const RT: f64 = 0.593;
+const MAX_LENGTH: usize = 40;
+const MAX_LIBRARY: usize = 500_000;
+const DEFAULT_TOP_N: usize = 50;
+const MAX_TOP_N: usize = 1000;
+/// Backbone and side-chain entropy lost per residue on binding, kcal/mol.
+const ENTROPY_PER_RESIDUE: f64 = 0.15;
+const DESOLVATION_CHARGED: f64 = 0.25;
+
+#[derive(Deserialize)]
+pub struct DesignRequest { mode: Option<String>, target_protein: Option<String>, pocket_id: Option<String>, interface_residues: Option<Vec<String>>, sequence: Option<String>, positions: Option<Vec<usize>>, template: Option<String>, alphabet: Option<String>, top_n: Option<usize> }
+
+#[derive(Serialize)]
+pub struct DesignedPeptide { rank: usize, sequence: String, #[serde(skip_serializing_if = "Vec::is_empty")] mutations: Vec<String>, binding_energy_kcal: f64, kd_nm: f64, #[serde(skip_serializing_if = "Option::is_none")] ddg_kcal: Option<f64>, net_charge: i32, #[serde(skip_serializing_if = "Option::is_none")] molecular_weight: Option<f64>, helm: String }
+#[derive(Serialize)]
+pub struct PositionProfile { position: usize, wild_type: char, best: char, ddg_kcal: BTreeMap<char, f64> }
+#[derive(Serialize)]
+pub struct Parent { sequence: String, binding_energy_kcal: f64, kd_nm: f64 }
+#[derive(Serialize)]
+pub struct DesignResponse { design_id: String, mode: &'static str, #[serde(skip_serializing_if = "Option::is_none")] target: Option<String>, #[serde(skip_serializing_if = "Option::is_none")] pocket_id: Option<String>, interface: Vec<String>, library_size: usize, #[serde(skip_serializing_if = "Option::is_none")] parent: Option<Parent>, hits: Vec<DesignedPeptide>, #[serde(skip_serializing_if = "Vec::is_empty")] positions: Vec<PositionProfile>, #[serde(skip_serializing_if = "Vec::is_empty")] warnings: Vec<String>, elapsed_us: u128 }
+
+fn round3(v: f64) -> f64 { (v * 1000.0).round() / 1000.0 }
+fn charge(r: u8) -> i32 { match r { b'K' | b'R' => 1, b'D' | b'E' => -1, _ => 0 } }
+fn hydrophobic(r: u8) -> bool { b"AVILMFWYC".contains(&r) }
+fn aromatic(r: u8) -> bool { b"FWYH".contains(&r) }
+fn polar(r: u8) -> bool { b"STNQHYWDEKR".contains(&r) }
+
+/// Contact free energy of peptide residue `p` against target residue `t`, kcal/mol.
+fn pair(p: u8, t: u8) -> f64 {
+    let (qp, qt) = (charge(p), charge(t));
+    if qp * qt < 0 { return -1.2; }
+    if qp * qt > 0 { return 0.8; }
+    if (qp > 0 && aromatic(t)) || (qt > 0 && aromatic(p)) { return -0.6; }
+    if hydrophobic(p) && hydrophobic(t) { return if aromatic(p) && aromatic(t) { -0.9 } else { -0.5 }; }
+    if (hydrophobic(p) && qt != 0) || (hydrophobic(t) && qp != 0) { return 0.3; }
+    if polar(p) && polar(t) { return -0.4; }
+    0.0
+}
+
+/// The interface residues each peptide position faces, with weights: position i of n centres on
+/// its share of the m interface residues and falls off linearly over two shares' width, so
+/// neighbouring residues count half.
+fn contacts(n: usize, m: usize) -> Vec<Vec<(usize, f64)>> {
+    let share = (m as f64 / n as f64).max(1.0);
+    (0..n).map(|i| {
+        let centre = (i as f64 + 0.5) * m as f64 / n as f64 - 0.5;
+        (0..m).filter_map(|j| { let w = 1.0 - (j as f64 - centre).abs() / (2.0 * share); (w > 0.0).then_some((j, w)) }).collect()
+    }).collect()
+}
+
+/// Binding free energy of `seq` on the interface, kcal/mol.
+fn score(seq: &[u8], interface: &[u8], faces: &[Vec<(usize, f64)>]) -> f64 {
+    seq.iter().zip(faces).map(|(&r, near)| {
+        let entropy = ENTROPY_PER_RESIDUE + match r { b'G' => 0.3, b'P' => -0.2, _ => 0.0 };
+        let desolvation = if charge(r) != 0 { DESOLVATION_CHARGED } else { 0.0 };
+        near.iter().map(|&(j, w)| w * pair(r, interface[j])).sum::<f64>() + entropy + desolvation
+    }).sum()
+}
+
+fn kd_nm(dg: f64) -> f64 { let kd = 1e9 * (dg / RT).exp(); if kd < 1e3 { round3(kd) } else { kd.round() } }
+
+/// One-letter code of an interface residue written `ASP45`, `A:ASP45` or `D45`.
+fn residue_code(name: &str) -> Option<u8> {
+    let name = name.rsplit(':').next()?.trim();
+    let letters: String = name.chars().take_while(|c| c.is_ascii_alphabetic()).collect::<String>().to_ascii_uppercase();
+    match letters.len() {
+        3 => THREE_LETTER.iter().position(|&t| t == letters).map(|i| AMINO[i]),
+        1 => AMINO.iter().copied().find(|&a| a == letters.as_bytes()[0]),
+        _ => None,
+    }
+}
+
+/// Interface residue names, their one-letter codes, and the pocket they line, if any.
+type Interface = (Vec<String>, Vec<u8>, Option<String>);
+
+fn interface(req: &DesignRequest) -> Result<Interface, String> {
+    let (names, pocket) = match (&req.interface_residues, &req.target_protein) {
+        (Some(names), _) => (names.clone(), None),
+        (None, Some(target)) => {
+            let found = pockets::detect(target);
+            let p = match &req.pocket_id { Some(id) => found.into_iter().find(|p| &p.pocket_id == id).ok_or_else(|| format!("{target} has no pocket {id}"))?, None => found.into_iter().next().ok_or_else(|| format!("no pocket found on {target}"))? };
+            (p.residues, Some(p.pocket_id))
+        }
+        (None, None) => return Err("give interface_residues or target_protein".into()),
+    };
+    if names.is_empty() { return Err("interface_residues is empty".into()); }
+    let codes = names.iter().map(|n| residue_code(n).ok_or_else(|| format!("{n} is not an amino-acid residue; write it as ASP45 or A:ASP45"))).collect::<Result<_, _>>()?;
+    Ok((names, codes, pocket))
+}
+
+fn alphabet(given: Option<&str>) -> Result<Vec<u8>, String> {
+    let mut out: Vec<u8> = Vec::new();
+    for c in given.unwrap_or(DEFAULT_ALPHABET).bytes().filter(|c| !c.is_ascii_whitespace()).map(|c| c.to_ascii_uppercase()) {
+        if !AMINO.contains(&c) { return Err(format!("'{}' is not one of the 20 amino acids", c as char)); }
+        if !out.contains(&c) { out.push(c); }
+    }
+    if out.is_empty() { return Err("alphabet is empty".into()); }
+    Ok(out)
+}
+
+/// The residues allowed at each position of a template: letters are fixed, `X` is any residue
+/// of the alphabet and `[...]` lists the choices.
+fn template_choices(template: &str, alphabet: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let mut out = Vec::new();
+    let mut chars = template.bytes().filter(|c| !c.is_ascii_whitespace()).map(|c| c.to_ascii_uppercase());
+    while let Some(c) = chars.next() {
+        match c {
+            b'X' => out.push(alphabet.to_vec()),
+            b'[' => {
+                let mut set: Vec<u8> = Vec::new();
+                loop {
+                    match chars.next() {
+                        Some(b']') => break,
+                        Some(r) if AMINO.contains(&r) => if !set.contains(&r) { set.push(r) },
+                        Some(r) => return Err(format!("'{}' is not one of the 20 amino acids", r as char)),
+                        None => return Err("unclosed [ in template".into()),
+                    }
+                }
+                if set.is_empty() { return Err("empty [] in template".into()); }
+                out.push(set);
+            }
+            r if AMINO.contains(&r) => out.push(vec![r]),
+            r => return Err(format!("'{}' is not an amino acid, X or [...]", r as char)),
+        }
+    }
+    Ok(out)
+}
+
+fn check_length(n: usize) -> Result<(), String> {
+    if !(2..=MAX_LENGTH).contains(&n) { return Err(format!("peptides must be 2 to {MAX_LENGTH} residues")); }
+    Ok(())
+}
+
+pub async fn design(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<DesignRequest>) -> Result<Json<DesignResponse>, ApiError> {
+    let meter = usage::Meter::start();
+    let resp = run(&req).map_err(|e| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e })))?;
+    let subject = req.target_protein.clone().unwrap_or_else(|| resp.interface.join(","));
+    record(&s, &headers, "peptide_design", &subject, DOCK_MODEL, &resp.design_id, &meter, &resp);
+    Ok(Json(resp))
+}
+
+fn run(req: &DesignRequest) -> Result<DesignResponse, String> {
+    let t = Instant::now();
+    let (names, target, pocket_id) = interface(req)?;
+    let alphabet = alphabet(req.alphabet.as_deref())?;
+    let top_n = req.top_n.unwrap_or(DEFAULT_TOP_N);
+    if top_n == 0 || top_n > MAX_TOP_N { return Err(format!("top_n must be between 1 and {MAX_TOP_N}")); }
+    let mode = match req.mode.as_deref() {
+        Some("positional_scan") => "positional_scan",
+        Some("combinatorial") => "combinatorial",
+        None if req.template.is_some() => "combinatorial",
+        None if req.sequence.is_some() => "positional_scan",
+        None => return Err("give a sequence to scan or a template to enumerate".into()),
+        Some(other) => return Err(format!("unknown mode {other}; expected positional_scan or combinatorial")),
+    };
+    // (sequence, mutations, binding free energy)
+    let mut library: Vec<(Vec<u8>, Vec<String>, f64)> = Vec::new();
+    let (mut parent, mut positions) = (None, Vec::new());
+    if mode == "positional_scan" {
+        let seq: Vec<u8> = req.sequence.as_deref().ok_or("a positional scan needs a sequence")?.bytes().filter(|c| !c.is_ascii_whitespace()).map(|c| c.to_ascii_uppercase()).collect();
+        if let Some(&c) = seq.iter().find(|c| !AMINO.contains(c)) { return Err(format!("'{}' is not one of the 20 amino acids", c as char)); }
+        check_length(seq.len())?;
+        let scanned = req.positions.clone().unwrap_or_else(|| (1..=seq.len()).collect());
+        if let Some(p) = scanned.iter().find(|&&p| p == 0 || p > seq.len()) { return Err(format!("position {p} is outside 1..{}", seq.len())); }
+        let faces = contacts(seq.len(), target.len());
+        let base = score(&seq, &target, &faces);
+        parent = Some(Parent { sequence: String::from_utf8_lossy(&seq).into(), binding_energy_kcal: round3(base), kd_nm: kd_nm(base) });
+        for &p in &scanned {
+            let wild = seq[p - 1];
+            let mut ddg = BTreeMap::new();
+            for &r in alphabet.iter().filter(|&&r| r != wild) {
+                let mut variant = seq.clone();
+                variant[p - 1] = r;
+                let dg = score(&variant, &target, &faces);
+                ddg.insert(r as char, round3(dg - base));
+                library.push((variant, vec![format!("{}{p}{}", wild as char, r as char)], dg));
+            }
+            let best = ddg.iter().min_by(|a, b| a.1.total_cmp(b.1)).filter(|b| *b.1 < 0.0).map_or(wild as char, |b| *b.0);
+            positions.push(PositionProfile { position: p, wild_type: wild as char, best, ddg_kcal: ddg });
+        }
+    } else {
+        let choices = template_choices(req.template.as_deref().ok_or("a combinatorial library needs a template")?, &alphabet)?;
+        check_length(choices.len())?;
+        let size = choices.iter().try_fold(1usize, |n, c| n.checked_mul(c.len()).filter(|&n| n <= MAX_LIBRARY));
+        let size = size.ok_or_else(|| format!("the template makes more than {MAX_LIBRARY} peptides; fix more positions or narrow their choices"))?;
+        let faces = contacts(choices.len(), target.len());
+        for k in 0..size {
+            // Mixed-radix digits of k pick each position's residue.
+            let mut rest = k;
+            let seq: Vec<u8> = choices.iter().rev().map(|c| { let r = c[rest % c.len()]; rest /= c.len(); r }).collect::<Vec<_>>().into_iter().rev().collect();
+            let dg = score(&seq, &target, &faces);
+            library.push((seq, Vec::new(), dg));
+        }
+    }
+    let library_size = library.len();
+    library.sort_by(|a, b| a.2.total_cmp(&b.2).then_with(|| a.0.cmp(&b.0)));
+    let base = parent.as_ref().map(|p| p.binding_energy_kcal);
+    let hits: Vec<DesignedPeptide> = library.into_iter().take(top_n).enumerate().map(|(i, (seq, mutations, dg))| {
+        let sequence = String::from_utf8_lossy(&seq).into_owned();
+        let built = helm::from_sequence(&sequence, "peptide").ok();
+        let molecular_weight = built.as_ref().and_then(|h| helm::molecule(h).ok()).and_then(|m| descriptors::molecular_weight(&m).ok()).map(|w| (w * 1000.0).round() / 1000.0);
+        DesignedPeptide { rank: i + 1, mutations, binding_energy_kcal: round3(dg), kd_nm: kd_nm(dg), ddg_kcal: base.map(|b| round3(dg - b)), net_charge: seq.iter().map(|&r| charge(r)).sum(), molecular_weight, helm: built.map(|h| h.to_string()).unwrap_or_default(), sequence }
+    }).collect();
+    let mut warnings = Vec::new();
+    if hits.iter().any(|h| h.sequence.contains('C')) { warnings.push("some hits have free Cys, which can oxidize or scramble into disulfides".into()); }
+    Ok(DesignResponse { design_id: uuid::Uuid::new_v4().to_string(), mode, target: req.target_protein.clone(), pocket_id, interface: names, library_size, parent, hits, positions, warnings, elapsed_us: t.elapsed().as_micros() })
+}
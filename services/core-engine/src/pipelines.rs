@@ -0,0 +1,250 @@
+//! Pipeline orchestration.
+//!
+//! A pipeline is a DAG of steps declared in one request. Steps run in dependency order in a
+//! background task; each step receives the outputs of the steps it depends on, so e.g. a
+//! `screen` step picks up the target of an upstream `fetch_structure` and a `simulate` step
+//! runs MD on the top hits of an upstream `rescore`. Failed steps mark their dependents
+//! `skipped`.
+
+use axum::{extract::{Path, State}, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::{alphafold, charges, chem, events::{Event, Progress}, homology, metad, not_found, notify, pockets, poses, projects, qmmm, resolver, record, predict, resolve_protocol, run_energy, run_predict, run_simulate, screen_and_score, standardize, unix_now, usage, ApiError, AppState, ErrorResponse, Predicted, DOCK_MODEL, MD_MODEL};
+
+pub const STEP_KINDS: &[&str] = &["fetch_structure", "detect_pockets", "screen", "rescore", "simulate", "predict", "energy"];
+
+#[derive(Deserialize)]
+pub struct PipelineRequest { name: Option<String>, steps: Vec<StepSpec> }
+#[derive(Deserialize, Clone)]
+pub struct StepSpec { id: String, kind: String, #[serde(default)] depends_on: Vec<String>, #[serde(default)] params: Value }
+
+#[derive(Serialize, Clone)]
+pub struct StepState { id: String, kind: String, depends_on: Vec<String>, status: String, #[serde(skip_serializing_if = "Option::is_none")] output: Option<Value>, #[serde(skip_serializing_if = "Option::is_none")] error: Option<String>, elapsed_us: u128 }
+#[derive(Serialize, Clone)]
+pub struct Pipeline { pipeline_id: String, name: String, project: String, status: String, created_at_unix: u64, steps: Vec<StepState> }
+#[derive(Serialize)]
+pub struct PipelinesResponse { project: String, pipelines: Vec<PipelineSummary> }
+#[derive(Serialize)]
+pub struct PipelineSummary { pipeline_id: String, name: String, status: String, created_at_unix: u64, steps: usize }
+
+pub struct PipelineStore { pipelines: Mutex<HashMap<String, Pipeline>> }
+
+impl PipelineStore {
+    pub fn new() -> Self { Self { pipelines: Mutex::new(HashMap::new()) } }
+    /// Kind of every step still to finish, across projects, and whether it is running.
+    pub fn outstanding(&self) -> Vec<(String, bool)> {
+        self.pipelines.lock().unwrap().values().flat_map(|p| &p.steps).filter(|st| st.status == "pending" || st.status == "running").map(|st| (st.kind.clone(), st.status == "running")).collect()
+    }
+    fn update(&self, id: &str, f: impl FnOnce(&mut Pipeline)) { if let Some(p) = self.pipelines.lock().unwrap().get_mut(id) { f(p); } }
+}
+
+/// Topological order of the steps (Kahn's algorithm), rejecting unknown dependencies and cycles.
+pub fn order(steps: &[StepSpec]) -> Result<Vec<usize>, String> {
+    let index: HashMap<&str, usize> = steps.iter().enumerate().map(|(i, s)| (s.id.as_str(), i)).collect();
+    if index.len() != steps.len() { return Err("step ids must be unique".into()); }
+    let mut indegree = vec![0usize; steps.len()];
+    let mut dependents = vec![Vec::new(); steps.len()];
+    for (i, step) in steps.iter().enumerate() {
+        for dep in &step.depends_on {
+            let &d = index.get(dep.as_str()).ok_or_else(|| format!("step {} depends on unknown step {dep}", step.id))?;
+            indegree[i] += 1;
+            dependents[d].push(i);
+        }
+    }
+    let mut ready: Vec<usize> = (0..steps.len()).filter(|&i| indegree[i] == 0).rev().collect();
+    let mut out = Vec::with_capacity(steps.len());
+    while let Some(i) = ready.pop() {
+        out.push(i);
+        for &j in dependents[i].iter().rev() { indegree[j] -= 1; if indegree[j] == 0 { ready.push(j); } }
+    }
+    if out.len() != steps.len() { return Err("pipeline steps contain a dependency cycle".into()); }
+    Ok(out)
+}
+
+pub async fn create(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<PipelineRequest>) -> Result<(StatusCode, Json<Pipeline>), ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    if req.steps.is_empty() { return Err(bad("pipeline needs at least one step".into())); }
+    if let Some(step) = req.steps.iter().find(|st| !STEP_KINDS.contains(&st.kind.as_str())) { return Err(bad(format!("step {} has unknown kind {}; expected one of {}", step.id, step.kind, STEP_KINDS.join(", ")))); }
+    let order = order(&req.steps).map_err(bad)?;
+    let pipeline = Pipeline {
+        pipeline_id: uuid::Uuid::new_v4().to_string(), name: req.name.unwrap_or_else(|| "pipeline".into()), project: s.projects.resolve(&headers), status: "queued".into(), created_at_unix: unix_now(),
+        steps: req.steps.iter().map(|st| StepState { id: st.id.clone(), kind: st.kind.clone(), depends_on: st.depends_on.clone(), status: "pending".into(), output: None, error: None, elapsed_us: 0 }).collect(),
+    };
+    s.pipelines.pipelines.lock().unwrap().insert(pipeline.pipeline_id.clone(), pipeline.clone());
+    s.events.publish(Event::new("submitted", "pipeline", &pipeline.project).job(&pipeline.pipeline_id));
+    s.compute.spawn(run(s.clone(), headers, pipeline.pipeline_id.clone(), req.steps, order));
+    Ok((StatusCode::ACCEPTED, Json(pipeline)))
+}
+
+async fn run(s: Arc<AppState>, headers: HeaderMap, id: String, steps: Vec<StepSpec>, order: Vec<usize>) {
+    let project = projects::project_id(&headers);
+    let event = |name: &'static str| Event::new(name, "pipeline", &project).job(&id);
+    let progress = |done: usize, step: &StepSpec, status: &str| s.events.publish(Event { progress: Some(Progress { step: step.id.clone(), status: status.into(), done, total: steps.len() }), ..event("progress") });
+    s.pipelines.update(&id, |p| p.status = "running".into());
+    s.events.publish(event("started"));
+    let started = Instant::now();
+    let mut outputs: HashMap<String, Value> = HashMap::new();
+    let (mut failed, mut skipped) = (0, 0);
+    for (done, i) in order.into_iter().enumerate() {
+        let step = &steps[i];
+        if step.depends_on.iter().any(|d| !outputs.contains_key(d)) {
+            s.pipelines.update(&id, |p| p.steps[i].status = "skipped".into());
+            progress(done + 1, step, "skipped");
+            skipped += 1;
+            continue;
+        }
+        s.pipelines.update(&id, |p| p.steps[i].status = "running".into());
+        let inputs: Vec<&Value> = step.depends_on.iter().filter_map(|d| outputs.get(d)).collect();
+        let slot = s.admission.acquire().await;
+        let t = Instant::now();
+        let result = execute(&s, &headers, step, &inputs).await;
+        drop(slot);
+        let elapsed_us = t.elapsed().as_micros();
+        match result {
+            Ok(out) => {
+                s.pipelines.update(&id, |p| { let st = &mut p.steps[i]; st.status = "completed".into(); st.output = Some(out.clone()); st.elapsed_us = elapsed_us; });
+                outputs.insert(step.id.clone(), out);
+                progress(done + 1, step, "completed");
+            }
+            Err(e) => {
+                failed += 1;
+                s.pipelines.update(&id, |p| { let st = &mut p.steps[i]; st.status = "failed".into(); st.error = Some(e); st.elapsed_us = elapsed_us; });
+                progress(done + 1, step, "failed");
+            }
+        }
+        tokio::task::yield_now().await;
+    }
+    let error = (failed > 0).then_some("a step failed; see the pipeline's steps");
+    s.pipelines.update(&id, |p| p.status = if error.is_some() { "failed".into() } else { "completed".into() });
+    s.events.publish(match error { Some(e) => Event { error: Some(e.into()), ..event("failed") }, None => event("completed") });
+    let name = s.pipelines.pipelines.lock().unwrap().get(&id).map(|p| p.name.clone()).unwrap_or_default();
+    let summary = vec![format!("{} of {} steps completed, {failed} failed, {skipped} skipped.", outputs.len(), steps.len())];
+    s.notifications.finished(&headers, &project, notify::Finished { kind: "pipeline", id: &id, subject: &name, error, wall_seconds: started.elapsed().as_secs_f64(), summary, link: Some(s.notifications.link(&format!("/api/v1/bio/pipelines/{id}"))) });
+}
+
+/// First string value for `key` found in the step's upstream artifacts.
+fn upstream_str(inputs: &[&Value], key: &str) -> Option<String> { inputs.iter().find_map(|v| v.get(key).and_then(Value::as_str).map(String::from)) }
+fn upstream_hits(inputs: &[&Value]) -> Option<Vec<Value>> { inputs.iter().find_map(|v| v.get("hits").and_then(Value::as_array).cloned()) }
+fn param_usize(params: &Value, key: &str, default: usize) -> usize { params.get(key).and_then(Value::as_u64).map_or(default, |n| n as usize) }
+
+/// Builds a request of type `T` from the step params, filling `key` from `fallback` when unset.
+fn request<T: serde::de::DeserializeOwned>(params: &Value, key: &str, fallback: Option<String>) -> Result<T, String> {
+    let mut p = if params.is_object() { params.clone() } else { json!({}) };
+    if p.get(key).is_none() { if let Some(v) = fallback { p[key] = Value::String(v); } }
+    serde_json::from_value(p).map_err(|e| format!("invalid params: {e}"))
+}
+
+async fn execute(s: &AppState, headers: &HeaderMap, step: &StepSpec, inputs: &[&Value]) -> Result<Value, String> {
+    let params = &step.params;
+    match step.kind.as_str() {
+        "fetch_structure" => {
+            let pdb_id = params.get("pdb_id").and_then(Value::as_str).ok_or("fetch_structure needs params.pdb_id")?.to_uppercase();
+            let target = params.get("target").and_then(Value::as_str).map_or_else(|| pdb_id.clone(), String::from);
+            Ok(json!({ "pdb_id": pdb_id, "target": target }))
+        }
+        "detect_pockets" => {
+            let target = params.get("target").and_then(Value::as_str).map(String::from).or_else(|| upstream_str(inputs, "target")).ok_or("detect_pockets needs a target")?;
+            Ok(json!({ "target": target, "pockets": pockets::detect(&target) }))
+        }
+        "screen" => {
+            let req: crate::ScreenRequest = request(params, "target_protein", upstream_str(inputs, "target"))?;
+            let meter = usage::Meter::start();
+            let resp = screen_and_score(s, req).await?;
+            record(s, headers, "screen", &resp.target, DOCK_MODEL, &resp.screen_id, &meter, &resp);
+            poses::persist(s, headers, &resp);
+            serde_json::to_value(&resp).map_err(|e| e.to_string())
+        }
+        "rescore" => {
+            // Re-rank upstream hits by force-field interaction energy, plus the QM strain of each
+            // stored pose when `qm_strain` is set, and keep the best `top_n`.
+            let hits = upstream_hits(inputs).ok_or("rescore needs an upstream step producing hits")?;
+            let force_field = params.get("force_field").and_then(Value::as_str).map(String::from);
+            let charge_model = params.get("charge_model").and_then(Value::as_str).map(String::from);
+            let qm_strain = params.get("qm_strain").and_then(Value::as_bool).unwrap_or(false);
+            let qm_method = params.get("qm_method").and_then(Value::as_str);
+            let qm_weight = params.get("qm_weight").and_then(Value::as_f64).unwrap_or(1.0);
+            let screen_id = upstream_str(inputs, "screen_id");
+            let resolved: Vec<(Value, resolver::Resolved)> = hits.into_iter().filter_map(|hit| {
+                let compound = hit.get("compound_id")?.as_str()?.to_string();
+                Some((hit, resolver::resolve_local(&compound).unwrap_or_else(|| resolver::Resolved::opaque(&compound))))
+            }).collect();
+            charges::prefetch(s, charge_model.as_deref(), &resolved.iter().filter_map(|(_, m)| m.canonical_smiles.clone()).collect::<Vec<_>>()).await;
+            let mut rescored = Vec::with_capacity(resolved.len());
+            for (mut hit, mol) in resolved {
+                let e = run_energy(s, &projects::project_id(headers), crate::EnergyRequest { molecule: mol.input.clone(), force_field: force_field.clone(), charge_model: charge_model.clone(), decompose: None }, &mol)?;
+                hit["rescore_kcal"] = json!(e.total_energy_kcal);
+                if qm_strain {
+                    let pose = screen_id.as_deref().and_then(|id| s.poses.get(&projects::project_id(headers), id, &mol.input)).ok_or_else(|| format!("qm_strain needs the docked pose of {}", mol.input))?;
+                    let strain = s.qm.strain(qm_method, &chem::parse_smiles(&pose.smiles)?, &pose.coords).await?;
+                    hit["qm_strain_kcal"] = json!(strain);
+                    hit["rescore_kcal"] = json!(((e.total_energy_kcal + qm_weight * strain) * 1e3).round() / 1e3);
+                }
+                rescored.push(hit);
+            }
+            rescored.sort_by(|a, b| a["rescore_kcal"].as_f64().unwrap_or(0.0).total_cmp(&b["rescore_kcal"].as_f64().unwrap_or(0.0)));
+            rescored.truncate(param_usize(params, "top_n", 100));
+            Ok(json!({ "target": upstream_str(inputs, "target"), "hits": rescored }))
+        }
+        "simulate" => {
+            // MD on the top upstream hits when there are any, otherwise on params.molecule.
+            let molecules: Vec<String> = match upstream_hits(inputs) {
+                Some(hits) => hits.iter().filter_map(|h| h.get("compound_id").and_then(Value::as_str).map(String::from)).take(param_usize(params, "top_n", 10)).collect(),
+                None => vec![params.get("molecule").and_then(Value::as_str).ok_or("simulate needs params.molecule or upstream hits")?.to_string()],
+            };
+            let mut sims = Vec::with_capacity(molecules.len());
+            for molecule in molecules {
+                let mut req: crate::SimulateRequest = request(params, "molecule", Some(molecule.clone()))?;
+                req.molecule = molecule;
+                let proto = resolve_protocol(s, headers, req.protocol.as_deref()).map_err(|(_, Json(e))| e.error)?;
+                let meter = usage::Meter::start();
+                let mol = standardize::resolve(s, headers, &req.molecule).await;
+                let qm_mm = match &req.qm_region { Some(region) => Some(qmmm::evaluate(s, region, &mol).await?), None => None };
+                let mut resp = run_simulate(s, &projects::project_id(headers), req, proto, &mol)?;
+                resp.qm_mm = qm_mm;
+                record(s, headers, "simulate", &resp.molecule, MD_MODEL, &resp.sim_id, &meter, &resp);
+                metad::persist(s, headers, &resp);
+                sims.push(serde_json::to_value(&resp).map_err(|e| e.to_string())?);
+            }
+            Ok(json!({ "simulations": sims }))
+        }
+        "predict" => {
+            let req: predict::PredictRequest = request(params, "sequence", upstream_str(inputs, "sequence"))?;
+            let meter = usage::Meter::start();
+            let (req, alphafold) = alphafold::for_request(s, req).await?;
+            let sequence = req.sequence.clone();
+            let (req, gene) = predict::prepare(req)?;
+            let homology = homology::for_request(s, &req).await?;
+            let curated = match &req.uniprot_accession { Some(acc) => s.uniprot.annotations(acc).await, None => None };
+            let resp = Predicted::new(run_predict(s, req, gene, curated), homology, alphafold);
+            record(s, headers, "predict", &sequence, resp.model(), &resp.prediction.prediction_id, &meter, &resp);
+            serde_json::to_value(&resp).map_err(|e| e.to_string())
+        }
+        "energy" => {
+            let req: crate::EnergyRequest = request(params, "molecule", upstream_str(inputs, "molecule"))?;
+            let meter = usage::Meter::start();
+            let mol = standardize::resolve(s, headers, &req.molecule).await;
+            charges::prefetch(s, req.charge_model.as_deref(), &mol.canonical_smiles.iter().cloned().collect::<Vec<_>>()).await;
+            let resp = run_energy(s, &projects::project_id(headers), req, &mol)?;
+            record(s, headers, "energy", &resp.molecule, &resp.force_field, &resp.calc_id, &meter, &resp);
+            serde_json::to_value(&resp).map_err(|e| e.to_string())
+        }
+        other => Err(format!("unknown step kind {other}")),
+    }
+}
+
+pub async fn list(State(s): State<Arc<AppState>>, headers: HeaderMap) -> Json<PipelinesResponse> {
+    let project = projects::project_id(&headers);
+    let mut pipelines: Vec<PipelineSummary> = s.pipelines.pipelines.lock().unwrap().values().filter(|p| p.project == project)
+        .map(|p| PipelineSummary { pipeline_id: p.pipeline_id.clone(), name: p.name.clone(), status: p.status.clone(), created_at_unix: p.created_at_unix, steps: p.steps.len() }).collect();
+    pipelines.sort_by_key(|p| std::cmp::Reverse(p.created_at_unix));
+    Json(PipelinesResponse { project, pipelines })
+}
+
+pub async fn get(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(id): Path<String>) -> Result<Json<Pipeline>, ApiError> {
+    let project = projects::project_id(&headers);
+    s.pipelines.pipelines.lock().unwrap().get(&id).filter(|p| p.project == project).cloned().map(Json).ok_or_else(|| not_found("pipeline", &id))
+}
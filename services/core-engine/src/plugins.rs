@@ -0,0 +1,284 @@
+//! Custom scoring and descriptor plugins for screening.
+//!
+//! A deployment registers plugins in the JSON file named by `BIO_PLUGINS`: each has a `name`,
+//! and either a `wasm` module the engine runs itself or a `url` the job is POSTed to. A screen
+//! sends every plugin it selects one job with all its hits, `{"plugin", "target", "molecules":
+//! [{"id", "smiles", "pocket_id", "pose_sdf"}]}`, and reads back `{"results": [{"score",
+//! "descriptors": {...}}]}`, one result per molecule in order, both fields optional.
+//!
+//! A WebAssembly plugin runs under wasmtime in a fresh instance per job. It is given no imports
+//! at all, so no WASI, files, network or clock; a module that imports anything is refused when
+//! the file is loaded. It exports `memory`, `alloc(len) -> ptr`, into which the job is copied,
+//! and `score(ptr, len) -> i64`, returning its answer as `ptr << 32 | len`. Each run is held to
+//! the plugin's `fuel` (instructions, roughly), `memory_mb` and `timeout_s`, enforced by
+//! wasmtime's fuel metering, a store limiter and epoch interruption, and is stopped when it
+//! exceeds any of them. WebAssembly plugins need the engine built with the `plugins` feature, which
+//! brings in wasmtime; without it a `wasm` plugin is served at its `url` if it has one and
+//! skipped if not. Native scorers don't run inside the engine: they sit behind a `url`. A
+//! plugin that fails is a warning on the screen, not an error, and its hits go without its
+//! values.
+
+use axum::{extract::State, response::Json};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+#[cfg(feature = "plugins")]
+use wasmtime::{Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder, Trap};
+
+use crate::{chem, poses, AppState, ScreenResponse};
+
+const DEFAULT_TIMEOUT_S: u64 = 60;
+/// Fuel a WebAssembly run gets unless its plugin sets `fuel`.
+const DEFAULT_FUEL: u64 = 10_000_000_000;
+/// Linear memory a WebAssembly run may grow to unless its plugin sets `memory_mb`.
+const DEFAULT_MEMORY_MB: usize = 256;
+/// How often the epoch advances; a run's time limit is counted in these ticks.
+#[cfg(feature = "plugins")]
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+/// Largest answer a plugin may give.
+const MAX_OUTPUT: u64 = 16 << 20;
+
+/// A registered plugin, as in the `BIO_PLUGINS` file.
+#[derive(Deserialize, Clone)]
+pub struct Plugin {
+    name: String, #[serde(default)] description: String, wasm: Option<String>, url: Option<String>, timeout_s: Option<u64>, fuel: Option<u64>, memory_mb: Option<usize>,
+    /// Whether screens run it when they don't name their plugins.
+    #[serde(default)] default: bool,
+    /// Whether a lower score ranks a hit higher, as for a binding energy.
+    #[serde(default = "lower_is_better")] lower_is_better: bool,
+    /// Native commands are no longer run; kept only to refuse them by name.
+    #[serde(default)] command: Option<Value>,
+    #[cfg(feature = "plugins")] #[serde(skip)] module: Option<Module>,
+}
+
+fn lower_is_better() -> bool { true }
+
+#[derive(Serialize)]
+pub struct PluginInfo { name: String, description: String, transport: &'static str, default: bool, lower_is_better: bool, timeout_s: u64, #[serde(skip_serializing_if = "Option::is_none")] fuel: Option<u64>, #[serde(skip_serializing_if = "Option::is_none")] memory_mb: Option<usize> }
+
+#[derive(Serialize)]
+pub struct PluginsResponse { source: Option<String>, plugins: Vec<PluginInfo> }
+
+#[derive(Deserialize, Default)]
+struct Answer { #[serde(default)] results: Vec<Outcome> }
+#[derive(Deserialize, Default)]
+struct Outcome { score: Option<f64>, #[serde(default)] descriptors: BTreeMap<String, f64> }
+
+pub struct PluginSet { plugins: Vec<Plugin>, source: Option<String>, client: reqwest::Client }
+
+/// What one WebAssembly run may use.
+#[cfg(feature = "plugins")]
+struct Limits { fuel: u64, memory_bytes: usize, timeout: Duration }
+
+impl Plugin {
+    fn timeout(&self) -> Duration { Duration::from_secs(self.timeout_s.unwrap_or(DEFAULT_TIMEOUT_S)) }
+    #[cfg(feature = "plugins")]
+    fn limits(&self) -> Limits { Limits { fuel: self.fuel.unwrap_or(DEFAULT_FUEL), memory_bytes: self.memory_mb.unwrap_or(DEFAULT_MEMORY_MB) << 20, timeout: self.timeout() } }
+
+    async fn run(&self, client: &reqwest::Client, job: &Value) -> Result<Answer, String> {
+        let body = serde_json::to_vec(job).map_err(|e| e.to_string())?;
+        #[cfg(feature = "plugins")]
+        if let Some(module) = &self.module {
+            let (module, limits) = (module.clone(), self.limits());
+            let text = tokio::task::spawn_blocking(move || call(&module, &limits, &body)).await.map_err(|e| e.to_string())??;
+            return serde_json::from_str(&text).map_err(|e| format!("answered with {e}"));
+        }
+        let Some(url) = &self.url else { return Err("has neither a wasm module nor a url".into()) };
+        let resp = client.post(url).header(reqwest::header::CONTENT_TYPE, "application/json").body(body).timeout(self.timeout()).send().await.and_then(|r| r.error_for_status()).map_err(|e| e.to_string())?;
+        let bytes = resp.bytes().await.map_err(|e| e.to_string())?;
+        if bytes.len() as u64 > MAX_OUTPUT { return Err(format!("answered with more than {} MiB", MAX_OUTPUT >> 20)); }
+        let text = String::from_utf8_lossy(&bytes);
+        serde_json::from_str(&text).map_err(|e| format!("answered with {e}"))
+    }
+}
+
+/// An engine that meters fuel and interrupts on epochs, with a thread advancing the epoch.
+#[cfg(feature = "plugins")]
+fn sandbox() -> Result<Engine, String> {
+    let mut config = wasmtime::Config::new();
+    config.consume_fuel(true).epoch_interruption(true);
+    let engine = Engine::new(&config).map_err(|e| e.to_string())?;
+    let ticker = engine.clone();
+    std::thread::Builder::new().name("plugin-epoch".into()).spawn(move || loop { std::thread::sleep(EPOCH_TICK); ticker.increment_epoch(); }).map_err(|e| e.to_string())?;
+    Ok(engine)
+}
+
+/// Compiles a plugin module, refusing one that imports anything.
+#[cfg(feature = "plugins")]
+fn compile(engine: &Engine, bytes: &[u8]) -> Result<Module, String> {
+    let module = Module::new(engine, bytes).map_err(|e| format!("doesn't compile: {e}"))?;
+    if let Some(i) = module.imports().next() { return Err(format!("imports {}::{}; plugins get no host functions, WASI included", i.module(), i.name())); }
+    Ok(module)
+}
+
+/// Runs one job in a fresh instance of `module` and returns its answer.
+#[cfg(feature = "plugins")]
+fn call(module: &Module, limits: &Limits, job: &[u8]) -> Result<String, String> {
+    let engine = module.engine();
+    let mut store: Store<StoreLimits> = Store::new(engine, StoreLimitsBuilder::new().memory_size(limits.memory_bytes).instances(1).memories(1).tables(1).table_elements(10_000).build());
+    store.limiter(|l| l);
+    store.set_fuel(limits.fuel).map_err(|e| e.to_string())?;
+    store.set_epoch_deadline((limits.timeout.as_millis() / EPOCH_TICK.as_millis()).max(1) as u64);
+    let stopped = |e: wasmtime::Error| match e.downcast_ref::<Trap>() {
+        Some(Trap::OutOfFuel) => format!("ran out of fuel ({} units)", limits.fuel),
+        Some(Trap::Interrupt) => format!("timed out after {} s", limits.timeout.as_secs_f64()),
+        _ => format!("was stopped: {e:#}"),
+    };
+    let instance = Linker::new(engine).instantiate(&mut store, module).map_err(stopped)?;
+    let memory = instance.get_memory(&mut store, "memory").ok_or("exports no memory")?;
+    let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc").map_err(|e| format!("alloc: {e}"))?;
+    let score = instance.get_typed_func::<(i32, i32), i64>(&mut store, "score").map_err(|e| format!("score: {e}"))?;
+    let len = i32::try_from(job.len()).map_err(|_| "was sent a job over 2 GiB".to_string())?;
+    let at = alloc.call(&mut store, len).map_err(stopped)?;
+    memory.write(&mut store, at as u32 as usize, job).map_err(|_| "allocated the job outside its memory".to_string())?;
+    let packed = score.call(&mut store, (at, len)).map_err(stopped)? as u64;
+    let (at, n) = ((packed >> 32) as usize, packed & 0xffff_ffff);
+    if n > MAX_OUTPUT { return Err(format!("answered with more than {} MiB", MAX_OUTPUT >> 20)); }
+    let mut out = vec![0; n as usize];
+    memory.read(&store, at, &mut out).map_err(|_| "answered from outside its memory".to_string())?;
+    Ok(String::from_utf8_lossy(&out).into_owned())
+}
+
+impl PluginSet {
+    pub fn load(path: Option<String>) -> Self {
+        let mut set = Self { plugins: Vec::new(), source: None, client: reqwest::Client::new() };
+        let Some(p) = path else { return set };
+        match std::fs::read_to_string(&p).map_err(|e| e.to_string()).and_then(|text| serde_json::from_str::<Vec<Plugin>>(&text).map_err(|e| e.to_string())) {
+            Ok(plugins) => {
+                #[cfg(feature = "plugins")]
+                let mut engine = None;
+                for mut plugin in plugins {
+                    if plugin.command.is_some() { tracing::warn!("plugin {} is a native command, which the engine no longer runs; build it to WebAssembly (`wasm`) or serve it at a `url`; skipped", plugin.name); continue; }
+                    if plugin.wasm.is_none() && plugin.url.is_none() { tracing::warn!("plugin {} has neither a wasm module nor a url; skipped", plugin.name); continue; }
+                    if set.plugins.iter().any(|q| q.name == plugin.name) { tracing::warn!("plugin {} is registered twice; the first is kept", plugin.name); continue; }
+                    #[cfg(not(feature = "plugins"))]
+                    if plugin.wasm.take().is_some() {
+                        if plugin.url.is_none() { tracing::warn!("plugin {} is a wasm module and the engine was built without the `plugins` feature; skipped", plugin.name); continue; }
+                        tracing::warn!("plugin {} is a wasm module and the engine was built without the `plugins` feature; it is served at its url instead", plugin.name);
+                    }
+                    #[cfg(feature = "plugins")]
+                    if let Some(path) = &plugin.wasm {
+                        // One engine, and one epoch thread, for all of the file's modules.
+                        let module = engine.get_or_insert_with(sandbox).clone().and_then(|e| std::fs::read(path).map_err(|e| format!("can't read {path}: {e}")).and_then(|bytes| compile(&e, &bytes)));
+                        match module {
+                            Ok(m) => plugin.module = Some(m),
+                            Err(e) => { tracing::warn!("plugin {} {e}; skipped", plugin.name); continue; }
+                        }
+                    }
+                    set.plugins.push(plugin);
+                }
+                set.source = Some(p);
+            }
+            Err(e) => tracing::warn!("plugins file {p} unavailable: {e}; no plugins are registered"),
+        }
+        set
+    }
+
+    /// The plugins a screen runs: those it names, or the defaults.
+    pub fn select(&self, names: Option<&[String]>, rank_by: Option<&str>) -> Result<Vec<Plugin>, String> {
+        let chosen: Vec<Plugin> = match names {
+            Some(names) => names.iter().map(|n| self.plugins.iter().find(|p| &p.name == n).cloned().ok_or_else(|| self.unknown(n))).collect::<Result<_, _>>()?,
+            None => self.plugins.iter().filter(|p| p.default).cloned().collect(),
+        };
+        if let Some(r) = rank_by.filter(|r| !chosen.iter().any(|p| p.name == *r)) {
+            return Err(if self.plugins.iter().any(|p| p.name == r) { format!("rank_by plugin {r} isn't among the screen's plugins") } else { self.unknown(r) });
+        }
+        Ok(chosen)
+    }
+
+    fn unknown(&self, name: &str) -> String {
+        if self.plugins.is_empty() { return format!("unknown plugin {name}; this deployment registers none"); }
+        format!("unknown plugin {name}; expected one of {}", self.plugins.iter().map(|p| p.name.as_str()).collect::<Vec<_>>().join(", "))
+    }
+}
+
+/// Runs `plugins` on a screen's hits, adding their scores and descriptors, and reorders the
+/// hits by the `rank_by` plugin's score, unscored hits last.
+pub async fn apply(s: &AppState, plugins: &[Plugin], rank_by: Option<&str>, resp: &mut ScreenResponse) {
+    if plugins.is_empty() || resp.hits.is_empty() { return; }
+    let molecules: Vec<Value> = resp.hits.iter().zip(&resp.poses).map(|(h, p)| {
+        let sdf = chem::parse_smiles(&h.smiles).map(|m| poses::to_sdf(p, &m)).unwrap_or_default();
+        json!({ "id": h.compound_id, "smiles": h.smiles, "pocket_id": p.pocket_id, "pose_sdf": sdf })
+    }).collect();
+    for plugin in plugins {
+        let t = Instant::now();
+        let job = json!({ "plugin": plugin.name, "target": resp.target, "molecules": molecules });
+        match plugin.run(&s.plugins.client, &job).await {
+            Ok(answer) if answer.results.len() == resp.hits.len() => {
+                for (hit, outcome) in resp.hits.iter_mut().zip(answer.results) {
+                    if let Some(score) = outcome.score.filter(|v| v.is_finite()) { hit.plugin_scores.insert(plugin.name.clone(), score); }
+                    hit.plugin_descriptors.extend(outcome.descriptors.into_iter().filter(|(_, v)| v.is_finite()).map(|(k, v)| (format!("{}.{k}", plugin.name), v)));
+                }
+                tracing::debug!("plugin {} scored {} hits in {} ms", plugin.name, resp.hits.len(), t.elapsed().as_millis());
+            }
+            Ok(answer) => resp.warnings.push(format!("plugin {} answered {} results for {} molecules; its values were dropped", plugin.name, answer.results.len(), resp.hits.len())),
+            Err(e) => resp.warnings.push(format!("plugin {} {e}; its values are missing", plugin.name)),
+        }
+    }
+    let Some(plugin) = rank_by.and_then(|r| plugins.iter().find(|p| p.name == r)) else { return };
+    let sign = if plugin.lower_is_better { 1.0 } else { -1.0 };
+    let key = |h: &crate::ScreenHit| h.plugin_scores.get(&plugin.name).map_or(f64::INFINITY, |v| sign * v);
+    let mut ranked: Vec<_> = std::mem::take(&mut resp.hits).into_iter().zip(std::mem::take(&mut resp.poses)).collect();
+    ranked.sort_by(|a, b| key(&a.0).total_cmp(&key(&b.0)));
+    (resp.hits, resp.poses) = ranked.into_iter().unzip();
+}
+
+/// The deployment's plugins, without their module paths or URLs.
+pub async fn list(State(s): State<Arc<AppState>>) -> Json<PluginsResponse> {
+    let plugins = s.plugins.plugins.iter().map(|p| {
+        let wasm = p.wasm.is_some();
+        PluginInfo { name: p.name.clone(), description: p.description.clone(), transport: if wasm { "wasm" } else { "http" }, default: p.default, lower_is_better: p.lower_is_better, timeout_s: p.timeout().as_secs(), fuel: wasm.then(|| p.fuel.unwrap_or(DEFAULT_FUEL)), memory_mb: wasm.then(|| p.memory_mb.unwrap_or(DEFAULT_MEMORY_MB)) }
+    }).collect();
+    Json(PluginsResponse { source: s.plugins.source.clone(), plugins })
+}
+
+#[cfg(all(test, feature = "plugins"))]
+mod tests {
+    use super::*;
+
+    const LIMITS: Limits = Limits { fuel: 1_000_000, memory_bytes: 1 << 20, timeout: Duration::from_secs(60) };
+
+    /// A module with the plugin exports, `score`'s body given.
+    fn plugin(engine: &Engine, score: &str) -> Result<Module, String> {
+        compile(engine, format!(r#"(module (memory (export "memory") 1) (data (i32.const 0) "{{\"results\":[{{\"score\":-7.5}}]}}")
+            (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+            (func (export "score") (param i32 i32) (result i64) {score}))"#).as_bytes())
+    }
+
+    #[test]
+    fn a_wasm_plugin_answers_through_its_memory() {
+        let engine = sandbox().unwrap();
+        let out = call(&plugin(&engine, "(i64.const 28)").unwrap(), &LIMITS, br#"{"molecules": []}"#).unwrap();
+        assert_eq!(serde_json::from_str::<Answer>(&out).unwrap().results[0].score, Some(-7.5));
+    }
+
+    #[test]
+    fn a_runaway_plugin_is_stopped() {
+        let engine = sandbox().unwrap();
+        let spin = plugin(&engine, "(loop $l (br $l)) (i64.const 0)").unwrap();
+        assert!(call(&spin, &LIMITS, b"{}").unwrap_err().contains("ran out of fuel"));
+        let slow = Limits { fuel: 1 << 60, timeout: Duration::from_millis(200), ..LIMITS };
+        let t = Instant::now();
+        assert!(call(&spin, &slow, b"{}").unwrap_err().contains("timed out"));
+        assert!(t.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn a_plugin_cannot_take_more_memory_than_its_cap() {
+        let engine = sandbox().unwrap();
+        // 64 KiB pages: growing by 100 would take it past 1 MiB.
+        let greedy = plugin(&engine, "(if (i32.eq (memory.grow (i32.const 100)) (i32.const -1)) (then unreachable)) (i64.const 0)").unwrap();
+        assert!(call(&greedy, &LIMITS, b"{}").unwrap_err().contains("was stopped"));
+        let big = compile(&engine, br#"(module (memory (export "memory") 100))"#).unwrap();
+        assert!(call(&big, &LIMITS, b"{}").is_err());
+    }
+
+    #[test]
+    fn a_plugin_importing_anything_is_refused() {
+        let engine = sandbox().unwrap();
+        let wasi = br#"(module (import "wasi_snapshot_preview1" "fd_write" (func (param i32 i32 i32 i32) (result i32))) (memory (export "memory") 1))"#;
+        assert!(compile(&engine, wasi).unwrap_err().contains("imports wasi_snapshot_preview1::fd_write"));
+    }
+}
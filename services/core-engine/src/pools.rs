@@ -0,0 +1,40 @@
+//! Separate thread pools for interactive calls and heavyweight compute.
+//!
+//! The server and every quick call (descriptors, conversion, depiction, lookups) run on the
+//! interactive runtime, `BIO_INTERACTIVE_THREADS` workers (default one per CPU, at least two).
+//! Compute requests (the routes `autoscale` counts as jobs) and pipeline steps are handed to a
+//! runtime of their own, `BIO_COMPUTE_THREADS` workers (default one per CPU). Their work runs
+//! to completion on those threads, so however many screens are in flight, interactive calls
+//! still have workers to run on. Admission control (see `admission`) bounds how many compute
+//! requests run at once; it is best set no higher than the compute threads.
+
+use axum::{extract::{Request, State}, http::StatusCode, middleware::Next, response::{IntoResponse, Json, Response}};
+use std::sync::Arc;
+use tokio::runtime::{Builder, Runtime};
+
+use crate::{admission, autoscale, AppState, ErrorResponse};
+
+pub struct Pools { pub interactive: Runtime, pub compute: Runtime }
+
+fn runtime(name: &str, threads: usize) -> Runtime {
+    Builder::new_multi_thread().worker_threads(threads).thread_name(name).enable_all().build().unwrap_or_else(|e| panic!("cannot start the {name} runtime: {e}"))
+}
+
+impl Pools {
+    pub fn from_env() -> Self {
+        let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let interactive = admission::setting("BIO_INTERACTIVE_THREADS", cpus.max(2), |&n| n > 0);
+        let compute = admission::setting("BIO_COMPUTE_THREADS", cpus, |&n| n > 0);
+        tracing::info!("{interactive} interactive and {compute} compute threads");
+        Self { interactive: runtime("bio-interactive", interactive), compute: runtime("bio-compute", compute) }
+    }
+}
+
+/// Runs a compute request on the compute runtime.
+pub async fn dispatch(State(s): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    if autoscale::job_kind(&req).is_none() { return next.run(req).await; }
+    match s.compute.spawn(next.run(req)).await {
+        Ok(resp) => resp,
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: format!("compute task failed: {e}") })).into_response(),
+    }
+}
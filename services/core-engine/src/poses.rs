@@ -0,0 +1,55 @@
+//! Docking poses of screening hits, kept per project and screen; placement and export are
+//! `bio_engine_core::poses`.
+
+use axum::{extract::{Path, Query, State}, http::{header, HeaderMap, StatusCode}, response::{IntoResponse, Json, Response}};
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+
+pub use bio_engine_core::poses::*;
+
+use crate::{chem, not_found, projects, retention::{self, Held, Keyed, Kept}, ApiError, AppState, ErrorResponse, ScreenResponse};
+
+pub struct PoseStore { poses: Mutex<Keyed<Vec<Pose>>> }
+
+impl PoseStore {
+    pub fn new() -> Self { Self { poses: Mutex::new(Keyed::new()) } }
+    pub fn held(&self, project: &str) -> Held { retention::held(&self.poses.lock().unwrap(), project) }
+    pub fn expire(&self, before: u64) -> Held { retention::expire(&mut self.poses.lock().unwrap(), before) }
+    pub fn of_project(&self, project: &str) -> Vec<(String, Vec<Pose>)> { retention::of_project(&self.poses.lock().unwrap(), project) }
+    pub fn import(&self, project: &str, screen_id: &str, poses: Vec<Pose>) -> bool { retention::insert_new(&mut self.poses.lock().unwrap(), project, screen_id, poses) }
+
+    pub fn for_screen(&self, project: &str, screen_id: &str) -> Option<Vec<Pose>> {
+        self.poses.lock().unwrap().get(&(project.to_string(), screen_id.to_string())).map(|k| k.value.clone())
+    }
+
+    pub fn get(&self, project: &str, screen_id: &str, compound_id: &str) -> Option<Pose> {
+        self.poses.lock().unwrap().get(&(project.to_string(), screen_id.to_string()))?.value.iter().find(|p| p.compound_id == compound_id).cloned()
+    }
+}
+
+/// Pose of a compound in `pocket` (or at the origin when the target has none).
+/// Keep the poses of a screen's hits under the caller's project.
+pub fn persist(s: &AppState, headers: &HeaderMap, resp: &ScreenResponse) {
+    s.poses.poses.lock().unwrap().insert((projects::project_id(headers), resp.screen_id.clone()), Kept::new(resp.poses.clone()));
+}
+
+#[derive(Deserialize)]
+pub struct PoseQuery { format: Option<String> }
+
+pub async fn get(State(s): State<Arc<AppState>>, headers: HeaderMap, Path((screen_id, compound_id)): Path<(String, String)>, Query(q): Query<PoseQuery>) -> Result<Response, ApiError> {
+    let project = projects::project_id(&headers);
+    let pose = s.poses.get(&project, &screen_id, &compound_id).ok_or_else(|| not_found("pose", &format!("{screen_id}/{compound_id}")))?;
+    let mol = chem::parse_smiles(&pose.smiles).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: e })))?;
+    Ok(match q.format.as_deref().unwrap_or("sdf") {
+        "sdf" => ([(header::CONTENT_TYPE, "chemical/x-mdl-sdfile")], to_sdf(&pose, &mol)).into_response(),
+        "pdb" => ([(header::CONTENT_TYPE, "chemical/x-pdb")], to_pdb(&pose, &mol)).into_response(),
+        "json" => Json(pose).into_response(),
+        other => return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: format!("unknown format {other}; expected sdf, pdb or json") }))),
+    })
+}
+
+/// Deletes a screen's stored poses.
+pub async fn delete(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(screen_id): Path<String>) -> Result<Json<retention::Deleted>, ApiError> {
+    let freed = retention::remove(&mut s.poses.poses.lock().unwrap(), &projects::project_id(&headers), &screen_id).ok_or_else(|| not_found("screen", &screen_id))?;
+    Ok(retention::deleted(screen_id, freed))
+}
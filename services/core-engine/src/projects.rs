@@ -0,0 +1,177 @@
+//! Project workspaces.
+//!
+//! Jobs, results, libraries and structures belong to exactly one project. The API gateway
+//! resolves the caller's project (API keys are pinned to one) and forwards it in
+//! `X-Project-Id`; requests without one land in the `default` workspace.
+//!
+//! A project can be archived for retention without deleting anything: its job results and
+//! libraries move to cold storage (see `coldstore`), it stays readable, and it refuses every
+//! change until it is restored.
+
+use axum::{extract::{Path, Query, Request, State}, http::{HeaderMap, Method, StatusCode}, middleware::Next, response::{IntoResponse, Json, Response}};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use crate::{audit, coldstore, libraries, not_found, standardize, unix_now, ApiError, AppState, ErrorResponse};
+
+pub const DEFAULT_PROJECT: &str = "default";
+
+#[derive(Serialize, Clone)]
+pub struct Project { pub project_id: String, pub name: String, pub created_at_unix: u64, #[serde(skip_serializing_if = "Option::is_none")] pub archived_at_unix: Option<u64>, pub standardization: standardize::Settings }
+
+#[derive(Deserialize)]
+pub struct CreateProjectRequest { name: String }
+#[derive(Serialize)]
+pub struct CreatedProject { #[serde(flatten)] project: Project, project_members_entry: String }
+#[derive(Serialize)]
+pub struct ProjectSummary { #[serde(flatten)] project: Project, job_count: usize }
+#[derive(Deserialize)]
+pub struct ProjectQuery { state: Option<String> }
+#[derive(Serialize)]
+pub struct ProjectsResponse { projects: Vec<ProjectSummary> }
+
+pub struct ProjectRegistry { projects: Mutex<BTreeMap<String, Project>> }
+
+impl ProjectRegistry {
+    pub fn new() -> Self {
+        let default = Project { project_id: DEFAULT_PROJECT.into(), name: "Default workspace".into(), created_at_unix: unix_now(), archived_at_unix: None, standardization: standardize::Settings::default() };
+        Self { projects: Mutex::new(BTreeMap::from([(DEFAULT_PROJECT.to_string(), default)])) }
+    }
+
+    /// Resolves the workspace for a request, registering it on first sight.
+    pub fn resolve(&self, headers: &HeaderMap) -> String {
+        let id = project_id(headers);
+        self.projects.lock().unwrap().entry(id.clone()).or_insert_with(|| Project { project_id: id.clone(), name: id.clone(), created_at_unix: unix_now(), archived_at_unix: None, standardization: standardize::Settings::default() });
+        id
+    }
+
+    pub fn is_archived(&self, id: &str) -> bool { self.projects.lock().unwrap().get(id).is_some_and(|p| p.archived_at_unix.is_some()) }
+
+    /// How the project's incoming molecules are standardized.
+    pub fn standardization(&self, id: &str) -> standardize::Settings { self.projects.lock().unwrap().get(id).map(|p| p.standardization).unwrap_or_default() }
+
+    pub fn set_standardization(&self, id: &str, settings: standardize::Settings) -> Option<Project> {
+        let mut projects = self.projects.lock().unwrap();
+        let p = projects.get_mut(id)?;
+        p.standardization = settings;
+        Some(p.clone())
+    }
+
+    fn set_archived(&self, id: &str, at: Option<u64>) -> Option<Project> {
+        let mut projects = self.projects.lock().unwrap();
+        let p = projects.get_mut(id)?;
+        p.archived_at_unix = at;
+        Some(p.clone())
+    }
+}
+
+/// Project the request is scoped to, as forwarded by the API gateway.
+pub fn project_id(headers: &HeaderMap) -> String {
+    headers.get("x-project-id").and_then(|v| v.to_str().ok()).filter(|v| !v.is_empty()).unwrap_or(DEFAULT_PROJECT).to_string()
+}
+
+/// Registers a new project. Nobody reaches it until an operator adds `project_members_entry` to
+/// the gateway's `PROJECT_MEMBERS` (or pins an API key to it), so creating one is for admins.
+pub async fn create(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<CreateProjectRequest>) -> Json<CreatedProject> {
+    let p = Project { project_id: uuid::Uuid::new_v4().to_string(), name: req.name, created_at_unix: unix_now(), archived_at_unix: None, standardization: standardize::Settings::default() };
+    s.projects.projects.lock().unwrap().insert(p.project_id.clone(), p.clone());
+    s.audit.record(&headers, &p.project_id, "create_project", &p.project_id, "", None);
+    Json(CreatedProject { project_members_entry: format!("{}:{}", audit::actor(&headers), p.project_id), project: p })
+}
+
+/// Lists the caller's own workspace; the gateway decides which project a caller may act in.
+pub async fn list(State(s): State<Arc<AppState>>, headers: HeaderMap, Query(q): Query<ProjectQuery>) -> Result<Json<ProjectsResponse>, ApiError> {
+    let archived = state_filter(q.state.as_deref())?;
+    let pid = s.projects.resolve(&headers);
+    let project = s.projects.projects.lock().unwrap().get(&pid).cloned().filter(|p| archived.is_none_or(|a| a == p.archived_at_unix.is_some()));
+    let projects = project.into_iter().map(|p| ProjectSummary { job_count: s.jobs.count(&p.project_id), project: p }).collect();
+    Ok(Json(ProjectsResponse { projects }))
+}
+
+/// Whether a listing wants live items (`active`, the default), archived ones or `all`.
+pub fn state_filter(state: Option<&str>) -> Result<Option<bool>, ApiError> {
+    match state.unwrap_or("active") {
+        "active" => Ok(Some(false)),
+        "archived" => Ok(Some(true)),
+        "all" => Ok(None),
+        other => Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: format!("unknown state {other}; expected active, archived or all") }))),
+    }
+}
+
+pub fn conflict(error: String) -> ApiError { (StatusCode::CONFLICT, Json(ErrorResponse { error })) }
+
+/// Refuses any change to an archived project; reads, its restore and creating other projects go through.
+pub async fn guard(State(s): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let path = req.uri().path();
+    let allowed = req.method() == Method::GET || path == "/api/v1/bio/projects" || (path.starts_with("/api/v1/bio/projects/") && path.ends_with("/restore"));
+    let project = project_id(req.headers());
+    if !allowed && s.projects.is_archived(&project) { return conflict(format!("project {project} is archived; restore it to make changes")).into_response(); }
+    next.run(req).await
+}
+
+#[derive(Serialize)]
+pub struct ArchiveResponse { #[serde(flatten)] project: Project, jobs: usize, libraries: Vec<String>, #[serde(skip_serializing_if = "Vec::is_empty")] errors: Vec<String> }
+
+#[derive(Serialize, Deserialize)]
+struct ArchivedResult { job_id: String, result: serde_json::Value }
+
+/// The caller's own project, if that is the one named.
+pub fn own(headers: &HeaderMap, id: &str) -> Result<String, ApiError> {
+    let project = project_id(headers);
+    if project == id { Ok(project) } else { Err(not_found("project", id)) }
+}
+
+/// Moves the project's job results and libraries to cold storage and freezes it.
+pub async fn archive(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(id): Path<String>) -> Result<Json<ArchiveResponse>, ApiError> {
+    let project = own(&headers, &id)?;
+    {
+        let mut projects = s.projects.projects.lock().unwrap();
+        let p = projects.get_mut(&project).ok_or_else(|| not_found("project", &id))?;
+        if p.archived_at_unix.is_some() { return Err(conflict(format!("project {id} is already archived"))); }
+        p.archived_at_unix = Some(unix_now());
+    }
+    let results = s.jobs.take_results(&project);
+    let mut blob = Vec::new();
+    for (job_id, result) in &results {
+        if serde_json::to_writer(&mut blob, &ArchivedResult { job_id: job_id.clone(), result: result.clone() }).is_ok() { blob.push(b'\n'); }
+    }
+    if let Err(e) = s.cold.put(&coldstore::key(&["projects", &project, "jobs.jsonl"]), blob).await {
+        s.jobs.put_results(&project, results.into_iter().collect());
+        s.projects.set_archived(&project, None);
+        return Err((StatusCode::BAD_GATEWAY, Json(ErrorResponse { error: e })));
+    }
+    let (mut archived, mut errors) = (Vec::new(), Vec::new());
+    for library in s.libraries.of_project(&project, false) {
+        match libraries::archive_one(&s, &project, &library, true).await {
+            Ok(_) => archived.push(library),
+            Err((_, Json(e))) => errors.push(e.error),
+        }
+    }
+    s.audit.record(&headers, &project, "archive_project", &project, "", None);
+    let p = s.projects.projects.lock().unwrap().get(&project).cloned().ok_or_else(|| not_found("project", &id))?;
+    Ok(Json(ArchiveResponse { project: p, jobs: results.len(), libraries: archived, errors }))
+}
+
+/// Brings back what `archive` moved to cold storage and unfreezes the project.
+pub async fn restore(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(id): Path<String>) -> Result<Json<ArchiveResponse>, ApiError> {
+    let project = own(&headers, &id)?;
+    if !s.projects.is_archived(&project) { return Err(conflict(format!("project {id} is not archived"))); }
+    let key = coldstore::key(&["projects", &project, "jobs.jsonl"]);
+    let blob = s.cold.get(&key).await.map_err(|e| (StatusCode::BAD_GATEWAY, Json(ErrorResponse { error: e })))?;
+    let results: HashMap<String, serde_json::Value> = blob.split(|&b| b == b'\n').filter(|l| !l.is_empty()).map(|l| serde_json::from_slice::<ArchivedResult>(l).map(|r| (r.job_id, r.result))).collect::<Result<_, _>>()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: format!("archived job results of project {id} are unreadable: {e}") })))?;
+    let jobs = results.len();
+    s.jobs.put_results(&project, results);
+    let (mut restored, mut errors) = (Vec::new(), Vec::new());
+    for library in s.libraries.of_project(&project, true) {
+        match libraries::restore_one(&s, &project, &library).await {
+            Ok(_) => restored.push(library),
+            Err((_, Json(e))) => errors.push(e.error),
+        }
+    }
+    if let Err(e) = s.cold.delete(&key).await { tracing::warn!("restored project {id} but could not delete its archive: {e}"); }
+    s.audit.record(&headers, &project, "restore_project", &project, "", None);
+    let p = s.projects.set_archived(&project, None).ok_or_else(|| not_found("project", &id))?;
+    Ok(Json(ArchiveResponse { project: p, jobs, libraries: restored, errors }))
+}
@@ -0,0 +1,124 @@
+//! Sequence-derived protein properties, as ExPASy ProtParam computes them.
+//!
+//! Molecular weight sums average (or monoisotopic) residue masses plus one water. The pI is
+//! the pH of zero net charge by bisection over Bjellqvist pK values, with terminal pKs
+//! depending on the terminal residue. Extinction coefficients at 280 nm use Pace's values
+//! (Trp 5500, Tyr 1490, cystine 125 M⁻¹cm⁻¹), once with all Cys paired and once reduced. The
+//! instability index is Guruprasad's dipeptide sum (above 40 is unstable), GRAVY the mean
+//! Kyte-Doolittle hydropathy and the aliphatic index Ikai's side-chain volume measure.
+
+use axum::{http::StatusCode, response::Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{topology, ApiError, ErrorResponse};
+
+const AMINO: &[u8] = b"ARNDCQEGHILKMFPSTWYV";
+const AVERAGE_MASS: [f64; 20] = [71.0788, 156.1875, 114.1038, 115.0886, 103.1388, 128.1307, 129.1155, 57.0519, 137.1411, 113.1594, 113.1594, 128.1741, 131.1926, 147.1766, 97.1167, 87.0782, 101.1051, 186.2132, 163.1760, 99.1326];
+const MONOISOTOPIC_MASS: [f64; 20] = [71.03711, 156.10111, 114.04293, 115.02694, 103.00919, 128.05858, 129.04259, 57.02146, 137.05891, 113.08406, 113.08406, 128.09496, 131.04049, 147.06841, 97.05276, 87.03203, 101.04768, 186.07931, 163.06333, 99.06841];
+const WATER_AVERAGE: f64 = 18.01524;
+const WATER_MONOISOTOPIC: f64 = 18.01056;
+/// Guruprasad et al. (1990) dipeptide instability weights, row = first residue.
+const DIWV: [[f64; 20]; 20] = [
+    [1.0, 1.0, 1.0, -7.49, 44.94, 1.0, 1.0, 1.0, -7.49, 1.0, 1.0, 1.0, 1.0, 1.0, 20.26, 1.0, 1.0, 1.0, 1.0, 1.0], // A
+    [1.0, 58.28, 13.34, 1.0, 1.0, 20.26, 1.0, -7.49, 20.26, 1.0, 1.0, 1.0, 1.0, 1.0, 20.26, 44.94, 1.0, 58.28, -6.54, 1.0], // R
+    [1.0, 1.0, 1.0, 1.0, -1.88, -6.54, 1.0, -14.03, 1.0, 44.94, 1.0, 24.68, 1.0, -14.03, -1.88, 1.0, -7.49, -9.37, 1.0, 1.0], // N
+    [1.0, -6.54, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, -7.49, 1.0, -6.54, 1.0, 20.26, -14.03, 1.0, 1.0, 1.0], // D
+    [1.0, 1.0, 1.0, 20.26, 1.0, -6.54, 1.0, 1.0, 33.6, 1.0, 20.26, 1.0, 33.6, 1.0, 20.26, 1.0, 33.6, 24.68, 1.0, -6.54], // C
+    [1.0, 1.0, 1.0, 20.26, -6.54, 20.26, 20.26, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, -6.54, 20.26, 44.94, 1.0, 1.0, -6.54, -6.54], // Q
+    [1.0, 1.0, 1.0, 20.26, 44.94, 20.26, 33.6, 1.0, -6.54, 20.26, 1.0, 1.0, 1.0, 1.0, 20.26, 20.26, 1.0, -14.03, 1.0, 1.0], // E
+    [-7.49, 1.0, -7.49, 1.0, 1.0, 1.0, -6.54, 13.34, 1.0, -7.49, 1.0, -7.49, 1.0, 1.0, 1.0, 1.0, -7.49, 13.34, -7.49, 1.0], // G
+    [1.0, 1.0, 24.68, 1.0, 1.0, 1.0, 1.0, -9.37, 1.0, 44.94, 1.0, 24.68, 1.0, -9.37, -1.88, 1.0, -6.54, -1.88, 44.94, 1.0], // H
+    [1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 44.94, 1.0, 13.34, 1.0, 20.26, -7.49, 1.0, 1.0, -1.88, 1.0, 1.0, 1.0, 1.0, -7.49], // I
+    [1.0, 20.26, 1.0, 1.0, 1.0, 33.6, 1.0, 1.0, 1.0, 1.0, 1.0, -7.49, 1.0, 1.0, 20.26, 1.0, 1.0, 24.68, 1.0, 1.0], // L
+    [1.0, 33.6, 1.0, 1.0, 1.0, 24.64, 1.0, -7.49, 1.0, -7.49, -7.49, 1.0, 33.6, 1.0, -6.54, 1.0, 1.0, 1.0, 1.0, -7.49], // K
+    [13.34, -6.54, 1.0, 1.0, 1.0, -6.54, 1.0, 1.0, 58.28, 1.0, 1.0, 1.0, -1.88, 1.0, 44.94, 44.94, -1.88, 1.0, 24.68, 1.0], // M
+    [1.0, 1.0, 1.0, 13.34, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, -14.03, 1.0, 1.0, 20.26, 1.0, 1.0, 1.0, 33.601, 1.0], // F
+    [20.26, -6.54, 1.0, -6.54, -6.54, 20.26, 18.38, 1.0, 1.0, 1.0, 1.0, 1.0, -6.54, 20.26, 20.26, 20.26, 1.0, -1.88, 1.0, 20.26], // P
+    [1.0, 20.26, 1.0, 1.0, 33.6, 20.26, 20.26, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 44.94, 20.26, 1.0, 1.0, 1.0, 1.0], // S
+    [1.0, 1.0, -14.03, 1.0, 1.0, -6.54, 20.26, -7.49, 1.0, 1.0, 1.0, 1.0, 1.0, 13.34, 1.0, 1.0, 1.0, -14.03, 1.0, 1.0], // T
+    [-14.03, 1.0, 13.34, 1.0, 1.0, 1.0, 1.0, -9.37, 24.68, 1.0, 13.34, 1.0, 24.68, 1.0, 1.0, 1.0, -14.03, 1.0, 1.0, -7.49], // W
+    [24.68, -15.91, 1.0, 24.68, 1.0, 1.0, -6.54, -7.49, 13.34, 1.0, 1.0, 1.0, 44.94, 1.0, 13.34, 1.0, -7.49, -9.37, 13.34, 1.0], // Y
+    [1.0, 1.0, 1.0, -14.03, 1.0, 1.0, 1.0, -7.49, 1.0, 1.0, 1.0, -1.88, 1.0, 1.0, 20.26, 1.0, -7.49, 1.0, -6.54, 1.0], // V
+];
+
+#[derive(Deserialize)]
+pub struct PropertiesRequest { sequence: String }
+
+#[derive(Serialize)]
+pub struct Composition { residue: char, count: usize, percent: f64 }
+#[derive(Serialize)]
+pub struct PropertiesResponse {
+    length: usize,
+    molecular_weight_da: f64,
+    monoisotopic_mass_da: f64,
+    isoelectric_point: f64,
+    charge_at_ph7: f64,
+    extinction_coefficient_cystines: u32,
+    extinction_coefficient_reduced: u32,
+    /// A280 of a 1 g/L solution, all Cys paired.
+    absorbance_0_1_percent: f64,
+    instability_index: f64,
+    stable: bool,
+    gravy: f64,
+    aliphatic_index: f64,
+    composition: Vec<Composition>,
+}
+
+fn index(r: u8) -> Option<usize> { AMINO.iter().position(|&a| a == r) }
+
+/// Monoisotopic mass of a peptide (residues plus water); `None` for nonstandard residues.
+pub fn monoisotopic_mass(seq: &[u8]) -> Option<f64> { seq.iter().map(|&r| index(r).map(|i| MONOISOTOPIC_MASS[i])).sum::<Option<f64>>().map(|m| m + WATER_MONOISOTOPIC) }
+fn round(v: f64, places: i32) -> f64 { let f = 10f64.powi(places); (v * f).round() / f }
+
+/// Net charge at `ph` from Bjellqvist pK values.
+fn charge(seq: &[u8], ph: f64) -> f64 {
+    let count = |r: u8| seq.iter().filter(|&&x| x == r).count() as f64;
+    let n_term = match seq[0] { b'A' => 7.59, b'M' => 7.0, b'S' => 6.93, b'P' => 8.36, b'T' => 6.82, b'V' => 7.44, b'E' => 7.7, _ => 7.5 };
+    let c_term = match seq[seq.len() - 1] { b'D' => 4.55, b'E' => 4.75, _ => 3.55 };
+    let positive = |pk: f64, n: f64| n / (1.0 + 10f64.powf(ph - pk));
+    let negative = |pk: f64, n: f64| n / (1.0 + 10f64.powf(pk - ph));
+    positive(n_term, 1.0) + positive(10.0, count(b'K')) + positive(12.0, count(b'R')) + positive(5.98, count(b'H'))
+        - negative(c_term, 1.0) - negative(4.05, count(b'D')) - negative(4.45, count(b'E')) - negative(9.0, count(b'C')) - negative(10.0, count(b'Y'))
+}
+
+fn isoelectric_point(seq: &[u8]) -> f64 {
+    let (mut lo, mut hi) = (0.0, 14.0);
+    while hi - lo > 1e-4 {
+        let mid = (lo + hi) / 2.0;
+        if charge(seq, mid) > 0.0 { lo = mid } else { hi = mid }
+    }
+    (lo + hi) / 2.0
+}
+
+pub async fn compute(Json(req): Json<PropertiesRequest>) -> Result<Json<PropertiesResponse>, ApiError> {
+    let seq: Vec<u8> = req.sequence.bytes().filter(|b| !b.is_ascii_whitespace()).map(|b| b.to_ascii_uppercase()).collect();
+    if seq.is_empty() { return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: "sequence is empty".into() }))); }
+    if let Some(&bad) = seq.iter().find(|&&r| index(r).is_none()) {
+        return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: format!("unsupported residue {} in sequence", bad as char) })));
+    }
+    let idx: Vec<usize> = seq.iter().filter_map(|&r| index(r)).collect();
+    let len = seq.len() as f64;
+    let count = |r: u8| seq.iter().filter(|&&x| x == r).count();
+    let mw = idx.iter().map(|&i| AVERAGE_MASS[i]).sum::<f64>() + WATER_AVERAGE;
+    let mono = monoisotopic_mass(&seq).unwrap_or_default();
+    let reduced = count(b'W') as u32 * 5500 + count(b'Y') as u32 * 1490;
+    let cystines = reduced + (count(b'C') / 2) as u32 * 125;
+    let instability = 10.0 / len * idx.windows(2).map(|w| DIWV[w[0]][w[1]]).sum::<f64>();
+    let mole = |r: u8| count(r) as f64 / len * 100.0;
+    let composition = AMINO.iter().map(|&r| Composition { residue: r as char, count: count(r), percent: round(mole(r), 2) }).collect();
+    Ok(Json(PropertiesResponse {
+        length: seq.len(),
+        molecular_weight_da: round(mw, 2),
+        monoisotopic_mass_da: round(mono, 4),
+        isoelectric_point: round(isoelectric_point(&seq), 2),
+        charge_at_ph7: round(charge(&seq, 7.0), 2),
+        extinction_coefficient_cystines: cystines,
+        extinction_coefficient_reduced: reduced,
+        absorbance_0_1_percent: round(cystines as f64 / mw, 3),
+        instability_index: round(instability, 2),
+        stable: instability < 40.0,
+        gravy: round(seq.iter().map(|&r| topology::kd(r)).sum::<f64>() / len, 3),
+        aliphatic_index: round(mole(b'A') + 2.9 * mole(b'V') + 3.9 * (mole(b'I') + mole(b'L')), 2),
+        composition,
+    }))
+}
@@ -0,0 +1,83 @@
+//! Saved simulation protocols.
+//!
+//! A protocol is a named, project-scoped parameter set for `/simulate`. Protocols are
+//! immutable once saved so that every run referencing one is directly comparable; save a new
+//! name to change parameters. Built-in protocols (`standard-equilibration`) are visible in
+//! every project and their names can't be taken.
+
+use axum::{extract::{Path, State}, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crate::{not_found, observables::ObservableSpec, projects, restraints::RestraintSpec, stages::{self, Stage}, unix_now, ApiError, AppState, ErrorResponse};
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct Protocol {
+    pub name: String,
+    pub simulation_type: Option<String>,
+    pub force_field: Option<String>,
+    pub steps: Option<u64>,
+    pub temperature_k: Option<f64>,
+    pub thermostat: Option<String>,
+    #[serde(default)]
+    pub analyses: Vec<String>,
+    #[serde(default)]
+    pub restraints: Vec<RestraintSpec>,
+    #[serde(default)]
+    pub observables: Vec<ObservableSpec>,
+    #[serde(default)]
+    pub stages: Vec<Stage>,
+    #[serde(default)]
+    pub created_at_unix: u64,
+}
+
+#[derive(Serialize)]
+pub struct ProtocolsResponse { project: String, protocols: Vec<Protocol> }
+
+pub struct ProtocolStore { protocols: Mutex<BTreeMap<(String, String), Protocol>> }
+
+/// Protocols every project has.
+fn builtin() -> Vec<Protocol> {
+    vec![Protocol { name: "standard-equilibration".into(), simulation_type: Some("molecular-dynamics".into()), thermostat: Some("langevin".into()), stages: stages::standard(), ..Protocol::default() }]
+}
+
+impl ProtocolStore {
+    pub fn new() -> Self { Self { protocols: Mutex::new(BTreeMap::new()) } }
+    pub fn get(&self, project: &str, name: &str) -> Option<Protocol> {
+        builtin().into_iter().find(|p| p.name == name).or_else(|| self.protocols.lock().unwrap().get(&(project.to_string(), name.to_string())).cloned())
+    }
+    /// The project's own protocols, without the built-in ones.
+    pub fn for_project(&self, project: &str) -> Vec<Protocol> { self.protocols.lock().unwrap().iter().filter(|((p, _), _)| p == project).map(|(_, v)| v.clone()).collect() }
+    /// Saves an exported protocol as is; false when the name is taken.
+    pub fn import(&self, project: &str, p: Protocol) -> bool {
+        let mut protocols = self.protocols.lock().unwrap();
+        let key = (project.to_string(), p.name.clone());
+        if protocols.contains_key(&key) || builtin().iter().any(|b| b.name == p.name) { return false; }
+        protocols.insert(key, p);
+        true
+    }
+}
+
+pub async fn create(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(mut p): Json<Protocol>) -> Result<(StatusCode, Json<Protocol>), ApiError> {
+    if p.name.trim().is_empty() { return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: "protocol name must not be empty".into() }))); }
+    stages::validate(&p.stages).map_err(|e| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e })))?;
+    if let Some(t) = p.temperature_k { crate::check_temperature(t).map_err(|e| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e })))?; }
+    let project = s.projects.resolve(&headers);
+    let mut protocols = s.protocols.protocols.lock().unwrap();
+    let key = (project, p.name.clone());
+    if protocols.contains_key(&key) || builtin().iter().any(|b| b.name == p.name) { return Err((StatusCode::CONFLICT, Json(ErrorResponse { error: format!("protocol {} already exists", p.name) }))); }
+    p.created_at_unix = unix_now();
+    protocols.insert(key, p.clone());
+    Ok((StatusCode::CREATED, Json(p)))
+}
+
+pub async fn list(State(s): State<Arc<AppState>>, headers: HeaderMap) -> Json<ProtocolsResponse> {
+    let project = projects::project_id(&headers);
+    let protocols = builtin().into_iter().chain(s.protocols.protocols.lock().unwrap().iter().filter(|((p, _), _)| *p == project).map(|(_, v)| v.clone())).collect();
+    Json(ProtocolsResponse { project, protocols })
+}
+
+pub async fn get(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(name): Path<String>) -> Result<Json<Protocol>, ApiError> {
+    s.protocols.get(&projects::project_id(&headers), &name).map(Json).ok_or_else(|| not_found("protocol", &name))
+}
@@ -0,0 +1,207 @@
+//! Signed provenance records for computed results.
+//!
+//! Every recorded job carries a provenance record: the engine version, the model version, the
+//! force field where one was used, a SHA-256 of the request's parameters, one of each of its
+//! inputs (the molecule, sequence, target, library and so on, as the request named them), and
+//! one of the result itself. The record is signed with the engine's key, so a result taken out
+//! of the platform can still be shown to be exactly what this engine computed from those
+//! inputs. `POST /api/v1/bio/verify` checks a stored job or a record presented with its result.
+//!
+//! `BIO_SIGNING_KEY` picks the key: `ed25519:/path/key.pk8` signs with the Ed25519 key in that
+//! PKCS#8 file (generated there on first start if the file doesn't exist), and `hmac:<secret>`
+//! signs with HMAC-SHA256. Unset, a fresh Ed25519 key is made at every start, so records
+//! signed before a restart no longer verify. `GET /api/v1/bio/provenance/key` gives the public
+//! key for checking signatures outside the platform.
+
+use axum::{body::Body, extract::{Request, State}, http::{header, HeaderMap, HeaderValue, Method, StatusCode}, middleware::Next, response::{Json, Response}};
+use ring::{digest, hmac, rand::SystemRandom, signature::{self, Ed25519KeyPair, KeyPair}};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::{not_found, projects, unix_now, ApiError, AppState, ErrorResponse};
+
+/// Carries the request digest to `record`; a client-sent value is discarded.
+pub const DIGEST_HEADER: &str = "x-bio-request-digest";
+/// The largest body hashed, as axum's default `Json` limit.
+const MAX_BODY: usize = 2 * 1024 * 1024;
+const VERSION: u32 = 1;
+/// The largest parameters kept with a job, serialized.
+const MAX_PARAMETERS: usize = 64 * 1024;
+/// Request fields hashed one by one as inputs; every other field is a parameter.
+const INPUTS: &[&str] = &[
+    "molecule", "sequence", "target_protein", "library", "compound", "concentrations", "responses", "fragments", "input", "ligand", "receptor",
+    "antigen_sequence", "antigen_pdb", "antibody_sequence", "pdb", "simulation_ids",
+];
+
+fn sha256(bytes: &[u8]) -> String { hex(digest::digest(&digest::SHA256, bytes).as_ref()) }
+
+fn hex(bytes: &[u8]) -> String { bytes.iter().map(|b| format!("{b:02x}")).collect() }
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) { return None; }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// Hashes of a request's parameters and of each of its inputs, and the parameters themselves
+/// when they are no larger than `MAX_PARAMETERS`.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct RequestDigest { pub parameters_sha256: String, pub inputs: BTreeMap<String, String>, #[serde(default, skip_serializing_if = "Value::is_null")] pub parameters: Value }
+
+impl RequestDigest {
+    /// Object keys are sorted (serde_json keeps maps ordered), so equal requests hash alike.
+    fn of(body: &Value) -> Self {
+        let mut parameters = body.clone();
+        let mut inputs = BTreeMap::new();
+        if let Some(fields) = parameters.as_object_mut() {
+            for key in INPUTS {
+                if let Some(v) = fields.remove(*key) { inputs.insert(key.to_string(), sha256(&serde_json::to_vec(&v).unwrap_or_default())); }
+            }
+        }
+        let bytes = serde_json::to_vec(&parameters).unwrap_or_default();
+        let parameters_sha256 = sha256(&bytes);
+        Self { parameters_sha256, inputs, parameters: if bytes.len() <= MAX_PARAMETERS { parameters } else { Value::Null } }
+    }
+}
+
+/// Hashes the JSON body of a `POST` for the job it records. Runs after `chain::resolve`, so
+/// the parameters are those the job actually ran with.
+pub async fn digest(req: Request, next: Next) -> Result<Response, ApiError> {
+    let (mut parts, body) = req.into_parts();
+    parts.headers.remove(DIGEST_HEADER);
+    let json = parts.headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).is_some_and(|v| v.starts_with("application/json"));
+    if parts.method != Method::POST || !json { return Ok(next.run(Request::from_parts(parts, body)).await); }
+    let bytes = axum::body::to_bytes(body, MAX_BODY).await.map_err(|e| (StatusCode::PAYLOAD_TOO_LARGE, Json(ErrorResponse { error: format!("request body unavailable: {e}") })))?;
+    if let Ok(body) = serde_json::from_slice::<Value>(&bytes) {
+        let digest = serde_json::to_string(&RequestDigest::of(&body)).unwrap_or_default();
+        if let Ok(v) = HeaderValue::from_bytes(digest.as_bytes()) { parts.headers.insert(DIGEST_HEADER, v); }
+    }
+    Ok(next.run(Request::from_parts(parts, Body::from(bytes))).await)
+}
+
+/// What is signed: everything in a provenance record but the signature.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Statement {
+    pub version: u32, pub job_id: String, pub kind: String, pub engine_version: String, pub model_version: String, #[serde(default, skip_serializing_if = "Option::is_none")] pub force_field: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")] pub parameters_sha256: Option<String>, #[serde(default)] pub inputs: BTreeMap<String, String>, pub result_sha256: String,
+    pub created_at_unix: u64, pub algorithm: String, pub key_id: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Provenance { #[serde(flatten)] pub statement: Statement, pub signature: String }
+
+enum Key { Ed25519(Ed25519KeyPair), Hmac(hmac::Key) }
+
+pub struct Signer { key: Key, key_id: String }
+
+fn ed25519(path: &str) -> Result<Ed25519KeyPair, String> {
+    let pkcs8 = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let doc = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).map_err(|_| "cannot generate an Ed25519 key".to_string())?;
+            std::fs::write(path, doc.as_ref()).map_err(|e| format!("cannot write {path}: {e}"))?;
+            tracing::info!("generated an Ed25519 signing key in {path}");
+            doc.as_ref().to_vec()
+        }
+        Err(e) => return Err(format!("cannot read {path}: {e}")),
+    };
+    Ed25519KeyPair::from_pkcs8_maybe_unchecked(&pkcs8).map_err(|e| format!("{path} is not a PKCS#8 Ed25519 key: {e}"))
+}
+
+/// The parameters of the request with these headers, as its provenance record hashed them.
+pub fn parameters(headers: &HeaderMap) -> Value {
+    headers.get(DIGEST_HEADER).and_then(|v| serde_json::from_slice::<RequestDigest>(v.as_bytes()).ok()).map(|d| d.parameters).unwrap_or_default()
+}
+
+impl Signer {
+    pub fn from_env() -> Self {
+        let spec = std::env::var("BIO_SIGNING_KEY").ok();
+        let key = match spec.as_deref().map(|s| s.split_once(':').unwrap_or((s, ""))) {
+            Some(("ed25519", path)) => ed25519(path).map(Key::Ed25519).unwrap_or_else(|e| panic!("BIO_SIGNING_KEY: {e}")),
+            Some(("hmac", secret)) if !secret.is_empty() => Key::Hmac(hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes())),
+            Some(_) => panic!("BIO_SIGNING_KEY must be ed25519:<path> or hmac:<secret>"),
+            None => {
+                tracing::warn!("BIO_SIGNING_KEY is unset; signing provenance with a temporary key that is lost on restart");
+                let doc = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).expect("cannot generate an Ed25519 key");
+                Key::Ed25519(Ed25519KeyPair::from_pkcs8(doc.as_ref()).expect("generated key is valid"))
+            }
+        };
+        // An HMAC key ID must not give the secret away, so it hashes the secret's own signature.
+        let id_bytes = match &key { Key::Ed25519(k) => k.public_key().as_ref().to_vec(), Key::Hmac(k) => hmac::sign(k, b"alice-bio key id").as_ref().to_vec() };
+        Self { key_id: sha256(&id_bytes)[..16].to_string(), key }
+    }
+
+    fn algorithm(&self) -> &'static str { match self.key { Key::Ed25519(_) => "ed25519", Key::Hmac(_) => "hmac-sha256" } }
+
+    fn sign(&self, message: &[u8]) -> String {
+        match &self.key { Key::Ed25519(k) => hex(k.sign(message).as_ref()), Key::Hmac(k) => hex(hmac::sign(k, message).as_ref()) }
+    }
+
+    /// A signed record for the result of job `job_id`.
+    pub fn stamp(&self, headers: &HeaderMap, job_id: &str, kind: &str, model: &str, result: &Value) -> Provenance {
+        let digest: Option<RequestDigest> = headers.get(DIGEST_HEADER).and_then(|v| serde_json::from_slice(v.as_bytes()).ok());
+        let force_field = result.get("force_field").and_then(Value::as_str).map(String::from);
+        let statement = Statement {
+            version: VERSION, job_id: job_id.into(), kind: kind.into(), engine_version: env!("CARGO_PKG_VERSION").into(), model_version: model.into(), force_field,
+            parameters_sha256: digest.as_ref().map(|d| d.parameters_sha256.clone()), inputs: digest.map(|d| d.inputs).unwrap_or_default(),
+            result_sha256: sha256(&serde_json::to_vec(result).unwrap_or_default()), created_at_unix: unix_now(), algorithm: self.algorithm().into(), key_id: self.key_id.clone(),
+        };
+        let signature = self.sign(&serde_json::to_vec(&statement).unwrap_or_default());
+        Provenance { statement, signature }
+    }
+
+    /// Whether `p` was signed by this engine's key, or by the Ed25519 `public_key` given.
+    fn check(&self, p: &Provenance, public_key: Option<&[u8]>) -> Result<(), String> {
+        let message = serde_json::to_vec(&p.statement).unwrap_or_default();
+        let signature = unhex(&p.signature).ok_or("signature is not hex")?;
+        let st = &p.statement;
+        match (st.algorithm.as_str(), &self.key, public_key) {
+            ("ed25519", _, Some(pk)) if sha256(pk)[..16] != st.key_id => Err(format!("the given public key is not key {}", st.key_id)),
+            ("ed25519", _, Some(pk)) => signature::UnparsedPublicKey::new(&signature::ED25519, pk).verify(&message, &signature).map_err(|_| "signature does not match the given public key".into()),
+            _ if st.key_id != self.key_id => Err(format!("signed with key {}, not this engine's key {}", st.key_id, self.key_id)),
+            ("ed25519", Key::Ed25519(k), None) => signature::UnparsedPublicKey::new(&signature::ED25519, k.public_key().as_ref()).verify(&message, &signature).map_err(|_| "signature does not match".into()),
+            ("hmac-sha256", Key::Hmac(k), None) => hmac::verify(k, &message, &signature).map_err(|_| "signature does not match".into()),
+            (other, _, _) => Err(format!("cannot check a {other} signature with this engine's {} key", self.algorithm())),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct KeyResponse { algorithm: &'static str, key_id: String, #[serde(skip_serializing_if = "Option::is_none")] public_key: Option<String> }
+
+pub async fn key(State(s): State<Arc<AppState>>) -> Json<KeyResponse> {
+    let public_key = match &s.signer.key { Key::Ed25519(k) => Some(hex(k.public_key().as_ref())), Key::Hmac(_) => None };
+    Json(KeyResponse { algorithm: s.signer.algorithm(), key_id: s.signer.key_id.clone(), public_key })
+}
+
+/// A stored job by ID, or a provenance record with (optionally) the result it describes.
+#[derive(Deserialize)]
+pub struct VerifyRequest { job_id: Option<String>, provenance: Option<Provenance>, result: Option<Value>, public_key: Option<String> }
+
+#[derive(Serialize)]
+pub struct VerifyResponse {
+    valid: bool, signature_valid: bool, #[serde(skip_serializing_if = "Option::is_none")] result_matches: Option<bool>, trusted_key: bool,
+    provenance: Provenance, #[serde(skip_serializing_if = "Vec::is_empty")] errors: Vec<String>,
+}
+
+pub async fn verify(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<VerifyRequest>) -> Result<Json<VerifyResponse>, ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let (provenance, result) = match (req.job_id, req.provenance) {
+        (Some(id), None) => {
+            let job = s.jobs.get(&projects::project_id(&headers), &id).ok_or_else(|| not_found("job", &id))?;
+            let p = job.provenance.ok_or_else(|| bad(format!("job {id} has no provenance record")))?;
+            // An archived job's result is in cold storage; the signature can still be checked.
+            (p, (!job.archived).then_some(job.result))
+        }
+        (None, Some(p)) => (p, req.result),
+        _ => return Err(bad("give either job_id or provenance".into())),
+    };
+    let public_key = req.public_key.as_deref().map(|k| unhex(k).ok_or_else(|| bad("public_key is not hex".into()))).transpose()?;
+    let mut errors = Vec::new();
+    let signature_valid = s.signer.check(&provenance, public_key.as_deref()).map_err(|e| errors.push(e)).is_ok();
+    let result_matches = result.map(|r| sha256(&serde_json::to_vec(&r).unwrap_or_default()) == provenance.statement.result_sha256);
+    if result_matches == Some(false) { errors.push("result does not hash to result_sha256".into()); }
+    let trusted_key = provenance.statement.key_id == s.signer.key_id;
+    Ok(Json(VerifyResponse { valid: signature_valid && result_matches != Some(false), signature_valid, result_matches, trusted_key, provenance, errors }))
+}
@@ -0,0 +1,92 @@
+//! Curated PTM annotations from UniProt; site prediction is `bio_engine_core::ptm`.
+//!
+//! With a UniProt accession, the entry's curated "Modified residue" and ubiquitin cross-link
+//! features are fetched from `BIO_UNIPROT_URL` (default the UniProt REST API) and cached. The
+//! same entry gives target triage its canonical sequence, gene and recommended name.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+pub use bio_engine_core::ptm::*;
+
+use crate::resolver::percent_encode;
+
+const DEFAULT_UNIPROT_URL: &str = "https://rest.uniprot.org/uniprotkb/{id}.json";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry { #[serde(default)] features: Vec<Feature>, sequence: Option<Text>, #[serde(default)] genes: Vec<GeneNames>, protein_description: Option<Description> }
+#[derive(Deserialize)]
+struct Text { value: String }
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeneNames { gene_name: Option<Text> }
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Description { recommended_name: Option<Names> }
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Names { full_name: Text }
+#[derive(Deserialize)]
+struct Feature { #[serde(rename = "type")] kind: String, location: Location, #[serde(default)] description: String }
+#[derive(Deserialize)]
+struct Location { start: Bound }
+#[derive(Deserialize)]
+struct Bound { value: Option<usize> }
+
+fn classify(kind: &str, description: &str) -> Option<&'static str> {
+    let d = description.to_lowercase();
+    match kind {
+        "Modified residue" if d.starts_with("phospho") => Some("phosphorylation"),
+        "Modified residue" if d.contains("acetyl") => Some("acetylation"),
+        "Cross-link" if d.contains("ubiquitin") => Some("ubiquitination"),
+        _ => None,
+    }
+}
+
+/// An entry's canonical sequence, first gene name and recommended protein name.
+#[derive(Clone)]
+pub struct Summary { pub sequence: String, pub gene: Option<String>, pub name: Option<String> }
+
+pub struct UniProt { client: reqwest::Client, url: String, cache: Mutex<HashMap<String, Option<Vec<Annotation>>>>, summaries: Mutex<HashMap<String, Option<Summary>>> }
+
+impl UniProt {
+    pub fn new(url: Option<String>) -> Self { Self { client: reqwest::Client::new(), url: url.unwrap_or_else(|| DEFAULT_UNIPROT_URL.into()), cache: Mutex::new(HashMap::new()), summaries: Mutex::new(HashMap::new()) } }
+
+    /// Sequence and names of an entry; `None` when the entry can't be fetched or has no sequence.
+    pub async fn summary(&self, accession: &str) -> Option<Summary> {
+        let key = accession.trim().to_uppercase();
+        if let Some(hit) = self.summaries.lock().unwrap().get(&key).cloned() { return hit; }
+        let fetched = self.entry(&key).await.and_then(|e| Some(Summary {
+            sequence: e.sequence?.value,
+            gene: e.genes.into_iter().find_map(|g| g.gene_name).map(|t| t.value),
+            name: e.protein_description.and_then(|d| d.recommended_name).map(|n| n.full_name.value),
+        }));
+        self.summaries.lock().unwrap().insert(key, fetched.clone());
+        fetched
+    }
+
+    /// PTM features of an entry; `None` when the entry can't be fetched.
+    pub async fn annotations(&self, accession: &str) -> Option<Vec<Annotation>> {
+        let key = accession.trim().to_uppercase();
+        if let Some(hit) = self.cache.lock().unwrap().get(&key).cloned() { return hit; }
+        let fetched = self.fetch(&key).await;
+        self.cache.lock().unwrap().insert(key, fetched.clone());
+        fetched
+    }
+
+    async fn entry(&self, accession: &str) -> Option<Entry> {
+        let url = self.url.replace("{id}", &percent_encode(accession));
+        let resp = self.client.get(&url).timeout(std::time::Duration::from_secs(5)).send().await.ok()?.error_for_status().ok()?;
+        serde_json::from_str(&resp.text().await.ok()?).ok()
+    }
+
+    async fn fetch(&self, accession: &str) -> Option<Vec<Annotation>> {
+        let entry = self.entry(accession).await?;
+        Some(entry.features.into_iter().filter_map(|f| {
+            let modification = classify(&f.kind, &f.description)?;
+            Some(Annotation { position: f.location.start.value?, modification, description: f.description })
+        }).collect())
+    }
+}
@@ -0,0 +1,242 @@
+//! Quantum-chemistry engine hook.
+//!
+//! Single-point energies and geometry optimizations of small molecules run on an external
+//! engine picked with `BIO_QM_ENGINE`: `xtb` or `psi4` runs that program (from `PATH`, or at
+//! the path after a colon as in `xtb:/opt/xtb/bin/xtb`) in a scratch directory, and an
+//! `http(s)://` URL posts the job to a service. Without `BIO_QM_ENGINE`, jobs go to the
+//! `BIO_QM_URL` service that also computes AM1-BCC charges. The service receives `{"task",
+//! "method", "smiles", "net_charge", "multiplicity", "xyz"}` as JSON and answers
+//! `{"energy_hartree"}`, plus `"xyz"` with the same atoms in the same order for
+//! optimizations. Hydrogens are placed on the engine's heavy-atom coordinates and every atom
+//! goes to the engine. Jobs may carry external point charges for electrostatic embedding
+//! (`"point_charges": [[q, x, y, z], ...]` in Å for the service). Results are cached per
+//! engine, task, method, input geometry and point charges.
+
+use axum::{extract::State, http::StatusCode, response::Json};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::{chem::{self, Molecule}, convert, fnv1a, ApiError, AppState, ErrorResponse};
+
+pub const TASKS: [&str; 2] = ["energy", "optimize"];
+pub const HARTREE_KCAL: f64 = 627.509474;
+/// Bohr per Å, for engines that take point charges in atomic units.
+const BOHR_PER_ANGSTROM: f64 = 1.0 / 0.529177210903;
+/// Heavy atoms a QM job accepts; larger molecules belong to the force field.
+const MAX_HEAVY_ATOMS: usize = 100;
+const TIMEOUT: Duration = Duration::from_secs(600);
+const XTB_METHOD: &str = "gfn2";
+const PSI4_METHOD: &str = "b3lyp-d3bj/def2-svp";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Task { Energy, Optimize }
+
+impl Task {
+    pub fn parse(name: Option<&str>) -> Result<Self, String> {
+        match name.map(str::to_lowercase).as_deref() {
+            None | Some("energy" | "single-point" | "sp") => Ok(Self::Energy),
+            Some("optimize" | "optimise" | "opt") => Ok(Self::Optimize),
+            Some(other) => Err(format!("unknown task {other}; expected one of {}", TASKS.join(", "))),
+        }
+    }
+
+    pub fn name(self) -> &'static str { match self { Self::Energy => TASKS[0], Self::Optimize => TASKS[1] } }
+}
+
+enum Backend { Xtb(String), Psi4(String), Remote(String) }
+
+impl Backend {
+    fn name(&self) -> &'static str { match self { Self::Xtb(_) => "xtb", Self::Psi4(_) => "psi4", Self::Remote(_) => "remote" } }
+}
+
+/// Energy of a QM job, with the optimized coordinates of every atom for optimizations.
+#[derive(Serialize, Clone)]
+pub struct QmResult { pub engine: &'static str, pub method: String, pub task: &'static str, pub energy_hartree: f64, pub energy_kcal_mol: f64, pub elapsed_ms: u64, #[serde(skip)] pub coords: Option<Vec<[f64; 3]>> }
+
+#[derive(Serialize)]
+struct RemoteJob<'a> { task: &'static str, method: &'a str, smiles: &'a str, net_charge: i32, multiplicity: u32, xyz: &'a str, #[serde(skip_serializing_if = "Vec::is_empty")] point_charges: Vec<[f64; 4]> }
+#[derive(Deserialize)]
+struct RemoteResult { energy_hartree: f64, xyz: Option<String> }
+
+/// The configured QM engine, if any, with its result cache.
+pub struct QmEngine { backend: Option<Backend>, client: reqwest::Client, cache: Mutex<HashMap<u64, QmResult>> }
+
+impl QmEngine {
+    pub fn new(engine: Option<String>, url: Option<String>) -> Self {
+        let backend = match engine.as_deref().map(str::trim) {
+            Some(u) if u.starts_with("http://") || u.starts_with("https://") => Some(Backend::Remote(u.to_string())),
+            Some(spec) => {
+                let (kind, program) = spec.split_once(':').unwrap_or((spec, spec));
+                match kind.to_lowercase().as_str() {
+                    "xtb" => Some(Backend::Xtb(program.to_string())),
+                    "psi4" => Some(Backend::Psi4(program.to_string())),
+                    _ => { tracing::warn!("unknown BIO_QM_ENGINE {spec}; QM jobs are disabled"); None }
+                }
+            }
+            None => url.map(Backend::Remote),
+        };
+        Self { backend, client: reqwest::Client::new(), cache: Mutex::new(HashMap::new()) }
+    }
+
+    /// Runs `task` on `mol` at heavy-atom `coords`, embedded in `point_charges` (position,
+    /// charge); returns the result and whether it was cached.
+    pub async fn run(&self, task: Task, method: Option<&str>, mol: &Molecule, coords: &[[f64; 3]], point_charges: &[([f64; 3], f64)]) -> Result<(QmResult, bool), String> {
+        let backend = self.backend.as_ref().ok_or("QM jobs need an engine; set BIO_QM_ENGINE (xtb, psi4 or a service URL) or BIO_QM_URL")?;
+        if mol.atoms.len() > MAX_HEAVY_ATOMS { return Err(format!("{} heavy atoms is more than a QM job takes ({MAX_HEAVY_ATOMS})", mol.atoms.len())); }
+        let method = method.map_or_else(|| match backend { Backend::Psi4(_) => PSI4_METHOD, _ => XTB_METHOD }.to_string(), str::to_lowercase);
+        if method.is_empty() || !method.chars().all(|c| c.is_ascii_alphanumeric() || "-+()*/,._".contains(c)) { return Err(format!("invalid QM method {method}")); }
+        let ex = convert::explicit(mol, coords, convert::Hydrogens::All, false)?;
+        let net_charge: i32 = mol.atoms.iter().map(|a| a.charge as i32).sum();
+        let electrons: i32 = ex.mol.atoms.iter().map(|a| chem::atomic_number(&a.element).unwrap_or(0) as i32).sum::<i32>() - net_charge;
+        let multiplicity = if electrons % 2 == 0 { 1 } else { 2 };
+        let xyz = convert::write_xyz(&mol.to_canonical_smiles(), &ex.mol, &ex.coords);
+        let charges: Vec<[f64; 4]> = point_charges.iter().map(|&(c, q)| [q, c[0], c[1], c[2]]).collect();
+        let key = fnv1a(format!("{}|{}|{method}|{net_charge}|{multiplicity}|{xyz}|{charges:?}", backend.name(), task.name()).as_bytes());
+        if let Some(hit) = self.cache.lock().unwrap().get(&key) { return Ok((hit.clone(), true)); }
+        let t = Instant::now();
+        let job = Job { task, method: &method, net_charge, multiplicity, atoms: ex.mol.atoms.len(), xyz: &xyz, point_charges: charges };
+        let (energy, coords) = match backend {
+            Backend::Xtb(program) => scratch(|dir| xtb(program, dir, &job)).await?,
+            Backend::Psi4(program) => scratch(|dir| psi4(program, dir, &job)).await?,
+            Backend::Remote(url) => self.remote(url, &job, &mol.to_canonical_smiles()).await?,
+        };
+        if task == Task::Optimize && coords.is_none() { return Err(format!("{} returned no optimized geometry", backend.name())); }
+        let result = QmResult { engine: backend.name(), method, task: task.name(), energy_hartree: energy, energy_kcal_mol: (energy * HARTREE_KCAL * 1e4).round() / 1e4, elapsed_ms: t.elapsed().as_millis() as u64, coords };
+        self.cache.lock().unwrap().insert(key, result.clone());
+        Ok((result, false))
+    }
+
+    /// Strain of a pose in kcal/mol: its single-point energy minus that of the nearest minimum.
+    pub async fn strain(&self, method: Option<&str>, mol: &Molecule, pose: &[[f64; 3]]) -> Result<f64, String> {
+        let (bound, _) = self.run(Task::Energy, method, mol, pose, &[]).await?;
+        let (relaxed, _) = self.run(Task::Optimize, method, mol, pose, &[]).await?;
+        Ok(((bound.energy_hartree - relaxed.energy_hartree).max(0.0) * HARTREE_KCAL * 1e3).round() / 1e3)
+    }
+
+    async fn remote(&self, url: &str, job: &Job<'_>, smiles: &str) -> Result<(f64, Option<Vec<[f64; 3]>>), String> {
+        let body = serde_json::to_string(&RemoteJob { task: job.task.name(), method: job.method, smiles, net_charge: job.net_charge, multiplicity: job.multiplicity, xyz: job.xyz, point_charges: job.point_charges.clone() }).map_err(|e| e.to_string())?;
+        let resp = self.client.post(url).header(reqwest::header::CONTENT_TYPE, "application/json").body(body).timeout(TIMEOUT).send().await
+            .and_then(|r| r.error_for_status()).map_err(|e| format!("QM service: {e}"))?;
+        let parsed: RemoteResult = serde_json::from_str(&resp.text().await.map_err(|e| format!("QM service: {e}"))?).map_err(|e| format!("QM service answered with {e}"))?;
+        Ok((parsed.energy_hartree, parsed.xyz.map(|x| read_xyz(&x, job.atoms)).transpose()?))
+    }
+}
+
+/// A QM job: every atom as XYZ, with the charge and spin the engine needs and the embedding
+/// point charges as `[q, x, y, z]`.
+struct Job<'a> { task: Task, method: &'a str, net_charge: i32, multiplicity: u32, atoms: usize, xyz: &'a str, point_charges: Vec<[f64; 4]> }
+
+/// Runs `f` in a fresh scratch directory and removes it afterwards.
+async fn scratch<F, Fut>(f: F) -> Result<(f64, Option<Vec<[f64; 3]>>), String>
+where F: FnOnce(std::path::PathBuf) -> Fut, Fut: std::future::Future<Output = Result<(f64, Option<Vec<[f64; 3]>>), String>> {
+    let dir = std::env::temp_dir().join(format!("alice-qm-{}", uuid::Uuid::new_v4()));
+    tokio::fs::create_dir_all(&dir).await.map_err(|e| format!("QM scratch directory: {e}"))?;
+    let out = f(dir.clone()).await;
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+    out
+}
+
+/// Runs `program` with `args` in `dir`, killing it after `TIMEOUT`; returns its stdout.
+async fn execute(program: &str, args: &[String], dir: &Path) -> Result<String, String> {
+    let child = tokio::process::Command::new(program).args(args).current_dir(dir).kill_on_drop(true).output();
+    let out = tokio::time::timeout(TIMEOUT, child).await.map_err(|_| format!("{program} timed out after {} s", TIMEOUT.as_secs()))?
+        .map_err(|e| format!("can't run {program}: {e}"))?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        return Err(format!("{program} failed ({}): {}", out.status, stderr.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or("no output").trim()));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+async fn xtb(program: &str, dir: std::path::PathBuf, job: &Job<'_>) -> Result<(f64, Option<Vec<[f64; 3]>>), String> {
+    let level = match job.method.trim_end_matches("-xtb") {
+        "gfn2" => vec!["--gfn".to_string(), "2".into()],
+        "gfn1" => vec!["--gfn".to_string(), "1".into()],
+        "gfn0" => vec!["--gfn".to_string(), "0".into()],
+        "gfnff" | "gfn-ff" => vec!["--gfnff".to_string()],
+        other => return Err(format!("xtb has no method {other}; expected gfn2, gfn1, gfn0 or gfnff")),
+    };
+    tokio::fs::write(dir.join("input.xyz"), job.xyz).await.map_err(|e| e.to_string())?;
+    let mut args = vec!["input.xyz".to_string()];
+    args.extend(level);
+    args.extend(["--chrg".to_string(), job.net_charge.to_string(), "--uhf".into(), (job.multiplicity - 1).to_string()]);
+    if job.task == Task::Optimize { args.push("--opt".into()); }
+    if !job.point_charges.is_empty() {
+        let pcharge: String = job.point_charges.iter().map(|p| format!("{:.6} {:.6} {:.6} {:.6}\n", p[0], p[1], p[2], p[3])).collect();
+        tokio::fs::write(dir.join("pcharge"), format!("{}\n{pcharge}", job.point_charges.len())).await.map_err(|e| e.to_string())?;
+        tokio::fs::write(dir.join("xcontrol"), "$embedding\n   input=pcharge\n   format=orca\n$end\n").await.map_err(|e| e.to_string())?;
+        args.extend(["--input".to_string(), "xcontrol".into()]);
+    }
+    let stdout = execute(program, &args, &dir).await?;
+    // "| TOTAL ENERGY   -5.070544440612 Eh   |"; the last one is the final geometry's.
+    let energy = stdout.lines().rev().find(|l| l.contains("TOTAL ENERGY")).and_then(|l| { let t: Vec<&str> = l.split_whitespace().collect(); t.iter().position(|&w| w == "Eh").and_then(|p| t.get(p.checked_sub(1)?)?.parse().ok()) })
+        .ok_or("xtb printed no total energy")?;
+    let coords = match job.task {
+        Task::Energy => None,
+        Task::Optimize => Some(read_xyz(&tokio::fs::read_to_string(dir.join("xtbopt.xyz")).await.map_err(|e| format!("xtb optimized geometry: {e}"))?, job.atoms)?),
+    };
+    Ok((energy, coords))
+}
+
+async fn psi4(program: &str, dir: std::path::PathBuf, job: &Job<'_>) -> Result<(f64, Option<Vec<[f64; 3]>>), String> {
+    let (functional, basis) = job.method.split_once('/').unwrap_or((job.method, PSI4_METHOD.split_once('/').map_or("", |m| m.1)));
+    let mut input = format!("molecule mol {{\n{} {}\n", job.net_charge, job.multiplicity);
+    for line in job.xyz.lines().skip(2) { input.push_str(line); input.push('\n'); }
+    input.push_str("symmetry c1\nno_reorient\nno_com\n}\n");
+    input.push_str(&format!("set basis {basis}\n"));
+    if job.multiplicity > 1 { input.push_str("set reference uhf\n"); }
+    let external = if job.point_charges.is_empty() { String::new() } else {
+        let b = BOHR_PER_ANGSTROM;
+        format!(", external_potentials=[{}]", job.point_charges.iter().map(|p| format!("[{:.6}, [{:.6}, {:.6}, {:.6}]]", p[0], p[1] * b, p[2] * b, p[3] * b)).collect::<Vec<_>>().join(", "))
+    };
+    input.push_str(&match job.task {
+        Task::Energy => format!("E = energy('{functional}'{external})\n"),
+        Task::Optimize => format!("E = optimize('{functional}'{external})\nmol.save_xyz_file('opt.xyz', False)\n"),
+    });
+    input.push_str("print_out('ALICE_ENERGY %.12f\\n' % E)\n");
+    tokio::fs::write(dir.join("input.dat"), input).await.map_err(|e| e.to_string())?;
+    execute(program, &["input.dat".to_string(), "output.dat".to_string()], &dir).await?;
+    let output = tokio::fs::read_to_string(dir.join("output.dat")).await.map_err(|e| format!("psi4 output: {e}"))?;
+    let energy = output.lines().find_map(|l| l.trim().strip_prefix("ALICE_ENERGY")?.trim().parse().ok()).ok_or("psi4 printed no energy")?;
+    let coords = match job.task {
+        Task::Energy => None,
+        Task::Optimize => Some(read_xyz(&tokio::fs::read_to_string(dir.join("opt.xyz")).await.map_err(|e| format!("psi4 optimized geometry: {e}"))?, job.atoms)?),
+    };
+    Ok((energy, coords))
+}
+
+/// Coordinates of an XYZ block that must list `atoms` atoms.
+fn read_xyz(text: &str, atoms: usize) -> Result<Vec<[f64; 3]>, String> {
+    let coords: Vec<[f64; 3]> = text.lines().skip(2).filter(|l| !l.trim().is_empty()).map(|l| {
+        let t: Vec<f64> = l.split_whitespace().skip(1).take(3).filter_map(|w| w.parse().ok()).collect();
+        if t.len() == 3 { Ok([t[0], t[1], t[2]]) } else { Err(format!("bad XYZ line: {l}")) }
+    }).collect::<Result<_, _>>()?;
+    if coords.len() != atoms { return Err(format!("optimized geometry has {} atoms; expected {atoms}", coords.len())); }
+    Ok(coords)
+}
+
+#[derive(Deserialize)]
+pub struct QmRequest { molecule: String, format: Option<String>, task: Option<String>, method: Option<String> }
+
+#[derive(Serialize)]
+pub struct QmResponse { name: String, canonical_smiles: String, atoms: usize, #[serde(flatten)] result: QmResult, cached: bool, #[serde(skip_serializing_if = "Option::is_none")] sdf: Option<String>, warnings: Vec<String> }
+
+pub async fn run(State(s): State<Arc<AppState>>, Json(req): Json<QmRequest>) -> Result<Json<QmResponse>, ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let task = Task::parse(req.task.as_deref()).map_err(bad)?;
+    let format = convert::format_name(req.format.as_deref(), &req.molecule).map_err(bad)?;
+    let mut warnings = Vec::new();
+    let mut st = convert::load(&s, &req.molecule, &format, &mut warnings).await.map_err(bad)?;
+    let coords = convert::coordinates(&mut st, &mut warnings);
+    let smiles = st.mol.to_canonical_smiles();
+    let (result, cached) = s.qm.run(task, req.method.as_deref(), &st.mol, &coords, &[]).await.map_err(bad)?;
+    let ex = convert::explicit(&st.mol, &coords, convert::Hydrogens::All, false).map_err(bad)?;
+    let sdf = match &result.coords {
+        Some(c) => Some(convert::write_sdf(&st.name, &format!("{smiles} {} {} optimized", result.engine, result.method), &ex.mol, c, &[("energy_hartree", format!("{:.8}", result.energy_hartree))]).map_err(bad)?),
+        None => None,
+    };
+    Ok(Json(QmResponse { name: st.name, canonical_smiles: smiles, atoms: ex.mol.atoms.len(), result, cached, sdf, warnings }))
+}
@@ -0,0 +1,144 @@
+//! Hybrid QM/MM regions for `/simulate`.
+//!
+//! The QM region is the ligand, docked into a pocket of the target, and the pocket residues
+//! within a cutoff of it, those named, or those a `selection` picks; there each residue is one
+//! CA atom at its lining site and the docked ligand is residue `LIG` on chain L. The QM hook treats it and the rest of the pocket
+//! stays MM. Residues enter as side-chain model compounds cut at the Cα–Cβ bond with a
+//! hydrogen link atom on Cβ (acetate for Asp, butylammonium for Lys, p-cresol for Tyr, ...),
+//! centred on the lining atom that stands for the residue; glycine and proline have no side
+//! chain to cut and stay MM. With electrostatic embedding the formal charges of MM residues,
+//! and the ligand's Gasteiger charges when it stays MM, enter the QM job as point charges;
+//! with mechanical embedding the QM job runs in vacuo and QM–MM electrostatics are Coulomb
+//! terms over Gasteiger charges. QM–MM van der Waals covers the ligand's contacts across the
+//! boundary. The region is evaluated once, on the starting structure.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{charges::{self, ChargeModel}, chem::{self, Bond, Molecule}, conformer, fnv1a, forcefield, frame::Frame, pockets, poses, qm, resolver, selection, vec3::dist, AppState};
+
+pub const EMBEDDINGS: [&str; 2] = ["electrostatic", "mechanical"];
+const DEFAULT_CUTOFF: f64 = 6.0;
+
+/// Side-chain model compound of a residue, capped where the Cα–Cβ bond is cut.
+fn side_chain(residue: &str) -> Option<&'static str> {
+    Some(match residue.get(..3)? {
+        "ALA" => "C",
+        "VAL" => "CCC",
+        "LEU" => "CC(C)C",
+        "ILE" => "CCCC",
+        "PHE" => "Cc1ccccc1",
+        "TYR" => "Cc1ccc(O)cc1",
+        "TRP" => "Cc1c[nH]c2ccccc12",
+        "SER" => "CO",
+        "THR" => "CCO",
+        "CYS" => "CS",
+        "MET" => "CCSC",
+        "ASP" => "CC(=O)[O-]",
+        "GLU" => "CCC(=O)[O-]",
+        "ASN" => "CC(N)=O",
+        "GLN" => "CCC(N)=O",
+        "LYS" => "CCCC[NH3+]",
+        "ARG" => "CCCNC(N)=[NH2+]",
+        "HIS" => "Cc1c[nH]cn1",
+        _ => return None,
+    })
+}
+
+#[derive(Deserialize, Clone)]
+pub struct QmRegion { pub target: String, pub pocket: Option<String>, pub ligand: Option<bool>, pub residues: Option<Vec<String>>, pub selection: Option<String>, pub cutoff_angstrom: Option<f64>, pub method: Option<String>, pub embedding: Option<String> }
+
+#[derive(Serialize, Clone)]
+pub struct QmMm { pub target: String, pub pocket_id: String, pub engine: &'static str, pub method: String, pub embedding: &'static str, pub ligand_in_qm: bool, pub qm_residues: Vec<String>, pub link_atoms: usize, pub qm_atoms: usize, pub qm_net_charge: i32, pub mm_point_charges: usize, pub qm_energy_hartree: f64, pub qm_energy_kcal_mol: f64, pub qm_mm_electrostatic_kcal_mol: f64, pub qm_mm_vdw_kcal_mol: f64, pub total_kcal_mol: f64, pub cached: bool }
+
+/// QM/MM single-point energy of `region` around the ligand `mol`.
+pub async fn evaluate(s: &AppState, region: &QmRegion, mol: &resolver::Resolved) -> Result<QmMm, String> {
+    let embedding = match region.embedding.as_deref().unwrap_or(EMBEDDINGS[0]) {
+        "electrostatic" => EMBEDDINGS[0],
+        "mechanical" => EMBEDDINGS[1],
+        other => return Err(format!("unknown embedding {other}; expected one of {}", EMBEDDINGS.join(", "))),
+    };
+    let cutoff = region.cutoff_angstrom.unwrap_or(DEFAULT_CUTOFF);
+    if cutoff.is_nan() || cutoff <= 0.0 { return Err("qm_region.cutoff_angstrom must be positive".into()); }
+    let pockets = pockets::detect(&region.target);
+    let pocket = match &region.pocket { Some(id) => pockets.iter().find(|p| &p.pocket_id == id), None => pockets.first() }
+        .ok_or_else(|| format!("pocket {} not found on {}", region.pocket.as_deref().unwrap_or("P1"), region.target))?;
+    let smiles = mol.canonical_smiles.as_deref().ok_or_else(|| format!("can't resolve {} to a structure for the QM region", mol.input))?;
+    let pose = poses::dock(&region.target, Some(pocket), &mol.input, smiles).ok_or_else(|| format!("can't dock {} into {}", mol.input, region.target))?;
+    let sites = pockets::residue_sites(pocket);
+    if let Some(named) = &region.residues {
+        if let Some(missing) = named.iter().find(|n| !sites.iter().any(|(r, _)| r.eq_ignore_ascii_case(n))) {
+            return Err(format!("{missing} doesn't line pocket {}; its residues are {}", pocket.pocket_id, pocket.residues.join(", ")));
+        }
+    }
+    let selected: Option<Vec<usize>> = match (&region.residues, &region.selection) {
+        (Some(_), Some(_)) => return Err("qm_region takes residues or a selection, not both".into()),
+        (None, Some(text)) => {
+            let mut atoms: Vec<selection::Atom> = sites.iter().map(|(r, site)| {
+                let split = r.find(|c: char| c.is_ascii_digit()).unwrap_or(r.len());
+                selection::Atom { name: "CA".into(), element: "C".into(), aromatic: false, resname: r[..split].into(), resid: r[split..].parse().unwrap_or(0), chain: 'A', protein: true, coords: Some(*site) }
+            }).collect();
+            atoms.extend(selection::molecule_atoms(&chem::parse_smiles(smiles)?, Some(&pose.coords)).into_iter().map(|a| selection::Atom { resname: "LIG".into(), resid: 1, chain: 'L', protein: false, ..a }));
+            Some(selection::select(text, &atoms)?.into_iter().filter(|&i| i < sites.len()).collect())
+        }
+        _ => None,
+    };
+    let ligand_in_qm = region.ligand.unwrap_or(true);
+
+    // QM fragments as (SMILES, molecule, heavy-atom coordinates); everything else is MM.
+    let mut fragments: Vec<(String, Molecule, Vec<[f64; 3]>)> = Vec::new();
+    if ligand_in_qm { fragments.push((smiles.to_string(), chem::parse_smiles(smiles)?, pose.coords.clone())); }
+    let mut qm_residues = Vec::new();
+    let mut mm_sites = Vec::new();
+    for (k, (residue, site)) in sites.iter().enumerate() {
+        let wanted = match (&region.residues, &selected) {
+            (Some(named), _) => named.iter().any(|n| n.eq_ignore_ascii_case(residue)),
+            (_, Some(picked)) => picked.contains(&k),
+            _ => pose.coords.iter().any(|&x| dist(x, *site) <= cutoff),
+        };
+        if let (true, Some(model)) = (wanted, side_chain(residue)) {
+            let m = chem::parse_smiles(model)?;
+            let mut x = conformer::embed(&m, 0);
+            conformer::place(&mut x, fnv1a(format!("{}/{}/{residue}", region.target, pocket.pocket_id).as_bytes()), *site);
+            fragments.push((model.to_string(), m, x));
+            qm_residues.push(residue.clone());
+            continue;
+        }
+        let q = pockets::residue_charge(residue);
+        if q != 0.0 { mm_sites.push((*site, q)); }
+    }
+    if !ligand_in_qm {
+        let q = charges::assign(s, smiles, ChargeModel::Gasteiger)?;
+        mm_sites.extend(pose.coords.iter().zip(&q.atoms).map(|(&c, &q)| (c, q)));
+    }
+    if fragments.is_empty() { return Err("the QM region is empty; include the ligand or residues within the cutoff".into()); }
+
+    let mut atoms = Vec::new();
+    let mut bonds = Vec::new();
+    let mut coords = Vec::new();
+    for (_, m, x) in &fragments {
+        let offset = atoms.len();
+        atoms.extend(m.atoms.iter().map(|a| (a.element.clone(), a.charge, Some(a.hydrogens))));
+        bonds.extend(m.bonds.iter().map(|b| Bond { a: b.a + offset, b: b.b + offset, kind: b.kind }));
+        coords.extend_from_slice(x);
+    }
+    let qm_mol = chem::from_graph(atoms, bonds)?;
+    let electrostatic = embedding == EMBEDDINGS[0];
+    let (result, cached) = s.qm.run(qm::Task::Energy, region.method.as_deref(), &qm_mol, &coords, if electrostatic { &mm_sites } else { &[] }).await?;
+    let qm_mm_electrostatic = if electrostatic { 0.0 } else {
+        let mut e = 0.0;
+        let mm = Frame::sites(&mm_sites);
+        for (smi, _, x) in &fragments { e += charges::interaction(x, &charges::assign(s, smi, ChargeModel::Gasteiger)?.atoms, &mm); }
+        e
+    };
+    // The ligand against the MM lining, or the QM residues against an MM ligand.
+    let qm_residue_atoms: Vec<[f64; 3]> = fragments.iter().skip(usize::from(ligand_in_qm)).flat_map(|f| f.2.iter().copied()).collect();
+    let vdw = if ligand_in_qm { forcefield::interaction_energy(&Frame::new(&pockets::lining(pocket).into_iter().filter(|c| !sites.iter().any(|(r, s)| s == c && qm_residues.contains(r))).collect::<Vec<_>>()), &pose.coords, None) }
+        else { forcefield::interaction_energy(&Frame::new(&qm_residue_atoms), &pose.coords, None) };
+    let round = |x: f64| (x * 1e3).round() / 1e3;
+    Ok(QmMm {
+        target: region.target.clone(), pocket_id: pocket.pocket_id.clone(), engine: result.engine, method: result.method, embedding, ligand_in_qm, link_atoms: qm_residues.len(), qm_residues,
+        qm_atoms: qm_mol.atoms.iter().map(|a| 1 + a.hydrogens as usize).sum(), qm_net_charge: qm_mol.atoms.iter().map(|a| a.charge as i32).sum(), mm_point_charges: if electrostatic { mm_sites.len() } else { 0 },
+        qm_energy_hartree: result.energy_hartree, qm_energy_kcal_mol: result.energy_kcal_mol, qm_mm_electrostatic_kcal_mol: round(qm_mm_electrostatic), qm_mm_vdw_kcal_mol: round(vdw),
+        total_kcal_mol: round(result.energy_kcal_mol + qm_mm_electrostatic + vdw), cached,
+    })
+}
@@ -0,0 +1,322 @@
+//! Receptor preparation for docking and simulation.
+//!
+//! `POST /api/v1/bio/prepare-receptor` takes a PDB file and returns its first model ready for
+//! docking or MD. Waters are removed and so are hetero groups other than metal ions and the
+//! cofactors with templates (see `cofactors`), unless `keep` names them; `remove` names more to
+//! drop. Hydrogens in the input are discarded.
+//! Each titratable group takes the state its model pKa gives at `ph` and is renamed to match
+//! (ASH, GLH, HIP, CYM, TYM, LYN, AR0); bridged cysteines become CYX and a cysteine bound to a
+//! metal is deprotonated. Histidines take the tautomer (HID or HIE) and ring orientation, and
+//! asparagines and glutamines the amide orientation, whose polar atoms best hydrogen-bond their
+//! surroundings, since X-ray density can't tell N from O or C; a histidine bound to a metal keeps
+//! its coordinating nitrogen bare. Chain ends left by missing residues are capped with ACE and NME
+//! (`caps`: `breaks`, the default, `all` or `none`), a free C-terminus without OXT gets one, and
+//! free termini are charged as the pH says. Hydrogens are then added from the residue templates
+//! (see `pdbqt::protonate`), all of them or only the polar ones.
+//! A predicted model (see `plddt`) has its low-confidence regions restrained by default: the
+//! response lists position restraints for `/simulate` that let them relax but not drift, and
+//! `low_confidence: "exclude"` drops them instead.
+
+use axum::{http::StatusCode, response::Json};
+use bio_engine_core::plddt;
+use serde::{Deserialize, Serialize};
+
+use crate::{cofactors, convert::Hydrogens, pdbqt::{self, Residue, Termini}, vec3::{sub, add, scale, cross, dist, unit, torsion}, ApiError, ErrorResponse};
+
+const DEFAULT_PH: f64 = 7.0;
+/// Model pKa values of the titratable side chains and termini in water.
+const PKA: [(&str, f64); 7] = [("ASP", 3.9), ("GLU", 4.3), ("HIS", 6.0), ("CYS", 8.3), ("TYR", 10.1), ("LYS", 10.5), ("ARG", 12.5)];
+const N_TERMINUS_PKA: f64 = 8.0;
+const C_TERMINUS_PKA: f64 = 3.1;
+/// Heavy-atom distance within which a donor and an acceptor hydrogen-bond, and under which two
+/// donors or two acceptors repel.
+const HBOND: f64 = 3.5;
+const REPULSION: f64 = 3.2;
+/// Distance within which a residue atom coordinates a metal ion.
+const COORDINATION: f64 = 2.8;
+const DISULFIDE: f64 = 2.5;
+/// Peptide C–N distance beyond which the chain is broken.
+const PEPTIDE_BOND: f64 = 2.0;
+/// Residue names of titration states and other force-field spellings, as the standard residue.
+const STATES: [(&str, &str); 14] = [
+    ("HID", "HIS"), ("HIE", "HIS"), ("HIP", "HIS"), ("HSD", "HIS"), ("HSE", "HIS"), ("HSP", "HIS"), ("ASH", "ASP"), ("GLH", "GLU"), ("LYN", "LYS"), ("CYX", "CYS"),
+    ("CYM", "CYS"), ("TYM", "TYR"), ("AR0", "ARG"), ("ARN", "ARG"),
+];
+
+/// The point `bond` from `c` with angle `angle` at `c` and torsion `tau` about b–c (degrees).
+fn place(a: [f64; 3], b: [f64; 3], c: [f64; 3], bond: f64, angle: f64, tau: f64) -> [f64; 3] {
+    let (angle, tau) = (angle.to_radians(), tau.to_radians());
+    let bc = unit(sub(c, b));
+    let n = unit(cross(sub(b, a), bc));
+    let m = cross(n, bc);
+    add(c, add(add(scale(bc, -bond * angle.cos()), scale(m, bond * angle.sin() * tau.cos())), scale(n, bond * angle.sin() * tau.sin())))
+}
+
+fn standard(name: &str) -> &str { STATES.iter().find(|s| s.0 == name).map_or(name, |s| s.1) }
+
+fn pka(residue: &str) -> Option<f64> { PKA.iter().find(|p| p.0 == residue).map(|p| p.1) }
+
+/// Formal charge of a titration state.
+fn state_charge(state: &str) -> i8 {
+    match state { "ASP" | "GLU" | "CYM" | "TYM" => -1, "LYS" | "ARG" | "HIP" => 1, _ => 0 }
+}
+
+fn atom(r: &Residue, name: &str) -> Option<[f64; 3]> { r.atoms.iter().find(|a| a.0.trim() == name).map(|a| a.2) }
+
+fn raw_name(name: &str) -> String { format!(" {name:<3}") }
+
+/// A hydrogen-bonding role.
+#[derive(Clone, Copy, PartialEq)]
+enum Role { Donor, Acceptor, Both, Neither }
+
+fn role(residue: &str, atom: &str, element: &str, metal: bool) -> Role {
+    if metal { return Role::Donor; }
+    match (standard(residue), atom) {
+        ("PRO", "N") => Role::Neither,
+        (_, "N") | ("LYS", "NZ") | ("ARG", "NE" | "NH1" | "NH2") | ("ASN", "ND2") | ("GLN", "NE2") | ("TRP", "NE1") => Role::Donor,
+        (_, "O" | "OXT") | ("ASP", "OD1" | "OD2") | ("GLU", "OE1" | "OE2") | ("ASN", "OD1") | ("GLN", "OE1") => Role::Acceptor,
+        _ if element == "N" || element == "O" => Role::Both,
+        _ => Role::Neither,
+    }
+}
+
+/// A polar heavy atom and the residue it belongs to.
+struct Polar { pos: [f64; 3], role: Role, residue: usize }
+
+/// How well an atom of `role` at `pos` fits its surroundings: a point for each partner within
+/// hydrogen-bonding reach, less one for each donor–donor or acceptor–acceptor contact.
+fn fit(pos: [f64; 3], role: Role, own: usize, polar: &[Polar]) -> i32 {
+    polar.iter().filter(|p| p.residue != own).map(|p| {
+        let d = dist(pos, p.pos);
+        match (role, p.role) {
+            _ if d > HBOND => 0,
+            (Role::Donor, Role::Acceptor | Role::Both) | (Role::Acceptor, Role::Donor | Role::Both) => 1,
+            (Role::Donor, Role::Donor) | (Role::Acceptor, Role::Acceptor) if d < REPULSION => -1,
+            _ => 0,
+        }
+    }).sum()
+}
+
+/// Swaps the positions of two atoms of a residue.
+fn swap(r: &mut Residue, a: &str, b: &str) {
+    let (Some(i), Some(j)) = (r.atoms.iter().position(|x| x.0.trim() == a), r.atoms.iter().position(|x| x.0.trim() == b)) else { return };
+    let p = r.atoms[i].2;
+    r.atoms[i].2 = r.atoms[j].2;
+    r.atoms[j].2 = p;
+}
+
+/// Matches a residue spec: a name ("HOH", "A:HEM") or a numbered residue as `pdbqt` reads them
+/// ("A:HOH301", "HOH301", "A:301").
+fn matches(spec: &str, r: &Residue) -> bool {
+    if spec.chars().any(|c| c.is_ascii_digit()) && !r.name.chars().any(|c| c.is_ascii_digit()) { return pdbqt::matches_spec(spec, r); }
+    let (chain, name) = spec.split_once(':').map_or((None, spec), |(c, n)| (c.chars().next(), n));
+    chain.is_none_or(|c| c.eq_ignore_ascii_case(&r.chain)) && name.trim().eq_ignore_ascii_case(&r.name)
+}
+
+#[derive(Deserialize)]
+pub struct ReceptorRequest { pdb: String, ph: Option<f64>, hydrogens: Option<String>, flips: Option<bool>, caps: Option<String>, keep: Option<Vec<String>>, remove: Option<Vec<String>>, low_confidence: Option<String>, plddt_cutoff: Option<f64> }
+
+#[derive(Serialize)]
+pub struct Protonation { residue: String, state: String, charge: i8, reason: String }
+
+#[derive(Serialize)]
+pub struct Removed { waters: usize, groups: Vec<String> }
+
+#[derive(Serialize)]
+pub struct ReceptorResponse {
+    ph: f64, atoms: usize, hydrogens: usize, residues: usize, net_charge: i32, protonation: Vec<Protonation>, flipped: Vec<String>, caps: Vec<String>, removed: Removed,
+    kept: Vec<String>, #[serde(skip_serializing_if = "Option::is_none")] confidence: Option<plddt::Report>, warnings: Vec<String>, pdb: String,
+}
+
+/// Chooses a histidine's tautomer and ring orientation. `charged` forces HIP; `bound` is the ring
+/// nitrogen coordinating a metal, which stays bare.
+fn histidine(r: &mut Residue, own: usize, charged: bool, bound: Option<&str>, flips: bool, polar: &[Polar]) -> (String, bool, String) {
+    let (Some(nd1), Some(ne2), Some(cd2), Some(ce1)) = (atom(r, "ND1"), atom(r, "NE2"), atom(r, "CD2"), atom(r, "CE1")) else { return (if charged { "HIP" } else { "HIE" }.into(), false, "incomplete ring; default state".into()) };
+    if let Some(n) = bound.filter(|_| !charged) {
+        let state = if n == "ND1" { "HIE" } else { "HID" };
+        return (state.into(), false, format!("{n} coordinates a metal"));
+    }
+    let states: &[(&str, Role, Role)] = if charged { &[("HIP", Role::Donor, Role::Donor)] } else { &[("HIE", Role::Acceptor, Role::Donor), ("HID", Role::Donor, Role::Acceptor)] };
+    // Flipped, the ring's ND1 sits where CD2 was and NE2 where CE1 was.
+    let orientations: &[(bool, [f64; 3], [f64; 3])] = if flips { &[(false, nd1, ne2), (true, cd2, ce1)] } else { &[(false, nd1, ne2)] };
+    let mut best = (i32::MIN, "HIE", false);
+    for &(flipped, p1, p2) in orientations {
+        for &(state, r1, r2) in states {
+            let score = fit(p1, r1, own, polar) + fit(p2, r2, own, polar);
+            if score > best.0 { best = (score, state, flipped); }
+        }
+    }
+    if best.2 { swap(r, "ND1", "CD2"); swap(r, "NE2", "CE1"); }
+    let reason = if charged { "model pKa 6.0 above the pH".to_string() } else if best.0 > 0 { format!("hydrogen bonds best as {} ({} contacts)", best.1, best.0) } else { "no hydrogen-bond partners; default tautomer".into() };
+    (best.1.into(), best.2, reason)
+}
+
+/// C-terminal atoms placed from a residue's backbone: OXT, or an NME cap's N and CH3.
+fn c_cap(r: &Residue, nme: bool) -> Option<Vec<(String, String, [f64; 3])>> {
+    let (n, ca, c, o) = (atom(r, "N")?, atom(r, "CA")?, atom(r, "C")?, atom(r, "O")?);
+    let opposite = torsion(n, ca, c, o) + 180.0;
+    if !nme { return Some(vec![(raw_name("OXT"), "O".into(), place(n, ca, c, 1.25, 117.0, opposite))]); }
+    let cap_n = place(n, ca, c, 1.335, 116.2, opposite);
+    Some(vec![(raw_name("N"), "N".into(), cap_n), (raw_name("CH3"), "C".into(), place(ca, c, cap_n, 1.458, 121.7, 180.0))])
+}
+
+/// An ACE cap's C, O and CH3 placed before a residue's N, trans, with φ at −60°.
+fn n_cap(r: &Residue) -> Option<Vec<(String, String, [f64; 3])>> {
+    let (n, ca, c) = (atom(r, "N")?, atom(r, "CA")?, atom(r, "C")?);
+    let cap_c = place(c, ca, n, 1.335, 121.7, -60.0);
+    Some(vec![(raw_name("C"), "C".into(), cap_c), (raw_name("O"), "O".into(), place(ca, n, cap_c, 1.229, 120.5, 0.0)), (raw_name("CH3"), "C".into(), place(ca, n, cap_c, 1.52, 116.2, 180.0))])
+}
+
+pub async fn prepare(Json(req): Json<ReceptorRequest>) -> Result<Json<ReceptorResponse>, ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let ph = req.ph.unwrap_or(DEFAULT_PH);
+    if !(0.0..=14.0).contains(&ph) { return Err(bad(format!("ph {ph} is outside 0–14"))); }
+    let hydrogens = match req.hydrogens.as_deref().unwrap_or("all") { "all" => Hydrogens::All, "polar" => Hydrogens::Polar, other => return Err(bad(format!("unknown hydrogens {other}; expected all or polar"))) };
+    let caps = req.caps.as_deref().unwrap_or("breaks");
+    if !["breaks", "all", "none"].contains(&caps) { return Err(bad(format!("unknown caps {caps}; expected breaks, all or none"))); }
+    let flips = req.flips.unwrap_or(true);
+    let (keep, remove) = (req.keep.unwrap_or_default(), req.remove.unwrap_or_default());
+    let mut warnings = Vec::new();
+    let (input, confidence) = plddt::apply(&req.pdb, req.low_confidence.as_deref(), req.plddt_cutoff, plddt::Policy::Restrain).map_err(bad)?;
+
+    // Waters and hetero groups: metal ions and cofactors stay by default, `keep` and then `remove` decide.
+    let (mut residues, mut removed, mut kept) = (Vec::new(), Removed { waters: 0, groups: Vec::new() }, Vec::new());
+    let mut matched = vec![false; keep.len() + remove.len()];
+    for r in pdbqt::parse_residues(&input, true) {
+        let water = pdbqt::WATERS.contains(&r.name.as_str());
+        let group = r.hetero && !pdbqt::TEMPLATED.contains(&standard(&r.name));
+        if !water && !group { residues.push(r); continue; }
+        for (m, spec) in matched.iter_mut().zip(keep.iter().chain(&remove)) { *m |= matches(spec, &r); }
+        let keeping = if keep.iter().any(|s| matches(s, &r)) { true } else if remove.iter().any(|s| matches(s, &r)) { false } else { cofactors::is_ion(&r) || cofactors::cofactor(&r.name).is_some() };
+        match (keeping, water) {
+            (true, _) => { kept.push(r.label()); residues.push(r); }
+            (false, true) => removed.waters += 1,
+            (false, false) => if !removed.groups.contains(&r.label()) { removed.groups.push(r.label()); },
+        }
+    }
+    for (spec, _) in keep.iter().chain(&remove).zip(&matched).filter(|(_, m)| !**m) { warnings.push(format!("{spec} matches no water or hetero group")); }
+    if residues.is_empty() { return Err(bad("the PDB file has no ATOM or HETATM records".into())); }
+    for r in &mut residues { if !r.hetero || pdbqt::TEMPLATED.contains(&standard(&r.name)) { r.name = standard(&r.name).to_string(); } }
+
+    // Metal atoms, free ions and those of cofactors such as heme alike.
+    let metals: Vec<([f64; 3], String)> = residues.iter().flat_map(|r| r.atoms.iter().filter(|a| cofactors::metal(&a.1).is_some()).map(|a| (a.2, r.label()))).collect();
+    let polar: Vec<Polar> = residues.iter().enumerate().flat_map(|(k, r)| {
+        r.atoms.iter().map(move |a| Polar { pos: a.2, role: role(&r.name, a.0.trim(), &a.1, cofactors::metal(&a.1).is_some()), residue: k })
+    }).filter(|p| p.role != Role::Neither).collect();
+    let near_metal = |p: [f64; 3]| metals.iter().find(|m| dist(m.0, p) <= COORDINATION).map(|m| m.1.clone());
+    let sulfurs: Vec<(usize, [f64; 3])> = residues.iter().enumerate().filter(|(_, r)| r.name == "CYS").filter_map(|(k, r)| atom(r, "SG").map(|p| (k, p))).collect();
+
+    let (mut protonation, mut flipped) = (Vec::new(), Vec::new());
+    let labels: Vec<String> = residues.iter().map(Residue::label).collect();
+    for k in 0..residues.len() {
+        let name = residues[k].name.clone();
+        let mut note = |r: &mut Residue, state: &str, reason: String| {
+            protonation.push(Protonation { residue: r.label(), state: state.into(), charge: state_charge(state), reason });
+            if state != r.name { r.name = state.into(); }
+        };
+        match name.as_str() {
+            "CYS" => {
+                let sg = atom(&residues[k], "SG");
+                if let Some(&(other, _)) = sulfurs.iter().find(|&&(o, p)| o != k && sg.is_some_and(|s| dist(s, p) <= DISULFIDE)) {
+                    note(&mut residues[k], "CYX", format!("disulfide with {}", labels[other]));
+                } else if let Some(m) = sg.and_then(near_metal) {
+                    note(&mut residues[k], "CYM", format!("SG coordinates {m}"));
+                } else if ph > 8.3 {
+                    note(&mut residues[k], "CYM", format!("model pKa 8.3 below pH {ph}"));
+                }
+            }
+            "HIS" => {
+                let bound = ["ND1", "NE2"].into_iter().find(|n| atom(&residues[k], n).and_then(near_metal).is_some());
+                let (state, ring_flipped, reason) = histidine(&mut residues[k], k, ph < 6.0 && bound.is_none(), bound, flips, &polar);
+                if ring_flipped { flipped.push(labels[k].clone()); }
+                note(&mut residues[k], &state, reason);
+            }
+            "ASN" | "GLN" if flips => {
+                let (o, n) = if name == "ASN" { ("OD1", "ND2") } else { ("OE1", "NE2") };
+                let (Some(po), Some(pn)) = (atom(&residues[k], o), atom(&residues[k], n)) else { continue };
+                let as_is = fit(po, Role::Acceptor, k, &polar) + fit(pn, Role::Donor, k, &polar);
+                if fit(pn, Role::Acceptor, k, &polar) + fit(po, Role::Donor, k, &polar) > as_is { swap(&mut residues[k], o, n); flipped.push(labels[k].clone()); }
+            }
+            "ASP" | "GLU" | "TYR" | "LYS" | "ARG" => {
+                let p = pka(&name).unwrap_or(7.0);
+                let (acid, state) = match name.as_str() { "ASP" => (true, "ASH"), "GLU" => (true, "GLH"), "TYR" => (true, "TYM"), "LYS" => (false, "LYN"), _ => (false, "AR0") };
+                // Acids gain a proton below their pKa (tyrosine loses one above it); bases lose theirs above it.
+                let changed = match name.as_str() { "TYR" => ph > p, _ if acid => ph < p, _ => ph > p };
+                if changed { note(&mut residues[k], state, format!("model pKa {p} {} pH {ph}", if ph < p { "above" } else { "below" })); }
+            }
+            _ => {}
+        }
+    }
+
+    // Chain ends: caps where residues are missing (or everywhere), OXT on free C-termini.
+    let protein = |r: &Residue| pdbqt::TEMPLATED.contains(&r.name.as_str()) && atom(r, "CA").is_some();
+    let mut capped = Vec::new();
+    let mut out: Vec<Residue> = Vec::with_capacity(residues.len() + 2);
+    let taken = |rs: &[Residue], chain: char, number: i64| rs.iter().any(|r| r.chain == chain && r.number == number);
+    for k in 0..residues.len() {
+        let r = &residues[k];
+        if !protein(r) { out.push(Residue { name: r.name.clone(), chain: r.chain, number: r.number, insertion: r.insertion, hetero: r.hetero, atoms: r.atoms.clone() }); continue; }
+        let linked = |a: &Residue, b: &Residue| a.chain == b.chain && protein(a) && protein(b) && atom(a, "C").zip(atom(b, "N")).is_some_and(|(c, n)| dist(c, n) <= PEPTIDE_BOND);
+        let before = k.checked_sub(1).map(|j| &residues[j]).filter(|p| p.chain == r.chain && protein(p));
+        let after = residues.get(k + 1).filter(|n| n.chain == r.chain && protein(n));
+        let (starts, ends) = (before.is_none_or(|p| !linked(p, r)), after.is_none_or(|n| !linked(r, n)));
+        // A break has residues on both sides; a chain end has none beyond it.
+        let cap_start = starts && (caps == "all" || (caps == "breaks" && before.is_some()));
+        let cap_end = ends && (caps == "all" || (caps == "breaks" && after.is_some()));
+        if cap_start {
+            match n_cap(r) {
+                Some(atoms) => {
+                    let (number, insertion) = if taken(&residues, r.chain, r.number - 1) { (r.number - 1, 'A') } else { (r.number - 1, ' ') };
+                    let cap = Residue { name: "ACE".into(), chain: r.chain, number, insertion, hetero: true, atoms };
+                    capped.push(cap.label());
+                    out.push(cap);
+                }
+                None => warnings.push(format!("{} lacks backbone atoms; not capped", r.label())),
+            }
+        }
+        let mut atoms = r.atoms.clone();
+        if ends && cap_end { atoms.retain(|a| a.0.trim() != "OXT"); }
+        if ends && !cap_end && atom(r, "OXT").is_none() {
+            match c_cap(r, false) { Some(oxt) => atoms.extend(oxt), None => warnings.push(format!("{} lacks backbone atoms; no OXT added", r.label())) }
+        }
+        out.push(Residue { name: r.name.clone(), chain: r.chain, number: r.number, insertion: r.insertion, hetero: r.hetero, atoms });
+        if cap_end {
+            match c_cap(r, true) {
+                Some(atoms) => {
+                    let (number, insertion) = if taken(&residues, r.chain, r.number + 1) { (r.number, 'A') } else { (r.number + 1, ' ') };
+                    let cap = Residue { name: "NME".into(), chain: r.chain, number, insertion, hetero: true, atoms };
+                    capped.push(cap.label());
+                    out.push(cap);
+                }
+                None => warnings.push(format!("{} lacks backbone atoms; not capped", r.label())),
+            }
+        }
+    }
+
+    let termini = Termini { n_charged: ph < N_TERMINUS_PKA, c_charged: ph > C_TERMINUS_PKA };
+    let p = pdbqt::protonate(&out, termini, hydrogens, &mut warnings).map_err(bad)?;
+    let mut pdb = format!("REMARK   1 PREPARED AT PH {ph:.1}\n");
+    let mut serial = 0;
+    for i in 0..p.heavy {
+        for a in std::iter::once(i).chain(p.hydrogens[i].iter().copied()) {
+            let r = &out[p.owner[a]];
+            serial += 1;
+            let element = &p.ex.mol.atoms[a].element;
+            let charge = match p.ex.mol.atoms[a].charge { 0 => String::new(), c if c > 0 => format!("{c}+"), c => format!("{}-", -c) };
+            let c = p.ex.coords[a];
+            let record = if r.hetero { "HETATM" } else { "ATOM  " };
+            pdb.push_str(&format!("{record}{:>5} {:<4} {:>3} {}{:>4}{}   {:>8.3}{:>8.3}{:>8.3}  1.00  0.00          {:>2}{charge:<2}\n", serial % 100_000, p.names[a], r.name, r.chain, r.number, r.insertion, c[0], c[1], c[2], element.to_uppercase()));
+        }
+        // TER after the last atom of each chain's polymer.
+        let (r, next) = (p.owner[i], (i + 1 < p.heavy).then(|| p.owner[i + 1]));
+        let polymer = |k: usize| !out[k].hetero || out[k].name == "ACE" || out[k].name == "NME";
+        if polymer(r) && next.is_none_or(|n| out[n].chain != out[r].chain || !polymer(n)) {
+            serial += 1;
+            pdb.push_str(&format!("TER   {:>5}      {:>3} {}{:>4}\n", serial % 100_000, out[r].name, out[r].chain, out[r].number));
+        }
+    }
+    pdb.push_str("END\n");
+    Ok(Json(ReceptorResponse {
+        ph, atoms: p.ex.mol.atoms.len(), hydrogens: p.ex.mol.atoms.len() - p.heavy, residues: out.len(), net_charge: p.net_charge, protonation, flipped, caps: capped, removed, kept, confidence, warnings, pdb,
+    }))
+}
@@ -0,0 +1,96 @@
+//! Pose refinement: relax a ligand pose against its receptor.
+//!
+//! The ligand comes as an SDF record or as a stored screening pose; the receptor as PDB text
+//! or, for targets without a structure, the pseudo-receptor lining the target's top pocket.
+//! A PDB receptor's metal ions are scored with their own parameters (see `cofactors`), so a
+//! ligand atom coordinating a zinc is bound to it rather than clashing, and hetero groups
+//! without parameters are reported.
+//! Refinement is restrained steepest-descent minimization, optionally followed by a short
+//! Langevin MD run and a second minimization. The score is the ligand-receptor interaction
+//! energy plus ligand internal energy, so relieving clashes and strain both improve it.
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{chem::Molecule, cofactors, conformer, fnv1a, forcefield::{self, Metals, System}, frame::Frame, not_found, pockets, poses, projects, record, selection, usage, ApiError, AppState, ErrorResponse, DOCK_MODEL};
+
+#[derive(Deserialize)]
+pub struct RefineRequest {
+    ligand_sdf: Option<String>,
+    screen_id: Option<String>,
+    compound_id: Option<String>,
+    receptor_pdb: Option<String>,
+    target: Option<String>,
+    max_steps: Option<usize>,
+    md_steps: Option<usize>,
+    temperature_k: Option<f64>,
+    /// Positional restraint toward the input pose, kcal/mol/Å².
+    restraint_k: Option<f64>,
+    /// Atoms the RMSD is taken over (see `selection`); all by default.
+    rmsd_selection: Option<String>,
+    format: Option<String>,
+}
+
+#[derive(Serialize, Clone, Copy)]
+pub struct PoseScore { interaction_energy: f64, internal_energy: f64, score: f64, clashes: usize }
+#[derive(Serialize)]
+pub struct RefineResponse {
+    refinement_id: String, compound_id: String, smiles: String, receptor_atoms: usize, metals: usize, #[serde(skip_serializing_if = "Vec::is_empty")] warnings: Vec<String>, initial: PoseScore,
+    refined: PoseScore, score_delta: f64, rmsd_angstrom: f64, minimization_steps: usize, md_steps: usize, format: String, pose: String,
+}
+
+fn score(restraints: &[conformer::Restraint], receptor: &Frame, metals: &Metals, x: &[[f64; 3]]) -> PoseScore {
+    let interaction = forcefield::interaction_energy(receptor, x, None) + metals.energy(x, None);
+    let internal = forcefield::internal_energy(restraints, x, None);
+    PoseScore { interaction_energy: interaction, internal_energy: internal, score: interaction + internal, clashes: forcefield::clashes(receptor, x) + metals.clashes(x) }
+}
+
+pub async fn refine(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<RefineRequest>) -> Result<Json<RefineResponse>, ApiError> {
+    let meter = usage::Meter::start();
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let (name, mol, coords, stored_target): (String, Molecule, Vec<[f64; 3]>, Option<String>) = match (&req.ligand_sdf, &req.screen_id, &req.compound_id) {
+        (Some(sdf), _, _) => { let (name, mol, coords) = poses::parse_sdf(sdf).map_err(bad)?; (req.compound_id.clone().unwrap_or(name), mol, coords, None) }
+        (None, Some(screen_id), Some(compound_id)) => {
+            let pose = s.poses.get(&projects::project_id(&headers), screen_id, compound_id).ok_or_else(|| not_found("pose", &format!("{screen_id}/{compound_id}")))?;
+            let mol = crate::chem::parse_smiles(&pose.smiles).map_err(bad)?;
+            (pose.compound_id, mol, pose.coords, Some(pose.target))
+        }
+        _ => return Err(bad("give ligand_sdf, or screen_id and compound_id of a stored pose".into())),
+    };
+    if mol.atoms.is_empty() { return Err(bad("ligand has no heavy atoms".into())); }
+    let parsed = match (&req.receptor_pdb, req.target.as_ref().or(stored_target.as_ref())) {
+        (Some(pdb), _) => cofactors::receptor(pdb),
+        (None, Some(target)) => cofactors::Receptor { atoms: pockets::detect(target).first().map(pockets::lining).unwrap_or_default(), metals: Vec::new(), warnings: Vec::new() },
+        (None, None) => return Err(bad("give receptor_pdb or target".into())),
+    };
+    let receptor = Frame::new(&parsed.atoms);
+    if receptor.is_empty() && parsed.metals.is_empty() { return Err(bad("receptor has no heavy atoms".into())); }
+    let metals = Metals::new(&parsed.metals, &mol.atoms.iter().map(|a| a.element.as_str()).collect::<Vec<_>>());
+    let format = req.format.clone().unwrap_or_else(|| "sdf".into());
+    if !matches!(format.as_str(), "sdf" | "pdb") { return Err(bad(format!("unknown format {format}; expected sdf or pdb"))); }
+
+    let rmsd_atoms = match &req.rmsd_selection {
+        Some(text) => selection::select(text, &selection::molecule_atoms(&mol, Some(&coords))).map_err(bad)?,
+        None => (0..mol.atoms.len()).collect(),
+    };
+    if rmsd_atoms.is_empty() { return Err(bad("rmsd_selection selects no atoms".into())); }
+    let restraints = conformer::restraints(&mol);
+    let system = System { restraints: &restraints, receptor: &receptor, metals: Some(&metals), anchor: &coords, k_pos: req.restraint_k.unwrap_or(1.0).max(0.0), bias: None };
+    let initial = score(&restraints, &receptor, &metals, &coords);
+    let max_steps = req.max_steps.unwrap_or(500).min(20_000);
+    let md_steps = req.md_steps.unwrap_or(0).min(50_000);
+    let mut x = coords.clone();
+    let mut steps = system.minimize(&mut x, max_steps);
+    if md_steps > 0 {
+        system.dynamics(&mut x, md_steps, req.temperature_k.unwrap_or(300.0), fnv1a(name.as_bytes()));
+        steps += system.minimize(&mut x, max_steps);
+    }
+    let refined = score(&restraints, &receptor, &metals, &x);
+    let smiles = mol.to_canonical_smiles();
+    let pose = poses::Pose { compound_id: name.clone(), smiles: smiles.clone(), target: req.target.clone().or(stored_target).unwrap_or_default(), pocket_id: String::new(), binding_affinity_nm: 0.0, coords: x.clone() };
+    let text = if format == "pdb" { poses::to_pdb(&pose, &mol) } else { poses::to_sdf(&pose, &mol) };
+    let resp = RefineResponse { refinement_id: uuid::Uuid::new_v4().to_string(), compound_id: name, smiles, receptor_atoms: receptor.len() + metals.len(), metals: metals.len(), warnings: parsed.warnings, initial, refined, score_delta: refined.score - initial.score, rmsd_angstrom: forcefield::rmsd(&rmsd_atoms.iter().map(|&i| coords[i]).collect::<Vec<_>>(), &rmsd_atoms.iter().map(|&i| x[i]).collect::<Vec<_>>()), minimization_steps: steps, md_steps, format, pose: text };
+    record(&s, &headers, "refine_pose", &resp.compound_id, DOCK_MODEL, &resp.refinement_id, &meter, &resp);
+    Ok(Json(resp))
+}
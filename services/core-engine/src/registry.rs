@@ -0,0 +1,337 @@
+//! Connectors that keep compound libraries in step with a corporate registry.
+//!
+//! A connector (`PUT /registry/connectors/:name`) names a REST endpoint of the registry, how
+//! its records map onto compounds (`fields`: where the ID, SMILES and description are, as JSON
+//! pointers or dotted paths), where the records are in a response (`records`), and how its
+//! pages follow one another (`pagination`: page numbers, offsets or a cursor). Each sync fetches
+//! every page and refreshes the connector's library under the same library ID, standardized and
+//! de-duplicated as the project does, reporting what was added, updated and removed. With
+//! `since_param`, syncs after the first ask only for records changed since the last one and
+//! merge them in, so deletions in the registry aren't seen. Syncs run on demand
+//! (`POST .../sync`) or every `every_minutes`.
+//!
+//! Header values (API keys, tokens) are never shown back, and are kept across a `PUT` only while
+//! the connector stays on the same scheme and host. `BIO_REGISTRY_HOSTS`, when set, lists the
+//! only hosts connectors may reach; without it a host must resolve to public addresses only, and
+//! each request is pinned to the address that was checked. Redirects aren't followed, a cursor
+//! URL must stay on the connector's scheme and host (or a listed one), and a response over
+//! `MAX_RESPONSE` is refused.
+
+use axum::{extract::{Path, State}, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::{libraries, projects, reports, unix_now, ApiError, AppState, ErrorResponse};
+
+const REDACTED: &str = "<redacted>";
+const MAX_PAGES: usize = 10_000;
+const MAX_RECORDS: usize = 1_000_000;
+const MIN_INTERVAL_MINUTES: u64 = 5;
+const PAGE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Largest response body read from a registry.
+const MAX_RESPONSE: usize = 64 << 20;
+/// Record errors kept in a sync report.
+const MAX_ERRORS: usize = 20;
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Fields { id: String, smiles: String, #[serde(default, skip_serializing_if = "Option::is_none")] description: Option<String> }
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Pagination {
+    /// `param` counts pages from `start`; a short or empty page is the last.
+    Page { param: String, #[serde(default = "one")] start: u64, #[serde(default, skip_serializing_if = "Option::is_none")] size_param: Option<String>, #[serde(default, skip_serializing_if = "Option::is_none")] size: Option<u64> },
+    /// `param` is the index of the first record, `size_param` the page size.
+    Offset { param: String, size_param: String, size: u64 },
+    /// `next` locates the next cursor in a response: a URL, or a token sent as `param`.
+    Cursor { next: String, #[serde(default, skip_serializing_if = "Option::is_none")] param: Option<String> },
+}
+
+fn one() -> u64 { 1 }
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Connector {
+    url: String, #[serde(default)] headers: BTreeMap<String, String>, #[serde(default)] query: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")] records: Option<String>, fields: Fields,
+    #[serde(default, skip_serializing_if = "Option::is_none")] pagination: Option<Pagination>, #[serde(default, skip_serializing_if = "Option::is_none")] since_param: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")] library_name: Option<String>, #[serde(default, skip_serializing_if = "Option::is_none")] every_minutes: Option<u64>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct SyncReport {
+    trigger: &'static str, status: &'static str, started_at_unix: u64, finished_at_unix: u64, #[serde(skip_serializing_if = "Option::is_none")] since: Option<String>, pages: usize, fetched: usize,
+    #[serde(flatten)] changes: libraries::Changes, failed: usize, duplicates: usize, #[serde(skip_serializing_if = "Vec::is_empty")] errors: Vec<libraries::RecordError>, #[serde(skip_serializing_if = "Option::is_none")] error: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ConnectorView { name: String, project: String, #[serde(flatten)] connector: Connector, library_id: String, created_at_unix: u64, running: bool, #[serde(skip_serializing_if = "Option::is_none")] next_sync_unix: Option<u64>, #[serde(skip_serializing_if = "Option::is_none")] last_sync: Option<SyncReport> }
+#[derive(Serialize)]
+pub struct ConnectorsResponse { project: String, connectors: Vec<ConnectorView> }
+
+struct Entry { connector: Connector, library_id: String, created_at_unix: u64, running: bool, next_sync_unix: Option<u64>, last_sync: Option<SyncReport>, last_success_unix: Option<u64> }
+
+/// Connectors by project, then name.
+pub struct Registry { connectors: Mutex<HashMap<(String, String), Entry>>, client: reqwest::Client, allowed_hosts: Option<Vec<String>> }
+
+impl Registry {
+    pub fn new(allowed_hosts: Option<String>) -> Self {
+        let allowed_hosts = allowed_hosts.filter(|h| !h.trim().is_empty()).map(|h| h.split(',').map(|h| h.trim().to_ascii_lowercase()).filter(|h| !h.is_empty()).collect());
+        let client = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none()).build().unwrap_or_default();
+        Self { connectors: Mutex::new(HashMap::new()), client, allowed_hosts }
+    }
+
+    fn listed(&self, host: &str) -> bool { self.allowed_hosts.as_ref().is_some_and(|a| a.iter().any(|h| h == host)) }
+
+    /// Whether a request may go to `url`, and the address to pin it to. A listed host may be
+    /// anywhere; with no list, every address the host resolves to must be public.
+    async fn reachable(&self, url: &reqwest::Url) -> Result<Option<SocketAddr>, String> {
+        if !matches!(url.scheme(), "http" | "https") { return Err("url must be http or https".into()); }
+        let host = url.host_str().unwrap_or("").to_ascii_lowercase();
+        if self.allowed_hosts.is_some() { return if self.listed(&host) { Ok(None) } else { Err(format!("{host} is not among BIO_REGISTRY_HOSTS")) }; }
+        let port = url.port_or_known_default().unwrap_or(443);
+        let addrs: Vec<SocketAddr> = match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            Ok(ip) => vec![SocketAddr::new(ip, port)],
+            Err(_) => tokio::net::lookup_host((host.as_str(), port)).await.map_err(|e| format!("{host} doesn't resolve: {e}"))?.collect(),
+        };
+        if let Some(a) = addrs.iter().find(|a| internal(a.ip())) { return Err(format!("{host} resolves to the internal address {}; list it in BIO_REGISTRY_HOSTS to reach it", a.ip())); }
+        addrs.first().map(|a| Some(*a)).ok_or_else(|| format!("{host} doesn't resolve"))
+    }
+
+    fn view(&self, project: &str, name: &str) -> Option<ConnectorView> {
+        let connectors = self.connectors.lock().unwrap();
+        let e = connectors.get(&(project.to_string(), name.to_string()))?;
+        let mut connector = e.connector.clone();
+        connector.headers.values_mut().for_each(|v| *v = REDACTED.into());
+        Some(ConnectorView { name: name.into(), project: project.into(), connector, library_id: e.library_id.clone(), created_at_unix: e.created_at_unix, running: e.running, next_sync_unix: e.next_sync_unix, last_sync: e.last_sync.clone() })
+    }
+
+    fn validate(&self, c: &Connector) -> Result<(), String> {
+        let url = reqwest::Url::parse(&c.url).map_err(|e| format!("url: {e}"))?;
+        if !matches!(url.scheme(), "http" | "https") { return Err("url must be http or https".into()); }
+        let host = url.host_str().unwrap_or("").to_ascii_lowercase();
+        if self.allowed_hosts.is_some() && !self.listed(&host) { return Err(format!("{host} is not among BIO_REGISTRY_HOSTS")); }
+        if c.fields.id.trim().is_empty() || c.fields.smiles.trim().is_empty() { return Err("fields.id and fields.smiles are required".into()); }
+        if let Some(m) = c.every_minutes { if m < MIN_INTERVAL_MINUTES { return Err(format!("every_minutes must be at least {MIN_INTERVAL_MINUTES}")); } }
+        if let Some(Pagination::Offset { size: 0, .. } | Pagination::Page { size: Some(0), .. }) = c.pagination { return Err("a page size must be at least 1".into()); }
+        for (k, v) in &c.headers {
+            if reqwest::header::HeaderName::from_bytes(k.as_bytes()).is_err() || reqwest::header::HeaderValue::from_str(v).is_err() { return Err(format!("header {k} is not a valid HTTP header")); }
+        }
+        Ok(())
+    }
+
+    /// One page of records and the cursor after it, if any.
+    async fn page(&self, c: &Connector, url: &reqwest::Url, params: &[(String, String)]) -> Result<(Vec<Value>, Option<String>), String> {
+        // Pinned to the address just checked, so a second lookup can't answer differently.
+        let client = match (self.reachable(url).await?, url.host_str()) {
+            (Some(addr), Some(host)) if host.parse::<IpAddr>().is_err() => reqwest::Client::builder().redirect(reqwest::redirect::Policy::none()).resolve(host, addr).build().map_err(|e| e.to_string())?,
+            _ => self.client.clone(),
+        };
+        let mut req = client.get(url.clone()).query(&c.query).query(params).timeout(PAGE_TIMEOUT);
+        for (k, v) in &c.headers { req = req.header(k.as_str(), v.as_str()); }
+        let mut resp = req.send().await.and_then(|r| r.error_for_status()).map_err(|e| format!("registry request failed: {e}"))?;
+        let too_big = || format!("the registry response is over {} MiB", MAX_RESPONSE >> 20);
+        if resp.content_length().is_some_and(|n| n > MAX_RESPONSE as u64) { return Err(too_big()); }
+        let mut bytes = Vec::new();
+        while let Some(chunk) = resp.chunk().await.map_err(|e| format!("registry response: {e}"))? {
+            if bytes.len() + chunk.len() > MAX_RESPONSE { return Err(too_big()); }
+            bytes.extend_from_slice(&chunk);
+        }
+        let body: Value = serde_json::from_slice(&bytes).map_err(|e| format!("registry response is not JSON: {e}"))?;
+        let records = match &c.records { Some(path) => lookup(&body, path).ok_or_else(|| format!("no {path} in the registry response"))?, None => &body };
+        let records = records.as_array().cloned().ok_or("the registry response's records are not an array")?;
+        let next = match &c.pagination { Some(Pagination::Cursor { next, .. }) => lookup(&body, next).and_then(text).filter(|t| !t.is_empty()), _ => None };
+        Ok((records, next))
+    }
+
+    /// Every record the connector reaches, following its pagination, and the pages read.
+    async fn fetch(&self, c: &Connector, since: Option<&str>) -> Result<(Vec<Value>, usize), String> {
+        let mut base: Vec<(String, String)> = Vec::new();
+        if let (Some(param), Some(since)) = (&c.since_param, since) { base.push((param.clone(), since.into())); }
+        let mut all = Vec::new();
+        let first = reqwest::Url::parse(&c.url).map_err(|e| format!("url: {e}"))?;
+        let mut url = first.clone();
+        let mut cursor: Option<String> = None;
+        for n in 0..MAX_PAGES {
+            let mut params = base.clone();
+            match &c.pagination {
+                None => {}
+                Some(Pagination::Page { param, start, size_param, size }) => {
+                    params.push((param.clone(), (start + n as u64).to_string()));
+                    if let (Some(p), Some(size)) = (size_param, size) { params.push((p.clone(), size.to_string())); }
+                }
+                Some(Pagination::Offset { param, size_param, size }) => params.extend([(param.clone(), (n as u64 * size).to_string()), (size_param.clone(), size.to_string())]),
+                Some(Pagination::Cursor { param: Some(param), .. }) => if let Some(t) = &cursor { params.push((param.clone(), t.clone())); },
+                Some(Pagination::Cursor { param: None, .. }) => if let Some(next) = &cursor { url = self.follow(&first, next)?; params.clear(); },
+            }
+            let (records, next) = self.page(c, &url, &params).await?;
+            let got = records.len() as u64;
+            all.extend(records);
+            if all.len() > MAX_RECORDS { return Err(format!("the registry returned over {MAX_RECORDS} records")); }
+            let more = match &c.pagination {
+                None => false,
+                Some(Pagination::Page { size, .. }) => got > 0 && size.is_none_or(|s| got >= s),
+                Some(Pagination::Offset { size, .. }) => got >= *size,
+                Some(Pagination::Cursor { .. }) => { let more = next.is_some() && next != cursor; cursor = next; more }
+            };
+            if !more { return Ok((all, n + 1)); }
+        }
+        Err(format!("the registry had more than {MAX_PAGES} pages"))
+    }
+
+    /// A next-page link from a response, which the connector's headers will be sent to: it must
+    /// keep the connector's scheme and host, or go to a listed host.
+    fn follow(&self, start: &reqwest::Url, next: &str) -> Result<reqwest::Url, String> {
+        let url = start.join(next).map_err(|e| format!("the registry's next-page link {next}: {e}"))?;
+        let host = url.host_str().unwrap_or("").to_ascii_lowercase();
+        let same = url.scheme() == start.scheme() && url.host_str() == start.host_str() && url.port_or_known_default() == start.port_or_known_default();
+        if !same && !(self.listed(&host) && matches!(url.scheme(), "http" | "https")) { return Err(format!("the registry's next-page link leaves {} for {host}; it wasn't followed", start.host_str().unwrap_or(""))); }
+        Ok(url)
+    }
+}
+
+/// Loopback, private, link-local, carrier-grade NAT, unspecified, broadcast or multicast.
+fn internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => v4.is_loopback() || v4.is_private() || v4.is_link_local() || v4.is_unspecified() || v4.is_broadcast() || v4.is_multicast() || (v4.octets()[0] == 100 && v4.octets()[1] & 0xc0 == 64),
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => internal(IpAddr::V4(v4)),
+            None => v6.is_loopback() || v6.is_unspecified() || v6.is_multicast() || v6.segments()[0] & 0xfe00 == 0xfc00 || v6.segments()[0] & 0xffc0 == 0xfe80,
+        },
+    }
+}
+
+/// A value by JSON pointer (`/data/0/id`) or dotted path (`structure.smiles`).
+fn lookup<'a>(v: &'a Value, path: &str) -> Option<&'a Value> {
+    if path.starts_with('/') { return v.pointer(path); }
+    path.split('.').try_fold(v, |v, key| match v { Value::Array(a) => a.get(key.parse::<usize>().ok()?), _ => v.get(key) })
+}
+
+fn text(v: &Value) -> Option<String> {
+    match v { Value::String(s) => Some(s.trim().to_string()), Value::Number(n) => Some(n.to_string()), _ => None }
+}
+
+fn valid_name(name: &str) -> bool { !name.is_empty() && name.len() <= 64 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') }
+
+/// Runs one sync of a connector and files its report.
+async fn sync(s: &AppState, project: &str, name: &str, trigger: &'static str) -> Result<SyncReport, ApiError> {
+    let key = (project.to_string(), name.to_string());
+    let (connector, library_id, last_success) = {
+        let mut connectors = s.registry.connectors.lock().unwrap();
+        let e = connectors.get_mut(&key).ok_or_else(|| crate::not_found("registry connector", name))?;
+        if e.running { return Err(projects::conflict(format!("connector {name} is already syncing"))); }
+        e.running = true;
+        (e.connector.clone(), e.library_id.clone(), e.last_success_unix)
+    };
+    let started = unix_now();
+    let since = connector.since_param.as_ref().and(last_success).map(reports::timestamp);
+    let mut report = SyncReport { trigger, status: "failed", started_at_unix: started, finished_at_unix: started, since: since.clone(), pages: 0, fetched: 0, changes: libraries::Changes::default(), failed: 0, duplicates: 0, errors: Vec::new(), error: None };
+    let outcome = match s.retention.over_quota(s, project) {
+        Some(e) => Err(e),
+        None => s.registry.fetch(&connector, since.as_deref()).await,
+    };
+    let outcome = outcome.and_then(|(records, pages)| {
+        report.pages = pages;
+        report.fetched = records.len();
+        let fetched = records.iter().map(|r| {
+            let field = |path: &str| lookup(r, path).and_then(text).filter(|t| !t.is_empty());
+            libraries::Fetched { id: field(&connector.fields.id).unwrap_or_default(), smiles: field(&connector.fields.smiles), description: connector.fields.description.as_deref().and_then(field) }
+        }).collect();
+        let name = connector.library_name.clone().unwrap_or_else(|| format!("registry: {name}"));
+        s.libraries.sync(project, &library_id, &name, s.projects.standardization(project), fetched, since.is_some())
+    });
+    match outcome {
+        Ok((summary, changes)) => {
+            report.status = "completed";
+            report.changes = changes;
+            report.failed = summary.failed;
+            report.duplicates = summary.duplicates;
+            report.errors = summary.errors.into_iter().take(MAX_ERRORS).collect();
+        }
+        Err(e) => { tracing::warn!("registry connector {name} of project {project}: {e}"); report.error = Some(e); }
+    }
+    report.finished_at_unix = unix_now();
+    if let Some(e) = s.registry.connectors.lock().unwrap().get_mut(&key) {
+        e.running = false;
+        e.next_sync_unix = e.connector.every_minutes.map(|m| report.finished_at_unix + m * 60);
+        if report.error.is_none() { e.last_success_unix = Some(started); }
+        e.last_sync = Some(report.clone());
+    }
+    Ok(report)
+}
+
+/// Runs the connectors whose `every_minutes` has come round.
+pub async fn schedule(s: Arc<AppState>) {
+    loop {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        let now = unix_now();
+        let due: Vec<(String, String)> = s.registry.connectors.lock().unwrap().iter().filter(|(_, e)| !e.running && e.next_sync_unix.is_some_and(|t| t <= now)).map(|(k, _)| k.clone()).collect();
+        for (project, name) in due {
+            let s = s.clone();
+            tokio::spawn(async move { let _ = sync(&s, &project, &name, "schedule").await; });
+        }
+    }
+}
+
+pub async fn list(State(s): State<Arc<AppState>>, headers: HeaderMap) -> Json<ConnectorsResponse> {
+    let project = projects::project_id(&headers);
+    let mut names: Vec<String> = s.registry.connectors.lock().unwrap().keys().filter(|(p, _)| *p == project).map(|(_, n)| n.clone()).collect();
+    names.sort();
+    let connectors = names.iter().filter_map(|n| s.registry.view(&project, n)).collect();
+    Json(ConnectorsResponse { project, connectors })
+}
+
+pub async fn get(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(name): Path<String>) -> Result<Json<ConnectorView>, ApiError> {
+    s.registry.view(&projects::project_id(&headers), &name).map(Json).ok_or_else(|| crate::not_found("registry connector", &name))
+}
+
+pub async fn put(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(name): Path<String>, Json(mut connector): Json<Connector>) -> Result<Json<ConnectorView>, ApiError> {
+    let bad = |error: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error }));
+    if !valid_name(&name) { return Err(bad("a connector name is 1-64 letters, digits, '-' or '_'".into())); }
+    let url = reqwest::Url::parse(&connector.url).map_err(|e| bad(format!("url: {e}")))?;
+    s.registry.reachable(&url).await.map_err(bad)?;
+    let project = s.projects.resolve(&headers);
+    let key = (project.clone(), name.clone());
+    {
+        let mut connectors = s.registry.connectors.lock().unwrap();
+        // A header sent back as it was shown keeps its stored value, but only for the same scheme
+        // and host: a secret isn't carried to wherever the connector now points.
+        if let Some(old) = connectors.get(&key) {
+            let origin = |u: &str| reqwest::Url::parse(u).ok().map(|u| (u.scheme().to_string(), u.host_str().map(str::to_ascii_lowercase)));
+            let moved = origin(&old.connector.url) != origin(&connector.url);
+            for (k, v) in connector.headers.iter_mut() {
+                if v != REDACTED { continue; }
+                if moved { return Err(bad(format!("the url moved to another host, so header {k} must be sent again"))); }
+                if let Some(kept) = old.connector.headers.get(k) { v.clone_from(kept); }
+            }
+        }
+        s.registry.validate(&connector).map_err(bad)?;
+        let next_sync_unix = connector.every_minutes.map(|m| unix_now() + m * 60);
+        match connectors.get_mut(&key) {
+            Some(e) => { e.connector = connector; e.next_sync_unix = next_sync_unix; }
+            None => { connectors.insert(key, Entry { connector, library_id: uuid::Uuid::new_v4().to_string(), created_at_unix: unix_now(), running: false, next_sync_unix, last_sync: None, last_success_unix: None }); }
+        }
+    }
+    s.audit.record(&headers, &project, "configure_registry_connector", &name, "", None);
+    s.registry.view(&project, &name).map(Json).ok_or_else(|| crate::not_found("registry connector", &name))
+}
+
+/// Removes the connector; the library it kept stays, no longer synced.
+pub async fn delete(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(name): Path<String>) -> Result<Json<ConnectorView>, ApiError> {
+    let project = projects::project_id(&headers);
+    let view = s.registry.view(&project, &name).ok_or_else(|| crate::not_found("registry connector", &name))?;
+    s.registry.connectors.lock().unwrap().remove(&(project.clone(), name.clone()));
+    s.audit.record(&headers, &project, "delete_registry_connector", &name, "", None);
+    Ok(Json(view))
+}
+
+pub async fn run(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(name): Path<String>) -> Result<Json<SyncReport>, ApiError> {
+    let project = projects::project_id(&headers);
+    let report = sync(&s, &project, &name, "manual").await?;
+    s.audit.record(&headers, &project, "sync_registry", &name, "", None);
+    if let Some(error) = report.error.clone() { return Err((StatusCode::BAD_GATEWAY, Json(ErrorResponse { error }))); }
+    Ok(Json(report))
+}
@@ -0,0 +1,353 @@
+//! Self-contained HTML reports of finished jobs, for attaching to an ELN entry.
+//!
+//! `GET /reports/:job_id` renders one page that loads nothing from elsewhere: plots are inline
+//! SVG, screen hits are depicted inline, and the job's own JSON is embedded in a collapsed
+//! section. The plots are whatever the result holds: energy against step (an annealing trace,
+//! the stages' start and end energies, `energy` observables), other observables' time series, a
+//! PMF profile, the distribution of screen hits' affinities as pKd, and a Ramachandran plot of
+//! the backbone of a PDB in the result. Scalar values and arrays of records become tables, and
+//! the resources used and signed provenance record close the page. A print stylesheet lays it
+//! out for A4, so a browser's Print to PDF gives the PDF.
+
+use axum::{extract::{Path, Query, State}, http::{header, HeaderMap, StatusCode}, response::{IntoResponse, Json, Response}};
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt::Write;
+use std::sync::Arc;
+
+use crate::{chem, depict, jobs::Job, loops, projects, vec3, ApiError, AppState, ErrorResponse};
+
+const WIDTH: f64 = 640.0;
+const HEIGHT: f64 = 320.0;
+/// Plot margins: left, right, top, bottom.
+const MARGIN: (f64, f64, f64, f64) = (64.0, 16.0, 32.0, 48.0);
+const PALETTE: [&str; 6] = ["#1f77b4", "#d62728", "#2ca02c", "#ff7f0e", "#9467bd", "#8c564b"];
+const HISTOGRAM_BINS: usize = 20;
+/// Hits drawn in a screen's hit table.
+const DEPICTED_HITS: usize = 10;
+const DEPICTION_SIZE: u32 = 120;
+/// Rows shown of a table; the rest are in the embedded JSON.
+const MAX_ROWS: usize = 50;
+/// Strings longer than this (a PDB, an SVG) are left out of tables.
+const MAX_CELL: usize = 200;
+
+const STYLE: &str = "body{font-family:system-ui,sans-serif;max-width:960px;margin:2em auto;padding:0 1em;color:#222}h1{font-size:1.5em}h2{font-size:1.15em;border-bottom:1px solid #ccc;padding-bottom:.2em;margin-top:1.8em}table{border-collapse:collapse;margin:.5em 0;font-size:.9em}td,th{border:1px solid #ddd;padding:.25em .6em;text-align:left;vertical-align:middle}th{background:#f4f4f4}td.n{text-align:right;font-variant-numeric:tabular-nums}code{font-size:.85em;word-break:break-all}svg{max-width:100%;height:auto}pre{white-space:pre-wrap;font-size:.8em}footer{margin-top:2em;color:#666;font-size:.85em}@page{size:A4;margin:15mm}@media print{body{margin:0;max-width:none}details{display:none}section{break-inside:avoid}}";
+
+#[derive(Deserialize)]
+pub struct ReportQuery { format: Option<String> }
+
+pub async fn report(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(id): Path<String>, Query(q): Query<ReportQuery>) -> Result<Response, ApiError> {
+    let bad = |error: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error }));
+    match q.format.as_deref().unwrap_or("html") {
+        "html" => {}
+        "pdf" => return Err(bad("the engine renders HTML only; the report has a print layout, so print it to PDF from a browser".into())),
+        f => return Err(bad(format!("unknown format {f}; expected html"))),
+    }
+    let job = s.jobs.get(&projects::project_id(&headers), &id).ok_or_else(|| crate::not_found("job", &id))?;
+    if job.archived { return Err(projects::conflict(format!("job {id} is archived; restore its project to report on it"))); }
+    let disposition = format!("inline; filename=\"{}-{}.html\"", job.kind, job.job_id.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-', "_"));
+    Ok(([(header::CONTENT_TYPE, "text/html; charset=utf-8".to_string()), (header::CONTENT_DISPOSITION, disposition)], render(&job)).into_response())
+}
+
+fn render(job: &Job) -> String {
+    let r = &job.result;
+    let title = format!("{} report: {}", job.kind, job.subject);
+    let mut out = format!("<!DOCTYPE html><html lang=\"en\"><head><meta charset=\"utf-8\"><title>{}</title><style>{STYLE}</style></head><body><h1>{}</h1>", esc(&title), esc(&title));
+    let mut about = vec![("Job", code(&job.job_id)), ("Project", esc(&job.project)), ("Kind", esc(&job.kind)), ("Subject", esc(&job.subject)), ("Model", esc(&job.model)), ("Status", esc(&job.status)), ("Created", timestamp(job.created_at_unix))];
+    if !job.depends_on.is_empty() { about.push(("Depends on", job.depends_on.iter().map(|d| code(d)).collect::<Vec<_>>().join("<br>"))); }
+    section(&mut out, "Job", &key_values(&about));
+
+    let scalars = scalar_rows(r);
+    if !scalars.is_empty() {
+        let rows: Vec<(&str, String)> = scalars.iter().map(|(k, v)| (k.as_str(), v.clone())).collect();
+        section(&mut out, "Results", &key_values(&rows));
+    }
+    for plot in plots(r) { section(&mut out, "", &plot); }
+    if let Some(hits) = r.get("hits").and_then(Value::as_array).filter(|h| !h.is_empty()) { section(&mut out, "Top hits", &hit_table(hits)); }
+    if let Value::Object(fields) = r {
+        for (key, value) in fields {
+            if key == "hits" { continue; }
+            if let Some(table) = value.as_array().and_then(|rows| record_table(rows)) { section(&mut out, &heading(key), &table); }
+        }
+    }
+
+    let res = &job.resources;
+    section(&mut out, "Resources", &key_values(&[("Wall time", format!("{:.3} s", res.wall_seconds)), ("CPU time", format!("{:.3} s", res.cpu_seconds)), ("GPU time", format!("{:.3} s", res.gpu_seconds)), ("Peak memory", format!("{:.1} MiB", res.peak_memory_bytes as f64 / (1024.0 * 1024.0)))]));
+    match &job.provenance {
+        Some(p) => {
+            let st = &p.statement;
+            let mut rows = vec![("Engine version", esc(&st.engine_version)), ("Model version", esc(&st.model_version))];
+            if let Some(ff) = &st.force_field { rows.push(("Force field", esc(ff))); }
+            if let Some(h) = &st.parameters_sha256 { rows.push(("Parameters SHA-256", code(h))); }
+            rows.extend(st.inputs.iter().map(|(name, h)| ("Input", format!("{} {}", esc(name), code(h)))));
+            rows.extend([("Result SHA-256", code(&st.result_sha256)), ("Signed", format!("{} with key {}", esc(&st.algorithm), code(&st.key_id))), ("Signature", code(&p.signature))]);
+            section(&mut out, "Provenance", &format!("{}<p>Check this record with <code>POST /api/v1/bio/verify</code>, sending it with the result below.</p>", key_values(&rows)));
+        }
+        None => section(&mut out, "Provenance", "<p>This job has no signed provenance record.</p>"),
+    }
+    let json = serde_json::to_string_pretty(r).unwrap_or_default();
+    let _ = write!(out, "<details><summary>Result JSON</summary><pre>{}</pre></details>", esc(&json));
+    let _ = write!(out, "<footer>ALICE Bio engine {}, report generated {}.</footer></body></html>", env!("CARGO_PKG_VERSION"), timestamp(crate::unix_now()));
+    out
+}
+
+fn section(out: &mut String, heading: &str, body: &str) {
+    out.push_str("<section>");
+    if !heading.is_empty() { let _ = write!(out, "<h2>{}</h2>", esc(heading)); }
+    out.push_str(body);
+    out.push_str("</section>");
+}
+
+fn heading(key: &str) -> String {
+    let words = key.replace('_', " ");
+    let mut chars = words.chars();
+    chars.next().map(|c| c.to_uppercase().chain(chars).collect()).unwrap_or_default()
+}
+
+fn esc(s: &str) -> String { s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&#39;") }
+fn code(s: &str) -> String { format!("<code>{}</code>", esc(s)) }
+
+/// RFC 3339, in UTC.
+pub fn timestamp(unix: u64) -> String {
+    let (days, secs) = ((unix / 86_400) as i64, unix % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z", secs / 3600, secs % 3600 / 60, secs % 60)
+}
+
+fn number(v: f64) -> String {
+    if v.fract() == 0.0 && v.abs() < 1e15 { return format!("{v:.0}"); }
+    if v != 0.0 && (v.abs() < 1e-3 || v.abs() >= 1e6) { return format!("{v:.3e}"); }
+    format!("{}", (v * 1e4).round() / 1e4)
+}
+
+fn cell(v: &Value) -> Option<String> {
+    match v {
+        Value::Number(n) => n.as_f64().map(number),
+        Value::String(s) if s.len() <= MAX_CELL => Some(esc(s)),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn key_values(rows: &[(&str, String)]) -> String {
+    let mut t = String::from("<table>");
+    for (k, v) in rows { let _ = write!(t, "<tr><th>{}</th><td>{v}</td></tr>", esc(k)); }
+    t + "</table>"
+}
+
+/// The result's scalar fields, and those of the objects directly inside it.
+fn scalar_rows(r: &Value) -> Vec<(String, String)> {
+    let Value::Object(fields) = r else { return Vec::new() };
+    let mut rows = Vec::new();
+    for (key, value) in fields {
+        match value {
+            Value::Object(inner) => rows.extend(inner.iter().filter_map(|(k, v)| Some((format!("{key}.{k}"), cell(v)?)))),
+            v => rows.extend(cell(v).map(|c| (key.clone(), c))),
+        }
+    }
+    rows
+}
+
+/// An array of objects as a table of their scalar fields.
+fn record_table(rows: &[Value]) -> Option<String> {
+    let mut columns: Vec<&str> = Vec::new();
+    for row in rows.iter().take(MAX_ROWS) {
+        for (k, v) in row.as_object()? { if cell(v).is_some() && !columns.contains(&k.as_str()) { columns.push(k); } }
+    }
+    if columns.is_empty() { return None; }
+    let mut t = String::from("<table><tr>");
+    for c in &columns { let _ = write!(t, "<th>{}</th>", esc(c)); }
+    t.push_str("</tr>");
+    for row in rows.iter().take(MAX_ROWS) {
+        t.push_str("<tr>");
+        for c in &columns {
+            let v = row.get(*c).unwrap_or(&Value::Null);
+            let class = if v.is_number() { " class=\"n\"" } else { "" };
+            let _ = write!(t, "<td{class}>{}</td>", cell(v).unwrap_or_default());
+        }
+        t.push_str("</tr>");
+    }
+    t.push_str("</table>");
+    if rows.len() > MAX_ROWS { let _ = write!(t, "<p>{} more rows in the result JSON.</p>", rows.len() - MAX_ROWS); }
+    Some(t)
+}
+
+fn hit_table(hits: &[Value]) -> String {
+    let mut t = String::from("<table><tr><th>#</th><th>Compound</th><th>Structure</th><th>K<sub>d</sub> (nM)</th><th>Cluster</th><th>SMILES</th></tr>");
+    for (i, h) in hits.iter().take(DEPICTED_HITS).enumerate() {
+        let smiles = h.get("smiles").and_then(Value::as_str).unwrap_or("");
+        let drawing = chem::parse_smiles(smiles).map(|m| depict::svg(&m, DEPICTION_SIZE)).unwrap_or_default();
+        let field = |k: &str| h.get(k).and_then(cell).unwrap_or_default();
+        let _ = write!(t, "<tr><td class=\"n\">{}</td><td>{}</td><td>{drawing}</td><td class=\"n\">{}</td><td class=\"n\">{}</td><td>{}</td></tr>", i + 1, field("compound_id"), field("binding_affinity_nm"), field("cluster_id"), code(smiles));
+    }
+    t.push_str("</table>");
+    if hits.len() > DEPICTED_HITS { let _ = write!(t, "<p>{} more hits in the result JSON.</p>", hits.len() - DEPICTED_HITS); }
+    t
+}
+
+type Series = (String, Vec<(f64, f64)>);
+
+fn points(rows: &Value, x: &str, y: &str) -> Vec<(f64, f64)> {
+    rows.as_array().into_iter().flatten().filter_map(|p| Some((p.get(x)?.as_f64()?, p.get(y)?.as_f64()?))).collect()
+}
+
+fn plots(r: &Value) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut energy: Vec<Series> = Vec::new();
+    if let Some(trace) = r.pointer("/annealing/trace") { energy.push(("annealing".into(), points(trace, "step", "energy_kcal_mol"))); }
+    if let Some(stages) = r.get("stages").and_then(Value::as_array) {
+        let mut step = 0.0;
+        let mut line = Vec::new();
+        for st in stages {
+            let (Some(start), Some(end)) = (st.get("energy_start_kcal_mol").and_then(Value::as_f64), st.get("energy_end_kcal_mol").and_then(Value::as_f64)) else { continue };
+            line.push((step, start));
+            step += st.get("steps").and_then(Value::as_f64).unwrap_or(0.0);
+            line.push((step, end));
+        }
+        energy.push(("stages".into(), line));
+    }
+    let series = r.pointer("/observables/series").and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default();
+    for s in series {
+        let name = s.get("name").and_then(Value::as_str).unwrap_or("observable");
+        let xy: Vec<(f64, f64)> = s.get("steps").and_then(Value::as_array).into_iter().flatten().zip(s.get("values").and_then(Value::as_array).into_iter().flatten()).filter_map(|(x, y)| Some((x.as_f64()?, y.as_f64()?))).collect();
+        if s.get("type").and_then(Value::as_str) == Some("energy") { energy.push((name.into(), xy)); continue; }
+        let unit = s.get("unit").and_then(Value::as_str).unwrap_or("");
+        out.extend(line_plot(name, "step", &if unit.is_empty() { name.to_string() } else { format!("{name} ({unit})") }, &[(name.into(), xy)]));
+    }
+    energy.retain(|(_, xy)| xy.len() > 1);
+    if !energy.is_empty() { out.insert(0, line_plot("Energy vs time", "step", "energy (kcal/mol)", &energy).unwrap_or_default()); }
+    if let Some(pmf) = r.get("pmf") {
+        let coordinate = pmf.get("reaction_coordinate").and_then(Value::as_str).unwrap_or("coordinate");
+        let unit = pmf.get("unit").and_then(Value::as_str).unwrap_or("");
+        out.extend(line_plot("Potential of mean force", &format!("{coordinate} ({unit})"), "PMF (kcal/mol)", &[("PMF".into(), points(&pmf["profile"], "coordinate", "pmf_kcal_mol"))]));
+    }
+    if let Some(hits) = r.get("hits").and_then(Value::as_array) {
+        let pkd: Vec<f64> = hits.iter().filter_map(|h| h.get("binding_affinity_nm")?.as_f64()).filter(|&nm| nm > 0.0).map(|nm| 9.0 - nm.log10()).collect();
+        out.extend(histogram("Hit affinity distribution", "pKd", &pkd));
+    }
+    if let Some(pdb) = r.get("pdb").and_then(Value::as_str) { out.extend(ramachandran(pdb)); }
+    out.retain(|p| !p.is_empty());
+    out
+}
+
+/// A data range mapped onto the plot area.
+struct Axes { x: (f64, f64), y: (f64, f64) }
+
+impl Axes {
+    fn fit(xs: impl Iterator<Item = f64> + Clone, ys: impl Iterator<Item = f64> + Clone) -> Self {
+        let span = |v: &mut dyn Iterator<Item = f64>| v.fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), x| (lo.min(x), hi.max(x)));
+        let pad = |(lo, hi): (f64, f64)| if hi - lo < 1e-12 { (lo - 1.0, hi + 1.0) } else { (lo, hi) };
+        Self { x: pad(span(&mut xs.clone())), y: pad(span(&mut ys.clone())) }
+    }
+    fn px(&self, x: f64) -> f64 { MARGIN.0 + (x - self.x.0) / (self.x.1 - self.x.0) * (WIDTH - MARGIN.0 - MARGIN.1) }
+    fn py(&self, y: f64) -> f64 { HEIGHT - MARGIN.3 - (y - self.y.0) / (self.y.1 - self.y.0) * (HEIGHT - MARGIN.2 - MARGIN.3) }
+
+    /// The SVG up to the plot contents: title, frame, ticks and axis labels.
+    fn frame(&self, title: &str, x_label: &str, y_label: &str) -> String {
+        let (left, right, top, bottom) = (MARGIN.0, WIDTH - MARGIN.1, MARGIN.2, HEIGHT - MARGIN.3);
+        let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{WIDTH}\" height=\"{HEIGHT}\" viewBox=\"0 0 {WIDTH} {HEIGHT}\" font-family=\"sans-serif\" font-size=\"11\"><text x=\"{}\" y=\"18\" text-anchor=\"middle\" font-size=\"13\" font-weight=\"bold\">{}</text>", WIDTH / 2.0, esc(title));
+        let _ = write!(svg, "<rect x=\"{left}\" y=\"{top}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"#888\"/>", right - left, bottom - top);
+        for t in ticks(self.x.0, self.x.1) { let x = self.px(t); let _ = write!(svg, "<line x1=\"{x:.1}\" y1=\"{bottom}\" x2=\"{x:.1}\" y2=\"{}\" stroke=\"#888\"/><text x=\"{x:.1}\" y=\"{}\" text-anchor=\"middle\">{}</text>", bottom + 4.0, bottom + 16.0, number(t)); }
+        for t in ticks(self.y.0, self.y.1) { let y = self.py(t); let _ = write!(svg, "<line x1=\"{}\" y1=\"{y:.1}\" x2=\"{left}\" y2=\"{y:.1}\" stroke=\"#888\"/><text x=\"{}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>", left - 4.0, left - 6.0, y + 4.0, number(t)); }
+        let _ = write!(svg, "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text><text transform=\"translate(14 {}) rotate(-90)\" text-anchor=\"middle\">{}</text>", (left + right) / 2.0, HEIGHT - 8.0, esc(x_label), (top + bottom) / 2.0, esc(y_label));
+        svg
+    }
+}
+
+/// Round tick positions within `lo..=hi`, about five of them.
+fn ticks(lo: f64, hi: f64) -> Vec<f64> {
+    let raw = (hi - lo) / 5.0;
+    let magnitude = 10f64.powf(raw.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0].into_iter().map(|m| m * magnitude).find(|s| *s >= raw).unwrap_or(10.0 * magnitude);
+    let first = (lo / step).ceil() as i64;
+    let last = (hi / step).floor() as i64;
+    (first..=last).map(|i| i as f64 * step).collect()
+}
+
+fn legend(svg: &mut String, names: &[&str], colors: &[&str]) {
+    if names.len() < 2 { return; }
+    for (i, (name, color)) in names.iter().zip(colors).enumerate() {
+        let y = MARGIN.2 + 14.0 + i as f64 * 14.0;
+        let x = WIDTH - MARGIN.1 - 120.0;
+        let _ = write!(svg, "<rect x=\"{x}\" y=\"{}\" width=\"10\" height=\"10\" fill=\"{color}\"/><text x=\"{}\" y=\"{y}\">{}</text>", y - 9.0, x + 14.0, esc(name));
+    }
+}
+
+fn line_plot(title: &str, x_label: &str, y_label: &str, series: &[Series]) -> Option<String> {
+    let all = || series.iter().flat_map(|(_, xy)| xy.iter());
+    if all().count() < 2 { return None; }
+    let axes = Axes::fit(all().map(|p| p.0), all().map(|p| p.1));
+    let mut svg = axes.frame(title, x_label, y_label);
+    for ((_, xy), color) in series.iter().zip(PALETTE.iter().cycle()) {
+        let path: Vec<String> = xy.iter().map(|&(x, y)| format!("{:.1},{:.1}", axes.px(x), axes.py(y))).collect();
+        let _ = write!(svg, "<polyline points=\"{}\" fill=\"none\" stroke=\"{color}\" stroke-width=\"1.5\"/>", path.join(" "));
+    }
+    legend(&mut svg, &series.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>(), &PALETTE);
+    Some(svg + "</svg>")
+}
+
+fn histogram(title: &str, x_label: &str, values: &[f64]) -> Option<String> {
+    if values.len() < 2 { return None; }
+    let (lo, hi) = values.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+    let (lo, hi) = if hi - lo < 1e-9 { (lo - 0.5, hi + 0.5) } else { (lo, hi) };
+    let width = (hi - lo) / HISTOGRAM_BINS as f64;
+    let mut counts = [0usize; HISTOGRAM_BINS];
+    for v in values { counts[(((v - lo) / width) as usize).min(HISTOGRAM_BINS - 1)] += 1; }
+    let most = counts.iter().copied().max().unwrap_or(1) as f64;
+    let axes = Axes { x: (lo, hi), y: (0.0, most) };
+    let mut svg = axes.frame(title, x_label, "hits");
+    for (i, &n) in counts.iter().enumerate() {
+        let (x0, x1) = (axes.px(lo + i as f64 * width), axes.px(lo + (i + 1) as f64 * width));
+        let y = axes.py(n as f64);
+        let _ = write!(svg, "<rect x=\"{x0:.1}\" y=\"{y:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\" stroke=\"#fff\"/>", x1 - x0, axes.py(0.0) - y, PALETTE[0]);
+    }
+    Some(svg + "</svg>")
+}
+
+/// φ and ψ of each residue with both neighbours bonded to it, and whether it is a glycine.
+fn backbone_dihedrals(pdb: &str) -> Vec<(f64, f64, bool)> {
+    struct Residue { id: (String, String), glycine: bool, n: Option<[f64; 3]>, ca: Option<[f64; 3]>, c: Option<[f64; 3]> }
+    let mut residues: Vec<Residue> = Vec::new();
+    for line in pdb.lines().filter(|l| l.starts_with("ATOM")) {
+        let field = |a: usize, b: usize| line.get(a..b).map(str::trim);
+        let (Some(name), Some(resname), Some(chain), Some(number)) = (field(12, 16), field(17, 20), field(21, 22), field(22, 27)) else { continue };
+        let Some(p) = [field(30, 38), field(38, 46), field(46, 54)].map(|c| c.and_then(|c| c.parse::<f64>().ok())).into_iter().collect::<Option<Vec<f64>>>() else { continue };
+        let id = (chain.to_string(), number.to_string());
+        if residues.last().is_none_or(|r| r.id != id) { residues.push(Residue { id, glycine: resname == "GLY", n: None, ca: None, c: None }); }
+        let Some(r) = residues.last_mut() else { continue };
+        let slot = match name { "N" => &mut r.n, "CA" => &mut r.ca, "C" => &mut r.c, _ => continue };
+        slot.get_or_insert([p[0], p[1], p[2]]);
+    }
+    let bonded = |c: [f64; 3], n: [f64; 3]| (0..3).map(|i| (c[i] - n[i]).powi(2)).sum::<f64>() < 2.0 * 2.0;
+    residues.windows(3).filter_map(|w| {
+        let (prev_c, n, ca, c, next_n) = (w[0].c?, w[1].n?, w[1].ca?, w[1].c?, w[2].n?);
+        (w[0].id.0 == w[1].id.0 && w[1].id.0 == w[2].id.0 && bonded(prev_c, n) && bonded(c, next_n)).then(|| (vec3::torsion(prev_c, n, ca, c), vec3::torsion(n, ca, c, next_n), w[1].glycine))
+    }).collect()
+}
+
+fn ramachandran(pdb: &str) -> Option<String> {
+    let angles = backbone_dihedrals(pdb);
+    if angles.is_empty() { return None; }
+    let axes = Axes { x: (-180.0, 180.0), y: (-180.0, 180.0) };
+    let mut svg = axes.frame("Ramachandran plot", "φ (degrees)", "ψ (degrees)");
+    let (rx, ry) = (axes.px(40.0) - axes.px(0.0), axes.py(0.0) - axes.py(40.0));
+    let _ = write!(svg, "<clipPath id=\"ramachandran-area\"><rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"/></clipPath><g clip-path=\"url(#ramachandran-area)\" fill=\"#e8eef7\">", MARGIN.0, MARGIN.2, WIDTH - MARGIN.0 - MARGIN.1, HEIGHT - MARGIN.2 - MARGIN.3);
+    for (phi, psi, _) in loops::RAMACHANDRAN { let _ = write!(svg, "<ellipse cx=\"{:.1}\" cy=\"{:.1}\" rx=\"{rx:.1}\" ry=\"{ry:.1}\"/>", axes.px(phi), axes.py(psi)); }
+    svg.push_str("</g>");
+    for (phi, psi, glycine) in &angles {
+        let color = if *glycine { PALETTE[3] } else { PALETTE[0] };
+        let _ = write!(svg, "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"2.5\" fill=\"{color}\"/>", axes.px(*phi), axes.py(*psi));
+    }
+    legend(&mut svg, &["residue", "glycine"], &[PALETTE[0], PALETTE[3]]);
+    let _ = write!(svg, "<text x=\"{}\" y=\"{}\" fill=\"#666\">{} residues; shaded: allowed regions</text>", MARGIN.0 + 6.0, MARGIN.2 + 14.0, angles.len());
+    Some(svg + "</svg>")
+}
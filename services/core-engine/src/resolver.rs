@@ -0,0 +1,53 @@
+//! Identifier resolution with an external fallback, and `GET /resolve`.
+//!
+//! Identifiers the bundled table and SMILES parsing (`bio_engine_core::resolver`) don't
+//! resolve go to an external service such as NCI CACTUS when `BIO_RESOLVER_URL` is set, with
+//! answers cached. `GET /resolve` also standardizes the result as the caller's project does
+//! (see `standardize`).
+
+use axum::{extract::{Query, State}, http::HeaderMap, response::Json};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+pub use bio_engine_core::resolver::*;
+
+use crate::{chem, standardize, AppState};
+
+pub struct Resolver { client: reqwest::Client, external_url: Option<String>, cache: Mutex<HashMap<String, Option<String>>> }
+
+impl Resolver {
+    pub fn new(external_url: Option<String>) -> Self { Self { client: reqwest::Client::new(), external_url, cache: Mutex::new(HashMap::new()) } }
+
+    pub async fn resolve(&self, input: &str) -> Resolved {
+        if let Some(r) = resolve_local(input) { return r; }
+        let Some(template) = &self.external_url else { return Resolved::opaque(input) };
+        let cached = self.cache.lock().unwrap().get(input).cloned();
+        let smiles = match cached {
+            Some(hit) => hit,
+            None => {
+                let fetched = self.fetch(template, input).await;
+                self.cache.lock().unwrap().insert(input.to_string(), fetched.clone());
+                fetched
+            }
+        };
+        match smiles {
+            Some(smi) => Resolved::new(input, Some(smi), None, "external"),
+            None => Resolved::opaque(input),
+        }
+    }
+
+    async fn fetch(&self, template: &str, input: &str) -> Option<String> {
+        let url = template.replace("{id}", &percent_encode(input.trim()));
+        let resp = self.client.get(&url).timeout(std::time::Duration::from_secs(5)).send().await.ok()?.error_for_status().ok()?;
+        let body = resp.text().await.ok()?;
+        chem::canonicalize(body.lines().next()?.trim()).ok()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ResolveQuery { id: String }
+
+pub async fn resolve(State(s): State<Arc<AppState>>, headers: HeaderMap, Query(q): Query<ResolveQuery>) -> Json<Resolved> {
+    Json(standardize::resolve(&s, &headers, &q.id).await)
+}
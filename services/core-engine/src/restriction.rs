@@ -0,0 +1,162 @@
+//! Restriction maps from a REBASE enzyme database.
+//!
+//! Enzymes come from a REBASE "withrefm" file: `<1>` starts an entry with the enzyme name and
+//! `<3>` gives the recognition site in IUPAC codes with its cut, either inside the site
+//! (`G^AATTC`) or downstream of it as top/bottom strand offsets (`GGTCTC(1/5)`). A bundled
+//! file of common cloning enzymes is used unless `BIO_REBASE_FILE` points at a full REBASE
+//! download. Sites are matched on both strands, and across the origin for circular
+//! sequences. Cut positions are 0-based indices of the first base after the top-strand cut.
+
+use axum::{extract::State, http::StatusCode, response::Json};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{ApiError, AppState, ErrorResponse};
+
+const BUNDLED: &str = include_str!("../data/rebase_withrefm.txt");
+
+/// A recognition site with its cuts, as offsets from the site start in top-strand
+/// coordinates (the bottom strand is cut before top-strand base `site start + bottom`).
+#[derive(Clone)]
+pub struct Enzyme { pub name: String, site: String, pattern: Vec<u8>, top: isize, bottom: isize }
+
+impl Enzyme {
+    /// Parses a REBASE site such as `G^AATTC` or `GGTCTC(1/5)`; `None` when the cut is unknown.
+    fn parse(name: &str, site: &str) -> Option<Self> {
+        // Enzymes cutting on both sides, `(8/13)GACNNNNNNGTC(12/7)`, keep the downstream cut.
+        let site = if site.starts_with('(') { &site[site.find(')')? + 1..] } else { site };
+        let (pattern, top, bottom) = if let Some(open) = site.find('(') {
+            let (a, b) = site[open + 1..].trim_end_matches(')').split_once('/')?;
+            let len = open as isize;
+            (site.as_bytes()[..open].to_vec(), len + a.parse::<isize>().ok()?, len + b.parse::<isize>().ok()?)
+        } else {
+            let caret = site.find('^')?;
+            let pattern: Vec<u8> = site.bytes().filter(|&b| b != b'^').collect();
+            (pattern.clone(), caret as isize, (pattern.len() - caret) as isize)
+        };
+        pattern.iter().all(|b| IUPAC.iter().any(|(c, _)| c == b)).then(|| Self { name: name.into(), site: site.into(), pattern, top, bottom })
+    }
+
+    /// Type IIS: both strands are cut downstream of the recognition site.
+    pub fn cuts_outside(&self) -> bool { self.top.min(self.bottom) >= self.pattern.len() as isize }
+}
+
+const IUPAC: [(u8, &[u8]); 15] = [(b'A', b"A"), (b'C', b"C"), (b'G', b"G"), (b'T', b"T"), (b'R', b"AG"), (b'Y', b"CT"), (b'S', b"CG"), (b'W', b"AT"), (b'K', b"GT"), (b'M', b"AC"), (b'B', b"CGT"), (b'D', b"AGT"), (b'H', b"ACT"), (b'V', b"ACG"), (b'N', b"ACGT")];
+
+fn matches(code: u8, base: u8) -> bool { IUPAC.iter().find(|(c, _)| *c == code).is_some_and(|(_, bases)| bases.contains(&base)) }
+
+fn complement(code: u8) -> u8 {
+    match code { b'A' => b'T', b'T' => b'A', b'C' => b'G', b'G' => b'C', b'R' => b'Y', b'Y' => b'R', b'K' => b'M', b'M' => b'K', b'B' => b'V', b'V' => b'B', b'D' => b'H', b'H' => b'D', other => other }
+}
+
+pub struct EnzymeDb { enzymes: Vec<Enzyme>, source: String }
+
+impl EnzymeDb {
+    pub fn load(path: Option<String>) -> Self {
+        if let Some(p) = path {
+            match std::fs::read_to_string(&p).map(|text| parse_withrefm(&text)) {
+                Ok(enzymes) if !enzymes.is_empty() => return Self { enzymes, source: p },
+                Ok(_) => tracing::warn!("REBASE file {p} has no usable enzymes; using the bundled set"),
+                Err(e) => tracing::warn!("REBASE file {p} unavailable: {e}; using the bundled set"),
+            }
+        }
+        Self { enzymes: parse_withrefm(BUNDLED), source: "bundled".into() }
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Enzyme> { self.enzymes.iter().find(|e| e.name.eq_ignore_ascii_case(name.trim())) }
+}
+
+/// Enzymes with a known cut; isoschizomers are separate entries, as in REBASE.
+fn parse_withrefm(text: &str) -> Vec<Enzyme> {
+    let mut enzymes = Vec::new();
+    let mut name: Option<&str> = None;
+    for line in text.lines().map(str::trim) {
+        if let Some(n) = line.strip_prefix("<1>") { name = Some(n.trim()); }
+        if let Some(site) = line.strip_prefix("<3>") {
+            enzymes.extend(name.take().and_then(|n| Enzyme::parse(n, &site.trim().to_uppercase())));
+        }
+    }
+    enzymes
+}
+
+#[derive(Deserialize)]
+pub struct RestrictionRequest { sequence: String, circular: Option<bool>, enzymes: Option<Vec<String>> }
+
+#[derive(Serialize)]
+pub struct Cut { pub position: usize, pub strand: char, pub overhang: String, pub overhang_type: &'static str }
+#[derive(Serialize)]
+pub struct EnzymeCuts { name: String, site: String, cuts: Vec<Cut>, fragments: Vec<usize> }
+#[derive(Serialize)]
+pub struct Digest { enzymes: Vec<String>, cuts: Vec<usize>, fragments: Vec<usize> }
+#[derive(Serialize)]
+pub struct RestrictionMapResponse { length: usize, circular: bool, database: String, enzymes_scanned: usize, enzymes: Vec<EnzymeCuts>, unique_cutters: Vec<String>, non_cutters: Vec<String>, #[serde(skip_serializing_if = "Option::is_none")] digest: Option<Digest> }
+
+pub fn cuts(enzyme: &Enzyme, seq: &[u8], circular: bool) -> Vec<Cut> {
+    let (n, len) = (seq.len() as isize, enzyme.pattern.len());
+    if seq.len() < len { return Vec::new(); }
+    let reverse: Vec<u8> = enzyme.pattern.iter().rev().map(|&b| complement(b)).collect();
+    let base = |i: isize| seq[i.rem_euclid(n) as usize];
+    let last = if circular { seq.len() } else { seq.len() - len + 1 };
+    let mut out: Vec<Cut> = Vec::new();
+    for p in 0..last as isize {
+        let hit = |pattern: &[u8]| pattern.iter().enumerate().all(|(k, &c)| matches(c, base(p + k as isize)));
+        // A palindromic site is found once; otherwise the reverse complement marks the
+        // enzyme bound to the bottom strand, which mirrors its cuts.
+        let orientations = [(hit(&enzyme.pattern), '+', enzyme.top, enzyme.bottom), (reverse != enzyme.pattern && hit(&reverse), '-', len as isize - enzyme.bottom, len as isize - enzyme.top)];
+        for (found, strand, top, bottom) in orientations {
+            let (top, bottom) = (p + top, p + bottom);
+            if !found || (!circular && (top <= 0 || top >= n || bottom <= 0 || bottom >= n)) { continue; }
+            let (lo, hi) = (top.min(bottom), top.max(bottom));
+            let overhang = (lo..hi).map(|i| base(i) as char).collect();
+            let overhang_type = if bottom > top { "5'" } else if bottom < top { "3'" } else { "blunt" };
+            out.push(Cut { position: top.rem_euclid(n) as usize, strand, overhang, overhang_type });
+        }
+    }
+    out.sort_by_key(|c| c.position);
+    out.dedup_by_key(|c| c.position);
+    out
+}
+
+/// Fragment lengths in sequence order; a circular molecule's last fragment spans the origin.
+fn fragments(positions: &[usize], n: usize, circular: bool) -> Vec<usize> {
+    if circular {
+        if positions.is_empty() { return Vec::new(); }
+        let mut sizes: Vec<usize> = positions.windows(2).map(|w| w[1] - w[0]).collect();
+        sizes.push(n - positions[positions.len() - 1] + positions[0]);
+        sizes
+    } else {
+        let mut bounds = vec![0];
+        bounds.extend_from_slice(positions);
+        bounds.push(n);
+        bounds.windows(2).map(|w| w[1] - w[0]).collect()
+    }
+}
+
+pub async fn map(State(s): State<Arc<AppState>>, Json(req): Json<RestrictionRequest>) -> Result<Json<RestrictionMapResponse>, ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let seq: Vec<u8> = req.sequence.bytes().filter(|b| !b.is_ascii_whitespace()).map(|b| match b.to_ascii_uppercase() { b'U' => b'T', b => b }).collect();
+    if seq.is_empty() { return Err(bad("sequence is empty".into())); }
+    if let Some(&b) = seq.iter().find(|b| !b"ACGTN".contains(b)) { return Err(bad(format!("invalid nucleotide {}", b as char))); }
+    let circular = req.circular.unwrap_or(false);
+    let db = &s.enzymes;
+    let selected: Vec<&Enzyme> = match &req.enzymes {
+        Some(names) => names.iter().map(|n| db.find(n).ok_or_else(|| bad(format!("unknown enzyme {n}")))).collect::<Result<_, _>>()?,
+        None => db.enzymes.iter().collect(),
+    };
+    let (mut enzymes, mut non_cutters) = (Vec::new(), Vec::new());
+    for e in &selected {
+        let cuts = cuts(e, &seq, circular);
+        if cuts.is_empty() { non_cutters.push(e.name.clone()); continue; }
+        let positions: Vec<usize> = cuts.iter().map(|c| c.position).collect();
+        enzymes.push(EnzymeCuts { name: e.name.clone(), site: e.site.clone(), fragments: fragments(&positions, seq.len(), circular), cuts });
+    }
+    let unique_cutters = enzymes.iter().filter(|e| e.cuts.len() == 1).map(|e| e.name.clone()).collect();
+    // Several named enzymes are also digested together.
+    let digest = (req.enzymes.as_ref().is_some_and(|n| n.len() > 1)).then(|| {
+        let mut all: Vec<usize> = enzymes.iter().flat_map(|e| e.cuts.iter().map(|c| c.position)).collect();
+        all.sort_unstable();
+        all.dedup();
+        Digest { enzymes: selected.iter().map(|e| e.name.clone()).collect(), fragments: fragments(&all, seq.len(), circular), cuts: all }
+    });
+    Ok(Json(RestrictionMapResponse { length: seq.len(), circular, database: db.source.clone(), enzymes_scanned: selected.len(), enzymes, unique_cutters, non_cutters, digest }))
+}
@@ -0,0 +1,171 @@
+//! Result retention, garbage collection and per-project storage quotas.
+//!
+//! Stored output falls into three classes, each with its own time to live: `jobs` (job records
+//! and their results), `trajectories` (simulation output kept for retrieval, such as
+//! metadynamics free-energy surfaces) and `exports` (screen hit poses kept for SDF/PDB export,
+//! strain and refinement). `BIO_JOB_TTL_DAYS`, `BIO_TRAJECTORY_TTL_DAYS` and
+//! `BIO_EXPORT_TTL_DAYS` set them; unset or 0 keeps a class forever. The `collect` task deletes
+//! whatever has outlived its TTL every `BIO_GC_INTERVAL_SECS` (default an hour).
+//!
+//! A project's storage is the approximate serialized size of its jobs, trajectories, exports
+//! and uploaded libraries. `BIO_STORAGE_QUOTA_MB` caps every project, and `BIO_STORAGE_QUOTAS`
+//! (`project=MB,...`) sets it per project. A project at its quota has compute requests,
+//! pipeline launches, library uploads and project imports refused with 507 Insufficient
+//! Storage until deletions or expiry free room; reads and deletes are never refused.
+
+use axum::{extract::{Request, State}, http::{HeaderMap, Method, StatusCode}, middleware::Next, response::{IntoResponse, Json, Response}};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::{admission, autoscale, projects, unix_now, AppState, ErrorResponse};
+
+const DAY: u64 = 86_400;
+const MB: u64 = 1024 * 1024;
+
+/// Items and their approximate bytes, held or freed.
+#[derive(Serialize, Clone, Copy, Default)]
+pub struct Held { pub items: usize, pub bytes: u64 }
+
+impl Held {
+    pub fn one(bytes: u64) -> Self { Self { items: 1, bytes } }
+    pub fn add(&mut self, other: Held) { self.items += other.items; self.bytes += other.bytes; }
+}
+
+/// A stored value with when it was stored and its approximate size.
+pub struct Kept<T> { pub value: T, created_at_unix: u64, bytes: u64 }
+
+impl<T: Serialize> Kept<T> {
+    pub fn new(value: T) -> Self { Self { bytes: serde_json::to_vec(&value).map_or(0, |v| v.len() as u64), value, created_at_unix: unix_now() } }
+}
+
+/// Values kept per (project, ID).
+pub type Keyed<T> = HashMap<(String, String), Kept<T>>;
+
+pub fn held<T>(map: &Keyed<T>, project: &str) -> Held {
+    let mut held = Held::default();
+    map.iter().filter(|((p, _), _)| p == project).for_each(|(_, k)| held.add(Held::one(k.bytes)));
+    held
+}
+
+/// Removes the values stored before `before`.
+pub fn expire<T>(map: &mut Keyed<T>, before: u64) -> Held {
+    let mut freed = Held::default();
+    map.retain(|_, k| k.created_at_unix >= before || { freed.add(Held::one(k.bytes)); false });
+    freed
+}
+
+/// The values of `project` with their IDs.
+pub fn of_project<T: Clone>(map: &Keyed<T>, project: &str) -> Vec<(String, T)> { map.iter().filter(|((p, _), _)| p == project).map(|((_, id), k)| (id.clone(), k.value.clone())).collect() }
+
+/// Stores a value under an ID `project` doesn't use yet; false when it does.
+pub fn insert_new<T: Serialize>(map: &mut Keyed<T>, project: &str, id: &str, value: T) -> bool {
+    match map.entry((project.to_string(), id.to_string())) {
+        std::collections::hash_map::Entry::Occupied(_) => false,
+        std::collections::hash_map::Entry::Vacant(e) => { e.insert(Kept::new(value)); true }
+    }
+}
+
+/// Removes one value of `project`.
+pub fn remove<T>(map: &mut Keyed<T>, project: &str, id: &str) -> Option<Held> { map.remove(&(project.to_string(), id.to_string())).map(|k| Held::one(k.bytes)) }
+
+/// What one pass of the collector removed.
+#[derive(Serialize, Clone, Copy)]
+pub struct Collection { at_unix: u64, jobs: Held, trajectories: Held, exports: Held }
+
+pub struct Retention { job_ttl: Option<u64>, trajectory_ttl: Option<u64>, export_ttl: Option<u64>, interval: u64, quota: Option<u64>, quotas: HashMap<String, u64>, last: Mutex<Option<Collection>> }
+
+fn ttl(var: &str) -> Option<u64> { Some(admission::setting(var, 0u64, |_| true) * DAY).filter(|&t| t > 0) }
+
+/// `project=MB` pairs separated by commas.
+fn parse_quotas(spec: &str) -> HashMap<String, u64> {
+    spec.split(',').filter(|p| !p.trim().is_empty()).filter_map(|pair| {
+        let parsed = pair.split_once('=').and_then(|(p, mb)| Some((p.trim().to_string(), mb.trim().parse::<u64>().ok()? * MB)));
+        if parsed.is_none() { tracing::warn!("BIO_STORAGE_QUOTAS entry {pair} is not project=MB; ignored"); }
+        parsed
+    }).collect()
+}
+
+impl Retention {
+    pub fn from_env() -> Self {
+        let quota = Some(admission::setting("BIO_STORAGE_QUOTA_MB", 0u64, |_| true) * MB).filter(|&q| q > 0);
+        let quotas = std::env::var("BIO_STORAGE_QUOTAS").map(|s| parse_quotas(&s)).unwrap_or_default();
+        Self {
+            job_ttl: ttl("BIO_JOB_TTL_DAYS"), trajectory_ttl: ttl("BIO_TRAJECTORY_TTL_DAYS"), export_ttl: ttl("BIO_EXPORT_TTL_DAYS"),
+            interval: admission::setting("BIO_GC_INTERVAL_SECS", 3600, |&n| n > 0), quota, quotas, last: Mutex::new(None),
+        }
+    }
+
+    fn quota_of(&self, project: &str) -> Option<u64> { self.quotas.get(project).copied().or(self.quota) }
+
+    /// Why `project` can't store more, if it is at its quota.
+    pub fn over_quota(&self, s: &AppState, project: &str) -> Option<String> {
+        let quota = self.quota_of(project)?;
+        let used = Usage::of(s, project).bytes();
+        (used >= quota).then(|| format!("project {project} is using {} MiB of its {} MiB storage quota; delete jobs, simulations, screens or libraries to free space", used / MB, quota / MB))
+    }
+
+    /// Deletes everything past its TTL as of `now`.
+    fn collect_once(&self, s: &AppState, now: u64) {
+        let before = |ttl: Option<u64>| ttl.map(|t| now.saturating_sub(t));
+        let c = Collection {
+            at_unix: now,
+            jobs: before(self.job_ttl).map(|t| s.jobs.expire(t)).unwrap_or_default(),
+            trajectories: before(self.trajectory_ttl).map(|t| s.fes.expire(t)).unwrap_or_default(),
+            exports: before(self.export_ttl).map(|t| s.poses.expire(t)).unwrap_or_default(),
+        };
+        let freed = c.jobs.items + c.trajectories.items + c.exports.items;
+        if freed > 0 { tracing::info!("retention: removed {} jobs, {} trajectories and {} exports ({} KiB)", c.jobs.items, c.trajectories.items, c.exports.items, (c.jobs.bytes + c.trajectories.bytes + c.exports.bytes) / 1024); }
+        *self.last.lock().unwrap() = Some(c);
+    }
+}
+
+/// Runs the collector every interval, for as long as the server runs.
+pub async fn collect(s: Arc<AppState>) {
+    loop {
+        tokio::time::sleep(Duration::from_secs(s.retention.interval)).await;
+        s.retention.collect_once(&s, unix_now());
+    }
+}
+
+#[derive(Serialize)]
+pub struct Usage { jobs: Held, trajectories: Held, exports: Held, libraries: Held }
+
+impl Usage {
+    fn of(s: &AppState, project: &str) -> Self { Self { jobs: s.jobs.held(project), trajectories: s.fes.held(project), exports: s.poses.held(project), libraries: s.libraries.held(project) } }
+    fn bytes(&self) -> u64 { self.jobs.bytes + self.trajectories.bytes + self.exports.bytes + self.libraries.bytes }
+}
+
+/// Refuses compute requests, pipeline launches, library uploads and imports from a project at its quota.
+pub async fn enforce(State(s): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let stores = autoscale::job_kind(&req).is_some() || (req.method() == Method::POST && ["/api/v1/bio/libraries", "/api/v1/bio/pipelines", "/api/v1/bio/import/project"].contains(&req.uri().path()));
+    if let Some(error) = stores.then(|| s.retention.over_quota(&s, &projects::project_id(req.headers()))).flatten() {
+        return (StatusCode::INSUFFICIENT_STORAGE, Json(ErrorResponse { error })).into_response();
+    }
+    next.run(req).await
+}
+
+#[derive(Serialize)]
+pub struct TtlDays { jobs: Option<u64>, trajectories: Option<u64>, exports: Option<u64> }
+
+#[derive(Serialize)]
+pub struct StorageReport { project: String, used_bytes: u64, quota_bytes: Option<u64>, usage: Usage, ttl_days: TtlDays, gc_interval_secs: u64, cold_storage: &'static str, #[serde(skip_serializing_if = "Option::is_none")] last_collection: Option<Collection> }
+
+pub async fn report(State(s): State<Arc<AppState>>, headers: HeaderMap) -> Json<StorageReport> {
+    let project = projects::project_id(&headers);
+    let usage = Usage::of(&s, &project);
+    let r = &s.retention;
+    let days = |t: Option<u64>| t.map(|t| t / DAY);
+    Json(StorageReport {
+        used_bytes: usage.bytes(), quota_bytes: r.quota_of(&project), usage, project,
+        ttl_days: TtlDays { jobs: days(r.job_ttl), trajectories: days(r.trajectory_ttl), exports: days(r.export_ttl) },
+        gc_interval_secs: r.interval, cold_storage: s.cold.backend(), last_collection: *r.last.lock().unwrap(),
+    })
+}
+
+#[derive(Serialize)]
+pub struct Deleted { deleted: String, bytes_freed: u64 }
+
+/// The response to a successful delete of `id`.
+pub fn deleted(id: String, freed: Held) -> Json<Deleted> { Json(Deleted { deleted: id, bytes_freed: freed.bytes }) }
@@ -0,0 +1,281 @@
+//! Retrosynthesis feasibility: whether a route to a molecule is found, and in how many steps.
+//!
+//! An external engine (AiZynthFinder, ASKCOS or anything behind the same contract) plugs in
+//! with `BIO_RETRO_URL`: it receives `{"smiles", "max_steps"}` as JSON and answers
+//! `{"route_found", "steps", "route"}`, the route as `[{"reaction", "product", "precursors"}]`.
+//! Without it, or when it fails, a built-in search breaks the molecule down with reaction
+//! templates run backwards — amide coupling, esterification, sulfonylation, Suzuki coupling,
+//! Buchwald–Hartwig and SNAr, N-arylation, reductive amination, N- and O-alkylation — each
+//! cutting one acyclic bond into two reagents, until every reagent is a stock building block
+//! (at most `STOCK_HEAVY_ATOMS` heavy atoms). The route kept is the one with the fewest
+//! reactions within `max_steps`. Ring construction isn't templated, so a ring system larger
+//! than a building block has no built-in route. Results are cached per engine, molecule and
+//! step limit.
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::{chem::{self, BondKind, Molecule}, record, standardize, usage, ApiError, AppState, ErrorResponse};
+
+const MODEL: &str = "retrosynthesis-templates";
+pub const DEFAULT_MAX_STEPS: usize = 5;
+const MAX_STEPS: usize = 10;
+const MAX_MOLECULES: usize = 200;
+/// Heavy atoms of the largest molecule taken as a purchasable building block.
+const STOCK_HEAVY_ATOMS: usize = 12;
+/// Molecules expanded per search before it gives up.
+const MAX_EXPANSIONS: usize = 5_000;
+const TIMEOUT: Duration = Duration::from_secs(300);
+
+/// A reagent's leaving group or handle, added where the cut bond was: SMILES with the
+/// attachment at atom 0 and the bond it takes; `None` is a hydrogen.
+type Handle = Option<(&'static str, BondKind)>;
+const H: Handle = None;
+const OH: Handle = Some(("O", BondKind::Single));
+const CL: Handle = Some(("Cl", BondKind::Single));
+const BR: Handle = Some(("Br", BondKind::Single));
+const F: Handle = Some(("F", BondKind::Single));
+const BORONIC: Handle = Some(("B(O)O", BondKind::Single));
+const OXO: Handle = Some(("O", BondKind::Double));
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Step { reaction: String, product: String, precursors: Vec<String> }
+
+/// Whether a route was found, with its reactions and longest linear sequence.
+#[derive(Serialize, Clone)]
+pub struct Feasibility { route_found: bool, #[serde(skip_serializing_if = "Option::is_none")] steps: Option<usize>, #[serde(skip_serializing_if = "Option::is_none")] longest_linear_sequence: Option<usize>, engine: &'static str, #[serde(skip_serializing_if = "Vec::is_empty")] route: Vec<Step> }
+
+impl Feasibility {
+    fn of(route: Option<Vec<Step>>, engine: &'static str) -> Self {
+        match route {
+            Some(route) => Self { route_found: true, steps: Some(route.len()), longest_linear_sequence: Some(longest_linear(&route)), engine, route },
+            None => Self { route_found: false, steps: None, longest_linear_sequence: None, engine, route: Vec::new() },
+        }
+    }
+
+    /// The flag and step counts without the route, for annotating other results.
+    pub fn summary(mut self) -> Self { self.route.clear(); self }
+}
+
+#[derive(Serialize)]
+struct RemoteJob<'a> { smiles: &'a str, max_steps: usize }
+#[derive(Deserialize)]
+struct RemoteResult { route_found: bool, steps: Option<usize>, #[serde(default)] route: Vec<Step> }
+
+/// The configured external engine, if any, with the result cache.
+pub struct Retro { client: reqwest::Client, url: Option<String>, cache: Mutex<HashMap<(String, usize), Feasibility>> }
+
+impl Retro {
+    pub fn new(url: Option<String>) -> Self { Self { client: reqwest::Client::new(), url: url.filter(|u| !u.is_empty()), cache: Mutex::new(HashMap::new()) } }
+
+    /// Feasibility of each canonical SMILES, from the external engine when one is set and
+    /// answers, else from the templates; failures of the engine come back as a warning.
+    pub async fn check(&self, smiles: &[String], max_steps: usize) -> (Vec<Feasibility>, Vec<String>) {
+        let mut out = Vec::new();
+        let mut failed: Vec<String> = Vec::new();
+        for smi in smiles {
+            if let Some(hit) = self.cache.lock().unwrap().get(&(smi.clone(), max_steps)) { out.push(hit.clone()); continue; }
+            let found = match &self.url {
+                Some(url) => match self.remote(url, smi, max_steps).await {
+                    Ok(f) => f,
+                    Err(e) => { failed.push(e); templates(smi, max_steps) }
+                },
+                None => templates(smi, max_steps),
+            };
+            if found.engine == "external" || self.url.is_none() { self.cache.lock().unwrap().insert((smi.clone(), max_steps), found.clone()); }
+            out.push(found);
+        }
+        let warnings = failed.first().map(|e| format!("{e}: {} of {} molecules fell back to the built-in templates", failed.len(), smiles.len())).into_iter().collect();
+        (out, warnings)
+    }
+
+    async fn remote(&self, url: &str, smiles: &str, max_steps: usize) -> Result<Feasibility, String> {
+        let body = serde_json::to_string(&RemoteJob { smiles, max_steps }).map_err(|e| e.to_string())?;
+        let resp = self.client.post(url).header(reqwest::header::CONTENT_TYPE, "application/json").body(body).timeout(TIMEOUT).send().await
+            .and_then(|r| r.error_for_status()).map_err(|e| format!("retrosynthesis service: {e}"))?;
+        let parsed: RemoteResult = serde_json::from_str(&resp.text().await.map_err(|e| format!("retrosynthesis service: {e}"))?).map_err(|e| format!("retrosynthesis service answered with {e}"))?;
+        let mut f = Feasibility::of(parsed.route_found.then_some(parsed.route), "external");
+        if f.route_found { f.steps = parsed.steps.or(f.steps); }
+        Ok(f)
+    }
+}
+
+/// Reactions in the longest chain from a building block to the target, the route's first
+/// product.
+fn longest_linear(route: &[Step]) -> usize {
+    fn depth(product: &str, route: &[Step]) -> usize {
+        route.iter().find(|s| s.product == product).map_or(0, |s| 1 + s.precursors.iter().map(|p| depth(p, route)).max().unwrap_or(0))
+    }
+    route.first().map_or(0, |s| depth(&s.product, route))
+}
+
+/// The route the templates find, or none within `max_steps`.
+pub fn templates(smiles: &str, max_steps: usize) -> Feasibility {
+    let mut memo = HashMap::new();
+    let mut expansions = 0;
+    Feasibility::of(solve(smiles, max_steps, &mut memo, &mut expansions), "templates")
+}
+
+fn heavy_atoms(m: &Molecule) -> usize { m.atoms.iter().filter(|a| a.element != "H").count() }
+
+/// The shortest route to `smiles` within `budget` reactions, its first step making `smiles`.
+fn solve(smiles: &str, budget: usize, memo: &mut HashMap<(String, usize), Option<Vec<Step>>>, expansions: &mut usize) -> Option<Vec<Step>> {
+    let m = chem::parse_smiles(smiles).ok()?;
+    if heavy_atoms(&m) <= STOCK_HEAVY_ATOMS { return Some(Vec::new()); }
+    if budget == 0 || *expansions >= MAX_EXPANSIONS { return None; }
+    if let Some(known) = memo.get(&(smiles.to_string(), budget)) { return known.clone(); }
+    *expansions += 1;
+    let mut best: Option<Vec<Step>> = None;
+    for (reaction, precursors) in disconnections(&m) {
+        let Some(first) = solve(&precursors[0], budget - 1, memo, expansions) else { continue };
+        let Some(rest) = budget.checked_sub(1 + first.len()) else { continue };
+        let Some(second) = solve(&precursors[1], rest, memo, expansions) else { continue };
+        if best.as_ref().is_some_and(|b| b.len() <= 1 + first.len() + second.len()) { continue; }
+        let mut route = vec![Step { reaction: reaction.into(), product: smiles.to_string(), precursors }];
+        route.extend(first);
+        route.extend(second);
+        best = Some(route);
+    }
+    memo.insert((smiles.to_string(), budget), best.clone());
+    best
+}
+
+/// Every template that applies to an acyclic single bond, as the reaction and its two
+/// reagents' canonical SMILES.
+fn disconnections(m: &Molecule) -> Vec<(&'static str, Vec<String>)> {
+    let adj = m.neighbors();
+    let double_o = |i: usize| adj[i].iter().filter(|&&(j, k)| k == BondKind::Double && m.atoms[j].element == "O").count();
+    let carbonyl = |i: usize| m.atoms[i].element == "C" && !m.atoms[i].aromatic && double_o(i) == 1;
+    let sulfonyl = |i: usize| m.atoms[i].element == "S" && double_o(i) == 2;
+    let acyl = |i: usize| adj[i].iter().any(|&(j, _)| carbonyl(j) || sulfonyl(j));
+    let sp3 = |i: usize| m.atoms[i].element == "C" && !m.atoms[i].aromatic && adj[i].iter().all(|&(_, k)| k == BondKind::Single);
+    let aryl = |i: usize| m.atoms[i].element == "C" && m.atoms[i].aromatic;
+    let is = |i: usize, e: &str| m.atoms[i].element == e && !m.atoms[i].aromatic;
+    let mut out = Vec::new();
+    for (k, bond) in m.bonds.iter().enumerate() {
+        if bond.kind != BondKind::Single || m.bond_in_ring(k) { continue; }
+        for (a, b) in [(bond.a, bond.b), (bond.b, bond.a)] {
+            // The carbonyl carbon's other neighbours must be carbon, so carbamates and ureas
+            // aren't taken for amides and esters.
+            let plain_carbonyl = carbonyl(a) && adj[a].iter().all(|&(j, kind)| j == b || kind == BondKind::Double || m.atoms[j].element == "C");
+            let rule: Option<(&str, Handle, Handle)> = if plain_carbonyl && is(b, "N") {
+                Some(("amide coupling", OH, H))
+            } else if plain_carbonyl && is(b, "O") && adj[b].iter().all(|&(j, _)| j == a || !carbonyl(j)) {
+                Some(("esterification", OH, H))
+            } else if sulfonyl(a) && is(b, "N") {
+                Some(("sulfonylation", CL, H))
+            } else if aryl(a) && aryl(b) && a < b {
+                Some(("Suzuki coupling", BR, BORONIC))
+            } else if aryl(a) && is(b, "N") && !acyl(b) {
+                Some(("Buchwald-Hartwig amination", BR, H))
+            } else if aryl(a) && m.atoms[b].element == "N" && m.atoms[b].aromatic {
+                Some(("N-arylation", BR, H))
+            } else if aryl(a) && is(b, "O") && adj[b].iter().all(|&(j, _)| j == a || aryl(j)) {
+                Some(("SNAr etherification", F, H))
+            } else if sp3(a) && m.atoms[a].hydrogens > 0 && is(b, "N") && !acyl(b) {
+                Some(("reductive amination", OXO, H))
+            } else if sp3(a) && m.atoms[b].element == "N" && m.atoms[b].aromatic {
+                Some(("N-alkylation", BR, H))
+            } else if sp3(a) && is(b, "O") && !acyl(b) {
+                Some(("O-alkylation", BR, H))
+            } else {
+                None
+            };
+            if let Some((reaction, ha, hb)) = rule {
+                if let Some(precursors) = cut(m, k, (a, ha), (b, hb)) { out.push((reaction, precursors)); }
+            }
+        }
+    }
+    out
+}
+
+/// The two reagents left when bond `k` is cut and each end takes its handle.
+fn cut(m: &Molecule, k: usize, ends: (usize, Handle), other: (usize, Handle)) -> Option<Vec<String>> {
+    let mut out = m.clone();
+    out.bonds.remove(k);
+    for (at, handle) in [ends, other] {
+        match handle {
+            None => out.atoms[at].hydrogens += 1,
+            Some((smiles, kind)) => {
+                let mut group = chem::parse_smiles(smiles).ok()?;
+                group.atoms[0].hydrogens = group.atoms[0].hydrogens.checked_sub(kind.valence())?;
+                // The cut bond counted one towards the atom's valence; the handle takes `kind`.
+                out.atoms[at].hydrogens = (out.atoms[at].hydrogens + 1).checked_sub(kind.valence())?;
+                let offset = out.atoms.len();
+                out.atoms.extend(group.atoms);
+                out.bonds.extend(group.bonds.iter().map(|b| chem::Bond { a: b.a + offset, b: b.b + offset, kind: b.kind }));
+                out.bonds.push(chem::Bond { a: at, b: offset, kind });
+            }
+        }
+    }
+    // The two sides of the cut, each as its own molecule.
+    let adj = out.neighbors();
+    let mut side = vec![None; out.atoms.len()];
+    let mut parts = Vec::new();
+    for start in [ends.0, other.0] {
+        if side[start].is_some() { return None; }
+        let mut stack = vec![start];
+        side[start] = Some(parts.len());
+        let mut atoms = Vec::new();
+        while let Some(i) = stack.pop() {
+            atoms.push(i);
+            for &(j, _) in &adj[i] { if side[j].is_none() { side[j] = Some(parts.len()); stack.push(j); } }
+        }
+        atoms.sort_unstable();
+        parts.push(atoms);
+    }
+    Some(parts.iter().map(|atoms| {
+        let index: HashMap<usize, usize> = atoms.iter().enumerate().map(|(n, &i)| (i, n)).collect();
+        let part = Molecule {
+            atoms: atoms.iter().map(|&i| out.atoms[i].clone()).collect(),
+            bonds: out.bonds.iter().filter_map(|b| Some(chem::Bond { a: *index.get(&b.a)?, b: *index.get(&b.b)?, kind: b.kind })).collect(),
+        };
+        part.to_canonical_smiles()
+    }).collect())
+}
+
+#[derive(Deserialize)]
+pub struct RetroRequest { molecule: Option<String>, molecules: Option<Vec<String>>, max_steps: Option<usize> }
+
+#[derive(Serialize)]
+pub struct RetroResult { molecule: String, #[serde(skip_serializing_if = "Option::is_none")] smiles: Option<String>, #[serde(flatten)] feasibility: Option<Feasibility>, #[serde(skip_serializing_if = "Option::is_none")] error: Option<String> }
+#[derive(Serialize)]
+pub struct RetroResponse { retro_id: String, max_steps: usize, routes_found: usize, results: Vec<RetroResult>, #[serde(skip_serializing_if = "Vec::is_empty")] warnings: Vec<String> }
+
+/// `max_steps` of a request, checked.
+pub fn max_steps(requested: Option<usize>) -> Result<usize, String> {
+    let n = requested.unwrap_or(DEFAULT_MAX_STEPS);
+    if n == 0 || n > MAX_STEPS { return Err(format!("max_steps must be between 1 and {MAX_STEPS}")); }
+    Ok(n)
+}
+
+pub async fn plan(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<RetroRequest>) -> Result<Json<RetroResponse>, ApiError> {
+    let meter = usage::Meter::start();
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let molecules = match (req.molecule, req.molecules) {
+        (Some(m), None) => vec![m],
+        (None, Some(ms)) if !ms.is_empty() && ms.len() <= MAX_MOLECULES => ms,
+        (None, Some(_)) => return Err(bad(format!("molecules must have between 1 and {MAX_MOLECULES} entries"))),
+        _ => return Err(bad("give molecule or molecules".into())),
+    };
+    let max_steps = max_steps(req.max_steps).map_err(bad)?;
+    let mut results = Vec::new();
+    let mut resolved = Vec::new();
+    for molecule in molecules {
+        match standardize::resolve(&s, &headers, &molecule).await.canonical_smiles {
+            Some(smiles) => { resolved.push(smiles.clone()); results.push(RetroResult { molecule, smiles: Some(smiles), feasibility: None, error: None }); }
+            None => results.push(RetroResult { error: Some(format!("{molecule} did not resolve to a structure")), molecule, smiles: None, feasibility: None }),
+        }
+    }
+    let (found, warnings) = s.retro.check(&resolved, max_steps).await;
+    let mut found = found.into_iter();
+    for r in results.iter_mut().filter(|r| r.smiles.is_some()) { r.feasibility = found.next(); }
+    let routes_found = results.iter().filter(|r| r.feasibility.as_ref().is_some_and(|f| f.route_found)).count();
+    let resp = RetroResponse { retro_id: uuid::Uuid::new_v4().to_string(), max_steps, routes_found, results, warnings };
+    let subject = format!("{} molecule(s)", resp.results.len());
+    record(&s, &headers, "retrosynthesis", &subject, MODEL, &resp.retro_id, &meter, &resp);
+    Ok(Json(resp))
+}
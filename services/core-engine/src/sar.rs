@@ -0,0 +1,266 @@
+//! R-group decomposition of a hit series and its SAR table.
+//!
+//! The series is given inline (molecules and their activities) or as a stored screen, whose
+//! hits carry their predicted affinities. The core is a SMILES substructure, or by default the
+//! series' most common Bemis-Murcko scaffold: ring systems and the linkers between them, with
+//! exocyclic double bonds kept. Core atoms match on element, aromaticity and charge, and a
+//! compound's atom may carry fewer hydrogens than the core's, since substituents take their
+//! place. Every heavy-atom branch off the core is an R-group at the core atom it hangs from,
+//! written as SMILES with `*` at the attachment. A symmetric core matches several ways, so each
+//! compound takes the mapping that puts its substituents on positions the earlier compounds
+//! already use. Positions are numbered R1, R2... by core atom, and each gets per-group counts
+//! and activity statistics, the best group and the spread between the best and the worst.
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use crate::{chem::{self, BondKind, Molecule}, not_found, projects, record, standardize, usage, ApiError, AppState, ErrorResponse};
+
+const MODEL: &str = "r-group-decomposition";
+const MAX_COMPOUNDS: usize = 2_000;
+/// Mappings of the core tried per compound.
+const MAX_MAPPINGS: usize = 64;
+
+#[derive(Deserialize)]
+pub struct SarCompound { id: Option<String>, molecule: String, activity: Option<f64> }
+#[derive(Deserialize)]
+pub struct SarRequest { compounds: Option<Vec<SarCompound>>, screen_id: Option<String>, core: Option<String>, activity: Option<String>, lower_is_better: Option<bool> }
+
+#[derive(Serialize)]
+pub struct GroupStats { smiles: String, count: usize, compounds: Vec<String>, #[serde(skip_serializing_if = "Option::is_none")] mean: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] median: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] min: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] max: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] delta_mean: Option<f64> }
+#[derive(Serialize)]
+pub struct Position { label: String, core_atom: usize, substituted: usize, distinct: usize, #[serde(skip_serializing_if = "Option::is_none")] best: Option<String>, #[serde(skip_serializing_if = "Option::is_none")] spread: Option<f64>, groups: Vec<GroupStats> }
+#[derive(Serialize)]
+pub struct SarTable { columns: Vec<String>, rows: Vec<Vec<Value>> }
+#[derive(Serialize)]
+pub struct SarResponse {
+    sar_id: String, core: String, core_source: &'static str, activity: String, lower_is_better: bool, compounds: usize, matched: usize, positions: Vec<Position>, table: SarTable,
+    #[serde(skip_serializing_if = "Vec::is_empty")] unmatched: Vec<String>, #[serde(skip_serializing_if = "Vec::is_empty")] warnings: Vec<String>,
+}
+
+struct Entry { id: String, smiles: String, mol: Molecule, activity: Option<f64> }
+
+fn round(v: f64) -> f64 { (v * 1000.0).round() / 1000.0 }
+
+/// Ring systems and the linkers between them: terminal chain atoms are pruned until none are
+/// left, except atoms double-bonded to a ring or linker atom. Hydrogens take the pruned bonds'
+/// place.
+fn murcko(m: &Molecule) -> Option<Molecule> {
+    let mut ring = vec![false; m.atoms.len()];
+    for (k, b) in m.bonds.iter().enumerate() { if m.bond_in_ring(k) { ring[b.a] = true; ring[b.b] = true; } }
+    if !ring.contains(&true) { return None; }
+    let mut keep = vec![true; m.atoms.len()];
+    loop {
+        let mut pruned = false;
+        for i in 0..m.atoms.len() {
+            if !keep[i] || ring[i] { continue; }
+            let live: Vec<&chem::Bond> = m.bonds.iter().filter(|b| (b.a == i || b.b == i) && keep[b.a] && keep[b.b]).collect();
+            // A double-bonded end stays on a ring atom or a linker atom, not on a chain end.
+            let exocyclic = live.len() == 1 && live[0].kind == BondKind::Double && {
+                let x = if live[0].a == i { live[0].b } else { live[0].a };
+                ring[x] || m.bonds.iter().filter(|b| (b.a == x || b.b == x) && b.a != i && b.b != i && keep[b.a] && keep[b.b]).count() >= 2
+            };
+            if live.len() <= 1 && !exocyclic { keep[i] = false; pruned = true; }
+        }
+        if !pruned { break; }
+    }
+    let mut out = Molecule::default();
+    let mut map = vec![None; m.atoms.len()];
+    for (i, a) in m.atoms.iter().enumerate().filter(|&(i, _)| keep[i]) { map[i] = Some(out.atoms.len()); out.atoms.push(a.clone()); }
+    for b in &m.bonds {
+        match (map[b.a], map[b.b]) {
+            (Some(a), Some(c)) => out.bonds.push(chem::Bond { a, b: c, kind: b.kind }),
+            (Some(a), None) | (None, Some(a)) => out.atoms[a].hydrogens += b.kind.valence(),
+            _ => {}
+        }
+    }
+    Some(out)
+}
+
+/// Every way the core maps onto the molecule, up to `MAX_MAPPINGS`: the images of core atom 0
+/// and one of its neighbours fix a mapping of a ring core, so each pair is searched in turn.
+fn mappings(m: &Molecule, core: &Molecule) -> Vec<Vec<usize>> {
+    let mut qadj = vec![Vec::new(); core.atoms.len()];
+    for (k, b) in core.bonds.iter().enumerate() { qadj[b.a].push((b.b, k)); qadj[b.b].push((b.a, k)); }
+    let base = |q: usize, i: usize| {
+        let (a, c) = (&m.atoms[i], &core.atoms[q]);
+        a.element == c.element && a.aromatic == c.aromatic && a.charge == c.charge && a.hydrogens <= c.hydrogens
+    };
+    let bond_ok = |q: usize, b: usize| m.bonds[b].kind == core.bonds[q].kind;
+    let second = qadj[0].first().map(|&(q, _)| q);
+    let madj = m.neighbors();
+    let mut pairs = Vec::new();
+    for a in (0..m.atoms.len()).filter(|&a| base(0, a)) {
+        match second {
+            Some(_) => pairs.extend(madj[a].iter().map(|&(b, _)| (a, Some(b)))),
+            None => pairs.push((a, None)),
+        }
+    }
+    let mut out: Vec<Vec<usize>> = Vec::new();
+    for (a, b) in pairs {
+        let atom_ok = |q: usize, i: usize| base(q, i) && (q != 0 || i == a) && (Some(q) != second || Some(i) == b);
+        if let Some(found) = m.find(&qadj, &atom_ok, &bond_ok) {
+            if !out.contains(&found) { out.push(found); }
+            if out.len() == MAX_MAPPINGS { break; }
+        }
+    }
+    out
+}
+
+/// The R-groups of a mapping by core atom: each branch of non-core atoms, with a `*` per bond
+/// to the core, goes to the lowest core atom it is bonded to; two branches on one atom are
+/// joined with `.`.
+fn decompose(m: &Molecule, mapping: &[usize]) -> BTreeMap<usize, String> {
+    let mut core_of = vec![None; m.atoms.len()];
+    for (q, &i) in mapping.iter().enumerate() { core_of[i] = Some(q); }
+    let madj = m.neighbors();
+    let mut seen = vec![false; m.atoms.len()];
+    let mut groups: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    for start in 0..m.atoms.len() {
+        if seen[start] || core_of[start].is_some() || !madj[start].iter().any(|&(j, _)| core_of[j].is_some()) { continue; }
+        let mut branch = vec![start];
+        seen[start] = true;
+        let mut k = 0;
+        while k < branch.len() {
+            for &(j, _) in &madj[branch[k]] { if !seen[j] && core_of[j].is_none() { seen[j] = true; branch.push(j); } }
+            k += 1;
+        }
+        let mut g = Molecule::default();
+        let mut map = HashMap::new();
+        for &i in &branch { map.insert(i, g.atoms.len()); g.atoms.push(m.atoms[i].clone()); }
+        let mut at = usize::MAX;
+        for b in &m.bonds {
+            match (map.get(&b.a), map.get(&b.b)) {
+                (Some(&x), Some(&y)) => g.bonds.push(chem::Bond { a: x, b: y, kind: b.kind }),
+                (Some(&x), None) | (None, Some(&x)) => {
+                    let q = core_of[if map.contains_key(&b.a) { b.b } else { b.a }].unwrap_or(usize::MAX);
+                    at = at.min(q);
+                    let mut dummy = m.atoms[branch[0]].clone();
+                    (dummy.element, dummy.aromatic, dummy.charge, dummy.isotope, dummy.hydrogens) = ("*".into(), false, 0, None, 0);
+                    g.atoms.push(dummy);
+                    g.bonds.push(chem::Bond { a: x, b: g.atoms.len() - 1, kind: b.kind });
+                }
+                _ => {}
+            }
+        }
+        groups.entry(at).or_default().push(g.to_canonical_smiles());
+    }
+    groups.into_iter().map(|(q, mut v)| { v.sort(); (q, v.join(".")) }).collect()
+}
+
+fn median(v: &mut [f64]) -> f64 {
+    v.sort_by(f64::total_cmp);
+    let n = v.len();
+    if n % 2 == 1 { v[n / 2] } else { (v[n / 2 - 1] + v[n / 2]) / 2.0 }
+}
+
+pub async fn decompose_series(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<SarRequest>) -> Result<Json<SarResponse>, ApiError> {
+    let meter = usage::Meter::start();
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let mut entries = Vec::new();
+    let mut warnings = Vec::new();
+    match (&req.compounds, &req.screen_id) {
+        (Some(_), Some(_)) => return Err(bad("give compounds or screen_id, not both".into())),
+        (None, None) => return Err(bad("give compounds or screen_id".into())),
+        (Some(compounds), None) => {
+            if compounds.len() > MAX_COMPOUNDS { return Err(bad(format!("at most {MAX_COMPOUNDS} compounds"))); }
+            for (k, c) in compounds.iter().enumerate() {
+                let id = c.id.clone().unwrap_or_else(|| format!("compound-{}", k + 1));
+                let Some(smiles) = standardize::resolve(&s, &headers, &c.molecule).await.canonical_smiles else { warnings.push(format!("{id}: {} did not resolve to a structure", c.molecule)); continue };
+                let mol = chem::parse_smiles(&smiles).map_err(bad)?;
+                entries.push(Entry { id, smiles, mol, activity: c.activity });
+            }
+        }
+        (None, Some(screen_id)) => {
+            let poses = s.poses.for_screen(&projects::project_id(&headers), screen_id).ok_or_else(|| not_found("screen", screen_id))?;
+            for p in poses {
+                let mol = chem::parse_smiles(&p.smiles).map_err(bad)?;
+                entries.push(Entry { id: p.compound_id, smiles: p.smiles, mol, activity: Some(p.binding_affinity_nm) });
+            }
+        }
+    }
+    if entries.len() < 2 { return Err(bad("a series needs at least two compounds".into())); }
+    let label = req.activity.clone().unwrap_or_else(|| if req.screen_id.is_some() { "binding_affinity_nm".into() } else { "activity".into() });
+    let resp = run(&req, entries, label, warnings).map_err(bad)?;
+    record(&s, &headers, "sar", &resp.core, MODEL, &resp.sar_id, &meter, &resp);
+    Ok(Json(resp))
+}
+
+fn run(req: &SarRequest, entries: Vec<Entry>, label: String, mut warnings: Vec<String>) -> Result<SarResponse, String> {
+    let lower_is_better = req.lower_is_better.unwrap_or(true);
+    let (core_smiles, core_source) = match &req.core {
+        Some(core) => (core.clone(), "given"),
+        None => {
+            let mut counts: HashMap<String, usize> = HashMap::new();
+            for e in &entries { if let Some(scaffold) = murcko(&e.mol) { *counts.entry(scaffold.to_canonical_smiles()).or_default() += 1; } }
+            let best = counts.into_iter().max_by(|a, b| a.1.cmp(&b.1).then(a.0.len().cmp(&b.0.len())).then(b.0.cmp(&a.0)));
+            (best.ok_or("no compound in the series has a ring scaffold; give the core")?.0, "murcko")
+        }
+    };
+    let core = chem::parse_smiles(&core_smiles).map_err(|e| format!("core {core_smiles}: {e}"))?;
+
+    // Decompose in series order, each compound taking the mapping whose substituted positions
+    // the earlier compounds use most; ties go to the lowest core atoms.
+    let mut used: HashMap<usize, usize> = HashMap::new();
+    let mut matched = Vec::new();
+    let mut unmatched = Vec::new();
+    for e in &entries {
+        let best = mappings(&e.mol, &core).into_iter().map(|mp| decompose(&e.mol, &mp)).max_by(|a, b| {
+            let score = |g: &BTreeMap<usize, String>| g.keys().map(|q| used.get(q).copied().unwrap_or(0)).sum::<usize>();
+            score(a).cmp(&score(b)).then_with(|| b.keys().cmp(a.keys())).then_with(|| b.values().cmp(a.values()))
+        });
+        match best {
+            Some(groups) => { for &q in groups.keys() { *used.entry(q).or_default() += 1; } matched.push((e, groups)); }
+            None => unmatched.push(e.id.clone()),
+        }
+    }
+    if matched.is_empty() { return Err(format!("the core {core_smiles} matches no compound in the series")); }
+    if !unmatched.is_empty() { warnings.push(format!("{} of {} compounds don't contain the core {core_smiles}", unmatched.len(), entries.len())); }
+
+    let sites: Vec<usize> = { let mut q: Vec<usize> = used.keys().copied().collect(); q.sort_unstable(); q };
+    let group_at = |groups: &BTreeMap<usize, String>, q: usize| groups.get(&q).cloned().unwrap_or_else(|| "[H]".into());
+    let all: Vec<f64> = matched.iter().filter_map(|(e, _)| e.activity).collect();
+    let overall = (!all.is_empty()).then(|| all.iter().sum::<f64>() / all.len() as f64);
+    if all.is_empty() { warnings.push(format!("no compound has {label}; the table has no statistics")); }
+
+    let positions = sites.iter().enumerate().map(|(k, &q)| {
+        let mut by_group: BTreeMap<String, (Vec<String>, Vec<f64>)> = BTreeMap::new();
+        for (e, groups) in &matched {
+            let slot = by_group.entry(group_at(groups, q)).or_default();
+            slot.0.push(e.id.clone());
+            slot.1.extend(e.activity);
+        }
+        let mut groups: Vec<GroupStats> = by_group.into_iter().map(|(smiles, (compounds, mut values))| {
+            let mean = (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64);
+            let stat = |f: fn(f64, f64) -> f64| values.iter().copied().reduce(f).map(round);
+            let (min, max) = (stat(f64::min), stat(f64::max));
+            let median = (!values.is_empty()).then(|| round(median(&mut values)));
+            GroupStats { smiles, count: compounds.len(), compounds, mean: mean.map(round), median, min, max, delta_mean: mean.zip(overall).map(|(m, o)| round(m - o)) }
+        }).collect();
+        groups.sort_by(|a, b| {
+            let key = |g: &GroupStats| g.mean.map(|m| if lower_is_better { m } else { -m }).unwrap_or(f64::INFINITY);
+            key(a).total_cmp(&key(b)).then(b.count.cmp(&a.count))
+        });
+        let means: Vec<f64> = groups.iter().filter_map(|g| g.mean).collect();
+        let spread = (means.len() > 1).then(|| round(means.iter().copied().fold(f64::MIN, f64::max) - means.iter().copied().fold(f64::MAX, f64::min)));
+        Position {
+            label: format!("R{}", k + 1), core_atom: q, substituted: matched.iter().filter(|(_, g)| g.contains_key(&q)).count(), distinct: groups.len(),
+            best: groups.first().filter(|g| g.mean.is_some()).map(|g| g.smiles.clone()), spread, groups,
+        }
+    }).collect();
+
+    let mut columns = vec!["id".to_string(), "smiles".into(), label.clone()];
+    columns.extend((1..=sites.len()).map(|k| format!("R{k}")));
+    let rows = matched.iter().map(|(e, groups)| {
+        let mut row = vec![Value::from(e.id.clone()), Value::from(e.smiles.clone()), e.activity.map_or(Value::Null, Value::from)];
+        row.extend(sites.iter().map(|&q| Value::from(group_at(groups, q))));
+        row
+    }).collect();
+    Ok(SarResponse {
+        sar_id: uuid::Uuid::new_v4().to_string(), core: core_smiles, core_source, activity: label, lower_is_better, compounds: entries.len(), matched: matched.len(), positions,
+        table: SarTable { columns, rows }, unmatched, warnings,
+    })
+}
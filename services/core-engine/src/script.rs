@@ -0,0 +1,239 @@
+//! Short user scripts over a job's trajectory frames or hit list.
+//!
+//! `POST /api/v1/bio/scripts/run` runs a Rhai script (the `rhai` crate) against a stored job: a
+//! simulation's observables become `frames`, one record per sampled step with each series' value
+//! by name, a screen's hits become `hits`, and the whole result is `result`; a request's own
+//! `input` is bound too. Rhai's standard library is there, with a few statistics builtins on top
+//! (`mean`, `median`, `std_dev`, `field`, ...). The value of the last statement is the script's
+//! result. Modules, `eval` and custom syntax are off, so a script has no access to files, the
+//! network or the rest of the engine, and it runs under limits the engine enforces as it goes: an
+//! operation budget, a wall-clock deadline checked on every operation, and a memory limit split
+//! between a fixed number of live variables, each string, list and record held to its share
+//! before it is stored. Each is capped by the deployment.
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope, FLOAT, INT};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{admission, not_found, projects, record, usage, ApiError, AppState, ErrorResponse};
+
+const MAX_SCRIPT_BYTES: usize = 64 << 10;
+const MAX_DEPTH: usize = 64;
+const MAX_CALL_LEVELS: usize = 32;
+const MAX_OUTPUT_LINES: usize = 1000;
+const DEFAULT_OPERATIONS: u64 = 1_000_000;
+const DEFAULT_MEMORY_MB: u64 = 64;
+/// Variables live at once; each value gets this share of the memory limit.
+const MAX_VARIABLES: usize = 64;
+
+/// The deployment's caps: operations, memory (MB) and seconds per script.
+struct Caps { operations: u64, memory_mb: u64, seconds: u64 }
+
+fn caps() -> Caps {
+    Caps {
+        operations: admission::setting("BIO_SCRIPT_MAX_OPERATIONS", 50_000_000, |&n| n > 0),
+        memory_mb: admission::setting("BIO_SCRIPT_MAX_MEMORY_MB", 256, |&n| n > 0),
+        seconds: admission::setting("BIO_SCRIPT_TIMEOUT_S", 30, |&n| n > 0),
+    }
+}
+
+struct Limits { operations: u64, memory: u64, deadline: Instant }
+
+type Fallible<T> = Result<T, Box<EvalAltResult>>;
+
+fn nums(xs: &Array) -> Fallible<Vec<f64>> {
+    xs.iter().map(|v| v.as_float().or_else(|_| v.as_int().map(|i| i as f64)).map_err(|t| format!("expected a list of numbers, found a {t}").into())).collect()
+}
+
+fn non_empty(xs: Vec<f64>, what: &str) -> Fallible<Vec<f64>> {
+    if xs.is_empty() { Err(format!("{what} of an empty list").into()) } else { Ok(xs) }
+}
+
+/// Builtins on top of Rhai's standard library.
+fn register(engine: &mut Engine) {
+    engine.register_fn("sum", |xs: Array| -> Fallible<FLOAT> { Ok(nums(&xs)?.iter().sum()) });
+    engine.register_fn("mean", |xs: Array| -> Fallible<FLOAT> { let x = non_empty(nums(&xs)?, "mean")?; Ok(x.iter().sum::<f64>() / x.len() as f64) });
+    engine.register_fn("median", |xs: Array| -> Fallible<FLOAT> {
+        let mut x = non_empty(nums(&xs)?, "median")?;
+        x.sort_by(f64::total_cmp);
+        let n = x.len();
+        Ok(if n % 2 == 1 { x[n / 2] } else { (x[n / 2 - 1] + x[n / 2]) / 2.0 })
+    });
+    engine.register_fn("std_dev", |xs: Array| -> Fallible<FLOAT> {
+        let x = non_empty(nums(&xs)?, "std_dev")?;
+        let mean = x.iter().sum::<f64>() / x.len() as f64;
+        Ok((x.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / x.len() as f64).sqrt())
+    });
+    engine.register_fn("min", |xs: Array| -> Fallible<FLOAT> { Ok(non_empty(nums(&xs)?, "min")?.into_iter().fold(f64::INFINITY, f64::min)) });
+    engine.register_fn("max", |xs: Array| -> Fallible<FLOAT> { Ok(non_empty(nums(&xs)?, "max")?.into_iter().fold(f64::NEG_INFINITY, f64::max)) });
+    engine.register_fn("round", |x: FLOAT, digits: INT| -> FLOAT { let f = 10f64.powi(digits.clamp(-15, 15) as i32); (x * f).round() / f });
+    engine.register_fn("log10", |x: FLOAT| -> FLOAT { x.log10() });
+    engine.register_fn("num", |s: &str| -> Fallible<FLOAT> { s.trim().parse().map_err(|_| format!("{s} is not a number").into()) });
+    engine.register_fn("num", |x: FLOAT| -> FLOAT { x });
+    engine.register_fn("num", |x: INT| -> FLOAT { x as f64 });
+    // A field of every record; records without it give ().
+    engine.register_fn("field", |xs: Array, name: &str| -> Array {
+        xs.iter().map(|r| r.read_lock::<Map>().and_then(|m| m.get(name).cloned()).unwrap_or(Dynamic::UNIT)).collect()
+    });
+    engine.register_fn("get", |m: Map, key: &str, default: Dynamic| -> Dynamic { m.get(key).cloned().unwrap_or(default) });
+    engine.register_fn("sort_by", |xs: Array, name: &str| -> Array {
+        let key = |r: &Dynamic| r.read_lock::<Map>().and_then(|m| m.get(name).cloned()).map(|v| v.as_float().or_else(|_| v.as_int().map(|i| i as f64)).unwrap_or(f64::NAN)).unwrap_or(f64::NAN);
+        let mut xs = xs;
+        xs.sort_by(|a, b| key(a).total_cmp(&key(b)));
+        xs
+    });
+}
+
+/// The engine for one run: limits, builtins, and `print` and `debug` collected into `output`.
+fn engine(limits: &Limits, output: Rc<RefCell<Vec<String>>>, used: Rc<Cell<u64>>) -> Engine {
+    let mut engine = Engine::new();
+    let share = (limits.memory / MAX_VARIABLES as u64).max(1) as usize;
+    engine.set_max_operations(limits.operations).set_max_variables(MAX_VARIABLES).set_max_call_levels(MAX_CALL_LEVELS).set_max_expr_depths(MAX_DEPTH, MAX_DEPTH)
+        .set_max_string_size(share).set_max_array_size((share / 16).max(1)).set_max_map_size((share / 48).max(1));
+    engine.disable_symbol("eval");
+    let deadline = limits.deadline;
+    engine.on_progress(move |ops| { used.set(ops); (Instant::now() > deadline).then(|| Dynamic::from("time")) });
+    let printed = output.clone();
+    engine.on_print(move |line| { let mut out = printed.borrow_mut(); if out.len() < MAX_OUTPUT_LINES { out.push(line.to_string()); } });
+    engine.on_debug(move |line, _, _| { let mut out = output.borrow_mut(); if out.len() < MAX_OUTPUT_LINES { out.push(line.to_string()); } });
+    register(&mut engine);
+    engine
+}
+
+/// A failed run as the client sees it, with the line at fault.
+fn describe(e: &EvalAltResult, limits: &Limits) -> String {
+    let at = e.position().line().map(|l| format!("line {l}: ")).unwrap_or_default();
+    match e.unwrap_inner() {
+        EvalAltResult::ErrorTooManyOperations(_) => format!("{at}the script went over its budget of {} operations", limits.operations),
+        EvalAltResult::ErrorTerminated(..) => format!("{at}the script ran out of time"),
+        EvalAltResult::ErrorDataTooLarge(what, _) => format!("{at}{} went over its share of the memory limit ({} MB over {MAX_VARIABLES} variables)", what.to_lowercase(), limits.memory >> 20),
+        EvalAltResult::ErrorTooManyVariables(_) => format!("{at}the script has more than {MAX_VARIABLES} variables live"),
+        EvalAltResult::ErrorStackOverflow(_) => format!("{at}calls nested deeper than {MAX_CALL_LEVELS}"),
+        inner => format!("{at}{inner}"),
+    }
+}
+
+/// A simulation's observables as one record per sampled step, each series' value by name.
+fn frames(result: &JsonValue) -> JsonValue {
+    let mut by_step: BTreeMap<u64, serde_json::Map<String, JsonValue>> = BTreeMap::new();
+    for s in result["observables"]["series"].as_array().into_iter().flatten() {
+        let name = s["name"].as_str().unwrap_or_default();
+        let steps = s["steps"].as_array().into_iter().flatten();
+        for (step, v) in steps.zip(s["values"].as_array().into_iter().flatten()) {
+            let Some(step) = step.as_u64() else { continue };
+            let frame = by_step.entry(step).or_insert_with(|| serde_json::Map::from_iter([("step".to_string(), JsonValue::from(step))]));
+            frame.insert(name.to_string(), v.clone());
+        }
+    }
+    JsonValue::Array(by_step.into_values().map(JsonValue::Object).collect())
+}
+
+/// Runs `src` with `bindings` under `limits`: the result, printed lines and operations used.
+fn run(src: &str, bindings: Vec<(String, JsonValue)>, limits: Limits) -> Result<(JsonValue, Vec<String>, u64), String> {
+    let (output, used) = (Rc::new(RefCell::new(Vec::new())), Rc::new(Cell::new(0)));
+    let engine = engine(&limits, output.clone(), used.clone());
+    let mut scope = Scope::new();
+    for (name, value) in bindings {
+        scope.push_dynamic(name, rhai::serde::to_dynamic(value).map_err(|e| e.to_string())?);
+    }
+    let ast = engine.compile_with_scope(&scope, src).map_err(|e| match e.position().line() { Some(l) => format!("line {l}: {}", e.err_type()), None => e.to_string() })?;
+    let value = engine.eval_ast_with_scope::<Dynamic>(&mut scope, &ast).map_err(|e| describe(&e, &limits))?;
+    let result = rhai::serde::from_dynamic::<JsonValue>(&value).map_err(|e| format!("the result can't be returned as JSON: {e}"))?;
+    let output = output.take();
+    Ok((result, output, used.get()))
+}
+
+#[derive(Deserialize)]
+pub struct ScriptRequest { script: String, job_id: Option<String>, input: Option<JsonValue>, max_operations: Option<u64>, max_memory_mb: Option<u64> }
+
+#[derive(Serialize)]
+pub struct ScriptResponse {
+    script_id: String, #[serde(skip_serializing_if = "Option::is_none")] job_id: Option<String>, #[serde(skip_serializing_if = "Option::is_none")] job_kind: Option<String>,
+    bound: Vec<String>, result: JsonValue, #[serde(skip_serializing_if = "Vec::is_empty")] output: Vec<String>, operations: u64, max_operations: u64, max_memory_mb: u64, elapsed_us: u128,
+}
+
+pub async fn run_script(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<ScriptRequest>) -> Result<Json<ScriptResponse>, ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let meter = usage::Meter::start();
+    let t = Instant::now();
+    if req.script.len() > MAX_SCRIPT_BYTES { return Err(bad(format!("scripts are limited to {} KiB", MAX_SCRIPT_BYTES >> 10))); }
+    let caps = caps();
+    let operations = req.max_operations.unwrap_or(DEFAULT_OPERATIONS.min(caps.operations));
+    let memory_mb = req.max_memory_mb.unwrap_or(DEFAULT_MEMORY_MB.min(caps.memory_mb));
+    if operations == 0 || operations > caps.operations { return Err(bad(format!("max_operations must be between 1 and {}", caps.operations))); }
+    if memory_mb == 0 || memory_mb > caps.memory_mb { return Err(bad(format!("max_memory_mb must be between 1 and {}", caps.memory_mb))); }
+    let job = match &req.job_id {
+        Some(id) => Some(s.jobs.get(&projects::project_id(&headers), id).ok_or_else(|| not_found("job", id))?),
+        None => None,
+    };
+    let mut bindings = Vec::new();
+    if let Some(j) = &job {
+        if j.kind == "simulate" { bindings.push(("frames".to_string(), frames(&j.result))); }
+        if let Some(hits) = j.result.get("hits") { bindings.push(("hits".to_string(), hits.clone())); }
+        bindings.push(("result".to_string(), j.result.clone()));
+    }
+    if let Some(v) = &req.input { bindings.push(("input".to_string(), v.clone())); }
+    let bound: Vec<String> = bindings.iter().map(|(n, _)| n.clone()).collect();
+    let script = req.script.clone();
+    let limits = Limits { operations, memory: memory_mb << 20, deadline: Instant::now() + Duration::from_secs(caps.seconds) };
+    // Script values are not `Send`; the script runs start to finish on a thread of its own.
+    let outcome = tokio::task::spawn_blocking(move || run(&script, bindings, limits)).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: format!("script task failed: {e}") })))?;
+    let (result, output, used) = outcome.map_err(bad)?;
+    let resp = ScriptResponse { script_id: uuid::Uuid::new_v4().to_string(), job_id: req.job_id, job_kind: job.map(|j| j.kind), bound, result, output, operations: used, max_operations: operations, max_memory_mb: memory_mb, elapsed_us: t.elapsed().as_micros() };
+    record(&s, &headers, "script", resp.job_id.as_deref().unwrap_or("input"), "script", &resp.script_id, &meter, &resp);
+    Ok(Json(resp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(operations: u64, memory_mb: u64, seconds: u64) -> Limits { Limits { operations, memory: memory_mb << 20, deadline: Instant::now() + Duration::from_secs(seconds) } }
+
+    #[test]
+    fn doubling_a_string_stops_at_the_memory_limit() {
+        let err = run(r#"let s = "xxxxxxxx"; let i = 0; while i < 60 { s = s + s; i += 1; } s.len()"#, Vec::new(), limits(DEFAULT_OPERATIONS, 64, 30)).unwrap_err();
+        assert!(err.contains("memory limit"), "{err}");
+    }
+
+    #[test]
+    fn growing_a_list_stops_at_the_memory_limit() {
+        let err = run("let xs = []; loop { xs.push(1.5); }", Vec::new(), limits(u64::MAX >> 1, 1, 30)).unwrap_err();
+        assert!(err.contains("memory limit"), "{err}");
+    }
+
+    #[test]
+    fn endless_loops_run_out_of_operations_or_time() {
+        let err = run("loop { }", Vec::new(), limits(10_000, 64, 30)).unwrap_err();
+        assert!(err.contains("budget of 10000 operations"), "{err}");
+        let err = run("loop { }", Vec::new(), limits(u64::MAX >> 1, 64, 0)).unwrap_err();
+        assert!(err.contains("ran out of time"), "{err}");
+    }
+
+    #[test]
+    fn eval_is_unavailable() {
+        assert!(run(r#"eval("1 + 1")"#, Vec::new(), limits(DEFAULT_OPERATIONS, 64, 30)).is_err());
+    }
+
+    #[test]
+    fn statistics_over_frames() {
+        let job = serde_json::json!({"observables": {"series": [{"name": "rg", "steps": [0, 10, 20], "values": [1.0, 2.0, 4.0]}]}});
+        let (result, output, used) = run(r#"let rg = field(frames, "rg"); print(rg.len()); #{ mean: mean(rg), median: median(rg), top: max(rg) }"#, vec![("frames".into(), frames(&job))], limits(DEFAULT_OPERATIONS, 64, 30)).unwrap();
+        assert_eq!(result, serde_json::json!({"mean": 7.0 / 3.0, "median": 2.0, "top": 4.0}));
+        assert_eq!(output, vec!["3"]);
+        assert!(used > 0);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let err = run("let a = 1;\nlet b = a + [];", Vec::new(), limits(DEFAULT_OPERATIONS, 64, 30)).unwrap_err();
+        assert!(err.starts_with("line 2: "), "{err}");
+    }
+}
@@ -0,0 +1,147 @@
+//! Thermal stability: melting temperature, per-residue contributions and mutation ΔΔTm.
+//!
+//! The baseline Tm comes from composition: the IVYWREL fraction tracks thermophilicity
+//! (Zeldovich et al. 2007), so the baseline rises from 70 °C at the mesophile average of
+//! 0.36 by 4 °C per extra percent. Each residue then adds a free-energy contribution from
+//! burial: buried hydrophobics stabilize, exposed ones and buried charges destabilize, salt
+//! bridges and disulfides add, Pro rigidifies loops and Gly loosens the core. Burial is the
+//! Cα contact number of a PDB structure, or estimated from local hydropathy for a bare
+//! sequence. Free energy becomes temperature through the unfolding entropy, ~4 cal/mol/K
+//! per residue, so a mutation shifts Tm by ΔΔG/ΔS with the composition baseline held fixed.
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{epitope, record, usage, vec3::dist, ApiError, AppState, ErrorResponse, FOLD_MODEL};
+
+const AMINO: &[u8] = b"ARNDCQEGHILKMFPSTWYV";
+const KYTE_DOOLITTLE: [f64; 20] = [1.8, -4.5, -3.5, -3.5, 2.5, -3.5, -3.5, -0.4, -3.2, 4.5, 3.8, -3.9, 1.9, 2.8, -1.6, -0.8, -0.7, -0.9, -1.3, 4.2];
+/// Unfolding entropy per residue, kcal/mol/K.
+const ENTROPY_PER_RESIDUE: f64 = 0.004;
+/// Mean residue contribution of a typical globular protein; the composition Tm already
+/// accounts for it.
+const REFERENCE_DG: f64 = 0.12;
+const SALT_BRIDGE_DG: f64 = 0.3;
+const DISULFIDE_DG: f64 = 1.2;
+/// |ΔTm| below this is reported as neutral.
+const NEUTRAL_TM: f64 = 0.5;
+
+#[derive(Deserialize)]
+pub struct StabilityRequest { sequence: Option<String>, pdb: Option<String>, mutations: Option<Vec<String>> }
+
+#[derive(Serialize)]
+pub struct ResidueStability { index: usize, residue: char, burial: f64, contribution_kcal: f64, contribution_tm_c: f64 }
+#[derive(Serialize)]
+pub struct MutationEffect { mutation: String, ddg_kcal: f64, delta_tm_c: f64, mutant_tm_celsius: f64, effect: &'static str }
+#[derive(Serialize)]
+pub struct StabilityResponse { stability_id: String, length: usize, structure: bool, tm_celsius: f64, composition_tm_celsius: f64, delta_g_kcal: f64, salt_bridges: usize, disulfides: usize, residues: Vec<ResidueStability>, mutations: Vec<MutationEffect> }
+
+fn kd(r: u8) -> f64 { AMINO.iter().position(|&a| a == r).map_or(0.0, |i| KYTE_DOOLITTLE[i]) }
+fn round2(v: f64) -> f64 { (v * 100.0).round() / 100.0 }
+
+/// Structural context that mutations don't change: burial and which residue pairs are close
+/// enough for salt bridges or disulfides.
+struct Context { burial: Vec<f64>, contacts: Vec<(usize, usize)>, ca: Option<Vec<[f64; 3]>> }
+
+impl Context {
+    fn new(seq: &[u8], ca: Option<Vec<[f64; 3]>>) -> Self {
+        let burial = match &ca {
+            // Surface residues have ~8 Cα within 10 Å, core residues 28 or more.
+            Some(ca) => ca.iter().map(|&a| ((ca.iter().filter(|&&b| dist(a, b) <= 10.0).count() as f64 - 9.0) / 20.0).clamp(0.0, 1.0)).collect(),
+            None => (0..seq.len()).map(|i| {
+                let (from, to) = (i.saturating_sub(4), (i + 5).min(seq.len()));
+                let mean = seq[from..to].iter().map(|&r| kd(r)).sum::<f64>() / (to - from) as f64;
+                ((mean + 1.0) / 4.0).clamp(0.0, 1.0)
+            }).collect(),
+        };
+        // Pairs in contact: within 7 Å in the structure, else i/i+3 and i/i+4 helix neighbours.
+        let contacts = match &ca {
+            Some(ca) => (0..ca.len()).flat_map(|i| (i + 2..ca.len()).map(move |j| (i, j))).filter(|&(i, j)| dist(ca[i], ca[j]) <= 7.0).collect(),
+            None => (0..seq.len()).flat_map(|i| [i + 3, i + 4].into_iter().filter(|&j| j < seq.len()).map(move |j| (i, j))).collect(),
+        };
+        Self { burial, contacts, ca }
+    }
+
+    /// Per-residue free energies (kcal/mol, positive stabilizes), salt bridges and disulfides.
+    fn contributions(&self, seq: &[u8]) -> (Vec<f64>, usize, usize) {
+        let mut dg: Vec<f64> = seq.iter().zip(&self.burial).map(|(&r, &b)| {
+            let h = kd(r) / 4.5;
+            let mut g = if h > 0.0 { b * h * 4.0 - (1.0 - b) * h * 0.3 } else { 0.0 };
+            if matches!(r, b'D' | b'E' | b'K' | b'R') { g -= b * 2.0; }
+            if r == b'P' { g += (1.0 - b) * 0.3; }
+            if r == b'G' { g -= b * 0.3; }
+            g
+        }).collect();
+        let (mut bridges, mut disulfides) = (0, 0);
+        for &(i, j) in &self.contacts {
+            let charge = |r: u8| match r { b'D' | b'E' => -1, b'K' | b'R' => 1, _ => 0 };
+            if charge(seq[i]) * charge(seq[j]) == -1 { bridges += 1; dg[i] += SALT_BRIDGE_DG / 2.0; dg[j] += SALT_BRIDGE_DG / 2.0; }
+            // Disulfides need real geometry.
+            if self.ca.as_ref().is_some_and(|ca| dist(ca[i], ca[j]) <= 6.5) && seq[i] == b'C' && seq[j] == b'C' { disulfides += 1; dg[i] += DISULFIDE_DG / 2.0; dg[j] += DISULFIDE_DG / 2.0; }
+        }
+        (dg, bridges, disulfides)
+    }
+
+}
+
+fn composition_tm(seq: &[u8]) -> f64 {
+    let ivywrel = seq.iter().filter(|r| b"IVYWREL".contains(r)).count() as f64 / seq.len() as f64;
+    (70.0 + 400.0 * (ivywrel - 0.36)).clamp(30.0, 110.0)
+}
+
+fn melting_point(composition: f64, total_dg: f64, len: usize) -> f64 {
+    (composition + (total_dg - REFERENCE_DG * len as f64) / (ENTROPY_PER_RESIDUE * len as f64)).clamp(0.0, 130.0)
+}
+
+/// Applies "A45V" style substitutions (1-based, several joined by '/' or '+').
+fn mutate(seq: &[u8], spec: &str) -> Result<Vec<u8>, String> {
+    let mut out = seq.to_vec();
+    for m in spec.split(['/', '+']).map(str::trim) {
+        let bytes = m.as_bytes();
+        let bad = || format!("bad mutation {m}; expected e.g. A45V");
+        if bytes.len() < 3 { return Err(bad()); }
+        let (wt, to) = (bytes[0].to_ascii_uppercase(), bytes[bytes.len() - 1].to_ascii_uppercase());
+        let pos: usize = m[1..m.len() - 1].parse().map_err(|_| bad())?;
+        if !AMINO.contains(&to) { return Err(bad()); }
+        match seq.get(pos.wrapping_sub(1)) {
+            Some(&r) if r == wt => out[pos - 1] = to,
+            Some(&r) => return Err(format!("mutation {m}: residue {pos} is {}, not {}", r as char, wt as char)),
+            None => return Err(format!("mutation {m}: position {pos} is outside the sequence")),
+        }
+    }
+    Ok(out)
+}
+
+pub async fn predict(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<StabilityRequest>) -> Result<Json<StabilityResponse>, ApiError> {
+    let meter = usage::Meter::start();
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let structure = req.pdb.as_deref().map(epitope::pdb_residues).unwrap_or_default();
+    if req.pdb.is_some() && structure.is_empty() { return Err(bad("pdb has no protein CA atoms".into())); }
+    let (seq, ca): (Vec<u8>, Option<Vec<[f64; 3]>>) = if structure.is_empty() {
+        (req.sequence.clone().unwrap_or_default().bytes().map(|b| b.to_ascii_uppercase()).collect(), None)
+    } else {
+        (structure.iter().map(|r| r.1).collect(), Some(structure.iter().map(|r| r.2).collect()))
+    };
+    if seq.is_empty() { return Err(bad("give sequence or pdb".into())); }
+    let context = Context::new(&seq, ca);
+    let composition = composition_tm(&seq);
+    let (dg, salt_bridges, disulfides) = context.contributions(&seq);
+    let total: f64 = dg.iter().sum();
+    let tm = melting_point(composition, total, seq.len());
+    let per_k = ENTROPY_PER_RESIDUE * seq.len() as f64;
+    let residues = seq.iter().enumerate().map(|(i, &r)| ResidueStability { index: i, residue: r as char, burial: round2(context.burial[i]), contribution_kcal: round2(dg[i]), contribution_tm_c: round2((dg[i] - REFERENCE_DG) / per_k) }).collect();
+    let mut mutations = Vec::new();
+    for spec in req.mutations.iter().flatten() {
+        let mutant = mutate(&seq, spec).map_err(bad)?;
+        let mutant_total: f64 = context.contributions(&mutant).0.iter().sum();
+        let mutant_tm = melting_point(composition, mutant_total, seq.len());
+        let delta = mutant_tm - tm;
+        let effect = if delta.abs() < NEUTRAL_TM { "neutral" } else if delta > 0.0 { "stabilizing" } else { "destabilizing" };
+        mutations.push(MutationEffect { mutation: spec.clone(), ddg_kcal: round2(mutant_total - total), delta_tm_c: round2(delta), mutant_tm_celsius: round2(mutant_tm), effect });
+    }
+    let subject = req.sequence.unwrap_or_else(|| "pdb".into());
+    let resp = StabilityResponse { stability_id: uuid::Uuid::new_v4().to_string(), length: seq.len(), structure: context.ca.is_some(), tm_celsius: round2(tm), composition_tm_celsius: round2(composition), delta_g_kcal: round2(total), salt_bridges, disulfides, residues, mutations };
+    record(&s, &headers, "stability", &subject, FOLD_MODEL, &resp.stability_id, &meter, &resp);
+    Ok(Json(resp))
+}
@@ -0,0 +1,63 @@
+//! Standardization under each project's settings, and `POST /standardize`; the steps are
+//! `bio_engine_core::standardize`.
+
+use axum::{extract::{Path, State}, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub use bio_engine_core::standardize::*;
+
+use crate::{not_found, projects, resolver::Resolved, ApiError, AppState, ErrorResponse};
+
+/// The most molecules `POST /standardize` takes at once.
+const MAX_BATCH: usize = 10_000;
+
+/// Resolves `input` and standardizes it under the settings of the caller's project.
+pub async fn resolve(s: &AppState, headers: &HeaderMap, input: &str) -> Resolved {
+    let mut r = s.resolver.resolve(input).await;
+    apply(&mut r, s.projects.standardization(&projects::project_id(headers)));
+    r
+}
+
+pub async fn configure(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(id): Path<String>, Json(settings): Json<Settings>) -> Result<Json<projects::Project>, ApiError> {
+    let project = projects::own(&headers, &id)?;
+    s.projects.resolve(&headers);
+    let p = s.projects.set_standardization(&project, settings).ok_or_else(|| not_found("project", &id))?;
+    s.audit.record(&headers, &project, "configure_standardization", &project, "", None);
+    Ok(Json(p))
+}
+
+#[derive(Deserialize)]
+pub struct StandardizeRequest { molecules: Vec<String>, settings: Option<Settings> }
+
+#[derive(Serialize)]
+pub struct Standardized {
+    input: String, #[serde(skip_serializing_if = "Option::is_none")] canonical_smiles: Option<String>, #[serde(skip_serializing_if = "Vec::is_empty")] changes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")] duplicate_of: Option<usize>, #[serde(skip_serializing_if = "Option::is_none")] error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct StandardizeResponse { settings: Settings, changed: usize, duplicates: usize, failed: usize, molecules: Vec<Standardized> }
+
+/// Standardizes a batch of molecules under the project's settings, or those given.
+pub async fn batch(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<StandardizeRequest>) -> Result<Json<StandardizeResponse>, ApiError> {
+    if req.molecules.len() > MAX_BATCH { return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: format!("at most {MAX_BATCH} molecules per request") }))); }
+    let settings = req.settings.unwrap_or_else(|| s.projects.standardization(&projects::project_id(&headers)));
+    let (mut seen, mut molecules) = (HashMap::new(), Vec::with_capacity(req.molecules.len()));
+    for (i, input) in req.molecules.into_iter().enumerate() {
+        let mut r = s.resolver.resolve(&input).await;
+        apply(&mut r, settings);
+        let mut out = Standardized { input, canonical_smiles: None, changes: r.standardization, duplicate_of: None, error: None };
+        match r.canonical_smiles {
+            None => out.error = Some(format!("{} did not resolve to a structure", out.input)),
+            Some(smiles) => {
+                if settings.deduplicate { out.duplicate_of = seen.get(&smiles).copied(); seen.entry(smiles.clone()).or_insert(i); }
+                out.canonical_smiles = Some(smiles);
+            }
+        }
+        molecules.push(out);
+    }
+    let count = |f: fn(&Standardized) -> bool| molecules.iter().filter(|m| f(m)).count();
+    Ok(Json(StandardizeResponse { settings, changed: count(|m| !m.changes.is_empty()), duplicates: count(|m| m.duplicate_of.is_some()), failed: count(|m| m.error.is_some()), molecules }))
+}
@@ -0,0 +1,337 @@
+//! Platform counters and per-endpoint latency and size histograms.
+//!
+//! The counters are atomics, so a compute call bumps them without taking a lock. Every routed
+//! request is timed by the `record` middleware and filed under its route pattern (so
+//! `/api/v1/bio/jobs/:id` is one endpoint, whatever the id). Each endpoint keeps three
+//! histograms, of latency in microseconds and of request and response body bytes, in
+//! log-linear buckets of atomic counts: eight per power of two, so a reported percentile (the
+//! middle of its bucket) is within about 6% of the true value. A request body streamed without
+//! a length (a chunked library upload, say) is timed but not sized. The endpoint table itself
+//! is behind a read-write lock that is only written the first time a route is hit.
+//!
+//! Once a minute, `keep_history` takes the difference between the counters and histograms and
+//! their values a minute before, and stores it as that minute's point: operation counts, and
+//! requests, errors and the latency buckets of each endpoint that saw traffic. Each completed
+//! hour is also rolled up into an hour point. `BIO_STATS_MINUTES` minute points (default a
+//! day's) and `BIO_STATS_HOURS` hour points (default thirty days') are kept. With
+//! `BIO_STATS_FILE` set, points are appended to that file as JSON lines and read back at
+//! startup, so the history outlives restarts; the file is rewritten with only the retained
+//! points at startup and on every roll-up.
+
+use axum::{body::HttpBody, extract::{MatchedPath, Query, Request, State}, http::StatusCode, middleware::Next, response::{Json, Response}};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::{admission, unix_now, ApiError, AppState, ErrorResponse};
+
+/// Values below this are bucketed exactly.
+const LINEAR: u64 = 16;
+/// Sub-buckets per power of two above `LINEAR`.
+const SUB_BITS: u32 = 3;
+/// Enough buckets for values up to 2⁴⁰ (twelve days in µs, a terabyte in bytes); larger ones land in the last.
+const BUCKETS: usize = 312;
+
+fn bucket(v: u64) -> usize {
+    if v < LINEAR { return v as usize; }
+    let log = 63 - v.leading_zeros();
+    (((log - SUB_BITS + 1) << SUB_BITS) as usize + ((v >> (log - SUB_BITS)) & ((1 << SUB_BITS) - 1)) as usize).min(BUCKETS - 1)
+}
+
+/// The smallest value in bucket `i`.
+fn lower(i: usize) -> u64 {
+    if i < LINEAR as usize { return i as u64; }
+    let log = (i >> SUB_BITS) as u32 + SUB_BITS - 1;
+    ((1 << SUB_BITS) + (i as u64 & ((1 << SUB_BITS) - 1))) << (log - SUB_BITS)
+}
+
+/// Non-empty buckets by index.
+type Buckets = BTreeMap<usize, u64>;
+
+/// The middle of the bucket holding the `q` quantile of `counts`, which hold `total` values.
+fn quantile(counts: &Buckets, total: u64, q: f64) -> f64 {
+    let rank = ((q * total as f64).ceil() as u64).max(1);
+    let mut seen = 0;
+    let i = counts.iter().find(|&(_, &c)| { seen += c; seen >= rank }).map_or(BUCKETS - 1, |(&i, _)| i);
+    if i < LINEAR as usize { i as f64 } else { (lower(i) + lower(i + 1) - 1) as f64 / 2.0 }
+}
+
+struct Histogram { buckets: Vec<AtomicU64>, sum: AtomicU64, max: AtomicU64 }
+
+#[derive(Serialize)]
+pub struct Summary { count: u64, mean: f64, p50: f64, p95: f64, p99: f64, max: f64 }
+
+impl Histogram {
+    fn new() -> Self { Self { buckets: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(), sum: AtomicU64::new(0), max: AtomicU64::new(0) } }
+
+    fn add(&self, v: u64) {
+        self.buckets[bucket(v)].fetch_add(1, Relaxed);
+        self.sum.fetch_add(v, Relaxed);
+        self.max.fetch_max(v, Relaxed);
+    }
+
+    fn counts(&self) -> Buckets { self.buckets.iter().enumerate().map(|(i, b)| (i, b.load(Relaxed))).filter(|&(_, c)| c > 0).collect() }
+
+    /// Count, mean, percentiles and maximum, in units of `scale`.
+    fn summary(&self, scale: f64) -> Option<Summary> {
+        let counts = self.counts();
+        let total: u64 = counts.values().sum();
+        if total == 0 { return None; }
+        let max = self.max.load(Relaxed) as f64;
+        let q = |q| round(quantile(&counts, total, q).min(max) / scale);
+        Some(Summary { count: total, mean: round(self.sum.load(Relaxed) as f64 / total as f64 / scale), p50: q(0.5), p95: q(0.95), p99: q(0.99), max: round(max / scale) })
+    }
+}
+
+fn round(v: f64) -> f64 { (v * 1000.0).round() / 1000.0 }
+
+struct Endpoint { latency: Histogram, request_bytes: Histogram, response_bytes: Histogram, errors: AtomicU64 }
+
+pub struct Stats { total_simulations: AtomicU64, total_screenings: AtomicU64, total_predictions: AtomicU64, molecules_analyzed: AtomicU64, endpoints: RwLock<HashMap<String, Arc<Endpoint>>>, history: Mutex<History> }
+
+impl Stats {
+    pub fn new(path: Option<String>) -> Self {
+        let counter = || AtomicU64::new(0);
+        Self { total_simulations: counter(), total_screenings: counter(), total_predictions: counter(), molecules_analyzed: counter(), endpoints: RwLock::new(HashMap::new()), history: Mutex::new(History::load(path)) }
+    }
+
+    pub fn simulated(&self) { self.total_simulations.fetch_add(1, Relaxed); self.analyzed(1); }
+    pub fn screened(&self, compounds: u64) { self.total_screenings.fetch_add(1, Relaxed); self.analyzed(compounds); }
+    pub fn predicted(&self, chains: u64) { self.total_predictions.fetch_add(chains, Relaxed); }
+    pub fn analyzed(&self, molecules: u64) { self.molecules_analyzed.fetch_add(molecules, Relaxed); }
+
+    /// Simulations, screenings and predictions run.
+    pub fn total_ops(&self) -> u64 { self.total_simulations.load(Relaxed) + self.total_screenings.load(Relaxed) + self.total_predictions.load(Relaxed) }
+
+    fn endpoint(&self, key: &str) -> Arc<Endpoint> {
+        if let Some(e) = self.endpoints.read().unwrap().get(key) { return e.clone(); }
+        self.endpoints.write().unwrap().entry(key.to_string()).or_insert_with(|| Arc::new(Endpoint { latency: Histogram::new(), request_bytes: Histogram::new(), response_bytes: Histogram::new(), errors: AtomicU64::new(0) })).clone()
+    }
+
+    /// The counters and every endpoint's errors, latency sum and latency buckets.
+    fn snapshot(&self) -> Snapshot {
+        let counters = [&self.total_simulations, &self.total_screenings, &self.total_predictions, &self.molecules_analyzed].map(|c| c.load(Relaxed));
+        let endpoints = self.endpoints.read().unwrap().iter().map(|(k, e)| (k.clone(), (e.errors.load(Relaxed), e.latency.sum.load(Relaxed), e.latency.counts()))).collect();
+        Snapshot { counters, endpoints }
+    }
+
+    /// Stores the minute starting at `start` as the change since the last call.
+    fn tick(&self, start: u64) {
+        let now = self.snapshot();
+        let mut history = self.history.lock().unwrap();
+        let point = Point::between(start, &history.last, &now);
+        history.last = now;
+        history.push(point);
+    }
+}
+
+#[derive(Default)]
+struct Snapshot { counters: [u64; 4], endpoints: HashMap<String, (u64, u64, Buckets)> }
+
+/// One endpoint's traffic over a point's period.
+#[derive(Serialize, Deserialize, Default, Clone)]
+struct Traffic { requests: u64, errors: u64, latency_sum_us: u64, latency: Buckets }
+
+impl Traffic {
+    fn add(&mut self, other: &Traffic) {
+        self.requests += other.requests;
+        self.errors += other.errors;
+        self.latency_sum_us += other.latency_sum_us;
+        for (&i, &c) in &other.latency { *self.latency.entry(i).or_default() += c; }
+    }
+}
+
+/// Activity over one minute or hour.
+#[derive(Serialize, Deserialize, Default, Clone)]
+struct Point { start_unix: u64, simulations: u64, screenings: u64, predictions: u64, molecules_analyzed: u64, endpoints: BTreeMap<String, Traffic> }
+
+impl Point {
+    fn between(start_unix: u64, before: &Snapshot, after: &Snapshot) -> Self {
+        let d = |k: usize| after.counters[k].saturating_sub(before.counters[k]);
+        let endpoints = after.endpoints.iter().filter_map(|(key, (errors, sum, counts))| {
+            let (e0, s0, c0) = before.endpoints.get(key).map_or((0, 0, None), |(e, s, c)| (*e, *s, Some(c)));
+            let latency: Buckets = counts.iter().map(|(&i, &c)| (i, c - c0.and_then(|c0| c0.get(&i)).copied().unwrap_or(0))).filter(|&(_, c)| c > 0).collect();
+            let requests = latency.values().sum();
+            (requests > 0).then(|| (key.clone(), Traffic { requests, errors: errors - e0, latency_sum_us: sum - s0, latency }))
+        }).collect();
+        Self { start_unix, simulations: d(0), screenings: d(1), predictions: d(2), molecules_analyzed: d(3), endpoints }
+    }
+
+    fn add(&mut self, other: &Point) {
+        self.simulations += other.simulations;
+        self.screenings += other.screenings;
+        self.predictions += other.predictions;
+        self.molecules_analyzed += other.molecules_analyzed;
+        for (k, t) in &other.endpoints { self.endpoints.entry(k.clone()).or_default().add(t); }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Resolution { Minute, Hour }
+
+impl Resolution {
+    fn seconds(self) -> u64 { match self { Self::Minute => 60, Self::Hour => 3600 } }
+}
+
+/// A point as a line of the history file.
+#[derive(Serialize, Deserialize)]
+struct Stored { resolution: Resolution, point: Point }
+
+struct History { minutes: VecDeque<Point>, hours: VecDeque<Point>, keep_minutes: usize, keep_hours: usize, last: Snapshot, path: Option<String> }
+
+impl History {
+    fn load(path: Option<String>) -> Self {
+        let keep_minutes = admission::setting("BIO_STATS_MINUTES", 1440, |&n| n > 0);
+        let keep_hours = admission::setting("BIO_STATS_HOURS", 720, |&n| n > 0);
+        let mut h = Self { minutes: VecDeque::new(), hours: VecDeque::new(), keep_minutes, keep_hours, last: Snapshot::default(), path };
+        let Some(p) = h.path.clone() else { return h };
+        match std::fs::read_to_string(&p) {
+            Ok(text) => {
+                for line in text.lines().filter(|l| !l.trim().is_empty()) {
+                    match serde_json::from_str::<Stored>(line) {
+                        Ok(s) if s.resolution == Resolution::Minute => h.minutes.push_back(s.point),
+                        Ok(s) => h.hours.push_back(s.point),
+                        Err(e) => tracing::warn!("skipping unreadable line of stats history {p}: {e}"),
+                    }
+                }
+                h.minutes.make_contiguous().sort_by_key(|m| m.start_unix);
+                h.hours.make_contiguous().sort_by_key(|m| m.start_unix);
+                tracing::info!("stats history: {} minute and {} hour points from {p}", h.minutes.len(), h.hours.len());
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("stats history {p} unreadable: {e}"),
+        }
+        h.roll_up(unix_now());
+        h.trim();
+        h.rewrite();
+        h
+    }
+
+    fn push(&mut self, point: Point) {
+        let start = point.start_unix;
+        self.append(Resolution::Minute, &point);
+        self.minutes.push_back(point);
+        if (start + 60).is_multiple_of(3600) {
+            self.roll_up(start + 60);
+            self.trim();
+            self.rewrite();
+        }
+    }
+
+    /// Adds an hour point for every hour that ended by `now`, has minute points and has none of its own.
+    fn roll_up(&mut self, now: u64) {
+        let after = self.hours.back().map(|h| h.start_unix);
+        let mut hours: BTreeMap<u64, Point> = BTreeMap::new();
+        for m in &self.minutes {
+            let hour = m.start_unix / 3600 * 3600;
+            if hour + 3600 > now || after.is_some_and(|a| hour <= a) { continue; }
+            hours.entry(hour).or_insert_with(|| Point { start_unix: hour, ..Point::default() }).add(m);
+        }
+        for (_, point) in hours { self.append(Resolution::Hour, &point); self.hours.push_back(point); }
+    }
+
+    fn trim(&mut self) {
+        while self.minutes.len() > self.keep_minutes { self.minutes.pop_front(); }
+        while self.hours.len() > self.keep_hours { self.hours.pop_front(); }
+    }
+
+    fn line(resolution: Resolution, point: &Point) -> Option<String> {
+        serde_json::to_string(&Stored { resolution, point: point.clone() }).ok()
+    }
+
+    fn append(&self, resolution: Resolution, point: &Point) {
+        let (Some(p), Some(line)) = (&self.path, Self::line(resolution, point)) else { return };
+        let written = std::fs::OpenOptions::new().create(true).append(true).open(p).and_then(|mut f| writeln!(f, "{line}"));
+        if let Err(e) = written { tracing::warn!("stats history write to {p} failed: {e}"); }
+    }
+
+    /// Replaces the file with the retained points.
+    fn rewrite(&self) {
+        let Some(p) = &self.path else { return };
+        let mut text = String::new();
+        for (resolution, points) in [(Resolution::Hour, &self.hours), (Resolution::Minute, &self.minutes)] {
+            for line in points.iter().filter_map(|point| Self::line(resolution, point)) { text.push_str(&line); text.push('\n'); }
+        }
+        let tmp = format!("{p}.tmp");
+        if let Err(e) = std::fs::write(&tmp, text).and_then(|_| std::fs::rename(&tmp, p)) { tracing::warn!("stats history rewrite of {p} failed: {e}"); }
+    }
+}
+
+/// Stores a point at the end of every minute, for as long as the server runs.
+pub async fn keep_history(s: Arc<AppState>) {
+    loop {
+        let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+        let next = now_ms / 60_000 * 60_000 + 60_000;
+        tokio::time::sleep(Duration::from_millis(next - now_ms)).await;
+        s.stats.tick(next / 1000 - 60);
+    }
+}
+
+/// Times a routed request and files it under `METHOD /route`.
+pub async fn record(State(s): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let Some(path) = req.extensions().get::<MatchedPath>() else { return next.run(req).await };
+    let endpoint = s.stats.endpoint(&format!("{} {}", req.method(), path.as_str()));
+    let request_bytes = req.body().size_hint().exact();
+    let start = Instant::now();
+    let resp = next.run(req).await;
+    endpoint.latency.add(start.elapsed().as_micros() as u64);
+    if let Some(n) = request_bytes { endpoint.request_bytes.add(n); }
+    if let Some(n) = resp.body().size_hint().exact() { endpoint.response_bytes.add(n); }
+    if resp.status().is_client_error() || resp.status().is_server_error() { endpoint.errors.fetch_add(1, Relaxed); }
+    resp
+}
+
+#[derive(Serialize)]
+pub struct EndpointStats {
+    requests: u64, errors: u64, latency_ms: Summary,
+    #[serde(skip_serializing_if = "Option::is_none")] request_bytes: Option<Summary>, #[serde(skip_serializing_if = "Option::is_none")] response_bytes: Option<Summary>,
+}
+
+#[derive(Serialize)]
+pub struct StatsResponse { total_simulations: u64, total_screenings: u64, total_predictions: u64, molecules_analyzed: u64, uptime_secs: u64, endpoints: BTreeMap<String, EndpointStats> }
+
+pub async fn report(State(s): State<Arc<AppState>>) -> Json<StatsResponse> {
+    let st = &s.stats;
+    let endpoints: Vec<(String, Arc<Endpoint>)> = st.endpoints.read().unwrap().iter().map(|(k, e)| (k.clone(), e.clone())).collect();
+    let endpoints = endpoints.into_iter().filter_map(|(key, e)| {
+        let latency_ms = e.latency.summary(1000.0)?;
+        Some((key, EndpointStats { requests: latency_ms.count, errors: e.errors.load(Relaxed), latency_ms, request_bytes: e.request_bytes.summary(1.0), response_bytes: e.response_bytes.summary(1.0) }))
+    }).collect();
+    Json(StatsResponse { total_simulations: st.total_simulations.load(Relaxed), total_screenings: st.total_screenings.load(Relaxed), total_predictions: st.total_predictions.load(Relaxed), molecules_analyzed: st.molecules_analyzed.load(Relaxed), uptime_secs: s.start_time.elapsed().as_secs(), endpoints })
+}
+
+#[derive(Deserialize)]
+pub struct HistoryQuery { from: Option<u64>, to: Option<u64>, resolution: Option<Resolution>, endpoint: Option<String> }
+
+#[derive(Serialize)]
+pub struct Latency { mean: f64, p50: f64, p95: f64, p99: f64 }
+
+#[derive(Serialize)]
+pub struct HistoryPoint { start_unix: u64, simulations: u64, screenings: u64, predictions: u64, molecules_analyzed: u64, requests: u64, errors: u64, #[serde(skip_serializing_if = "Option::is_none")] latency_ms: Option<Latency> }
+
+#[derive(Serialize)]
+pub struct HistoryResponse { resolution: Resolution, from_unix: u64, to_unix: u64, #[serde(skip_serializing_if = "Option::is_none")] endpoint: Option<String>, retained: usize, points: Vec<HistoryPoint> }
+
+/// Points starting in `[from, to)`; requests, errors and latency of one endpoint (`METHOD /route`) or all.
+pub async fn history(State(s): State<Arc<AppState>>, Query(q): Query<HistoryQuery>) -> Result<Json<HistoryResponse>, ApiError> {
+    let resolution = q.resolution.unwrap_or(Resolution::Minute);
+    let to = q.to.unwrap_or_else(unix_now);
+    let from = q.from.unwrap_or_else(|| to.saturating_sub(60 * resolution.seconds()));
+    if from > to { return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: "from must not be after to".into() }))); }
+    let h = s.stats.history.lock().unwrap();
+    let series = match resolution { Resolution::Minute => &h.minutes, Resolution::Hour => &h.hours };
+    let points = series.iter().filter(|p| (from..to).contains(&p.start_unix)).map(|p| {
+        let mut traffic = Traffic::default();
+        for (k, t) in &p.endpoints { if q.endpoint.as_ref().is_none_or(|e| e == k) { traffic.add(t); } }
+        let latency_ms = (traffic.requests > 0).then(|| {
+            let q = |q| round(quantile(&traffic.latency, traffic.requests, q) / 1000.0);
+            Latency { mean: round(traffic.latency_sum_us as f64 / traffic.requests as f64 / 1000.0), p50: q(0.5), p95: q(0.95), p99: q(0.99) }
+        });
+        HistoryPoint { start_unix: p.start_unix, simulations: p.simulations, screenings: p.screenings, predictions: p.predictions, molecules_analyzed: p.molecules_analyzed, requests: traffic.requests, errors: traffic.errors, latency_ms }
+    }).collect();
+    Ok(Json(HistoryResponse { resolution, from_unix: from, to_unix: to, endpoint: q.endpoint, retained: series.len(), points }))
+}
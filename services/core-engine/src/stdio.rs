@@ -0,0 +1,125 @@
+//! JSON-RPC over stdio, for embedding the engine as a subprocess.
+//!
+//! `bio-engine --stdio` serves the same router as the HTTP server, middleware included, to
+//! JSON-RPC 2.0 messages read one per line from stdin, answering one per line on stdout (logs go
+//! to stderr). A method is an HTTP method and path, `"POST /api/v1/bio/energy"` or `"GET
+//! /api/v1/bio/jobs?kind=screen"`, and `params` is the request body. Headers such as
+//! `x-project-id` or `accept` are set for the rest of the session with `session.set_headers`.
+//! A JSON response body is the `result` and any other body its text; an error status becomes a
+//! JSON-RPC error with the body's message, and the status and body in `data`. Requests run
+//! concurrently, so answers may come out of order; batches and notifications are supported.
+//! The session ends at end of input, once every request has been answered.
+
+use axum::{body::Body, http::{HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode}, Router};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tower::ServiceExt;
+
+/// The largest response body read back, as for any HTTP client.
+const MAX_BODY: usize = 256 << 20;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// An HTTP error status from the handler.
+const SERVER_ERROR: i64 = -32000;
+
+fn error(id: Value, code: i64, message: impl Into<String>, data: Option<Value>) -> Value {
+    let mut e = json!({ "code": code, "message": message.into() });
+    if let Some(d) = data { e["data"] = d; }
+    json!({ "jsonrpc": "2.0", "id": id, "error": e })
+}
+
+/// Answers one request; `None` for a notification.
+async fn handle(app: Router, headers: Arc<Mutex<HeaderMap>>, msg: Value) -> Option<Value> {
+    let notification = msg.get("id").is_none();
+    let id = msg.get("id").cloned().unwrap_or(Value::Null);
+    let reply = |v: Value| (!notification).then_some(v);
+    let (Some("2.0"), Some(method)) = (msg.get("jsonrpc").and_then(Value::as_str), msg.get("method").and_then(Value::as_str)) else {
+        return Some(error(id, INVALID_REQUEST, "expected a JSON-RPC 2.0 request with a method", None));
+    };
+    let params = msg.get("params").cloned();
+    if method == "session.set_headers" {
+        let Some(Value::Object(map)) = params else { return reply(error(id, INVALID_PARAMS, "session.set_headers takes an object of header names and values", None)) };
+        let mut parsed = Vec::new();
+        for (name, value) in map {
+            let value = match value { Value::String(s) => s, Value::Null => String::new(), other => other.to_string() };
+            match (HeaderName::try_from(name.as_str()), HeaderValue::try_from(value.as_str())) {
+                (Ok(n), Ok(v)) => parsed.push((n, v, value.is_empty())),
+                _ => return reply(error(id, INVALID_PARAMS, format!("invalid header {name}"), None)),
+            }
+        }
+        let mut h = headers.lock().unwrap();
+        for (n, v, clear) in parsed { if clear { h.remove(&n); } else { h.insert(n, v); } }
+        return reply(json!({ "jsonrpc": "2.0", "id": id, "result": h.iter().map(|(n, v)| (n.to_string(), Value::String(v.to_str().unwrap_or_default().into()))).collect::<serde_json::Map<_, _>>() }));
+    }
+    let Some((verb, path)) = method.split_once(' ').and_then(|(v, p)| Some((Method::from_bytes(v.as_bytes()).ok()?, p.trim()))).filter(|(_, p)| p.starts_with('/')) else {
+        return reply(error(id, METHOD_NOT_FOUND, format!("unknown method {method}; expected an HTTP method and path such as \"POST /api/v1/bio/energy\", or session.set_headers"), None));
+    };
+    let mut req = Request::builder().method(verb).uri(path);
+    for (n, v) in headers.lock().unwrap().iter() { req = req.header(n, v); }
+    let body = match params {
+        Some(p) => { req = req.header("content-type", "application/json"); Body::from(p.to_string()) }
+        None => Body::empty(),
+    };
+    let req = match req.body(body) { Ok(r) => r, Err(e) => return reply(error(id, INVALID_REQUEST, format!("invalid request: {e}"), None)) };
+    let resp = match app.oneshot(req).await { Ok(r) => r, Err(e) => match e {} };
+    let status = resp.status();
+    let bytes = match axum::body::to_bytes(resp.into_body(), MAX_BODY).await { Ok(b) => b, Err(e) => return reply(error(id, SERVER_ERROR, format!("reading the response: {e}"), None)) };
+    let body = serde_json::from_slice::<Value>(&bytes).unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()));
+    if status.is_success() { return reply(json!({ "jsonrpc": "2.0", "id": id, "result": body })); }
+    if status == StatusCode::NOT_FOUND && bytes.is_empty() { return reply(error(id, METHOD_NOT_FOUND, format!("no route for {method}"), None)); }
+    let message = body.get("error").and_then(Value::as_str).map_or_else(|| status.canonical_reason().unwrap_or("error").to_string(), String::from);
+    reply(error(id, SERVER_ERROR, message, Some(json!({ "status": status.as_u16(), "body": body }))))
+}
+
+/// Serves `app` over stdin and stdout until end of input.
+pub async fn run(app: Router) {
+    let headers = Arc::new(Mutex::new(HeaderMap::new()));
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Value>();
+    let writer = tokio::spawn(async move {
+        let mut out = tokio::io::stdout();
+        while let Some(v) = rx.recv().await {
+            let mut line = v.to_string();
+            line.push('\n');
+            if out.write_all(line.as_bytes()).await.is_err() || out.flush().await.is_err() { break; }
+        }
+    });
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut running = tokio::task::JoinSet::new();
+    tracing::info!("Bio Engine on stdio");
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => { tracing::warn!("stdin: {e}"); break; }
+        };
+        if line.trim().is_empty() { continue; }
+        let parsed = serde_json::from_str::<Value>(&line);
+        // Headers apply to the requests after them, so they're set before reading on.
+        if let Some(msg) = parsed.as_ref().ok().filter(|m| m.get("method").and_then(Value::as_str) == Some("session.set_headers")) {
+            if let Some(a) = handle(app.clone(), headers.clone(), msg.clone()).await { let _ = tx.send(a); }
+            continue;
+        }
+        let (app, headers, tx) = (app.clone(), headers.clone(), tx.clone());
+        running.spawn(async move {
+            let answer = match parsed {
+                Err(e) => Some(error(Value::Null, PARSE_ERROR, format!("invalid JSON: {e}"), None)),
+                Ok(Value::Array(batch)) if batch.is_empty() => Some(error(Value::Null, INVALID_REQUEST, "empty batch", None)),
+                Ok(Value::Array(batch)) => {
+                    // A batch runs in order, so a `session.set_headers` in it applies to the requests after it.
+                    let mut answers = Vec::new();
+                    for msg in batch { answers.extend(handle(app.clone(), headers.clone(), msg).await); }
+                    (!answers.is_empty()).then_some(Value::Array(answers))
+                }
+                Ok(msg) => handle(app, headers, msg).await,
+            };
+            if let Some(a) = answer { let _ = tx.send(a); }
+        });
+    }
+    while running.join_next().await.is_some() {}
+    drop(tx);
+    let _ = writer.await;
+}
@@ -0,0 +1,29 @@
+//! `GET /screens/{id}/strain`; the strain energy is `bio_engine_core::strain`.
+
+use axum::{extract::{Path, Query, State}, http::HeaderMap, response::Json};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+pub use bio_engine_core::strain::*;
+
+use crate::{chem, not_found, projects, ApiError, AppState};
+
+const DEFAULT_THRESHOLD_KCAL: f64 = 6.0;
+
+#[derive(Deserialize)]
+pub struct StrainQuery { threshold_kcal: Option<f64> }
+#[derive(Serialize)]
+pub struct HitStrain { compound_id: String, #[serde(flatten)] strain: Strain, flagged: bool }
+#[derive(Serialize)]
+pub struct StrainReport { screen_id: String, threshold_kcal: f64, flagged_count: usize, hits: Vec<HitStrain> }
+
+pub async fn report(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(screen_id): Path<String>, Query(q): Query<StrainQuery>) -> Result<Json<StrainReport>, ApiError> {
+    let poses = s.poses.for_screen(&projects::project_id(&headers), &screen_id).ok_or_else(|| not_found("screen", &screen_id))?;
+    let threshold = q.threshold_kcal.unwrap_or(DEFAULT_THRESHOLD_KCAL);
+    let hits: Vec<HitStrain> = poses.iter().filter_map(|p| {
+        let mol = chem::parse_smiles(&p.smiles).ok()?;
+        let strain = strain(&mol, &p.coords);
+        Some(HitStrain { compound_id: p.compound_id.clone(), flagged: strain.strain_kcal_mol > threshold, strain })
+    }).collect();
+    Ok(Json(StrainReport { screen_id, threshold_kcal: threshold, flagged_count: hits.iter().filter(|h| h.flagged).count(), hits }))
+}
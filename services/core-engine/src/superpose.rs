@@ -0,0 +1,424 @@
+//! `POST /superpose`: superposition of one protein structure (`mobile`) onto another
+//! (`reference`), each uploaded as PDB text or fetched by PDB ID from `BIO_PDB_URL`, on the Cα
+//! atoms of one chain each (`reference_chain` and `mobile_chain`, by default the first).
+//!
+//! `method` `structure` (default) is sequence-independent, after TM-align: seed alignments from
+//! gapless threading, secondary structure and sequence are each refined by alternating a
+//! TM-score-maximising superposition with dynamic programming on the superposed distances, and
+//! the alignment with the highest TM-score wins. Its `aligned` residues are the pairs within 5 Å,
+//! and the RMSD is of their least-squares fit. `method` `sequence` pairs residues by their
+//! BLOSUM62 sequence alignment (see `homology`) and fits all pairs by least squares. Either way
+//! the TM-score is reported normalised by each structure's length, the rotation and translation
+//! take the mobile structure onto the reference, and `pdb` is the whole mobile file moved by them.
+//!
+//! `POST /superpose/cluster` compares up to 100 structures all against all the same way and
+//! clusters them by TM-score (normalised by the longer chain), optionally also by RMSD, with
+//! Butina's algorithm (`cluster::butina_by`). Each cluster's medoid, the member with the highest
+//! summed TM-score to the others, is its representative, e.g. a receptor conformation for
+//! ensemble docking; the representatives come back superposed onto the largest cluster's.
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use bio_engine_core::{cluster, forcefield::{self, Fit}, pdbqt};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{homology, loops, record, usage, vec3::dist, ApiError, AppState, ErrorResponse};
+
+const MODEL: &str = "tm-superpose/0.1";
+const MIN_RESIDUES: usize = 5;
+const MAX_RESIDUES: usize = 2_000;
+const MAX_CLUSTERED: usize = 100;
+/// Pairs closer than this after superposition count as structurally aligned.
+const ALIGNED_CUTOFF: f64 = 5.0;
+/// Rounds of superposition and re-alignment per seed, and of pair selection per fragment.
+const REFINE_ROUNDS: usize = 20;
+const SEARCH_ROUNDS: usize = 20;
+/// TM-score above which two structures generally share a fold, and below which the similarity is
+/// what unrelated structures reach.
+const SAME_FOLD: f64 = 0.5;
+const RANDOM: f64 = 0.3;
+
+#[derive(Deserialize)]
+pub struct SuperposeRequest {
+    reference: Option<String>, reference_id: Option<String>, reference_chain: Option<String>,
+    mobile: Option<String>, mobile_id: Option<String>, mobile_chain: Option<String>, method: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Structure { name: String, chain: String, residues: usize }
+/// Gapped sequences with a marker line: `:` under pairs within 5 Å, `.` under farther ones.
+#[derive(Serialize)]
+pub struct Alignment { reference: String, mobile: String, markers: String }
+
+#[derive(Serialize)]
+pub struct SuperposeResponse {
+    superposition_id: String, method: &'static str, reference: Structure, mobile: Structure, aligned: usize, rmsd_angstrom: f64,
+    tm_score: f64, tm_score_mobile: f64, sequence_identity: f64, rotation: [[f64; 3]; 3], translation: [f64; 3], alignment: Alignment,
+    #[serde(skip_serializing_if = "Vec::is_empty")] warnings: Vec<String>, pdb: String,
+}
+
+fn round(v: f64) -> f64 { (v * 1000.0).round() / 1000.0 }
+
+/// A chain's Cα trace: its one-letter sequence and coordinates.
+struct Trace { chain: char, sequence: Vec<u8>, ca: Vec<[f64; 3]> }
+
+/// The Cα trace of `chain`, or of the first chain with enough residues.
+fn trace(text: &str, chain: Option<&str>) -> Result<Trace, String> {
+    let mut traces: Vec<Trace> = Vec::new();
+    for r in pdbqt::parse_residues(text, false) {
+        let Some(ca) = r.atoms.iter().find(|(name, element, _)| name.trim() == "CA" && element == "C").map(|a| a.2) else { continue };
+        if traces.last().is_none_or(|t| t.chain != r.chain) { traces.push(Trace { chain: r.chain, sequence: Vec::new(), ca: Vec::new() }); }
+        let t = traces.last_mut().unwrap();
+        t.sequence.push(loops::one_letter(&r.name) as u8);
+        t.ca.push(ca);
+    }
+    let found = match chain.map(str::trim) {
+        Some(c) => traces.into_iter().find(|t| t.chain.to_string().trim() == c).ok_or_else(|| format!("chain {c} has no Cα atoms"))?,
+        None => traces.into_iter().find(|t| t.ca.len() >= MIN_RESIDUES).ok_or("the structure has no chain of Cα atoms")?,
+    };
+    if found.ca.len() < MIN_RESIDUES { return Err(format!("chain {} has {} residues; at least {MIN_RESIDUES} are needed", found.chain, found.ca.len())); }
+    if found.ca.len() > MAX_RESIDUES { return Err(format!("chain {} has {} residues; at most {MAX_RESIDUES} are superposed", found.chain, found.ca.len())); }
+    Ok(found)
+}
+
+/// The TM-score distance scale for a structure of `length` residues.
+fn d0(length: usize) -> f64 { if length > 21 { (1.24 * (length as f64 - 15.0).cbrt() - 1.8).max(0.5) } else { 0.5 } }
+
+fn fit(x: &[[f64; 3]], y: &[[f64; 3]], pairs: &[(usize, usize)]) -> Fit {
+    let (a, b): (Vec<_>, Vec<_>) = pairs.iter().map(|&(i, j)| (x[i], y[j])).unzip();
+    forcefield::superpose(&a, &b)
+}
+
+/// The TM-score of `pairs` under `f`, normalised by `length`.
+fn tm(x: &[[f64; 3]], y: &[[f64; 3]], pairs: &[(usize, usize)], f: &Fit, length: usize) -> f64 {
+    let d0 = d0(length);
+    pairs.iter().map(|&(i, j)| 1.0 / (1.0 + (dist(x[i], f.apply(y[j])) / d0).powi(2))).sum::<f64>() / length as f64
+}
+
+/// The superposition maximising the TM-score of `pairs`, as in TM-score: fragments of the
+/// alignment are fitted and the fit repeatedly redone on the pairs it brings close.
+fn tm_search(x: &[[f64; 3]], y: &[[f64; 3]], pairs: &[(usize, usize)], length: usize) -> (f64, Fit) {
+    let n = pairs.len();
+    let mut best = (-1.0, fit(x, y, pairs));
+    if n < 3 { return (tm(x, y, pairs, &best.1, length), best.1); }
+    let cutoff = d0(length).clamp(4.5, 8.0);
+    let mut size = n;
+    loop {
+        let step = (size / 2).max(1);
+        let mut start = 0;
+        while start + size <= n {
+            let mut f = fit(x, y, &pairs[start..start + size]);
+            let mut last: Vec<(usize, usize)> = Vec::new();
+            for _ in 0..SEARCH_ROUNDS {
+                let score = tm(x, y, pairs, &f, length);
+                if score > best.0 { best = (score, f); }
+                let mut d = cutoff;
+                let close = loop {
+                    let close: Vec<_> = pairs.iter().copied().filter(|&(i, j)| dist(x[i], f.apply(y[j])) < d).collect();
+                    if close.len() >= 3 || close.len() == n { break close; }
+                    d += 0.5;
+                };
+                if close == last { break; }
+                f = fit(x, y, &close);
+                last = close;
+            }
+            start += step;
+        }
+        if size <= 4 { break; }
+        size = (size / 2).max(4);
+    }
+    best
+}
+
+/// Global alignment maximising `score` with end gaps free and `gap` for opening any other, as
+/// TM-align's; the aligned pairs in order.
+fn dp(n: usize, m: usize, score: impl Fn(usize, usize) -> f64, gap: f64) -> Vec<(usize, usize)> {
+    let mut val = vec![0.0; (n + 1) * (m + 1)];
+    let mut diag = vec![false; (n + 1) * (m + 1)];
+    let at = |i: usize, j: usize| i * (m + 1) + j;
+    for i in 1..=n {
+        for j in 1..=m {
+            let d = val[at(i - 1, j - 1)] + score(i - 1, j - 1);
+            let up = val[at(i - 1, j)] + if diag[at(i - 1, j)] { gap } else { 0.0 };
+            let left = val[at(i, j - 1)] + if diag[at(i, j - 1)] { gap } else { 0.0 };
+            if d >= up && d >= left { val[at(i, j)] = d; diag[at(i, j)] = true; } else { val[at(i, j)] = up.max(left); }
+        }
+    }
+    let (mut i, mut j, mut pairs) = (n, m, Vec::new());
+    while i > 0 && j > 0 {
+        if diag[at(i, j)] { pairs.push((i - 1, j - 1)); i -= 1; j -= 1; continue; }
+        let up = val[at(i - 1, j)] + if diag[at(i - 1, j)] { gap } else { 0.0 };
+        if (val[at(i, j)] - up).abs() < 1e-9 { i -= 1; } else { j -= 1; }
+    }
+    pairs.reverse();
+    pairs
+}
+
+/// Secondary structure from Cα distances, as TM-align assigns it: `H` helix, `E` strand, `C` coil.
+fn secondary(ca: &[[f64; 3]]) -> Vec<u8> {
+    (0..ca.len()).map(|i| {
+        if i < 2 || i + 2 >= ca.len() { return b'C'; }
+        let d = [dist(ca[i - 2], ca[i]), dist(ca[i - 2], ca[i + 1]), dist(ca[i - 2], ca[i + 2]), dist(ca[i - 1], ca[i + 1]), dist(ca[i - 1], ca[i + 2]), dist(ca[i], ca[i + 2])];
+        let like = |ideal: [f64; 6], tolerance: f64| d.iter().zip(ideal).all(|(d, e)| (d - e).abs() < tolerance);
+        if like([5.45, 5.18, 6.37, 5.45, 5.18, 5.45], 2.1) { b'H' } else if like([6.1, 10.4, 13.0, 6.1, 10.4, 6.1], 1.42) { b'E' } else { b'C' }
+    }).collect()
+}
+
+/// Pairs of the sequence alignment.
+fn by_sequence(x: &Trace, y: &Trace) -> Vec<(usize, usize)> {
+    homology::align(&x.sequence, &y.sequence).0.into_iter().filter_map(|c| match c { (Some(i), Some(j)) => Some((i, j)), _ => None }).collect()
+}
+
+/// The best ungapped threading of `y` along `x`, by TM-score after a quick fit.
+fn threading(x: &[[f64; 3]], y: &[[f64; 3]]) -> Vec<(usize, usize)> {
+    let (n, m) = (x.len() as i64, y.len() as i64);
+    let min_overlap = (n.min(m) / 2).max(3);
+    let mut best = (-1.0, Vec::new());
+    for shift in -(m - 1)..n {
+        let pairs: Vec<(usize, usize)> = (shift.max(0)..n.min(m + shift)).map(|i| (i as usize, (i - shift) as usize)).collect();
+        if (pairs.len() as i64) < min_overlap { continue; }
+        let mut f = fit(x, y, &pairs);
+        for _ in 0..2 {
+            let close: Vec<_> = pairs.iter().copied().filter(|&(i, j)| dist(x[i], f.apply(y[j])) < 5.0).collect();
+            if close.len() < 3 { break; }
+            f = fit(x, y, &close);
+        }
+        let score = tm(x, y, &pairs, &f, x.len());
+        if score > best.0 { best = (score, pairs); }
+    }
+    best.1
+}
+
+/// The TM-align-style alignment: the best of each seed refined by alternating superposition and
+/// alignment on the superposed distances.
+fn structural(x: &Trace, y: &Trace) -> Vec<(usize, usize)> {
+    let (ss_x, ss_y) = (secondary(&x.ca), secondary(&y.ca));
+    let seeds = [threading(&x.ca, &y.ca), dp(x.ca.len(), y.ca.len(), |i, j| if ss_x[i] == ss_y[j] { 1.0 } else { 0.0 }, -1.0), by_sequence(x, y)];
+    let (length, d0) = (x.ca.len(), d0(x.ca.len()));
+    let mut best = (-1.0, Vec::new());
+    for seed in seeds {
+        if seed.len() < 3 { continue; }
+        let (mut score, mut f) = tm_search(&x.ca, &y.ca, &seed, length);
+        if score > best.0 { best = (score, seed); }
+        for _ in 0..REFINE_ROUNDS {
+            let moved: Vec<[f64; 3]> = y.ca.iter().map(|p| f.apply(*p)).collect();
+            let mut improved = false;
+            for gap in [-0.6, 0.0] {
+                let pairs = dp(x.ca.len(), y.ca.len(), |i, j| 1.0 / (1.0 + (dist(x.ca[i], moved[j]) / d0).powi(2)), gap);
+                if pairs.len() < 3 { continue; }
+                let (s, g) = tm_search(&x.ca, &y.ca, &pairs, length);
+                if s > score + 1e-6 { (score, f, improved) = (s, g, true); }
+                if s > best.0 { best = (s, pairs); }
+            }
+            if !improved { break; }
+        }
+    }
+    best.1
+}
+
+/// The mobile PDB file with every atom moved by `f`.
+fn moved(text: &str, f: &Fit) -> String {
+    text.lines().map(|l| {
+        if !(l.starts_with("ATOM") || l.starts_with("HETATM")) { return l.to_string(); }
+        let c = |a: usize, b: usize| l.get(a..b).and_then(|v| v.trim().parse::<f64>().ok());
+        let (Some(x), Some(y), Some(z)) = (c(30, 38), c(38, 46), c(46, 54)) else { return l.to_string() };
+        let p = f.apply([x, y, z]);
+        format!("{}{:>8.3}{:>8.3}{:>8.3}{}", &l[..30], p[0], p[1], p[2], l.get(54..).unwrap_or(""))
+    }).collect::<Vec<_>>().join("\n") + "\n"
+}
+
+async fn structure(s: &AppState, text: Option<String>, id: Option<&str>, which: &str) -> Result<(String, String), String> {
+    match (text, id) {
+        (Some(text), _) => Ok((text, "uploaded".into())),
+        (None, Some(id)) => {
+            let id = homology::pdb_id(id)?;
+            let text = s.templates.fetch(&id).await.ok_or_else(|| format!("{id} could not be fetched from the PDB; upload it as {which}"))?;
+            Ok((text, id))
+        }
+        (None, None) => Err(format!("the {which} structure is needed, as PDB text ({which}) or a PDB ID ({which}_id)")),
+    }
+}
+
+/// One chain compared with another: the alignment, the superposition of `y` onto `x` and their
+/// scores, over the pairs within 5 Å for `structure` and all pairs for `sequence`.
+struct Comparison { pairs: Vec<(usize, usize)>, close: Vec<bool>, fit: Fit, aligned: usize, rmsd: f64, tm_score: f64, tm_score_mobile: f64, identity: f64 }
+
+fn compare(x: &Trace, y: &Trace, method: &str) -> Result<Comparison, String> {
+    let pairs = if method == "structure" { structural(x, y) } else { by_sequence(x, y) };
+    if pairs.len() < 3 {
+        let hint = if method == "sequence" { "; the sequences don't align, try method structure" } else { "" };
+        return Err(format!("fewer than three residues could be paired{hint}"));
+    }
+    let f = if method == "structure" { tm_search(&x.ca, &y.ca, &pairs, x.ca.len()).1 } else { fit(&x.ca, &y.ca, &pairs) };
+    let close: Vec<bool> = pairs.iter().map(|&(i, j)| dist(x.ca[i], f.apply(y.ca[j])) < ALIGNED_CUTOFF).collect();
+    let counted: Vec<(usize, usize)> = if method == "structure" { pairs.iter().zip(&close).filter(|(_, c)| **c).map(|(p, _)| *p).collect() } else { pairs.clone() };
+    let identical = counted.iter().filter(|&&(i, j)| x.sequence[i] == y.sequence[j] && x.sequence[i] != b'X').count();
+    Ok(Comparison {
+        rmsd: if counted.is_empty() { 0.0 } else { fit(&x.ca, &y.ca, &counted).rmsd }, aligned: counted.len(),
+        tm_score: tm_search(&x.ca, &y.ca, &pairs, x.ca.len()).0, tm_score_mobile: tm_search(&x.ca, &y.ca, &pairs, y.ca.len()).0,
+        identity: if counted.is_empty() { 0.0 } else { identical as f64 / counted.len() as f64 }, pairs, close, fit: f,
+    })
+}
+
+fn method_of(name: Option<&str>) -> Result<&'static str, String> {
+    match name.map(str::trim).unwrap_or("structure") {
+        "structure" => Ok("structure"), "sequence" => Ok("sequence"),
+        other => Err(format!("unknown method {other}; expected structure or sequence")),
+    }
+}
+
+fn chain_name(t: &Trace) -> String { t.chain.to_string().trim().to_string() }
+
+pub async fn superpose(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<SuperposeRequest>) -> Result<Json<SuperposeResponse>, ApiError> {
+    let meter = usage::Meter::start();
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let method = method_of(req.method.as_deref()).map_err(bad)?;
+    let (reference_text, reference_name) = structure(&s, req.reference, req.reference_id.as_deref(), "reference").await.map_err(bad)?;
+    let (mobile_text, mobile_name) = structure(&s, req.mobile, req.mobile_id.as_deref(), "mobile").await.map_err(bad)?;
+    let x = trace(&reference_text, req.reference_chain.as_deref()).map_err(|e| bad(format!("reference: {e}")))?;
+    let y = trace(&mobile_text, req.mobile_chain.as_deref()).map_err(|e| bad(format!("mobile: {e}")))?;
+
+    let (x, y, c) = tokio::task::spawn_blocking(move || { let c = compare(&x, &y, method); (x, y, c) }).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: format!("superposition failed: {e}") })))?;
+    let c = c.map_err(bad)?;
+
+    let mut alignment = Alignment { reference: String::new(), mobile: String::new(), markers: String::new() };
+    let (mut i, mut j) = (0, 0);
+    let mut column = |a: Option<usize>, b: Option<usize>, mark: char| {
+        alignment.reference.push(a.map_or('-', |a| x.sequence[a] as char));
+        alignment.mobile.push(b.map_or('-', |b| y.sequence[b] as char));
+        alignment.markers.push(mark);
+    };
+    for (&(pi, pj), &close) in c.pairs.iter().zip(&c.close) {
+        while i < pi { column(Some(i), None, ' '); i += 1; }
+        while j < pj { column(None, Some(j), ' '); j += 1; }
+        column(Some(pi), Some(pj), if close { ':' } else { '.' });
+        (i, j) = (pi + 1, pj + 1);
+    }
+    while i < x.ca.len() { column(Some(i), None, ' '); i += 1; }
+    while j < y.ca.len() { column(None, Some(j), ' '); j += 1; }
+
+    let mut warnings = Vec::new();
+    let best = c.tm_score.max(c.tm_score_mobile);
+    if best < RANDOM { warnings.push(format!("TM-score below {RANDOM}: no more similar than unrelated structures")); }
+    else if best < SAME_FOLD { warnings.push(format!("TM-score below {SAME_FOLD}: the structures probably don't share a fold")); }
+    if method == "sequence" && c.aligned < x.ca.len().min(y.ca.len()) / 2 { warnings.push("the sequences align over less than half the shorter chain; try method structure".into()); }
+    let resp = SuperposeResponse {
+        superposition_id: uuid::Uuid::new_v4().to_string(), method,
+        reference: Structure { name: reference_name, chain: chain_name(&x), residues: x.ca.len() },
+        mobile: Structure { name: mobile_name, chain: chain_name(&y), residues: y.ca.len() },
+        aligned: c.aligned, rmsd_angstrom: round(c.rmsd), tm_score: round(c.tm_score), tm_score_mobile: round(c.tm_score_mobile), sequence_identity: round(c.identity),
+        rotation: c.fit.rotation.map(|r| r.map(|v| (v * 1e6).round() / 1e6)), translation: c.fit.translation.map(round), alignment, warnings, pdb: moved(&mobile_text, &c.fit),
+    };
+    record(&s, &headers, "superpose", &format!("{}:{}", resp.reference.name, resp.mobile.name), MODEL, &resp.superposition_id, &meter, &resp);
+    Ok(Json(resp))
+}
+
+#[derive(Deserialize)]
+pub struct ClusterEntry { id: Option<String>, pdb: Option<String>, pdb_id: Option<String>, chain: Option<String> }
+#[derive(Deserialize)]
+pub struct ClusterRequest { structures: Vec<ClusterEntry>, method: Option<String>, tm_threshold: Option<f64>, rmsd_threshold: Option<f64> }
+
+#[derive(Serialize)]
+pub struct ClusterMember { id: String, chain: String, residues: usize, cluster_id: usize }
+#[derive(Serialize)]
+pub struct StructureCluster { cluster_id: usize, size: usize, medoid: String, mean_tm_score_to_medoid: f64, max_rmsd_to_medoid_angstrom: f64, members: Vec<String> }
+/// A cluster's medoid, moved onto the largest cluster's medoid so the representatives share a frame.
+#[derive(Serialize)]
+pub struct Representative { cluster_id: usize, id: String, pdb: String }
+
+#[derive(Serialize)]
+pub struct ClusterResponse {
+    clustering_id: String, method: &'static str, tm_threshold: f64, #[serde(skip_serializing_if = "Option::is_none")] rmsd_threshold: Option<f64>,
+    structures: Vec<ClusterMember>, clusters: Vec<StructureCluster>, tm_score: Vec<Vec<f64>>, rmsd_angstrom: Vec<Vec<f64>>, representatives: Vec<Representative>,
+    #[serde(skip_serializing_if = "Vec::is_empty")] warnings: Vec<String>,
+}
+
+/// The all-against-all comparisons, `(tm_score, rmsd)` with the TM-score normalised by the longer
+/// chain, spread over the available cores.
+fn all_pairs(traces: &[Trace], method: &str) -> Result<Vec<Vec<(f64, f64)>>, String> {
+    let n = traces.len();
+    let pairs: Vec<(usize, usize)> = (0..n).flat_map(|i| (i + 1..n).map(move |j| (i, j))).collect();
+    let cpus = std::thread::available_parallelism().map_or(1, |c| c.get()).min(pairs.len().max(1));
+    let results: Vec<Result<(usize, usize, f64, f64), String>> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..cpus).map(|w| {
+            let pairs = &pairs;
+            scope.spawn(move || pairs.iter().skip(w).step_by(cpus).map(|&(i, j)| {
+                let c = compare(&traces[i], &traces[j], method).map_err(|e| format!("{} and {}: {e}", i + 1, j + 1))?;
+                Ok((i, j, c.tm_score.min(c.tm_score_mobile), c.rmsd))
+            }).collect::<Vec<_>>())
+        }).collect();
+        workers.into_iter().flat_map(|w| w.join().unwrap_or_default()).collect()
+    });
+    let mut matrix = vec![vec![(1.0, 0.0); n]; n];
+    for r in results {
+        let (i, j, tm, rmsd) = r?;
+        (matrix[i][j], matrix[j][i]) = ((tm, rmsd), (tm, rmsd));
+    }
+    Ok(matrix)
+}
+
+pub async fn cluster(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<ClusterRequest>) -> Result<Json<ClusterResponse>, ApiError> {
+    let meter = usage::Meter::start();
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let method = method_of(req.method.as_deref()).map_err(bad)?;
+    if !(2..=MAX_CLUSTERED).contains(&req.structures.len()) { return Err(bad(format!("between 2 and {MAX_CLUSTERED} structures are clustered"))); }
+    let tm_threshold = req.tm_threshold.unwrap_or(SAME_FOLD);
+    if !(tm_threshold > 0.0 && tm_threshold <= 1.0) { return Err(bad("tm_threshold must be in (0, 1]".into())); }
+    if req.rmsd_threshold.is_some_and(|r| r.is_nan() || r <= 0.0) { return Err(bad("rmsd_threshold must be positive".into())); }
+
+    let (mut ids, mut texts, mut traces) = (Vec::new(), Vec::new(), Vec::new());
+    for (k, e) in req.structures.into_iter().enumerate() {
+        if e.pdb.is_none() && e.pdb_id.is_none() { return Err(bad(format!("structure {} needs pdb (PDB text) or pdb_id", k + 1))); }
+        let (text, name) = structure(&s, e.pdb, e.pdb_id.as_deref(), "pdb").await.map_err(|m| bad(format!("structure {}: {m}", k + 1)))?;
+        let id = e.id.unwrap_or_else(|| if name == "uploaded" { format!("structure_{}", k + 1) } else { name });
+        if ids.contains(&id) { return Err(bad(format!("duplicate structure id {id}"))); }
+        traces.push(trace(&text, e.chain.as_deref()).map_err(|m| bad(format!("{id}: {m}")))?);
+        ids.push(id);
+        texts.push(text);
+    }
+
+    let (traces, matrix) = tokio::task::spawn_blocking(move || { let m = all_pairs(&traces, method); (traces, m) }).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: format!("clustering failed: {e}") })))?;
+    let matrix = matrix.map_err(|e| bad(format!("structures {e}")))?;
+    let similar = |i: usize, j: usize| matrix[i][j].0 >= tm_threshold && req.rmsd_threshold.is_none_or(|r| matrix[i][j].1 <= r);
+    let groups = cluster::butina_by(ids.len(), similar);
+
+    let mut structures: Vec<ClusterMember> = traces.iter().zip(&ids).map(|(t, id)| ClusterMember { id: id.clone(), chain: chain_name(t), residues: t.ca.len(), cluster_id: 0 }).collect();
+    let mut clusters = Vec::new();
+    for (k, members) in groups.iter().enumerate() {
+        // The medoid is the member most similar to the rest, by summed TM-score.
+        let medoid = members.iter().copied().max_by(|&a, &b| {
+            let total = |m: usize| members.iter().map(|&o| matrix[m][o].0).sum::<f64>();
+            total(a).total_cmp(&total(b)).then(b.cmp(&a))
+        }).unwrap_or(members[0]);
+        for &m in members { structures[m].cluster_id = k + 1; }
+        let others: Vec<usize> = members.iter().copied().filter(|&m| m != medoid).collect();
+        clusters.push(StructureCluster {
+            cluster_id: k + 1, size: members.len(), medoid: ids[medoid].clone(),
+            mean_tm_score_to_medoid: round(if others.is_empty() { 1.0 } else { others.iter().map(|&m| matrix[medoid][m].0).sum::<f64>() / others.len() as f64 }),
+            max_rmsd_to_medoid_angstrom: round(others.iter().map(|&m| matrix[medoid][m].1).fold(0.0, f64::max)), members: members.iter().map(|&m| ids[m].clone()).collect(),
+        });
+    }
+
+    let medoids: Vec<usize> = clusters.iter().map(|c| ids.iter().position(|id| *id == c.medoid).unwrap_or(0)).collect();
+    let frame = medoids[0];
+    let mut representatives = Vec::new();
+    for (k, &m) in medoids.iter().enumerate() {
+        let pdb = if m == frame { texts[m].clone() } else {
+            let c = compare(&traces[frame], &traces[m], method).map_err(|e| bad(format!("{}: {e}", ids[m])))?;
+            moved(&texts[m], &c.fit)
+        };
+        representatives.push(Representative { cluster_id: k + 1, id: ids[m].clone(), pdb });
+    }
+    let mut warnings = Vec::new();
+    if groups.len() == ids.len() { warnings.push("every structure is its own cluster; lower tm_threshold or raise rmsd_threshold to group them".into()); }
+    else if groups.len() == 1 && ids.len() > 2 { warnings.push("all structures fall in one cluster; raise tm_threshold or set rmsd_threshold to separate conformations".into()); }
+    let resp = ClusterResponse {
+        clustering_id: uuid::Uuid::new_v4().to_string(), method, tm_threshold, rmsd_threshold: req.rmsd_threshold, structures, clusters,
+        tm_score: matrix.iter().map(|r| r.iter().map(|v| round(v.0)).collect()).collect(), rmsd_angstrom: matrix.iter().map(|r| r.iter().map(|v| round(v.1)).collect()).collect(),
+        representatives, warnings,
+    };
+    record(&s, &headers, "structure_clustering", &format!("{} structures", ids.len()), MODEL, &resp.clustering_id, &meter, &resp);
+    Ok(Json(resp))
+}
@@ -0,0 +1,163 @@
+//! Parameter sweeps.
+//!
+//! A sweep expands the cartesian product of temperatures, force fields and step counts into
+//! one child simulation job per combination. The children run in the background, like a
+//! pipeline's steps; the sweep tracks them as a group and keeps a comparison table sorted by
+//! energy as they finish.
+
+use axum::{extract::{Path, State}, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::{events::{Event, Progress}, not_found, notify, projects, protocols::Protocol, record, resolve_protocol, run_simulate, standardize, unix_now, usage, ApiError, AppState, ErrorResponse, SimulateRequest, MD_MODEL};
+
+/// Upper bound on grid size so one request can't enqueue an unbounded amount of work.
+const MAX_GRID: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct SweepRequest { molecule: String, protocol: Option<String>, simulation_type: Option<String>, thermostat: Option<String>, #[serde(default)] temperatures_k: Vec<f64>, #[serde(default)] force_fields: Vec<String>, #[serde(default)] steps: Vec<u64> }
+
+#[derive(Serialize, Clone)]
+pub struct SweepRow { sim_id: String, temperature_k: f64, force_field: String, steps: u64, energy_kcal_mol: f64, rmsd_angstrom: f64, folding_state: String }
+#[derive(Serialize, Clone)]
+pub struct Sweep { sweep_id: String, project: String, molecule: String, status: String, created_at_unix: u64, grid_size: usize, completed: usize, failed: usize, #[serde(skip_serializing_if = "Vec::is_empty")] errors: Vec<String>, child_jobs: Vec<String>, best_sim_id: Option<String>, table: Vec<SweepRow> }
+#[derive(Serialize)]
+pub struct SweepSummary { sweep_id: String, molecule: String, status: String, created_at_unix: u64, grid_size: usize, completed: usize, failed: usize }
+#[derive(Serialize)]
+pub struct SweepsResponse { project: String, sweeps: Vec<SweepSummary> }
+
+pub struct SweepStore { sweeps: Mutex<HashMap<String, Sweep>> }
+
+impl SweepStore {
+    pub fn new() -> Self { Self { sweeps: Mutex::new(HashMap::new()) } }
+    pub fn mean_grid_size(&self) -> Option<f64> {
+        let sweeps = self.sweeps.lock().unwrap();
+        (!sweeps.is_empty()).then(|| sweeps.values().map(|w| w.grid_size as f64).sum::<f64>() / sweeps.len() as f64)
+    }
+    /// Grid points still to finish across projects, as `(queued, running)`. They are
+    /// simulations, and are counted as that kind.
+    pub fn outstanding(&self) -> (usize, usize) {
+        self.sweeps.lock().unwrap().values().filter(|w| w.status == "queued" || w.status == "running").fold((0, 0), |(queued, running), w| {
+            let left = w.grid_size - w.completed - w.failed;
+            let now = usize::from(w.status == "running" && left > 0);
+            (queued + left - now, running + now)
+        })
+    }
+    fn update(&self, id: &str, f: impl FnOnce(&mut Sweep)) { if let Some(w) = self.sweeps.lock().unwrap().get_mut(id) { f(w); } }
+}
+
+/// One point of the grid; `None` on an axis falls through to the protocol/engine default.
+#[derive(Clone, Debug, PartialEq)]
+struct Point { temperature_k: Option<f64>, force_field: Option<String>, steps: Option<u64> }
+
+/// The cartesian product of the request's axes, rejecting grids too large to run and
+/// temperatures no simulation could run at.
+fn grid(req: &SweepRequest) -> Result<Vec<Point>, String> {
+    if let Some(t) = req.temperatures_k.iter().find(|t| crate::check_temperature(**t).is_err()) { return Err(format!("temperatures_k: {t} is not a temperature; each must be a positive number of kelvin")); }
+    // An empty axis means "not swept".
+    let temps: Vec<Option<f64>> = if req.temperatures_k.is_empty() { vec![None] } else { req.temperatures_k.iter().copied().map(Some).collect() };
+    let ffs: Vec<Option<String>> = if req.force_fields.is_empty() { vec![None] } else { req.force_fields.iter().cloned().map(Some).collect() };
+    let steps: Vec<Option<u64>> = if req.steps.is_empty() { vec![None] } else { req.steps.iter().copied().map(Some).collect() };
+    let grid_size = temps.len() * ffs.len() * steps.len();
+    if grid_size > MAX_GRID { return Err(format!("sweep grid has {grid_size} points; the maximum is {MAX_GRID}")); }
+    let mut points = Vec::with_capacity(grid_size);
+    for t in &temps {
+        for ff in &ffs {
+            for n in &steps { points.push(Point { temperature_k: *t, force_field: ff.clone(), steps: *n }); }
+        }
+    }
+    Ok(points)
+}
+
+pub async fn create(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<SweepRequest>) -> Result<(StatusCode, Json<Sweep>), ApiError> {
+    let points = grid(&req).map_err(|e| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e })))?;
+    let proto = resolve_protocol(&s, &headers, req.protocol.as_deref())?;
+    let sweep = Sweep { sweep_id: uuid::Uuid::new_v4().to_string(), project: s.projects.resolve(&headers), molecule: req.molecule.clone(), status: "queued".into(), created_at_unix: unix_now(), grid_size: points.len(), completed: 0, failed: 0, errors: Vec::new(), child_jobs: Vec::new(), best_sim_id: None, table: Vec::new() };
+    s.sweeps.sweeps.lock().unwrap().insert(sweep.sweep_id.clone(), sweep.clone());
+    s.events.publish(Event::new("submitted", "sweep", &sweep.project).job(&sweep.sweep_id));
+    s.compute.spawn(run(s.clone(), headers, sweep.sweep_id.clone(), req, proto, points));
+    Ok((StatusCode::ACCEPTED, Json(sweep)))
+}
+
+/// Runs the grid points one after another, each as a child simulation job admitted like any
+/// other compute request, and keeps the group's counts and table current.
+async fn run(s: Arc<AppState>, headers: HeaderMap, id: String, req: SweepRequest, proto: Protocol, points: Vec<Point>) {
+    let project = projects::project_id(&headers);
+    let event = |name: &'static str| Event::new(name, "sweep", &project).job(&id);
+    s.sweeps.update(&id, |w| w.status = "running".into());
+    s.events.publish(event("started"));
+    let started = Instant::now();
+    let mol = standardize::resolve(&s, &headers, &req.molecule).await;
+    for (done, point) in points.iter().enumerate() {
+        let child = SimulateRequest { molecule: req.molecule.clone(), simulation_type: req.simulation_type.clone(), steps: point.steps, temperature_k: point.temperature_k, force_field: point.force_field.clone(), thermostat: req.thermostat.clone(), protocol: req.protocol.clone(), restraints: None, observables: None, temperature_schedule: None, qm_region: None, umbrella: None, metadynamics: None, validate_only: None };
+        let slot = s.admission.acquire().await;
+        let meter = usage::Meter::start();
+        let result = run_simulate(&s, &project, child, proto.clone(), &mol);
+        drop(slot);
+        let label = format!("{} K, {}, {} steps", point.temperature_k.map_or("default".into(), |t| t.to_string()), point.force_field.as_deref().unwrap_or("default force field"), point.steps.map_or("default".into(), |n| n.to_string()));
+        let status = match result {
+            Ok(resp) => {
+                record(&s, &headers, "simulate", &resp.molecule, MD_MODEL, &resp.sim_id, &meter, &resp);
+                let row = SweepRow { sim_id: resp.sim_id, temperature_k: resp.temperature_k, force_field: resp.force_field, steps: resp.steps, energy_kcal_mol: resp.energy_kcal_mol, rmsd_angstrom: resp.rmsd_angstrom, folding_state: resp.folding_state };
+                s.sweeps.update(&id, |w| {
+                    w.completed += 1;
+                    w.child_jobs.push(row.sim_id.clone());
+                    let at = w.table.partition_point(|r| r.energy_kcal_mol <= row.energy_kcal_mol);
+                    w.table.insert(at, row);
+                    w.best_sim_id = w.table.first().map(|r| r.sim_id.clone());
+                });
+                "completed"
+            }
+            Err(e) => { s.sweeps.update(&id, |w| { w.failed += 1; w.errors.push(format!("{label}: {e}")); }); "failed" }
+        };
+        s.events.publish(Event { progress: Some(Progress { step: label, status: status.into(), done: done + 1, total: points.len() }), ..event("progress") });
+        tokio::task::yield_now().await;
+    }
+    let Some(sweep) = s.sweeps.sweeps.lock().unwrap().get_mut(&id).map(|w| { w.status = if w.failed > 0 { "failed".into() } else { "completed".into() }; w.clone() }) else { return };
+    let error = (sweep.failed > 0).then_some("a grid point failed; see the sweep's errors");
+    s.events.publish(match error { Some(e) => Event { error: Some(e.into()), ..event("failed") }, None => event("completed") });
+    let best = sweep.table.first().map(|r| format!("Lowest energy {:.2} kcal/mol at {} K with {}, {} steps.", r.energy_kcal_mol, r.temperature_k, r.force_field, r.steps));
+    s.notifications.finished(&headers, &project, notify::Finished { kind: "sweep", id: &id, subject: &sweep.molecule, error, wall_seconds: started.elapsed().as_secs_f64(), summary: best.into_iter().collect(), link: Some(s.notifications.link(&format!("/api/v1/bio/sweeps/{id}"))) });
+}
+
+pub async fn list(State(s): State<Arc<AppState>>, headers: HeaderMap) -> Json<SweepsResponse> {
+    let project = projects::project_id(&headers);
+    let mut sweeps: Vec<SweepSummary> = s.sweeps.sweeps.lock().unwrap().values().filter(|w| w.project == project)
+        .map(|w| SweepSummary { sweep_id: w.sweep_id.clone(), molecule: w.molecule.clone(), status: w.status.clone(), created_at_unix: w.created_at_unix, grid_size: w.grid_size, completed: w.completed, failed: w.failed }).collect();
+    sweeps.sort_by_key(|w| std::cmp::Reverse(w.created_at_unix));
+    Json(SweepsResponse { project, sweeps })
+}
+
+pub async fn get(State(s): State<Arc<AppState>>, headers: HeaderMap, Path(id): Path<String>) -> Result<Json<Sweep>, ApiError> {
+    let project = projects::project_id(&headers);
+    s.sweeps.sweeps.lock().unwrap().get(&id).filter(|w| w.project == project).cloned().map(Json).ok_or_else(|| not_found("sweep", &id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(body: serde_json::Value) -> SweepRequest { serde_json::from_value(body).unwrap() }
+
+    #[test]
+    fn grid_is_the_product_of_the_axes() {
+        let points = grid(&request(serde_json::json!({"molecule": "CCO", "temperatures_k": [280.0, 310.0], "force_fields": ["amber-ff14", "charmm36"], "steps": [1000]}))).unwrap();
+        assert_eq!(points.len(), 4);
+        assert_eq!(points[0], Point { temperature_k: Some(280.0), force_field: Some("amber-ff14".into()), steps: Some(1000) });
+        let single = grid(&request(serde_json::json!({"molecule": "CCO"}))).unwrap();
+        assert_eq!(single, vec![Point { temperature_k: None, force_field: None, steps: None }]);
+    }
+
+    #[test]
+    fn grid_rejects_bad_temperatures_and_oversized_grids() {
+        for t in [-5.0, 0.0] {
+            let err = grid(&request(serde_json::json!({"molecule": "CCO", "temperatures_k": [300.0, t]}))).unwrap_err();
+            assert!(err.contains("temperatures_k"), "{err}");
+        }
+        let temps: Vec<f64> = (1..=101).map(f64::from).collect();
+        let err = grid(&request(serde_json::json!({"molecule": "CCO", "temperatures_k": temps, "steps": (1..=10).collect::<Vec<u64>>()}))).unwrap_err();
+        assert!(err.contains("1010 points"), "{err}");
+    }
+}
@@ -0,0 +1,313 @@
+//! Ternary complexes: target–degrader–E3 ligase.
+//!
+//! A PROTAC is split into its warhead (given as SMARTS, or what lies past the linker), its E3
+//! ligand (a bundled pattern for CRBN, VHL, MDM2 or IAP, or given) and the linker between
+//! them. Linker conformations are random turns about the linker's rotatable bonds from an
+//! embedded conformer, the least strained of a few tries each. Each is docked with its
+//! warhead in the target's pocket and the warhead's exit vector leaving through the pocket
+//! mouth at a range of tilts and spins; the E3 ligase follows rigidly, its pocket on the E3
+//! ligand with the mouth along that ligand's exit vector. Both proteins are spheres sized
+//! from their residue counts, lined at the pocket with the pseudo-receptor atoms of `pockets`.
+//! A pose scores the degrader's interaction with both linings, its internal strain and the
+//! protein–protein term: surface buried within 4 Å of contact is favourable and overlap is
+//! penalized. Cooperativity α is the change the protein–protein term makes to the
+//! Boltzmann-weighted ensemble of poses the binary interactions allow, so α > 1 when the
+//! proteins can meet productively and α < 1 when the linker forces them together. The best
+//! poses are minimized against both linings.
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use bio_engine_core::smarts::{Pattern, Target};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+
+use crate::{chem::{self, Molecule}, conformer, fnv1a, forcefield::{self, System}, frame::Frame, pockets, poses, record, rng::Rng, standardize, usage, vec3::{sub, add, scale, norm, unit, rotate, aligning}, ApiError, AppState, ErrorResponse, DOCK_MODEL};
+
+/// RT at 298 K, kcal/mol.
+const RT: f64 = 0.593;
+/// E3 ligase, its ligands' common core, and residues of the ligand-binding construct.
+const LIGASES: &[(&str, &str, usize)] = &[
+    ("CRBN", "C1CC(=O)NC(=O)C1N1Cc2ccccc2C1=O", 442),
+    ("VHL", "OC1CC(C(=O)NC)N(C1)C(=O)C(N)C(C)(C)C", 213),
+    ("MDM2", "Clc1ccc(cc1)C1N=CNC1c1ccc(Cl)cc1", 110),
+    ("IAP", "CNC(C)C(=O)NC(C1CCCCC1)C(=O)N1CCCC1", 95),
+];
+const DEFAULT_TARGET_RESIDUES: usize = 250;
+const DEFAULT_SAMPLES: usize = 32;
+const MAX_SAMPLES: usize = 256;
+const DEFAULT_TOP_N: usize = 5;
+const MAX_TOP_N: usize = 20;
+const TILTS_DEG: [f64; 3] = [0.0, 25.0, 50.0];
+const AZIMUTHS: usize = 4;
+const SPINS: usize = 6;
+/// Protein surfaces closer than this bury area between them, Å.
+const CONTACT: f64 = 4.0;
+/// Free energy per Å² of protein–protein interface buried, kcal/mol.
+const GAMMA: f64 = 0.008;
+/// Penalty per Å² of surface overlap, kcal/mol.
+const K_OVERLAP: f64 = 2.0;
+const MINIMIZE_STEPS: usize = 300;
+/// Random linker conformations tried for each one kept.
+const TRIES: usize = 8;
+
+#[derive(Deserialize)]
+pub struct TernaryRequest { protac: String, target_protein: String, target_residues: Option<usize>, e3_ligase: Option<String>, e3_ligand: Option<String>, e3_residues: Option<usize>, warhead: Option<String>, linker_samples: Option<usize>, top_n: Option<usize>, format: Option<String> }
+
+#[derive(Serialize)]
+pub struct Linker { atoms: usize, path_bonds: usize, rotatable_bonds: usize, end_to_end_min: f64, end_to_end_mean: f64, end_to_end_max: f64 }
+#[derive(Serialize)]
+pub struct Cooperativity { alpha: f64, delta_g_kcal: f64, class: &'static str, productive_fraction: f64 }
+#[derive(Serialize)]
+pub struct TernaryModel { rank: usize, conformer: usize, tilt_deg: f64, score: f64, interaction_energy: f64, strain_energy: f64, ppi_energy: f64, surface_gap_angstrom: f64, clashes: usize, e3_pocket_center: [f64; 3], pose: String }
+#[derive(Serialize)]
+pub struct TernaryResponse { ternary_id: String, protac: String, target: String, e3_ligase: String, warhead_atoms: usize, e3_ligand_atoms: usize, linker: Linker, poses_sampled: usize, cooperativity: Cooperativity, models: Vec<TernaryModel>, format: String, #[serde(skip_serializing_if = "Vec::is_empty")] warnings: Vec<String> }
+
+fn round(v: f64) -> f64 { (v * 1000.0).round() / 1000.0 }
+fn centroid(x: &[[f64; 3]], atoms: &[usize]) -> [f64; 3] { scale(atoms.iter().fold([0.0; 3], |c, &i| add(c, x[i])), 1.0 / atoms.len() as f64) }
+
+/// Radius of a globular protein of `residues`, from ~140 Å³ per residue.
+fn protein_radius(residues: usize) -> f64 { (3.0 * 140.0 * residues as f64 / (4.0 * std::f64::consts::PI)).cbrt() }
+fn pocket_radius(p: &pockets::Pocket) -> f64 { (3.0 * p.volume_a3 / (4.0 * std::f64::consts::PI)).cbrt() }
+
+/// Protein–protein free energy at a surface gap of `gap` Å between spheres of radii `a` and `b`:
+/// the contact zone's area, a cap of the smaller effective sphere, is buried up to `CONTACT`
+/// apart, and overlap is penalized by its depth squared.
+fn ppi_energy(gap: f64, a: f64, b: f64) -> f64 {
+    let r = a * b / (a + b);
+    let buried = 2.0 * std::f64::consts::PI * r * (CONTACT - gap).max(0.0);
+    -GAMMA * buried + if gap < 0.0 { K_OVERLAP * gap * gap } else { 0.0 }
+}
+
+fn pattern_atoms(m: &Molecule, smarts: &str, what: &str) -> Result<Vec<usize>, String> {
+    let p = Pattern::parse(smarts).map_err(|e| format!("{what}: {e}"))?;
+    p.find(&Target::new(m)).ok_or_else(|| format!("the PROTAC doesn't contain the {what} {smarts}"))
+}
+
+/// Connected components of the atoms not in `removed`.
+fn components(m: &Molecule, removed: &[usize]) -> Vec<Vec<usize>> {
+    let adj = m.neighbors();
+    let mut seen: HashSet<usize> = removed.iter().copied().collect();
+    let mut out = Vec::new();
+    for start in 0..m.atoms.len() {
+        if !seen.insert(start) { continue; }
+        let (mut part, mut queue) = (vec![start], VecDeque::from([start]));
+        while let Some(i) = queue.pop_front() {
+            for &(j, _) in &adj[i] { if seen.insert(j) { part.push(j); queue.push_back(j); } }
+        }
+        out.push(part);
+    }
+    out
+}
+
+fn touches(m: &Molecule, part: &[usize], set: &[usize]) -> bool { m.bonds.iter().any(|b| (part.contains(&b.a) && set.contains(&b.b)) || (part.contains(&b.b) && set.contains(&b.a))) }
+
+/// Splits a degrader into (warhead, linker, E3 ligand). Groups hanging off only one end (the
+/// phenylthiazole of a VHL ligand) join that end. Without a warhead pattern, the linker is
+/// the acyclic atoms reached from the E3 ligand before the first ring, and the warhead the
+/// rest of the largest group beyond it.
+fn partition(m: &Molecule, warhead: Option<Vec<usize>>, mut e3: Vec<usize>) -> (Vec<usize>, Vec<usize>, Vec<usize>) {
+    let ring_atom: Vec<bool> = (0..m.atoms.len()).map(|i| m.bonds.iter().enumerate().any(|(b, bond)| (bond.a == i || bond.b == i) && m.bond_in_ring(b))).collect();
+    let mut w = match warhead {
+        Some(w) => w,
+        None => {
+            let mut parts = components(m, &e3);
+            parts.sort_by_key(|p| std::cmp::Reverse(p.len()));
+            let Some(main) = parts.first().cloned() else { return (Vec::new(), Vec::new(), e3) };
+            for p in &parts[1..] { e3.extend(p); }
+            // The linker: acyclic atoms of the main group reached from the E3 ligand.
+            let adj = m.neighbors();
+            let mut linker: Vec<usize> = Vec::new();
+            let mut queue: VecDeque<usize> = main.iter().copied().filter(|&i| !ring_atom[i] && adj[i].iter().any(|e| e3.contains(&e.0))).collect();
+            while let Some(i) = queue.pop_front() {
+                if linker.contains(&i) { continue; }
+                linker.push(i);
+                queue.extend(adj[i].iter().map(|e| e.0).filter(|&j| main.contains(&j) && !ring_atom[j] && !linker.contains(&j)));
+            }
+            let w = main.into_iter().filter(|i| !linker.contains(i)).collect();
+            return (w, linker, e3);
+        }
+    };
+    let mut linker = Vec::new();
+    for part in components(m, &[w.clone(), e3.clone()].concat()) {
+        match (touches(m, &part, &w), touches(m, &part, &e3)) {
+            (true, false) => w.extend(part),
+            (false, true) => e3.extend(part),
+            _ => linker.extend(part),
+        }
+    }
+    (w, linker, e3)
+}
+
+/// The atom of `part` bonded to `other`, where a degrader's linker leaves it.
+fn exit_atom(m: &Molecule, part: &[usize], other: &[usize]) -> Option<usize> {
+    m.bonds.iter().find_map(|b| if part.contains(&b.a) && other.contains(&b.b) { Some(b.a) } else if part.contains(&b.b) && other.contains(&b.a) { Some(b.b) } else { None })
+}
+
+/// Bonds on the shortest path between two atom sets.
+fn path_bonds(m: &Molecule, from: &[usize], to: &[usize]) -> usize {
+    let adj = m.neighbors();
+    let mut dist = vec![usize::MAX; m.atoms.len()];
+    let mut queue: VecDeque<usize> = from.iter().copied().collect();
+    for &i in from { dist[i] = 0; }
+    while let Some(i) = queue.pop_front() {
+        if to.contains(&i) { return dist[i]; }
+        for &(j, _) in &adj[i] { if dist[j] == usize::MAX { dist[j] = dist[i] + 1; queue.push_back(j); } }
+    }
+    0
+}
+
+/// Linker conformations: the embedded conformer, then random turns about the linker's
+/// rotatable bonds, each kept as the least strained of a few tries. `turns` are the bonds with
+/// the atoms on their far side.
+fn linker_conformers(base: &[[f64; 3]], turns: &[(usize, usize, Vec<usize>)], restraints: &[conformer::Restraint], samples: usize, seed: u64) -> Vec<Vec<[f64; 3]>> {
+    let mut rng = Rng(seed | 1);
+    let mut out = vec![base.to_vec()];
+    while out.len() < samples && !turns.is_empty() {
+        let mut best: Option<(f64, Vec<[f64; 3]>)> = None;
+        for _ in 0..TRIES {
+            let mut x = base.to_vec();
+            for (a, b, side) in turns {
+                let (origin, axis) = (x[*a], unit(sub(x[*b], x[*a])));
+                let theta = std::f64::consts::TAU * rng.uniform();
+                for &i in side { x[i] = add(origin, rotate(sub(x[i], origin), axis, theta)); }
+            }
+            let e = forcefield::internal_energy(restraints, &x, None);
+            if best.as_ref().is_none_or(|b| e < b.0) { best = Some((e, x)); }
+        }
+        out.extend(best.map(|b| b.1));
+    }
+    out
+}
+
+/// One placement of a conformer: its coordinates in the complex, the E3 pocket centre and the
+/// E3 lining there.
+struct Placement { x: Vec<[f64; 3]>, e3_center: [f64; 3], e3_lining: Vec<[f64; 3]>, gap: f64 }
+
+pub async fn model(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<TernaryRequest>) -> Result<Json<TernaryResponse>, ApiError> {
+    let meter = usage::Meter::start();
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let resolved = standardize::resolve(&s, &headers, &req.protac).await;
+    let smiles = resolved.canonical_smiles.clone().ok_or_else(|| bad(format!("{} did not resolve to a structure", req.protac)))?;
+    let mol = chem::parse_smiles(&smiles).map_err(bad)?;
+    let resp = run(&req, &smiles, &mol).map_err(bad)?;
+    record(&s, &headers, "ternary", &resp.protac, DOCK_MODEL, &resp.ternary_id, &meter, &resp);
+    Ok(Json(resp))
+}
+
+fn run(req: &TernaryRequest, smiles: &str, mol: &Molecule) -> Result<TernaryResponse, String> {
+    let samples = req.linker_samples.unwrap_or(DEFAULT_SAMPLES);
+    if samples == 0 || samples > MAX_SAMPLES { return Err(format!("linker_samples must be between 1 and {MAX_SAMPLES}")); }
+    let top_n = req.top_n.unwrap_or(DEFAULT_TOP_N);
+    if top_n == 0 || top_n > MAX_TOP_N { return Err(format!("top_n must be between 1 and {MAX_TOP_N}")); }
+    let format = req.format.clone().unwrap_or_else(|| "sdf".into());
+    if !matches!(format.as_str(), "sdf" | "pdb") { return Err(format!("unknown format {format}; expected sdf or pdb")); }
+
+    // The E3 ligand: the named ligase's pattern, a given one, or the first bundled one found.
+    let known = req.e3_ligase.as_deref().map(|name| LIGASES.iter().find(|l| l.0.eq_ignore_ascii_case(name)));
+    let (ligase, e3, e3_residues) = match (&req.e3_ligand, known) {
+        (Some(smarts), known) => (req.e3_ligase.clone().unwrap_or_else(|| "custom".into()), pattern_atoms(mol, smarts, "E3 ligand")?, known.flatten().map_or(200, |l| l.2)),
+        (None, Some(Some(l))) => (l.0.to_string(), pattern_atoms(mol, l.1, &format!("{} ligand", l.0))?, l.2),
+        (None, Some(None)) => return Err(format!("no bundled ligand for {}; give e3_ligand as SMARTS or use one of CRBN, VHL, MDM2, IAP", req.e3_ligase.as_deref().unwrap_or_default())),
+        (None, None) => {
+            let t = Target::new(mol);
+            LIGASES.iter().find_map(|l| Pattern::parse(l.1).ok()?.find(&t).map(|atoms| (l.0.to_string(), atoms, l.2))).ok_or("no CRBN, VHL, MDM2 or IAP ligand found; give e3_ligase and e3_ligand")?
+        }
+    };
+    let e3_residues = req.e3_residues.unwrap_or(e3_residues);
+    let given = req.warhead.as_deref().map(|smarts| pattern_atoms(mol, smarts, "warhead")).transpose()?;
+    if given.as_ref().is_some_and(|w| w.iter().any(|i| e3.contains(i))) { return Err("the warhead and E3 ligand patterns overlap".into()); }
+    let (warhead, linker, e3) = partition(mol, given, e3);
+    if warhead.is_empty() { return Err("nothing is left for a warhead beside the E3 ligand and linker; give warhead".into()); }
+    let mut warnings = Vec::new();
+    if req.warhead.is_none() { warnings.push("warhead taken as everything past the first ring beyond the E3 ligand; give warhead to set it".into()); }
+    let (exit_w, exit_e) = match (exit_atom(mol, &warhead, &[linker.clone(), e3.clone()].concat()), exit_atom(mol, &e3, &[linker.clone(), warhead.clone()].concat())) {
+        (Some(w), Some(e)) => (w, e),
+        _ => return Err("the warhead and E3 ligand aren't connected".into()),
+    };
+
+    let target = pockets::detect(&req.target_protein).into_iter().next().ok_or_else(|| format!("no pocket found on {}", req.target_protein))?;
+    let e3_pocket = pockets::detect(&ligase).into_iter().next().ok_or_else(|| format!("no pocket found on {ligase}"))?;
+    let (r_t, r_e) = (protein_radius(req.target_residues.unwrap_or(DEFAULT_TARGET_RESIDUES)), protein_radius(e3_residues));
+    let target_lining = pockets::lining(&target);
+    let e3_local: Vec<[f64; 3]> = pockets::lining(&e3_pocket).into_iter().map(|p| sub(p, e3_pocket.center)).collect();
+    // Both linings open toward +z; each protein's body lies behind its pocket.
+    let mouth = [0.0, 0.0, 1.0];
+    let target_body = sub(target.center, scale(mouth, r_t - pocket_radius(&target)));
+
+    // Linker torsions: acyclic single bonds between heavy atoms, amides excepted.
+    let adj = mol.neighbors();
+    let carbonyl = |i: usize| mol.atoms[i].element == "C" && adj[i].iter().any(|&(j, k)| k == chem::BondKind::Double && mol.atoms[j].element == "O");
+    let turns: Vec<(usize, usize, Vec<usize>)> = mol.bonds.iter().enumerate().filter(|&(b, bond)| {
+        (linker.contains(&bond.a) || linker.contains(&bond.b)) && bond.kind == chem::BondKind::Single && !mol.bond_in_ring(b) && adj[bond.a].len() > 1 && adj[bond.b].len() > 1
+            && !((mol.atoms[bond.a].element == "N" && carbonyl(bond.b)) || (mol.atoms[bond.b].element == "N" && carbonyl(bond.a)))
+    }).map(|(_, bond)| {
+        // Turn the side away from the warhead, so the warhead stays put.
+        let far = |from: usize, to: usize| components(mol, &[from]).into_iter().find(|p| p.contains(&to)).unwrap_or_default();
+        let side = far(bond.a, bond.b);
+        if side.contains(&exit_w) { (bond.b, bond.a, far(bond.b, bond.a)) } else { (bond.a, bond.b, side) }
+    }).collect();
+
+    let restraints = conformer::restraints(mol);
+    let seed = fnv1a(format!("{smiles}/{}/{ligase}", req.target_protein).as_bytes());
+    let conformers = linker_conformers(&conformer::embed(mol, seed), &turns, &restraints, samples, seed);
+    let ends: Vec<f64> = conformers.iter().map(|x| norm(sub(centroid(x, &e3), centroid(x, &warhead)))).collect();
+    let linker_report = Linker { atoms: linker.len(), path_bonds: path_bonds(mol, &warhead, &e3), rotatable_bonds: turns.len(), end_to_end_min: round(ends.iter().copied().fold(f64::INFINITY, f64::min)), end_to_end_mean: round(ends.iter().sum::<f64>() / ends.len() as f64), end_to_end_max: round(ends.iter().copied().fold(0.0, f64::max)) };
+
+    // The warhead's exit vector leaves the target pocket through its mouth, tilted and spun;
+    // the E3 pocket sits on the E3 ligand with its mouth along that ligand's exit vector.
+    let place = |k: usize, tilt: f64, azimuth: f64, spin: f64| -> Placement {
+        let x0 = &conformers[k];
+        let w = centroid(x0, &warhead);
+        let exit = unit([tilt.sin() * azimuth.cos(), tilt.sin() * azimuth.sin(), tilt.cos()]);
+        let (axis, angle) = aligning(unit(sub(x0[exit_w], w)), exit);
+        let x: Vec<[f64; 3]> = x0.iter().map(|&p| add(target.center, rotate(rotate(sub(p, w), axis, angle), exit, spin))).collect();
+        let e3_center = centroid(&x, &e3);
+        let e3_exit = unit(sub(x[exit_e], e3_center));
+        let (axis_e, angle_e) = aligning(mouth, e3_exit);
+        let e3_lining = e3_local.iter().map(|&p| add(e3_center, rotate(p, axis_e, angle_e))).collect();
+        let e3_body = sub(e3_center, scale(e3_exit, r_e - pocket_radius(&e3_pocket)));
+        Placement { x, e3_center, e3_lining, gap: norm(sub(e3_body, target_body)) - r_t - r_e }
+    };
+    // (conformer, tilt, azimuth, spin, interaction + strain, protein–protein)
+    let mut sampled: Vec<(usize, f64, f64, f64, f64, f64)> = Vec::new();
+    for (k, x0) in conformers.iter().enumerate() {
+        let strain = forcefield::internal_energy(&restraints, x0, None);
+        for tilt in TILTS_DEG.map(f64::to_radians) {
+            let azimuths = if tilt == 0.0 { 1 } else { AZIMUTHS };
+            for a in 0..azimuths {
+                for sp in 0..SPINS {
+                    let (azimuth, spin) = (std::f64::consts::TAU * a as f64 / AZIMUTHS as f64, std::f64::consts::TAU * sp as f64 / SPINS as f64);
+                    let p = place(k, tilt, azimuth, spin);
+                    let receptor = Frame::new(&[target_lining.clone(), p.e3_lining].concat());
+                    sampled.push((k, tilt, azimuth, spin, forcefield::interaction_energy(&receptor, &p.x, None) + strain, ppi_energy(p.gap, r_t, r_e)));
+                }
+            }
+        }
+    }
+    // α: the protein–protein term's effect on the ensemble the binary interactions allow.
+    let floor = sampled.iter().map(|p| p.4).fold(f64::INFINITY, f64::min);
+    let (mut z_binary, mut z_ternary) = (0.0, 0.0);
+    for p in &sampled { let w = (-(p.4 - floor) / RT).exp(); z_binary += w; z_ternary += w * (-p.5 / RT).exp(); }
+    let alpha = z_ternary / z_binary;
+    let delta_g = -RT * alpha.ln();
+    let class = if alpha > 1.5 { "positive" } else if alpha < 1.0 / 1.5 { "negative" } else { "non-cooperative" };
+    let productive = sampled.iter().filter(|p| p.5 < 0.0).count() as f64 / sampled.len() as f64;
+
+    sampled.sort_by(|a, b| (a.4 + a.5).total_cmp(&(b.4 + b.5)));
+    let mut models: Vec<TernaryModel> = Vec::new();
+    for &(k, tilt, azimuth, spin, _, ppi) in sampled.iter().take(top_n * 4) {
+        let p = place(k, tilt, azimuth, spin);
+        let receptor = Frame::new(&[target_lining.clone(), p.e3_lining].concat());
+        let mut x = p.x.clone();
+        System { restraints: &restraints, receptor: &receptor, metals: None, anchor: &p.x, k_pos: 1.0, bias: None }.minimize(&mut x, MINIMIZE_STEPS);
+        let (interaction, strain) = (forcefield::interaction_energy(&receptor, &x, None), forcefield::internal_energy(&restraints, &x, None));
+        let pose = poses::Pose { compound_id: format!("ternary-{}", models.len() + 1), smiles: smiles.into(), target: req.target_protein.clone(), pocket_id: target.pocket_id.clone(), binding_affinity_nm: 0.0, coords: x.clone() };
+        let text = if format == "pdb" { poses::to_pdb(&pose, mol) } else { poses::to_sdf(&pose, mol) };
+        models.push(TernaryModel { rank: 0, conformer: k + 1, tilt_deg: round(tilt.to_degrees()), score: round(interaction + strain + ppi), interaction_energy: round(interaction), strain_energy: round(strain), ppi_energy: round(ppi), surface_gap_angstrom: round(p.gap), clashes: forcefield::clashes(&receptor, &x), e3_pocket_center: p.e3_center.map(round), pose: text });
+    }
+    models.sort_by(|a, b| a.score.total_cmp(&b.score));
+    models.truncate(top_n);
+    for (i, m) in models.iter_mut().enumerate() { m.rank = i + 1; }
+    if models.first().is_some_and(|m| m.clashes > 0) { warnings.push("even the best model has clashes; the linker may be too short for this pair".into()); }
+    if productive == 0.0 { warnings.push("the proteins overlap or never meet in every sampled pose; the linker length may not suit this pair".into()); }
+    Ok(TernaryResponse { ternary_id: uuid::Uuid::new_v4().to_string(), protac: smiles.into(), target: req.target_protein.clone(), e3_ligase: ligase, warhead_atoms: warhead.len(), e3_ligand_atoms: e3.len(), linker: linker_report, poses_sampled: sampled.len(), cooperativity: Cooperativity { alpha: (alpha * 1000.0).round() / 1000.0, delta_g_kcal: round(delta_g), class, productive_fraction: round(productive) }, models, format, warnings })
+}
@@ -0,0 +1,104 @@
+//! Relaxed torsion scans.
+//!
+//! `POST /torsion-scan` drives one dihedral of a molecule through a full turn in equal
+//! increments, holding it at each angle with a stiff harmonic restraint while steepest descent
+//! relaxes every other degree of freedom, and reports the force field energy without the
+//! restraint. The turn is scanned forwards and then backwards from the same structure, and each
+//! angle keeps the lower of its two energies, so a structure caught in one direction's local
+//! minimum doesn't leave a step in the profile. Besides the energy relative to the profile's
+//! minimum, each point gives its strain against the molecule's global minimum (see `strain`),
+//! which tells how much a conformation at that angle costs the ligand overall.
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use std::{cell::Cell, sync::Arc};
+
+use crate::{chem, conformer, cv::{Cv, CvSpec}, fnv1a, forcefield::{self, System}, frame, record, resolver, standardize, strain, usage, ApiError, AppState, ErrorResponse, MD_MODEL};
+
+const DEFAULT_INCREMENT_DEG: f64 = 15.0;
+const MAX_POINTS: usize = 360;
+/// kcal/mol/rad².
+const DEFAULT_FORCE_CONSTANT: f64 = 500.0;
+const MINIMIZE_STEPS: usize = 2000;
+/// A point whose dihedral ends further than this from its target gets a warning.
+const TOLERANCE_DEG: f64 = 5.0;
+
+#[derive(Deserialize)]
+pub struct TorsionScanRequest { pub molecule: String, pub atoms: Vec<usize>, pub increment_deg: Option<f64>, pub force_constant: Option<f64> }
+
+#[derive(Serialize, Clone)]
+pub struct ScanPoint { pub angle_deg: f64, pub achieved_deg: f64, pub energy_kcal_mol: f64, pub relative_kcal_mol: f64, pub strain_kcal_mol: f64 }
+
+#[derive(Serialize)]
+pub struct TorsionScan {
+    pub scan_id: String, pub molecule: String, pub canonical_smiles: String, #[serde(skip_serializing_if = "Vec::is_empty")] pub standardization: Vec<String>, pub dihedral: String, pub increment_deg: f64, pub force_constant: f64, pub start_angle_deg: f64,
+    pub points: Vec<ScanPoint>, pub minimum_angle_deg: f64, pub maximum_angle_deg: f64, pub barrier_kcal_mol: f64, pub global_minimum_energy_kcal_mol: f64, pub warnings: Vec<String>,
+}
+
+pub async fn scan(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<TorsionScanRequest>) -> Result<Json<TorsionScan>, ApiError> {
+    let meter = usage::Meter::start();
+    let mol = standardize::resolve(&s, &headers, &req.molecule).await;
+    let resp = run(&req, &mol).map_err(|e| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e })))?;
+    record(&s, &headers, "torsion_scan", &resp.molecule, MD_MODEL, &resp.scan_id, &meter, &resp);
+    Ok(Json(resp))
+}
+
+/// Wraps an angle in degrees into (−180, 180].
+fn wrap(deg: f64) -> f64 { let d = deg - 360.0 * (deg / 360.0).round(); if d <= -180.0 { d + 360.0 } else { d } }
+
+pub fn run(req: &TorsionScanRequest, mol: &resolver::Resolved) -> Result<TorsionScan, String> {
+    let smiles = mol.canonical_smiles.as_deref().ok_or_else(|| format!("can't resolve {} to a structure to scan", mol.input))?;
+    let m = chem::parse_smiles(smiles)?;
+    let cv = Cv::parse(&CvSpec { kind: "dihedral".into(), atoms: req.atoms.clone() }, m.atoms.len())?;
+    let Cv::Dihedral([a, b, c, d]) = cv else { return Err("atoms must give a dihedral".into()) };
+    let bond = |i: usize, j: usize| m.bonds.iter().position(|e| (e.a, e.b) == (i, j) || (e.a, e.b) == (j, i));
+    if bond(a, b).is_none() || bond(c, d).is_none() { return Err(format!("{} is not a dihedral: each end atom must be bonded to its neighbour", cv.label())); }
+    let central = bond(b, c).ok_or_else(|| format!("atoms {} and {} are not bonded", b + 1, c + 1))?;
+    if m.bond_in_ring(central) { return Err(format!("bond {}-{} is in a ring and can't turn through 360 degrees", b + 1, c + 1)); }
+    let increment = req.increment_deg.unwrap_or(DEFAULT_INCREMENT_DEG);
+    if increment.is_nan() || increment <= 0.0 || increment > 180.0 { return Err("increment_deg must be above 0 and at most 180".into()); }
+    let n = (360.0 / increment).round() as usize;
+    if n > MAX_POINTS { return Err(format!("an increment of {increment} degrees gives more than {MAX_POINTS} points")); }
+    let increment = 360.0 / n as f64;
+    let k = req.force_constant.unwrap_or(DEFAULT_FORCE_CONSTANT);
+    if k.is_nan() || k <= 0.0 { return Err("force_constant must be positive".into()); }
+
+    let restraints = conformer::restraints(&m);
+    let mut x = conformer::embed(&m, fnv1a(smiles.as_bytes()));
+    System { restraints: &restraints, receptor: &frame::EMPTY, metals: None, anchor: &[], k_pos: 0.0, bias: None }.minimize(&mut x, MINIMIZE_STEPS);
+    let start = cv.value(&x);
+    let target = Cell::new(start);
+    let bias = |x: &[[f64; 3]], grad: Option<&mut [[f64; 3]]>| {
+        let (v, g) = cv.gradient(x);
+        let dv = cv.delta(v, target.get());
+        if let Some(grad) = grad { for (i, gi) in g { for axis in 0..3 { grad[i][axis] += k * dv * gi[axis]; } } }
+        0.5 * k * dv * dv
+    };
+    let system = System { restraints: &restraints, receptor: &frame::EMPTY, metals: None, anchor: &[], k_pos: 0.0, bias: Some(&bias) };
+    let angles: Vec<f64> = (0..n).map(|i| start + (i as f64 * increment).to_radians()).collect();
+    // (energy, achieved angle) per point, the lower of the forward and backward passes.
+    let mut best = vec![(f64::INFINITY, 0.0); n];
+    for i in (0..n).chain((0..n).rev()) {
+        target.set(angles[i]);
+        system.minimize(&mut x, MINIMIZE_STEPS);
+        let e = forcefield::internal_energy(&restraints, &x, None);
+        if e < best[i].0 { best[i] = (e, cv.value(&x)); }
+    }
+
+    let round = |v: f64| (v * 1e3).round() / 1e3 + 0.0;
+    let min = best.iter().map(|p| p.0).fold(f64::INFINITY, f64::min);
+    // A scan point the conformer search can't beat is itself the best conformer found.
+    let global = strain::global_minimum(&m, &restraints).min(min);
+    let mut warnings = Vec::new();
+    let points: Vec<ScanPoint> = best.iter().zip(&angles).map(|(&(e, achieved), &angle)| {
+        let off = cv.delta(achieved, angle).to_degrees().abs();
+        if off > TOLERANCE_DEG { warnings.push(format!("the dihedral stayed {off:.1} degrees from {:.1}; raise force_constant", wrap(angle.to_degrees()))); }
+        ScanPoint { angle_deg: round(wrap(angle.to_degrees())), achieved_deg: round(wrap(achieved.to_degrees())), energy_kcal_mol: round(e), relative_kcal_mol: round(e - min), strain_kcal_mol: round(e - global) }
+    }).collect();
+    let lowest = points.iter().min_by(|p, q| p.energy_kcal_mol.total_cmp(&q.energy_kcal_mol)).map_or(0.0, |p| p.angle_deg);
+    let highest = points.iter().max_by(|p, q| p.energy_kcal_mol.total_cmp(&q.energy_kcal_mol)).map_or(0.0, |p| p.angle_deg);
+    Ok(TorsionScan {
+        scan_id: uuid::Uuid::new_v4().to_string(), molecule: req.molecule.clone(), canonical_smiles: smiles.into(), standardization: mol.standardization.clone(), dihedral: cv.label(), increment_deg: round(increment), force_constant: k, start_angle_deg: round(wrap(start.to_degrees())),
+        minimum_angle_deg: lowest, maximum_angle_deg: highest, barrier_kcal_mol: points.iter().map(|p| p.relative_kcal_mol).fold(0.0, f64::max), global_minimum_energy_kcal_mol: round(global), points, warnings,
+    })
+}
@@ -0,0 +1,91 @@
+//! Per-job resource accounting and monthly usage reports.
+//!
+//! CPU time is read from the kernel's per-thread scheduler statistics, so it only counts the
+//! work done on the thread running the job. Jobs share one process, so peak memory is the
+//! process resident high-water mark observed when the job finished. No compute path uses a
+//! GPU yet, so GPU-seconds are always zero until one does.
+
+use axum::{extract::{Query, State}, http::HeaderMap, response::Json};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::{projects, AppState};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+pub struct Resources { pub cpu_seconds: f64, pub wall_seconds: f64, pub peak_memory_bytes: u64, pub gpu_seconds: f64 }
+
+/// Started when a job begins; `finish` yields what it consumed.
+pub struct Meter { wall: Instant, cpu_ns: Option<u64> }
+
+impl Meter {
+    pub fn start() -> Self { Self { wall: Instant::now(), cpu_ns: thread_cpu_ns() } }
+    pub fn finish(&self) -> Resources {
+        let cpu_seconds = match (self.cpu_ns, thread_cpu_ns()) { (Some(a), Some(b)) => b.saturating_sub(a) as f64 / 1e9, _ => 0.0 };
+        Resources { cpu_seconds, wall_seconds: self.wall.elapsed().as_secs_f64(), peak_memory_bytes: peak_rss_bytes().unwrap_or(0), gpu_seconds: 0.0 }
+    }
+}
+
+/// Nanoseconds the current thread has spent on-CPU (first field of `schedstat`).
+fn thread_cpu_ns() -> Option<u64> {
+    std::fs::read_to_string("/proc/thread-self/schedstat").ok()?.split_whitespace().next()?.parse().ok()
+}
+
+fn peak_rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kb: u64 = status.lines().find(|l| l.starts_with("VmHWM:"))?.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// `YYYY-MM` for a unix timestamp (UTC), via the days-to-civil algorithm.
+pub fn month_of(unix: u64) -> String {
+    let z = (unix / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}")
+}
+
+#[derive(Deserialize)]
+pub struct UsageQuery { month: Option<String> }
+#[derive(Serialize, Default)]
+pub struct UsageTotals { jobs: u64, cpu_seconds: f64, wall_seconds: f64, gpu_seconds: f64, max_peak_memory_bytes: u64, estimated_cost: f64 }
+#[derive(Serialize)]
+pub struct MonthUsage { month: String, #[serde(flatten)] totals: UsageTotals, by_kind: BTreeMap<String, UsageTotals> }
+#[derive(Serialize)]
+pub struct UsageResponse { project: String, cpu_hour_rate: f64, gpu_hour_rate: f64, months: Vec<MonthUsage> }
+
+impl UsageTotals {
+    fn add(&mut self, r: &Resources, cpu_rate: f64, gpu_rate: f64) {
+        self.jobs += 1;
+        self.cpu_seconds += r.cpu_seconds;
+        self.wall_seconds += r.wall_seconds;
+        self.gpu_seconds += r.gpu_seconds;
+        self.max_peak_memory_bytes = self.max_peak_memory_bytes.max(r.peak_memory_bytes);
+        self.estimated_cost += r.cpu_seconds / 3600.0 * cpu_rate + r.gpu_seconds / 3600.0 * gpu_rate;
+    }
+}
+
+pub fn rate(var: &str) -> f64 { std::env::var(var).ok().and_then(|v| v.parse().ok()).unwrap_or(0.0) }
+
+/// Usage of the caller's project per calendar month, optionally restricted to `?month=YYYY-MM`.
+/// Costs use the operator's `BIO_CPU_HOUR_RATE` / `BIO_GPU_HOUR_RATE`.
+pub async fn report(State(s): State<Arc<AppState>>, headers: HeaderMap, Query(q): Query<UsageQuery>) -> Json<UsageResponse> {
+    let project = projects::project_id(&headers);
+    let (cpu_rate, gpu_rate) = (rate("BIO_CPU_HOUR_RATE"), rate("BIO_GPU_HOUR_RATE"));
+    let mut months: BTreeMap<String, (UsageTotals, BTreeMap<String, UsageTotals>)> = BTreeMap::new();
+    for job in s.jobs.billing(&project) {
+        let month = month_of(job.created_at_unix);
+        if q.month.as_ref().is_some_and(|m| m != &month) { continue; }
+        let (totals, by_kind) = months.entry(month).or_default();
+        totals.add(&job.resources, cpu_rate, gpu_rate);
+        by_kind.entry(job.kind.clone()).or_default().add(&job.resources, cpu_rate, gpu_rate);
+    }
+    let months = months.into_iter().map(|(month, (totals, by_kind))| MonthUsage { month, totals, by_kind }).collect();
+    Json(UsageResponse { project, cpu_hour_rate: cpu_rate, gpu_hour_rate: gpu_rate, months })
+}
@@ -0,0 +1,239 @@
+//! Experimental measurements and how the engine's predictions compare with them.
+//!
+//! Measurements are project-scoped: binding constants (`kd`, `ki`, `ic50`, `ec50`) of a compound
+//! against a target, or a protein's melting temperature (`tm`, optionally of a mutant). A
+//! potency can also be taken from a recorded `/fit/dose-response` job by its `fit_id`. The
+//! validation report pairs each measurement with the latest prediction of every model version
+//! in the project's job history: a screen hit's (or panel entry's) `binding_affinity_nm` for
+//! the compound and target, or `/stability`'s Tm for the sequence. Affinities are compared as
+//! log10 nM, melting temperatures in °C. Per kind and model version the report gives Pearson
+//! and Spearman correlation, RMSE, mean signed error (bias) and MAE, overall and per month of
+//! prediction, so a model's track record can be followed as data comes in.
+
+use axum::{extract::{Query, State}, http::{HeaderMap, StatusCode}, response::Json};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+use crate::{jobs::Job, projects, unix_now, usage, ApiError, AppState, ErrorResponse};
+
+const AFFINITY_KINDS: &[&str] = &["kd", "ki", "ic50", "ec50"];
+const MOLAR: &[(&str, f64)] = &[("M", 1e9), ("mM", 1e6), ("uM", 1e3), ("µM", 1e3), ("nM", 1.0), ("pM", 1e-3)];
+
+#[derive(Deserialize)]
+pub struct MeasurementInput { compound: Option<String>, target: String, kind: Option<String>, value: Option<f64>, unit: Option<String>, mutation: Option<String>, source: Option<String>, fit_id: Option<String>, measured_at_unix: Option<u64> }
+
+#[derive(Deserialize)]
+pub struct UploadRequest { measurements: Vec<MeasurementInput> }
+
+#[derive(Serialize, Clone)]
+pub struct Measurement {
+    pub measurement_id: String, #[serde(skip)] project: String, pub kind: String, #[serde(skip_serializing_if = "Option::is_none")] pub compound: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")] canonical_smiles: Option<String>, pub target: String, #[serde(skip_serializing_if = "Option::is_none")] mutation: Option<String>,
+    value: f64, unit: String, pub standard_value: f64, standard_unit: &'static str, #[serde(skip_serializing_if = "Option::is_none")] source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")] fit_id: Option<String>, measured_at_unix: u64, created_at_unix: u64,
+}
+
+pub struct MeasurementStore { measurements: Mutex<Vec<Measurement>> }
+
+impl MeasurementStore {
+    pub fn new() -> Self { Self { measurements: Mutex::new(Vec::new()) } }
+    pub fn for_project(&self, project: &str) -> Vec<Measurement> { self.measurements.lock().unwrap().iter().filter(|m| m.project == project).cloned().collect() }
+    /// Adds a measurement from an export bundle, standardized again; false when its ID is taken.
+    pub fn import(&self, project: &str, m: Exported) -> Result<bool, String> {
+        let kind = m.kind.to_lowercase();
+        if !is_affinity(&kind) && kind != "tm" { return Err(format!("unknown kind {kind}; expected kd, ki, ic50, ec50 or tm")); }
+        let (standard_value, standard_unit) = standardize(&kind, m.value, &m.unit)?;
+        let mut measurements = self.measurements.lock().unwrap();
+        if measurements.iter().any(|x| x.measurement_id == m.measurement_id && x.project == project) { return Ok(false); }
+        measurements.push(Measurement {
+            measurement_id: m.measurement_id, project: project.into(), kind, compound: m.compound, canonical_smiles: m.canonical_smiles, target: m.target, mutation: m.mutation,
+            value: m.value, unit: m.unit, standard_value, standard_unit, source: m.source, fit_id: m.fit_id, measured_at_unix: m.measured_at_unix, created_at_unix: m.created_at_unix,
+        });
+        Ok(true)
+    }
+}
+
+/// A measurement as exported, before it is standardized again.
+#[derive(Deserialize)]
+pub struct Exported {
+    measurement_id: String, kind: String, compound: Option<String>, canonical_smiles: Option<String>, target: String, mutation: Option<String>, value: f64, unit: String,
+    source: Option<String>, fit_id: Option<String>, measured_at_unix: u64, created_at_unix: u64,
+}
+
+#[derive(Serialize)]
+pub struct UploadResponse { project: String, added: usize, measurements: Vec<Measurement> }
+
+#[derive(Deserialize)]
+pub struct MeasurementQuery { kind: Option<String>, target: Option<String>, compound: Option<String> }
+
+#[derive(Serialize)]
+pub struct MeasurementsResponse { project: String, total: usize, measurements: Vec<Measurement> }
+
+fn is_affinity(kind: &str) -> bool { AFFINITY_KINDS.contains(&kind) }
+
+/// A measurement's value in nM (binding) or °C (`tm`).
+fn standardize(kind: &str, value: f64, unit: &str) -> Result<(f64, &'static str), String> {
+    if !value.is_finite() { return Err("value must be a finite number".into()); }
+    if is_affinity(kind) {
+        let scale = MOLAR.iter().find(|u| u.0 == unit).map(|u| u.1).ok_or_else(|| format!("unknown unit {unit} for {kind}; expected M, mM, uM, nM or pM"))?;
+        if value <= 0.0 { return Err(format!("{kind} must be positive")); }
+        Ok((value * scale, "nM"))
+    } else {
+        match unit { "C" | "°C" => Ok((value, "C")), "K" => Ok((((value - 273.15) * 100.0).round() / 100.0, "C")), _ => Err(format!("unknown unit {unit} for tm; expected C or K")) }
+    }
+}
+
+pub async fn upload(State(s): State<Arc<AppState>>, headers: HeaderMap, Json(req): Json<UploadRequest>) -> Result<(StatusCode, Json<UploadResponse>), ApiError> {
+    let bad = |e: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    if req.measurements.is_empty() { return Err(bad("measurements must not be empty".into())); }
+    let project = s.projects.resolve(&headers);
+    let now = unix_now();
+    let mut added = Vec::new();
+    for (i, m) in req.measurements.into_iter().enumerate() {
+        let at = |e: String| bad(format!("measurements[{i}]: {e}"));
+        // A dose-response fit supplies the value, unit and kind it measured.
+        let fit = match &m.fit_id {
+            Some(id) => Some(s.jobs.get(&project, id).filter(|j| j.kind == "dose_response").ok_or_else(|| at(format!("no dose-response fit {id} in this project")))?.result),
+            None => None,
+        };
+        let kind = m.kind.or_else(|| fit.as_ref().and_then(|f| f["potency_label"].as_str()).map(str::to_lowercase)).ok_or_else(|| at("kind is required".into()))?.to_lowercase();
+        if !is_affinity(&kind) && kind != "tm" { return Err(at(format!("unknown kind {kind}; expected kd, ki, ic50, ec50 or tm"))); }
+        let value = m.value.or_else(|| fit.as_ref().and_then(|f| f["potency"]["value"].as_f64())).ok_or_else(|| at("value is required".into()))?;
+        let unit = m.unit.or_else(|| fit.as_ref().and_then(|f| f["concentration_unit"].as_str()).map(String::from)).unwrap_or_else(|| if kind == "tm" { "C".into() } else { "nM".into() });
+        let (standard_value, standard_unit) = standardize(&kind, value, &unit).map_err(at)?;
+        if is_affinity(&kind) && m.compound.is_none() { return Err(at(format!("{kind} needs the compound"))); }
+        if m.target.trim().is_empty() { return Err(at("target must not be empty".into())); }
+        let canonical_smiles = match &m.compound { Some(c) => crate::standardize::resolve(&s, &headers, c).await.canonical_smiles, None => None };
+        added.push(Measurement {
+            measurement_id: uuid::Uuid::new_v4().to_string(), project: project.clone(), kind, compound: m.compound, canonical_smiles, target: m.target.trim().into(), mutation: m.mutation,
+            value, unit, standard_value, standard_unit, source: m.source, fit_id: m.fit_id, measured_at_unix: m.measured_at_unix.unwrap_or(now), created_at_unix: now,
+        });
+    }
+    s.measurements.measurements.lock().unwrap().extend(added.iter().cloned());
+    Ok((StatusCode::CREATED, Json(UploadResponse { project, added: added.len(), measurements: added })))
+}
+
+pub async fn list(State(s): State<Arc<AppState>>, headers: HeaderMap, Query(q): Query<MeasurementQuery>) -> Json<MeasurementsResponse> {
+    let project = projects::project_id(&headers);
+    let measurements: Vec<Measurement> = s.measurements.for_project(&project).into_iter().filter(|m| {
+        q.kind.as_ref().is_none_or(|k| m.kind.eq_ignore_ascii_case(k)) && q.target.as_ref().is_none_or(|t| m.target.eq_ignore_ascii_case(t)) && q.compound.as_ref().is_none_or(|c| m.compound.as_deref() == Some(c))
+    }).collect();
+    Json(MeasurementsResponse { project, total: measurements.len(), measurements })
+}
+
+/// The engine's prediction for `m` in job `j`, in the measurement's standard unit.
+fn prediction(m: &Measurement, j: &Job) -> Option<f64> {
+    let r = &j.result;
+    if is_affinity(&m.kind) && j.kind == "screen" {
+        let compound = m.compound.as_deref()?;
+        let hit = r["hits"].as_array()?.iter().find(|h| h["compound_id"].as_str().is_some_and(|id| id.eq_ignore_ascii_case(compound)) || (m.canonical_smiles.is_some() && h["smiles"].as_str() == m.canonical_smiles.as_deref()))?;
+        if r["target"].as_str().is_some_and(|t| t.eq_ignore_ascii_case(&m.target)) { return hit["binding_affinity_nm"].as_f64(); }
+        hit["panel"].as_array()?.iter().find(|p| p["target"].as_str().is_some_and(|t| t.eq_ignore_ascii_case(&m.target)))?["binding_affinity_nm"].as_f64()
+    } else if m.kind == "tm" && j.kind == "stability" && j.subject.trim().eq_ignore_ascii_case(&m.target) {
+        match &m.mutation {
+            Some(mutation) => r["mutations"].as_array()?.iter().find(|x| x["mutation"].as_str() == Some(mutation))?["mutant_tm_celsius"].as_f64(),
+            None => r["tm_celsius"].as_f64(),
+        }
+    } else {
+        None
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ValidationQuery { kind: Option<String>, target: Option<String> }
+
+#[derive(Serialize, Clone)]
+pub struct Pair {
+    measurement_id: String, kind: String, #[serde(skip_serializing_if = "Option::is_none")] compound: Option<String>, target: String, #[serde(skip_serializing_if = "Option::is_none")] mutation: Option<String>,
+    unit: &'static str, measured: f64, predicted: f64, error: f64, model_version: String, job_id: String, predicted_at_unix: u64, measured_at_unix: u64,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Metrics { n: usize, #[serde(skip_serializing_if = "Option::is_none")] pearson_r: Option<f64>, #[serde(skip_serializing_if = "Option::is_none")] spearman_rho: Option<f64>, rmse: f64, mean_error: f64, mean_absolute_error: f64 }
+
+#[derive(Serialize)]
+pub struct ModelMetrics { kind: String, model_version: String, scale: &'static str, #[serde(flatten)] metrics: Metrics, first_prediction_unix: u64, last_prediction_unix: u64 }
+
+#[derive(Serialize)]
+pub struct PeriodMetrics { month: String, kind: String, model_version: String, #[serde(flatten)] metrics: Metrics }
+
+#[derive(Serialize)]
+pub struct ValidationReport { project: String, measurements: usize, unmatched: usize, by_model: Vec<ModelMetrics>, timeline: Vec<PeriodMetrics>, pairs: Vec<Pair> }
+
+fn round(v: f64) -> f64 { (v * 1e4).round() / 1e4 + 0.0 }
+
+fn pearson(x: &[f64], y: &[f64]) -> Option<f64> {
+    let n = x.len() as f64;
+    let (mx, my) = (x.iter().sum::<f64>() / n, y.iter().sum::<f64>() / n);
+    let (sxy, sxx, syy) = x.iter().zip(y).fold((0.0, 0.0, 0.0), |(a, b, c), (&xi, &yi)| (a + (xi - mx) * (yi - my), b + (xi - mx).powi(2), c + (yi - my).powi(2)));
+    (x.len() >= 3 && sxx > 0.0 && syy > 0.0).then(|| sxy / (sxx * syy).sqrt())
+}
+
+/// Ranks from 1, ties sharing their mean rank.
+fn ranks(v: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..v.len()).collect();
+    order.sort_by(|&a, &b| v[a].total_cmp(&v[b]));
+    let mut r = vec![0.0; v.len()];
+    let mut i = 0;
+    while i < order.len() {
+        let j = (i..order.len()).take_while(|&j| v[order[j]] == v[order[i]]).last().unwrap_or(i);
+        for &k in &order[i..=j] { r[k] = (i + j) as f64 / 2.0 + 1.0; }
+        i = j + 1;
+    }
+    r
+}
+
+/// Metrics over pairs of (measured, predicted) on the comparison scale.
+fn metrics(pairs: &[(f64, f64)]) -> Metrics {
+    let (x, y): (Vec<f64>, Vec<f64>) = pairs.iter().copied().unzip();
+    let n = pairs.len() as f64;
+    let errors: Vec<f64> = pairs.iter().map(|(m, p)| p - m).collect();
+    Metrics {
+        n: pairs.len(), pearson_r: pearson(&x, &y).map(round), spearman_rho: pearson(&ranks(&x), &ranks(&y)).map(round),
+        rmse: round((errors.iter().map(|e| e * e).sum::<f64>() / n).sqrt()), mean_error: round(errors.iter().sum::<f64>() / n), mean_absolute_error: round(errors.iter().map(|e| e.abs()).sum::<f64>() / n),
+    }
+}
+
+pub async fn report(State(s): State<Arc<AppState>>, headers: HeaderMap, Query(q): Query<ValidationQuery>) -> Json<ValidationReport> {
+    let project = projects::project_id(&headers);
+    let measurements: Vec<Measurement> = s.measurements.for_project(&project).into_iter()
+        .filter(|m| q.kind.as_ref().is_none_or(|k| m.kind.eq_ignore_ascii_case(k)) && q.target.as_ref().is_none_or(|t| m.target.eq_ignore_ascii_case(t))).collect();
+    let jobs = s.jobs.for_project(&project);
+    let mut pairs = Vec::new();
+    let mut unmatched = 0;
+    for m in &measurements {
+        // The latest prediction of each model version.
+        let mut latest: Vec<(&Job, f64)> = Vec::new();
+        for j in &jobs {
+            let Some(p) = prediction(m, j).filter(|p| p.is_finite()) else { continue };
+            match latest.iter_mut().find(|l| l.0.model == j.model) { Some(l) if l.0.created_at_unix <= j.created_at_unix => *l = (j, p), Some(_) => {} None => latest.push((j, p)) }
+        }
+        if latest.is_empty() { unmatched += 1; }
+        for (j, p) in latest {
+            let scale = |v: f64| if is_affinity(&m.kind) { v.max(1e-12).log10() } else { v };
+            pairs.push(Pair {
+                measurement_id: m.measurement_id.clone(), kind: m.kind.clone(), compound: m.compound.clone(), target: m.target.clone(), mutation: m.mutation.clone(), unit: m.standard_unit,
+                measured: round(m.standard_value), predicted: round(p), error: round(scale(p) - scale(m.standard_value)), model_version: j.model.clone(), job_id: j.job_id.clone(), predicted_at_unix: j.created_at_unix, measured_at_unix: m.measured_at_unix,
+            });
+        }
+    }
+    let value = |p: &Pair| if is_affinity(&p.kind) { (p.measured.max(1e-12).log10(), p.predicted.max(1e-12).log10()) } else { (p.measured, p.predicted) };
+    let mut groups: Vec<(String, String)> = pairs.iter().map(|p| (p.kind.clone(), p.model_version.clone())).collect();
+    groups.sort();
+    groups.dedup();
+    let by_model = groups.iter().map(|(kind, model)| {
+        let members: Vec<&Pair> = pairs.iter().filter(|p| &p.kind == kind && &p.model_version == model).collect();
+        ModelMetrics {
+            kind: kind.clone(), model_version: model.clone(), scale: if is_affinity(kind) { "log10_nM" } else { "celsius" }, metrics: metrics(&members.iter().map(|p| value(p)).collect::<Vec<_>>()),
+            first_prediction_unix: members.iter().map(|p| p.predicted_at_unix).min().unwrap_or(0), last_prediction_unix: members.iter().map(|p| p.predicted_at_unix).max().unwrap_or(0),
+        }
+    }).collect();
+    let mut periods: Vec<(String, String, String)> = pairs.iter().map(|p| (usage::month_of(p.predicted_at_unix), p.kind.clone(), p.model_version.clone())).collect();
+    periods.sort();
+    periods.dedup();
+    let timeline = periods.into_iter().map(|(month, kind, model)| {
+        let members: Vec<(f64, f64)> = pairs.iter().filter(|p| p.kind == kind && p.model_version == model && usage::month_of(p.predicted_at_unix) == month).map(value).collect();
+        PeriodMetrics { month, kind, model_version: model, metrics: metrics(&members) }
+    }).collect();
+    Json(ValidationReport { project, measurements: measurements.len(), unmatched, by_model, timeline, pairs })
+}